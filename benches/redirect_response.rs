@@ -0,0 +1,103 @@
+//! Time and allocations per redirect response, next to building it with
+//! `Response::builder` as the handler used to. Run with
+//! `cargo bench --bench redirect_response`; it fails when building a
+//! redirect allocates more than `ALLOCATION_BUDGET`.
+
+// Revalidation answers are not measured.
+#[allow(dead_code)]
+#[path = "../src/redirect_response.rs"]
+mod redirect_response;
+
+use axum::body::Body;
+use axum::http::StatusCode;
+use axum::response::Response;
+use redirect_response::{
+    location, target_etag, temporary_redirect, DEFAULT_CACHE_CONTROL_HEADER_VALUE,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const ITERATIONS: usize = 1_000_000;
+
+/// The header map's table and entries, and the ETag; the target and the
+/// static Cache-Control value are shared, not copied.
+const ALLOCATION_BUDGET: usize = 3;
+
+const TARGET_URL: &str =
+    "https://example.com/a/fairly/long/path?utm_source=newsletter&utm_medium=email";
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Allocations per response, not counting the target the handler already
+/// holds.
+fn measure(name: &str, build: impl Fn(String) -> Response) -> usize {
+    let targets: Vec<String> = (0..ITERATIONS).map(|_| TARGET_URL.to_string()).collect();
+
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let started_at = Instant::now();
+    for target in targets {
+        black_box(build(black_box(target)));
+    }
+    let elapsed = started_at.elapsed();
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations_before) / ITERATIONS;
+
+    println!(
+        "{:<20} {:>6.1} ns {:>3} allocations per response",
+        name,
+        elapsed.as_nanos() as f64 / ITERATIONS as f64,
+        allocations
+    );
+
+    allocations
+}
+
+fn main() {
+    measure("builder", |target| {
+        Response::builder()
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header("ETag", target_etag(&target))
+            .header("Location", target)
+            .header("Cache-Control", DEFAULT_CACHE_CONTROL_HEADER_VALUE)
+            .body(Body::empty())
+            .expect("This response should always be constructable")
+    });
+
+    let allocations = measure("temporary_redirect", |target| {
+        let etag = target_etag(&target);
+        temporary_redirect(
+            location(target).expect("The target should be a valid header"),
+            etag,
+        )
+    });
+
+    if allocations > ALLOCATION_BUDGET {
+        eprintln!(
+            "Building a redirect allocates {} times, more than the budget of {}",
+            allocations, ALLOCATION_BUDGET
+        );
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,10 @@
+create table if not exists jwt_signing_keys
+(
+    kid text not null primary key,
+    private_key bytea not null,
+    public_key bytea not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    retired_at TIMESTAMP
+);
+
+CREATE INDEX idx_jwt_signing_keys_retired_at on jwt_signing_keys (retired_at);
@@ -0,0 +1,2 @@
+drop table if exists admin_audit;
+drop function if exists admin_audit_append_only();
\ No newline at end of file
@@ -0,0 +1,26 @@
+create table if not exists admin_audit
+(
+    id serial primary key,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    actor text not null,
+    ip text,
+    action text not null,
+    target text,
+    before jsonb,
+    after jsonb
+);
+
+CREATE INDEX idx_admin_audit_created_at on admin_audit (created_at);
+
+create or replace function admin_audit_append_only() returns trigger as
+$$
+begin
+    raise exception 'admin_audit is append-only';
+end;
+$$ language plpgsql;
+
+create trigger admin_audit_append_only
+    before update or delete
+    on admin_audit
+    for each row
+execute function admin_audit_append_only();
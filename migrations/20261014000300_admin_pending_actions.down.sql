@@ -0,0 +1 @@
+drop table if exists admin_pending_actions;
\ No newline at end of file
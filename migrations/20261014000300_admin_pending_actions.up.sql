@@ -0,0 +1,14 @@
+create table if not exists admin_pending_actions
+(
+    id text not null primary key,
+    action jsonb not null,
+    status text not null default 'pending',
+    requested_by text not null,
+    requested_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP not null,
+    decided_by text,
+    decided_at TIMESTAMP,
+    result jsonb
+);
+
+CREATE INDEX idx_admin_pending_actions_status on admin_pending_actions (status);
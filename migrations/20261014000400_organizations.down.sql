@@ -0,0 +1,3 @@
+alter table links drop column if exists organization_id;
+drop table if exists organization_members;
+drop table if exists organizations;
\ No newline at end of file
@@ -0,0 +1,22 @@
+create table if not exists organizations
+(
+    id text not null primary key,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    name text not null
+);
+
+create table if not exists organization_members
+(
+    organization_id text not null references organizations (id),
+    user_id text not null references users (id),
+    role text not null default 'member',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    primary key (organization_id, user_id)
+);
+
+alter table links
+    add column if not exists organization_id text references organizations (id);
+
+CREATE INDEX idx_links_organization_id on links (organization_id);
+CREATE INDEX idx_organization_members_user_id on organization_members (user_id);
@@ -0,0 +1 @@
+drop table if exists organization_exports;
\ No newline at end of file
@@ -0,0 +1,13 @@
+create table if not exists organization_exports
+(
+    id text not null primary key,
+    organization_id text not null references organizations (id),
+    requested_by text not null,
+    status text not null default 'pending',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMP,
+    error text,
+    archive bytea
+);
+
+CREATE INDEX idx_organization_exports_organization_id on organization_exports (organization_id);
@@ -0,0 +1,4 @@
+drop table if exists user_deletions;
+alter table links
+    drop column if exists owner_id,
+    drop column if exists disabled_at;
\ No newline at end of file
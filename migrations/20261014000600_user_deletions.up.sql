@@ -0,0 +1,18 @@
+alter table links
+    add column if not exists owner_id text references users (id),
+    add column if not exists disabled_at TIMESTAMP;
+
+CREATE INDEX idx_links_owner_id on links (owner_id);
+
+create table if not exists user_deletions
+(
+    user_id text not null primary key references users (id),
+    requested_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    scheduled_for TIMESTAMP not null,
+    link_disposition text not null,
+    transfer_to text references users (id),
+    status text not null default 'scheduled',
+    completed_at TIMESTAMP
+);
+
+CREATE INDEX idx_user_deletions_scheduled_for on user_deletions (scheduled_for);
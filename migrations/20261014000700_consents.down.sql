@@ -0,0 +1 @@
+drop table if exists consents;
\ No newline at end of file
@@ -0,0 +1,10 @@
+create table if not exists consents
+(
+    id serial primary key,
+    visitor_id text not null,
+    link_id text not null references links (id),
+    granted boolean not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_consents_visitor_id on consents (visitor_id);
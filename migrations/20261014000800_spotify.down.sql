@@ -0,0 +1,3 @@
+drop table if exists group_playlists;
+drop table if exists spotify_oauth_states;
+drop table if exists spotify_accounts;
\ No newline at end of file
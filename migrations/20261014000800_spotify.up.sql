@@ -0,0 +1,27 @@
+create table if not exists spotify_accounts
+(
+    user_id text not null primary key references users (id),
+    spotify_user_id text not null,
+    access_token text not null,
+    refresh_token text not null,
+    expires_at TIMESTAMP not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+create table if not exists spotify_oauth_states
+(
+    state text not null primary key,
+    user_id text not null references users (id),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+create table if not exists group_playlists
+(
+    group_id text not null primary key references groups (id),
+    playlist_id text not null,
+    playlist_url text not null,
+    owner_user_id text not null references users (id),
+    link_id text not null references links (id),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
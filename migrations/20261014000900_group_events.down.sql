@@ -0,0 +1,2 @@
+drop table if exists group_events;
+alter table groups drop column if exists calendar_token;
\ No newline at end of file
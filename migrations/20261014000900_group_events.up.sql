@@ -0,0 +1,21 @@
+alter table groups
+    add column if not exists calendar_token text;
+
+CREATE UNIQUE INDEX idx_groups_calendar_token on groups (calendar_token);
+
+create table if not exists group_events
+(
+    id text not null primary key,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    group_id text not null references groups (id),
+    title text not null,
+    description text,
+    location text,
+    url text,
+    link_id text references links (id),
+    starts_at TIMESTAMP not null,
+    ends_at TIMESTAMP
+);
+
+CREATE INDEX idx_group_events_group_id on group_events (group_id, starts_at);
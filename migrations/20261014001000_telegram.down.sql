@@ -0,0 +1,2 @@
+drop table if exists telegram_link_codes;
+drop table if exists telegram_accounts;
\ No newline at end of file
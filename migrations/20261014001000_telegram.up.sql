@@ -0,0 +1,15 @@
+create table if not exists telegram_accounts
+(
+    telegram_user_id bigint not null primary key,
+    user_id text not null references users (id),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_telegram_accounts_user_id on telegram_accounts (user_id);
+
+create table if not exists telegram_link_codes
+(
+    code text not null primary key,
+    user_id text not null references users (id),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
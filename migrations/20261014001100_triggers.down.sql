@@ -0,0 +1,3 @@
+drop table if exists trigger_subscriptions;
+alter table link_statistics drop column if exists created_at;
+alter table links drop column if exists created_at;
\ No newline at end of file
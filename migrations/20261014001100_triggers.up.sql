@@ -0,0 +1,16 @@
+alter table links
+    add column if not exists created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP;
+
+alter table link_statistics
+    add column if not exists created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP;
+
+create table if not exists trigger_subscriptions
+(
+    id text not null primary key,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    user_id text not null references users (id),
+    event text not null,
+    target_url text not null
+);
+
+CREATE INDEX idx_trigger_subscriptions_user_id on trigger_subscriptions (user_id, event);
@@ -0,0 +1,14 @@
+create table if not exists device_authorizations
+(
+    device_code text not null primary key,
+    user_code text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP not null,
+    client_name text,
+    scope text not null,
+    status text not null default 'pending',
+    user_id text references users (id),
+    last_polled_at TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_device_authorizations_user_code on device_authorizations (user_code);
@@ -0,0 +1 @@
+drop table if exists page_templates;
\ No newline at end of file
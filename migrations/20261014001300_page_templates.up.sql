@@ -0,0 +1,9 @@
+create table if not exists page_templates
+(
+    domain text not null,
+    kind text not null,
+    body text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    primary key (domain, kind)
+);
@@ -0,0 +1 @@
+alter table users drop column if exists locale;
\ No newline at end of file
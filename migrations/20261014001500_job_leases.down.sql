@@ -0,0 +1 @@
+drop table if exists job_leases;
\ No newline at end of file
@@ -0,0 +1,7 @@
+create table if not exists job_leases
+(
+    name text not null primary key,
+    holder text not null,
+    started_at TIMESTAMP not null,
+    finished_at TIMESTAMP
+);
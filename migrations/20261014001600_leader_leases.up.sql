@@ -0,0 +1,8 @@
+create table if not exists leader_leases
+(
+    name text not null primary key,
+    holder text not null,
+    acquired_at TIMESTAMP not null,
+    heartbeat_at TIMESTAMP not null,
+    expires_at TIMESTAMP not null
+);
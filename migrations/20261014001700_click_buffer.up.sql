@@ -0,0 +1,4 @@
+alter table link_statistics
+    add column if not exists click_id text;
+
+CREATE UNIQUE INDEX idx_link_statistics_click_id on link_statistics (click_id);
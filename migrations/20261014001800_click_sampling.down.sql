@@ -0,0 +1,2 @@
+alter table link_statistics drop column if exists sample_rate;
+alter table links drop column if exists click_sample_rate;
\ No newline at end of file
@@ -0,0 +1,5 @@
+alter table links
+    add column if not exists click_sample_rate integer not null default 1;
+
+alter table link_statistics
+    add column if not exists sample_rate integer not null default 1;
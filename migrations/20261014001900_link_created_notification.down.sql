@@ -0,0 +1,2 @@
+drop trigger if exists links_notify_created on links;
+drop function if exists notify_link_created();
\ No newline at end of file
@@ -0,0 +1,12 @@
+create or replace function notify_link_created() returns trigger as $$
+begin
+    perform pg_notify('link_created', new.id);
+    return new;
+end;
+$$ language plpgsql;
+
+drop trigger if exists links_notify_created on links;
+
+create trigger links_notify_created
+    after insert on links
+    for each row execute function notify_link_created();
@@ -0,0 +1,2 @@
+alter table link_statistics drop column if exists rolled_up;
+drop table if exists link_statistics_daily;
\ No newline at end of file
@@ -0,0 +1,16 @@
+create table if not exists link_statistics_daily
+(
+    link_id text not null references links (id) on delete cascade,
+    day date not null,
+    referer text,
+    user_agent text,
+    amount bigint not null,
+    sampled boolean not null default false
+);
+
+CREATE UNIQUE INDEX idx_link_statistics_daily_key on link_statistics_daily (link_id, day, referer, user_agent) NULLS NOT DISTINCT;
+
+alter table link_statistics
+    add column if not exists rolled_up boolean not null default false;
+
+CREATE INDEX idx_link_statistics_not_rolled_up on link_statistics (link_id) WHERE NOT rolled_up;
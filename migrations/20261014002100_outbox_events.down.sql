@@ -0,0 +1 @@
+drop table if exists outbox_events;
\ No newline at end of file
@@ -0,0 +1,7 @@
+create table if not exists outbox_events
+(
+    id serial primary key,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    event text not null,
+    subject text not null
+);
@@ -0,0 +1 @@
+drop table if exists quota_warnings;
\ No newline at end of file
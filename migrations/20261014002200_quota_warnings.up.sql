@@ -0,0 +1,8 @@
+create table if not exists quota_warnings
+(
+    user_id text not null references users (id) on delete cascade,
+    period date not null,
+    threshold int not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    primary key (user_id, period, threshold)
+);
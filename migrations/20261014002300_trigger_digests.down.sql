@@ -0,0 +1,5 @@
+drop table if exists trigger_digest_clicks;
+alter table trigger_subscriptions
+    drop column if exists delivery,
+    drop column if exists digest_interval_minutes,
+    drop column if exists digest_max_events;
\ No newline at end of file
@@ -0,0 +1,15 @@
+alter table trigger_subscriptions
+    add column if not exists delivery text not null default 'each',
+    add column if not exists digest_interval_minutes integer,
+    add column if not exists digest_max_events integer;
+
+create table if not exists trigger_digest_clicks
+(
+    subscription_id text not null references trigger_subscriptions (id) on delete cascade,
+    statistic_id integer not null references link_statistics (id) on delete cascade,
+    link_id text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    primary key (subscription_id, statistic_id)
+);
+
+CREATE INDEX idx_trigger_digest_clicks_batch on trigger_digest_clicks (subscription_id, link_id);
@@ -0,0 +1 @@
+alter table link_statistics drop column if exists country;
\ No newline at end of file
@@ -0,0 +1,2 @@
+alter table link_statistics
+    add column if not exists country text;
@@ -0,0 +1,5 @@
+CREATE INDEX IF NOT EXISTS idx_link_statistics_link_id on link_statistics (link_id);
+DROP INDEX IF EXISTS idx_link_statistics_link_id_created_at;
+
+CREATE INDEX IF NOT EXISTS idx_links_owner_id on links (owner_id);
+DROP INDEX IF EXISTS idx_links_owner_id_created_at;
\ No newline at end of file
@@ -0,0 +1,7 @@
+-- Links of an owner, newest first, as quotas and the new links trigger read them.
+CREATE INDEX IF NOT EXISTS idx_links_owner_id_created_at on links (owner_id, created_at DESC, id DESC);
+DROP INDEX IF EXISTS idx_links_owner_id;
+
+-- Clicks of a link, newest first, as the tail and recent clicks read them.
+CREATE INDEX IF NOT EXISTS idx_link_statistics_link_id_created_at on link_statistics (link_id, created_at DESC, id DESC);
+DROP INDEX IF EXISTS idx_link_statistics_link_id;
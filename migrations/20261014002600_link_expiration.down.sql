@@ -0,0 +1 @@
+alter table links drop column if exists expires_at;
\ No newline at end of file
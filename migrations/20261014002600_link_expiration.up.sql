@@ -0,0 +1,4 @@
+alter table links
+    add column if not exists expires_at TIMESTAMP;
+
+CREATE INDEX idx_links_expires_at on links (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1 @@
+alter table links drop column if exists deleted_at;
\ No newline at end of file
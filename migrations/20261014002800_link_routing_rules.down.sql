@@ -0,0 +1 @@
+alter table links drop column if exists routing_rules;
\ No newline at end of file
@@ -0,0 +1,2 @@
+alter table links
+    add column if not exists routing_rules jsonb not null default '[]';
@@ -0,0 +1 @@
+drop table if exists link_scheduled_changes;
\ No newline at end of file
@@ -0,0 +1,13 @@
+create table if not exists link_scheduled_changes
+(
+    id serial primary key,
+    link_id text not null references links (id),
+    target_url text not null,
+    change_at TIMESTAMP not null,
+    created_by text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    applied_at TIMESTAMP
+);
+
+CREATE INDEX idx_link_scheduled_changes_link_id on link_scheduled_changes (link_id, change_at);
+CREATE INDEX idx_link_scheduled_changes_pending on link_scheduled_changes (change_at) where applied_at is null;
@@ -0,0 +1,3 @@
+alter table links
+    drop column if exists kind,
+    drop column if exists payload;
\ No newline at end of file
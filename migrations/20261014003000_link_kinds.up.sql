@@ -0,0 +1,5 @@
+alter table links
+    add column if not exists kind text not null default 'url';
+
+alter table links
+    add column if not exists payload jsonb;
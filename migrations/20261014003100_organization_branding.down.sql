@@ -0,0 +1 @@
+drop table if exists organization_branding;
\ No newline at end of file
@@ -0,0 +1,9 @@
+create table if not exists organization_branding
+(
+    organization_id text not null primary key references organizations (id),
+    logo_url text,
+    primary_color text,
+    background_color text,
+    footer text,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1 @@
+drop table if exists premium_slugs;
\ No newline at end of file
@@ -0,0 +1,8 @@
+create table if not exists premium_slugs
+(
+    slug text not null primary key,
+    organization_id text references organizations (id),
+    created_by text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    assigned_at TIMESTAMP
+);
@@ -0,0 +1 @@
+alter table links drop column if exists state;
\ No newline at end of file
@@ -0,0 +1,8 @@
+alter table links
+    add column if not exists state text not null default 'active';
+
+update links set state = case
+    when deleted_at is not null then 'deleted'
+    when disabled_at is not null then 'disabled'
+    else 'active'
+end;
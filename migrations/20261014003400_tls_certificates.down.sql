@@ -0,0 +1,3 @@
+drop table if exists acme_challenges;
+drop table if exists acme_accounts;
+drop table if exists tls_certificates;
\ No newline at end of file
@@ -0,0 +1,28 @@
+create table if not exists tls_certificates
+(
+    domain text not null primary key,
+    certificate_chain text,
+    private_key text,
+    not_after TIMESTAMP,
+    managed boolean not null default false,
+    renewal_error text,
+    renewal_attempted_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+create table if not exists acme_accounts
+(
+    directory_url text not null primary key,
+    private_key bytea not null,
+    account_url text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+create table if not exists acme_challenges
+(
+    token text not null primary key,
+    key_authorization text not null,
+    domain text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
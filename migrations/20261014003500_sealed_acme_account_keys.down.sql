@@ -0,0 +1,6 @@
+-- Sealed keys only open with the service's key, so their accounts are
+-- dropped and registered again on the next renewal.
+delete from acme_accounts where private_key like 'sealed:v1:%';
+
+alter table acme_accounts
+    alter column private_key type bytea using decode(private_key, 'base64');
\ No newline at end of file
@@ -0,0 +1,2 @@
+alter table acme_accounts
+    alter column private_key type text using replace(encode(private_key, 'base64'), E'\n', '');
@@ -0,0 +1 @@
+drop table if exists custom_domains;
\ No newline at end of file
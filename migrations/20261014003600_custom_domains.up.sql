@@ -0,0 +1,16 @@
+create table if not exists custom_domains
+(
+    domain text not null primary key,
+    organization_id text not null references organizations (id) on delete cascade,
+    method text not null,
+    verification_token text not null,
+    status text not null default 'pending',
+    verification_error text,
+    verification_started_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    checked_at TIMESTAMP,
+    verified_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_custom_domains_organization_id on custom_domains (organization_id);
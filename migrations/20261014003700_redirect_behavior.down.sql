@@ -0,0 +1,12 @@
+alter table links
+    drop column if exists domain,
+    drop column if exists redirect_status,
+    drop column if exists cache_ttl_seconds,
+    drop column if exists fallback_url,
+    drop column if exists interstitial;
+
+alter table custom_domains
+    drop column if exists redirect_status,
+    drop column if exists cache_ttl_seconds,
+    drop column if exists fallback_url,
+    drop column if exists interstitial;
\ No newline at end of file
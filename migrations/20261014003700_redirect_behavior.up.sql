@@ -0,0 +1,12 @@
+alter table custom_domains
+    add column if not exists redirect_status integer,
+    add column if not exists cache_ttl_seconds integer,
+    add column if not exists fallback_url text,
+    add column if not exists interstitial boolean;
+
+alter table links
+    add column if not exists domain text,
+    add column if not exists redirect_status integer,
+    add column if not exists cache_ttl_seconds integer,
+    add column if not exists fallback_url text,
+    add column if not exists interstitial boolean;
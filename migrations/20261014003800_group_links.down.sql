@@ -0,0 +1 @@
+drop table if exists group_links;
\ No newline at end of file
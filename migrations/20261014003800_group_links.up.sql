@@ -0,0 +1,9 @@
+create table if not exists group_links
+(
+    group_id text not null references groups (id) on delete cascade,
+    link_id text not null references links (id) on delete cascade,
+    added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    primary key (group_id, link_id)
+);
+
+CREATE INDEX idx_group_links_link_id on group_links (link_id);
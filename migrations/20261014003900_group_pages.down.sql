@@ -0,0 +1,5 @@
+alter table group_links
+    drop column if exists title,
+    drop column if exists position;
+
+alter table groups drop column if exists slug;
\ No newline at end of file
@@ -0,0 +1,8 @@
+alter table groups
+    add column if not exists slug text;
+
+CREATE UNIQUE INDEX idx_groups_slug on groups (slug);
+
+alter table group_links
+    add column if not exists title text,
+    add column if not exists position integer not null default 0;
@@ -0,0 +1,15 @@
+create table if not exists sessions
+(
+    id text not null primary key,
+    user_id text not null references users (id) on delete cascade,
+    refresh_token_hash text not null,
+    previous_refresh_token_hash text,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    refreshed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP not null,
+    revoked_at TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_sessions_refresh_token_hash on sessions (refresh_token_hash);
+CREATE INDEX idx_sessions_previous_refresh_token_hash on sessions (previous_refresh_token_hash);
+CREATE INDEX idx_sessions_user_id on sessions (user_id);
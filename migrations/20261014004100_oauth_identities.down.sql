@@ -0,0 +1,2 @@
+drop table if exists oauth_login_states;
+drop table if exists oauth_identities;
\ No newline at end of file
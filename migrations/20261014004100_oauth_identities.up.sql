@@ -0,0 +1,19 @@
+create table if not exists oauth_identities
+(
+    provider text not null,
+    subject text not null,
+    user_id text not null references users (id) on delete cascade,
+    email text,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    last_login_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    primary key (provider, subject)
+);
+
+CREATE INDEX idx_oauth_identities_user_id on oauth_identities (user_id);
+
+create table if not exists oauth_login_states
+(
+    state text not null primary key,
+    provider text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
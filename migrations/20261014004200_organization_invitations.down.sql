@@ -0,0 +1 @@
+drop table if exists organization_invitations;
\ No newline at end of file
@@ -0,0 +1,15 @@
+create table if not exists organization_invitations
+(
+    id text not null primary key,
+    token text not null,
+    organization_id text not null references organizations (id) on delete cascade,
+    email text not null,
+    role text not null,
+    invited_by text references users (id) on delete set null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP not null,
+    accepted_at TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_organization_invitations_token on organization_invitations (token);
+CREATE INDEX idx_organization_invitations_organization_id on organization_invitations (organization_id);
@@ -0,0 +1,6 @@
+create or replace function admin_audit_append_only() returns trigger as
+$$
+begin
+    raise exception 'admin_audit is append-only';
+end;
+$$ language plpgsql;
\ No newline at end of file
@@ -0,0 +1,12 @@
+-- Entries past the retention the deleting transaction declares in
+-- groupify.audit_log_retention_days may be deleted; nothing else may change.
+create or replace function admin_audit_append_only() returns trigger as
+$$
+begin
+    if tg_op = 'DELETE' and old.created_at < CURRENT_TIMESTAMP - make_interval(
+            days => nullif(current_setting('groupify.audit_log_retention_days', true), '')::int) then
+        return old;
+    end if;
+    raise exception 'admin_audit is append-only';
+end;
+$$ language plpgsql;
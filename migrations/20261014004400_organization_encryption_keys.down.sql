@@ -0,0 +1,2 @@
+alter table organization_exports drop column if exists encryption_key_id;
+drop table if exists organization_encryption_keys;
\ No newline at end of file
@@ -0,0 +1,11 @@
+create table if not exists organization_encryption_keys
+(
+    id text not null primary key,
+    organization_id text not null unique references organizations (id) on delete cascade,
+    sealed_key text not null,
+    fingerprint text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+alter table organization_exports
+    add column if not exists encryption_key_id text;
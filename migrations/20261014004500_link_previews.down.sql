@@ -0,0 +1,5 @@
+alter table links
+    drop column if exists preview_title,
+    drop column if exists preview_description,
+    drop column if exists preview_image_url,
+    drop column if exists preview_fetched_at;
\ No newline at end of file
@@ -0,0 +1,5 @@
+alter table links
+    add column if not exists preview_title text,
+    add column if not exists preview_description text,
+    add column if not exists preview_image_url text,
+    add column if not exists preview_fetched_at TIMESTAMP;
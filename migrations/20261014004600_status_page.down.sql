@@ -0,0 +1,2 @@
+drop table if exists status_checks;
+drop table if exists status_incidents;
\ No newline at end of file
@@ -0,0 +1,23 @@
+create table if not exists status_incidents
+(
+    id text not null primary key,
+    title text not null,
+    message text not null,
+    impact text not null,
+    components text[] not null default '{}',
+    created_by text not null,
+    started_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    resolved_at TIMESTAMP
+);
+
+CREATE INDEX idx_status_incidents_started_at on status_incidents (started_at);
+
+create table if not exists status_checks
+(
+    checked_at TIMESTAMP not null DEFAULT CURRENT_TIMESTAMP,
+    component text not null,
+    status text not null
+);
+
+CREATE INDEX idx_status_checks_component_checked_at on status_checks (component, checked_at);
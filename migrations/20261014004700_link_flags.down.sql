@@ -0,0 +1 @@
+drop table if exists link_flags;
\ No newline at end of file
@@ -0,0 +1,14 @@
+create table if not exists link_flags
+(
+    id text not null primary key,
+    link_id text not null references links (id) on delete cascade,
+    target_url text not null,
+    threats text[] not null,
+    flagged_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    reviewed_at TIMESTAMP,
+    reviewed_by text,
+    resolution text
+);
+
+CREATE INDEX idx_link_flags_link_id on link_flags (link_id);
+CREATE INDEX idx_link_flags_pending on link_flags (flagged_at) where reviewed_at is null;
@@ -0,0 +1 @@
+drop table if exists host_rules;
\ No newline at end of file
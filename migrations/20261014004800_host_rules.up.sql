@@ -0,0 +1,7 @@
+create table if not exists host_rules
+(
+    pattern text not null primary key,
+    list text not null,
+    created_by text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,2 @@
+drop table if exists usage_records;
+drop table if exists hook_deliveries_daily;
\ No newline at end of file
@@ -0,0 +1,19 @@
+create table if not exists hook_deliveries_daily
+(
+    organization_id text not null references organizations (id),
+    day date not null,
+    amount bigint not null,
+    primary key (organization_id, day)
+);
+
+create table if not exists usage_records
+(
+    organization_id text not null references organizations (id),
+    period date not null,
+    metric text not null,
+    quantity bigint not null,
+    generated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    primary key (organization_id, period, metric)
+);
+
+CREATE INDEX idx_usage_records_period on usage_records (period);
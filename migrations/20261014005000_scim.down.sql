@@ -0,0 +1,4 @@
+drop table if exists scim_group_members;
+drop table if exists scim_groups;
+drop table if exists scim_users;
+drop table if exists scim_tokens;
\ No newline at end of file
@@ -0,0 +1,37 @@
+create table if not exists scim_tokens
+(
+    organization_id text not null primary key references organizations (id),
+    token_hash text not null unique,
+    created_by text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+create table if not exists scim_users
+(
+    organization_id text not null references organizations (id),
+    user_id text not null references users (id) on delete cascade,
+    external_id text,
+    active boolean not null default true,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    primary key (organization_id, user_id)
+);
+
+create table if not exists scim_groups
+(
+    id text not null primary key,
+    organization_id text not null references organizations (id),
+    display_name text not null,
+    external_id text,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_scim_groups_display_name on scim_groups (organization_id, lower(display_name));
+
+create table if not exists scim_group_members
+(
+    group_id text not null references scim_groups (id) on delete cascade,
+    user_id text not null references users (id) on delete cascade,
+    primary key (group_id, user_id)
+);
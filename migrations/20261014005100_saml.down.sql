@@ -0,0 +1,4 @@
+alter table scim_users drop column if exists created_user;
+drop table if exists saml_identities;
+drop table if exists saml_login_requests;
+drop table if exists saml_connections;
\ No newline at end of file
@@ -0,0 +1,33 @@
+create table if not exists saml_connections
+(
+    organization_id text not null primary key references organizations (id),
+    idp_entity_id text not null,
+    idp_sso_url text not null,
+    idp_certificate text not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+create table if not exists saml_login_requests
+(
+    id text not null primary key,
+    organization_id text not null references organizations (id),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+create table if not exists saml_identities
+(
+    organization_id text not null references organizations (id),
+    name_id text not null,
+    user_id text not null references users (id) on delete cascade,
+    email text,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    last_login_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    primary key (organization_id, name_id)
+);
+
+CREATE INDEX idx_saml_identities_user_id on saml_identities (user_id);
+
+-- Only accounts an organization created may be taken over by its identity
+-- provider.
+alter table scim_users add column if not exists created_user boolean not null default false;
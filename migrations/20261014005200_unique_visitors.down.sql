@@ -0,0 +1,3 @@
+alter table link_statistics_daily drop column if exists visitors;
+alter table link_statistics drop column if exists visitor_hash;
+drop table if exists visitor_salts;
\ No newline at end of file
@@ -0,0 +1,13 @@
+create table if not exists visitor_salts
+(
+    day date primary key,
+    salt bytea not null
+);
+
+alter table link_statistics
+    add column if not exists visitor_hash text;
+
+CREATE INDEX idx_link_statistics_visitor on link_statistics (link_id, visitor_hash) WHERE visitor_hash IS NOT NULL;
+
+alter table link_statistics_daily
+    add column if not exists visitors bigint not null default 0;
@@ -0,0 +1,13 @@
+DROP INDEX IF EXISTS idx_link_statistics_daily_key;
+
+alter table link_statistics_daily
+    drop column if exists is_bot,
+    drop column if exists browser,
+    drop column if exists os;
+
+alter table link_statistics
+    drop column if exists is_bot,
+    drop column if exists browser,
+    drop column if exists os;
+
+CREATE UNIQUE INDEX idx_link_statistics_daily_key on link_statistics_daily (link_id, day, referer, user_agent) NULLS NOT DISTINCT;
\ No newline at end of file
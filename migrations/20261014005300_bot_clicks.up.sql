@@ -0,0 +1,13 @@
+alter table link_statistics
+    add column if not exists is_bot boolean not null default false,
+    add column if not exists browser text,
+    add column if not exists os text;
+
+alter table link_statistics_daily
+    add column if not exists is_bot boolean not null default false,
+    add column if not exists browser text,
+    add column if not exists os text;
+
+DROP INDEX IF EXISTS idx_link_statistics_daily_key;
+
+CREATE UNIQUE INDEX idx_link_statistics_daily_key on link_statistics_daily (link_id, day, referer, user_agent, is_bot) NULLS NOT DISTINCT;
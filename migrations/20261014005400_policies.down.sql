@@ -0,0 +1,5 @@
+alter table device_authorizations drop column if exists policy_id;
+drop table if exists organization_policies;
+alter table links
+    drop column if exists tags,
+    drop column if exists campaign;
\ No newline at end of file
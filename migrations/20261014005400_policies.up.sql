@@ -0,0 +1,21 @@
+alter table links
+    add column if not exists tags text[] not null default '{}',
+    add column if not exists campaign text;
+
+create table if not exists organization_policies
+(
+    id text not null primary key,
+    organization_id text not null references organizations (id),
+    name text not null,
+    -- Roles the policy applies to, all of them when empty.
+    roles text[] not null default '{}',
+    statements jsonb not null,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_organization_policies_organization_id on organization_policies (organization_id);
+
+-- Authorizations under a deleted policy are dropped, their device starting over.
+alter table device_authorizations
+    add column if not exists policy_id text references organization_policies (id) on delete cascade;
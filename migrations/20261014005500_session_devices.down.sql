@@ -0,0 +1,9 @@
+drop table if exists user_devices;
+alter table sessions
+    drop column if exists ip,
+    drop column if exists country,
+    drop column if exists user_agent,
+    drop column if exists browser,
+    drop column if exists os,
+    drop column if exists device_fingerprint,
+    drop column if exists revoke_token_hash;
\ No newline at end of file
@@ -0,0 +1,23 @@
+alter table sessions
+    add column if not exists ip text,
+    add column if not exists country text,
+    add column if not exists user_agent text,
+    add column if not exists browser text,
+    add column if not exists os text,
+    add column if not exists device_fingerprint text,
+    add column if not exists revoke_token_hash text;
+
+CREATE UNIQUE INDEX idx_sessions_revoke_token_hash on sessions (revoke_token_hash);
+
+-- Devices and countries users logged in from, outliving their sessions so
+-- a login is only new once.
+create table if not exists user_devices
+(
+    user_id text not null references users (id) on delete cascade,
+    device_fingerprint text not null,
+    country text,
+    first_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_user_devices_key on user_devices (user_id, device_fingerprint, country) NULLS NOT DISTINCT;
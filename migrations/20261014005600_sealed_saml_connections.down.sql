@@ -0,0 +1,5 @@
+-- Connections sealed with an organization's key would read as ciphertext,
+-- so they are dropped and have to be set up again.
+delete from saml_connections where encryption_key_id is not null;
+
+alter table saml_connections drop column if exists encryption_key_id;
\ No newline at end of file
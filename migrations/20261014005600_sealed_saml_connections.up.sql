@@ -0,0 +1,2 @@
+alter table saml_connections
+    add column if not exists encryption_key_id text;
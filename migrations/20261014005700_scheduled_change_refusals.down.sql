@@ -0,0 +1 @@
+alter table link_scheduled_changes drop column if exists refused_reason;
\ No newline at end of file
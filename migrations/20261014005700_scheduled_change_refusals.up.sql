@@ -0,0 +1,2 @@
+alter table link_scheduled_changes
+    add column if not exists refused_reason text;
@@ -0,0 +1,711 @@
+//! Certificates from an ACME authority such as Let's Encrypt, for domains
+//! marked as managed in `tls_certificates`. The leader orders them, proving
+//! control of each domain through one of two challenges:
+//!
+//! - HTTP-01, where the authority fetches a token from
+//!   `/.well-known/acme-challenge/`, which every replica answers from
+//!   `acme_challenges`, so the domain must reach this service over plain
+//!   HTTP on port 80.
+//! - DNS-01, where a TXT record is published through the DNS hook, which
+//!   is the only way to get wildcard certificates. The hook takes
+//!   `{"fqdn", "value"}` posted to `/present` and `/cleanup`, the way
+//!   lego's `httpreq` provider sends them, so existing DNS integrations
+//!   for it work unchanged.
+//!
+//! Replicas pick up issued certificates on their next refresh.
+
+use crate::configuration::Settings;
+use crate::der;
+use crate::jobs;
+use crate::sealing;
+use crate::tls;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use chrono::NaiveDateTime;
+use once_cell::sync::Lazy;
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Often enough that a newly managed domain gets its certificate soon.
+const RENEWAL_JOB_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Certificates are renewed this many days before they expire, the way
+/// authorities issuing 90 day certificates recommend.
+const RENEW_BEFORE_DAYS: i32 = 30;
+
+/// Hours to wait after a failed order before trying the domain again, to
+/// stay clear of the authority's rate limits.
+const RETRY_AFTER_HOURS: i32 = 6;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 30;
+
+const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+
+static ACME_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .expect("The ACME client should always be constructable")
+});
+
+#[derive(Debug, thiserror::Error)]
+pub enum AcmeError {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("Could not reach the ACME server: {0}")]
+    Unreachable(#[from] reqwest::Error),
+    #[error("The ACME server refused {url}: {problem}")]
+    Refused { url: String, problem: String },
+    #[error("The ACME server answered unexpectedly: {0}")]
+    Protocol(String),
+    #[error("Could not generate a key")]
+    KeyGeneration,
+    #[error("The stored account key is unusable: {0}")]
+    Sealing(#[from] sealing::SealingError),
+    #[error("The DNS hook answered {0}")]
+    DnsHook(reqwest::StatusCode),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    /// Set for the authorizations of wildcards, whose identifier is the
+    /// domain below the wildcard.
+    #[serde(default)]
+    wildcard: bool,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    detail: Option<String>,
+}
+
+struct IssuedCertificate {
+    chain: String,
+    private_key: String,
+    not_after: NaiveDateTime,
+}
+
+/// Order certificates for managed domains due for one, when an ACME
+/// directory is configured.
+pub async fn run_certificate_renewal_job(db: PgPool, settings: Arc<Settings>) {
+    let Some(directory_url) = settings.acme_directory_url.clone() else {
+        return;
+    };
+
+    jobs::run_periodically(db, "tls_certificate_renewal", RENEWAL_JOB_INTERVAL, |db| {
+        renew_due_certificates(db, directory_url.clone(), settings.clone())
+    })
+    .await
+}
+
+async fn renew_due_certificates(
+    db: PgPool,
+    directory_url: String,
+    settings: Arc<Settings>,
+) -> Result<(), sqlx::Error> {
+    let due: Vec<String> = sqlx::query_scalar(
+        r#"SELECT domain FROM tls_certificates
+        WHERE managed
+        AND (not_after IS NULL OR not_after < localtimestamp + make_interval(days => $1))
+        AND (renewal_error IS NULL
+            OR renewal_attempted_at < localtimestamp - make_interval(hours => $2))
+        ORDER BY domain"#,
+    )
+    .bind(RENEW_BEFORE_DAYS)
+    .bind(RETRY_AFTER_HOURS)
+    .fetch_all(&db)
+    .await?;
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    let mut client = match AcmeClient::connect(&db, &directory_url, &settings).await {
+        Ok(client) => client,
+        Err(AcmeError::Database(err)) => return Err(err),
+        Err(err) => {
+            tracing::warn!("Could not set up the ACME account: {}", err);
+            return Ok(());
+        }
+    };
+
+    for domain in due {
+        match client.issue(&db, &domain).await {
+            Ok(issued) => {
+                sqlx::query(
+                    r#"UPDATE tls_certificates
+                    SET certificate_chain = $2, private_key = $3, not_after = $4,
+                        renewal_error = NULL, renewal_attempted_at = localtimestamp,
+                        updated_at = localtimestamp
+                    WHERE domain = $1"#,
+                )
+                .bind(&domain)
+                .bind(&issued.chain)
+                .bind(sealing::seal(
+                    settings.certificate_encryption_key.as_ref(),
+                    &domain,
+                    &issued.private_key,
+                ))
+                .bind(issued.not_after)
+                .execute(&db)
+                .await?;
+                tracing::info!(
+                    "Issued a certificate for {} valid until {}",
+                    domain,
+                    issued.not_after
+                );
+            }
+            Err(AcmeError::Database(err)) => return Err(err),
+            Err(err) => {
+                tracing::warn!("Could not issue a certificate for {}: {}", domain, err);
+                sqlx::query(
+                    r#"UPDATE tls_certificates
+                    SET renewal_error = $2, renewal_attempted_at = localtimestamp
+                    WHERE domain = $1"#,
+                )
+                .bind(&domain)
+                .bind(err.to_string())
+                .execute(&db)
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// An account with the authority, signing every request with its key as
+/// the protocol requires.
+struct AcmeClient {
+    directory: Directory,
+    key: EcdsaKeyPair,
+    account_url: String,
+    nonce: Option<String>,
+    dns_hook_url: Option<String>,
+    dns_propagation_delay: Duration,
+}
+
+impl AcmeClient {
+    /// Use the account kept for the directory, registering one first if
+    /// there is none yet.
+    async fn connect(
+        db: &PgPool,
+        directory_url: &str,
+        settings: &Settings,
+    ) -> Result<Self, AcmeError> {
+        let sealing_key = settings.certificate_encryption_key.as_ref();
+        let directory: Directory = ACME_CLIENT
+            .get(directory_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let stored: Option<(String, String)> = sqlx::query_as(
+            r#"SELECT private_key, account_url FROM acme_accounts WHERE directory_url = $1"#,
+        )
+        .bind(directory_url)
+        .fetch_optional(db)
+        .await?;
+
+        let (private_key, account_url) = match stored {
+            Some((private_key, account_url)) => {
+                let private_key = sealing::open(sealing_key, directory_url, &private_key)?;
+                let private_key = general_purpose::STANDARD.decode(private_key).map_err(|_| {
+                    AcmeError::Protocol("The stored account key is malformed".to_string())
+                })?;
+                (private_key, Some(account_url))
+            }
+            None => (generate_key()?, None),
+        };
+
+        let mut client = Self {
+            directory,
+            key: signing_key(&private_key)?,
+            account_url: account_url.clone().unwrap_or_default(),
+            nonce: None,
+            dns_hook_url: settings.acme_dns_hook_url.clone(),
+            dns_propagation_delay: settings.acme_dns_propagation_delay,
+        };
+        if account_url.is_some() {
+            return Ok(client);
+        }
+
+        let contact: Vec<String> = settings
+            .acme_contact_email
+            .as_deref()
+            .map(|email| format!("mailto:{}", email))
+            .into_iter()
+            .collect();
+        let url = client.directory.new_account.clone();
+        let response = client
+            .post(
+                &url,
+                Some(json!({ "termsOfServiceAgreed": true, "contact": contact })),
+            )
+            .await?;
+        client.account_url = location(&response)?;
+
+        sqlx::query(
+            r#"INSERT INTO acme_accounts (directory_url, private_key, account_url)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (directory_url) DO NOTHING"#,
+        )
+        .bind(directory_url)
+        .bind(sealing::seal(
+            sealing_key,
+            directory_url,
+            &general_purpose::STANDARD.encode(&private_key),
+        ))
+        .bind(&client.account_url)
+        .execute(db)
+        .await?;
+
+        tracing::info!("Registered ACME account {}", client.account_url);
+        Ok(client)
+    }
+
+    async fn issue(&mut self, db: &PgPool, domain: &str) -> Result<IssuedCertificate, AcmeError> {
+        let url = self.directory.new_order.clone();
+        let response = self
+            .post(
+                &url,
+                Some(json!({ "identifiers": [{ "type": "dns", "value": domain }] })),
+            )
+            .await?;
+        let order_url = location(&response)?;
+        let order: Order = response.json().await?;
+
+        for authorization_url in &order.authorizations {
+            self.authorize(db, domain, authorization_url).await?;
+        }
+
+        let private_key = generate_key()?;
+        let csr = signing_request(domain, &private_key)?;
+        self.post(
+            &order.finalize,
+            Some(json!({ "csr": general_purpose::URL_SAFE_NO_PAD.encode(csr) })),
+        )
+        .await?;
+
+        let mut certificate_url = None;
+        for _ in 0..POLL_ATTEMPTS {
+            let order: Order = self.post_as_get(&order_url).await?;
+            match order.status.as_str() {
+                "valid" => {
+                    certificate_url = order.certificate;
+                    break;
+                }
+                "pending" | "ready" | "processing" => tokio::time::sleep(POLL_INTERVAL).await,
+                status => {
+                    return Err(AcmeError::Protocol(format!(
+                        "The order for {} is {}",
+                        domain, status
+                    )))
+                }
+            }
+        }
+        let certificate_url = certificate_url.ok_or_else(|| {
+            AcmeError::Protocol(format!("The order for {} was not issued in time", domain))
+        })?;
+
+        let chain = self.post(&certificate_url, None).await?.text().await?;
+        let private_key = pem("PRIVATE KEY", &private_key);
+        let not_after = tls::parse_certificate(&chain, &private_key)
+            .map_err(|err| AcmeError::Protocol(format!("The certificate is unusable: {}", err)))?
+            .not_after;
+
+        Ok(IssuedCertificate {
+            chain,
+            private_key,
+            not_after,
+        })
+    }
+
+    /// Complete a challenge of an authorization, publishing its token for as
+    /// long as the authority may check it.
+    async fn authorize(
+        &mut self,
+        db: &PgPool,
+        domain: &str,
+        authorization_url: &str,
+    ) -> Result<(), AcmeError> {
+        let authorization: Authorization = self.post_as_get(authorization_url).await?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+
+        // Wildcards can only be proven through DNS, and other domains are
+        // when the authority offers nothing else.
+        let dns_hook_url = self.dns_hook_url.clone();
+        let challenge = authorization
+            .challenges
+            .into_iter()
+            .filter(|challenge| match challenge.kind.as_str() {
+                "http-01" => !authorization.wildcard,
+                "dns-01" => dns_hook_url.is_some(),
+                _ => false,
+            })
+            .min_by_key(|challenge| challenge.kind != "http-01")
+            .ok_or_else(|| {
+                AcmeError::Protocol(format!("No challenge this service can meet for {}", domain))
+            })?;
+        let token = challenge
+            .token
+            .ok_or_else(|| AcmeError::Protocol("The challenge has no token".to_string()))?;
+        let key_authorization = format!("{}.{}", token, self.thumbprint());
+
+        if challenge.kind == "http-01" {
+            sqlx::query(
+                r#"INSERT INTO acme_challenges (token, key_authorization, domain)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (token) DO UPDATE SET key_authorization = excluded.key_authorization"#,
+            )
+            .bind(&token)
+            .bind(&key_authorization)
+            .bind(domain)
+            .execute(db)
+            .await?;
+
+            let outcome = self
+                .await_challenge(domain, &challenge.url, authorization_url)
+                .await;
+
+            sqlx::query(r#"DELETE FROM acme_challenges WHERE token = $1"#)
+                .bind(&token)
+                .execute(db)
+                .await?;
+
+            return outcome;
+        }
+
+        let record = json!({
+            "fqdn": format!("_acme-challenge.{}.", authorization.identifier.value),
+            "value": general_purpose::URL_SAFE_NO_PAD
+                .encode(digest(&SHA256, key_authorization.as_bytes())),
+        });
+        let dns_hook_url = dns_hook_url.expect("DNS challenges are only picked with a hook");
+        call_dns_hook(&dns_hook_url, "present", &record).await?;
+        tokio::time::sleep(self.dns_propagation_delay).await;
+
+        let outcome = self
+            .await_challenge(domain, &challenge.url, authorization_url)
+            .await;
+
+        if let Err(err) = call_dns_hook(&dns_hook_url, "cleanup", &record).await {
+            tracing::warn!("Could not remove the DNS challenge of {}: {}", domain, err);
+        }
+
+        outcome
+    }
+
+    /// Tell the authority the challenge is ready and wait for its verdict.
+    async fn await_challenge(
+        &mut self,
+        domain: &str,
+        challenge_url: &str,
+        authorization_url: &str,
+    ) -> Result<(), AcmeError> {
+        self.post(challenge_url, Some(json!({}))).await?;
+
+        for _ in 0..POLL_ATTEMPTS {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let authorization: Authorization = self.post_as_get(authorization_url).await?;
+            match authorization.status.as_str() {
+                "valid" => return Ok(()),
+                "pending" => {}
+                status => {
+                    return Err(AcmeError::Protocol(format!(
+                        "The authorization of {} is {}",
+                        domain, status
+                    )))
+                }
+            }
+        }
+
+        Err(AcmeError::Protocol(format!(
+            "The authorization of {} did not complete in time",
+            domain
+        )))
+    }
+
+    async fn post_as_get<T: DeserializeOwned>(&mut self, url: &str) -> Result<T, AcmeError> {
+        Ok(self.post(url, None).await?.json().await?)
+    }
+
+    /// Send a signed request, with an empty payload when there is none.
+    /// A nonce the server no longer accepts is retried once with a fresh
+    /// one.
+    async fn post(
+        &mut self,
+        url: &str,
+        payload: Option<Value>,
+    ) -> Result<reqwest::Response, AcmeError> {
+        let payload = match payload {
+            Some(payload) => general_purpose::URL_SAFE_NO_PAD.encode(payload.to_string()),
+            None => String::new(),
+        };
+
+        for attempt in 0..2 {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.new_nonce().await?,
+            };
+
+            let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+            if self.account_url.is_empty() {
+                protected["jwk"] = self.jwk();
+            } else {
+                protected["kid"] = json!(self.account_url);
+            }
+            let protected = general_purpose::URL_SAFE_NO_PAD.encode(protected.to_string());
+            let signature = self
+                .key
+                .sign(
+                    &SystemRandom::new(),
+                    format!("{}.{}", protected, payload).as_bytes(),
+                )
+                .map_err(|_| AcmeError::Protocol("Could not sign the request".to_string()))?;
+
+            let response = ACME_CLIENT
+                .post(url)
+                .header("Content-Type", "application/jose+json")
+                .json(&json!({
+                    "protected": protected,
+                    "payload": payload,
+                    "signature": general_purpose::URL_SAFE_NO_PAD.encode(signature.as_ref()),
+                }))
+                .send()
+                .await?;
+
+            self.nonce = replay_nonce(&response);
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let status = response.status();
+            let problem: Problem = response.json().await.unwrap_or(Problem {
+                kind: None,
+                detail: None,
+            });
+            if attempt == 0 && problem.kind.as_deref() == Some(BAD_NONCE) {
+                continue;
+            }
+            return Err(AcmeError::Refused {
+                url: url.to_string(),
+                problem: problem.detail.unwrap_or_else(|| status.to_string()),
+            });
+        }
+
+        unreachable!("The last attempt always returns")
+    }
+
+    async fn new_nonce(&self) -> Result<String, AcmeError> {
+        let response = ACME_CLIENT
+            .head(&self.directory.new_nonce)
+            .send()
+            .await?
+            .error_for_status()?;
+        replay_nonce(&response)
+            .ok_or_else(|| AcmeError::Protocol("No nonce in the answer".to_string()))
+    }
+
+    /// The account's public key as a JWK, with its members in the order
+    /// the thumbprint needs.
+    fn jwk(&self) -> Value {
+        // An uncompressed point: a tag byte, then both coordinates.
+        let point = self.key.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": general_purpose::URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": general_purpose::URL_SAFE_NO_PAD.encode(&point[33..65]),
+        })
+    }
+
+    fn thumbprint(&self) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(digest(&SHA256, self.jwk().to_string().as_bytes()))
+    }
+}
+
+fn generate_key() -> Result<Vec<u8>, AcmeError> {
+    EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+        .map(|pkcs8| pkcs8.as_ref().to_vec())
+        .map_err(|_| AcmeError::KeyGeneration)
+}
+
+fn signing_key(pkcs8: &[u8]) -> Result<EcdsaKeyPair, AcmeError> {
+    EcdsaKeyPair::from_pkcs8(
+        &ECDSA_P256_SHA256_FIXED_SIGNING,
+        pkcs8,
+        &SystemRandom::new(),
+    )
+    .map_err(|_| AcmeError::KeyGeneration)
+}
+
+/// A PKCS#10 request for a certificate naming `domain`, signed by the key.
+fn signing_request(domain: &str, pkcs8: &[u8]) -> Result<Vec<u8>, AcmeError> {
+    // Signatures inside certificates are DER encoded, unlike those of JWS.
+    let key =
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8, &SystemRandom::new())
+            .map_err(|_| AcmeError::KeyGeneration)?;
+
+    let subject = der::constructed(
+        der::SEQUENCE,
+        &[&der::constructed(
+            der::SET,
+            &[&der::constructed(
+                der::SEQUENCE,
+                &[
+                    der::OID_COMMON_NAME,
+                    &der::tlv(der::UTF8_STRING, domain.as_bytes()),
+                ],
+            )],
+        )],
+    );
+    let public_key_info = der::constructed(
+        der::SEQUENCE,
+        &[
+            &der::constructed(
+                der::SEQUENCE,
+                &[der::OID_EC_PUBLIC_KEY, der::OID_PRIME256V1],
+            ),
+            &der::bit_string(key.public_key().as_ref()),
+        ],
+    );
+    let alt_names = der::constructed(
+        der::SEQUENCE,
+        &[&der::tlv(der::DNS_NAME, domain.as_bytes())],
+    );
+    let attributes = der::constructed(
+        der::CONTEXT_0,
+        &[&der::constructed(
+            der::SEQUENCE,
+            &[
+                der::OID_EXTENSION_REQUEST,
+                &der::constructed(
+                    der::SET,
+                    &[&der::constructed(
+                        der::SEQUENCE,
+                        &[&der::constructed(
+                            der::SEQUENCE,
+                            &[
+                                der::OID_SUBJECT_ALT_NAME,
+                                &der::tlv(der::OCTET_STRING, &alt_names),
+                            ],
+                        )],
+                    )],
+                ),
+            ],
+        )],
+    );
+    let info = der::constructed(
+        der::SEQUENCE,
+        &[
+            &der::tlv(der::INTEGER, &[0]),
+            &subject,
+            &public_key_info,
+            &attributes,
+        ],
+    );
+
+    let signature = key
+        .sign(&SystemRandom::new(), &info)
+        .map_err(|_| AcmeError::KeyGeneration)?;
+
+    Ok(der::constructed(
+        der::SEQUENCE,
+        &[
+            &info,
+            &der::constructed(der::SEQUENCE, &[der::OID_ECDSA_WITH_SHA256]),
+            &der::bit_string(signature.as_ref()),
+        ],
+    ))
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = general_purpose::STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("Base64 is always ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+/// Have the DNS hook publish or remove a challenge record.
+async fn call_dns_hook(hook_url: &str, action: &str, record: &Value) -> Result<(), AcmeError> {
+    let response = ACME_CLIENT
+        .post(format!("{}/{}", hook_url.trim_end_matches('/'), action))
+        .json(record)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(AcmeError::DnsHook(response.status()));
+    }
+    Ok(())
+}
+
+fn location(response: &reqwest::Response) -> Result<String, AcmeError> {
+    response
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| AcmeError::Protocol("No Location in the answer".to_string()))
+}
+
+fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get("Replay-Nonce")
+        .and_then(|nonce| nonce.to_str().ok())
+        .map(str::to_string)
+}
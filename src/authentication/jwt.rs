@@ -0,0 +1,381 @@
+use crate::authentication::{is_session_active, AuthError};
+use crate::client_ip::ClientIp;
+use crate::routes::record_admin_action;
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use anyhow::Context;
+use axum::extract::{FromRequestParts, State};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::Json;
+use base64::engine::general_purpose;
+use base64::Engine;
+use chrono::NaiveDateTime;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// How long an issued token stays valid. A retired signing key keeps
+/// verifying tokens for this long after it stops being primary.
+pub const TOKEN_LIFETIME_DAYS: i64 = 90;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Claims {
+    pub sub: String,
+    pub role: String,
+    pub exp: usize,
+    /// Set on tokens issued to devices, which never carry admin rights.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Session the token was issued for by `/auth/login` or `/auth/refresh`,
+    /// checked on every request so the token dies with its session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Organization policies limiting what the token may do, see
+    /// `policy::permits`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<String>,
+}
+
+impl Claims {
+    pub fn new(sub: &str, role: &str) -> Self {
+        Self {
+            sub: sub.to_owned(),
+            role: role.to_owned(),
+            exp: (chrono::Utc::now() + chrono::Duration::days(TOKEN_LIFETIME_DAYS)).timestamp()
+                as usize,
+            scope: None,
+            sid: None,
+            policies: Vec::new(),
+        }
+    }
+
+    /// Claims of an access token for a session, living for `lifetime`
+    /// rather than the usual [`TOKEN_LIFETIME_DAYS`].
+    pub fn for_session(sub: &str, role: &str, session_id: &str, lifetime: Duration) -> Self {
+        Self {
+            exp: (chrono::Utc::now().timestamp() as u64 + lifetime.as_secs()) as usize,
+            sid: Some(session_id.to_owned()),
+            ..Self::new(sub, role)
+        }
+    }
+
+    pub fn with_scope(mut self, scope: &str) -> Self {
+        self.scope = Some(scope.to_owned());
+        self
+    }
+
+    pub fn with_policy(mut self, policy_id: &str) -> Self {
+        self.policies.push(policy_id.to_owned());
+        self
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == "admin" && self.scope.is_none() && self.policies.is_empty()
+    }
+}
+
+#[derive(FromRow)]
+struct StoredSigningKey {
+    kid: String,
+    private_key: Vec<u8>,
+    public_key: Vec<u8>,
+    retired_at: Option<NaiveDateTime>,
+}
+
+struct SigningKey {
+    kid: String,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    public_key: Vec<u8>,
+    retired_at: Option<NaiveDateTime>,
+}
+
+impl From<StoredSigningKey> for SigningKey {
+    fn from(stored: StoredSigningKey) -> Self {
+        Self {
+            encoding_key: EncodingKey::from_ed_der(&stored.private_key),
+            decoding_key: DecodingKey::from_ed_der(&stored.public_key),
+            kid: stored.kid,
+            public_key: stored.public_key,
+            retired_at: stored.retired_at,
+        }
+    }
+}
+
+/// The set of Ed25519 keys used to sign and verify session tokens.
+///
+/// Exactly one key is primary and signs new tokens; retired keys are kept
+/// around until every token they signed has expired.
+pub struct JwtKeys {
+    keys: RwLock<Vec<SigningKey>>,
+}
+
+impl JwtKeys {
+    /// Load the active keys from the database, creating a first primary key
+    /// if none exists yet.
+    pub async fn load(pool: &PgPool) -> anyhow::Result<Self> {
+        let jwt_keys = Self {
+            keys: RwLock::new(Vec::new()),
+        };
+
+        jwt_keys.refresh(pool).await?;
+
+        if jwt_keys.keys.read().await.is_empty() {
+            jwt_keys.rotate(pool).await?;
+        }
+
+        Ok(jwt_keys)
+    }
+
+    /// Re-read the keys from the database, so rotations done by another
+    /// replica are picked up.
+    pub async fn refresh(&self, pool: &PgPool) -> anyhow::Result<()> {
+        let stored = sqlx::query_as::<_, StoredSigningKey>(
+            r#"SELECT kid, private_key, public_key, retired_at FROM jwt_signing_keys
+            WHERE retired_at IS NULL OR retired_at > CURRENT_TIMESTAMP - make_interval(days => $1)
+            ORDER BY retired_at DESC NULLS FIRST, created_at DESC"#,
+        )
+        .bind(TOKEN_LIFETIME_DAYS as i32)
+        .fetch_all(pool)
+        .await
+        .context("Failed to load JWT signing keys.")?;
+
+        *self.keys.write().await = stored.into_iter().map(SigningKey::from).collect();
+        Ok(())
+    }
+
+    /// Generate a new primary key and retire the previous one. Tokens signed
+    /// by the previous key stay valid until they expire.
+    #[tracing::instrument(name = "Rotate JWT signing key", skip(self, pool))]
+    pub async fn rotate(&self, pool: &PgPool) -> anyhow::Result<String> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| anyhow::anyhow!("Failed to generate signing key."))?;
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+            .map_err(|_| anyhow::anyhow!("Failed to parse generated signing key."))?;
+        let kid = Uuid::new_v4().simple().to_string();
+
+        let mut transaction = pool.begin().await?;
+
+        sqlx::query(
+            r#"UPDATE jwt_signing_keys SET retired_at = CURRENT_TIMESTAMP WHERE retired_at IS NULL"#,
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query(
+            r#"DELETE FROM jwt_signing_keys WHERE retired_at < CURRENT_TIMESTAMP - make_interval(days => $1)"#,
+        )
+        .bind(TOKEN_LIFETIME_DAYS as i32)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query(
+            r#"INSERT INTO jwt_signing_keys (kid, private_key, public_key) VALUES ($1, $2, $3)"#,
+        )
+        .bind(&kid)
+        .bind(pkcs8.as_ref())
+        .bind(key_pair.public_key().as_ref())
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        self.refresh(pool).await?;
+        Ok(kid)
+    }
+
+    /// Sign the claims with the current primary key.
+    pub async fn sign(&self, claims: &Claims) -> Result<String, (StatusCode, String)> {
+        let keys = self.keys.read().await;
+        let primary = keys
+            .iter()
+            .find(|key| key.retired_at.is_none())
+            .ok_or_else(|| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "No primary signing key".to_string(),
+                )
+            })?;
+
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(primary.kid.clone());
+
+        encode(&header, claims, &primary.encoding_key).map_err(internal_error)
+    }
+
+    /// Verify a token against whichever key signed it, as named by its `kid`.
+    pub async fn verify(&self, token: &str, pool: &PgPool) -> Result<Claims, AuthError> {
+        let kid = decode_header(token)
+            .context("Malformed token.")
+            .map_err(AuthError::InvalidCredentials)?
+            .kid
+            .ok_or_else(|| anyhow::anyhow!("Token has no key id."))
+            .map_err(AuthError::InvalidCredentials)?;
+
+        if !self.contains(&kid).await {
+            self.refresh(pool).await?;
+        }
+
+        let keys = self.keys.read().await;
+        let key = keys
+            .iter()
+            .find(|key| key.kid == kid)
+            .ok_or_else(|| anyhow::anyhow!("Unknown signing key."))
+            .map_err(AuthError::InvalidCredentials)?;
+
+        decode::<Claims>(token, &key.decoding_key, &Validation::new(Algorithm::EdDSA))
+            .map(|data| data.claims)
+            .context("Invalid token.")
+            .map_err(AuthError::InvalidCredentials)
+    }
+
+    /// The key id new tokens are currently signed with.
+    pub async fn primary_kid(&self) -> Option<String> {
+        self.keys
+            .read()
+            .await
+            .iter()
+            .find(|key| key.retired_at.is_none())
+            .map(|key| key.kid.clone())
+    }
+
+    async fn contains(&self, kid: &str) -> bool {
+        self.keys.read().await.iter().any(|key| key.kid == kid)
+    }
+
+    /// The public half of every active key, in JWKS format.
+    pub async fn jwks(&self) -> JwkSet {
+        let keys = self.keys.read().await;
+
+        JwkSet {
+            keys: keys
+                .iter()
+                .map(|key| Jwk {
+                    common: CommonParameters {
+                        public_key_use: Some(PublicKeyUse::Signature),
+                        key_algorithm: Some(KeyAlgorithm::EdDSA),
+                        key_id: Some(key.kid.clone()),
+                        ..Default::default()
+                    },
+                    algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: general_purpose::URL_SAFE_NO_PAD.encode(&key.public_key),
+                    }),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[axum::async_trait]
+impl FromRequestParts<InnerState> for Claims {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &InnerState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "Missing bearer token".to_string()))?;
+
+        let claims = state
+            .jwt_keys
+            .verify(token, &state.db)
+            .await
+            .map_err(|err| (StatusCode::UNAUTHORIZED, err.to_string()))?;
+
+        if let Some(session_id) = &claims.sid {
+            let active = is_session_active(&state.db, session_id)
+                .await
+                .map_err(internal_error)?;
+            if !active {
+                return Err((StatusCode::UNAUTHORIZED, "Session has ended".to_string()));
+            }
+        }
+
+        Ok(claims)
+    }
+}
+
+/// A caller whose token carries the admin role, plus the address the
+/// request came from for the audit log.
+pub struct AdminUser {
+    pub claims: Claims,
+    pub ip: Option<String>,
+}
+
+#[axum::async_trait]
+impl FromRequestParts<InnerState> for AdminUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &InnerState,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+
+        if !claims.is_admin() {
+            return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+        }
+
+        let ip = ClientIp::from_request_parts(parts, state)
+            .await
+            .ok()
+            .map(|client| client.ip.to_string());
+
+        Ok(Self { claims, ip })
+    }
+}
+
+pub async fn jwks(State(inner): State<InnerState>) -> Json<JwkSet> {
+    Json(inner.jwt_keys.jwks().await)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RotatedKey {
+    pub kid: String,
+}
+
+#[tracing::instrument(name = "Rotate signing key", skip(inner, admin))]
+pub async fn rotate_signing_key(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+) -> Result<Json<RotatedKey>, (StatusCode, String)> {
+    let previous_kid = inner.jwt_keys.primary_kid().await;
+
+    let kid = inner.jwt_keys.rotate(&inner.db).await.map_err(|err| {
+        tracing::error!("{:?}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    })?;
+
+    record_admin_action(
+        &inner.db,
+        &admin,
+        "jwt.rotate",
+        None,
+        Some(serde_json::json!({ "kid": previous_kid })),
+        Some(serde_json::json!({ "kid": kid })),
+    )
+    .await?;
+
+    Ok(Json(RotatedKey { kid }))
+}
@@ -1,3 +1,7 @@
+mod jwt;
 mod password;
+mod session;
 
-pub use password::*;
\ No newline at end of file
+pub use jwt::*;
+pub use password::*;
+pub use session::*;
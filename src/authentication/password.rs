@@ -7,15 +7,16 @@ use anyhow::Context;
 use std::collections::HashMap;
 
 use crate::email::EmailClient;
+use crate::i18n;
 use crate::InnerState;
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 use axum::extract::State;
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
 use axum::Json;
+use chrono::NaiveDateTime;
 use serde::Deserialize;
 use sqlx::{Executor, PgPool, Postgres, Transaction};
-use url::quirks::password;
 
 #[derive(Deserialize)]
 pub struct Credentials {
@@ -42,20 +43,19 @@ pub enum AuthError {
 pub async fn validate_credentials(
     credentials: &Credentials,
     pool: &PgPool,
-) -> Result<String, AuthError> {
-    let mut user_id = None;
+) -> Result<User, AuthError> {
+    let mut user = None;
     let mut expected_password_hash = String::from(
         "$argon2id$v=19$m=15000,t=2,p=1$\
         gZiV/M1gPc22ElAH/Jh1Hw$\
-        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .to_string(),
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
     );
 
     match get_stored_credentials(&credentials.email, pool).await {
-        Ok(user) => {
-            // If the Result is Ok, user will contain the User
-            user_id = user.id;
-            expected_password_hash = user.encrypted_password;
+        Ok(stored_user) => {
+            // If the Result is Ok, stored_user will contain the User
+            expected_password_hash = stored_user.encrypted_password.clone();
+            user = Some(stored_user);
         }
         Err(error) => {
             // If the Result is Err, error will contain the Error
@@ -65,28 +65,56 @@ pub async fn validate_credentials(
 
     verify_password_hash(&expected_password_hash, &credentials.password)?;
 
-    user_id
-        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+    if let Some(user) = &user {
+        require_not_suspended(user)?;
+    }
+
+    user.ok_or_else(|| anyhow::anyhow!("Unknown username."))
         .map_err(AuthError::InvalidCredentials)
 }
 
+/// Fail for users banned until a time still to come.
+pub fn require_not_suspended(user: &User) -> Result<(), AuthError> {
+    if let Some(banned_until) = user.banned_until.as_deref() {
+        let banned_until = NaiveDateTime::parse_from_str(banned_until, "%Y-%m-%d %H:%M:%S%.f")
+            .context("Failed to parse banned_until.")?;
+
+        if banned_until > chrono::Utc::now().naive_utc() {
+            return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                "Account suspended."
+            )));
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn forget_password(
     State(inner): State<InnerState>,
-    Json(user): Json<User>,
+    headers: HeaderMap,
+    Json(mut user): Json<User>,
 ) -> Result<Json<String>, (StatusCode, String)> {
-    let InnerState { email_client, db } = inner;
+    let InnerState {
+        email_client, db, ..
+    } = inner;
 
     let mut transaction = db.begin().await.map_err(internal_error)?;
 
     let user_id = get_stored_credentials(&user.email, &db).await?;
 
+    // Answer in the language the account was created with, if it has one.
+    user.locale = user_id
+        .locale
+        .clone()
+        .or_else(|| Some(i18n::negotiate(&headers).to_string()));
+
     let subscription_token = generate_subscription_token();
 
     store_token(&mut transaction, &user_id.id, &subscription_token).await?;
 
     transaction.commit().await.map_err(internal_error)?;
 
-    let resp = send_forget_password_email(&email_client, user, &subscription_token).await?;
+    send_forget_password_email(&email_client, user, &subscription_token).await?;
 
     Ok(Json("OK".to_owned()))
 }
@@ -106,9 +134,12 @@ pub async fn send_forget_password_email(
         forget_password_token
     );
 
-    let template_id = "35815619";
+    let locale = user.locale.as_deref().unwrap_or(i18n::DEFAULT_LOCALE);
+    let template_id = i18n::translate(locale, "email.forget_password.template_id")
+        .expect("The default catalog should name every email template");
 
     let mut template_model = HashMap::new();
+    template_model.insert("locale".to_owned(), locale.to_owned());
     template_model.insert("product_name".to_owned(), "Groupify".to_owned());
     template_model.insert("action_url".to_owned(), confirmation_link);
     template_model.insert("support_email".to_owned(), "admin@groupify.dev".to_owned());
@@ -180,7 +211,7 @@ pub async fn change_password(
     return Ok((StatusCode::OK, "Password successfully changed.".to_string()));
 }
 
-fn compute_password_hash(password: String) -> Result<String, (StatusCode, String)> {
+pub fn compute_password_hash(password: String) -> Result<String, (StatusCode, String)> {
     let salt = SaltString::generate(&mut rand::thread_rng());
     let password_hash = Argon2::new(
         Algorithm::Argon2id,
@@ -203,7 +234,7 @@ pub async fn store_token(
     subscription_token: &str,
 ) -> Result<(), (StatusCode, String)> {
     let query = sqlx::query_as::<_, User>(r#" UPDATE users SET recovery_token = $1, recovery_sent_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $2"#)
-        .bind(&subscription_token)
+        .bind(subscription_token)
         .bind(subscriber_id);
 
     transaction.execute(query).await.map_err(internal_error)?;
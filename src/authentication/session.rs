@@ -0,0 +1,347 @@
+//! Login sessions behind short-lived access tokens. A session holds one
+//! refresh token at a time, replaced every time it is used; a replaced
+//! token presented again must have leaked, so the session is revoked.
+//!
+//! Sessions remember the device and country they were started from, and
+//! users the devices and countries they ever logged in from, so logins from
+//! new ones can be told.
+
+use crate::client_ip::ClientIp;
+use crate::configuration::Settings;
+use crate::user_agent;
+
+use axum::http::header::USER_AGENT;
+use axum::http::HeaderMap;
+use base64::engine::general_purpose;
+use base64::Engine;
+use chrono::NaiveDateTime;
+use rand::RngCore;
+use ring::digest::{digest, SHA256};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor, PgPool};
+use std::net::IpAddr;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Longest user agent kept with a session.
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// A session and the refresh token it can currently be refreshed with.
+pub struct SessionToken {
+    pub session_id: String,
+    pub user_id: String,
+    pub refresh_token: String,
+    /// Whether the session was started from a device or country the user
+    /// never logged in from, when they had logged in before.
+    pub new_device: bool,
+}
+
+/// Where a session is started from.
+pub struct SessionDevice {
+    pub ip: IpAddr,
+    pub country: Option<String>,
+    pub user_agent: Option<String>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+}
+
+impl SessionDevice {
+    pub fn new(settings: &Settings, client: &ClientIp, headers: &HeaderMap) -> Self {
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|user_agent| {
+                user_agent
+                    .chars()
+                    .take(MAX_USER_AGENT_LENGTH)
+                    .collect::<String>()
+            });
+        let agent = user_agent::classify(user_agent.as_deref());
+
+        Self {
+            ip: client.ip.to_canonical(),
+            country: client.country(settings, headers),
+            user_agent,
+            browser: agent.browser,
+            os: agent.os,
+        }
+    }
+
+    /// The browser and operating system, leaving out the browser version
+    /// so updating it does not make a device new.
+    fn fingerprint(&self) -> String {
+        let device = format!(
+            "{}\n{}",
+            self.browser.as_deref().unwrap_or_default(),
+            self.os.as_deref().unwrap_or_default()
+        );
+        hex::encode(digest(&SHA256, device.as_bytes()))
+    }
+}
+
+/// An active session as its user sees it.
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveSession {
+    pub id: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub refreshed_at: Option<NaiveDateTime>,
+    pub expires_at: NaiveDateTime,
+    pub ip: Option<String>,
+    pub country: Option<String>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    /// Whether the request listing the sessions came with a token of this
+    /// one.
+    #[sqlx(skip)]
+    pub current: bool,
+}
+
+fn generate_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Refresh tokens are only stored hashed, so reading the table does not
+/// hand out sessions.
+fn hash_refresh_token(refresh_token: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(digest(&SHA256, refresh_token.as_bytes()))
+}
+
+/// Start a session for the user, dropping those of theirs that ended, and
+/// remember the device it is started from.
+#[tracing::instrument(name = "Start session", skip(pool, device))]
+pub async fn start_session(
+    pool: &PgPool,
+    user_id: &str,
+    lifetime: Duration,
+    device: &SessionDevice,
+) -> Result<SessionToken, sqlx::Error> {
+    sqlx::query(
+        r#"DELETE FROM sessions WHERE user_id = $1
+        AND (revoked_at IS NOT NULL OR expires_at <= CURRENT_TIMESTAMP)"#,
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    let session_id = Uuid::new_v4().simple().to_string();
+    let refresh_token = generate_refresh_token();
+    let fingerprint = device.fingerprint();
+
+    sqlx::query(
+        r#"INSERT INTO sessions (id, user_id, refresh_token_hash, expires_at, ip, country,
+            user_agent, browser, os, device_fingerprint)
+        VALUES ($1, $2, $3, CURRENT_TIMESTAMP + make_interval(secs => $4), $5, $6, $7, $8, $9, $10)"#,
+    )
+    .bind(&session_id)
+    .bind(user_id)
+    .bind(hash_refresh_token(&refresh_token))
+    .bind(lifetime.as_secs_f64())
+    .bind(device.ip.to_string())
+    .bind(&device.country)
+    .bind(&device.user_agent)
+    .bind(&device.browser)
+    .bind(&device.os)
+    .bind(&fingerprint)
+    .execute(pool)
+    .await?;
+
+    // Both statements see the devices from before this one is remembered.
+    // Logins from an unknown country count as from a known one.
+    let new_device: bool = sqlx::query_scalar(
+        r#"WITH remembered AS (
+            INSERT INTO user_devices (user_id, device_fingerprint, country) VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, device_fingerprint, country)
+                DO UPDATE SET last_seen_at = CURRENT_TIMESTAMP
+        )
+        SELECT EXISTS (SELECT 1 FROM user_devices WHERE user_id = $1) AND NOT (
+            EXISTS (SELECT 1 FROM user_devices WHERE user_id = $1 AND device_fingerprint = $2)
+            AND ($3::text IS NULL OR EXISTS (SELECT 1 FROM user_devices WHERE user_id = $1 AND country = $3))
+        )"#,
+    )
+    .bind(user_id)
+    .bind(&fingerprint)
+    .bind(&device.country)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(SessionToken {
+        session_id,
+        user_id: user_id.to_string(),
+        refresh_token,
+        new_device,
+    })
+}
+
+/// Replace the refresh token of the session it belongs to, extending the
+/// session by `lifetime`. None for tokens of no active session; presenting
+/// one that was already replaced also revokes its session.
+#[tracing::instrument(name = "Rotate refresh token", skip(pool, refresh_token))]
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    refresh_token: &str,
+    lifetime: Duration,
+) -> Result<Option<SessionToken>, sqlx::Error> {
+    let hash = hash_refresh_token(refresh_token);
+    let next_refresh_token = generate_refresh_token();
+
+    let rotated: Option<(String, String)> = sqlx::query_as(
+        r#"UPDATE sessions SET previous_refresh_token_hash = refresh_token_hash,
+            refresh_token_hash = $2, refreshed_at = CURRENT_TIMESTAMP,
+            expires_at = CURRENT_TIMESTAMP + make_interval(secs => $3)
+        WHERE refresh_token_hash = $1 AND revoked_at IS NULL
+            AND expires_at > CURRENT_TIMESTAMP
+        RETURNING id, user_id"#,
+    )
+    .bind(&hash)
+    .bind(hash_refresh_token(&next_refresh_token))
+    .bind(lifetime.as_secs_f64())
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some((session_id, user_id)) = rotated {
+        return Ok(Some(SessionToken {
+            session_id,
+            user_id,
+            refresh_token: next_refresh_token,
+            new_device: false,
+        }));
+    }
+
+    let reused: Option<String> = sqlx::query_scalar(
+        r#"UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+        WHERE previous_refresh_token_hash = $1 AND revoked_at IS NULL
+        RETURNING id"#,
+    )
+    .bind(&hash)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(session_id) = reused {
+        tracing::warn!(
+            "Revoked session {} after its replaced refresh token was used again",
+            session_id
+        );
+    }
+
+    Ok(None)
+}
+
+/// Revoke the session the refresh token belongs to, whether it is the
+/// current one or was already replaced. False when there is none.
+#[tracing::instrument(name = "End session", skip(pool, refresh_token))]
+pub async fn end_session(pool: &PgPool, refresh_token: &str) -> Result<bool, sqlx::Error> {
+    let ended = sqlx::query(
+        r#"UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+        WHERE (refresh_token_hash = $1 OR previous_refresh_token_hash = $1)
+            AND revoked_at IS NULL"#,
+    )
+    .bind(hash_refresh_token(refresh_token))
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(ended > 0)
+}
+
+/// Whether access tokens issued for the session are still good, checked on
+/// every request they come with so logging out takes effect right away.
+pub async fn is_session_active(pool: &PgPool, session_id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"SELECT EXISTS (SELECT 1 FROM sessions WHERE id = $1
+            AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP)"#,
+    )
+    .bind(session_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// The user's sessions that have not ended, most recently used first.
+pub async fn active_sessions(
+    pool: &PgPool,
+    user_id: &str,
+) -> Result<Vec<ActiveSession>, sqlx::Error> {
+    sqlx::query_as::<_, ActiveSession>(
+        r#"SELECT id, created_at, refreshed_at, expires_at, ip, country, browser, os
+        FROM sessions WHERE user_id = $1
+            AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        ORDER BY refreshed_at DESC, id"#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Revoke one of the user's sessions. False when they have no such active
+/// session.
+#[tracing::instrument(name = "Revoke session", skip(pool))]
+pub async fn revoke_session(
+    pool: &PgPool,
+    user_id: &str,
+    session_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let revoked = sqlx::query(
+        r#"UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL"#,
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(revoked > 0)
+}
+
+/// Revoke every session of the user, such as when they are suspended.
+#[tracing::instrument(name = "Revoke user sessions", skip(executor))]
+pub async fn revoke_user_sessions<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user_id: &str,
+) -> Result<u64, sqlx::Error> {
+    let revoked = sqlx::query(
+        r#"UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+        WHERE user_id = $1 AND revoked_at IS NULL"#,
+    )
+    .bind(user_id)
+    .execute(executor)
+    .await?
+    .rows_affected();
+
+    Ok(revoked)
+}
+
+/// A token revoking the session without signing in, for the user to follow
+/// from an email. Only stored hashed, like refresh tokens, and replacing
+/// any the session had.
+pub async fn issue_revoke_token(pool: &PgPool, session_id: &str) -> Result<String, sqlx::Error> {
+    let revoke_token = generate_refresh_token();
+
+    sqlx::query(r#"UPDATE sessions SET revoke_token_hash = $2 WHERE id = $1"#)
+        .bind(session_id)
+        .bind(hash_refresh_token(&revoke_token))
+        .execute(pool)
+        .await?;
+
+    Ok(revoke_token)
+}
+
+/// Revoke the session of a token from [`issue_revoke_token`]. False when
+/// there is no such session or it already ended.
+#[tracing::instrument(name = "Revoke session by token", skip(pool, revoke_token))]
+pub async fn revoke_session_by_token(
+    pool: &PgPool,
+    revoke_token: &str,
+) -> Result<bool, sqlx::Error> {
+    let revoked = sqlx::query(
+        r#"UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+        WHERE revoke_token_hash = $1 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP"#,
+    )
+    .bind(hash_refresh_token(revoke_token))
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(revoked > 0)
+}
@@ -0,0 +1,256 @@
+//! Shields.io style "label | value" badges, as SVG or PNG.
+
+const HEIGHT: usize = 20;
+const PADDING: usize = 6;
+
+pub const DEFAULT_LABEL_COLOR: &str = "555";
+pub const DEFAULT_COLOR: &str = "007ec6";
+
+/// The named colors shields.io accepts.
+const NAMED_COLORS: [(&str, &str); 10] = [
+    ("brightgreen", "44cc11"),
+    ("green", "97ca00"),
+    ("yellowgreen", "a4a61d"),
+    ("yellow", "dfb317"),
+    ("orange", "fe7d37"),
+    ("red", "e05d44"),
+    ("blue", "007ec6"),
+    ("lightgrey", "9f9f9f"),
+    ("grey", "555555"),
+    ("black", "000000"),
+];
+
+/// 5x8 column-major glyphs for ASCII 0x20 to 0x7E, least significant bit on
+/// top. Used to rasterize PNG badges.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5F, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14],
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x56, 0x20, 0x50],
+    [0x00, 0x08, 0x07, 0x03, 0x00],
+    [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00],
+    [0x2A, 0x1C, 0x7F, 0x1C, 0x2A],
+    [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x80, 0x70, 0x30, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x00, 0x60, 0x60, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3E, 0x51, 0x49, 0x45, 0x3E],
+    [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x72, 0x49, 0x49, 0x49, 0x46],
+    [0x21, 0x41, 0x49, 0x4D, 0x33],
+    [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3C, 0x4A, 0x49, 0x49, 0x31],
+    [0x41, 0x21, 0x11, 0x09, 0x07],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x46, 0x49, 0x49, 0x29, 0x1E],
+    [0x00, 0x00, 0x14, 0x00, 0x00],
+    [0x00, 0x40, 0x34, 0x00, 0x00],
+    [0x00, 0x08, 0x14, 0x22, 0x41],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08],
+    [0x02, 0x01, 0x59, 0x09, 0x06],
+    [0x3E, 0x41, 0x5D, 0x59, 0x4E],
+    [0x7C, 0x12, 0x11, 0x12, 0x7C],
+    [0x7F, 0x49, 0x49, 0x49, 0x36],
+    [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x49, 0x49, 0x49, 0x41],
+    [0x7F, 0x09, 0x09, 0x09, 0x01],
+    [0x3E, 0x41, 0x41, 0x51, 0x73],
+    [0x7F, 0x08, 0x08, 0x08, 0x7F],
+    [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01],
+    [0x7F, 0x08, 0x14, 0x22, 0x41],
+    [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x1C, 0x02, 0x7F],
+    [0x7F, 0x04, 0x08, 0x10, 0x7F],
+    [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06],
+    [0x3E, 0x41, 0x51, 0x21, 0x5E],
+    [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x26, 0x49, 0x49, 0x49, 0x32],
+    [0x03, 0x01, 0x7F, 0x01, 0x03],
+    [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F],
+    [0x3F, 0x40, 0x38, 0x40, 0x3F],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x03, 0x04, 0x78, 0x04, 0x03],
+    [0x61, 0x59, 0x49, 0x4D, 0x43],
+    [0x00, 0x7F, 0x41, 0x41, 0x41],
+    [0x02, 0x04, 0x08, 0x10, 0x20],
+    [0x00, 0x41, 0x41, 0x41, 0x7F],
+    [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x03, 0x07, 0x08, 0x00],
+    [0x20, 0x54, 0x54, 0x78, 0x40],
+    [0x7F, 0x28, 0x44, 0x44, 0x38],
+    [0x38, 0x44, 0x44, 0x44, 0x28],
+    [0x38, 0x44, 0x44, 0x28, 0x7F],
+    [0x38, 0x54, 0x54, 0x54, 0x18],
+    [0x00, 0x08, 0x7E, 0x09, 0x02],
+    [0x18, 0xA4, 0xA4, 0x9C, 0x78],
+    [0x7F, 0x08, 0x04, 0x04, 0x78],
+    [0x00, 0x44, 0x7D, 0x40, 0x00],
+    [0x20, 0x40, 0x40, 0x3D, 0x00],
+    [0x7F, 0x10, 0x28, 0x44, 0x00],
+    [0x00, 0x41, 0x7F, 0x40, 0x00],
+    [0x7C, 0x04, 0x78, 0x04, 0x78],
+    [0x7C, 0x08, 0x04, 0x04, 0x78],
+    [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0xFC, 0x18, 0x24, 0x24, 0x18],
+    [0x18, 0x24, 0x24, 0x18, 0xFC],
+    [0x7C, 0x08, 0x04, 0x04, 0x08],
+    [0x48, 0x54, 0x54, 0x54, 0x24],
+    [0x04, 0x04, 0x3F, 0x44, 0x24],
+    [0x3C, 0x40, 0x40, 0x20, 0x7C],
+    [0x1C, 0x20, 0x40, 0x20, 0x1C],
+    [0x3C, 0x40, 0x30, 0x40, 0x3C],
+    [0x44, 0x28, 0x10, 0x28, 0x44],
+    [0x4C, 0x90, 0x90, 0x90, 0x7C],
+    [0x44, 0x64, 0x54, 0x4C, 0x44],
+    [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x77, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00],
+    [0x02, 0x01, 0x02, 0x04, 0x02],
+];
+
+/// Width of a rasterized glyph plus its one pixel of spacing.
+const GLYPH_ADVANCE: usize = 6;
+
+/// Top row of rasterized text.
+const TEXT_TOP: usize = 6;
+
+/// Resolve a named or hex (`"4c1"`, `"#44cc11"`) color to six hex digits,
+/// or `None` when it is neither.
+pub fn parse_color(color: &str) -> Option<String> {
+    if let Some((_, hex)) = NAMED_COLORS.iter().find(|(name, _)| *name == color) {
+        return Some(hex.to_string());
+    }
+
+    let hex = color.trim_start_matches('#');
+    if !hex.chars().all(|character| character.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    match hex.len() {
+        3 => Some(
+            hex.chars()
+                .flat_map(|character| [character, character])
+                .collect(),
+        ),
+        6 => Some(hex.to_string()),
+        _ => None,
+    }
+    .map(|hex| hex.to_ascii_lowercase())
+}
+
+/// Rough advance of an 11px Verdana glyph, which is what shields.io uses.
+fn text_width(text: &str) -> usize {
+    text.chars()
+        .map(|character| match character {
+            'i' | 'l' | '.' | ',' | ':' | '\'' => 4,
+            'm' | 'w' | 'M' | 'W' => 10,
+            _ => 7,
+        })
+        .sum()
+}
+
+/// Abbreviate large counts the way badges usually do: 999, 1.2k, 3.4M.
+pub fn format_count(count: i64) -> String {
+    match count {
+        count if count >= 1_000_000 => format!("{:.1}M", count as f64 / 1_000_000.0),
+        count if count >= 1_000 => format!("{:.1}k", count as f64 / 1_000.0),
+        count => count.to_string(),
+    }
+}
+
+/// Render with colors as returned by `parse_color`.
+pub fn render_svg(label: &str, value: &str, label_color: &str, color: &str) -> String {
+    let label_width = text_width(label) + PADDING * 2;
+    let value_width = text_width(value) + PADDING * 2;
+    let label = xml_escape(label);
+    let value = xml_escape(value);
+    let width = label_width + value_width;
+    let label_x = label_width / 2;
+    let value_x = label_width + value_width / 2;
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{HEIGHT}" role="img" aria-label="{label}: {value}"><title>{label}: {value}</title><linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient><clipPath id="r"><rect width="{width}" height="{HEIGHT}" rx="3" fill="#fff"/></clipPath><g clip-path="url(#r)"><rect width="{label_width}" height="{HEIGHT}" fill="#{label_color}"/><rect x="{label_width}" width="{value_width}" height="{HEIGHT}" fill="#{color}"/><rect width="{width}" height="{HEIGHT}" fill="url(#s)"/></g><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="{label_x}" y="15" fill="#010101" fill-opacity=".3">{label}</text><text x="{label_x}" y="14">{label}</text><text x="{value_x}" y="15" fill="#010101" fill-opacity=".3">{value}</text><text x="{value_x}" y="14">{value}</text></g></svg>"##
+    )
+}
+
+/// Render a flat PNG badge using the built in bitmap font.
+pub fn render_png(label: &str, value: &str, label_color: &str, color: &str) -> Vec<u8> {
+    let glyphs = |text: &str| -> Vec<[u8; 5]> {
+        text.chars()
+            .map(|character| {
+                let index = (character as usize).wrapping_sub(0x20);
+                // Anything outside printable ASCII becomes a question mark.
+                FONT.get(index)
+                    .copied()
+                    .unwrap_or(FONT['?' as usize - 0x20])
+            })
+            .collect()
+    };
+    let label = glyphs(label);
+    let value = glyphs(value);
+
+    let label_width = (label.len() * GLYPH_ADVANCE).saturating_sub(1) + PADDING * 2;
+    let value_width = (value.len() * GLYPH_ADVANCE).saturating_sub(1) + PADDING * 2;
+    let width = label_width + value_width;
+
+    let label_rgb = hex_to_rgb(label_color);
+    let value_rgb = hex_to_rgb(color);
+    let mut pixels: Vec<[u8; 3]> = (0..width * HEIGHT)
+        .map(|index| {
+            if index % width < label_width {
+                label_rgb
+            } else {
+                value_rgb
+            }
+        })
+        .collect();
+
+    for (glyphs, left) in [(&label, PADDING), (&value, label_width + PADDING)] {
+        for (position, glyph) in glyphs.iter().enumerate() {
+            for (column, bits) in glyph.iter().enumerate() {
+                for row in (0..8).filter(|row| bits >> row & 1 != 0) {
+                    let x = left + position * GLYPH_ADVANCE + column;
+                    pixels[(TEXT_TOP + row) * width + x] = [0xFF, 0xFF, 0xFF];
+                }
+            }
+        }
+    }
+
+    let mut scanlines = Vec::with_capacity((width * 3 + 1) * HEIGHT);
+    for row in pixels.chunks(width) {
+        // Filter type "none" for every scanline.
+        scanlines.push(0);
+        scanlines.extend(row.iter().flatten());
+    }
+
+    crate::png::encode_rgb(width as u32, HEIGHT as u32, &scanlines)
+}
+
+fn hex_to_rgb(hex: &str) -> [u8; 3] {
+    let channel = |index: usize| {
+        hex.get(index * 2..index * 2 + 2)
+            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+            .unwrap_or_default()
+    };
+    [channel(0), channel(1), channel(2)]
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
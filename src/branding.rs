@@ -0,0 +1,147 @@
+//! Logos, colors and footers organizations put on the pages and QR codes
+//! of their links. Looked up on every such page, so each replica keeps
+//! them in memory for a while; a replica sees another's change once its
+//! copy expires.
+
+use crate::db::links;
+use crate::validation::{Validate, ValidationErrors};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a replica keeps an organization's branding.
+const BRANDING_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+const MAX_FOOTER_LENGTH: usize = 500;
+
+/// An organization's branding, or lack of it, and when it was looked up.
+type CachedBranding = (Instant, Option<Arc<Branding>>);
+
+static CACHE: Lazy<Mutex<HashMap<String, CachedBranding>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Branding {
+    pub logo_url: Option<String>,
+    /// `#rrggbb` of links and buttons, and of dark QR code modules.
+    pub primary_color: Option<String>,
+    /// `#rrggbb` of the page background.
+    pub background_color: Option<String>,
+    /// Plain text shown at the bottom of every page.
+    pub footer: Option<String>,
+}
+
+impl Validate for Branding {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if let Some(logo_url) = &self.logo_url {
+            errors.require_web_url("logoUrl", logo_url);
+        }
+        for (field, color) in [
+            ("primaryColor", &self.primary_color),
+            ("backgroundColor", &self.background_color),
+        ] {
+            if color.as_deref().is_some_and(|color| !is_hex_color(color)) {
+                errors.add(field, "must be a #rrggbb color");
+            }
+        }
+        if let Some(footer) = &self.footer {
+            errors.require_max_length("footer", footer, MAX_FOOTER_LENGTH);
+        }
+    }
+}
+
+impl Branding {
+    /// Values for page templates, empty ones for what is not set so
+    /// `{{#if}}` sections leave it out.
+    pub fn page_values(&self) -> [(&'static str, &str); 4] {
+        [
+            (
+                "brand_logo_url",
+                self.logo_url.as_deref().unwrap_or_default(),
+            ),
+            (
+                "brand_primary_color",
+                self.primary_color.as_deref().unwrap_or_default(),
+            ),
+            (
+                "brand_background_color",
+                self.background_color.as_deref().unwrap_or_default(),
+            ),
+            ("brand_footer", self.footer.as_deref().unwrap_or_default()),
+        ]
+    }
+}
+
+fn is_hex_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The branding of an organization, `None` when it has none.
+pub async fn for_organization(
+    db: &PgPool,
+    organization_id: &str,
+) -> Result<Option<Arc<Branding>>, sqlx::Error> {
+    if let Some((fetched_at, branding)) = CACHE
+        .lock()
+        .expect("The branding cache lock should never be poisoned")
+        .get(organization_id)
+    {
+        if fetched_at.elapsed() < BRANDING_CACHE_TTL {
+            return Ok(branding.clone());
+        }
+    }
+
+    let branding = sqlx::query_as::<_, Branding>(
+        r#"SELECT logo_url, primary_color, background_color, footer
+        FROM organization_branding WHERE organization_id = $1"#,
+    )
+    .bind(organization_id)
+    .fetch_optional(db)
+    .await?
+    .map(Arc::new);
+
+    CACHE
+        .lock()
+        .expect("The branding cache lock should never be poisoned")
+        .insert(
+            organization_id.to_string(),
+            (Instant::now(), branding.clone()),
+        );
+
+    Ok(branding)
+}
+
+/// The branding of the organization a link belongs to. Pages are shown
+/// unbranded rather than not at all when it cannot be looked up.
+pub async fn for_link(db: &PgPool, link_id: &str) -> Option<Arc<Branding>> {
+    let branding = async {
+        match links::organization_id(db, link_id).await? {
+            Some(organization_id) => for_organization(db, &organization_id).await,
+            None => Ok(None),
+        }
+    }
+    .await;
+
+    branding.unwrap_or_else(|err| {
+        tracing::error!(
+            "Could not look up the branding of link {}: {}",
+            link_id,
+            err
+        );
+        None
+    })
+}
+
+/// Drop this replica's copy of an organization's branding after changing
+/// it.
+pub fn forget(organization_id: &str) {
+    CACHE
+        .lock()
+        .expect("The branding cache lock should never be poisoned")
+        .remove(organization_id);
+}
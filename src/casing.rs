@@ -0,0 +1,464 @@
+//! One casing for every field name the API answers with, whatever the
+//! struct it came from was derived with.
+//!
+//! Handlers return this module's `Json` instead of axum's, which writes
+//! struct fields in the casing set by `RESPONSE_CASING`. Map keys are data,
+//! such as link ids, and are left as they are, as are enum values. Bodies
+//! whose shape a third party defines, like Grafana's, Zapier's, JWKS or
+//! chat platform replies, keep using axum's `Json`.
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, Request};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use once_cell::sync::OnceCell;
+use serde::de::DeserializeOwned;
+use serde::ser::{self, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+
+static RESPONSE_CASING: OnceCell<Casing> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Casing {
+    Camel,
+    Snake,
+}
+
+impl Casing {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "camelCase" => Some(Casing::Camel),
+            "snake_case" => Some(Casing::Snake),
+            _ => None,
+        }
+    }
+
+    fn apply<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match self {
+            Casing::Camel if name.contains('_') => {
+                let mut camel = String::with_capacity(name.len());
+                let mut upper = false;
+                for c in name.chars() {
+                    if c == '_' {
+                        upper = !camel.is_empty();
+                    } else if upper {
+                        camel.extend(c.to_uppercase());
+                        upper = false;
+                    } else {
+                        camel.push(c);
+                    }
+                }
+                Cow::Owned(camel)
+            }
+            Casing::Snake if name.chars().any(|c| c.is_ascii_uppercase()) => {
+                let mut snake = String::with_capacity(name.len() + 4);
+                for c in name.chars() {
+                    if c.is_ascii_uppercase() {
+                        if !snake.is_empty() {
+                            snake.push('_');
+                        }
+                        snake.push(c.to_ascii_lowercase());
+                    } else {
+                        snake.push(c);
+                    }
+                }
+                Cow::Owned(snake)
+            }
+            _ => Cow::Borrowed(name),
+        }
+    }
+}
+
+/// Set the casing of every response. Called once at startup; until then,
+/// and if never called, responses are camelCase.
+pub fn init(casing: Casing) {
+    if RESPONSE_CASING.set(casing).is_err() {
+        tracing::warn!("The response casing was already set");
+    }
+}
+
+fn response_casing() -> Casing {
+    RESPONSE_CASING.get().copied().unwrap_or(Casing::Camel)
+}
+
+/// A JSON request or response body, with a response's field names in the
+/// configured casing. Requests are read as their DTOs are derived.
+pub struct Json<T>(pub T);
+
+#[axum::async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = JsonRejection;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(value) = axum::Json::<T>::from_request(request, state).await?;
+
+        Ok(Json(value))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        match to_value(&self.0, response_casing()) {
+            Ok(value) => axum::Json(value).into_response(),
+            Err(err) => {
+                tracing::error!("Could not serialize response: {}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// Serialize `value` like `serde_json::to_value`, renaming struct fields.
+pub fn to_value<T: Serialize + ?Sized>(value: &T, casing: Casing) -> serde_json::Result<Value> {
+    value.serialize(ValueSerializer { casing })
+}
+
+#[derive(Clone, Copy)]
+struct ValueSerializer {
+    casing: Casing,
+}
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = VariantSerializer<SeqSerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = VariantSerializer<MapSerializer>;
+
+    fn serialize_bool(self, v: bool) -> serde_json::Result<Value> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> serde_json::Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_char(self, v: char) -> serde_json::Result<Value> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> serde_json::Result<Value> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> serde_json::Result<Value> {
+        Ok(Value::Array(
+            v.iter().map(|byte| Value::from(*byte)).collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> serde_json::Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> serde_json::Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> serde_json::Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> serde_json::Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> serde_json::Result<Value> {
+        Ok(Value::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> serde_json::Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> serde_json::Result<Value> {
+        let mut map = Map::new();
+        map.insert(variant.to_string(), value.serialize(self)?);
+        Ok(Value::Object(map))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> serde_json::Result<SeqSerializer> {
+        Ok(SeqSerializer {
+            casing: self.casing,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> serde_json::Result<SeqSerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> serde_json::Result<SeqSerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> serde_json::Result<VariantSerializer<SeqSerializer>> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> serde_json::Result<MapSerializer> {
+        Ok(MapSerializer {
+            casing: self.casing,
+            // Serde writes flattened struct fields as entries of a map of
+            // unknown length, while actual maps always know theirs.
+            rename_keys: len.is_none(),
+            map: Map::new(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> serde_json::Result<MapSerializer> {
+        Ok(MapSerializer {
+            casing: self.casing,
+            rename_keys: true,
+            map: Map::new(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> serde_json::Result<VariantSerializer<MapSerializer>> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_struct(name, len)?,
+        })
+    }
+}
+
+struct SeqSerializer {
+    casing: Casing,
+    items: Vec<Value>,
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> serde_json::Result<()> {
+        self.items.push(to_value(value, self.casing)?);
+        Ok(())
+    }
+
+    fn end(self) -> serde_json::Result<Value> {
+        Ok(Value::Array(self.items))
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> serde_json::Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> serde_json::Result<Value> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> serde_json::Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> serde_json::Result<Value> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct MapSerializer {
+    casing: Casing,
+    rename_keys: bool,
+    map: Map<String, Value>,
+    next_key: Option<String>,
+}
+
+impl MapSerializer {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> serde_json::Result<()> {
+        let key = match self.rename_keys {
+            true => self.casing.apply(key).into_owned(),
+            false => key.to_string(),
+        };
+        self.map.insert(key, to_value(value, self.casing)?);
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> serde_json::Result<()> {
+        let key = match serde_json::to_value(key)? {
+            Value::String(key) => key,
+            Value::Number(key) => key.to_string(),
+            Value::Bool(key) => key.to_string(),
+            _ => return Err(ser::Error::custom("key must be a string")),
+        };
+        self.next_key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> serde_json::Result<()> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| ser::Error::custom("serialize_value called before serialize_key"))?;
+        self.insert(&key, value)
+    }
+
+    fn end(self) -> serde_json::Result<Value> {
+        Ok(Value::Object(self.map))
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> serde_json::Result<()> {
+        self.insert(key, value)
+    }
+
+    fn end(self) -> serde_json::Result<Value> {
+        Ok(Value::Object(self.map))
+    }
+}
+
+/// An enum variant holding a tuple or struct, written as an object with
+/// the variant's name as its only key.
+struct VariantSerializer<T> {
+    variant: &'static str,
+    inner: T,
+}
+
+impl<T> VariantSerializer<T> {
+    fn wrap(variant: &'static str, value: Value) -> Value {
+        let mut map = Map::new();
+        map.insert(variant.to_string(), value);
+        Value::Object(map)
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<SeqSerializer> {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> serde_json::Result<()> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> serde_json::Result<Value> {
+        let value = ser::SerializeSeq::end(self.inner)?;
+        Ok(Self::wrap(self.variant, value))
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<MapSerializer> {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> serde_json::Result<()> {
+        self.inner.insert(key, value)
+    }
+
+    fn end(self) -> serde_json::Result<Value> {
+        let value = ser::SerializeStruct::end(self.inner)?;
+        Ok(Self::wrap(self.variant, value))
+    }
+}
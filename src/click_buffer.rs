@@ -0,0 +1,452 @@
+//! Clicks are appended to a local write-ahead file before the redirect is
+//! answered and written to the database in batches. The file is rotated
+//! into a numbered segment on every flush and a segment is only removed once
+//! its clicks are committed, so a crash between flushes loses nothing: the
+//! write-ahead file and segments left behind are replayed on startup. Each
+//! click carries an id that is unique in `link_statistics`, which makes
+//! replaying a segment that had already been committed harmless.
+
+use crate::configuration::Settings;
+use crate::routes::notify_new_click;
+use crate::task_health;
+
+// The registry served on /metrics is the one axum-prometheus records into.
+use axum_prometheus::metrics::{counter, gauge};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Name of the flusher in the task health report.
+const FLUSHER_TASK: &str = "click_flusher";
+
+/// What to do with a click while the buffer is full. The redirect is served
+/// whatever happens to the click.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackpressurePolicy {
+    /// Drop the click.
+    Drop,
+    /// Keep one click in every `n`, dropping the others.
+    Sample(u64),
+    /// Wait up to the deadline for a flush to make room, then drop it.
+    Block(Duration),
+}
+
+impl BackpressurePolicy {
+    /// Parse `drop`, `sample:<n>` or `block:<milliseconds>`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().split_once(':') {
+            None if value.trim().eq_ignore_ascii_case("drop") => Some(BackpressurePolicy::Drop),
+            Some(("sample", n)) => n
+                .parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .map(BackpressurePolicy::Sample),
+            Some(("block", milliseconds)) => milliseconds
+                .parse()
+                .ok()
+                .map(|milliseconds| BackpressurePolicy::Block(Duration::from_millis(milliseconds))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferedClick {
+    pub click_id: String,
+    pub link_id: String,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    /// ISO 3166 code of the visitor's country, as told by the CDN.
+    #[serde(default)]
+    pub country: Option<String>,
+    pub clicked_at: NaiveDateTime,
+    /// How many clicks this one stands for, see `links.click_sample_rate`.
+    #[serde(default = "unsampled")]
+    pub sample_rate: i32,
+    /// Salted hash telling the visitor apart from others of the day, see
+    /// `visitor_salt`.
+    #[serde(default)]
+    pub visitor_hash: Option<String>,
+    /// Whether the user agent is a bot's, see `user_agent`.
+    #[serde(default)]
+    pub is_bot: bool,
+    #[serde(default)]
+    pub browser: Option<String>,
+    #[serde(default)]
+    pub os: Option<String>,
+}
+
+fn unsampled() -> i32 {
+    1
+}
+
+impl BufferedClick {
+    pub fn new(
+        link_id: String,
+        referer: Option<String>,
+        user_agent: Option<String>,
+        country: Option<String>,
+        sample_rate: i32,
+    ) -> Self {
+        Self {
+            click_id: Uuid::new_v4().simple().to_string(),
+            link_id,
+            referer,
+            user_agent,
+            country,
+            clicked_at: chrono::Utc::now().naive_utc(),
+            sample_rate,
+            visitor_hash: None,
+            is_bot: false,
+            browser: None,
+            os: None,
+        }
+    }
+}
+
+struct Segment {
+    path: PathBuf,
+    clicks: Vec<BufferedClick>,
+}
+
+struct BufferState {
+    file: File,
+    pending: Vec<BufferedClick>,
+    next_segment: u64,
+}
+
+pub struct ClickBuffer {
+    path: PathBuf,
+    /// Sync every append to disk, surviving power loss as well as crashes.
+    fsync: bool,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    state: Mutex<BufferState>,
+    /// Clicks recorded but not yet committed, including failed segments.
+    unflushed: AtomicUsize,
+    /// Clicks that arrived while the buffer was full, for sampling.
+    overflowed: AtomicU64,
+    /// Woken after every flush, for clicks waiting for room.
+    room: Notify,
+}
+
+impl ClickBuffer {
+    pub fn open(settings: &Settings) -> std::io::Result<Self> {
+        let path = settings.click_buffer_path.clone();
+
+        if let Some(directory) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(directory)?;
+        }
+
+        let mut next_segment = segment_paths(&path)?
+            .iter()
+            .filter_map(|segment| segment_number(&path, segment))
+            .max()
+            .map_or(0, |number| number + 1);
+
+        // What a previous process left in the write-ahead file becomes a
+        // segment, to be replayed by the flusher along with the others.
+        if std::fs::metadata(&path).is_ok_and(|metadata| metadata.len() > 0) {
+            std::fs::rename(&path, segment_path(&path, next_segment))?;
+            next_segment += 1;
+        }
+
+        Ok(Self {
+            state: Mutex::new(BufferState {
+                file: open_append(&path)?,
+                pending: Vec::new(),
+                next_segment,
+            }),
+            path,
+            fsync: settings.click_buffer_fsync,
+            capacity: settings.click_buffer_capacity,
+            policy: settings.click_backpressure,
+            unflushed: AtomicUsize::new(0),
+            overflowed: AtomicU64::new(0),
+            room: Notify::new(),
+        })
+    }
+
+    /// Buffer a click, applying the backpressure policy when the buffer is
+    /// full. Once this returns a buffered click survives a crash of the
+    /// process.
+    pub async fn record(&self, click: BufferedClick) -> std::io::Result<()> {
+        if let Some(reason) = self.reject().await {
+            counter!("click_buffer_dropped", "reason" => reason).increment(1);
+            return Ok(());
+        }
+
+        self.append(click)
+    }
+
+    /// Why the next click should be dropped, if it should.
+    async fn reject(&self) -> Option<&'static str> {
+        if !self.is_full() {
+            return None;
+        }
+
+        match self.policy {
+            BackpressurePolicy::Drop => Some("full"),
+            BackpressurePolicy::Sample(n) => {
+                let overflowed = self.overflowed.fetch_add(1, Ordering::Relaxed);
+                (!overflowed.is_multiple_of(n)).then_some("sampled")
+            }
+            BackpressurePolicy::Block(deadline) => {
+                (!self.wait_for_room(deadline).await).then_some("deadline")
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.unflushed.load(Ordering::Relaxed) >= self.capacity
+    }
+
+    async fn wait_for_room(&self, deadline: Duration) -> bool {
+        let room = async {
+            loop {
+                // Created before checking, so a flush in between still wakes us.
+                let flushed = self.room.notified();
+                if !self.is_full() {
+                    return;
+                }
+                flushed.await;
+            }
+        };
+
+        tokio::time::timeout(deadline, room).await.is_ok()
+    }
+
+    fn append(&self, click: BufferedClick) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(&click)?;
+        line.push(b'\n');
+
+        let mut state = self.lock();
+        state.file.write_all(&line)?;
+        if self.fsync {
+            state.file.sync_data()?;
+        }
+        state.pending.push(click);
+        self.unflushed.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BufferState> {
+        self.state
+            .lock()
+            .expect("The click buffer lock should never be poisoned")
+    }
+
+    fn leftover_segments(&self) -> std::io::Result<Vec<Segment>> {
+        segment_paths(&self.path)?
+            .into_iter()
+            .map(|path| {
+                Ok(Segment {
+                    clicks: read_clicks(&path)?,
+                    path,
+                })
+            })
+            .collect()
+    }
+
+    /// Move the clicks recorded so far into a segment of their own and start
+    /// a fresh write-ahead file.
+    fn rotate(&self) -> std::io::Result<Option<Segment>> {
+        let mut state = self.lock();
+        if state.pending.is_empty() {
+            return Ok(None);
+        }
+
+        let segment = segment_path(&self.path, state.next_segment);
+        state.file.sync_data()?;
+        std::fs::rename(&self.path, &segment)?;
+        state.file = open_append(&self.path)?;
+        state.next_segment += 1;
+
+        Ok(Some(Segment {
+            path: segment,
+            clicks: std::mem::take(&mut state.pending),
+        }))
+    }
+
+    /// Flush buffered clicks to the database for as long as the process runs,
+    /// starting with the segments found on disk. Segments that fail to
+    /// commit stay on disk and are retried.
+    pub async fn run_flusher(self: Arc<Self>, db: PgPool) {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        task_health::register(FLUSHER_TASK, FLUSH_INTERVAL);
+        let mut failed = match self.leftover_segments() {
+            Ok(segments) => segments,
+            Err(err) => {
+                tracing::error!("Could not read leftover click segments: {}", err);
+                Vec::new()
+            }
+        };
+        if !failed.is_empty() {
+            tracing::info!("Replaying {} leftover click segments", failed.len());
+        }
+        let leftover_clicks = failed.iter().map(|segment| segment.clicks.len()).sum();
+        self.unflushed.fetch_add(leftover_clicks, Ordering::Relaxed);
+
+        loop {
+            interval.tick().await;
+
+            match self.rotate() {
+                Ok(Some(segment)) => failed.push(segment),
+                Ok(None) => {}
+                Err(err) => tracing::error!("Could not rotate the click buffer: {}", err),
+            }
+
+            let mut still_failing = Vec::new();
+            let mut last_error = None;
+            for segment in failed.drain(..) {
+                match commit_segment(&db, &segment).await {
+                    Ok(()) => {
+                        self.unflushed
+                            .fetch_sub(segment.clicks.len(), Ordering::Relaxed);
+                    }
+                    Err(err) => {
+                        tracing::error!("Could not flush buffered clicks: {}", err);
+                        still_failing.push(segment);
+                        last_error = Some(err);
+                    }
+                }
+            }
+            failed = still_failing;
+
+            self.room.notify_waiters();
+            let unflushed = self.unflushed.load(Ordering::Relaxed);
+            gauge!("click_buffer_unflushed").set(unflushed as f64);
+            task_health::report_queue_depth(FLUSHER_TASK, unflushed as i64);
+            match last_error {
+                Some(err) => task_health::failed(FLUSHER_TASK, &err),
+                None => task_health::succeeded(FLUSHER_TASK),
+            }
+        }
+    }
+}
+
+/// Insert the segment's clicks, then drop the segment file.
+async fn commit_segment(db: &PgPool, segment: &Segment) -> Result<(), sqlx::Error> {
+    let mut click_ids = Vec::with_capacity(segment.clicks.len());
+    let mut link_ids = Vec::with_capacity(segment.clicks.len());
+    let mut referers = Vec::with_capacity(segment.clicks.len());
+    let mut user_agents = Vec::with_capacity(segment.clicks.len());
+    let mut countries = Vec::with_capacity(segment.clicks.len());
+    let mut clicked_at = Vec::with_capacity(segment.clicks.len());
+    let mut sample_rates = Vec::with_capacity(segment.clicks.len());
+    let mut visitor_hashes = Vec::with_capacity(segment.clicks.len());
+    let mut bots = Vec::with_capacity(segment.clicks.len());
+    let mut browsers = Vec::with_capacity(segment.clicks.len());
+    let mut oses = Vec::with_capacity(segment.clicks.len());
+    for click in &segment.clicks {
+        click_ids.push(click.click_id.clone());
+        link_ids.push(click.link_id.clone());
+        referers.push(click.referer.clone());
+        user_agents.push(click.user_agent.clone());
+        countries.push(click.country.clone());
+        clicked_at.push(click.clicked_at);
+        sample_rates.push(click.sample_rate);
+        visitor_hashes.push(click.visitor_hash.clone());
+        bots.push(click.is_bot);
+        browsers.push(click.browser.clone());
+        oses.push(click.os.clone());
+    }
+
+    // Clicks on links deleted in the meantime are dropped.
+    let statistic_ids: Vec<i32> = sqlx::query_scalar(
+        r#"INSERT INTO link_statistics (click_id, link_id, referer, user_agent, created_at, sample_rate, country, visitor_hash, is_bot, browser, os)
+        SELECT clicks.* FROM unnest($1::text[], $2::text[], $3::text[], $4::text[], $5::timestamp[], $6::int[], $7::text[], $8::text[], $9::bool[], $10::text[], $11::text[])
+            AS clicks (click_id, link_id, referer, user_agent, created_at, sample_rate, country, visitor_hash, is_bot, browser, os)
+        WHERE EXISTS (SELECT 1 FROM links WHERE links.id = clicks.link_id)
+        ON CONFLICT (click_id) DO NOTHING
+        RETURNING id"#,
+    )
+    .bind(click_ids)
+    .bind(link_ids)
+    .bind(referers)
+    .bind(user_agents)
+    .bind(clicked_at)
+    .bind(sample_rates)
+    .bind(countries)
+    .bind(visitor_hashes)
+    .bind(bots)
+    .bind(browsers)
+    .bind(oses)
+    .fetch_all(db)
+    .await?;
+
+    if let Err(err) = std::fs::remove_file(&segment.path) {
+        tracing::error!("Could not remove flushed click segment: {}", err);
+    }
+
+    for statistic_id in statistic_ids {
+        notify_new_click(db.clone(), statistic_id);
+    }
+
+    Ok(())
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn segment_path(path: &Path, number: u64) -> PathBuf {
+    let mut segment = path.as_os_str().to_owned();
+    segment.push(format!(".{:010}", number));
+    PathBuf::from(segment)
+}
+
+fn segment_number(path: &Path, segment: &Path) -> Option<u64> {
+    let prefix = format!("{}.", path.file_name()?.to_str()?);
+    segment
+        .file_name()?
+        .to_str()?
+        .strip_prefix(&prefix)?
+        .parse()
+        .ok()
+}
+
+/// Segments left next to the write-ahead file, oldest first.
+fn segment_paths(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut segments: Vec<(u64, PathBuf)> = std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter_map(|segment| Some((segment_number(path, &segment)?, segment)))
+        .collect();
+    segments.sort();
+
+    Ok(segments.into_iter().map(|(_, segment)| segment).collect())
+}
+
+/// Read the clicks of a file, skipping a line torn by a crash mid-write.
+fn read_clicks(path: &Path) -> std::io::Result<Vec<BufferedClick>> {
+    let mut clicks = Vec::new();
+
+    for line in BufReader::new(File::open(path)?).lines() {
+        match serde_json::from_str(&line?) {
+            Ok(click) => clicks.push(click),
+            Err(err) => tracing::warn!("Skipping unreadable buffered click: {}", err),
+        }
+    }
+
+    Ok(clicks)
+}
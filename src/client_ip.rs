@@ -0,0 +1,200 @@
+//! The address a request came from, believing `Forwarded` and
+//! `X-Forwarded-For` only when the connection is from a proxy listed in
+//! `TRUSTED_PROXIES`. Anyone can send those headers, so with no proxies
+//! configured the peer address is the client and the headers are ignored.
+
+use crate::configuration::Settings;
+use crate::InnerState;
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use std::net::{IpAddr, SocketAddr};
+
+/// An address, or a network of them in CIDR notation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpNetwork {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+
+        let network: IpAddr = address.trim().parse().ok()?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|p| *p <= max_prefix)?,
+            None => max_prefix,
+        };
+
+        Some(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let max_prefix = if self.network.is_ipv4() { 32 } else { 128 };
+        if self.prefix == max_prefix {
+            write!(f, "{}", self.network)
+        } else {
+            write!(f, "{}/{}", self.network, self.prefix)
+        }
+    }
+}
+
+/// Parse a comma separated `TRUSTED_PROXIES` list.
+pub fn parse_trusted_proxies(value: &str) -> Option<Vec<IpNetwork>> {
+    value
+        .split(',')
+        .filter(|proxy| !proxy.trim().is_empty())
+        .map(IpNetwork::parse)
+        .collect()
+}
+
+pub struct ClientIp {
+    pub ip: IpAddr,
+    /// Whether the connection came from a trusted proxy, whose other
+    /// headers, such as the country a CDN adds, may be believed too.
+    pub proxied: bool,
+}
+
+impl ClientIp {
+    /// The ISO 3166 country code the CDN reports in `COUNTRY_HEADER`, only
+    /// believed from a trusted proxy.
+    pub fn country(&self, settings: &Settings, headers: &HeaderMap) -> Option<String> {
+        settings
+            .country_header
+            .as_ref()
+            .filter(|_| self.proxied)
+            .and_then(|header| headers.get(header))
+            .and_then(|value| value.to_str().ok())
+            .map(|country| country.trim().to_ascii_uppercase())
+            .filter(|country| country.len() == 2)
+    }
+}
+
+#[axum::async_trait]
+impl FromRequestParts<InnerState> for ClientIp {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &InnerState,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_canonical())
+            .ok_or_else(|| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Missing peer address".to_string(),
+                )
+            })?;
+
+        Ok(client_ip(
+            &state.settings.trusted_proxies,
+            peer,
+            &parts.headers,
+        ))
+    }
+}
+
+/// Walk the forwarded hops from the nearest one back, past every trusted
+/// proxy, and take the first address that is not one. A hop that cannot be
+/// read ends the walk at the proxy that reported it.
+pub fn client_ip(trusted_proxies: &[IpNetwork], peer: IpAddr, headers: &HeaderMap) -> ClientIp {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(ip));
+
+    if !is_trusted(peer) {
+        return ClientIp {
+            ip: peer,
+            proxied: false,
+        };
+    }
+
+    let hops = if headers.contains_key("forwarded") {
+        header_values(headers, "forwarded")
+            .map(forwarded_for)
+            .collect::<Vec<_>>()
+    } else {
+        header_values(headers, "x-forwarded-for")
+            .map(parse_hop)
+            .collect::<Vec<_>>()
+    };
+
+    let mut ip = peer;
+    for hop in hops.into_iter().rev() {
+        match hop {
+            Some(hop) => {
+                ip = hop;
+                if !is_trusted(hop) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+
+    ClientIp { ip, proxied: true }
+}
+
+/// Every comma separated element of every instance of a header.
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(str::trim)
+}
+
+/// The `for` parameter of a `Forwarded` element.
+fn forwarded_for(element: &str) -> Option<IpAddr> {
+    element.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("for")
+            .then(|| parse_hop(value.trim().trim_matches('"')))
+            .flatten()
+    })
+}
+
+/// An address as proxies write it: bare, or with a port, IPv6 then in
+/// brackets. Obfuscated identifiers and `unknown` are not addresses.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    if let Ok(ip) = hop.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+
+    if let Some(rest) = hop.strip_prefix('[') {
+        let (ip, _) = rest.split_once(']')?;
+        return ip.parse::<IpAddr>().ok().map(|ip| ip.to_canonical());
+    }
+
+    hop.parse::<SocketAddr>()
+        .ok()
+        .map(|addr| addr.ip().to_canonical())
+}
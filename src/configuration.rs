@@ -0,0 +1,488 @@
+use crate::casing::Casing;
+use crate::click_buffer::BackpressurePolicy;
+use crate::client_ip::{parse_trusted_proxies, IpNetwork};
+use crate::db::fault_injection::Faults;
+use crate::host_rules::{parse_host_patterns, HostPattern, DEFAULT_BLOCKED_HOSTS};
+use crate::sealing::SealingKey;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Runtime settings read from the environment at startup.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Scheme and host short links are served from, without a trailing slash.
+    pub public_base_url: String,
+    /// Show a consent interstitial before storing anything beyond a bare
+    /// click count for a visitor.
+    pub require_tracking_consent: bool,
+    /// Signing secret of the Slack app serving the slash commands.
+    pub slack_signing_secret: Option<String>,
+    /// Hex encoded Ed25519 public key of the Discord application.
+    pub discord_public_key: Option<String>,
+    /// Theme directory holding `<kind>.html` page templates, optionally
+    /// nested in a directory per domain. Read on every render, so a theme
+    /// can be changed without a restart.
+    pub theme_dir: Option<PathBuf>,
+    /// Write-ahead file of clicks not yet flushed to the database.
+    pub click_buffer_path: PathBuf,
+    /// Sync the click buffer to disk on every click, not just every flush.
+    pub click_buffer_fsync: bool,
+    /// Unflushed clicks the buffer holds before applying backpressure.
+    pub click_buffer_capacity: usize,
+    pub click_backpressure: BackpressurePolicy,
+    /// File holding the snapshot of every link redirects are served from.
+    /// Redirects query the database directly when unset.
+    pub redirect_snapshot_path: Option<PathBuf>,
+    /// How often the redirect snapshot is rebuilt, which bounds how long a
+    /// changed or disabled link keeps redirecting as before.
+    pub redirect_snapshot_interval: Duration,
+    /// Redis caching the targets of links the snapshot does not have, as
+    /// `redis://[[user]:password@]host[:port][/database]`. Cached in memory
+    /// instead when unset.
+    pub redis_url: Option<String>,
+    /// Links the in-memory redirect cache holds; none are when zero.
+    pub redirect_cache_capacity: usize,
+    /// How long a cached redirect target is kept, which bounds how long a
+    /// link changed other than through the API redirects as before.
+    pub redirect_cache_ttl: Duration,
+    /// Turn away requests for link ids known not to exist before querying.
+    pub link_filter: bool,
+    /// Apply pending migrations on startup. When off, the server refuses to
+    /// start on a schema that is behind.
+    pub auto_migrate: bool,
+    /// Repair what the startup integrity checks find instead of refusing
+    /// writes until an operator does.
+    pub integrity_auto_repair: bool,
+    /// Start in read-only mode, refusing requests changing data until an
+    /// operator lifts it.
+    pub read_only: bool,
+    /// Address HTTPS is served on directly, with a certificate per domain
+    /// from `tls_certificates`. Only plain HTTP is served when unset.
+    pub tls_listen_address: Option<SocketAddr>,
+    /// ACME directory managed domains get their certificates from, such as
+    /// Let's Encrypt's. None are issued when unset.
+    pub acme_directory_url: Option<String>,
+    /// Where the ACME authority sends notices about the account.
+    pub acme_contact_email: Option<String>,
+    /// Base URL of the hook publishing DNS-01 challenge records, needed for
+    /// wildcard certificates. Only HTTP-01 challenges are met when unset.
+    pub acme_dns_hook_url: Option<String>,
+    /// How long a published challenge record is given to reach the
+    /// authority's resolvers.
+    pub acme_dns_propagation_delay: Duration,
+    /// Key sealing the private keys of certificates and of the ACME account
+    /// in the database. They are stored as is when unset.
+    pub certificate_encryption_key: Option<SealingKey>,
+    /// Zone the CNAME records verifying custom domains point below, as
+    /// `<token>.<zone>`. Only TXT verification is offered when unset.
+    pub domain_verification_zone: Option<String>,
+    /// DNS over HTTPS endpoint answering in the JSON format, which custom
+    /// domain records are looked up through.
+    pub dns_resolver_url: String,
+    /// How often the link filter is rebuilt from scratch. New links are
+    /// added as they are created, so this mostly resizes it and drops
+    /// deleted ids.
+    pub link_filter_interval: Duration,
+    /// Links a user may create per calendar month. Unlimited when unset.
+    pub monthly_link_quota: Option<i64>,
+    /// Percentages of the quota at which the owner is warned, once each per
+    /// month.
+    pub quota_warning_thresholds: Vec<i32>,
+    /// Postmark template of the quota warning email. Warnings are only
+    /// logged when unset.
+    pub quota_warning_template_id: Option<String>,
+    /// Prometheus remote-write endpoint per-link click counters are pushed
+    /// to. Nothing is pushed when unset.
+    pub remote_write_url: Option<String>,
+    pub remote_write_bearer_token: Option<String>,
+    pub remote_write_interval: Duration,
+    /// Request header a CDN puts the visitor's country code in, such as
+    /// `CF-IPCountry`. Countries are not recorded when unset, nor for
+    /// requests that did not come through a trusted proxy.
+    pub country_header: Option<String>,
+    /// Proxies whose `Forwarded` and `X-Forwarded-For` headers are believed,
+    /// as addresses or CIDR networks. None by default, for a service
+    /// exposed directly.
+    pub trusted_proxies: Vec<IpNetwork>,
+    /// Casing of the field names in responses, `camelCase` unless
+    /// `RESPONSE_CASING` is `snake_case`.
+    pub response_casing: Casing,
+    /// Repository calls taking at least this long are logged, counted and
+    /// listed at `/admin/slow-queries`.
+    pub slow_query_threshold: Duration,
+    /// Links a client address may create per minute, zero for no limit.
+    pub link_creation_rate_limit_per_ip: u32,
+    /// Links an account may create per minute, from any address.
+    pub link_creation_rate_limit_per_account: u32,
+    /// Redirects a client address may follow per minute.
+    pub redirect_rate_limit_per_ip: u32,
+    /// Share of redirects that must answer within `redirect_slo_latency`
+    /// and without a server error, `0.99` by default.
+    pub redirect_slo_objective: f64,
+    pub redirect_slo_latency: Duration,
+    /// How far back the error budget of the redirect SLO reaches.
+    pub redirect_slo_window: Duration,
+    /// Burn rates of the error budget that raise the fast burn alert, over
+    /// an hour and five minutes, and the slow burn one, over six hours and
+    /// half an hour.
+    pub redirect_slo_fast_burn_rate: f64,
+    pub redirect_slo_slow_burn_rate: f64,
+    /// Where SLO alerts are posted as they start and stop firing.
+    pub slo_alert_webhook_url: Option<String>,
+    /// Delays, timeouts and errors injected into repository calls, none by
+    /// default. Only builds with the `fault-injection` feature take them.
+    pub fault_injection: Faults,
+    /// How long access tokens issued by `/auth/login` and `/auth/refresh`
+    /// stay valid, 15 minutes by default.
+    pub access_token_lifetime: Duration,
+    /// How long a session may go without being refreshed before it has to
+    /// log in again, 30 days by default.
+    pub refresh_token_lifetime: Duration,
+    /// Page logins through Google or GitHub end on, given the tokens in its
+    /// fragment. Without one the callback answers with the tokens as JSON.
+    pub oauth_login_redirect_url: Option<String>,
+    /// Clicks a referer or user agent needs before statistics break it out
+    /// on its own, so a link with few visitors cannot tell who they were.
+    /// Smaller ones are counted together as `other`. Zero, the default,
+    /// shows every one.
+    pub statistics_min_bucket_clicks: i64,
+    /// Postmark template of the email inviting someone into an
+    /// organization. Invitations are only logged when unset.
+    pub invitation_template_id: Option<String>,
+    /// Postmark template of the email telling users of a login from a new
+    /// device or country. Such logins are only logged when unset.
+    pub new_device_template_id: Option<String>,
+    /// Days raw clicks are kept once rolled up, after which only the daily
+    /// rollup and its hourly gaps remain. Kept forever when unset.
+    pub raw_click_retention_days: Option<i32>,
+    /// Days of the daily statistics rollup kept. Kept forever when unset.
+    pub rollup_retention_days: Option<i32>,
+    /// Days entries of the admin audit log are kept. Kept forever when
+    /// unset.
+    pub audit_log_retention_days: Option<i32>,
+    /// Key sealing the encryption keys organizations bring. Organizations
+    /// cannot set one when unset.
+    pub organization_key_encryption_key: Option<SealingKey>,
+    /// Google Safe Browsing API key target URLs are checked with. Targets
+    /// are not checked when unset.
+    pub safe_browsing_api_key: Option<String>,
+    /// Base URL of the Safe Browsing API.
+    pub safe_browsing_url: String,
+    /// Refuse targets the scanner finds unsafe, instead of creating the
+    /// link and flagging it for review.
+    pub reject_unsafe_links: bool,
+    /// Hosts link targets may not point at, as domains, addresses or CIDR
+    /// networks. Loopback, link-local and private addresses by default.
+    pub blocked_hosts: Vec<HostPattern>,
+    /// Hosts link targets may point at even when blocked.
+    pub allowed_hosts: Vec<HostPattern>,
+}
+
+impl Settings {
+    pub fn from_env() -> Self {
+        Self {
+            public_base_url: std::env::var("PUBLIC_BASE_URL")
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or_else(|_| "https://groupify.dev".to_string()),
+            require_tracking_consent: env_flag("REQUIRE_TRACKING_CONSENT", false),
+            slack_signing_secret: std::env::var("SLACK_SIGNING_SECRET").ok(),
+            discord_public_key: std::env::var("DISCORD_PUBLIC_KEY").ok(),
+            theme_dir: std::env::var("THEME_DIR").ok().map(PathBuf::from),
+            click_buffer_path: std::env::var("CLICK_BUFFER_PATH")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from("data/clicks.wal")),
+            click_buffer_fsync: env_flag("CLICK_BUFFER_FSYNC", false),
+            click_buffer_capacity: std::env::var("CLICK_BUFFER_CAPACITY")
+                .ok()
+                .and_then(|capacity| capacity.parse().ok())
+                .unwrap_or(100_000),
+            click_backpressure: std::env::var("CLICK_BACKPRESSURE")
+                .map(|policy| {
+                    BackpressurePolicy::parse(&policy).expect(
+                        "CLICK_BACKPRESSURE should be drop, sample:<n> or block:<milliseconds>",
+                    )
+                })
+                .unwrap_or(BackpressurePolicy::Drop),
+            redirect_snapshot_path: std::env::var("REDIRECT_SNAPSHOT_PATH")
+                .ok()
+                .map(PathBuf::from),
+            redirect_snapshot_interval: std::env::var("REDIRECT_SNAPSHOT_INTERVAL_SECONDS")
+                .ok()
+                .and_then(|seconds| seconds.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(60)),
+            redis_url: std::env::var("REDIS_URL").ok(),
+            redirect_cache_capacity: std::env::var("REDIRECT_CACHE_CAPACITY")
+                .map(|capacity| {
+                    capacity
+                        .parse()
+                        .expect("REDIRECT_CACHE_CAPACITY should be a number of links")
+                })
+                .unwrap_or(10_000),
+            redirect_cache_ttl: std::env::var("REDIRECT_CACHE_TTL_SECONDS")
+                .ok()
+                .and_then(|seconds| seconds.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(300)),
+            link_filter: env_flag("LINK_FILTER", false),
+            auto_migrate: env_flag("AUTO_MIGRATE", true),
+            integrity_auto_repair: env_flag("INTEGRITY_AUTO_REPAIR", false),
+            read_only: env_flag("READ_ONLY", false),
+            tls_listen_address: std::env::var("TLS_LISTEN_ADDRESS").ok().map(|address| {
+                address
+                    .parse()
+                    .expect("TLS_LISTEN_ADDRESS should be an address and port, like 0.0.0.0:443")
+            }),
+            acme_directory_url: std::env::var("ACME_DIRECTORY_URL").ok(),
+            acme_contact_email: std::env::var("ACME_CONTACT_EMAIL").ok(),
+            acme_dns_hook_url: std::env::var("ACME_DNS_HOOK_URL").ok(),
+            acme_dns_propagation_delay: std::env::var("ACME_DNS_PROPAGATION_SECONDS")
+                .ok()
+                .and_then(|seconds| seconds.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(30)),
+            certificate_encryption_key: std::env::var("CERTIFICATE_ENCRYPTION_KEY").ok().map(
+                |key| {
+                    SealingKey::from_base64(&key)
+                        .expect("CERTIFICATE_ENCRYPTION_KEY should be 32 base64 encoded bytes")
+                },
+            ),
+            domain_verification_zone: std::env::var("DOMAIN_VERIFICATION_ZONE").ok(),
+            dns_resolver_url: std::env::var("DNS_RESOLVER_URL")
+                .unwrap_or_else(|_| "https://cloudflare-dns.com/dns-query".to_string()),
+            link_filter_interval: std::env::var("LINK_FILTER_INTERVAL_SECONDS")
+                .ok()
+                .and_then(|seconds| seconds.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(3600)),
+            monthly_link_quota: std::env::var("MONTHLY_LINK_QUOTA")
+                .ok()
+                .and_then(|quota| quota.parse().ok()),
+            quota_warning_thresholds: std::env::var("QUOTA_WARNING_THRESHOLDS")
+                .map(|thresholds| {
+                    thresholds
+                        .split(',')
+                        .map(|threshold| {
+                            threshold
+                                .trim()
+                                .parse()
+                                .ok()
+                                .filter(|threshold| (1..100).contains(threshold))
+                                .expect("QUOTA_WARNING_THRESHOLDS should list percentages below 100")
+                        })
+                        .collect()
+                })
+                .unwrap_or_else(|_| vec![80, 95]),
+            quota_warning_template_id: std::env::var("QUOTA_WARNING_TEMPLATE_ID").ok(),
+            remote_write_url: std::env::var("REMOTE_WRITE_URL").ok(),
+            remote_write_bearer_token: std::env::var("REMOTE_WRITE_BEARER_TOKEN").ok(),
+            remote_write_interval: std::env::var("REMOTE_WRITE_INTERVAL_SECONDS")
+                .ok()
+                .and_then(|seconds| seconds.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(60)),
+            country_header: std::env::var("COUNTRY_HEADER").ok(),
+            trusted_proxies: std::env::var("TRUSTED_PROXIES")
+                .map(|proxies| {
+                    parse_trusted_proxies(&proxies)
+                        .expect("TRUSTED_PROXIES should list IP addresses or CIDR networks")
+                })
+                .unwrap_or_default(),
+            response_casing: std::env::var("RESPONSE_CASING")
+                .map(|casing| {
+                    Casing::parse(&casing)
+                        .expect("RESPONSE_CASING should be camelCase or snake_case")
+                })
+                .unwrap_or(Casing::Camel),
+            slow_query_threshold: std::env::var("SLOW_QUERY_THRESHOLD_MS")
+                .map(|millis| {
+                    millis
+                        .parse()
+                        .map(Duration::from_millis)
+                        .expect("SLOW_QUERY_THRESHOLD_MS should be a number of milliseconds")
+                })
+                .unwrap_or(Duration::from_millis(500)),
+            link_creation_rate_limit_per_ip: env_rate_limit("RATE_LIMIT_LINK_CREATION_PER_IP", 30),
+            link_creation_rate_limit_per_account: env_rate_limit(
+                "RATE_LIMIT_LINK_CREATION_PER_ACCOUNT",
+                120,
+            ),
+            redirect_rate_limit_per_ip: env_rate_limit("RATE_LIMIT_REDIRECTS_PER_IP", 600),
+            redirect_slo_objective: std::env::var("REDIRECT_SLO_OBJECTIVE")
+                .map(|objective| {
+                    objective
+                        .parse()
+                        .ok()
+                        .filter(|objective| *objective > 0.0 && *objective < 1.0)
+                        .expect("REDIRECT_SLO_OBJECTIVE should be a fraction between 0 and 1")
+                })
+                .unwrap_or(0.99),
+            redirect_slo_latency: std::env::var("REDIRECT_SLO_LATENCY_MS")
+                .map(|millis| {
+                    millis
+                        .parse()
+                        .map(Duration::from_millis)
+                        .expect("REDIRECT_SLO_LATENCY_MS should be a number of milliseconds")
+                })
+                .unwrap_or(Duration::from_millis(50)),
+            redirect_slo_window: std::env::var("REDIRECT_SLO_WINDOW_HOURS")
+                .map(|hours| {
+                    hours
+                        .parse()
+                        .ok()
+                        .filter(|hours| *hours > 0)
+                        .map(|hours: u64| Duration::from_secs(hours * 60 * 60))
+                        .expect("REDIRECT_SLO_WINDOW_HOURS should be a number of hours")
+                })
+                .unwrap_or(Duration::from_secs(30 * 24 * 60 * 60)),
+            redirect_slo_fast_burn_rate: env_burn_rate("REDIRECT_SLO_FAST_BURN_RATE", 14.4),
+            redirect_slo_slow_burn_rate: env_burn_rate("REDIRECT_SLO_SLOW_BURN_RATE", 6.0),
+            slo_alert_webhook_url: std::env::var("SLO_ALERT_WEBHOOK_URL").ok(),
+            fault_injection: Faults {
+                delay_probability: env_probability("FAULT_DB_DELAY_PROBABILITY"),
+                delay_ms: env_millis("FAULT_DB_DELAY_MS", 1_000),
+                timeout_probability: env_probability("FAULT_DB_TIMEOUT_PROBABILITY"),
+                timeout_ms: env_millis("FAULT_DB_TIMEOUT_MS", 5_000),
+                error_probability: env_probability("FAULT_DB_ERROR_PROBABILITY"),
+                queries: std::env::var("FAULT_DB_QUERIES")
+                    .map(|queries| {
+                        queries
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|query| !query.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            },
+            access_token_lifetime: std::env::var("ACCESS_TOKEN_LIFETIME_MINUTES")
+                .map(|minutes| {
+                    minutes
+                        .parse()
+                        .ok()
+                        .filter(|minutes| *minutes > 0)
+                        .map(|minutes: u64| Duration::from_secs(minutes * 60))
+                        .expect("ACCESS_TOKEN_LIFETIME_MINUTES should be a number of minutes")
+                })
+                .unwrap_or(Duration::from_secs(15 * 60)),
+            refresh_token_lifetime: std::env::var("REFRESH_TOKEN_LIFETIME_DAYS")
+                .map(|days| {
+                    days.parse()
+                        .ok()
+                        .filter(|days| *days > 0)
+                        .map(|days: u64| Duration::from_secs(days * 24 * 60 * 60))
+                        .expect("REFRESH_TOKEN_LIFETIME_DAYS should be a number of days")
+                })
+                .unwrap_or(Duration::from_secs(30 * 24 * 60 * 60)),
+            oauth_login_redirect_url: std::env::var("OAUTH_LOGIN_REDIRECT_URL").ok(),
+            statistics_min_bucket_clicks: std::env::var("STATISTICS_MIN_BUCKET_CLICKS")
+                .map(|clicks| {
+                    clicks
+                        .parse()
+                        .ok()
+                        .filter(|clicks| *clicks >= 0)
+                        .expect("STATISTICS_MIN_BUCKET_CLICKS should be a number of clicks")
+                })
+                .unwrap_or(0),
+            invitation_template_id: std::env::var("INVITATION_TEMPLATE_ID").ok(),
+            new_device_template_id: std::env::var("NEW_DEVICE_TEMPLATE_ID").ok(),
+            raw_click_retention_days: env_retention_days("RAW_CLICK_RETENTION_DAYS"),
+            rollup_retention_days: env_retention_days("ROLLUP_RETENTION_DAYS"),
+            audit_log_retention_days: env_retention_days("AUDIT_LOG_RETENTION_DAYS"),
+            organization_key_encryption_key: std::env::var("ORGANIZATION_KEY_ENCRYPTION_KEY")
+                .ok()
+                .map(|key| {
+                    SealingKey::from_base64(&key).expect(
+                        "ORGANIZATION_KEY_ENCRYPTION_KEY should be 32 base64 encoded bytes",
+                    )
+                }),
+            safe_browsing_api_key: std::env::var("SAFE_BROWSING_API_KEY").ok(),
+            safe_browsing_url: std::env::var("SAFE_BROWSING_URL")
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or_else(|_| "https://safebrowsing.googleapis.com".to_string()),
+            reject_unsafe_links: env_flag("REJECT_UNSAFE_LINKS", false),
+            blocked_hosts: parse_host_patterns(
+                &std::env::var("BLOCKED_HOSTS")
+                    .unwrap_or_else(|_| DEFAULT_BLOCKED_HOSTS.to_string()),
+            )
+            .expect("BLOCKED_HOSTS should list domains, IP addresses or CIDR networks"),
+            allowed_hosts: std::env::var("ALLOWED_HOSTS")
+                .map(|hosts| {
+                    parse_host_patterns(&hosts)
+                        .expect("ALLOWED_HOSTS should list domains, IP addresses or CIDR networks")
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Read a boolean variable, accepting `1`/`true`/`yes`/`on` in any case.
+pub fn env_flag(name: &str, default: bool) -> bool {
+    std::env::var(name)
+        .map(|value| {
+            matches!(
+                value.to_lowercase().as_str(),
+                "1" | "true" | "yes" | "on"
+            )
+        })
+        .unwrap_or(default)
+}
+
+/// Read how many times faster than the SLO allows an error budget may be
+/// spent.
+fn env_burn_rate(name: &str, default: f64) -> f64 {
+    std::env::var(name)
+        .map(|rate| {
+            rate.parse()
+                .ok()
+                .filter(|rate: &f64| *rate > 0.0)
+                .unwrap_or_else(|| panic!("{} should be a positive number", name))
+        })
+        .unwrap_or(default)
+}
+
+/// Read a chance from 0 to 1, zero when unset.
+fn env_probability(name: &str) -> f64 {
+    std::env::var(name)
+        .map(|probability| {
+            probability
+                .parse()
+                .ok()
+                .filter(|probability: &f64| (0.0..=1.0).contains(probability))
+                .unwrap_or_else(|| panic!("{} should be a number from 0 to 1", name))
+        })
+        .unwrap_or(0.0)
+}
+
+/// Read how many days a class of data is kept, `None` for forever.
+fn env_retention_days(name: &str) -> Option<i32> {
+    std::env::var(name).ok().map(|days| {
+        days.parse()
+            .ok()
+            .filter(|days| *days > 0)
+            .unwrap_or_else(|| panic!("{} should be a number of days", name))
+    })
+}
+
+fn env_millis(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .map(|millis| {
+            millis
+                .parse()
+                .unwrap_or_else(|_| panic!("{} should be a number of milliseconds", name))
+        })
+        .unwrap_or(default)
+}
+
+/// Read a requests per minute limit, where zero means no limit.
+fn env_rate_limit(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .map(|limit| {
+            limit
+                .parse()
+                .unwrap_or_else(|_| panic!("{} should be a number of requests per minute", name))
+        })
+        .unwrap_or(default)
+}
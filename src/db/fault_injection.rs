@@ -0,0 +1,121 @@
+//! Faults injected into repository calls on purpose: delays, timeouts and
+//! errors at chosen rates, so the way failures are handled can be
+//! exercised in integration tests and staging. Only builds with the
+//! `fault-injection` feature inject anything; others refuse to start with
+//! faults configured, so a production deployment cannot pick them up by
+//! accident.
+
+use axum_prometheus::metrics::counter;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Whether this build may inject faults.
+pub const ENABLED: bool = cfg!(feature = "fault-injection");
+
+static FAULTS: Lazy<RwLock<Faults>> = Lazy::new(|| RwLock::new(Faults::default()));
+
+/// Chances, from 0 to 1, of each fault hitting a repository call.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Faults {
+    pub delay_probability: f64,
+    /// How long a delayed call waits before running.
+    pub delay_ms: u64,
+    pub timeout_probability: f64,
+    /// How long a call that times out waits before failing, as one waiting
+    /// for a connection from an exhausted pool would.
+    pub timeout_ms: u64,
+    /// Calls failing right away, as if the connection dropped.
+    pub error_probability: f64,
+    /// Names of the calls faults may hit, such as
+    /// `links::find_redirect_target`, or prefixes ending in `::`. Every
+    /// call when empty.
+    #[serde(default)]
+    pub queries: Vec<String>,
+}
+
+impl Faults {
+    pub fn is_active(&self) -> bool {
+        self.delay_probability > 0.0
+            || self.timeout_probability > 0.0
+            || self.error_probability > 0.0
+    }
+
+    fn applies_to(&self, name: &str) -> bool {
+        self.queries.is_empty()
+            || self.queries.iter().any(|query| {
+                query == name || (query.ends_with("::") && name.starts_with(query.as_str()))
+            })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Faults are configured, but this build does not have the fault-injection feature")]
+pub struct FaultInjectionUnavailable;
+
+/// Replace the faults being injected.
+pub fn set(faults: Faults) -> Result<(), FaultInjectionUnavailable> {
+    if faults.is_active() && !ENABLED {
+        return Err(FaultInjectionUnavailable);
+    }
+    if faults.is_active() {
+        tracing::warn!("Injecting faults into repository calls: {:?}", faults);
+    }
+
+    *FAULTS
+        .write()
+        .expect("The fault injection lock should never be poisoned") = faults;
+
+    Ok(())
+}
+
+pub fn current() -> Faults {
+    FAULTS
+        .read()
+        .expect("The fault injection lock should never be poisoned")
+        .clone()
+}
+
+/// The fault to inject into a call about to run, if any, after waiting as
+/// long as it takes.
+pub async fn inject(name: &'static str) -> Result<(), sqlx::Error> {
+    if !ENABLED {
+        return Ok(());
+    }
+
+    let (delay, timeout, error) = {
+        let faults = FAULTS
+            .read()
+            .expect("The fault injection lock should never be poisoned");
+        if !faults.is_active() || !faults.applies_to(name) {
+            return Ok(());
+        }
+        let roll = |probability: f64| rand::random::<f64>() < probability;
+        (
+            roll(faults.delay_probability).then(|| Duration::from_millis(faults.delay_ms)),
+            roll(faults.timeout_probability).then(|| Duration::from_millis(faults.timeout_ms)),
+            roll(faults.error_probability),
+        )
+    };
+
+    if let Some(delay) = delay {
+        counter!("injected_faults_total", "fault" => "delay", "query" => name).increment(1);
+        tokio::time::sleep(delay).await;
+    }
+    if let Some(timeout) = timeout {
+        counter!("injected_faults_total", "fault" => "timeout", "query" => name).increment(1);
+        tokio::time::sleep(timeout).await;
+        return Err(sqlx::Error::PoolTimedOut);
+    }
+    if error {
+        counter!("injected_faults_total", "fault" => "error", "query" => name).increment(1);
+        return Err(sqlx::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "Injected fault",
+        )));
+    }
+
+    Ok(())
+}
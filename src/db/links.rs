@@ -0,0 +1,1233 @@
+//! Queries on `links` and the statistics recorded for them.
+
+use crate::db::slow_queries::timed;
+use crate::link_payload::{LinkPayload, URL_KIND};
+use crate::link_state::LinkState;
+use crate::open_graph::PagePreview;
+use crate::policy::{self, Resource, Statement};
+use crate::routing_rules::RoutingRule;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use sqlx::{FromRow, PgExecutor, PgPool, Postgres, Transaction};
+
+#[derive(serde::Deserialize, serde::Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Link {
+    pub id: String,
+    /// `url`, or the kind of payload the link carries.
+    pub kind: String,
+    pub target_url: String,
+    #[sqlx(try_from = "String")]
+    pub state: LinkState,
+    /// When the link stops redirecting, answering 410 Gone instead.
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+    /// The custom domain the link was created under.
+    pub domain: Option<String>,
+    /// Free labels, which policies may tell links apart by, as by campaign.
+    pub tags: Vec<String>,
+    pub campaign: Option<String>,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub behavior: RedirectBehavior,
+}
+
+/// A link as a member of a group.
+#[derive(serde::Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupLink {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub link: Link,
+    /// What the public page of the group shows for the link, its target
+    /// when unset.
+    pub title: Option<String>,
+    /// Where the link comes on the page, lowest first.
+    pub position: i32,
+}
+
+/// How a link answers besides where it leads. Custom domains hold defaults
+/// for links created under them, which take whatever they leave unset.
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct RedirectBehavior {
+    /// 301, 302, 307 or 308, 307 when unset.
+    pub redirect_status: Option<i32>,
+    /// How long caches may keep the redirect, five minutes when unset.
+    pub cache_ttl_seconds: Option<i32>,
+    /// Where visitors go once the link is disabled or expired, instead of
+    /// the gone page.
+    pub fallback_url: Option<String>,
+    /// Show where the link leads and let visitors follow it, rather than
+    /// redirecting right away.
+    pub interstitial: Option<bool>,
+}
+
+impl RedirectBehavior {
+    /// This behavior, with what it leaves unset taken from `defaults`.
+    pub fn or(self, defaults: &RedirectBehavior) -> RedirectBehavior {
+        RedirectBehavior {
+            redirect_status: self.redirect_status.or(defaults.redirect_status),
+            cache_ttl_seconds: self.cache_ttl_seconds.or(defaults.cache_ttl_seconds),
+            fallback_url: self.fallback_url.or_else(|| defaults.fallback_url.clone()),
+            interstitial: self.interstitial.or(defaults.interstitial),
+        }
+    }
+}
+
+/// What a redirect needs to know about a link.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, FromRow)]
+pub struct RedirectTarget {
+    pub id: String,
+    pub target_url: String,
+    pub click_sample_rate: i32,
+    #[sqlx(json)]
+    pub routing_rules: Vec<RoutingRule>,
+    /// What a landing page shows instead of redirecting, for links of a
+    /// kind other than `url`.
+    pub payload: Option<sqlx::types::Json<LinkPayload>>,
+    /// Missing from targets cached before links had one, which all
+    /// redirected the default way.
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub behavior: RedirectBehavior,
+    #[serde(default)]
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// A link that exists but no longer redirects.
+#[derive(FromRow)]
+pub struct GoneLink {
+    pub fallback_url: Option<String>,
+}
+
+#[derive(Clone, serde::Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct CounterLinkStatistics {
+    /// Estimated clicks, with sampled clicks scaled by their rate.
+    pub amount: Option<i64>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    /// Whether `amount` is an estimate from sampled clicks.
+    pub sampled: Option<bool>,
+    /// Unique visitors among the recorded clicks, each counted once a day.
+    /// Clicks without tracking consent have no visitor.
+    pub visitors: Option<i64>,
+    /// Whether the clicks are all bots'.
+    pub bot: Option<bool>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+}
+
+/// A click as recorded, before any rollup.
+#[derive(serde::Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedClick {
+    pub created_at: Option<NaiveDateTime>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
+    /// How many clicks this one stands for on sampled links.
+    pub sample_rate: i32,
+    #[serde(skip_serializing)]
+    pub is_bot: bool,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+}
+
+/// A link's clicks in a period and the one before, next to its siblings'.
+#[derive(FromRow)]
+pub struct ComparedLink {
+    pub id: String,
+    pub target_url: String,
+    pub clicks: i64,
+    pub previous_clicks: i64,
+    /// Clicks in the period over every compared link, not just this page.
+    pub total_clicks: i64,
+}
+
+pub struct NewLink<'a> {
+    pub id: &'a str,
+    pub target_url: &'a str,
+    pub organization_id: Option<&'a str>,
+    pub owner_email: Option<&'a str>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub payload: Option<&'a LinkPayload>,
+    /// Created in the `draft` state rather than redirecting right away.
+    pub draft: bool,
+    pub domain: Option<&'a str>,
+    pub behavior: &'a RedirectBehavior,
+    pub tags: &'a [String],
+    pub campaign: Option<&'a str>,
+}
+
+/// A target a link switches to once `change_at` passes.
+#[derive(serde::Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledChange {
+    pub id: i32,
+    pub link_id: String,
+    pub target_url: String,
+    pub change_at: NaiveDateTime,
+    pub created_by: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub applied_at: Option<NaiveDateTime>,
+    /// Why the change was not applied when it came due, such as its target
+    /// having been blocked since.
+    pub refused_reason: Option<String>,
+}
+
+pub struct NewScheduledChange<'a> {
+    pub link_id: &'a str,
+    pub target_url: &'a str,
+    pub change_at: NaiveDateTime,
+    pub created_by: &'a str,
+}
+
+/// Which of a user's links to list.
+pub struct LinkListFilter<'a> {
+    /// Only links whose target contains this, ignoring case.
+    pub target_url: Option<&'a str>,
+    /// First and last day of creation, inclusive.
+    pub created_from: Option<NaiveDate>,
+    pub created_to: Option<NaiveDate>,
+}
+
+/// Where a page of links starts, exclusive.
+pub struct LinkListKey<'a> {
+    pub created_at: Option<NaiveDateTime>,
+    pub id: &'a str,
+}
+
+/// The links whose clicks are added up in a series.
+pub enum ClickScope<'a> {
+    Link(&'a str),
+    Organization(&'a str),
+    Group(&'a str),
+}
+
+/// Where a page of statistics starts, exclusive.
+pub struct StatisticsKey<'a> {
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+pub struct StatisticsPage<'a> {
+    pub after: Option<StatisticsKey<'a>>,
+    pub limit: i64,
+    /// Buckets with fewer clicks are left out, and only counted together by
+    /// [`other_statistics`].
+    pub min_clicks: i64,
+    pub exclude_bots: bool,
+}
+
+/// What the referer and user agent of buckets too small to show on their
+/// own are replaced with once they are counted together.
+pub const OTHER_BUCKET: &str = "other";
+
+pub async fn find_redirect_target(
+    db: &PgPool,
+    link_id: &str,
+) -> Result<Option<RedirectTarget>, sqlx::Error> {
+    timed(
+        "links::find_redirect_target",
+        sqlx::query_as::<_, RedirectTarget>(
+            r#" select id, target_url, click_sample_rate, routing_rules, payload, redirect_status,
+            cache_ttl_seconds, fallback_url, interstitial, expires_at from links where id = $1 and disabled_at is null
+            and (expires_at is null or expires_at > CURRENT_TIMESTAMP)"#,
+        )
+        .bind(link_id)
+        .fetch_optional(db),
+    )
+    .await
+}
+
+/// Links that will expire are left out, so redirects always check their
+/// expiry against the database rather than a snapshot that may be stale,
+/// and so are links with routing rules or their own redirect behavior,
+/// which the snapshot has no room for.
+pub async fn active_redirect_targets(db: &PgPool) -> Result<Vec<RedirectTarget>, sqlx::Error> {
+    timed(
+        "links::active_redirect_targets",
+        sqlx::query_as::<_, RedirectTarget>(
+            r#"SELECT id, target_url, click_sample_rate, routing_rules, payload, redirect_status,
+            cache_ttl_seconds, fallback_url, interstitial, expires_at FROM links
+            WHERE disabled_at IS NULL AND expires_at IS NULL AND routing_rules = '[]'
+            AND payload IS NULL AND redirect_status IS NULL AND cache_ttl_seconds IS NULL
+            AND interstitial IS NOT TRUE"#,
+        )
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// The redirecting links clicked most in the past `hours`, busiest first.
+/// Links that will expire are left out, as the redirect cache never holds
+/// them.
+pub async fn busiest_redirect_targets(
+    db: &PgPool,
+    hours: i32,
+    limit: i64,
+) -> Result<Vec<RedirectTarget>, sqlx::Error> {
+    timed(
+        "links::busiest_redirect_targets",
+        sqlx::query_as::<_, RedirectTarget>(
+            r#"SELECT links.id, links.target_url, links.click_sample_rate, links.routing_rules,
+            links.payload, links.redirect_status, links.cache_ttl_seconds, links.fallback_url,
+            links.interstitial, links.expires_at
+            FROM links JOIN link_statistics ON link_statistics.link_id = links.id
+            WHERE link_statistics.created_at > CURRENT_TIMESTAMP - make_interval(hours => $1)
+            AND links.disabled_at IS NULL AND links.expires_at IS NULL
+            GROUP BY links.id
+            ORDER BY sum(link_statistics.sample_rate) DESC, links.id
+            LIMIT $2"#,
+        )
+        .bind(hours)
+        .bind(limit)
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// Every link id, disabled ones included.
+pub async fn all_link_ids(db: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+    timed(
+        "links::all_link_ids",
+        sqlx::query_scalar(r#"SELECT id FROM links"#).fetch_all(db),
+    )
+    .await
+}
+
+/// Whether the link exists at all, even disabled.
+pub async fn link_exists(db: &PgPool, link_id: &str) -> Result<bool, sqlx::Error> {
+    timed(
+        "links::link_exists",
+        sqlx::query_scalar(r#"select exists(select 1 from links where id = $1)"#)
+            .bind(link_id)
+            .fetch_one(db),
+    )
+    .await
+}
+
+/// The link if it exists but no longer redirects, having been disabled or
+/// having expired rather than deleted.
+pub async fn find_gone_link(db: &PgPool, link_id: &str) -> Result<Option<GoneLink>, sqlx::Error> {
+    timed(
+        "links::find_gone_link",
+        sqlx::query_as::<_, GoneLink>(
+            r#"select fallback_url from links where id = $1 and deleted_at is null"#,
+        )
+        .bind(link_id)
+        .fetch_optional(db),
+    )
+    .await
+}
+
+/// Whether the link exists but no longer redirects, having been disabled or
+/// having expired rather than deleted.
+pub async fn is_gone(db: &PgPool, link_id: &str) -> Result<bool, sqlx::Error> {
+    timed(
+        "links::is_gone",
+        sqlx::query_scalar(
+            r#"select exists(select 1 from links where id = $1 and deleted_at is null)"#,
+        )
+        .bind(link_id)
+        .fetch_one(db),
+    )
+    .await
+}
+
+/// How a user stands to a link: whether they created it, their role in the
+/// organization it belongs to, if they are a member, and what policies
+/// bearing on them can tell the link apart by.
+#[derive(FromRow)]
+pub struct LinkAccess {
+    pub is_owner: bool,
+    pub in_organization: bool,
+    pub organization_role: Option<String>,
+    pub tags: Vec<String>,
+    pub campaign: Option<String>,
+    pub domain: Option<String>,
+    /// Statements of the policies of the link's organization for the
+    /// user's role there.
+    pub role_statements: sqlx::types::Json<Vec<Statement>>,
+    /// Statements of the policies the user's token carries.
+    pub token_statements: sqlx::types::Json<Vec<Statement>>,
+}
+
+impl LinkAccess {
+    pub fn resource(&self) -> Resource<'_> {
+        Resource {
+            tags: &self.tags,
+            campaign: self.campaign.as_deref(),
+            domain: self.domain.as_deref(),
+        }
+    }
+}
+
+/// None when the link or the user does not exist. `policies` are those the
+/// user's token carries.
+pub async fn access_of(
+    db: &PgPool,
+    link_id: &str,
+    email: &str,
+    policies: &[String],
+) -> Result<Option<LinkAccess>, sqlx::Error> {
+    timed(
+        "links::access_of",
+        sqlx::query_as::<_, LinkAccess>(&format!(
+            r#"SELECT coalesce(links.owner_id = users.id, false) AS is_owner,
+            links.organization_id IS NOT NULL AS in_organization,
+            organization_members.role AS organization_role,
+            links.tags, links.campaign, links.domain, {}
+        FROM links JOIN users ON users.email = $2
+        LEFT JOIN organization_members ON organization_members.organization_id = links.organization_id
+            AND organization_members.user_id = users.id
+        WHERE links.id = $1"#,
+            policy::statements_sql("links.organization_id", "organization_members.role", "$3")
+        ))
+        .bind(link_id)
+        .bind(email)
+        .bind(policies)
+        .fetch_optional(db),
+    )
+    .await
+}
+
+/// The links the user owns or their organizations own, newest first,
+/// deleted ones left out.
+pub async fn list_visible_to(
+    db: &PgPool,
+    email: &str,
+    filter: &LinkListFilter<'_>,
+    after: Option<&LinkListKey<'_>>,
+    limit: i64,
+) -> Result<Vec<Link>, sqlx::Error> {
+    // Links from before creation times were recorded sort last.
+    timed(
+        "links::list_visible_to",
+        sqlx::query_as::<_, Link>(
+            r#"SELECT id, kind, target_url,
+            CASE WHEN state = 'active' AND expires_at <= CURRENT_TIMESTAMP THEN 'expired' ELSE state END AS state,
+            expires_at, created_at, domain, redirect_status, cache_ttl_seconds, fallback_url,
+            interstitial, tags, campaign FROM links
+            WHERE deleted_at IS NULL AND (
+                owner_id = (SELECT id FROM users WHERE email = $1) OR organization_id IN (
+                    SELECT organization_id FROM organization_members
+                    JOIN users ON users.id = organization_members.user_id WHERE users.email = $1
+                )
+            )
+            AND ($2::text IS NULL OR strpos(lower(target_url), lower($2)) > 0)
+            AND ($3::date IS NULL OR created_at >= $3)
+            AND ($4::date IS NULL OR created_at < $4 + 1)
+            AND (NOT $5 OR (coalesce(created_at, '-infinity'), id) < (coalesce($6, '-infinity'), $7))
+            ORDER BY coalesce(created_at, '-infinity') DESC, id DESC
+            LIMIT $8"#,
+        )
+        .bind(email)
+        .bind(filter.target_url)
+        .bind(filter.created_from)
+        .bind(filter.created_to)
+        .bind(after.is_some())
+        .bind(after.and_then(|after| after.created_at))
+        .bind(after.map(|after| after.id))
+        .bind(limit)
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// The links of a group, in their order on its page, deleted ones left out.
+pub async fn list_in_group(db: &PgPool, group_id: &str) -> Result<Vec<GroupLink>, sqlx::Error> {
+    timed(
+        "links::list_in_group",
+        sqlx::query_as::<_, GroupLink>(
+            r#"SELECT id, kind, target_url,
+            CASE WHEN state = 'active' AND expires_at <= CURRENT_TIMESTAMP THEN 'expired' ELSE state END AS state,
+            expires_at, created_at, domain, redirect_status, cache_ttl_seconds, fallback_url,
+            interstitial, tags, campaign, group_links.title, group_links.position
+            FROM links JOIN group_links ON group_links.link_id = links.id
+            WHERE group_links.group_id = $1 AND deleted_at IS NULL
+            ORDER BY group_links.position, group_links.added_at, links.id"#,
+        )
+        .bind(group_id)
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// The organization a link belongs to, if any.
+pub async fn organization_id(db: &PgPool, link_id: &str) -> Result<Option<String>, sqlx::Error> {
+    let organization_id: Option<Option<String>> = timed(
+        "links::organization_id",
+        sqlx::query_scalar(r#"SELECT organization_id FROM links WHERE id = $1"#)
+            .bind(link_id)
+            .fetch_optional(db),
+    )
+    .await?;
+
+    Ok(organization_id.flatten())
+}
+
+pub async fn find_active_target_url(
+    db: &PgPool,
+    link_id: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    timed(
+        "links::find_active_target_url",
+        sqlx::query_scalar(
+            r#"select target_url from links where id = $1 and disabled_at is null
+            and (expires_at is null or expires_at > CURRENT_TIMESTAMP)"#,
+        )
+        .bind(link_id)
+        .fetch_optional(db),
+    )
+    .await
+}
+
+pub async fn insert_link<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link: &NewLink<'_>,
+) -> Result<Link, sqlx::Error> {
+    timed(
+        "links::insert_link",
+        sqlx::query_as::<_, Link>(
+            r#"INSERT INTO links (id, target_url, organization_id, owner_id, expires_at, kind, payload, state, disabled_at,
+            domain, redirect_status, cache_ttl_seconds, fallback_url, interstitial, tags, campaign)
+        VALUES ($1, $2, $3, (SELECT id FROM users WHERE email = $4), $5, $6, $7,
+            CASE WHEN $8 THEN 'draft' ELSE 'active' END, CASE WHEN $8 THEN CURRENT_TIMESTAMP END,
+            $9, $10, $11, $12, $13, $14, $15)
+        RETURNING id, kind, target_url,
+            CASE WHEN state = 'active' AND expires_at <= CURRENT_TIMESTAMP THEN 'expired' ELSE state END AS state,
+            expires_at, created_at, domain, redirect_status, cache_ttl_seconds, fallback_url,
+            interstitial, tags, campaign"#,
+        )
+        .bind(link.id)
+        .bind(link.target_url)
+        .bind(link.organization_id)
+        .bind(link.owner_email)
+        .bind(link.expires_at)
+        .bind(link.payload.map_or(URL_KIND, LinkPayload::kind))
+        .bind(link.payload.map(sqlx::types::Json))
+        .bind(link.draft)
+        .bind(link.domain)
+        .bind(link.behavior.redirect_status)
+        .bind(link.behavior.cache_ttl_seconds)
+        .bind(&link.behavior.fallback_url)
+        .bind(link.behavior.interstitial)
+        .bind(link.tags)
+        .bind(link.campaign)
+        .fetch_one(executor),
+    )
+    .await
+}
+
+pub async fn update_target<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_id: &str,
+    target_url: &str,
+    expires_at: Option<NaiveDateTime>,
+    tags: &[String],
+    campaign: Option<&str>,
+) -> Result<Link, sqlx::Error> {
+    timed(
+        "links::update_target",
+        sqlx::query_as::<_, Link>(
+            r#"update links set target_url = $1, expires_at = $3, tags = $4, campaign = $5,
+            preview_title = case when target_url = $1 then preview_title end,
+            preview_description = case when target_url = $1 then preview_description end,
+            preview_image_url = case when target_url = $1 then preview_image_url end,
+            preview_fetched_at = case when target_url = $1 then preview_fetched_at end
+            where id = $2
+            returning id, kind, target_url,
+            case when state = 'active' and expires_at <= CURRENT_TIMESTAMP then 'expired' else state end as state,
+            expires_at, created_at, domain, redirect_status, cache_ttl_seconds, fallback_url,
+            interstitial, tags, campaign"#,
+        )
+        .bind(target_url)
+        .bind(link_id)
+        .bind(expires_at)
+        .bind(tags)
+        .bind(campaign)
+        .fetch_one(executor),
+    )
+    .await
+}
+
+/// The preview of a link's target as last fetched, for link cards.
+#[derive(serde::Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkPreview {
+    pub link_id: String,
+    #[serde(skip)]
+    pub kind: String,
+    pub target_url: String,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub preview: PagePreview,
+    /// When the preview was fetched, `None` while it never was since the
+    /// target last changed.
+    pub fetched_at: Option<NaiveDateTime>,
+    /// Whether the preview was never fetched or is older than asked for.
+    #[serde(skip)]
+    pub stale: bool,
+}
+
+pub async fn preview(
+    db: &PgPool,
+    link_id: &str,
+    max_age_hours: i32,
+) -> Result<Option<LinkPreview>, sqlx::Error> {
+    timed(
+        "links::preview",
+        sqlx::query_as::<_, LinkPreview>(
+            r#"SELECT id AS link_id, kind, target_url, preview_title AS title,
+            preview_description AS description, preview_image_url AS image_url,
+            preview_fetched_at AS fetched_at,
+            coalesce(preview_fetched_at <= CURRENT_TIMESTAMP - make_interval(hours => $2), true) AS stale
+            FROM links WHERE id = $1 AND deleted_at IS NULL"#,
+        )
+        .bind(link_id)
+        .bind(max_age_hours)
+        .fetch_optional(db),
+    )
+    .await
+}
+
+/// Store the preview fetched from `target_url`, unless the link was pointed
+/// elsewhere in the meantime.
+pub async fn store_preview(
+    db: &PgPool,
+    link_id: &str,
+    target_url: &str,
+    preview: &PagePreview,
+) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    timed(
+        "links::store_preview",
+        sqlx::query_scalar(
+            r#"UPDATE links SET preview_title = $3, preview_description = $4,
+            preview_image_url = $5, preview_fetched_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND target_url = $2
+            RETURNING preview_fetched_at"#,
+        )
+        .bind(link_id)
+        .bind(target_url)
+        .bind(&preview.title)
+        .bind(&preview.description)
+        .bind(&preview.image_url)
+        .fetch_optional(db),
+    )
+    .await
+}
+
+/// The state of the link, locking it until the transaction ends so it can
+/// be moved to another.
+pub async fn lock_state(
+    transaction: &mut Transaction<'_, Postgres>,
+    link_id: &str,
+) -> Result<Option<LinkState>, sqlx::Error> {
+    let state: Option<String> = timed(
+        "links::lock_state",
+        sqlx::query_scalar(
+            r#"SELECT CASE WHEN state = 'active' AND expires_at <= CURRENT_TIMESTAMP THEN 'expired' ELSE state END
+            FROM links WHERE id = $1 FOR UPDATE"#,
+        )
+        .bind(link_id)
+        .fetch_optional(&mut **transaction),
+    )
+    .await?;
+
+    state
+        .map(LinkState::try_from)
+        .transpose()
+        .map_err(|err| sqlx::Error::Decode(err.into()))
+}
+
+/// Move a link to another state, keeping the columns redirects go by in
+/// line: only active links have no `disabled_at`, and expiring one sets
+/// its `expires_at` to now. Whether the transition is allowed is up to
+/// the caller.
+pub async fn set_state<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_id: &str,
+    state: LinkState,
+) -> Result<Link, sqlx::Error> {
+    timed(
+        "links::set_state",
+        sqlx::query_as::<_, Link>(
+            r#"UPDATE links SET state = $2,
+            disabled_at = CASE WHEN $2 = 'active' THEN NULL ELSE coalesce(disabled_at, CURRENT_TIMESTAMP) END,
+            deleted_at = CASE WHEN $2 = 'deleted' THEN CURRENT_TIMESTAMP ELSE deleted_at END,
+            expires_at = CASE
+                WHEN $3 THEN CURRENT_TIMESTAMP
+                WHEN $2 = 'active' AND expires_at <= CURRENT_TIMESTAMP THEN NULL
+                ELSE expires_at
+            END
+            WHERE id = $1
+            RETURNING id, kind, target_url,
+            CASE WHEN state = 'active' AND expires_at <= CURRENT_TIMESTAMP THEN 'expired' ELSE state END AS state,
+            expires_at, created_at, domain, redirect_status, cache_ttl_seconds, fallback_url,
+            interstitial, tags, campaign"#,
+        )
+        .bind(link_id)
+        .bind(state.stored())
+        .bind(state == LinkState::Expired)
+        .fetch_one(executor),
+    )
+    .await
+}
+
+/// Stop redirecting a link for good while keeping its statistics. Deleted
+/// links are disabled too, so everything serving active links leaves them
+/// out. Returns whether there was such a link not deleted yet.
+pub async fn soft_delete<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let deleted = timed(
+        "links::soft_delete",
+        sqlx::query(
+            r#"UPDATE links SET state = 'deleted', deleted_at = CURRENT_TIMESTAMP,
+            disabled_at = coalesce(disabled_at, CURRENT_TIMESTAMP)
+            WHERE id = $1 AND deleted_at IS NULL"#,
+        )
+        .bind(link_id)
+        .execute(executor),
+    )
+    .await?;
+
+    Ok(deleted.rows_affected() > 0)
+}
+
+/// Delete links along with their clicks and consents, unsetting them on
+/// group events. Fails on links a group playlist points at, since the
+/// playlist cannot do without one. Returns how many links were deleted.
+pub async fn hard_delete(
+    transaction: &mut Transaction<'_, Postgres>,
+    link_ids: &[String],
+) -> Result<u64, sqlx::Error> {
+    timed("links::hard_delete", async {
+        for statement in [
+            r#"DELETE FROM link_statistics WHERE link_id = ANY($1)"#,
+            r#"DELETE FROM consents WHERE link_id = ANY($1)"#,
+            r#"DELETE FROM link_scheduled_changes WHERE link_id = ANY($1)"#,
+            r#"UPDATE group_events SET link_id = NULL WHERE link_id = ANY($1)"#,
+        ] {
+            sqlx::query(statement)
+                .bind(link_ids)
+                .execute(&mut **transaction)
+                .await?;
+        }
+
+        let deleted = sqlx::query(r#"DELETE FROM links WHERE id = ANY($1)"#)
+            .bind(link_ids)
+            .execute(&mut **transaction)
+            .await?;
+
+        Ok(deleted.rows_affected())
+    })
+    .await
+}
+
+/// The routing rules of a link not deleted, or `None` when there is no such
+/// link.
+pub async fn routing_rules(
+    db: &PgPool,
+    link_id: &str,
+) -> Result<Option<Vec<RoutingRule>>, sqlx::Error> {
+    let rules: Option<sqlx::types::Json<Vec<RoutingRule>>> = timed(
+        "links::routing_rules",
+        sqlx::query_scalar(
+            r#"SELECT routing_rules FROM links WHERE id = $1 AND deleted_at IS NULL"#,
+        )
+        .bind(link_id)
+        .fetch_optional(db),
+    )
+    .await?;
+
+    Ok(rules.map(|rules| rules.0))
+}
+
+/// Replace the routing rules of a link not deleted. Returns whether there
+/// was such a link.
+pub async fn set_routing_rules<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_id: &str,
+    rules: &[RoutingRule],
+) -> Result<bool, sqlx::Error> {
+    let updated = timed(
+        "links::set_routing_rules",
+        sqlx::query(r#"UPDATE links SET routing_rules = $1 WHERE id = $2 AND deleted_at IS NULL"#)
+            .bind(sqlx::types::Json(rules))
+            .bind(link_id)
+            .execute(executor),
+    )
+    .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
+
+/// The target changes scheduled for a link, applied or not, soonest first.
+pub async fn scheduled_changes(
+    db: &PgPool,
+    link_id: &str,
+) -> Result<Vec<ScheduledChange>, sqlx::Error> {
+    timed(
+        "links::scheduled_changes",
+        sqlx::query_as::<_, ScheduledChange>(
+            r#"SELECT * FROM link_scheduled_changes WHERE link_id = $1 ORDER BY change_at, id"#,
+        )
+        .bind(link_id)
+        .fetch_all(db),
+    )
+    .await
+}
+
+pub async fn schedule_change<'e, E: PgExecutor<'e>>(
+    executor: E,
+    change: &NewScheduledChange<'_>,
+) -> Result<ScheduledChange, sqlx::Error> {
+    timed(
+        "links::schedule_change",
+        sqlx::query_as::<_, ScheduledChange>(
+            r#"INSERT INTO link_scheduled_changes (link_id, target_url, change_at, created_by)
+            VALUES ($1, $2, $3, $4) RETURNING *"#,
+        )
+        .bind(change.link_id)
+        .bind(change.target_url)
+        .bind(change.change_at)
+        .bind(change.created_by)
+        .fetch_one(executor),
+    )
+    .await
+}
+
+/// Drop a change not applied yet. Returns whether there was such a change.
+pub async fn cancel_scheduled_change<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_id: &str,
+    change_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let cancelled = timed(
+        "links::cancel_scheduled_change",
+        sqlx::query(
+            r#"DELETE FROM link_scheduled_changes
+            WHERE id = $1 AND link_id = $2 AND applied_at IS NULL"#,
+        )
+        .bind(change_id)
+        .bind(link_id)
+        .execute(executor),
+    )
+    .await?;
+
+    Ok(cancelled.rows_affected() > 0)
+}
+
+/// The payload of an active link, `Some(None)` for a link redirecting to
+/// its target, or `None` when there is no such link.
+pub async fn active_payload(
+    db: &PgPool,
+    link_id: &str,
+) -> Result<Option<Option<LinkPayload>>, sqlx::Error> {
+    let payload: Option<Option<sqlx::types::Json<LinkPayload>>> = timed(
+        "links::active_payload",
+        sqlx::query_scalar(
+            r#"SELECT payload FROM links WHERE id = $1 AND disabled_at IS NULL
+            AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)"#,
+        )
+        .bind(link_id)
+        .fetch_optional(db),
+    )
+    .await?;
+
+    Ok(payload.map(|payload| payload.map(|payload| payload.0)))
+}
+
+/// The sample rate of a link, locked until the end of the transaction.
+pub async fn lock_click_sample_rate<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_id: &str,
+) -> Result<Option<i32>, sqlx::Error> {
+    timed(
+        "links::lock_click_sample_rate",
+        sqlx::query_scalar(r#"SELECT click_sample_rate FROM links WHERE id = $1 FOR UPDATE"#)
+            .bind(link_id)
+            .fetch_optional(executor),
+    )
+    .await
+}
+
+pub async fn set_click_sample_rate<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_id: &str,
+    click_sample_rate: i32,
+) -> Result<(), sqlx::Error> {
+    timed(
+        "links::set_click_sample_rate",
+        sqlx::query(r#"UPDATE links SET click_sample_rate = $1 WHERE id = $2"#)
+            .bind(click_sample_rate)
+            .bind(link_id)
+            .execute(executor),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Clicks per link, from the daily rollup plus the clicks not rolled up
+/// yet, so raw clicks past their retention still count.
+const CLICK_AMOUNTS: &str = r#"SELECT link_id, amount FROM link_statistics_daily
+    UNION ALL
+    SELECT link_id, sample_rate FROM link_statistics WHERE NOT rolled_up"#;
+
+/// Estimated clicks on an active link, or `None` when there is no such link.
+pub async fn click_count(db: &PgPool, link_id: &str) -> Result<Option<i64>, sqlx::Error> {
+    timed(
+        "links::click_count",
+        sqlx::query_scalar(&format!(
+            r#"SELECT coalesce(sum(clicks.amount), 0)::bigint FROM links
+        LEFT JOIN ({}) clicks ON clicks.link_id = links.id
+        WHERE links.id = $1 AND links.disabled_at IS NULL
+        AND (links.expires_at IS NULL OR links.expires_at > CURRENT_TIMESTAMP) GROUP BY links.id"#,
+            CLICK_AMOUNTS
+        ))
+        .bind(link_id)
+        .fetch_optional(db),
+    )
+    .await
+}
+
+/// Clicks of link `$1` per referer and user agent, from the daily rollup
+/// plus the clicks not rolled up yet, rather than every click. A click not
+/// rolled up yet counts as a visitor when it is the first of its visitor
+/// that day in its bucket.
+const LINK_STATISTICS: &str = r#"SELECT amount, referer, user_agent, sampled, visitors, is_bot, browser, os
+    FROM link_statistics_daily WHERE link_id = $1
+    UNION ALL
+    SELECT sample_rate, referer, user_agent, sample_rate > 1,
+        (visitor_hash IS NOT NULL
+            AND row_number() OVER (
+                PARTITION BY coalesce(created_at, CURRENT_TIMESTAMP)::date, referer, user_agent, is_bot, visitor_hash
+                ORDER BY id
+            ) = 1
+            AND NOT EXISTS (
+                SELECT 1 FROM link_statistics seen
+                WHERE seen.link_id = $1 AND seen.visitor_hash = link_statistics.visitor_hash AND seen.rolled_up
+                    AND coalesce(seen.created_at, CURRENT_TIMESTAMP)::date = coalesce(link_statistics.created_at, CURRENT_TIMESTAMP)::date
+                    AND seen.referer IS NOT DISTINCT FROM link_statistics.referer
+                    AND seen.user_agent IS NOT DISTINCT FROM link_statistics.user_agent
+                    AND seen.is_bot = link_statistics.is_bot
+            ))::int::bigint,
+        is_bot, browser, os
+    FROM link_statistics
+    WHERE link_id = $1 AND NOT rolled_up"#;
+
+/// Clicks per referer and user agent, ordered by both, leaving out the
+/// buckets with fewer than `page.min_clicks`.
+pub async fn statistics_page(
+    db: &PgPool,
+    link_id: &str,
+    page: &StatisticsPage<'_>,
+) -> Result<Vec<CounterLinkStatistics>, sqlx::Error> {
+    // Nulls sort before every value, including the empty string.
+    timed(
+        "links::statistics_page",
+        sqlx::query_as::<_, CounterLinkStatistics>(&format!(
+            r#"SELECT sum(amount)::bigint as amount, referer, user_agent, bool_or(sampled) as sampled,
+            sum(visitors)::bigint as visitors, bool_and(is_bot) as bot, max(browser) as browser, max(os) as os
+        FROM ({}) statistics
+        WHERE NOT $9 OR NOT is_bot
+        GROUP BY referer, user_agent
+        HAVING (NOT $2 OR (referer IS NOT NULL, coalesce(referer, ''), user_agent IS NOT NULL, coalesce(user_agent, ''))
+            > ($3, coalesce($4, ''), $5, coalesce($6, '')))
+            AND sum(amount) >= $8
+        ORDER BY referer IS NOT NULL, coalesce(referer, ''), user_agent IS NOT NULL, coalesce(user_agent, '')
+        LIMIT $7"#,
+            LINK_STATISTICS
+        ))
+        .bind(link_id)
+        .bind(page.after.is_some())
+        .bind(page.after.as_ref().is_some_and(|after| after.referer.is_some()))
+        .bind(page.after.as_ref().and_then(|after| after.referer))
+        .bind(page.after.as_ref().is_some_and(|after| after.user_agent.is_some()))
+        .bind(page.after.as_ref().and_then(|after| after.user_agent))
+        .bind(page.limit)
+        .bind(page.min_clicks)
+        .bind(page.exclude_bots)
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// The buckets [`statistics_page`] leaves out for having fewer than
+/// `min_clicks`, counted together under [`OTHER_BUCKET`]. None when there
+/// are none.
+pub async fn other_statistics(
+    db: &PgPool,
+    link_id: &str,
+    min_clicks: i64,
+    exclude_bots: bool,
+) -> Result<Option<CounterLinkStatistics>, sqlx::Error> {
+    let other = timed(
+        "links::other_statistics",
+        sqlx::query_as::<_, CounterLinkStatistics>(&format!(
+            r#"SELECT sum(amount)::bigint as amount, $3 as referer, $3 as user_agent, bool_or(sampled) as sampled,
+            sum(visitors)::bigint as visitors, bool_and(bot) as bot, NULL as browser, NULL as os
+        FROM (
+            SELECT sum(amount) as amount, bool_or(sampled) as sampled, sum(visitors) as visitors,
+                bool_and(is_bot) as bot
+            FROM ({}) statistics
+            WHERE NOT $4 OR NOT is_bot
+            GROUP BY referer, user_agent HAVING sum(amount) < $2
+        ) buckets"#,
+            LINK_STATISTICS
+        ))
+        .bind(link_id)
+        .bind(min_clicks)
+        .bind(OTHER_BUCKET)
+        .bind(exclude_bots)
+        .fetch_one(db),
+    )
+    .await?;
+
+    Ok(other.amount.is_some().then_some(other))
+}
+
+/// Estimated clicks per day between two days, inclusive, leaving out days
+/// without clicks.
+pub async fn daily_clicks(
+    db: &PgPool,
+    scope: &ClickScope<'_>,
+    from: NaiveDate,
+    to: NaiveDate,
+    exclude_bots: bool,
+) -> Result<Vec<(NaiveDate, i64)>, sqlx::Error> {
+    let (link_id, organization_id, group_id) = match scope {
+        ClickScope::Link(link_id) => (Some(*link_id), None, None),
+        ClickScope::Organization(organization_id) => (None, Some(*organization_id), None),
+        ClickScope::Group(group_id) => (None, None, Some(*group_id)),
+    };
+
+    timed("links::daily_clicks", sqlx::query_as(
+        r#"SELECT day, sum(amount)::bigint FROM (
+            SELECT link_id, day, amount FROM link_statistics_daily
+            WHERE day BETWEEN $3 AND $4 AND (NOT $6 OR NOT is_bot)
+            UNION ALL
+            SELECT link_id, coalesce(created_at, CURRENT_TIMESTAMP)::date, sample_rate FROM link_statistics
+            WHERE NOT rolled_up AND coalesce(created_at, CURRENT_TIMESTAMP)::date BETWEEN $3 AND $4
+                AND (NOT $6 OR NOT is_bot)
+        ) statistics
+        JOIN links ON links.id = statistics.link_id
+        WHERE links.id = $1 OR links.organization_id = $2
+            OR (links.deleted_at IS NULL
+                AND links.id IN (SELECT link_id FROM group_links WHERE group_id = $5))
+        GROUP BY day ORDER BY day"#,
+    )
+    .bind(link_id)
+    .bind(organization_id)
+    .bind(from)
+    .bind(to)
+    .bind(group_id)
+    .bind(exclude_bots)
+    .fetch_all(db))
+    .await
+}
+
+/// Estimated clicks between two days, inclusive, on each link of a group
+/// that has any, most clicked first.
+pub async fn group_link_clicks(
+    db: &PgPool,
+    group_id: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+    exclude_bots: bool,
+) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    timed(
+        "links::group_link_clicks",
+        sqlx::query_as(
+            r#"SELECT statistics.link_id, sum(amount)::bigint AS clicks FROM (
+            SELECT link_id, amount FROM link_statistics_daily
+            WHERE day BETWEEN $2 AND $3 AND (NOT $4 OR NOT is_bot)
+            UNION ALL
+            SELECT link_id, sample_rate FROM link_statistics
+            WHERE NOT rolled_up AND coalesce(created_at, CURRENT_TIMESTAMP)::date BETWEEN $2 AND $3
+                AND (NOT $4 OR NOT is_bot)
+        ) statistics
+        JOIN group_links ON group_links.link_id = statistics.link_id AND group_links.group_id = $1
+        JOIN links ON links.id = statistics.link_id AND links.deleted_at IS NULL
+        GROUP BY statistics.link_id ORDER BY clicks DESC, statistics.link_id"#,
+        )
+        .bind(group_id)
+        .bind(from)
+        .bind(to)
+        .bind(exclude_bots)
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// The latest clicks on a link, newest first.
+/// Estimated clicks on a link per hour from `from` to before `to`, leaving
+/// out hours without any. Read from the raw clicks, which the daily rollup
+/// keeps.
+pub async fn hourly_clicks(
+    db: &PgPool,
+    link_id: &str,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    exclude_bots: bool,
+) -> Result<Vec<(NaiveDateTime, i64)>, sqlx::Error> {
+    timed(
+        "links::hourly_clicks",
+        sqlx::query_as(
+            r#"SELECT date_trunc('hour', created_at) AS hour, sum(sample_rate)::bigint
+            FROM link_statistics WHERE link_id = $1 AND created_at >= $2 AND created_at < $3
+                AND (NOT $4 OR NOT is_bot)
+            GROUP BY hour ORDER BY hour"#,
+        )
+        .bind(link_id)
+        .bind(from)
+        .bind(to)
+        .bind(exclude_bots)
+        .fetch_all(db),
+    )
+    .await
+}
+
+pub async fn recent_clicks(
+    db: &PgPool,
+    link_id: &str,
+    limit: i64,
+) -> Result<Vec<RecordedClick>, sqlx::Error> {
+    timed(
+        "links::recent_clicks",
+        sqlx::query_as::<_, RecordedClick>(
+            r#"SELECT created_at, referer, user_agent, country, sample_rate, is_bot, browser, os
+        FROM link_statistics WHERE link_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2"#,
+        )
+        .bind(link_id)
+        .bind(limit)
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// Estimated clicks per day, from the rollup plus clicks not rolled up yet,
+/// for links in `$1` between the days `$2` and `$3`, inclusive.
+const BATCH_CLICKS: &str = r#"SELECT link_id, day, referer, amount FROM link_statistics_daily
+    WHERE link_id = ANY($1) AND day BETWEEN $2 AND $3
+    UNION ALL
+    SELECT link_id, coalesce(created_at, CURRENT_TIMESTAMP)::date, referer, sample_rate FROM link_statistics
+    WHERE link_id = ANY($1) AND NOT rolled_up AND coalesce(created_at, CURRENT_TIMESTAMP)::date BETWEEN $2 AND $3"#;
+
+/// Estimated clicks of each link with any in the range.
+pub async fn batch_clicks(
+    db: &PgPool,
+    link_ids: &[String],
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    timed(
+        "links::batch_clicks",
+        sqlx::query_as(&format!(
+            "SELECT link_id, sum(amount)::bigint FROM ({}) statistics GROUP BY link_id",
+            BATCH_CLICKS
+        ))
+        .bind(link_ids)
+        .bind(from)
+        .bind(to)
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// Estimated clicks per link and day, leaving out days without clicks.
+pub async fn batch_daily_clicks(
+    db: &PgPool,
+    link_ids: &[String],
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<(String, NaiveDate, i64)>, sqlx::Error> {
+    timed(
+        "links::batch_daily_clicks",
+        sqlx::query_as(&format!(
+            "SELECT link_id, day, sum(amount)::bigint FROM ({}) statistics
+        GROUP BY link_id, day ORDER BY link_id, day",
+            BATCH_CLICKS
+        ))
+        .bind(link_ids)
+        .bind(from)
+        .bind(to)
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// The referers sending each link the most clicks, most first, leaving out
+/// those with fewer than `min_clicks`.
+pub async fn batch_top_referers(
+    db: &PgPool,
+    link_ids: &[String],
+    from: NaiveDate,
+    to: NaiveDate,
+    per_link: i64,
+    min_clicks: i64,
+) -> Result<Vec<(String, Option<String>, i64)>, sqlx::Error> {
+    timed(
+        "links::batch_top_referers",
+        sqlx::query_as(&format!(
+            "SELECT link_id, referer, amount FROM (
+            SELECT link_id, referer, sum(amount)::bigint AS amount,
+                row_number() OVER (PARTITION BY link_id ORDER BY sum(amount) DESC, referer) AS rank
+            FROM ({}) statistics GROUP BY link_id, referer HAVING sum(amount) >= $5
+        ) ranked
+        WHERE rank <= $4 ORDER BY link_id, rank",
+            BATCH_CLICKS
+        ))
+        .bind(link_ids)
+        .bind(from)
+        .bind(to)
+        .bind(per_link)
+        .bind(min_clicks)
+        .fetch_all(db),
+    )
+    .await
+}
+
+/// An organization's links ranked by estimated clicks between `from` and
+/// `to`, with their clicks from `previous_from` up to `from` to compare.
+pub async fn compare_organization_links(
+    db: &PgPool,
+    organization_id: &str,
+    previous_from: NaiveDate,
+    from: NaiveDate,
+    to: NaiveDate,
+    limit: i64,
+) -> Result<Vec<ComparedLink>, sqlx::Error> {
+    timed("links::compare_organization_links", sqlx::query_as::<_, ComparedLink>(
+        r#"SELECT id, target_url, clicks, previous_clicks, (sum(clicks) OVER ())::bigint AS total_clicks
+        FROM (
+            SELECT links.id, links.target_url,
+                coalesce(sum(statistics.amount) FILTER (WHERE statistics.day >= $3), 0)::bigint AS clicks,
+                coalesce(sum(statistics.amount) FILTER (WHERE statistics.day < $3), 0)::bigint AS previous_clicks
+            FROM links LEFT JOIN (
+                SELECT link_id, day, amount FROM link_statistics_daily
+                WHERE link_id IN (SELECT id FROM links WHERE organization_id = $1) AND day BETWEEN $2 AND $4
+                UNION ALL
+                SELECT link_id, coalesce(created_at, CURRENT_TIMESTAMP)::date, sample_rate FROM link_statistics
+                WHERE link_id IN (SELECT id FROM links WHERE organization_id = $1) AND NOT rolled_up
+                    AND coalesce(created_at, CURRENT_TIMESTAMP)::date BETWEEN $2 AND $4
+            ) statistics ON statistics.link_id = links.id
+            WHERE links.organization_id = $1
+            GROUP BY links.id
+        ) compared
+        ORDER BY clicks DESC, id LIMIT $5"#,
+    )
+    .bind(organization_id)
+    .bind(previous_from)
+    .bind(from)
+    .bind(to)
+    .bind(limit)
+    .fetch_all(db))
+    .await
+}
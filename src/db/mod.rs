@@ -0,0 +1,157 @@
+pub mod fault_injection;
+pub mod links;
+pub mod slow_queries;
+
+use anyhow::Result;
+use sqlx::postgres::PgConnectOptions;
+use sqlx::PgPool;
+use std::str::FromStr;
+
+/// Create a database connection pool. Run pending migrations when
+/// `auto_migrate` is set, and refuse a schema behind them otherwise.
+///
+/// PostgreSQL is the only supported store: queries and migrations use its
+/// dialect, so a URL for any other database is refused up front.
+///
+/// ## Returns
+/// * A ready-to-use connection pool.
+pub async fn init_db(auto_migrate: bool) -> Result<PgPool> {
+    let database_url = std::env::var("DATABASE_URL")?;
+    if !database_url.starts_with("postgres://") && !database_url.starts_with("postgresql://") {
+        anyhow::bail!("DATABASE_URL must point to a PostgreSQL database, like postgres://host/db");
+    }
+
+    // Timestamps are kept without a time zone, and those coming from
+    // clients, like link expiry, are in UTC. Reading the clock in UTC on
+    // every connection keeps comparisons with them independent of the
+    // server's time zone.
+    let options = PgConnectOptions::from_str(&database_url)?.options([("timezone", "UTC")]);
+    let connection_pool = PgPool::connect_with(options).await?;
+    if auto_migrate {
+        sqlx::migrate!().run(&connection_pool).await?;
+    } else {
+        require_current_schema(&connection_pool).await?;
+    }
+    warn_missing_indexes(&connection_pool).await?;
+    Ok(connection_pool)
+}
+
+/// Fail when a migration embedded in this binary has not been applied, as
+/// queries would otherwise fail one by one on the missing schema.
+async fn require_current_schema(db: &PgPool) -> Result<()> {
+    let applied: Vec<i64> =
+        sqlx::query_scalar(r#"SELECT version FROM _sqlx_migrations WHERE success"#)
+            .fetch_all(db)
+            .await
+            .or_else(|err| match err {
+                // No migration ever ran on this database.
+                sqlx::Error::Database(err) if err.code().as_deref() == Some("42P01") => {
+                    Ok(Vec::new())
+                }
+                err => Err(err),
+            })?;
+
+    let migrator = sqlx::migrate!();
+    let pending: Vec<_> = migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect();
+
+    if let Some(first) = pending.first() {
+        anyhow::bail!(
+            "The database schema is behind: {} migrations are pending, starting with {} {}. \
+            Apply them with `sqlx migrate run`, or set AUTO_MIGRATE to apply them on startup",
+            pending.len(),
+            first.version,
+            first.description
+        );
+    }
+
+    Ok(())
+}
+
+/// Indexes the hot queries rely on, by table. Migrations create them, so a
+/// missing one was dropped by hand and those queries now scan the table.
+const EXPECTED_INDEXES: &[(&str, &str)] = &[
+    ("links", "idx_links_owner_id_created_at"),
+    ("links", "idx_links_organization_id"),
+    ("link_statistics", "idx_link_statistics_link_id_created_at"),
+    ("link_statistics", "idx_link_statistics_not_rolled_up"),
+    ("link_statistics", "idx_link_statistics_click_id"),
+    ("link_statistics_daily", "idx_link_statistics_daily_key"),
+    ("organization_members", "idx_organization_members_user_id"),
+];
+
+/// Warn about every expected index the database does not have.
+async fn warn_missing_indexes(db: &PgPool) -> Result<()> {
+    let present: Vec<String> = sqlx::query_scalar(
+        r#"SELECT indexname::text FROM pg_indexes WHERE schemaname = current_schema()"#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    for (table, index) in EXPECTED_INDEXES {
+        if !present.iter().any(|name| name == index) {
+            tracing::warn!("Index {} on {} is missing", index, table);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    /*
+    #[sqlx::test]
+    async fn get_all() {
+        dotenv::dotenv().ok();
+        let cnn = init_db().await.unwrap();
+        let all_rows = all_channels(&cnn).await.unwrap();
+        assert!(!all_rows.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn get_one() {
+        dotenv::dotenv().ok();
+        let cnn = init_db().await.unwrap();
+        let book = book_by_id(&cnn, 1).await.unwrap();
+        assert_eq!(1, book.id);
+        assert_eq!("Hands-on Rust", book.title);
+        assert_eq!("Wolverson, Herbert", book.author);
+    }
+
+    #[sqlx::test]
+    async fn test_create() {
+        dotenv::dotenv().ok();
+        let cnn = init_db().await.unwrap();
+        let new_id = add_book(&cnn, "Test Book", "Test Author").await.unwrap();
+        let new_book = book_by_id(&cnn, new_id).await.unwrap();
+        assert_eq!(new_id, new_book.id);
+        assert_eq!("Test Book", new_book.title);
+        assert_eq!("Test Author", new_book.author);
+    }
+
+    #[sqlx::test]
+    async fn test_update() {
+        dotenv::dotenv().ok();
+        let cnn = init_db().await.unwrap();
+        let mut book = book_by_id(&cnn, 2).await.unwrap();
+        book.title = "Updated Book".to_string();
+        update_book(&cnn, &book).await.unwrap();
+        let updated_book = book_by_id(&cnn, 2).await.unwrap();
+        assert_eq!("Updated Book", updated_book.title);
+    }
+
+    #[sqlx::test]
+    async fn test_delete() {
+        dotenv::dotenv().ok();
+        let cnn = init_db().await.unwrap();
+        let new_id = add_book(&cnn, "DeleteMe", "Test Author").await.unwrap();
+        let _new_book = book_by_id(&cnn, new_id).await.unwrap();
+        delete_book(&cnn, new_id).await.unwrap();
+        let all_books = all_books(&cnn).await.unwrap();
+        assert!(all_books.iter().find(|b| b.title == "DeleteMe").is_none());
+    }
+     */
+}
@@ -0,0 +1,132 @@
+//! Repository calls slower than `SLOW_QUERY_THRESHOLD_MS`, logged and
+//! counted as they happen and kept in memory for a while, so the worst of
+//! them can be looked up when deciding which index to add next.
+
+use crate::db::fault_injection;
+
+use axum_prometheus::metrics::counter;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Slow calls remembered, the oldest forgotten first.
+const RECENT_SLOW_QUERIES: usize = 1_000;
+
+static SLOW_QUERY_THRESHOLD: OnceCell<Duration> = OnceCell::new();
+
+static RECENT: Lazy<Mutex<VecDeque<SlowQuery>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(RECENT_SLOW_QUERIES)));
+
+struct SlowQuery {
+    name: &'static str,
+    duration: Duration,
+    finished_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// How often a query was slow among the recent slow calls, and how slow.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlowQueryStats {
+    pub name: &'static str,
+    pub count: usize,
+    pub max_millis: u64,
+    pub mean_millis: u64,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub fn init(threshold: Duration) {
+    if SLOW_QUERY_THRESHOLD.set(threshold).is_err() {
+        tracing::warn!("The slow query threshold was already set");
+    }
+}
+
+fn threshold() -> Duration {
+    SLOW_QUERY_THRESHOLD
+        .get()
+        .copied()
+        .unwrap_or(Duration::from_millis(500))
+}
+
+/// Run a repository call, noting it when it takes longer than the
+/// threshold. Failed calls count too, since a timeout is often the slowest,
+/// and so do the faults injected into them.
+pub async fn timed<T, F>(name: &'static str, query: F) -> Result<T, sqlx::Error>
+where
+    F: Future<Output = Result<T, sqlx::Error>>,
+{
+    let started_at = Instant::now();
+    let output = match fault_injection::inject(name).await {
+        Ok(()) => query.await,
+        Err(err) => Err(err),
+    };
+    let duration = started_at.elapsed();
+
+    if duration >= threshold() {
+        record(name, duration);
+    }
+
+    output
+}
+
+fn record(name: &'static str, duration: Duration) {
+    tracing::warn!(query = name, duration_ms = millis(duration), "Slow query");
+    counter!("slow_queries_total", "query" => name).increment(1);
+
+    let mut recent = RECENT
+        .lock()
+        .expect("The slow query lock should never be poisoned");
+    if recent.len() >= RECENT_SLOW_QUERIES {
+        recent.pop_front();
+    }
+    recent.push_back(SlowQuery {
+        name,
+        duration,
+        finished_at: chrono::Utc::now(),
+    });
+}
+
+/// The queries among the recent slow calls, slowest first.
+pub fn worst_offenders(limit: usize) -> Vec<SlowQueryStats> {
+    let recent = RECENT
+        .lock()
+        .expect("The slow query lock should never be poisoned");
+
+    let mut by_name: HashMap<&'static str, Vec<&SlowQuery>> = HashMap::new();
+    for query in recent.iter() {
+        by_name.entry(query.name).or_default().push(query);
+    }
+
+    let mut offenders = by_name
+        .into_iter()
+        .map(|(name, calls)| {
+            let total: Duration = calls.iter().map(|call| call.duration).sum();
+            SlowQueryStats {
+                name,
+                count: calls.len(),
+                max_millis: millis(
+                    calls
+                        .iter()
+                        .map(|call| call.duration)
+                        .max()
+                        .unwrap_or_default(),
+                ),
+                mean_millis: millis(total / calls.len() as u32),
+                last_seen_at: calls
+                    .iter()
+                    .map(|call| call.finished_at)
+                    .max()
+                    .unwrap_or_else(chrono::Utc::now),
+            }
+        })
+        .collect::<Vec<_>>();
+    offenders.sort_by(|a, b| b.max_millis.cmp(&a.max_millis).then(b.count.cmp(&a.count)));
+    offenders.truncate(limit);
+
+    offenders
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
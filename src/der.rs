@@ -0,0 +1,143 @@
+//! Just enough DER to read the expiry and public key of an X.509
+//! certificate and to write the certificate signing requests sent to ACME
+//! authorities.
+
+use chrono::NaiveDateTime;
+
+pub const SEQUENCE: u8 = 0x30;
+pub const SET: u8 = 0x31;
+pub const INTEGER: u8 = 0x02;
+pub const BIT_STRING: u8 = 0x03;
+pub const OCTET_STRING: u8 = 0x04;
+pub const UTF8_STRING: u8 = 0x0c;
+pub const UTC_TIME: u8 = 0x17;
+pub const GENERALIZED_TIME: u8 = 0x18;
+/// `[0]`, as wrapping the version of a certificate and the attributes of a
+/// signing request.
+pub const CONTEXT_0: u8 = 0xa0;
+/// `[2]` implicitly tagging a `dNSName` in a `GeneralName`.
+pub const DNS_NAME: u8 = 0x82;
+
+pub const OID_RSA_ENCRYPTION: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01,
+];
+pub const OID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+pub const OID_PRIME256V1: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+pub const OID_SECP384R1: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+pub const OID_ECDSA_WITH_SHA256: &[u8] =
+    &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+pub const OID_COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+pub const OID_EXTENSION_REQUEST: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x0e,
+];
+pub const OID_SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x11];
+
+/// Encode one element.
+pub fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let length = content.len();
+    if length < 0x80 {
+        encoded.push(length as u8);
+    } else {
+        let bytes = length.to_be_bytes();
+        let significant = &bytes[bytes.iter().take_while(|byte| **byte == 0).count()..];
+        encoded.push(0x80 | significant.len() as u8);
+        encoded.extend_from_slice(significant);
+    }
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+/// Encode a constructed element from already encoded parts.
+pub fn constructed(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+    tlv(tag, &parts.concat())
+}
+
+/// A bit string without unused bits, the only kind keys and signatures use.
+pub fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    tlv(BIT_STRING, &[&[0], bytes].concat())
+}
+
+/// Split the first element off `input`, returning its tag, content and
+/// whatever follows it.
+pub fn read(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+
+    let (length, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > std::mem::size_of::<usize>() || rest.len() < count {
+            return None;
+        }
+        let length = rest[..count]
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+        (length, &rest[count..])
+    };
+
+    if rest.len() < length {
+        return None;
+    }
+    Some((tag, &rest[..length], &rest[length..]))
+}
+
+/// Split off the first element, requiring it to have `tag`.
+fn expect(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    match read(input)? {
+        (found, content, rest) if found == tag => Some((content, rest)),
+        _ => None,
+    }
+}
+
+/// The fields of a DER encoded X.509 certificate from its validity on.
+fn certificate_validity(certificate: &[u8]) -> Option<&[u8]> {
+    let (certificate, _) = expect(certificate, SEQUENCE)?;
+    let (tbs, _) = expect(certificate, SEQUENCE)?;
+
+    let (tag, _, mut fields) = read(tbs)?;
+    if tag == CONTEXT_0 {
+        // The version came first, so skip the serial number too.
+        (_, _, fields) = read(fields)?;
+    }
+    let (_, _, fields) = read(fields)?; // signature algorithm
+    let (_, _, fields) = read(fields)?; // issuer
+    Some(fields)
+}
+
+/// The public key of a DER encoded X.509 certificate, as the content of
+/// its algorithm identifier, such as `OID_EC_PUBLIC_KEY` followed by the
+/// curve, and the key itself.
+pub fn certificate_public_key(certificate: &[u8]) -> Option<(&[u8], &[u8])> {
+    let fields = certificate_validity(certificate)?;
+    let (_, _, fields) = read(fields)?; // validity
+    let (_, _, fields) = read(fields)?; // subject
+    let (public_key_info, _) = expect(fields, SEQUENCE)?;
+
+    let (algorithm, rest) = expect(public_key_info, SEQUENCE)?;
+    let (key, _) = expect(rest, BIT_STRING)?;
+    match key.split_first()? {
+        (0, key) => Some((algorithm, key)),
+        _ => None,
+    }
+}
+
+/// When a DER encoded X.509 certificate stops being valid.
+pub fn certificate_not_after(certificate: &[u8]) -> Option<NaiveDateTime> {
+    let fields = certificate_validity(certificate)?;
+    let (validity, _) = expect(fields, SEQUENCE)?;
+
+    let (_, _, validity) = read(validity)?; // not before
+    let (tag, time, _) = read(validity)?;
+    let time = std::str::from_utf8(time).ok()?;
+    match tag {
+        // Two digit years stand for 1950 to 2049.
+        UTC_TIME => {
+            let century = if time.get(..2)? < "50" { "20" } else { "19" };
+            NaiveDateTime::parse_from_str(&format!("{}{}", century, time), "%Y%m%d%H%M%SZ").ok()
+        }
+        GENERALIZED_TIME => NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%SZ").ok(),
+        _ => None,
+    }
+}
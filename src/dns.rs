@@ -0,0 +1,106 @@
+//! DNS lookups over HTTPS, through the JSON API Cloudflare and Google
+//! resolvers offer, so records are read as public resolvers see them
+//! rather than through whatever the host is configured with.
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::time::Duration;
+
+static DNS_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("The DNS client should always be constructable")
+});
+
+/// Response code of names that do not exist, which have no records.
+const NXDOMAIN: u16 = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RecordType {
+    Txt,
+    Cname,
+}
+
+impl RecordType {
+    fn name(self) -> &'static str {
+        match self {
+            RecordType::Txt => "TXT",
+            RecordType::Cname => "CNAME",
+        }
+    }
+
+    fn code(self) -> u16 {
+        match self {
+            RecordType::Txt => 16,
+            RecordType::Cname => 5,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DnsError {
+    #[error("Could not reach the DNS resolver: {0}")]
+    Unreachable(#[from] reqwest::Error),
+    #[error("The DNS resolver failed with response code {0}")]
+    Failed(u16),
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(rename = "Status")]
+    status: u16,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<Answer>,
+}
+
+#[derive(Deserialize)]
+struct Answer {
+    #[serde(rename = "type")]
+    kind: u16,
+    data: String,
+}
+
+/// The records of the given type at `name`. TXT records come unquoted, with
+/// their strings joined, and CNAME targets without the trailing dot.
+pub async fn lookup(
+    resolver_url: &str,
+    name: &str,
+    record_type: RecordType,
+) -> Result<Vec<String>, DnsError> {
+    let response: Response = DNS_CLIENT
+        .get(resolver_url)
+        .query(&[("name", name), ("type", record_type.name())])
+        .header("accept", "application/dns-json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    match response.status {
+        0 => {}
+        NXDOMAIN => return Ok(Vec::new()),
+        status => return Err(DnsError::Failed(status)),
+    }
+
+    // Answers to a TXT query may start with the CNAME chain leading to them.
+    Ok(response
+        .answer
+        .into_iter()
+        .filter(|answer| answer.kind == record_type.code())
+        .map(|answer| match record_type {
+            RecordType::Txt => unquote(&answer.data),
+            RecordType::Cname => answer.data.trim_end_matches('.').to_ascii_lowercase(),
+        })
+        .collect())
+}
+
+/// The text of a TXT record as resolvers present it, `"one" "two"` for
+/// a record made of two strings. Some leave a single string unquoted.
+fn unquote(data: &str) -> String {
+    if !data.starts_with('"') {
+        return data.to_string();
+    }
+    data.split('"').skip(1).step_by(2).collect::<String>()
+}
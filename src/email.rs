@@ -1,7 +1,7 @@
-use reqwest::{Client, Request, Response};
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool, Row};
-use std::collections::{BTreeMap, HashMap};
+use sqlx::FromRow;
+use std::collections::HashMap;
 
 #[derive(Clone, Debug)]
 pub struct EmailClient {
@@ -60,39 +60,8 @@ impl EmailClient {
             .json(&request_body)
             .build()?;
 
-        let curl_command = request_to_curl(&request);
-
         let response = self.http_client.execute(request).await?;
 
         Ok(response)
     }
 }
-
-fn request_to_curl(request: &Request) -> Result<String, reqwest::Error> {
-    let command = format!("curl -X {} '{}'", request.method(), request.url());
-
-    // Add headers to the curl command
-    let mut command = request
-        .headers()
-        .iter()
-        .fold(command, |cmd, (name, value)| {
-            format!(
-                "{} -H '{}: {}'",
-                cmd,
-                name.as_str(),
-                value.to_str().unwrap()
-            )
-        });
-
-    // Add request body to the curl command
-    if let Some(body) = request.body() {
-        if let Some(Ok(body_str)) = body
-            .as_bytes()
-            .and_then(|bytes| Some(String::from_utf8(bytes.to_vec())))
-        {
-            command.push_str(&format!(" -d '{}'", body_str));
-        }
-    }
-
-    Ok(command)
-}
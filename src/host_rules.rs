@@ -0,0 +1,181 @@
+//! Hosts link targets may not point at. `BLOCKED_HOSTS` lists them, by
+//! default loopback, link-local and private addresses, and `ALLOWED_HOSTS`
+//! carves exceptions out of it; operators add to both at runtime.
+//!
+//! Entries are domains, which match their subdomains too, or addresses and
+//! CIDR networks, which match targets given by address.
+
+use crate::client_ip::IpNetwork;
+
+use once_cell::sync::Lazy;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use url::{Host, Url};
+
+pub const DEFAULT_BLOCKED_HOSTS: &str = "localhost,0.0.0.0/8,10.0.0.0/8,127.0.0.0/8,\
+    169.254.0.0/16,172.16.0.0/12,192.168.0.0/16,::1,fc00::/7,fe80::/10";
+
+static DEFAULT_BLOCKED: Lazy<Vec<HostPattern>> = Lazy::new(|| {
+    parse_host_patterns(DEFAULT_BLOCKED_HOSTS)
+        .expect("The default blocked hosts should always parse")
+});
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostPattern {
+    Domain(String),
+    Network(IpNetwork),
+}
+
+impl HostPattern {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some(network) = IpNetwork::parse(value) {
+            return Some(HostPattern::Network(network));
+        }
+
+        match Host::parse(value).ok()? {
+            Host::Domain(domain) => {
+                let domain = domain.trim_end_matches('.');
+                (!domain.is_empty()).then(|| HostPattern::Domain(domain.to_string()))
+            }
+            Host::Ipv4(ip) => IpNetwork::parse(&ip.to_string()).map(HostPattern::Network),
+            Host::Ipv6(ip) => IpNetwork::parse(&ip.to_string()).map(HostPattern::Network),
+        }
+    }
+
+    pub fn matches(&self, host: &Host<&str>) -> bool {
+        match (self, host) {
+            (HostPattern::Domain(domain), Host::Domain(host)) => {
+                let host = host.trim_end_matches('.');
+                host == domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|subdomain| subdomain.ends_with('.'))
+            }
+            (HostPattern::Network(network), Host::Ipv4(ip)) => network.contains(IpAddr::V4(*ip)),
+            (HostPattern::Network(network), Host::Ipv6(ip)) => network.contains(IpAddr::V6(*ip)),
+            _ => false,
+        }
+    }
+}
+
+/// The host of a target given by address.
+pub fn ip_host(ip: IpAddr) -> Host<&'static str> {
+    match ip {
+        IpAddr::V4(ip) => Host::Ipv4(ip),
+        IpAddr::V6(ip) => Host::Ipv6(ip),
+    }
+}
+
+impl std::fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostPattern::Domain(domain) => write!(f, "{}", domain),
+            HostPattern::Network(network) => write!(f, "{}", network),
+        }
+    }
+}
+
+/// Parse a comma separated `BLOCKED_HOSTS` or `ALLOWED_HOSTS` list.
+pub fn parse_host_patterns(value: &str) -> Option<Vec<HostPattern>> {
+    value
+        .split(',')
+        .filter(|pattern| !pattern.trim().is_empty())
+        .map(HostPattern::parse)
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostList {
+    Blocked,
+    Allowed,
+}
+
+impl HostList {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HostList::Blocked => "blocked",
+            HostList::Allowed => "allowed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "blocked" => Some(HostList::Blocked),
+            "allowed" => Some(HostList::Allowed),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a target on `host` is refused: when a blocked entry matches it
+/// and no allowed one does.
+pub fn is_blocked<'a>(
+    rules: impl IntoIterator<Item = (HostList, &'a HostPattern)>,
+    host: &Host<&str>,
+) -> bool {
+    let mut blocked = false;
+    for (list, pattern) in rules {
+        if pattern.matches(host) {
+            match list {
+                HostList::Allowed => return false,
+                HostList::Blocked => blocked = true,
+            }
+        }
+    }
+    blocked
+}
+
+/// Whether the service may fetch `url` itself, as for previews: an http or
+/// https URL not given by a loopback, link-local or private address. Names
+/// are checked as `PublicResolver` resolves them.
+pub fn is_fetchable(url: &Url) -> bool {
+    let host_blocked = match url.host() {
+        Some(Host::Domain(_)) => false,
+        Some(host) => DEFAULT_BLOCKED.iter().any(|pattern| pattern.matches(&host)),
+        None => true,
+    };
+    matches!(url.scheme(), "http" | "https") && !host_blocked
+}
+
+/// Follow at most `max_redirects` redirects, and only to URLs
+/// `is_fetchable` lets through, for clients resolving through
+/// `PublicResolver`.
+pub fn fetchable_redirects(max_redirects: usize) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= max_redirects || !is_fetchable(attempt.url()) {
+            attempt.stop()
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// Resolves names for requests made to link targets, leaving out loopback,
+/// link-local and private addresses, so a target resolving to an internal
+/// service is never reached however its name was checked when saved.
+#[derive(Debug, Default)]
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| {
+                    !DEFAULT_BLOCKED
+                        .iter()
+                        .any(|pattern| pattern.matches(&ip_host(addr.ip())))
+                })
+                .collect();
+            if addrs.is_empty() {
+                return Err(
+                    format!("{} only resolves to internal addresses", name.as_str()).into(),
+                );
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
@@ -0,0 +1,179 @@
+//! Translations of API errors, pages and emails.
+//!
+//! Catalogs live in `locales/<language>.txt` as `key = text` lines. A locale
+//! such as `pt-BR` falls back to `pt` and then to English, so a catalog only
+//! needs the messages that differ from the next one in the chain.
+
+use axum::body::{Body, HttpBody};
+use axum::extract::Request;
+use axum::http::header::{CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Error bodies longer than this are not plain messages worth translating.
+const MAX_TRANSLATED_BODY: usize = 1024;
+
+const CATALOG_SOURCES: [(&str, &str); 3] = [
+    ("en", include_str!("../locales/en.txt")),
+    ("es", include_str!("../locales/es.txt")),
+    ("pt", include_str!("../locales/pt.txt")),
+];
+
+type Catalog = HashMap<&'static str, &'static str>;
+
+static CATALOGS: Lazy<HashMap<&'static str, Catalog>> = Lazy::new(|| {
+    CATALOG_SOURCES
+        .iter()
+        .map(|(locale, source)| (*locale, parse_catalog(source)))
+        .collect()
+});
+
+/// English text of every message, lowercased, to find the key of an error
+/// that handlers report as a plain string.
+static KEYS_BY_ENGLISH_TEXT: Lazy<HashMap<String, &'static str>> = Lazy::new(|| {
+    CATALOGS[DEFAULT_LOCALE]
+        .iter()
+        .map(|(key, text)| (text.to_lowercase(), *key))
+        .collect()
+});
+
+fn parse_catalog(source: &'static str) -> Catalog {
+    source
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, text)| (key.trim(), text.trim()))
+        .collect()
+}
+
+/// The supported locale closest to a language tag such as `pt-BR`, if any.
+pub fn resolve(tag: &str) -> Option<&'static str> {
+    let tag = tag.trim().to_ascii_lowercase();
+    let primary = tag.split(['-', '_']).next().unwrap_or_default();
+
+    CATALOGS
+        .keys()
+        .find(|locale| locale.eq_ignore_ascii_case(&tag))
+        .or_else(|| CATALOGS.keys().find(|locale| **locale == primary))
+        .copied()
+}
+
+/// The language ranges of an `Accept-Language` header, most preferred first
+/// by quality value, leaving out refused ones.
+pub fn language_preferences(headers: &HeaderMap) -> Vec<&str> {
+    let Some(header) = headers
+        .get("accept-language")
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Vec::new();
+    };
+
+    let mut ranges: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.trim().parse().ok())?;
+            (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // Stable, so equally preferred languages keep the client's order.
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    ranges.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// Pick the locale to answer in from an `Accept-Language` header, honouring
+/// its quality values.
+pub fn negotiate(headers: &HeaderMap) -> &'static str {
+    language_preferences(headers)
+        .into_iter()
+        .find_map(|tag| {
+            if tag == "*" {
+                Some(DEFAULT_LOCALE)
+            } else {
+                resolve(tag)
+            }
+        })
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+/// The translation of a message, following the fallback chain to English.
+pub fn translate(locale: &str, key: &str) -> Option<&'static str> {
+    let primary = locale.split('-').next().unwrap_or_default();
+
+    [locale, primary, DEFAULT_LOCALE]
+        .into_iter()
+        .filter_map(|locale| CATALOGS.get(locale))
+        .find_map(|catalog| catalog.get(key).copied())
+}
+
+/// Every message under a key prefix such as `page.`, translated.
+pub fn translations(locale: &str, prefix: &str) -> Vec<(&'static str, &'static str)> {
+    CATALOGS[DEFAULT_LOCALE]
+        .keys()
+        .filter(|key| key.starts_with(prefix))
+        .filter_map(|key| Some((*key, translate(locale, key)?)))
+        .collect()
+}
+
+/// Translate an English message, as long as it is in the catalog.
+pub fn translate_message(locale: &str, message: &str) -> Option<&'static str> {
+    let key = KEYS_BY_ENGLISH_TEXT.get(&message.trim().to_lowercase())?;
+    translate(locale, key)
+}
+
+/// Translate the plain text error messages handlers return into the
+/// language the client asked for.
+pub async fn translate_errors(request: Request, next: Next) -> Response {
+    let locale = negotiate(request.headers());
+    let response = next.run(request).await;
+
+    let is_plain_error = (response.status().is_client_error()
+        || response.status().is_server_error())
+        && response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/plain"));
+
+    if locale == DEFAULT_LOCALE || !is_plain_error {
+        return response;
+    }
+
+    if response.body().size_hint().upper() > Some(MAX_TRANSLATED_BODY as u64) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_TRANSLATED_BODY).await else {
+        tracing::warn!("Could not read error body to translate it");
+        return Response::from_parts(parts, Body::empty());
+    };
+
+    let translated = std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|message| translate_message(locale, message));
+
+    let Some(translated) = translated else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_LANGUAGE, HeaderValue::from_static(locale));
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("Accept-Language"));
+
+    Response::from_parts(parts, Body::from(translated))
+}
@@ -0,0 +1,260 @@
+//! Integrity checks run on startup, and again on demand by operators. A
+//! check counts rows breaking an invariant the schema does not enforce, or
+//! no longer does after a constraint was dropped by hand, and knows how to
+//! repair them. While a problem is left unrepaired, requests changing data
+//! are refused, except those operators need to investigate and sign in.
+
+use crate::InnerState;
+
+use axum::extract::{Request, State};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::RwLock;
+
+/// Paths still taking writes while data is corrupt, so operators can sign
+/// in and repair it.
+const WRITABLE_PATH_PREFIXES: [&str; 3] = ["/admin/", "/authorize", "/auth/"];
+
+struct Check {
+    name: &'static str,
+    count: &'static str,
+    repair: &'static str,
+}
+
+const CHECKS: [Check; 6] = [
+    Check {
+        name: "orphaned_statistics",
+        count: r#"SELECT count(*) FROM link_statistics s
+            WHERE NOT EXISTS (SELECT 1 FROM links WHERE links.id = s.link_id)"#,
+        repair: r#"DELETE FROM link_statistics s
+            WHERE NOT EXISTS (SELECT 1 FROM links WHERE links.id = s.link_id)"#,
+    },
+    Check {
+        name: "orphaned_daily_statistics",
+        count: r#"SELECT count(*) FROM link_statistics_daily s
+            WHERE NOT EXISTS (SELECT 1 FROM links WHERE links.id = s.link_id)"#,
+        repair: r#"DELETE FROM link_statistics_daily s
+            WHERE NOT EXISTS (SELECT 1 FROM links WHERE links.id = s.link_id)"#,
+    },
+    Check {
+        name: "dangling_scheduled_changes",
+        count: r#"SELECT count(*) FROM link_scheduled_changes c
+            WHERE NOT EXISTS (SELECT 1 FROM links WHERE links.id = c.link_id)"#,
+        repair: r#"DELETE FROM link_scheduled_changes c
+            WHERE NOT EXISTS (SELECT 1 FROM links WHERE links.id = c.link_id)"#,
+    },
+    Check {
+        name: "dangling_group_event_links",
+        count: r#"SELECT count(*) FROM group_events e WHERE e.link_id IS NOT NULL
+            AND NOT EXISTS (SELECT 1 FROM links WHERE links.id = e.link_id)"#,
+        repair: r#"UPDATE group_events e SET link_id = NULL WHERE e.link_id IS NOT NULL
+            AND NOT EXISTS (SELECT 1 FROM links WHERE links.id = e.link_id)"#,
+    },
+    Check {
+        name: "unknown_link_states",
+        count: r#"SELECT count(*) FROM links
+            WHERE state NOT IN ('draft', 'active', 'paused', 'disabled', 'deleted')"#,
+        repair: r#"UPDATE links SET state = 'disabled', disabled_at = coalesce(disabled_at, localtimestamp)
+            WHERE state NOT IN ('draft', 'active', 'paused', 'disabled', 'deleted')"#,
+    },
+    // Redirects go by `disabled_at`, so a link whose state disagrees with
+    // it redirects when it should not, or the other way round. Repairs err
+    // on the side of not redirecting.
+    Check {
+        name: "inconsistent_link_states",
+        count: r#"SELECT count(*) FROM links
+            WHERE (deleted_at IS NOT NULL) <> (state = 'deleted')
+            OR (disabled_at IS NOT NULL) <> (state <> 'active')"#,
+        repair: r#"UPDATE links SET
+            state = CASE
+                WHEN deleted_at IS NOT NULL THEN 'deleted'
+                WHEN state = 'active' AND disabled_at IS NOT NULL THEN 'disabled'
+                ELSE state
+            END,
+            deleted_at = CASE WHEN state = 'deleted' THEN coalesce(deleted_at, localtimestamp) ELSE deleted_at END,
+            disabled_at = CASE WHEN state <> 'active' THEN coalesce(disabled_at, localtimestamp) ELSE disabled_at END
+            WHERE (deleted_at IS NOT NULL) <> (state = 'deleted')
+            OR (disabled_at IS NOT NULL) <> (state <> 'active')"#,
+    },
+];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityReport {
+    pub checked_at: NaiveDateTime,
+    pub schema: SchemaStatus,
+    pub problems: Vec<IntegrityProblem>,
+    /// Whether requests changing data are refused until a check passes.
+    pub read_only: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaStatus {
+    /// Latest migration this build ships.
+    pub expected_version: i64,
+    /// Latest migration applied to the database.
+    pub applied_version: Option<i64>,
+    /// Migrations the database records as having failed halfway.
+    pub failed_versions: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityProblem {
+    pub check: &'static str,
+    /// Rows found breaking the invariant.
+    pub rows: i64,
+    /// Rows the repair changed, when one was run.
+    pub repaired_rows: Option<u64>,
+}
+
+impl SchemaStatus {
+    fn is_current(&self) -> bool {
+        self.failed_versions.is_empty() && self.applied_version == Some(self.expected_version)
+    }
+}
+
+/// The latest report, which every request changing data consults.
+pub struct Integrity {
+    report: RwLock<IntegrityReport>,
+}
+
+impl Integrity {
+    /// Run every check, repairing what they find when `repair` is set.
+    pub async fn check(db: &PgPool, repair: bool) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            report: RwLock::new(run_checks(db, repair).await?),
+        })
+    }
+
+    pub async fn recheck(&self, db: &PgPool, repair: bool) -> Result<IntegrityReport, sqlx::Error> {
+        let report = run_checks(db, repair).await?;
+        *self
+            .report
+            .write()
+            .expect("The integrity report lock should never be poisoned") = report.clone();
+        Ok(report)
+    }
+
+    pub fn report(&self) -> IntegrityReport {
+        self.report
+            .read()
+            .expect("The integrity report lock should never be poisoned")
+            .clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.report
+            .read()
+            .expect("The integrity report lock should never be poisoned")
+            .read_only
+    }
+}
+
+async fn run_checks(db: &PgPool, repair: bool) -> Result<IntegrityReport, sqlx::Error> {
+    let schema = schema_status(db).await?;
+    if !schema.is_current() {
+        tracing::error!(
+            "The database schema is at version {:?}, failed {:?}, but this build expects {}",
+            schema.applied_version,
+            schema.failed_versions,
+            schema.expected_version
+        );
+    }
+
+    let mut problems = Vec::new();
+    for check in &CHECKS {
+        let rows: i64 = sqlx::query_scalar(check.count).fetch_one(db).await?;
+        if rows == 0 {
+            continue;
+        }
+
+        let repaired_rows = if repair {
+            let repaired = sqlx::query(check.repair).execute(db).await?.rows_affected();
+            tracing::warn!("Repaired {} rows failing {}", repaired, check.name);
+            Some(repaired)
+        } else {
+            tracing::error!(
+                "{} rows fail {}, leaving them alone as repairs are off",
+                rows,
+                check.name
+            );
+            None
+        };
+        problems.push(IntegrityProblem {
+            check: check.name,
+            rows,
+            repaired_rows,
+        });
+    }
+
+    let read_only = !schema.is_current()
+        || problems
+            .iter()
+            .any(|problem| problem.repaired_rows.is_none());
+    if read_only {
+        tracing::error!("Refusing writes until integrity problems are repaired");
+    }
+
+    Ok(IntegrityReport {
+        checked_at: Utc::now().naive_utc(),
+        schema,
+        problems,
+        read_only,
+    })
+}
+
+async fn schema_status(db: &PgPool) -> Result<SchemaStatus, sqlx::Error> {
+    let expected_version = sqlx::migrate!()
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default();
+
+    let (applied_version, failed_versions): (Option<i64>, Vec<i64>) = sqlx::query_as(
+        r#"SELECT max(version) FILTER (WHERE success),
+        coalesce(array_agg(version ORDER BY version) FILTER (WHERE NOT success), '{}')
+        FROM _sqlx_migrations"#,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(SchemaStatus {
+        expected_version,
+        applied_version,
+        failed_versions,
+    })
+}
+
+/// Refuse requests changing data while the latest integrity check found
+/// problems left unrepaired.
+pub async fn refuse_writes_when_corrupt(
+    State(inner): State<InnerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let reads = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let path = request.uri().path();
+    let writable = WRITABLE_PATH_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix));
+
+    if !reads && !writable && inner.integrity.is_read_only() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Writes are paused while data integrity problems are repaired, see /admin/integrity"
+                .to_string(),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,130 @@
+//! Background jobs that must run once per interval across every replica.
+//!
+//! Only the elected leader runs jobs. On top of that, a run takes the job's
+//! lease in `job_leases`, which is only granted when the previous run
+//! started at least a full interval ago, so a change of leader cannot run a
+//! job twice in one interval. A session advisory lock keeps a run that
+//! overruns its interval from overlapping with the next one; it is released
+//! by Postgres if the replica dies mid-run.
+
+use crate::leader::{self, REPLICA_ID};
+use crate::task_health;
+
+use sqlx::{PgConnection, PgPool};
+use std::future::Future;
+use std::time::Duration;
+
+/// Lets a replica whose timer fires marginally early still take its turn.
+const LEASE_SLACK: Duration = Duration::from_secs(1);
+
+/// Run `job` every `every` on the leader.
+pub async fn run_periodically<F, Fut>(db: PgPool, name: &'static str, every: Duration, job: F)
+where
+    F: Fn(PgPool) -> Fut,
+    Fut: Future<Output = Result<(), sqlx::Error>>,
+{
+    // Check often, so a new leader picks up jobs soon after failing over.
+    let check_every = every.min(leader::HEARTBEAT_INTERVAL);
+    let mut interval = tokio::time::interval(check_every);
+    task_health::register(name, check_every);
+
+    loop {
+        interval.tick().await;
+
+        if !leader::is_leader() {
+            task_health::idle(name);
+            continue;
+        }
+
+        match run_once(&db, name, every, &job).await {
+            Ok(true) => {
+                tracing::debug!("Job {} ran", name);
+                task_health::succeeded(name);
+            }
+            Ok(false) => {
+                tracing::trace!("Job {} is not due", name);
+                task_health::idle(name);
+            }
+            Err(err) => {
+                tracing::error!("Job {} failed: {:?}", name, err);
+                task_health::failed(name, &err);
+            }
+        }
+    }
+}
+
+/// Run the job if this replica wins its lease. Returns whether it ran.
+async fn run_once<F, Fut>(
+    db: &PgPool,
+    name: &'static str,
+    every: Duration,
+    job: &F,
+) -> Result<bool, sqlx::Error>
+where
+    F: Fn(PgPool) -> Fut,
+    Fut: Future<Output = Result<(), sqlx::Error>>,
+{
+    // Session locks belong to a connection, so hold one for the whole run.
+    let mut connection = db.acquire().await?;
+
+    let locked: bool = sqlx::query_scalar(r#"SELECT pg_try_advisory_lock(hashtext($1))"#)
+        .bind(name)
+        .fetch_one(&mut *connection)
+        .await?;
+    if !locked {
+        return Ok(false);
+    }
+
+    let result = run_leased(&mut connection, db, name, every, job).await;
+
+    let unlocked = sqlx::query(r#"SELECT pg_advisory_unlock(hashtext($1))"#)
+        .bind(name)
+        .execute(&mut *connection)
+        .await;
+    if let Err(err) = unlocked {
+        // Closing the connection is the only other way to release the lock.
+        connection.detach();
+        return Err(err);
+    }
+
+    result
+}
+
+async fn run_leased<F, Fut>(
+    connection: &mut PgConnection,
+    db: &PgPool,
+    name: &'static str,
+    every: Duration,
+    job: &F,
+) -> Result<bool, sqlx::Error>
+where
+    F: Fn(PgPool) -> Fut,
+    Fut: Future<Output = Result<(), sqlx::Error>>,
+{
+    let leased = sqlx::query(
+        r#"INSERT INTO job_leases (name, holder, started_at) VALUES ($1, $2, CURRENT_TIMESTAMP)
+        ON CONFLICT (name) DO UPDATE SET holder = excluded.holder,
+            started_at = excluded.started_at, finished_at = NULL
+        WHERE job_leases.started_at <= CURRENT_TIMESTAMP - make_interval(secs => $3)"#,
+    )
+    .bind(name)
+    .bind(REPLICA_ID.as_str())
+    .bind(every.saturating_sub(LEASE_SLACK).as_secs_f64())
+    .execute(&mut *connection)
+    .await?
+    .rows_affected()
+        > 0;
+
+    if !leased {
+        return Ok(false);
+    }
+
+    job(db.clone()).await?;
+
+    sqlx::query(r#"UPDATE job_leases SET finished_at = CURRENT_TIMESTAMP WHERE name = $1"#)
+        .bind(name)
+        .execute(&mut *connection)
+        .await?;
+
+    Ok(true)
+}
@@ -0,0 +1,102 @@
+//! Lease based leader election between replicas.
+//!
+//! Every replica heartbeats the `scheduler` row of `leader_leases`. The
+//! holder extends its lease on each beat; anyone else can only take the row
+//! over once the lease has expired, so a crashed leader is replaced within
+//! `LEASE_TTL`. A replica stops considering itself leader as soon as a
+//! renewal fails or is overdue, before its lease can be handed to another.
+
+use once_cell::sync::Lazy;
+use sqlx::PgPool;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+pub const SCHEDULER_LEASE: &str = "scheduler";
+
+/// How long a lease lasts without a heartbeat.
+const LEASE_TTL: Duration = Duration::from_secs(30);
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Identifies this process in leases.
+pub static REPLICA_ID: Lazy<String> = Lazy::new(|| {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string());
+    format!(
+        "{}:{}:{}",
+        host,
+        std::process::id(),
+        Uuid::new_v4().simple()
+    )
+});
+
+/// When this replica last renewed the scheduler lease, if it holds it.
+static RENEWED_AT: RwLock<Option<Instant>> = RwLock::new(None);
+
+/// Whether this replica currently leads and may run the scheduler.
+pub fn is_leader() -> bool {
+    RENEWED_AT
+        .read()
+        .expect("The leadership lock should never be poisoned")
+        .is_some_and(|renewed_at| renewed_at.elapsed() < LEASE_TTL)
+}
+
+fn set_renewed_at(renewed_at: Option<Instant>) {
+    *RENEWED_AT
+        .write()
+        .expect("The leadership lock should never be poisoned") = renewed_at;
+}
+
+/// Take or extend the lease when it is ours or has expired. Returns whether
+/// this replica holds it afterwards.
+async fn heartbeat(db: &PgPool) -> Result<bool, sqlx::Error> {
+    let renewed = sqlx::query(
+        r#"INSERT INTO leader_leases (name, holder, acquired_at, heartbeat_at, expires_at)
+        VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP + make_interval(secs => $3))
+        ON CONFLICT (name) DO UPDATE SET holder = excluded.holder,
+            acquired_at = CASE WHEN leader_leases.holder = excluded.holder
+                THEN leader_leases.acquired_at ELSE excluded.acquired_at END,
+            heartbeat_at = excluded.heartbeat_at, expires_at = excluded.expires_at
+        WHERE leader_leases.holder = excluded.holder OR leader_leases.expires_at < CURRENT_TIMESTAMP"#,
+    )
+    .bind(SCHEDULER_LEASE)
+    .bind(REPLICA_ID.as_str())
+    .bind(LEASE_TTL.as_secs_f64())
+    .execute(db)
+    .await?;
+
+    Ok(renewed.rows_affected() > 0)
+}
+
+/// Keep competing for leadership for as long as the process runs.
+pub async fn run_election(db: PgPool) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Taken before the query, so a slow renewal shortens our term
+        // rather than stretching it past the lease in the database.
+        let attempted_at = Instant::now();
+        let was_leader = is_leader();
+
+        match heartbeat(&db).await {
+            Ok(true) => {
+                set_renewed_at(Some(attempted_at));
+                if !was_leader {
+                    tracing::info!("Replica {} became leader", REPLICA_ID.as_str());
+                }
+            }
+            Ok(false) => {
+                set_renewed_at(None);
+                if was_leader {
+                    tracing::warn!("Replica {} lost leadership", REPLICA_ID.as_str());
+                }
+            }
+            Err(err) => {
+                set_renewed_at(None);
+                tracing::error!("Leader heartbeat failed: {:?}", err);
+            }
+        }
+    }
+}
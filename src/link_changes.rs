@@ -0,0 +1,107 @@
+//! Telling every replica that links changed, so none keeps redirecting to
+//! what they were from its redirect snapshot or in-memory redirect cache.
+//!
+//! Whatever changes how a link redirects, be it a request or a job, sends a
+//! `link_changed` notification per link, which every replica, the sending
+//! one included, listens for to forget the link. Notifications sent inside
+//! a transaction are only delivered once it commits. A replica that lost
+//! its connection may have missed some, so it drops its snapshot and
+//! in-memory cache until they are rebuilt.
+
+use crate::redirect_cache::RedirectCache;
+use crate::redirect_snapshot::RedirectSnapshot;
+
+use sqlx::postgres::PgListener;
+use sqlx::{PgExecutor, PgPool};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const LINK_CHANGED_CHANNEL: &str = "link_changed";
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Tell every replica the links changed. Within a transaction, this takes
+/// effect when it commits.
+pub async fn announce_link_changes<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_ids: &[String],
+) -> Result<(), sqlx::Error> {
+    if link_ids.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(r#"SELECT pg_notify($1, link_id) FROM unnest($2::text[]) AS link_id"#)
+        .bind(LINK_CHANGED_CHANNEL)
+        .bind(link_ids)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Forget a link this replica just changed and committed, right away here
+/// and through a notification everywhere else.
+pub async fn forget_link(
+    db: &PgPool,
+    redirects: &RedirectSnapshot,
+    redirect_cache: &RedirectCache,
+    link_id: &str,
+) {
+    redirects.forget(link_id);
+    redirect_cache.forget(link_id).await;
+
+    if let Err(err) = announce_link_changes(db, &[link_id.to_string()]).await {
+        tracing::error!("Could not announce the change of link {}: {}", link_id, err);
+    }
+}
+
+/// Forget links as they are announced, for as long as the process runs.
+pub async fn run_link_change_listener(
+    db: PgPool,
+    redirects: Arc<RedirectSnapshot>,
+    redirect_cache: Arc<RedirectCache>,
+) {
+    let mut reconnecting = false;
+
+    loop {
+        if let Err(err) = listen_for_changes(&db, &redirects, &redirect_cache, reconnecting).await {
+            tracing::error!("Link change listener failed: {:?}", err);
+        }
+
+        reconnecting = true;
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen_for_changes(
+    db: &PgPool,
+    redirects: &RedirectSnapshot,
+    redirect_cache: &RedirectCache,
+    reconnecting: bool,
+) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(db).await?;
+    listener.listen(LINK_CHANGED_CHANNEL).await?;
+
+    // Changes may have been missed while not listening.
+    if reconnecting {
+        forget_all(redirects, redirect_cache);
+    }
+
+    loop {
+        match listener.try_recv().await? {
+            Some(notification) => {
+                redirects.forget(notification.payload());
+                redirect_cache.forget(notification.payload()).await;
+            }
+            None => {
+                tracing::warn!("Link change listener lost its connection");
+                forget_all(redirects, redirect_cache);
+            }
+        }
+    }
+}
+
+fn forget_all(redirects: &RedirectSnapshot, redirect_cache: &RedirectCache) {
+    redirects.forget_all();
+    redirect_cache.forget_local();
+}
@@ -0,0 +1,237 @@
+//! Bloom filter of every link id, consulted before looking a link up, so
+//! scanners probing random slugs are turned away without a query.
+//!
+//! The filter may let an unknown id through, which then just misses in the
+//! database, but must never reject a link that exists. It is rebuilt from
+//! `links` on a schedule, and a trigger on `links` sends a `link_created`
+//! notification on every insert, which each replica listens for to add new
+//! ids as they appear. Notifications sent while the listener was
+//! disconnected are lost, so the filter is rebuilt after reconnecting. Until
+//! the first build completes every id is let through.
+
+use crate::configuration::Settings;
+use crate::db::links;
+
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::collections::hash_map::DefaultHasher;
+use std::f64::consts::LN_2;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+const LINK_CREATED_CHANNEL: &str = "link_created";
+
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Room for ids created between rebuilds, as a multiple of the current count.
+const GROWTH_FACTOR: usize = 2;
+
+const MIN_CAPACITY: usize = 1024;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+pub struct LinkFilter {
+    enabled: bool,
+    every: Duration,
+    current: RwLock<Option<Bloom>>,
+    /// Ids added while a rebuild is reading `links`, to carry over into the
+    /// new filter in case the read missed them.
+    during_rebuild: Mutex<Option<Vec<String>>>,
+    /// Woken when notifications may have been missed.
+    stale: Notify,
+}
+
+impl LinkFilter {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            enabled: settings.link_filter,
+            every: settings.link_filter_interval,
+            current: RwLock::new(None),
+            during_rebuild: Mutex::new(None),
+            stale: Notify::new(),
+        }
+    }
+
+    /// Whether a link with this id may exist. When `false` it certainly does
+    /// not.
+    pub fn might_exist(&self, id: &str) -> bool {
+        self.current
+            .read()
+            .expect("The link filter lock should never be poisoned")
+            .as_ref()
+            .is_none_or(|bloom| bloom.contains(id))
+    }
+
+    fn insert(&self, id: &str) {
+        let current = self
+            .current
+            .read()
+            .expect("The link filter lock should never be poisoned");
+        if let Some(bloom) = current.as_ref() {
+            bloom.insert(id);
+        }
+
+        if let Some(during_rebuild) = self
+            .during_rebuild
+            .lock()
+            .expect("The link filter lock should never be poisoned")
+            .as_mut()
+        {
+            during_rebuild.push(id.to_string());
+        }
+    }
+
+    /// Keep the filter up to date for as long as the process runs, when it
+    /// is enabled.
+    pub async fn run_maintainer(self: Arc<Self>, db: PgPool) {
+        if !self.enabled {
+            return;
+        }
+
+        tokio::spawn(self.clone().listen(db.clone()));
+
+        let mut interval = tokio::time::interval(self.every);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = self.stale.notified() => {}
+            }
+
+            match self.rebuild(&db).await {
+                Ok(ids) => tracing::debug!("Link filter rebuilt with {} ids", ids),
+                Err(err) => tracing::error!("Could not rebuild link filter: {:?}", err),
+            }
+        }
+    }
+
+    async fn rebuild(&self, db: &PgPool) -> Result<usize, sqlx::Error> {
+        *self
+            .during_rebuild
+            .lock()
+            .expect("The link filter lock should never be poisoned") = Some(Vec::new());
+
+        let ids = links::all_link_ids(db).await;
+        let ids = match ids {
+            Ok(ids) => ids,
+            Err(err) => {
+                *self
+                    .during_rebuild
+                    .lock()
+                    .expect("The link filter lock should never be poisoned") = None;
+                return Err(err);
+            }
+        };
+
+        let bloom = Bloom::with_capacity(ids.len() * GROWTH_FACTOR);
+        for id in &ids {
+            bloom.insert(id);
+        }
+
+        // Taking the write lock waits out inserts in flight, so every id ends
+        // up in the new filter either way.
+        let mut current = self
+            .current
+            .write()
+            .expect("The link filter lock should never be poisoned");
+        let during_rebuild = self
+            .during_rebuild
+            .lock()
+            .expect("The link filter lock should never be poisoned")
+            .take()
+            .unwrap_or_default();
+        for id in &during_rebuild {
+            bloom.insert(id);
+        }
+        *current = Some(bloom);
+
+        Ok(ids.len())
+    }
+
+    async fn listen(self: Arc<Self>, db: PgPool) {
+        let mut reconnecting = false;
+
+        loop {
+            match self.listen_until_failure(&db, reconnecting).await {
+                Ok(()) => {}
+                Err(err) => tracing::error!("Link filter listener failed: {:?}", err),
+            }
+
+            reconnecting = true;
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn listen_until_failure(
+        &self,
+        db: &PgPool,
+        reconnecting: bool,
+    ) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect_with(db).await?;
+        listener.listen(LINK_CREATED_CHANNEL).await?;
+
+        if reconnecting {
+            self.stale.notify_one();
+        }
+
+        loop {
+            match listener.try_recv().await? {
+                Some(notification) => self.insert(notification.payload()),
+                None => {
+                    // The listener reconnected on its own, dropping whatever
+                    // was sent in between.
+                    tracing::warn!("Link filter listener lost its connection");
+                    self.stale.notify_one();
+                }
+            }
+        }
+    }
+}
+
+struct Bloom {
+    words: Vec<AtomicU64>,
+    hashes: u64,
+}
+
+impl Bloom {
+    fn with_capacity(ids: usize) -> Self {
+        let ids = ids.max(MIN_CAPACITY) as f64;
+        let bits = (-ids * FALSE_POSITIVE_RATE.ln() / (LN_2 * LN_2)).ceil() as usize;
+        let words = bits.div_ceil(64);
+        let hashes = ((words * 64) as f64 / ids * LN_2).round().max(1.0) as u64;
+
+        Self {
+            words: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            hashes,
+        }
+    }
+
+    /// The word and mask of every bit for `id`, derived from a single hash by
+    /// double hashing.
+    fn probes(&self, id: &str) -> impl Iterator<Item = (usize, u64)> {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        let hash = hasher.finish();
+        let step = hash.rotate_left(32) | 1;
+        let bits = self.words.len() as u64 * 64;
+
+        (0..self.hashes).map(move |probe| {
+            let bit = hash.wrapping_add(probe.wrapping_mul(step)) % bits;
+            ((bit / 64) as usize, 1 << (bit % 64))
+        })
+    }
+
+    fn insert(&self, id: &str) {
+        for (word, mask) in self.probes(id) {
+            self.words[word].fetch_or(mask, Ordering::Relaxed);
+        }
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.probes(id)
+            .all(|(word, mask)| self.words[word].load(Ordering::Relaxed) & mask != 0)
+    }
+}
@@ -0,0 +1,185 @@
+//! What links of a kind other than `url` carry instead of a target: a
+//! contact card or Wi-Fi credentials. Their QR code encodes the payload
+//! itself, so scanning it works offline, and their short link serves a
+//! landing page showing it.
+
+use crate::validation::ValidationErrors;
+
+use serde::{Deserialize, Serialize};
+
+/// Kind of the links redirecting to their target URL.
+pub const URL_KIND: &str = "url";
+
+const MAX_PAYLOAD_FIELD_LENGTH: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum LinkPayload {
+    Vcard(Contact),
+    Wifi(WifiNetwork),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Contact {
+    pub name: String,
+    pub organization: Option<String>,
+    pub title: Option<String>,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub website: Option<String>,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WifiNetwork {
+    pub ssid: String,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub security: WifiSecurity,
+    /// Whether the network does not broadcast its name.
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WifiSecurity {
+    #[default]
+    Wpa,
+    Wep,
+    Open,
+}
+
+impl LinkPayload {
+    /// The `kind` links carrying this payload have.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LinkPayload::Vcard(_) => "vcard",
+            LinkPayload::Wifi(_) => "wifi",
+        }
+    }
+
+    /// What the QR code of the link encodes.
+    pub fn qr_text(&self) -> String {
+        match self {
+            LinkPayload::Vcard(contact) => contact.to_vcard(),
+            LinkPayload::Wifi(network) => network.to_wifi_uri(),
+        }
+    }
+
+    pub fn validate(&self, errors: &mut ValidationErrors) {
+        match self {
+            LinkPayload::Vcard(contact) => {
+                errors.require_not_blank("payload.name", &contact.name);
+                for (field, value) in [
+                    ("payload.name", Some(&contact.name)),
+                    ("payload.organization", contact.organization.as_ref()),
+                    ("payload.title", contact.title.as_ref()),
+                    ("payload.phone", contact.phone.as_ref()),
+                    ("payload.email", contact.email.as_ref()),
+                    ("payload.website", contact.website.as_ref()),
+                    ("payload.note", contact.note.as_ref()),
+                ] {
+                    if let Some(value) = value {
+                        errors.require_max_length(field, value, MAX_PAYLOAD_FIELD_LENGTH);
+                    }
+                }
+                if let Some(website) = &contact.website {
+                    errors.require_web_url("payload.website", website);
+                }
+            }
+            LinkPayload::Wifi(network) => {
+                errors.require_not_blank("payload.ssid", &network.ssid);
+                errors.require_max_length("payload.ssid", &network.ssid, 32);
+                match (&network.password, network.security) {
+                    (Some(_), WifiSecurity::Open) => {
+                        errors.add("payload.password", "must be left out for open networks")
+                    }
+                    (None, WifiSecurity::Wpa | WifiSecurity::Wep) => {
+                        errors.add("payload.password", "must not be empty")
+                    }
+                    (Some(password), _) => {
+                        errors.require_not_blank("payload.password", password);
+                        errors.require_max_length(
+                            "payload.password",
+                            password,
+                            MAX_PAYLOAD_FIELD_LENGTH,
+                        );
+                    }
+                    (None, WifiSecurity::Open) => {}
+                }
+            }
+        }
+    }
+}
+
+impl Contact {
+    /// A vCard 3.0, which phones import when scanning it.
+    pub fn to_vcard(&self) -> String {
+        let mut lines = vec![
+            "BEGIN:VCARD".to_string(),
+            "VERSION:3.0".to_string(),
+            format!("N:{};;;;", vcard_escape(&self.name)),
+            format!("FN:{}", vcard_escape(&self.name)),
+        ];
+        for (property, value) in [
+            ("ORG", &self.organization),
+            ("TITLE", &self.title),
+            ("TEL", &self.phone),
+            ("EMAIL", &self.email),
+            ("URL", &self.website),
+            ("NOTE", &self.note),
+        ] {
+            if let Some(value) = value.as_deref().filter(|value| !value.trim().is_empty()) {
+                lines.push(format!("{}:{}", property, vcard_escape(value)));
+            }
+        }
+        lines.push("END:VCARD".to_string());
+
+        lines.join("\r\n") + "\r\n"
+    }
+}
+
+impl WifiNetwork {
+    /// The `WIFI:` URI phone cameras offer to join the network from.
+    pub fn to_wifi_uri(&self) -> String {
+        let security = match self.security {
+            WifiSecurity::Wpa => "WPA",
+            WifiSecurity::Wep => "WEP",
+            WifiSecurity::Open => "nopass",
+        };
+
+        let mut uri = format!("WIFI:T:{};S:{};", security, wifi_escape(&self.ssid));
+        if let Some(password) = &self.password {
+            uri.push_str(&format!("P:{};", wifi_escape(password)));
+        }
+        if self.hidden {
+            uri.push_str("H:true;");
+        }
+        uri.push(';');
+
+        uri
+    }
+}
+
+fn vcard_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn wifi_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
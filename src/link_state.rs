@@ -0,0 +1,76 @@
+//! The states a link goes through, and which one it may go to from each.
+//! Links only redirect while active. `expired` is not stored: it is what
+//! an active link whose `expires_at` passed is in, and making a link
+//! expire sets `expires_at` instead.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    /// Created without redirecting yet.
+    Draft,
+    Active,
+    /// Stopped redirecting by its owner for a while.
+    Paused,
+    Expired,
+    /// Stopped redirecting by operators, or along with its owner's account.
+    Disabled,
+    /// In the trash for good, keeping its statistics and id.
+    Deleted,
+}
+
+impl LinkState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkState::Draft => "draft",
+            LinkState::Active => "active",
+            LinkState::Paused => "paused",
+            LinkState::Expired => "expired",
+            LinkState::Disabled => "disabled",
+            LinkState::Deleted => "deleted",
+        }
+    }
+
+    /// What the `state` column holds for links in this state.
+    pub fn stored(self) -> &'static str {
+        match self {
+            LinkState::Expired => LinkState::Active.as_str(),
+            state => state.as_str(),
+        }
+    }
+
+    pub fn can_transition_to(self, to: LinkState) -> bool {
+        use LinkState::*;
+
+        matches!(
+            (self, to),
+            (Draft, Active | Deleted)
+                | (Active, Paused | Expired | Disabled | Deleted)
+                | (Paused, Active | Expired | Disabled | Deleted)
+                | (Expired, Active | Disabled | Deleted)
+                | (Disabled, Active | Deleted)
+        )
+    }
+
+    /// Whether only operators may move links into or out of this state.
+    pub fn is_operator_only(self) -> bool {
+        self == LinkState::Disabled
+    }
+}
+
+impl TryFrom<String> for LinkState {
+    type Error = String;
+
+    fn try_from(state: String) -> Result<Self, Self::Error> {
+        match state.as_str() {
+            "draft" => Ok(LinkState::Draft),
+            "active" => Ok(LinkState::Active),
+            "paused" => Ok(LinkState::Paused),
+            "expired" => Ok(LinkState::Expired),
+            "disabled" => Ok(LinkState::Disabled),
+            "deleted" => Ok(LinkState::Deleted),
+            _ => Err(format!("Unknown link state {}", state)),
+        }
+    }
+}
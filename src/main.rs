@@ -1,35 +1,134 @@
+mod acme;
 mod auth;
 mod authentication;
+mod badge;
+mod branding;
+mod casing;
+mod click_buffer;
+mod client_ip;
+mod configuration;
 mod db;
+mod der;
+mod dns;
 mod email;
+mod host_rules;
+mod i18n;
+mod integrity;
+mod jobs;
+mod leader;
+mod link_changes;
+mod link_filter;
+mod link_payload;
+mod link_state;
+mod oauth;
+mod open_graph;
+mod pagination;
+mod pdf;
+mod png;
+mod policy;
+mod qr;
+mod rate_limit;
+mod read_only;
+mod redirect_cache;
+mod redirect_response;
+mod redirect_snapshot;
+mod redis;
+mod remote_write;
 mod routes;
+mod routing_rules;
+mod saml;
+mod sealing;
+mod slo;
+mod spotify;
+mod task_health;
+mod telegram;
+mod templates;
+mod tls;
+mod url_scanner;
+mod user_agent;
 mod utils;
+mod validation;
+mod visitor_salt;
 
+use crate::click_buffer::ClickBuffer;
+use crate::configuration::Settings;
+use crate::redirect_cache::RedirectCache;
+use crate::redirect_snapshot::RedirectSnapshot;
 use crate::email::EmailClient;
-use std::collections::HashMap;
+use crate::integrity::{refuse_writes_when_corrupt, Integrity};
+use crate::link_filter::LinkFilter;
+use crate::rate_limit::{limit_link_creation, limit_redirects, RateLimits};
+use crate::read_only::{refuse_writes_when_read_only, ReadOnlyMode};
+use crate::oauth::OAuthClients;
+use crate::spotify::SpotifyClient;
+use crate::telegram::TelegramClient;
+use crate::tls::CertificateStore;
+use crate::url_scanner::UrlScanner;
+use crate::visitor_salt::VisitorSalts;
 
 use crate::db::init_db;
 
 use crate::routes::{
-    all_channels, all_groups, confirm, create_channel, create_group, create_link,
-    get_link_statistics, health_check, login_user, redirect, root, subscribe, update_link, Counter,
+    accept_organization_invitation, acme_challenge, add_group_link, all_channels, all_group_events,
+    all_groups, approve_device_authorization, approve_pending_action, assign_premium_slug,
+    cancel_link_scheduled_change, cancel_user_deletion, check_integrity,
+    compare_organization_links, confirm, connect_spotify, create_channel, create_group,
+    create_group_event, create_group_playlist, create_link, create_organization,
+    create_organization_policy, create_scim_group, create_scim_user, create_status_incident,
+    custom_domain, delete_current_user, delete_custom_domain, delete_host_rule, delete_link,
+    delete_organization_policy, delete_page_template, delete_saml_connection, delete_scim_group,
+    delete_scim_user, delete_session, delete_tls_certificate, deny_pending_action, device_page,
+    discord_interaction, download_organization_export, export_usage_records, fault_injection,
+    generate_scim_token, get_link_statistics, get_scim_group, get_scim_user, grafana_datasource,
+    grafana_query, grafana_search, group_events_feed, group_links, group_playlist,
+    group_statistics, hard_delete_link, health_check, integrity_report, invite_organization_member,
+    leader_status, link_availability, link_preview, link_qr_code_png, link_qr_code_svg,
+    link_routing_rules, link_scheduled_changes, link_statistics_timeseries, link_telegram_account,
+    link_vcard, list_admin_audit, list_background_tasks, list_custom_domains, list_host_rules,
+    list_link_flags, list_links, list_organization_policies, list_page_templates,
+    list_pending_actions, list_premium_slugs, list_scim_groups, list_scim_users, list_sessions,
+    list_slow_queries, list_status_incidents, list_tls_certificates, log_in, log_out, login_user,
+    manage_tls_certificate, new_clicks_trigger, new_links_trigger, oauth_login_callback,
+    openapi_document, organization_branding, organization_encryption_key,
+    organization_export_status, organization_invitations, organization_members,
+    organization_saml_connection, organization_usage_records, patch_scim_group, patch_scim_user,
+    poll_device_authorization, preview_link, prewarm_redirect_cache, public_group_page,
+    public_link_clicks, public_link_clicks_badge, public_link_clicks_badge_png, publish_group_page,
+    qr_code_sheet, query_statistics, read_only_status, record_consent, redirect, redirect_slo,
+    refresh_session, register_account, release_premium_slug, remove_group_link,
+    remove_organization_member, replace_scim_group, replace_scim_user, request_organization_export,
+    request_pending_action, review_link_flag, revoke_organization_encryption_key,
+    revoke_organization_invitation, revoke_scim_token, revoke_session_from_email,
+    revoke_session_page, root, rotate_calendar_token, run_custom_domain_verification_job,
+    run_data_retention_jobs, run_link_expiration_job, run_outbox_dispatcher,
+    run_scheduled_link_changes_job, run_statistics_cache_invalidator, run_statistics_rollup_job,
+    run_status_check_job, run_trigger_digest_job, run_usage_records_job, run_user_deletion_job,
+    saml_assertion_consumer, saml_metadata, schedule_link_change, scim_service_provider_config,
+    service_status, set_custom_domain_defaults, set_fault_injection, set_host_rule,
+    set_link_routing_rules, set_link_sampling, set_organization_encryption_key, set_read_only,
+    set_saml_connection, simulate_link_redirect, slack_command, spotify_callback,
+    start_device_authorization, start_domain_verification, start_oauth_login, start_saml_login,
+    subscribe, subscribe_trigger, suspend_user, swagger_ui, tail_link_statistics, telegram_webhook,
+    transition_link, unpublish_group_page, unsubscribe_trigger, update_link,
+    update_organization_branding, update_organization_member, update_organization_policy,
+    update_page_template, update_status_incident, upload_tls_certificate, usage_forecast,
+    verify_device,
 };
 
-use serde::{Deserialize, Serialize};
-
-use crate::authentication::{change_password, forget_password};
+use crate::authentication::{change_password, forget_password, jwks, rotate_signing_key, JwtKeys};
 
 use axum::extract::FromRef;
-use axum::response::IntoResponse;
-use axum::routing::{get, patch, post, put};
-use axum::{Extension, Router};
+use axum::handler::Handler;
+use axum::routing::{delete, get, patch, post, put};
+use axum::Router;
 use axum_prometheus::PrometheusMetricLayer;
 use sqlx::PgPool;
 use std::error::Error;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use time::Duration;
 use tower_http::trace::TraceLayer;
-use tower_sessions::{Expiry, MemoryStore, Session, SessionManagerLayer};
+use tower_sessions::{Expiry, MemoryStore, SessionManagerLayer};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
@@ -37,18 +136,25 @@ struct AppState {
     inner: InnerState,
 }
 
-const COUNTER_KEY: &str = "counter";
-
 #[derive(Clone)]
 struct InnerState {
     pub db: PgPool,
     pub email_client: EmailClient,
-}
-
-async fn handler(session: Session) -> impl IntoResponse {
-    let counter: Counter = session.get(COUNTER_KEY).await.unwrap().unwrap_or_default();
-    session.insert(COUNTER_KEY, counter.0 + 1).await.unwrap();
-    format!("Current count: {}", counter.0)
+    pub jwt_keys: Arc<JwtKeys>,
+    pub settings: Arc<Settings>,
+    pub spotify: Option<SpotifyClient>,
+    pub telegram: Option<TelegramClient>,
+    pub oauth: OAuthClients,
+    pub clicks: Arc<ClickBuffer>,
+    pub redirects: Arc<RedirectSnapshot>,
+    pub redirect_cache: Arc<RedirectCache>,
+    pub link_filter: Arc<LinkFilter>,
+    pub rate_limits: Arc<RateLimits>,
+    pub integrity: Arc<Integrity>,
+    pub read_only: Arc<ReadOnlyMode>,
+    pub tls_certificates: Arc<CertificateStore>,
+    pub url_scanner: Arc<UrlScanner>,
+    pub visitor_salts: Arc<VisitorSalts>,
 }
 
 impl FromRef<AppState> for InnerState {
@@ -69,6 +175,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    let settings = Arc::new(Settings::from_env());
+    casing::init(settings.response_casing);
+    db::slow_queries::init(settings.slow_query_threshold);
+    slo::init(&settings);
+    db::fault_injection::set(settings.fault_injection.clone()).map_err(|err| err.to_string())?;
+
     let sender_email = std::env::var("EMAIL_SENDER")?;
 
     let email_client = EmailClient::new(
@@ -77,7 +189,51 @@ async fn main() -> Result<(), Box<dyn Error>> {
         std::env::var("EMAIL_TOKEN")?,
     );
 
-    let db = init_db().await?;
+    let db = init_db(settings.auto_migrate).await?;
+    let integrity = Arc::new(Integrity::check(&db, settings.integrity_auto_repair).await?);
+    if let Some(key) = &settings.certificate_encryption_key {
+        tls::seal_stored_keys(&db, key).await?;
+    }
+
+    let jwt_keys = Arc::new(JwtKeys::load(&db).await?);
+
+    tokio::spawn(leader::run_election(db.clone()));
+    tokio::spawn(run_user_deletion_job(db.clone()));
+    tokio::spawn(run_statistics_rollup_job(db.clone()));
+    tokio::spawn(run_link_expiration_job(db.clone()));
+    tokio::spawn(run_statistics_cache_invalidator(db.clone()));
+    tokio::spawn(run_outbox_dispatcher(db.clone()));
+    tokio::spawn(run_trigger_digest_job(db.clone()));
+
+    let clicks = Arc::new(ClickBuffer::open(&settings)?);
+    tokio::spawn(clicks.clone().run_flusher(db.clone()));
+
+    let redirects = Arc::new(RedirectSnapshot::open(&settings));
+    tokio::spawn(redirects.clone().run_refresher(db.clone()));
+
+    let redirect_cache = Arc::new(RedirectCache::new(&settings));
+    tokio::spawn(link_changes::run_link_change_listener(
+        db.clone(),
+        redirects.clone(),
+        redirect_cache.clone(),
+    ));
+
+    let link_filter = Arc::new(LinkFilter::new(&settings));
+    tokio::spawn(link_filter.clone().run_maintainer(db.clone()));
+
+    tokio::spawn(remote_write::run_remote_write(db.clone(), settings.clone()));
+    tokio::spawn(slo::run_alerts(settings.clone()));
+    tokio::spawn(acme::run_certificate_renewal_job(db.clone(), settings.clone()));
+    tokio::spawn(run_custom_domain_verification_job(db.clone(), settings.clone()));
+    tokio::spawn(run_data_retention_jobs(db.clone(), settings.clone()));
+    tokio::spawn(run_status_check_job(db.clone(), redirect_cache.clone()));
+    tokio::spawn(run_usage_records_job(db.clone()));
+
+    let tls_certificates = Arc::new(CertificateStore::new(&settings));
+    let tls_listen_address = settings.tls_listen_address;
+    if tls_listen_address.is_some() {
+        tokio::spawn(tls_certificates.clone().run_refresher(db.clone()));
+    }
 
     let (prometheus_layer, metric_handle) = PrometheusMetricLayer::pair();
 
@@ -86,32 +242,308 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .with_secure(false)
         .with_expiry(Expiry::OnInactivity(Duration::days(120)));
 
-    let app_state = InnerState { db, email_client };
+    let rate_limits = Arc::new(RateLimits::new(&settings));
+    let read_only = Arc::new(ReadOnlyMode::new(settings.read_only));
+    let url_scanner = Arc::new(UrlScanner::new(&settings));
+
+    let app_state = InnerState {
+        db,
+        email_client,
+        jwt_keys,
+        settings,
+        spotify: SpotifyClient::from_env(),
+        telegram: TelegramClient::from_env(),
+        oauth: OAuthClients::from_env(),
+        clicks,
+        redirects,
+        redirect_cache,
+        link_filter,
+        rate_limits,
+        integrity,
+        read_only,
+        tls_certificates: tls_certificates.clone(),
+        url_scanner,
+        visitor_salts: Arc::new(VisitorSalts::default()),
+    };
+
+    tokio::spawn(run_scheduled_link_changes_job(app_state.clone()));
 
     let app = Router::new()
-        .route("/create", post(create_link))
+        .route(
+            "/create",
+            post(create_link).layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                limit_link_creation,
+            )),
+        )
         .route("/:id/statistics", get(get_link_statistics))
-        .route("/:id", patch(update_link).get(redirect))
+        .route("/statistics/query", post(query_statistics))
+        .route("/links/availability", get(link_availability))
+        .route("/links", get(list_links))
+        .route("/links/:id", delete(delete_link))
+        .route(
+            "/links/:id/routing-rules",
+            get(link_routing_rules).put(set_link_routing_rules),
+        )
+        .route(
+            "/links/:id/scheduled-changes",
+            post(schedule_link_change).get(link_scheduled_changes),
+        )
+        .route(
+            "/links/:id/scheduled-changes/:change_id",
+            delete(cancel_link_scheduled_change),
+        )
+        .route("/links/:id/preview", get(link_preview))
+        .route("/links/:id/simulate", post(simulate_link_redirect))
+        .route("/links/:id/transition", post(transition_link))
+        .route("/links/:id/statistics/tail", get(tail_link_statistics))
+        .route(
+            "/links/:id/statistics/timeseries",
+            get(link_statistics_timeseries),
+        )
+        .route(
+            "/:id",
+            patch(update_link).get(
+                redirect
+                    .layer(axum::middleware::from_fn(slo::track_redirect_latency))
+                    .layer(axum::middleware::from_fn_with_state(
+                        app_state.clone(),
+                        limit_redirects,
+                    )),
+            ),
+        )
+        .route("/g/:slug", get(public_group_page))
+        .route("/:id/consent", post(record_consent))
+        .route("/:id/preview", get(preview_link))
+        .route("/:id/qr.png", get(link_qr_code_png))
+        .route("/:id/qr.svg", get(link_qr_code_svg))
+        .route("/:id/contact.vcf", get(link_vcard))
+        .route("/qr/sheet", post(qr_code_sheet))
+        .route("/public/links/:id/clicks", get(public_link_clicks))
+        .route("/public/links/:id/clicks-badge.svg", get(public_link_clicks_badge))
+        .route(
+            "/public/links/:id/clicks-badge.png",
+            get(public_link_clicks_badge_png),
+        )
         .route("/metrics", get(|| async move { metric_handle.render() }))
         .route("/health", get(health_check))
+        .route("/status", get(service_status))
+        .route("/openapi.json", get(openapi_document))
+        .route("/docs", get(swagger_ui))
+        .route("/grafana", get(grafana_datasource))
+        .route("/grafana/search", post(grafana_search))
+        .route("/grafana/query", post(grafana_query))
 
         .route("/groups/:user_id", get(all_groups))
+        .route(
+            "/groups/:id/playlist",
+            post(create_group_playlist).get(group_playlist),
+        )
+        .route(
+            "/groups/:id/events",
+            post(create_group_event).get(all_group_events),
+        )
+        .route("/groups/:id/events.ics", get(group_events_feed))
+        .route("/groups/:id/calendar-token", post(rotate_calendar_token))
+        .route("/groups/:id/links", get(group_links))
+        .route(
+            "/groups/:id/links/:link_id",
+            put(add_group_link).delete(remove_group_link),
+        )
+        .route("/groups/:id/statistics", get(group_statistics))
+        .route(
+            "/groups/:id/page",
+            put(publish_group_page).delete(unpublish_group_page),
+        )
+        .route("/integrations/spotify/connect", get(connect_spotify))
+        .route("/integrations/spotify/callback", get(spotify_callback))
+        .route("/integrations/slack/commands", post(slack_command))
+        .route("/integrations/discord/interactions", post(discord_interaction))
+        .route("/integrations/telegram/link", post(link_telegram_account))
+        .route("/integrations/telegram/webhook", post(telegram_webhook))
+        .route("/triggers/new-links", get(new_links_trigger))
+        .route("/triggers/new-clicks", get(new_clicks_trigger))
+        .route("/triggers/subscriptions", post(subscribe_trigger))
+        .route("/triggers/subscriptions/:id", delete(unsubscribe_trigger))
         .route("/channel", post(create_channel))
         .route("/group", post(create_group))
         .route("/channels/:user_id", get(all_channels))
         .route("/subscription", post(subscribe))
         .route("/subscription/confirm/:subscription_token", post(confirm))
+        .route("/users/me", delete(delete_current_user))
+        .route("/users/me/deletion/cancel", post(cancel_user_deletion))
+        .route("/usage/forecast", get(usage_forecast))
+        .route("/organizations", post(create_organization))
+        .route("/organizations/:id/members", get(organization_members))
+        .route(
+            "/organizations/:id/members/:user_id",
+            put(update_organization_member).delete(remove_organization_member),
+        )
+        .route(
+            "/organizations/:id/invitations",
+            post(invite_organization_member).get(organization_invitations),
+        )
+        .route(
+            "/organizations/:id/invitations/:invitation_id",
+            delete(revoke_organization_invitation),
+        )
+        .route("/invitations/accept", post(accept_organization_invitation))
+        .route(
+            "/organizations/:id/branding",
+            get(organization_branding).put(update_organization_branding),
+        )
+        .route(
+            "/organizations/:id/links/comparison",
+            get(compare_organization_links),
+        )
+        .route(
+            "/organizations/:id/domains",
+            get(list_custom_domains).post(start_domain_verification),
+        )
+        .route(
+            "/organizations/:id/domains/:domain",
+            get(custom_domain).delete(delete_custom_domain),
+        )
+        .route(
+            "/organizations/:id/domains/:domain/defaults",
+            put(set_custom_domain_defaults),
+        )
+        .route(
+            "/organizations/:id/encryption-key",
+            get(organization_encryption_key)
+                .put(set_organization_encryption_key)
+                .delete(revoke_organization_encryption_key),
+        )
+        .route(
+            "/organizations/:id/policies",
+            get(list_organization_policies).post(create_organization_policy),
+        )
+        .route(
+            "/organizations/:id/policies/:policy_id",
+            put(update_organization_policy).delete(delete_organization_policy),
+        )
+        .route("/organizations/:id/export", post(request_organization_export))
+        .route("/organizations/:id/usage-records", get(organization_usage_records))
+        .route(
+            "/organizations/:id/exports/:export_id",
+            get(organization_export_status),
+        )
+        .route(
+            "/organizations/:id/exports/:export_id/download",
+            get(download_organization_export),
+        )
+        .route(
+            "/organizations/:id/saml",
+            get(organization_saml_connection)
+                .put(set_saml_connection)
+                .delete(delete_saml_connection),
+        )
+        .route(
+            "/organizations/:id/scim-token",
+            put(generate_scim_token).delete(revoke_scim_token),
+        )
+        .route("/scim/v2/ServiceProviderConfig", get(scim_service_provider_config))
+        .route("/scim/v2/Users", get(list_scim_users).post(create_scim_user))
+        .route(
+            "/scim/v2/Users/:id",
+            get(get_scim_user)
+                .put(replace_scim_user)
+                .patch(patch_scim_user)
+                .delete(delete_scim_user),
+        )
+        .route("/scim/v2/Groups", get(list_scim_groups).post(create_scim_group))
+        .route(
+            "/scim/v2/Groups/:id",
+            get(get_scim_group)
+                .put(replace_scim_group)
+                .patch(patch_scim_group)
+                .delete(delete_scim_group),
+        )
 
         .route("/", get(root))
         .route("/authorize", post(login_user))
+        .route("/auth/register", post(register_account))
+        .route("/auth/login", post(log_in))
+        .route("/auth/refresh", post(refresh_session))
+        .route("/auth/logout", post(log_out))
+        .route("/auth/sessions", get(list_sessions))
+        .route(
+            "/auth/sessions/revoke",
+            get(revoke_session_page).post(revoke_session_from_email),
+        )
+        .route("/auth/sessions/:id", delete(delete_session))
+        .route("/auth/oauth/:provider/start", get(start_oauth_login))
+        .route("/auth/oauth/:provider/callback", get(oauth_login_callback))
+        .route("/auth/saml/:id/metadata", get(saml_metadata))
+        .route("/auth/saml/:id/start", get(start_saml_login))
+        .route("/auth/saml/:id/acs", post(saml_assertion_consumer))
         .route("/forget-password", post(forget_password))
         .route("/forget-password/confirm", put(change_password))
+        .route("/.well-known/jwks.json", get(jwks))
+        .route("/.well-known/acme-challenge/:token", get(acme_challenge))
+        .route("/auth/device/start", post(start_device_authorization))
+        .route("/auth/device/approve", post(approve_device_authorization))
+        .route("/auth/device/poll", post(poll_device_authorization))
+        .route("/device", get(device_page).post(verify_device))
+        .route("/admin/jwt/rotate", post(rotate_signing_key))
+        .route("/admin/audit", get(list_admin_audit))
+        .route("/admin/leader", get(leader_status))
+        .route("/admin/integrity", get(integrity_report))
+        .route("/admin/integrity/check", post(check_integrity))
+        .route("/admin/read-only", get(read_only_status).put(set_read_only))
+        .route("/admin/redirect-cache/prewarm", post(prewarm_redirect_cache))
+        .route("/admin/tasks", get(list_background_tasks))
+        .route("/admin/incidents", get(list_status_incidents).post(create_status_incident))
+        .route("/admin/incidents/:id", patch(update_status_incident))
+        .route("/admin/slow-queries", get(list_slow_queries))
+        .route("/admin/slo", get(redirect_slo))
+        .route(
+            "/admin/fault-injection",
+            get(fault_injection).put(set_fault_injection),
+        )
+        .route("/admin/page-templates", get(list_page_templates))
+        .route("/admin/page-templates/:kind", put(update_page_template).delete(delete_page_template))
+        .route("/admin/links/:id", delete(hard_delete_link))
+        .route("/admin/host-rules", get(list_host_rules))
+        .route("/admin/host-rules/:pattern", put(set_host_rule).delete(delete_host_rule))
+        .route("/admin/link-flags", get(list_link_flags))
+        .route("/admin/link-flags/:id/review", post(review_link_flag))
+        .route("/admin/premium-slugs", get(list_premium_slugs))
+        .route("/admin/tls-certificates", get(list_tls_certificates))
+        .route("/admin/usage-records", get(export_usage_records))
+        .route(
+            "/admin/tls-certificates/:domain",
+            put(upload_tls_certificate).delete(delete_tls_certificate),
+        )
+        .route("/admin/tls-certificates/:domain/acme", put(manage_tls_certificate))
+        .route("/admin/premium-slugs/:slug", put(assign_premium_slug).delete(release_premium_slug))
+        .route("/admin/links/:id/sampling", put(set_link_sampling))
+        .route("/admin/users/:user_id/suspend", put(suspend_user))
+        .route(
+            "/admin/pending-actions",
+            post(request_pending_action).get(list_pending_actions),
+        )
+        .route("/admin/pending-actions/:id/approve", post(approve_pending_action))
+        .route("/admin/pending-actions/:id/deny", post(deny_pending_action))
 
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            refuse_writes_when_corrupt,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            refuse_writes_when_read_only,
+        ))
+        .layer(axum::middleware::from_fn(i18n::translate_errors))
         .layer(TraceLayer::new_for_http())
         .layer(prometheus_layer)
         .layer(session)
         .with_state(app_state);
 
+    if let Some(address) = tls_listen_address {
+        tokio::spawn(tls::serve(address, app.clone(), tls_certificates));
+    }
+
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
         .await
         .expect("Could not initialize TcpListener");
@@ -123,7 +555,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .expect("Could not convert listener address to local address")
     );
 
-    axum::serve(listener, app)
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
         .await
         .expect("Could not successfully connect");
 
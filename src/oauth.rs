@@ -0,0 +1,242 @@
+//! Logging in through Google and GitHub with the OAuth2 authorization code
+//! flow, for users who would rather not keep another password.
+
+use reqwest::Client;
+use serde::Deserialize;
+use url::Url;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OAuthProvider {
+    Google,
+    Github,
+}
+
+impl OAuthProvider {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "google" => Some(OAuthProvider::Google),
+            "github" => Some(OAuthProvider::Github),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OAuthProvider::Google => "google",
+            OAuthProvider::Github => "github",
+        }
+    }
+
+    fn scopes(self) -> &'static str {
+        match self {
+            OAuthProvider::Google => "openid email profile",
+            OAuthProvider::Github => "read:user user:email",
+        }
+    }
+}
+
+/// Who the provider says logged in.
+#[derive(Debug)]
+pub struct OAuthIdentity {
+    /// Id of the account at the provider, which stays the same when its
+    /// email address changes.
+    pub subject: String,
+    /// Address the provider verified the account owns, if any.
+    pub email: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleUser {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubUser {
+    id: i64,
+    login: String,
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct OAuthClient {
+    http_client: Client,
+    provider: OAuthProvider,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    authorize_url: String,
+    token_url: String,
+    api_base_url: String,
+}
+
+impl OAuthClient {
+    pub fn new(
+        provider: OAuthProvider,
+        client_id: String,
+        client_secret: String,
+        redirect_uri: String,
+    ) -> Self {
+        let (authorize_url, token_url, api_base_url) = match provider {
+            OAuthProvider::Google => (
+                "https://accounts.google.com/o/oauth2/v2/auth",
+                "https://oauth2.googleapis.com/token",
+                "https://openidconnect.googleapis.com/v1",
+            ),
+            OAuthProvider::Github => (
+                "https://github.com/login/oauth/authorize",
+                "https://github.com/login/oauth/access_token",
+                "https://api.github.com",
+            ),
+        };
+
+        Self {
+            http_client: Client::new(),
+            provider,
+            client_id,
+            client_secret,
+            redirect_uri,
+            authorize_url: authorize_url.to_owned(),
+            token_url: token_url.to_owned(),
+            api_base_url: api_base_url.to_owned(),
+        }
+    }
+
+    /// Build a client from `<PROVIDER>_CLIENT_ID`, `<PROVIDER>_CLIENT_SECRET`
+    /// and `<PROVIDER>_REDIRECT_URI`, or `None` when the provider is not set
+    /// up.
+    pub fn from_env(provider: OAuthProvider) -> Option<Self> {
+        let prefix = provider.name().to_uppercase();
+        let var = |name: &str| std::env::var(format!("{}_{}", prefix, name)).ok();
+
+        Some(Self::new(
+            provider,
+            var("CLIENT_ID")?,
+            var("CLIENT_SECRET")?,
+            var("REDIRECT_URI")?,
+        ))
+    }
+
+    pub fn authorize_url(&self, state: &str) -> String {
+        let mut url =
+            Url::parse(&self.authorize_url).expect("The authorize URL should always be valid");
+
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client_id)
+            .append_pair("response_type", "code")
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", self.provider.scopes())
+            .append_pair("state", state);
+
+        url.to_string()
+    }
+
+    /// Trade the code the provider sent back for an access token to its API.
+    pub async fn exchange_code(&self, code: &str) -> Result<String, reqwest::Error> {
+        let tokens: TokenResponse = self
+            .http_client
+            .post(&self.token_url)
+            .header("accept", "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_uri),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(tokens.access_token)
+    }
+
+    pub async fn identity(&self, access_token: &str) -> Result<OAuthIdentity, reqwest::Error> {
+        match self.provider {
+            OAuthProvider::Google => {
+                let user: GoogleUser = self.get(access_token, "/userinfo").await?;
+
+                Ok(OAuthIdentity {
+                    subject: user.sub,
+                    email: user.email.filter(|_| user.email_verified),
+                    name: user.name,
+                })
+            }
+            OAuthProvider::Github => {
+                let user: GithubUser = self.get(access_token, "/user").await?;
+                // The address on the profile is whatever the user made
+                // public, verified or not, so ask for the primary one.
+                let emails: Vec<GithubEmail> = self.get(access_token, "/user/emails").await?;
+
+                Ok(OAuthIdentity {
+                    subject: user.id.to_string(),
+                    email: emails
+                        .into_iter()
+                        .find(|email| email.primary && email.verified)
+                        .map(|email| email.email),
+                    name: user.name.or(Some(user.login)),
+                })
+            }
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        access_token: &str,
+        path: &str,
+    ) -> Result<T, reqwest::Error> {
+        self.http_client
+            .get(format!("{}{}", self.api_base_url, path))
+            .bearer_auth(access_token)
+            .header("accept", "application/json")
+            // GitHub turns away requests without one.
+            .header("user-agent", "groupify")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+}
+
+/// The providers users may log in with, those without credentials left
+/// out.
+#[derive(Clone, Debug)]
+pub struct OAuthClients {
+    google: Option<OAuthClient>,
+    github: Option<OAuthClient>,
+}
+
+impl OAuthClients {
+    pub fn from_env() -> Self {
+        Self {
+            google: OAuthClient::from_env(OAuthProvider::Google),
+            github: OAuthClient::from_env(OAuthProvider::Github),
+        }
+    }
+
+    pub fn get(&self, provider: OAuthProvider) -> Option<&OAuthClient> {
+        match provider {
+            OAuthProvider::Google => self.google.as_ref(),
+            OAuthProvider::Github => self.github.as_ref(),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Reading the Open Graph title, description and image of a web page, for
+//! link cards. Only the start of the page is read, and only the `<meta>`
+//! and `<title>` tags in its head are looked at, which is all the metadata
+//! needs without pulling in a whole HTML parser.
+
+use crate::host_rules::{fetchable_redirects, is_fetchable, PublicResolver};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use sqlx::FromRow;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Redirects followed at most.
+const MAX_REDIRECTS: usize = 5;
+
+static PREVIEW_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(fetchable_redirects(MAX_REDIRECTS))
+        .user_agent("groupify-link-preview")
+        .build()
+        .expect("The link preview client should always be constructable")
+});
+
+/// Bytes of a page read at most. The head comes first, so this is plenty.
+const MAX_PAGE_BYTES: usize = 256 * 1024;
+
+const MAX_TITLE_LENGTH: usize = 300;
+const MAX_DESCRIPTION_LENGTH: usize = 1_000;
+const MAX_IMAGE_URL_LENGTH: usize = 2_048;
+
+#[derive(Debug, Default, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PagePreview {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Absolute, whatever the page gave.
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PreviewError {
+    #[error("Only public http and https pages have previews")]
+    UnsupportedScheme,
+    #[error("The page is not HTML")]
+    NotHtml,
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
+
+/// Fetch the page at `url` and read its preview.
+pub async fn fetch_preview(url: &str) -> Result<PagePreview, PreviewError> {
+    let url = Url::parse(url).map_err(|_| PreviewError::UnsupportedScheme)?;
+    if !is_fetchable(&url) {
+        return Err(PreviewError::UnsupportedScheme);
+    }
+
+    let mut response = PREVIEW_CLIENT
+        .get(url)
+        .header("accept", "text/html,application/xhtml+xml")
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let is_html = response
+        .headers()
+        .get("content-type")
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| {
+            content_type.starts_with("text/html")
+                || content_type.starts_with("application/xhtml+xml")
+        });
+    if !is_html {
+        return Err(PreviewError::NotHtml);
+    }
+
+    // Redirects followed, image paths are relative to where they ended.
+    let page_url = response.url().clone();
+    let mut page = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        page.extend_from_slice(&chunk);
+        if page.len() >= MAX_PAGE_BYTES {
+            page.truncate(MAX_PAGE_BYTES);
+            break;
+        }
+    }
+
+    Ok(parse_preview(&String::from_utf8_lossy(&page), &page_url))
+}
+
+/// The preview of a page, preferring Open Graph tags over Twitter card
+/// ones, and those over the plain title and description.
+pub fn parse_preview(html: &str, page_url: &Url) -> PagePreview {
+    // Lowercasing ASCII keeps every byte where it was, so positions found
+    // in it hold for the page too.
+    let lower = html.to_ascii_lowercase();
+    let head_end = lower.find("</head").unwrap_or(lower.len());
+
+    let mut meta = Vec::new();
+    let mut position = 0;
+    while let Some(start) = lower[position..head_end].find("<meta") {
+        let start = position + start + "<meta".len();
+        let end = lower[start..]
+            .find('>')
+            .map_or(lower.len(), |end| start + end);
+        meta.push(parse_attributes(&html[start..end]));
+        position = end.min(head_end);
+    }
+
+    let content = |keys: &[&str]| {
+        keys.iter().find_map(|key| {
+            meta.iter().find_map(|attributes| {
+                let named = attribute(attributes, "property")
+                    .or_else(|| attribute(attributes, "name"))
+                    .is_some_and(|name| name.eq_ignore_ascii_case(key));
+                attribute(attributes, "content")
+                    .filter(|content| named && !content.trim().is_empty())
+            })
+        })
+    };
+
+    let title = content(&["og:title", "twitter:title"])
+        .map(str::to_string)
+        .or_else(|| page_title(html, &lower[..head_end]));
+    let description = content(&["og:description", "twitter:description", "description"]);
+    let image_url = content(&["og:image", "og:image:url", "twitter:image"])
+        .and_then(|image| page_url.join(image.trim()).ok())
+        .filter(|image| matches!(image.scheme(), "http" | "https"))
+        .map(String::from)
+        .filter(|image| image.len() <= MAX_IMAGE_URL_LENGTH);
+
+    PagePreview {
+        title: title.map(|title| clean_text(&title, MAX_TITLE_LENGTH)),
+        description: description.map(|description| clean_text(description, MAX_DESCRIPTION_LENGTH)),
+        image_url,
+    }
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(attribute, _)| attribute == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Names and values of the attributes in the inside of a tag, after its
+/// name. Names are lowercased and values have their entities decoded.
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            return attributes;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            // A stray `=`, skipped so parsing moves on.
+            rest = &rest[1..];
+            continue;
+        }
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let value = match rest.strip_prefix('=').map(str::trim_start) {
+            Some(quoted) if quoted.starts_with(['"', '\'']) => {
+                let quote = &quoted[..1];
+                let inner = &quoted[1..];
+                let end = inner.find(quote).unwrap_or(inner.len());
+                rest = inner.get(end + 1..).unwrap_or_default();
+                &inner[..end]
+            }
+            Some(unquoted) => {
+                let end = unquoted
+                    .find(|c: char| c.is_ascii_whitespace())
+                    .unwrap_or(unquoted.len());
+                rest = &unquoted[end..];
+                &unquoted[..end]
+            }
+            None => "",
+        };
+
+        attributes.push((name, decode_entities(value)));
+    }
+}
+
+fn page_title(html: &str, lower_head: &str) -> Option<String> {
+    let start = lower_head.find("<title")?;
+    let start = start + lower_head[start..].find('>')? + 1;
+    let end = start + lower_head[start..].find("</title")?;
+
+    Some(decode_entities(&html[start..end])).filter(|title| !title.trim().is_empty())
+}
+
+/// Decode the character references pages commonly use, leaving anything
+/// else as written.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 8)
+            .map(|end| &rest[1..=end]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|code| code.parse().ok()))
+                .and_then(char::from_u32),
+        });
+
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Collapse runs of whitespace and cut the text to `max` characters.
+fn clean_text(text: &str, max: usize) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(max)
+        .collect()
+}
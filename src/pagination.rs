@@ -0,0 +1,100 @@
+//! The envelope every list endpoint answers with, and the `limit` and
+//! `cursor` query parameters of the ones that are paged.
+//!
+//! A cursor is opaque to callers: the key of the last item of a page,
+//! serialized by the endpoint and encoded as base64url JSON. A page that
+//! comes back shorter than its limit is the last one. Endpoints returning
+//! every item at once answer with a single page carrying its length as
+//! `total`, and leave `total` out where counting would cost another scan.
+
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use base64::engine::general_purpose;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Pass as `cursor` to get the following page, absent on the last.
+    pub next_cursor: Option<String>,
+    pub total: Option<i64>,
+}
+
+impl<T> Page<T> {
+    /// Every item there is, in one page.
+    pub fn complete(items: Vec<T>) -> Self {
+        Self {
+            total: Some(items.len() as i64),
+            items,
+            next_cursor: None,
+        }
+    }
+
+    /// One page of at most `limit` items, continued after the key `cursor`
+    /// picks from its last item when the page is full.
+    pub fn after_last<C: Serialize>(items: Vec<T>, limit: i64, cursor: impl Fn(&T) -> C) -> Self {
+        let next_cursor = match items.last() {
+            Some(last) if items.len() as i64 >= limit => Some(encode_cursor(&cursor(last))),
+            _ => None,
+        };
+
+        Self {
+            items,
+            next_cursor,
+            total: None,
+        }
+    }
+}
+
+/// The `limit` and `cursor` query parameters, which endpoints may take next
+/// to a `Query` of their own filters.
+#[derive(Debug, Default, Deserialize)]
+pub struct Pagination {
+    pub limit: Option<i64>,
+    /// The `nextCursor` of the previous page.
+    pub cursor: Option<String>,
+}
+
+impl Pagination {
+    /// The requested page size, or `default`, kept between 1 and `max`.
+    pub fn limit(&self, default: i64, max: i64) -> i64 {
+        self.limit.unwrap_or(default).clamp(1, max)
+    }
+
+    /// The key to continue after, or `None` for the first page.
+    pub fn cursor<C: DeserializeOwned>(&self) -> Result<Option<C>, (StatusCode, String)> {
+        match &self.cursor {
+            Some(cursor) => decode_cursor(cursor)
+                .map(Some)
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, "Invalid cursor".to_string())),
+            None => Ok(None),
+        }
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Pagination {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(pagination) = Query::<Pagination>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| (StatusCode::BAD_REQUEST, rejection.body_text()))?;
+
+        Ok(pagination)
+    }
+}
+
+fn encode_cursor<C: Serialize>(cursor: &C) -> String {
+    let json = serde_json::to_vec(cursor).expect("A cursor should always serialize");
+    general_purpose::URL_SAFE_NO_PAD.encode(json)
+}
+
+fn decode_cursor<C: DeserializeOwned>(cursor: &str) -> Option<C> {
+    let json = general_purpose::URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    serde_json::from_slice(&json).ok()
+}
@@ -0,0 +1,118 @@
+//! Minimal PDF writer for printable sheets. Pages are A4 content streams,
+//! stored uncompressed, that may use the standard Helvetica fonts, which
+//! every viewer provides, so nothing needs embedding.
+
+/// A4 in points.
+pub const PAGE_WIDTH: f64 = 595.0;
+pub const PAGE_HEIGHT: f64 = 842.0;
+
+#[derive(Clone, Copy)]
+pub enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn resource_name(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PdfDocument {
+    pages: Vec<String>,
+}
+
+impl PdfDocument {
+    /// Append a page drawn by the given content stream operators.
+    pub fn add_page(&mut self, content: String) {
+        self.pages.push(content);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        // Catalog, page tree and the two fonts come first, then each page
+        // followed by its content stream.
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                (0..self.pages.len())
+                    .map(|page| format!("{} 0 R", 5 + page * 2))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                self.pages.len()
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                .to_string(),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+                .to_string(),
+        ];
+        for (page, content) in self.pages.iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                6 + page * 2
+            ));
+            objects.push(format!(
+                "<< /Length {} >>\nstream\n{}\nendstream",
+                content.len(),
+                content
+            ));
+        }
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (index, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", index + 1, object).as_bytes());
+        }
+
+        let xref_offset = pdf.len();
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            xref.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        xref.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref_offset
+        ));
+        pdf.extend_from_slice(xref.as_bytes());
+
+        pdf
+    }
+}
+
+/// Operators drawing a line of text with its baseline starting at `x`, `y`.
+pub fn text(x: f64, y: f64, font: Font, size: f64, value: &str) -> String {
+    format!(
+        "BT /{} {} Tf {:.2} {:.2} Td ({}) Tj ET\n",
+        font.resource_name(),
+        size,
+        x,
+        y,
+        escape(value)
+    )
+}
+
+/// A string literal's content. Latin-1 characters mean the same in
+/// WinAnsiEncoding; anything beyond it is shown as a question mark.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            '\u{a0}'..='\u{ff}' => escaped.push_str(&format!("\\{:03o}", c as u32)),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,225 @@
+//! Policies refining what organization roles allow, by the tags, campaign
+//! and domain of the links acted on.
+//!
+//! An organization attaches policies to some of its roles, and tokens may
+//! carry policies of their own. A statement applies to an action when it
+//! names it and its conditions all hold for the resource; within a
+//! condition listing several values, any of them matches. Statements
+//! denying an action win over those allowing it. Role policies may allow
+//! what the role alone would not, while a token carrying policies is
+//! limited to what they allow.
+
+use serde::{Deserialize, Serialize};
+
+/// Actions policies may name, besides `*` and `<resource>:*` for all the
+/// actions on a resource.
+pub const ACTIONS: [&str; 11] = [
+    "organization:read",
+    "organization:manage",
+    "organization:own",
+    "link:create",
+    "link:read",
+    "link:statistics",
+    "link:update",
+    "link:delete",
+    "link:schedule",
+    "link:transition",
+    "link:route",
+];
+
+/// Something a caller does, and the least organization role doing it takes
+/// unless a policy allows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    ReadOrganization,
+    ManageOrganization,
+    OwnOrganization,
+    CreateLink,
+    ReadLink,
+    ReadLinkStatistics,
+    UpdateLink,
+    DeleteLink,
+    ScheduleLink,
+    TransitionLink,
+    RouteLink,
+}
+
+impl Action {
+    /// The action on the organization itself a role gives.
+    pub fn on_organization(minimum_role: &str) -> Self {
+        match minimum_role {
+            "member" => Self::ReadOrganization,
+            "admin" => Self::ManageOrganization,
+            _ => Self::OwnOrganization,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ReadOrganization => "organization:read",
+            Self::ManageOrganization => "organization:manage",
+            Self::OwnOrganization => "organization:own",
+            Self::CreateLink => "link:create",
+            Self::ReadLink => "link:read",
+            Self::ReadLinkStatistics => "link:statistics",
+            Self::UpdateLink => "link:update",
+            Self::DeleteLink => "link:delete",
+            Self::ScheduleLink => "link:schedule",
+            Self::TransitionLink => "link:transition",
+            Self::RouteLink => "link:route",
+        }
+    }
+
+    pub fn minimum_role(self) -> &'static str {
+        match self {
+            Self::ReadOrganization
+            | Self::CreateLink
+            | Self::ReadLink
+            | Self::ReadLinkStatistics => "member",
+            Self::OwnOrganization => "owner",
+            _ => "admin",
+        }
+    }
+}
+
+/// Whether `pattern`, as policies name actions, is a known one.
+pub fn is_action_pattern(pattern: &str) -> bool {
+    match pattern.strip_suffix(":*") {
+        Some(resource) => ACTIONS
+            .iter()
+            .any(|action| action.split(':').next() == Some(resource)),
+        None => pattern == "*" || ACTIONS.contains(&pattern),
+    }
+}
+
+/// Ordered so that denying is the greater effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Statement {
+    pub effect: Effect,
+    /// Action names, `*`, or `<resource>:*`.
+    pub actions: Vec<String>,
+    /// Always hold when empty.
+    #[serde(default)]
+    pub conditions: Conditions,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Conditions {
+    /// Holds for links with any of these tags.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub campaigns: Vec<String>,
+    /// Custom domains, which links created under none never match.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub domains: Vec<String>,
+}
+
+/// What policies can tell about the resource acted on. Organizations
+/// themselves have none of these, so only unconditional statements apply
+/// to acting on them.
+#[derive(Debug, Default)]
+pub struct Resource<'a> {
+    pub tags: &'a [String],
+    pub campaign: Option<&'a str>,
+    pub domain: Option<&'a str>,
+}
+
+impl Conditions {
+    fn hold_for(&self, resource: &Resource) -> bool {
+        let tags = self.tags.is_empty() || self.tags.iter().any(|tag| resource.tags.contains(tag));
+        let campaign = self.campaigns.is_empty()
+            || resource
+                .campaign
+                .is_some_and(|campaign| self.campaigns.iter().any(|wanted| wanted == campaign));
+        let domain = self.domains.is_empty()
+            || resource.domain.is_some_and(|domain| {
+                self.domains
+                    .iter()
+                    .any(|wanted| wanted.eq_ignore_ascii_case(domain))
+            });
+
+        tags && campaign && domain
+    }
+}
+
+impl Statement {
+    fn applies_to(&self, action: Action, resource: &Resource) -> bool {
+        let name = action.name();
+        let names_action = self
+            .actions
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => pattern == name,
+            });
+
+        names_action && self.conditions.hold_for(resource)
+    }
+}
+
+/// What the statements say of the action, denying ones first, or None
+/// when none applies.
+pub fn decide(statements: &[Statement], action: Action, resource: &Resource) -> Option<Effect> {
+    statements
+        .iter()
+        .filter(|statement| statement.applies_to(action, resource))
+        .map(|statement| statement.effect)
+        .max()
+}
+
+/// Statements of the policies an organization attaches to the role
+/// `{role}`, or to all roles, as a JSON array.
+const ROLE_STATEMENTS: &str = r#"(SELECT coalesce(jsonb_agg(statement), '[]')
+    FROM organization_policies, jsonb_array_elements(organization_policies.statements) AS statement
+    WHERE organization_policies.organization_id = {organization}
+        AND {role} IS NOT NULL
+        AND (organization_policies.roles = '{}' OR {role} = ANY(organization_policies.roles)))"#;
+
+/// Statements of the policies `{ids}` lists, as a JSON array.
+pub const TOKEN_STATEMENTS: &str = r#"(SELECT coalesce(jsonb_agg(statement), '[]')
+    FROM organization_policies, jsonb_array_elements(organization_policies.statements) AS statement
+    WHERE organization_policies.id = ANY({ids}))"#;
+
+/// The statements bearing on the caller's role, and those of the policies
+/// their token carries, with `role` and `ids` the SQL giving them.
+pub fn statements_sql(organization: &str, role: &str, ids: &str) -> String {
+    format!(
+        "{} AS role_statements, {} AS token_statements",
+        ROLE_STATEMENTS
+            .replace("{organization}", organization)
+            .replace("{role}", role),
+        TOKEN_STATEMENTS.replace("{ids}", ids)
+    )
+}
+
+/// Whether a caller may take the action. `granted` tells whether their
+/// role alone allows it, which the policies of their role may widen or
+/// narrow. `token_statements` are those of the policies their token
+/// carries, None when it carries none.
+pub fn permits(
+    granted: bool,
+    role_statements: &[Statement],
+    token_statements: Option<&[Statement]>,
+    action: Action,
+    resource: &Resource,
+) -> bool {
+    let by_role = match decide(role_statements, action, resource) {
+        Some(Effect::Deny) => false,
+        Some(Effect::Allow) => true,
+        None => granted,
+    };
+    let by_token = token_statements
+        .is_none_or(|statements| decide(statements, action, resource) == Some(Effect::Allow));
+
+    by_role && by_token
+}
@@ -0,0 +1,511 @@
+//! A small QR code encoder covering what short links need: byte mode,
+//! medium error correction, versions 1 to 40, rendered as SVG, PNG or PDF.
+
+/// Error correction codewords per block for level M, indexed by version.
+const ECC_CODEWORDS_PER_BLOCK: [usize; 41] = [
+    0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+
+/// Error correction blocks for level M, indexed by version.
+const NUM_ERROR_CORRECTION_BLOCKS: [usize; 41] = [
+    0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23,
+    25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+
+/// Format information bits identifying error correction level M.
+const ECC_LEVEL_M_FORMAT_BITS: u32 = 0;
+
+/// Light modules around the symbol, as the specification requires.
+const QUIET_ZONE: usize = 4;
+
+/// Width of a logo shown over the symbol, relative to the symbol's. It hides
+/// well under the codewords medium error correction makes up for.
+const LOGO_SHARE: f64 = 0.2;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Data is too long to fit in a QR code")]
+pub struct DataTooLong;
+
+#[derive(Debug, Clone)]
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    pub fn encode(data: &[u8]) -> Result<Self, DataTooLong> {
+        let version = (1..=40)
+            .find(|&version| {
+                let count_bits = if version <= 9 { 8 } else { 16 };
+                4 + count_bits + data.len() * 8 <= num_data_codewords(version) * 8
+            })
+            .ok_or(DataTooLong)?;
+
+        let mut bits = BitBuffer::default();
+        bits.append(0b0100, 4);
+        bits.append(data.len() as u32, if version <= 9 { 8 } else { 16 });
+        for &byte in data {
+            bits.append(byte as u32, 8);
+        }
+
+        let capacity = num_data_codewords(version) * 8;
+        bits.append(0, (capacity - bits.0.len()).min(4));
+        bits.append(0, (8 - bits.0.len() % 8) % 8);
+        for &pad in [0xEC, 0x11].iter().cycle() {
+            if bits.0.len() >= capacity {
+                break;
+            }
+            bits.append(pad, 8);
+        }
+
+        let codewords: Vec<u8> = bits
+            .0
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0, |acc, &bit| acc << 1 | bit as u8))
+            .collect();
+
+        let size = version * 4 + 17;
+        let mut qr = Self {
+            size,
+            modules: vec![false; size * size],
+            is_function: vec![false; size * size],
+        };
+
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&add_ecc_and_interleave(&codewords, version));
+
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                qr.apply_mask(mask);
+                qr.draw_format_bits(mask);
+                let penalty = qr.penalty_score();
+                qr.apply_mask(mask);
+                penalty
+            })
+            .unwrap_or_default();
+        qr.apply_mask(mask);
+        qr.draw_format_bits(mask);
+
+        Ok(qr)
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// Render as an SVG where each module is one user unit.
+    pub fn to_svg(&self, dark: &str, light: &str) -> String {
+        let dimension = self.size + QUIET_ZONE * 2;
+        let mut path = String::new();
+
+        for y in 0..self.size {
+            for x in (0..self.size).filter(|&x| self.is_dark(x, y)) {
+                path.push_str(&format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE));
+            }
+        }
+
+        format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {dimension} {dimension}" shape-rendering="crispEdges"><rect width="100%" height="100%" fill="{light}"/><path d="{path}" fill="{dark}"/></svg>"#
+        )
+    }
+
+    /// Render as an SVG with the image at `logo_url` over the middle, on a
+    /// light square.
+    pub fn to_svg_with_logo(&self, dark: &str, light: &str, logo_url: &str) -> String {
+        let dimension = (self.size + QUIET_ZONE * 2) as f64;
+        let side = (self.size as f64 * LOGO_SHARE).round();
+        let offset = (dimension - side) / 2.0;
+        let logo_url = logo_url
+            .replace('&', "&amp;")
+            .replace('"', "&quot;")
+            .replace('<', "&lt;");
+
+        let logo = format!(
+            r#"<rect x="{offset}" y="{offset}" width="{side}" height="{side}" fill="{light}"/><image href="{logo_url}" x="{offset}" y="{offset}" width="{side}" height="{side}" preserveAspectRatio="xMidYMid meet"/></svg>"#
+        );
+
+        self.to_svg(dark, light).replacen("</svg>", &logo, 1)
+    }
+
+    /// Render as PDF content stream operators filling the dark modules of
+    /// a `side` points wide square, quiet zone included, whose bottom left
+    /// corner is at `x`, `y`.
+    pub fn to_pdf(&self, x: f64, y: f64, side: f64) -> String {
+        let module = side / (self.size + QUIET_ZONE * 2) as f64;
+        let mut operators = String::from("0 g\n");
+
+        for row in 0..self.size {
+            let bottom = y + side - (row + QUIET_ZONE + 1) as f64 * module;
+            let mut column = 0;
+            while column < self.size {
+                if !self.is_dark(column, row) {
+                    column += 1;
+                    continue;
+                }
+
+                // One rectangle per run of dark modules keeps pages small.
+                let start = column;
+                while column < self.size && self.is_dark(column, row) {
+                    column += 1;
+                }
+                operators.push_str(&format!(
+                    "{:.3} {:.3} {:.3} {:.3} re\n",
+                    x + (start + QUIET_ZONE) as f64 * module,
+                    bottom,
+                    (column - start) as f64 * module,
+                    module
+                ));
+            }
+        }
+        operators.push_str("f\n");
+
+        operators
+    }
+
+    /// Render as a black and white PNG with `scale` pixels per module.
+    pub fn to_png(&self, scale: usize) -> Vec<u8> {
+        let dimension = (self.size + QUIET_ZONE * 2) * scale;
+        let row_bytes = dimension.div_ceil(8);
+        let mut pixels = Vec::with_capacity((row_bytes + 1) * dimension);
+
+        for pixel_y in 0..dimension {
+            // Filter type "none" for every scanline.
+            pixels.push(0);
+            let mut row = vec![0xFF; row_bytes];
+            let y = (pixel_y / scale).checked_sub(QUIET_ZONE);
+            for pixel_x in 0..dimension {
+                let x = (pixel_x / scale).checked_sub(QUIET_ZONE);
+                if let (Some(x), Some(y)) = (x, y) {
+                    if x < self.size && y < self.size && self.is_dark(x, y) {
+                        row[pixel_x / 8] &= !(0x80 >> (pixel_x % 8));
+                    }
+                }
+            }
+            pixels.extend(row);
+        }
+
+        crate::png::encode_grayscale(dimension as u32, dimension as u32, 1, &pixels)
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.is_function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        for i in 0..self.size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        self.draw_finder_pattern(3, 3);
+        self.draw_finder_pattern(self.size - 4, 3);
+        self.draw_finder_pattern(3, self.size - 4);
+
+        let positions = alignment_pattern_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // The corners overlap the finder patterns.
+                let corner = (i == 0 && (j == 0 || j == last)) || (i == last && j == 0);
+                if !corner {
+                    self.draw_alignment_pattern(x, y);
+                }
+            }
+        }
+
+        self.draw_format_bits(0);
+        self.draw_version(version);
+    }
+
+    fn draw_finder_pattern(&mut self, x: usize, y: usize) {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+                if (0..self.size as i32).contains(&xx) && (0..self.size as i32).contains(&yy) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment_pattern(&mut self, x: usize, y: usize) {
+        for dy in -2i32..=2 {
+            for dx in -2i32..=2 {
+                self.set_function(
+                    (x as i32 + dx) as usize,
+                    (y as i32 + dy) as usize,
+                    dx.abs().max(dy.abs()) != 1,
+                );
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let data = ECC_LEVEL_M_FORMAT_BITS << 3 | mask;
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set_function(self.size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, self.size - 15 + i, bit(i));
+        }
+        self.set_function(8, self.size - 8, true);
+    }
+
+    fn draw_version(&mut self, version: usize) {
+        if version < 7 {
+            return;
+        }
+
+        let mut remainder = version as u32;
+        for _ in 0..12 {
+            remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+        }
+        let bits = (version as u32) << 12 | remainder;
+
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    /// Place data bits in the two-column zigzag, skipping function modules.
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let mut i = 0;
+        let mut right = self.size as i32 - 1;
+
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..self.size {
+                for j in 0..2 {
+                    let x = (right - j) as usize;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward {
+                        self.size - 1 - vertical
+                    } else {
+                        vertical
+                    };
+                    if !self.is_function[y * self.size + x] && i < data.len() * 8 {
+                        self.modules[y * self.size + x] = (data[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    /// XOR the data modules with one of the eight mask patterns. Applying
+    /// the same mask twice undoes it.
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                self.modules[index] ^= invert && !self.is_function[index];
+            }
+        }
+    }
+
+    fn penalty_score(&self) -> usize {
+        const FINDER_LIKE: [bool; 11] = [
+            true, false, true, true, true, false, true, false, false, false, false,
+        ];
+
+        let mut penalty = 0;
+        let lines = (0..self.size).flat_map(|i| {
+            [
+                (0..self.size)
+                    .map(|j| self.is_dark(j, i))
+                    .collect::<Vec<_>>(),
+                (0..self.size)
+                    .map(|j| self.is_dark(i, j))
+                    .collect::<Vec<_>>(),
+            ]
+        });
+
+        for line in lines {
+            for run in line.chunk_by(|a, b| a == b) {
+                if run.len() >= 5 {
+                    penalty += run.len() - 2;
+                }
+            }
+            for window in line.windows(FINDER_LIKE.len()) {
+                if window == FINDER_LIKE || window.iter().rev().eq(FINDER_LIKE.iter()) {
+                    penalty += 40;
+                }
+            }
+        }
+
+        for y in 0..self.size - 1 {
+            for x in 0..self.size - 1 {
+                let color = self.is_dark(x, y);
+                if color == self.is_dark(x + 1, y)
+                    && color == self.is_dark(x, y + 1)
+                    && color == self.is_dark(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let total = self.modules.len();
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let deviation = (dark * 20).abs_diff(total * 10);
+        penalty + (deviation.div_ceil(total)).saturating_sub(1) * 10
+    }
+}
+
+#[derive(Default)]
+struct BitBuffer(Vec<bool>);
+
+impl BitBuffer {
+    fn append(&mut self, value: u32, length: usize) {
+        self.0
+            .extend((0..length).rev().map(|i| (value >> i) & 1 != 0));
+    }
+}
+
+fn num_raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        result -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn num_data_codewords(version: usize) -> usize {
+    num_raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[version] * NUM_ERROR_CORRECTION_BLOCKS[version]
+}
+
+fn alignment_pattern_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+
+    let count = version / 7 + 2;
+    let step = if version == 32 {
+        26
+    } else {
+        (version * 4 + count * 2 + 1) / (count * 2 - 2) * 2
+    };
+    let size = version * 4 + 17;
+
+    let mut positions: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// Split the data into blocks, append Reed-Solomon codewords to each and
+/// interleave them in the order they are placed in the symbol.
+fn add_ecc_and_interleave(data: &[u8], version: usize) -> Vec<u8> {
+    let blocks_count = NUM_ERROR_CORRECTION_BLOCKS[version];
+    let ecc_length = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = num_raw_data_modules(version) / 8;
+    let short_blocks = blocks_count - raw_codewords % blocks_count;
+    let short_block_length = raw_codewords / blocks_count;
+    let divisor = reed_solomon_divisor(ecc_length);
+
+    let mut blocks = Vec::with_capacity(blocks_count);
+    let mut offset = 0;
+    for i in 0..blocks_count {
+        let data_length = short_block_length - ecc_length + usize::from(i >= short_blocks);
+        let mut block = data[offset..offset + data_length].to_vec();
+        offset += data_length;
+        let ecc = reed_solomon_remainder(&block, &divisor);
+        if i < short_blocks {
+            block.push(0);
+        }
+        block.extend(ecc);
+        blocks.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..=short_block_length {
+        for (j, block) in blocks.iter().enumerate() {
+            // Skip the placeholder padding short blocks carry.
+            if i != short_block_length - ecc_length || j >= short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (x, &y) in result.iter_mut().zip(divisor) {
+            *x ^= gf_multiply(y, factor);
+        }
+    }
+    result
+}
+
+/// Multiply in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u8 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x1D);
+        z ^= ((y >> i) & 1) * x;
+    }
+    z
+}
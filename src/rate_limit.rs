@@ -0,0 +1,193 @@
+//! Token buckets limiting how often a client may create links or follow
+//! them, answering `429 Too Many Requests` with a `Retry-After` once a
+//! bucket runs dry.
+//!
+//! Buckets are per client address, as `ClientIp` reports it, and for link
+//! creation also per account, the bearer token being this API's key. A
+//! bucket holds a minute's worth of requests and refills continuously, so
+//! clients may burst up to the limit. Each replica counts on its own, so
+//! the effective limit scales with the number of replicas.
+
+use crate::authentication::Claims;
+use crate::client_ip::ClientIp;
+use crate::configuration::Settings;
+use crate::InnerState;
+
+use axum::extract::{Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum_prometheus::metrics::counter;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets tracked per limiter before full ones, which a new bucket would
+/// equal, are forgotten.
+const MAX_BUCKETS: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum BucketKey {
+    Ip(IpAddr),
+    Account(u64),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Buckets allowing `per_minute` requests each, or every request when it is
+/// zero.
+pub struct RateLimiter {
+    name: &'static str,
+    per_minute: u32,
+    buckets: Mutex<HashMap<BucketKey, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(name: &'static str, per_minute: u32) -> Self {
+        Self {
+            name,
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token from the key's bucket, or say how long until there is
+    /// one.
+    fn acquire(&self, key: BucketKey) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("The rate limiter lock should never be poisoned");
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated_at).as_secs_f64() * per_second
+                    < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        let refilled = now.duration_since(bucket.updated_at).as_secs_f64() * per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+
+    /// The response turning the request away, if its bucket is empty.
+    fn refusal(&self, key: BucketKey) -> Option<Response> {
+        let retry_after = self.acquire(key).err()?;
+        counter!("rate_limited_total", "limit" => self.name).increment(1);
+
+        Some(too_many_requests(retry_after))
+    }
+}
+
+/// The limiters of every rate limited route.
+pub struct RateLimits {
+    link_creation_per_ip: RateLimiter,
+    link_creation_per_account: RateLimiter,
+    redirects_per_ip: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            link_creation_per_ip: RateLimiter::new(
+                "link_creation_per_ip",
+                settings.link_creation_rate_limit_per_ip,
+            ),
+            link_creation_per_account: RateLimiter::new(
+                "link_creation_per_account",
+                settings.link_creation_rate_limit_per_account,
+            ),
+            redirects_per_ip: RateLimiter::new(
+                "redirects_per_ip",
+                settings.redirect_rate_limit_per_ip,
+            ),
+        }
+    }
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+    // Whole seconds, rounded up so a retry right on time succeeds.
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, seconds.max(1).to_string())],
+        "Too many requests".to_string(),
+    )
+        .into_response()
+}
+
+/// FNV-1a, so buckets do not hold on to account emails.
+fn account_key(subject: &str) -> BucketKey {
+    BucketKey::Account(subject.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    }))
+}
+
+pub async fn limit_link_creation(
+    State(inner): State<InnerState>,
+    client: ClientIp,
+    claims: Option<Claims>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limits = &inner.rate_limits;
+
+    if let Some(response) = limits
+        .link_creation_per_ip
+        .refusal(BucketKey::Ip(client.ip))
+    {
+        return response;
+    }
+    if let Some(claims) = claims {
+        if let Some(response) = limits
+            .link_creation_per_account
+            .refusal(account_key(&claims.sub))
+        {
+            return response;
+        }
+    }
+
+    next.run(request).await
+}
+
+pub async fn limit_redirects(
+    State(inner): State<InnerState>,
+    client: ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(response) = inner
+        .rate_limits
+        .redirects_per_ip
+        .refusal(BucketKey::Ip(client.ip))
+    {
+        return response;
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,100 @@
+//! Read-only mode, in which redirects and reads are served while requests
+//! changing data are refused, such as during migrations, while a replica is
+//! promoted, or during an incident. It starts as `READ_ONLY` says and
+//! operators switch it at runtime, for the replica they reach.
+
+use crate::InnerState;
+
+use axum::extract::{Request, State};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use std::sync::RwLock;
+
+/// Paths still taking writes in read-only mode, so operators can sign in
+/// and lift it.
+const WRITABLE_PATH_PREFIXES: [&str; 3] = ["/admin/read-only", "/authorize", "/auth/"];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadOnlyStatus {
+    pub enabled: bool,
+    pub reason: Option<String>,
+    /// When an operator last switched it, none when still as configured.
+    pub changed_at: Option<NaiveDateTime>,
+    pub changed_by: Option<String>,
+}
+
+pub struct ReadOnlyMode {
+    status: RwLock<ReadOnlyStatus>,
+}
+
+impl ReadOnlyMode {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            status: RwLock::new(ReadOnlyStatus {
+                enabled,
+                reason: enabled.then(|| "Configured with READ_ONLY".to_string()),
+                changed_at: None,
+                changed_by: None,
+            }),
+        }
+    }
+
+    pub fn status(&self) -> ReadOnlyStatus {
+        self.status
+            .read()
+            .expect("The read-only mode lock should never be poisoned")
+            .clone()
+    }
+
+    /// Switch read-only mode, returning the status it replaced.
+    pub fn set(&self, enabled: bool, reason: Option<String>, changed_by: &str) -> ReadOnlyStatus {
+        let status = ReadOnlyStatus {
+            enabled,
+            reason,
+            changed_at: Some(Utc::now().naive_utc()),
+            changed_by: Some(changed_by.to_string()),
+        };
+        let mut current = self
+            .status
+            .write()
+            .expect("The read-only mode lock should never be poisoned");
+        std::mem::replace(&mut *current, status)
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.status
+            .read()
+            .expect("The read-only mode lock should never be poisoned")
+            .enabled
+    }
+}
+
+/// Refuse requests changing data while read-only mode is on.
+pub async fn refuse_writes_when_read_only(
+    State(inner): State<InnerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let reads = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let path = request.uri().path();
+    let writable = WRITABLE_PATH_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix));
+
+    if !reads && !writable && inner.read_only.is_enabled() {
+        let message = match inner.read_only.status().reason {
+            Some(reason) => format!("The API is read-only for now: {}", reason),
+            None => "The API is read-only for now".to_string(),
+        };
+        return (StatusCode::SERVICE_UNAVAILABLE, message).into_response();
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,238 @@
+//! Cache of redirect targets for links the redirect snapshot does not
+//! have: in Redis, shared by every replica, when `REDIS_URL` is set, and
+//! otherwise in memory, keeping the most recently used links. Every
+//! replica removes a link's entry when the link is announced as changed.
+//! Links that expire are never cached, as an entry could outlive them.
+//! Redis being slow or down only sends redirects to the database.
+
+use crate::configuration::Settings;
+use crate::db::links::RedirectTarget;
+use crate::redis::RedisClient;
+
+use axum_prometheus::metrics::counter;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const KEY_PREFIX: &str = "groupify:redirect:";
+
+/// How long a redirect waits on Redis before asking the database.
+const REDIS_TIMEOUT: Duration = Duration::from_millis(50);
+
+pub struct RedirectCache {
+    backend: Backend,
+    ttl: Duration,
+}
+
+enum Backend {
+    Redis(RedisClient),
+    Memory(Mutex<LruCache>),
+    Disabled,
+}
+
+impl RedirectCache {
+    pub fn new(settings: &Settings) -> Self {
+        let backend = match settings.redis_url.as_deref() {
+            Some(url) => Backend::Redis(
+                RedisClient::from_url(url).expect("REDIS_URL should be a redis:// URL"),
+            ),
+            None if settings.redirect_cache_capacity > 0 => {
+                Backend::Memory(Mutex::new(LruCache::new(settings.redirect_cache_capacity)))
+            }
+            None => Backend::Disabled,
+        };
+
+        Self {
+            backend,
+            ttl: settings.redirect_cache_ttl,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self.backend, Backend::Disabled)
+    }
+
+    /// Whether the cache is shared by every replica rather than this one's.
+    pub fn is_shared(&self) -> bool {
+        matches!(self.backend, Backend::Redis(_))
+    }
+
+    /// Whether the cache answers within `timeout`. In memory, it always
+    /// does.
+    pub async fn ping(&self, timeout: Duration) -> Result<(), anyhow::Error> {
+        match &self.backend {
+            Backend::Redis(redis) => tokio::time::timeout(timeout, redis.ping())
+                .await
+                .map_err(|_| anyhow::anyhow!("Timed out pinging Redis"))?,
+            Backend::Memory(_) | Backend::Disabled => Ok(()),
+        }
+    }
+
+    pub async fn get(&self, link_id: &str) -> Option<RedirectTarget> {
+        let target = match &self.backend {
+            Backend::Redis(redis) => {
+                let cached =
+                    match tokio::time::timeout(REDIS_TIMEOUT, redis.get(&key(link_id))).await {
+                        Ok(Ok(cached)) => cached,
+                        Ok(Err(err)) => {
+                            tracing::warn!(
+                                "Could not read link {} from the redirect cache: {:#}",
+                                link_id,
+                                err
+                            );
+                            return None;
+                        }
+                        Err(_) => {
+                            tracing::warn!(
+                                "Timed out reading link {} from the redirect cache",
+                                link_id
+                            );
+                            return None;
+                        }
+                    };
+
+                cached.and_then(|cached| serde_json::from_slice(&cached).ok())
+            }
+            Backend::Memory(lru) => lock(lru).get(link_id, self.ttl),
+            Backend::Disabled => return None,
+        };
+
+        let outcome = if target.is_some() { "hit" } else { "miss" };
+        counter!("redirect_cache_lookups_total", "outcome" => outcome).increment(1);
+
+        target
+    }
+
+    pub async fn put(&self, target: &RedirectTarget) {
+        // Redirects have to notice the link expiring, which only the
+        // database tells.
+        if target.expires_at.is_some() {
+            return;
+        }
+
+        match &self.backend {
+            Backend::Redis(redis) => {
+                let value =
+                    serde_json::to_vec(target).expect("Redirect targets should always serialize");
+                let stored = tokio::time::timeout(
+                    REDIS_TIMEOUT,
+                    redis.set_ex(&key(&target.id), &value, self.ttl.as_secs().max(1)),
+                )
+                .await;
+                if !matches!(stored, Ok(Ok(()))) {
+                    tracing::warn!("Could not cache the redirect target of link {}", target.id);
+                }
+            }
+            Backend::Memory(lru) => lock(lru).insert(target.clone()),
+            Backend::Disabled => {}
+        }
+    }
+
+    /// Drop the cached target of a link after changing it. With Redis,
+    /// other replicas stop serving it too.
+    pub async fn forget(&self, link_id: &str) {
+        match &self.backend {
+            Backend::Redis(redis) => {
+                if let Err(err) = redis.del(&key(link_id)).await {
+                    tracing::error!(
+                        "Could not remove link {} from the redirect cache: {:#}",
+                        link_id,
+                        err
+                    );
+                }
+            }
+            Backend::Memory(lru) => lock(lru).remove(link_id),
+            Backend::Disabled => {}
+        }
+    }
+
+    /// Drop every target this replica keeps in memory. Redis is left alone,
+    /// as the replicas still listening remove entries from it.
+    pub fn forget_local(&self) {
+        if let Backend::Memory(lru) = &self.backend {
+            lock(lru).clear();
+        }
+    }
+}
+
+fn key(link_id: &str) -> String {
+    format!("{}{}", KEY_PREFIX, link_id)
+}
+
+fn lock(lru: &Mutex<LruCache>) -> std::sync::MutexGuard<'_, LruCache> {
+    lru.lock()
+        .expect("The redirect cache lock should never be poisoned")
+}
+
+/// Targets by link id, evicting the least recently used one when full.
+/// Every use is stamped with a new sequence number, so the oldest stamp in
+/// `recency` belongs to the entry to evict.
+struct LruCache {
+    capacity: usize,
+    entries: HashMap<String, CachedTarget>,
+    recency: BTreeMap<u64, String>,
+    next_use: u64,
+}
+
+struct CachedTarget {
+    target: RedirectTarget,
+    cached_at: Instant,
+    last_use: u64,
+}
+
+impl LruCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_use: 0,
+        }
+    }
+
+    fn get(&mut self, link_id: &str, ttl: Duration) -> Option<RedirectTarget> {
+        let entry = self.entries.get_mut(link_id)?;
+        if entry.cached_at.elapsed() >= ttl {
+            self.remove(link_id);
+            return None;
+        }
+
+        self.recency.remove(&entry.last_use);
+        entry.last_use = self.next_use;
+        self.recency.insert(self.next_use, link_id.to_string());
+        self.next_use += 1;
+
+        Some(entry.target.clone())
+    }
+
+    fn insert(&mut self, target: RedirectTarget) {
+        self.remove(&target.id);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, evicted)) = self.recency.pop_first() {
+                self.entries.remove(&evicted);
+            }
+        }
+
+        self.recency.insert(self.next_use, target.id.clone());
+        self.entries.insert(
+            target.id.clone(),
+            CachedTarget {
+                target,
+                cached_at: Instant::now(),
+                last_use: self.next_use,
+            },
+        );
+        self.next_use += 1;
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    fn remove(&mut self, link_id: &str) {
+        if let Some(entry) = self.entries.remove(link_id) {
+            self.recency.remove(&entry.last_use);
+        }
+    }
+}
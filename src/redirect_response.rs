@@ -0,0 +1,117 @@
+//! The response sent for every followed short link, built without copying
+//! the target or parsing header values per request. Kept free of crate
+//! dependencies so `benches/redirect_response.rs` can include it.
+//!
+//! Redirects carry an ETag derived from the target alone, so it changes
+//! exactly when a link is pointed elsewhere. Caches revalidating a stored
+//! redirect, as CDNs do in the background under `stale-while-revalidate`,
+//! then get a `304` while the target is unchanged and the new redirect as
+//! soon as it is not, whatever its age.
+
+use axum::body::{Body, Bytes};
+use axum::http::header::{InvalidHeaderValue, CACHE_CONTROL, ETAG, IF_NONE_MATCH, LOCATION};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
+
+pub const DEFAULT_CACHE_CONTROL_HEADER_VALUE: &str =
+    "public, max-age=300, s-maxage=300, stale-while-revalidate=300, stale-if-error=300";
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+pub const PRIVATE_CACHE_CONTROL_HEADER_VALUE: &str = "private, no-cache";
+
+/// Cloning a static header value shares it rather than copying.
+static CACHE_CONTROL_VALUE: HeaderValue =
+    HeaderValue::from_static(DEFAULT_CACHE_CONTROL_HEADER_VALUE);
+
+static PRIVATE_CACHE_CONTROL_VALUE: HeaderValue =
+    HeaderValue::from_static(PRIVATE_CACHE_CONTROL_HEADER_VALUE);
+
+/// A `Location` value taking over the target's buffer. Fails for targets
+/// that cannot be sent in a header, such as ones holding a newline.
+pub fn location(target_url: String) -> Result<HeaderValue, InvalidHeaderValue> {
+    HeaderValue::try_from(target_url)
+}
+
+/// A strong ETag naming the target, the same on every replica and release:
+/// a 64-bit FNV-1a hash of it in hex.
+pub fn target_etag(target_url: &str) -> HeaderValue {
+    let hash = target_url.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    });
+
+    let mut etag = Vec::with_capacity(18);
+    etag.push(b'"');
+    for shift in (0..16).rev() {
+        etag.push(b"0123456789abcdef"[(hash >> (shift * 4)) as usize & 0xf]);
+    }
+    etag.push(b'"');
+
+    HeaderValue::from_maybe_shared(Bytes::from(etag)).expect("A hex ETag is a valid header")
+}
+
+/// Whether the request's `If-None-Match` lists `etag`, meaning the caller
+/// already holds this redirect.
+pub fn etag_matches(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/").as_bytes() == etag.as_bytes())
+}
+
+pub fn temporary_redirect(location: HeaderValue, etag: HeaderValue) -> Response {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::TEMPORARY_REDIRECT;
+
+    let headers = response.headers_mut();
+    headers.reserve(3);
+    headers.insert(LOCATION, location);
+    headers.insert(ETAG, etag);
+    headers.insert(CACHE_CONTROL, CACHE_CONTROL_VALUE.clone());
+
+    response
+}
+
+/// The answer to a revalidation of a redirect whose target is unchanged.
+pub fn not_modified(etag: HeaderValue) -> Response {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NOT_MODIFIED;
+
+    let headers = response.headers_mut();
+    headers.reserve(2);
+    headers.insert(ETAG, etag);
+    headers.insert(CACHE_CONTROL, CACHE_CONTROL_VALUE.clone());
+
+    response
+}
+
+/// Let caches keep a response for `seconds` rather than the default five
+/// minutes, or revalidate it every time when zero.
+pub fn cached_for(mut response: Response, seconds: u32) -> Response {
+    let value = if seconds == 0 {
+        HeaderValue::from_static("public, no-cache")
+    } else {
+        HeaderValue::try_from(format!(
+            "public, max-age={0}, s-maxage={0}, stale-while-revalidate={0}, stale-if-error={0}",
+            seconds
+        ))
+        .expect("A cache lifetime is a valid header")
+    };
+    response.headers_mut().insert(CACHE_CONTROL, value);
+
+    response
+}
+
+/// Keep a response out of shared caches, for redirects whose target
+/// depends on the visitor. Browsers still revalidate by ETag.
+pub fn private(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, PRIVATE_CACHE_CONTROL_VALUE.clone());
+
+    response
+}
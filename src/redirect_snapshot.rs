@@ -0,0 +1,408 @@
+//! Snapshot of every active link, so redirects can be served without a query.
+//!
+//! The `id -> target` mapping is periodically written to a file as a perfect
+//! hash table and memory-mapped, which leaves caching it to the kernel and
+//! costs a lookup two hashes and a key comparison. Ids missing from the
+//! snapshot, such as links created since the last refresh, fall back to the
+//! database. Links announced as changed, on any replica, are forgotten
+//! until a refresh begun after the change replaces the snapshot, so they
+//! fall back too.
+//!
+//! The table is built by hash and displace: ids are grouped into buckets and
+//! each bucket stores the displacement that sends all of its ids to free
+//! slots. The file layout, with integers as little endian `u32`, is:
+//!
+//! ```text
+//! magic | bucket count | slot count | displacement per bucket
+//!       | record offset per slot, EMPTY_SLOT when free | records
+//! ```
+//!
+//! where a record is its id length, target length and sample rate, followed
+//! by the id and the target.
+
+use crate::configuration::Settings;
+use crate::db::links::{self, RedirectBehavior, RedirectTarget};
+
+use anyhow::Context;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 8] = b"GRPSNAP1";
+const HEADER_LEN: usize = MAGIC.len() + 8;
+const RECORD_HEADER_LEN: usize = 12;
+const EMPTY_SLOT: u32 = u32::MAX;
+
+/// Average ids per bucket; smaller buckets are quicker to place.
+const BUCKET_SIZE: usize = 4;
+
+/// Give up on building a table when a bucket cannot be placed in this many
+/// attempts, keeping the previous snapshot.
+const MAX_DISPLACEMENT: u32 = 1 << 20;
+
+pub struct RedirectSnapshot {
+    path: Option<PathBuf>,
+    every: Duration,
+    current: RwLock<Option<Arc<Snapshot>>>,
+    /// When each link changed since the current snapshot was read from the
+    /// database.
+    forgotten: Mutex<HashMap<String, Instant>>,
+}
+
+impl RedirectSnapshot {
+    /// Start from the snapshot a previous process left behind, if any, so a
+    /// restart does not have to wait for the first refresh.
+    pub fn open(settings: &Settings) -> Self {
+        let current = settings
+            .redirect_snapshot_path
+            .as_deref()
+            .filter(|path| path.exists())
+            .and_then(|path| match Snapshot::open(path) {
+                Ok(snapshot) => Some(Arc::new(snapshot)),
+                Err(err) => {
+                    tracing::warn!("Ignoring unreadable redirect snapshot: {}", err);
+                    None
+                }
+            });
+
+        Self {
+            path: settings.redirect_snapshot_path.clone(),
+            every: settings.redirect_snapshot_interval,
+            current: RwLock::new(current),
+            forgotten: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The link as of the last refresh, or `None` to ask the database.
+    pub fn lookup(&self, id: &str) -> Option<RedirectTarget> {
+        if self.forgotten().contains_key(id) {
+            return None;
+        }
+
+        let snapshot = self
+            .current
+            .read()
+            .expect("The redirect snapshot lock should never be poisoned")
+            .clone()?;
+
+        snapshot.lookup(id)
+    }
+
+    /// Stop answering for a link after changing it, until the snapshot is
+    /// rebuilt from the database as the change left it.
+    pub fn forget(&self, id: &str) {
+        if self.path.is_some() {
+            self.forgotten().insert(id.to_string(), Instant::now());
+        }
+    }
+
+    /// Stop answering for every link until the next refresh, when changes
+    /// may have gone unnoticed.
+    pub fn forget_all(&self) {
+        *self
+            .current
+            .write()
+            .expect("The redirect snapshot lock should never be poisoned") = None;
+    }
+
+    fn forgotten(&self) -> std::sync::MutexGuard<'_, HashMap<String, Instant>> {
+        self.forgotten
+            .lock()
+            .expect("The forgotten links lock should never be poisoned")
+    }
+
+    /// Rebuild the snapshot every interval, when snapshots are enabled.
+    pub async fn run_refresher(self: Arc<Self>, db: PgPool) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+
+        let mut interval = tokio::time::interval(self.every);
+
+        loop {
+            interval.tick().await;
+
+            match self.refresh(&db, &path).await {
+                Ok(links) => tracing::debug!("Redirect snapshot holds {} links", links),
+                Err(err) => tracing::error!("Could not refresh redirect snapshot: {:?}", err),
+            }
+        }
+    }
+
+    async fn refresh(&self, db: &PgPool, path: &Path) -> Result<usize, anyhow::Error> {
+        let started = Instant::now();
+        let links = links::active_redirect_targets(db)
+            .await
+            .context("Failed to fetch links.")?;
+        let count = links.len();
+
+        let path = path.to_path_buf();
+        let snapshot = tokio::task::spawn_blocking(move || write_snapshot(&path, &links))
+            .await
+            .context("Snapshot builder panicked.")??;
+
+        *self
+            .current
+            .write()
+            .expect("The redirect snapshot lock should never be poisoned") =
+            Some(Arc::new(snapshot));
+
+        // Links changed once the query had begun may be stale in it still.
+        self.forgotten()
+            .retain(|_, forgotten_at| *forgotten_at >= started);
+
+        Ok(count)
+    }
+}
+
+/// Replace the snapshot file with one holding `links` and map it. The new
+/// file is renamed over the old one, so existing mappings remain valid.
+fn write_snapshot(path: &Path, links: &[RedirectTarget]) -> Result<Snapshot, anyhow::Error> {
+    let table = build_table(links).context("Could not place every link in the table.")?;
+
+    if let Some(directory) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(directory)?;
+    }
+
+    let staging = path.with_extension("tmp");
+    let mut file = File::create(&staging)?;
+    file.write_all(&table)?;
+    file.sync_all()?;
+    std::fs::rename(&staging, path)?;
+
+    Ok(Snapshot::open(path)?)
+}
+
+fn build_table(links: &[RedirectTarget]) -> Option<Vec<u8>> {
+    let slot_count = (links.len() + links.len() / 4).max(1);
+    let bucket_count = (links.len() / BUCKET_SIZE).max(1);
+
+    let hashes: Vec<u64> = links.iter().map(|link| key_hash(&link.id)).collect();
+
+    let mut buckets = vec![Vec::new(); bucket_count];
+    for (index, hash) in hashes.iter().enumerate() {
+        buckets[bucket_of(*hash, bucket_count)].push(index);
+    }
+
+    // Placing the largest buckets first, while most slots are free, is what
+    // keeps the search for displacements short.
+    let mut order: Vec<usize> = (0..bucket_count).collect();
+    order.sort_by_key(|bucket| std::cmp::Reverse(buckets[*bucket].len()));
+
+    let mut displacements = vec![0u32; bucket_count];
+    let mut slots: Vec<Option<usize>> = vec![None; slot_count];
+    let mut candidate = Vec::new();
+    for bucket in order {
+        let members = &buckets[bucket];
+        if members.is_empty() {
+            break;
+        }
+
+        displacements[bucket] = (0..MAX_DISPLACEMENT).find(|displacement| {
+            candidate.clear();
+            members.iter().all(|member| {
+                let slot = slot_of(hashes[*member], *displacement, slot_count);
+                let free = slots[slot].is_none() && !candidate.contains(&slot);
+                candidate.push(slot);
+                free
+            })
+        })?;
+
+        for (member, slot) in members.iter().zip(&candidate) {
+            slots[*slot] = Some(*member);
+        }
+    }
+
+    let records_start = HEADER_LEN + 4 * (bucket_count + slot_count);
+    let mut offsets = vec![EMPTY_SLOT; slot_count];
+    let mut records = Vec::new();
+    for (slot, link) in slots.iter().enumerate() {
+        let Some(link) = link.map(|index| &links[index]) else {
+            continue;
+        };
+
+        offsets[slot] = u32::try_from(records_start + records.len()).ok()?;
+        records.extend_from_slice(&u32::try_from(link.id.len()).ok()?.to_le_bytes());
+        records.extend_from_slice(&u32::try_from(link.target_url.len()).ok()?.to_le_bytes());
+        records.extend_from_slice(&link.click_sample_rate.to_le_bytes());
+        records.extend_from_slice(link.id.as_bytes());
+        records.extend_from_slice(link.target_url.as_bytes());
+    }
+
+    let mut table = Vec::with_capacity(records_start + records.len());
+    table.extend_from_slice(MAGIC);
+    table.extend_from_slice(&(bucket_count as u32).to_le_bytes());
+    table.extend_from_slice(&(slot_count as u32).to_le_bytes());
+    for value in displacements.iter().chain(&offsets) {
+        table.extend_from_slice(&value.to_le_bytes());
+    }
+    table.extend_from_slice(&records);
+
+    Some(table)
+}
+
+/// FNV-1a, which is plenty for ids we generate ourselves.
+fn key_hash(id: &str) -> u64 {
+    id.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// The murmur3 finalizer, spreading every bit of the hash over the result.
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+fn bucket_of(hash: u64, bucket_count: usize) -> usize {
+    (mix(hash) % bucket_count as u64) as usize
+}
+
+fn slot_of(hash: u64, displacement: u32, slot_count: usize) -> usize {
+    let seed = (displacement as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (mix(hash ^ seed) % slot_count as u64) as usize
+}
+
+struct Snapshot {
+    mapping: Mapping,
+    bucket_count: usize,
+    slot_count: usize,
+}
+
+impl Snapshot {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let mapping = Mapping::open(path)?;
+        let bytes = mapping.bytes();
+
+        let malformed =
+            || std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed snapshot");
+        if !bytes.starts_with(MAGIC) {
+            return Err(malformed());
+        }
+        let bucket_count = read_u32(bytes, MAGIC.len()).ok_or_else(malformed)? as usize;
+        let slot_count = read_u32(bytes, MAGIC.len() + 4).ok_or_else(malformed)? as usize;
+        if bucket_count == 0
+            || slot_count == 0
+            || bytes.len() < HEADER_LEN + 4 * (bucket_count + slot_count)
+        {
+            return Err(malformed());
+        }
+
+        Ok(Self {
+            mapping,
+            bucket_count,
+            slot_count,
+        })
+    }
+
+    fn lookup(&self, id: &str) -> Option<RedirectTarget> {
+        let bytes = self.mapping.bytes();
+        let hash = key_hash(id);
+
+        let displacement = read_u32(bytes, HEADER_LEN + 4 * bucket_of(hash, self.bucket_count))?;
+        let slot = slot_of(hash, displacement, self.slot_count);
+        let offset = read_u32(bytes, HEADER_LEN + 4 * (self.bucket_count + slot))?;
+        if offset == EMPTY_SLOT {
+            return None;
+        }
+
+        // Every id lands on some slot, so check it is the one stored there.
+        let offset = offset as usize;
+        let id_len = read_u32(bytes, offset)? as usize;
+        let target_len = read_u32(bytes, offset + 4)? as usize;
+        let sample_rate = read_u32(bytes, offset + 8)? as i32;
+        let id_start = offset + RECORD_HEADER_LEN;
+        if bytes.get(id_start..id_start + id_len)? != id.as_bytes() {
+            return None;
+        }
+        let target_start = id_start + id_len;
+        let target_url = bytes.get(target_start..target_start + target_len)?;
+
+        Some(RedirectTarget {
+            id: id.to_string(),
+            target_url: std::str::from_utf8(target_url).ok()?.to_string(),
+            click_sample_rate: sample_rate,
+            // Links with rules are never in the snapshot, nor are those
+            // redirecting in their own way or expiring.
+            routing_rules: Vec::new(),
+            payload: None,
+            behavior: RedirectBehavior::default(),
+            expires_at: None,
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let word = bytes.get(at..at + 4)?;
+    Some(u32::from_le_bytes(word.try_into().ok()?))
+}
+
+/// A read-only memory mapping of a whole file.
+struct Mapping {
+    address: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is never written to, so sharing it between threads is sound.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Snapshot too large")
+        })?;
+        if len < HEADER_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Snapshot is truncated",
+            ));
+        }
+
+        // SAFETY: a private read-only mapping of a file we opened. Snapshot
+        // files are never modified after being written, only replaced by
+        // renaming a new file over them, which leaves this mapping intact.
+        let address = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if address == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(Self { address, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: `address` points at `len` mapped bytes until we unmap them
+        // on drop.
+        unsafe { std::slice::from_raw_parts(self.address as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: unmapping exactly what `open` mapped, once.
+        unsafe {
+            libc::munmap(self.address, self.len);
+        }
+    }
+}
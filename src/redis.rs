@@ -0,0 +1,198 @@
+//! Minimal Redis client speaking RESP2 over plain TCP, covering the few
+//! commands caches need. Connections are kept in a small pool and dropped
+//! on any error, so a restarted server is reconnected to on the next use.
+
+use anyhow::{anyhow, bail, Context};
+use std::sync::Mutex;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use url::Url;
+
+const DEFAULT_PORT: u16 = 6379;
+
+/// Idle connections kept for reuse; more are opened under load and closed
+/// once returned.
+const MAX_IDLE_CONNECTIONS: usize = 16;
+
+/// Longest bulk reply accepted, so a corrupt length cannot exhaust memory.
+const MAX_BULK_LENGTH: usize = 1 << 20;
+
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Status(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+}
+
+pub struct RedisClient {
+    address: String,
+    username: Option<String>,
+    password: Option<String>,
+    database: Option<u32>,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl RedisClient {
+    /// A client for a `redis://[[user]:password@]host[:port][/database]`
+    /// URL. Nothing is connected to until the first command.
+    pub fn from_url(url: &str) -> Result<Self, anyhow::Error> {
+        let url = Url::parse(url).context("Could not parse the Redis URL")?;
+        if url.scheme() != "redis" {
+            bail!("Redis URLs should start with redis://");
+        }
+        let host = url.host_str().context("The Redis URL should have a host")?;
+        let database = match url.path().trim_start_matches('/') {
+            "" => None,
+            database => Some(
+                database
+                    .parse()
+                    .context("The Redis URL path should be a database number")?,
+            ),
+        };
+
+        Ok(Self {
+            address: format!("{}:{}", host, url.port().unwrap_or(DEFAULT_PORT)),
+            username: Some(url.username())
+                .filter(|username| !username.is_empty())
+                .map(str::to_string),
+            password: url.password().map(str::to_string),
+            database,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        match self.command(&[b"GET", key.as_bytes()]).await? {
+            Reply::Bulk(value) => Ok(value),
+            reply => Err(anyhow!("Unexpected reply to GET: {:?}", reply)),
+        }
+    }
+
+    /// Store a value that expires after `ttl_seconds`.
+    pub async fn set_ex(
+        &self,
+        key: &str,
+        value: &[u8],
+        ttl_seconds: u64,
+    ) -> Result<(), anyhow::Error> {
+        let ttl = ttl_seconds.to_string();
+        self.command(&[b"SET", key.as_bytes(), value, b"EX", ttl.as_bytes()])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn del(&self, key: &str) -> Result<(), anyhow::Error> {
+        self.command(&[b"DEL", key.as_bytes()]).await?;
+        Ok(())
+    }
+
+    pub async fn ping(&self) -> Result<(), anyhow::Error> {
+        match self.command(&[b"PING"]).await? {
+            Reply::Status(status) if status == "PONG" => Ok(()),
+            reply => Err(anyhow!("Unexpected reply to PING: {:?}", reply)),
+        }
+    }
+
+    async fn command(&self, args: &[&[u8]]) -> Result<Reply, anyhow::Error> {
+        let idle = self
+            .idle
+            .lock()
+            .expect("The Redis connection pool lock should never be poisoned")
+            .pop();
+        let mut connection = match idle {
+            Some(connection) => connection,
+            None => self.connect().await?,
+        };
+
+        // A connection that failed may be left mid-reply, so it is dropped.
+        let reply = connection.command(args).await?;
+
+        let mut idle = self
+            .idle
+            .lock()
+            .expect("The Redis connection pool lock should never be poisoned");
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(connection);
+        }
+
+        Ok(reply)
+    }
+
+    async fn connect(&self) -> Result<Connection, anyhow::Error> {
+        let stream = TcpStream::connect(&self.address)
+            .await
+            .with_context(|| format!("Could not connect to Redis at {}", self.address))?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            stream: BufReader::new(stream),
+        };
+
+        if let Some(password) = &self.password {
+            match &self.username {
+                Some(username) => {
+                    connection
+                        .command(&[b"AUTH", username.as_bytes(), password.as_bytes()])
+                        .await?
+                }
+                None => connection.command(&[b"AUTH", password.as_bytes()]).await?,
+            };
+        }
+        if let Some(database) = self.database {
+            connection
+                .command(&[b"SELECT", database.to_string().as_bytes()])
+                .await?;
+        }
+
+        Ok(connection)
+    }
+}
+
+struct Connection {
+    stream: BufReader<TcpStream>,
+}
+
+impl Connection {
+    async fn command(&mut self, args: &[&[u8]]) -> Result<Reply, anyhow::Error> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg);
+            request.extend_from_slice(b"\r\n");
+        }
+        self.stream.get_mut().write_all(&request).await?;
+
+        self.read_reply().await
+    }
+
+    async fn read_reply(&mut self) -> Result<Reply, anyhow::Error> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            bail!("Redis closed the connection");
+        }
+        let line = line
+            .strip_suffix("\r\n")
+            .context("Redis replied with an unterminated line")?;
+        let (kind, rest) = line.split_at(line.len().min(1));
+
+        match kind {
+            "+" => Ok(Reply::Status(rest.to_string())),
+            "-" => Err(anyhow!("Redis replied with an error: {}", rest)),
+            ":" => Ok(Reply::Integer(rest.parse()?)),
+            "$" => {
+                let length: i64 = rest.parse()?;
+                if length < 0 {
+                    return Ok(Reply::Bulk(None));
+                }
+                let length = length as usize;
+                if length > MAX_BULK_LENGTH {
+                    bail!("Redis replied with a {} byte value", length);
+                }
+                let mut value = vec![0; length + 2];
+                self.stream.read_exact(&mut value).await?;
+                value.truncate(length);
+                Ok(Reply::Bulk(Some(value)))
+            }
+            _ => Err(anyhow!("Unsupported Redis reply: {}", line)),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Push per-link click counters to a Prometheus remote-write endpoint, for
+//! operators graphing link traffic next to the rest of their stack.
+//!
+//! The leader reads the totals from the database, so every replica's clicks
+//! are included and each is counted once. The protobuf `WriteRequest` is
+//! encoded by hand, and the snappy framing uses literal blocks only, which
+//! every decoder accepts and which is plenty for one request a minute.
+
+use crate::configuration::Settings;
+use crate::jobs;
+
+use once_cell::sync::Lazy;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const METRIC_NAME: &str = "groupify_link_clicks_total";
+
+/// Longest literal a single snappy tag with a four byte length describes.
+const MAX_SNAPPY_LITERAL: usize = u32::MAX as usize;
+
+static REMOTE_WRITE_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .expect("The remote-write client should always be constructable")
+});
+
+/// Push the counters on every interval, when an endpoint is configured.
+pub async fn run_remote_write(db: PgPool, settings: Arc<Settings>) {
+    let Some(url) = settings.remote_write_url.clone() else {
+        return;
+    };
+
+    jobs::run_periodically(db, "remote_write", settings.remote_write_interval, |db| {
+        push_link_clicks(db, url.clone(), settings.clone())
+    })
+    .await
+}
+
+async fn push_link_clicks(
+    db: PgPool,
+    url: String,
+    settings: Arc<Settings>,
+) -> Result<(), sqlx::Error> {
+    let counters: Vec<(String, i64)> = sqlx::query_as(
+        r#"SELECT link_id, sum(amount)::bigint FROM (
+            SELECT link_id, amount FROM link_statistics_daily
+            UNION ALL
+            SELECT link_id, sample_rate FROM link_statistics WHERE NOT rolled_up
+        ) statistics
+        GROUP BY link_id"#,
+    )
+    .fetch_all(&db)
+    .await?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The clock should be past the epoch")
+        .as_millis() as i64;
+
+    let body = snappy_literal(&write_request(&counters, timestamp));
+
+    let mut request = REMOTE_WRITE_CLIENT
+        .post(&url)
+        .header("Content-Encoding", "snappy")
+        .header("Content-Type", "application/x-protobuf")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .body(body);
+    if let Some(token) = &settings.remote_write_bearer_token {
+        request = request.bearer_auth(token);
+    }
+
+    // Counters are totals, so a failed push is made up for by the next one.
+    match request.send().await {
+        Ok(response) if !response.status().is_success() => {
+            tracing::warn!("Remote-write endpoint answered {}", response.status())
+        }
+        Ok(_) => tracing::debug!("Pushed click counters of {} links", counters.len()),
+        Err(err) => tracing::warn!("Could not push click counters: {}", err),
+    }
+
+    Ok(())
+}
+
+/// A `WriteRequest` with one series per link, each holding a single sample.
+fn write_request(counters: &[(String, i64)], timestamp: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+
+    for (link_id, clicks) in counters {
+        // Labels must be sorted by name.
+        let mut series = Vec::new();
+        write_message(&mut series, 1, &label("__name__", METRIC_NAME));
+        write_message(&mut series, 1, &label("link_id", link_id));
+
+        let mut sample = Vec::new();
+        write_key(&mut sample, 1, 1);
+        sample.extend_from_slice(&(*clicks as f64).to_le_bytes());
+        write_key(&mut sample, 2, 0);
+        write_varint(&mut sample, timestamp as u64);
+        write_message(&mut series, 2, &sample);
+
+        write_message(&mut request, 1, &series);
+    }
+
+    request
+}
+
+fn label(name: &str, value: &str) -> Vec<u8> {
+    let mut label = Vec::new();
+    write_message(&mut label, 1, name.as_bytes());
+    write_message(&mut label, 2, value.as_bytes());
+    label
+}
+
+/// A length-delimited field: a string, bytes or an embedded message.
+fn write_message(buffer: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(buffer, field, 2);
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn write_key(buffer: &mut Vec<u8>, field: u32, wire_type: u8) {
+    write_varint(buffer, u64::from(field) << 3 | u64::from(wire_type));
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Snappy block format holding the data as literals, uncompressed.
+fn snappy_literal(data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(data.len() + 16);
+    write_varint(&mut block, data.len() as u64);
+
+    for chunk in data.chunks(MAX_SNAPPY_LITERAL) {
+        let length = chunk.len() - 1;
+        if length < 60 {
+            block.push((length as u8) << 2);
+        } else {
+            // Tags 60 to 63 are followed by the length in 1 to 4 bytes.
+            let bytes = (length.ilog2() / 8 + 1) as usize;
+            block.push(((59 + bytes) as u8) << 2);
+            block.extend_from_slice(&length.to_le_bytes()[..bytes]);
+        }
+        block.extend_from_slice(chunk);
+    }
+
+    block
+}
@@ -0,0 +1,252 @@
+//! Signing up and logging in with JSON, for clients that are not the
+//! browser forms `/subscription` and `/authorize` were made for.
+
+use crate::authentication::{
+    end_session, require_not_suspended, rotate_refresh_token, start_session, validate_credentials,
+    AuthError, Claims, Credentials, JwtKeys, SessionDevice, SessionToken,
+};
+use crate::casing::Json;
+use crate::client_ip::ClientIp;
+use crate::configuration::Settings;
+use crate::i18n;
+use crate::routes::{
+    create_user, generate_subscription_token, send_confirmation_email, send_new_device_alert,
+    store_token, User,
+};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+#[derive(Deserialize)]
+pub struct Registration {
+    pub email: String,
+    pub password: String,
+    /// Language emails are sent in, negotiated from the request when unset.
+    pub locale: Option<String>,
+}
+
+impl Validate for Registration {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_email("email", self.email.trim());
+
+        if self.password.chars().count() < MIN_PASSWORD_LENGTH {
+            errors.add(
+                "password",
+                format!("must be at least {} characters", MIN_PASSWORD_LENGTH),
+            );
+        }
+        errors.require_max_length("password", &self.password, MAX_PASSWORD_LENGTH);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisteredAccount {
+    pub id: String,
+    pub email: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessToken {
+    pub token: String,
+    /// Trades for a new pair at `/auth/refresh` once the token expires, and
+    /// only works once.
+    pub refresh_token: String,
+    /// Seconds until the access token expires.
+    pub expires_in: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[tracing::instrument(name = "Register account", skip(inner, headers, registration))]
+pub async fn register_account(
+    State(inner): State<InnerState>,
+    headers: HeaderMap,
+    Valid(registration): Valid<Registration>,
+) -> Result<(StatusCode, Json<RegisteredAccount>), (StatusCode, String)> {
+    let InnerState {
+        email_client, db, ..
+    } = inner;
+
+    let email = registration.email.trim().to_string();
+    let locale = registration
+        .locale
+        .as_deref()
+        .and_then(i18n::resolve)
+        .unwrap_or_else(|| i18n::negotiate(&headers));
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let taken: bool = sqlx::query_scalar(r#"SELECT EXISTS (SELECT 1 FROM users WHERE email = $1)"#)
+        .bind(&email)
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+    if taken {
+        return Err((
+            StatusCode::CONFLICT,
+            "Email is already registered".to_string(),
+        ));
+    }
+
+    let user = User {
+        email: email.clone(),
+        encrypted_password: registration.password,
+        locale: Some(locale.to_string()),
+        ..User::default()
+    };
+    let user_id = create_user(&mut transaction, user.clone()).await?;
+    let confirmation_token = generate_subscription_token();
+    store_token(&mut transaction, &user_id, &confirmation_token).await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    // The account exists either way, and logging in does not wait for the
+    // confirmation.
+    if let Err((_, err)) = send_confirmation_email(&email_client, user, &confirmation_token).await {
+        tracing::warn!("Could not send the confirmation email: {}", err);
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(RegisteredAccount { id: user_id, email }),
+    ))
+}
+
+#[tracing::instrument(name = "Log in", skip(inner, client, headers, credentials))]
+pub async fn log_in(
+    State(inner): State<InnerState>,
+    client: ClientIp,
+    headers: HeaderMap,
+    Json(credentials): Json<Credentials>,
+) -> Result<Json<AccessToken>, (StatusCode, String)> {
+    let InnerState {
+        db,
+        email_client,
+        jwt_keys,
+        settings,
+        ..
+    } = inner;
+
+    let user = validate_credentials(&credentials, &db)
+        .await
+        .map_err(|err| match err {
+            AuthError::InvalidCredentials(_) => (
+                StatusCode::UNAUTHORIZED,
+                "Authentication failed".to_string(),
+            ),
+            AuthError::UnexpectedError(err) => internal_error(&*err),
+        })?;
+    let user_id = user.id.clone().unwrap_or_default();
+
+    let device = SessionDevice::new(&settings, &client, &headers);
+    let session = start_session(&db, &user_id, settings.refresh_token_lifetime, &device)
+        .await
+        .map_err(internal_error)?;
+    if session.new_device {
+        send_new_device_alert(
+            db,
+            email_client,
+            &settings,
+            &user,
+            &session.session_id,
+            device,
+        );
+    }
+
+    issue_access_token(&jwt_keys, &settings, &user, session).await
+}
+
+#[tracing::instrument(name = "Refresh session", skip(inner, request))]
+pub async fn refresh_session(
+    State(inner): State<InnerState>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<Json<AccessToken>, (StatusCode, String)> {
+    let InnerState {
+        db,
+        jwt_keys,
+        settings,
+        ..
+    } = inner;
+    let unauthorized = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            "Invalid refresh token".to_string(),
+        )
+    };
+
+    let session =
+        rotate_refresh_token(&db, &request.refresh_token, settings.refresh_token_lifetime)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(unauthorized)?;
+
+    // Read again, so a changed role or a suspension applies from the next
+    // access token on.
+    let user =
+        sqlx::query_as::<_, User>(r#"SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL"#)
+            .bind(&session.user_id)
+            .fetch_optional(&db)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(unauthorized)?;
+    if require_not_suspended(&user).is_err() {
+        end_session(&db, &session.refresh_token)
+            .await
+            .map_err(internal_error)?;
+        return Err(unauthorized());
+    }
+
+    issue_access_token(&jwt_keys, &settings, &user, session).await
+}
+
+/// End the session of the refresh token, which also stops the access
+/// tokens issued for it from working.
+#[tracing::instrument(name = "Log out", skip(inner, request))]
+pub async fn log_out(
+    State(inner): State<InnerState>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    end_session(&db, &request.refresh_token)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sign an access token for the session and answer with it and the
+/// session's refresh token.
+pub async fn issue_access_token(
+    jwt_keys: &JwtKeys,
+    settings: &Settings,
+    user: &User,
+    session: SessionToken,
+) -> Result<Json<AccessToken>, (StatusCode, String)> {
+    let claims = Claims::for_session(
+        &user.email,
+        user.role.as_deref().unwrap_or("user"),
+        &session.session_id,
+        settings.access_token_lifetime,
+    );
+    let token = jwt_keys.sign(&claims).await?;
+
+    Ok(Json(AccessToken {
+        token,
+        refresh_token: session.refresh_token,
+        expires_in: settings.access_token_lifetime.as_secs(),
+    }))
+}
@@ -0,0 +1,497 @@
+use crate::authentication::{revoke_user_sessions, AdminUser};
+use crate::casing::Json;
+use crate::db::fault_injection::{self, Faults};
+use crate::db::links;
+use crate::db::slow_queries::{self, SlowQueryStats};
+use crate::integrity::IntegrityReport;
+use crate::pagination::{Page, Pagination};
+use crate::read_only::ReadOnlyStatus;
+use crate::routes::{OUTBOX_DISPATCHER_TASK, SCHEDULED_CHANGES_JOB};
+use crate::slo::{self, SloReport};
+use crate::task_health::{self, TaskStatus};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgExecutor};
+
+const DEFAULT_AUDIT_LIMIT: i64 = 50;
+const MAX_AUDIT_LIMIT: i64 = 500;
+
+const DEFAULT_SLOW_QUERY_LIMIT: i64 = 20;
+const MAX_SLOW_QUERY_LIMIT: i64 = 100;
+
+const MAX_READ_ONLY_REASON_LENGTH: usize = 500;
+
+const DEFAULT_PREWARM_LIMIT: i64 = 1_000;
+const MAX_PREWARM_LIMIT: i64 = 10_000;
+
+const DEFAULT_PREWARM_HOURS: i32 = 24;
+const MAX_PREWARM_HOURS: i32 = 7 * 24;
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminAuditEntry {
+    pub id: i32,
+    pub created_at: Option<NaiveDateTime>,
+    pub actor: String,
+    pub ip: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminAuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityCheckOptions {
+    /// Repair what the checks find, lifting the refusal of writes.
+    #[serde(default)]
+    pub repair: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachePrewarmOptions {
+    /// How many of the busiest links to cache.
+    pub limit: Option<i64>,
+    /// How far back clicks are counted to rank links.
+    pub hours: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachePrewarmReport {
+    /// Links now in the redirect cache.
+    pub cached: usize,
+    /// Links left out for being served from the redirect snapshot already.
+    pub in_snapshot: usize,
+    /// Whether every replica got the links, or only the one answering.
+    pub shared: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSuspension {
+    /// Suspend until this instant, or lift the suspension when absent.
+    pub banned_until: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadOnlyChange {
+    pub enabled: bool,
+    /// Shown to callers whose writes are refused.
+    pub reason: Option<String>,
+}
+
+impl Validate for ReadOnlyChange {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if let Some(reason) = &self.reason {
+            errors.require_not_blank("reason", reason);
+            errors.require_max_length("reason", reason, MAX_READ_ONLY_REASON_LENGTH);
+        }
+    }
+}
+
+impl Validate for UserSuspension {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if let Some(banned_until) = self.banned_until {
+            errors.require_future("bannedUntil", banned_until);
+        }
+    }
+}
+
+/// Append an entry to the admin audit log. Pass a transaction as the
+/// executor to make the entry part of the change it describes.
+#[tracing::instrument(name = "Record admin action", skip(executor, admin, before, after))]
+pub async fn record_admin_action<'e, E: PgExecutor<'e>>(
+    executor: E,
+    admin: &AdminUser,
+    action: &str,
+    target: Option<&str>,
+    before: Option<Value>,
+    after: Option<Value>,
+) -> Result<(), (StatusCode, String)> {
+    sqlx::query(
+        r#"INSERT INTO admin_audit (actor, ip, action, target, before, after) VALUES ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(&admin.claims.sub)
+    .bind(&admin.ip)
+    .bind(action)
+    .bind(target)
+    .bind(before)
+    .bind(after)
+    .execute(executor)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(())
+}
+
+pub async fn list_admin_audit(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+    Query(query): Query<AdminAuditQuery>,
+    pagination: Pagination,
+) -> Result<Json<Page<AdminAuditEntry>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let before_id = pagination.cursor::<i32>()?;
+    let limit = pagination.limit(DEFAULT_AUDIT_LIMIT, MAX_AUDIT_LIMIT);
+
+    let fetch_audit_timeout = tokio::time::Duration::from_millis(1000);
+
+    let entries = tokio::time::timeout(
+        fetch_audit_timeout,
+        sqlx::query_as::<_, AdminAuditEntry>(
+            r#"SELECT * FROM admin_audit
+            WHERE ($1::text IS NULL OR actor = $1)
+            AND ($2::text IS NULL OR action = $2)
+            AND ($3::text IS NULL OR target = $3)
+            AND ($4::int IS NULL OR id < $4)
+            ORDER BY id DESC
+            LIMIT $5"#,
+        )
+        .bind(query.actor)
+        .bind(query.action)
+        .bind(query.target)
+        .bind(before_id)
+        .bind(limit)
+        .fetch_all(&db),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    Ok(Json(Page::after_last(entries, limit, |last| last.id)))
+}
+
+/// The queries this replica recently found slowest, to guide index work.
+/// Other replicas keep their own.
+pub async fn list_slow_queries(
+    _admin: AdminUser,
+    pagination: Pagination,
+) -> Result<Json<Page<SlowQueryStats>>, (StatusCode, String)> {
+    let limit = pagination.limit(DEFAULT_SLOW_QUERY_LIMIT, MAX_SLOW_QUERY_LIMIT);
+
+    Ok(Json(Page {
+        items: slow_queries::worst_offenders(limit as usize),
+        next_cursor: None,
+        total: None,
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FaultInjectionStatus {
+    /// Whether this build has the fault-injection feature.
+    pub available: bool,
+    #[serde(flatten)]
+    pub faults: Faults,
+}
+
+impl Validate for Faults {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        for (field, probability) in [
+            ("delayProbability", self.delay_probability),
+            ("timeoutProbability", self.timeout_probability),
+            ("errorProbability", self.error_probability),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                errors.add(field, "must be between 0 and 1");
+            }
+        }
+    }
+}
+
+/// The faults this replica injects into repository calls.
+pub async fn fault_injection(
+    _admin: AdminUser,
+) -> Result<Json<FaultInjectionStatus>, (StatusCode, String)> {
+    Ok(Json(FaultInjectionStatus {
+        available: fault_injection::ENABLED,
+        faults: fault_injection::current(),
+    }))
+}
+
+/// Change the faults this replica injects, all zero to stop. Other
+/// replicas keep theirs.
+#[tracing::instrument(name = "Set fault injection", skip(inner, admin))]
+pub async fn set_fault_injection(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Valid(faults): Valid<Faults>,
+) -> Result<Json<FaultInjectionStatus>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let before = fault_injection::current();
+    fault_injection::set(faults.clone()).map_err(|err| (StatusCode::CONFLICT, err.to_string()))?;
+
+    record_admin_action(
+        &db,
+        &admin,
+        "fault_injection.set",
+        None,
+        Some(serde_json::to_value(&before).map_err(internal_error)?),
+        Some(serde_json::to_value(&faults).map_err(internal_error)?),
+    )
+    .await?;
+
+    Ok(Json(FaultInjectionStatus {
+        available: fault_injection::ENABLED,
+        faults,
+    }))
+}
+
+/// How redirects served by this replica are doing against their latency
+/// SLO, and the burn rate alerts. Other replicas keep their own.
+pub async fn redirect_slo(_admin: AdminUser) -> Result<Json<SloReport>, (StatusCode, String)> {
+    slo::report().map(Json).ok_or_else(|| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The redirect SLO is not set up".to_string(),
+        )
+    })
+}
+
+/// The background loops of this replica and how they are doing. Jobs only
+/// run on the leader, so other replicas report them idle but not stuck.
+pub async fn list_background_tasks(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Result<Json<Page<TaskStatus>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let (outbox_depth, due_changes): (i64, i64) = sqlx::query_as(
+        r#"SELECT (SELECT count(*) FROM outbox_events),
+        (SELECT count(*) FROM link_scheduled_changes
+            WHERE applied_at IS NULL AND refused_reason IS NULL
+            AND change_at <= localtimestamp)"#,
+    )
+    .fetch_one(&db)
+    .await
+    .map_err(internal_error)?;
+
+    let mut tasks = task_health::statuses();
+    for task in &mut tasks {
+        match task.name {
+            OUTBOX_DISPATCHER_TASK => task.queue_depth = Some(outbox_depth),
+            SCHEDULED_CHANGES_JOB => task.queue_depth = Some(due_changes),
+            _ => {}
+        }
+    }
+
+    Ok(Json(Page::complete(tasks)))
+}
+
+/// What the latest integrity check found, and whether writes are refused
+/// because of it.
+pub async fn integrity_report(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Json<IntegrityReport> {
+    Json(inner.integrity.report())
+}
+
+/// Check integrity again, repairing what is found when asked to. Only this
+/// replica's report is updated; others keep refusing writes until checked
+/// or restarted.
+#[tracing::instrument(name = "Check integrity", skip(inner, admin))]
+pub async fn check_integrity(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Query(options): Query<IntegrityCheckOptions>,
+) -> Result<Json<IntegrityReport>, (StatusCode, String)> {
+    let InnerState { db, integrity, .. } = inner;
+
+    let report = integrity
+        .recheck(&db, options.repair)
+        .await
+        .map_err(internal_error)?;
+
+    if options.repair {
+        record_admin_action(
+            &db,
+            &admin,
+            "integrity.repair",
+            None,
+            None,
+            Some(serde_json::to_value(&report).map_err(internal_error)?),
+        )
+        .await?;
+    }
+
+    Ok(Json(report))
+}
+
+/// Put the targets of the busiest links in the redirect cache, so a fresh
+/// deploy does not send their redirects to the database until each is
+/// looked up once. Without Redis only this replica's cache is filled.
+#[tracing::instrument(name = "Prewarm redirect cache", skip(inner, _admin))]
+pub async fn prewarm_redirect_cache(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+    Query(options): Query<CachePrewarmOptions>,
+) -> Result<Json<CachePrewarmReport>, (StatusCode, String)> {
+    let InnerState {
+        db,
+        redirects,
+        redirect_cache,
+        ..
+    } = inner;
+
+    if !redirect_cache.is_enabled() {
+        return Err((
+            StatusCode::CONFLICT,
+            "No redirect cache is configured".to_string(),
+        ));
+    }
+
+    let limit = options
+        .limit
+        .unwrap_or(DEFAULT_PREWARM_LIMIT)
+        .clamp(1, MAX_PREWARM_LIMIT);
+    let hours = options
+        .hours
+        .unwrap_or(DEFAULT_PREWARM_HOURS)
+        .clamp(1, MAX_PREWARM_HOURS);
+
+    let targets = links::busiest_redirect_targets(&db, hours, limit)
+        .await
+        .map_err(internal_error)?;
+
+    let mut report = CachePrewarmReport {
+        cached: 0,
+        in_snapshot: 0,
+        shared: redirect_cache.is_shared(),
+    };
+    // Least busy first, so a full in-memory cache evicts those.
+    for target in targets.iter().rev() {
+        if redirects.lookup(&target.id).is_some() {
+            report.in_snapshot += 1;
+        } else {
+            redirect_cache.put(target).await;
+            report.cached += 1;
+        }
+    }
+
+    tracing::info!(
+        "Cached the redirect targets of {} busy links",
+        report.cached
+    );
+
+    Ok(Json(report))
+}
+
+pub async fn read_only_status(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Json<ReadOnlyStatus> {
+    Json(inner.read_only.status())
+}
+
+/// Switch read-only mode on or off. Only this replica is switched; others
+/// keep their mode until switched or restarted.
+#[tracing::instrument(name = "Set read-only mode", skip(inner, admin))]
+pub async fn set_read_only(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Valid(change): Valid<ReadOnlyChange>,
+) -> Result<Json<ReadOnlyStatus>, (StatusCode, String)> {
+    let InnerState { db, read_only, .. } = inner;
+
+    let before = read_only.set(change.enabled, change.reason, &admin.claims.sub);
+    let after = read_only.status();
+    if after.enabled {
+        tracing::warn!("{} made the API read-only", admin.claims.sub);
+    } else {
+        tracing::warn!("{} lifted read-only mode", admin.claims.sub);
+    }
+
+    // Recording fails when read-only mode is on because the database is, so
+    // the switch stands regardless.
+    if let Err(err) = record_admin_action(
+        &db,
+        &admin,
+        "read_only.set",
+        None,
+        Some(serde_json::to_value(&before).map_err(internal_error)?),
+        Some(serde_json::to_value(&after).map_err(internal_error)?),
+    )
+    .await
+    {
+        tracing::error!("Could not audit switching read-only mode: {}", err.1);
+    }
+
+    Ok(Json(after))
+}
+
+#[tracing::instrument(name = "Suspend user", skip(inner, admin, suspension))]
+pub async fn suspend_user(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(user_id): Path<String>,
+    Valid(suspension): Valid<UserSuspension>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let before: Option<String> =
+        sqlx::query_scalar(r#"SELECT banned_until FROM users WHERE id = $1 FOR UPDATE"#)
+            .bind(&user_id)
+            .fetch_optional(&mut *transaction)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let after = suspension.banned_until.map(|until| until.to_string());
+
+    sqlx::query(
+        r#"UPDATE users SET banned_until = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2"#,
+    )
+    .bind(&after)
+    .bind(&user_id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    // Refreshing already fails for suspended users, but access tokens
+    // issued before would keep working until they expire.
+    let suspended = suspension
+        .banned_until
+        .is_some_and(|until| until > chrono::Utc::now().naive_utc());
+    if suspended {
+        revoke_user_sessions(&mut *transaction, &user_id)
+            .await
+            .map_err(internal_error)?;
+    }
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "user.suspend",
+        Some(&user_id),
+        Some(serde_json::json!({ "bannedUntil": before })),
+        Some(serde_json::json!({ "bannedUntil": after })),
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(Json("OK".to_owned()))
+}
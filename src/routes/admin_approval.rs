@@ -0,0 +1,302 @@
+use crate::authentication::AdminUser;
+use crate::casing::Json;
+use crate::db::links;
+use crate::link_changes::forget_link;
+use crate::pagination::Page;
+use crate::routes::record_admin_action;
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Json as SqlJson;
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// How long a requested action waits for a second admin before it lapses.
+const PENDING_ACTION_TTL_HOURS: i32 = 24;
+
+/// Operations dangerous enough to need a second admin's approval before
+/// they run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum DestructiveAction {
+    #[serde(rename_all = "camelCase")]
+    PurgeLinkStatistics { link_id: String },
+    /// Erase a link with its statistics, freeing its id.
+    #[serde(rename_all = "camelCase")]
+    HardDeleteLink { link_id: String },
+}
+
+impl DestructiveAction {
+    fn name(&self) -> &'static str {
+        match self {
+            DestructiveAction::PurgeLinkStatistics { .. } => "linkStatistics.purge",
+            DestructiveAction::HardDeleteLink { .. } => "link.hard_delete",
+        }
+    }
+
+    fn target(&self) -> &str {
+        match self {
+            DestructiveAction::PurgeLinkStatistics { link_id }
+            | DestructiveAction::HardDeleteLink { link_id } => link_id,
+        }
+    }
+
+    async fn execute(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> Result<Value, (StatusCode, String)> {
+        match self {
+            DestructiveAction::PurgeLinkStatistics { link_id } => {
+                let deleted = sqlx::query(r#"DELETE FROM link_statistics WHERE link_id = $1"#)
+                    .bind(link_id)
+                    .execute(&mut **transaction)
+                    .await
+                    .map_err(internal_error)?
+                    .rows_affected();
+
+                // After the raw clicks, whose locks wait out a rollup in progress.
+                sqlx::query(r#"DELETE FROM link_statistics_daily WHERE link_id = $1"#)
+                    .bind(link_id)
+                    .execute(&mut **transaction)
+                    .await
+                    .map_err(internal_error)?;
+
+                Ok(serde_json::json!({ "deletedRows": deleted }))
+            }
+            DestructiveAction::HardDeleteLink { link_id } => {
+                let before = sqlx::query_scalar::<_, Value>(
+                    r#"SELECT to_jsonb(links) FROM links WHERE id = $1 FOR UPDATE"#,
+                )
+                .bind(link_id)
+                .fetch_optional(&mut **transaction)
+                .await
+                .map_err(internal_error)?
+                .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+                links::hard_delete(transaction, std::slice::from_ref(link_id))
+                    .await
+                    .map_err(|err| match err.as_database_error() {
+                        Some(err) if err.is_foreign_key_violation() => (
+                            StatusCode::CONFLICT,
+                            "Link is used by a group playlist".to_string(),
+                        ),
+                        _ => internal_error(err),
+                    })?;
+
+                Ok(serde_json::json!({ "deletedLink": before }))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingAction {
+    pub id: String,
+    pub action: SqlJson<DestructiveAction>,
+    pub status: String,
+    pub requested_by: String,
+    pub requested_at: Option<NaiveDateTime>,
+    pub expires_at: NaiveDateTime,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<NaiveDateTime>,
+    pub result: Option<Value>,
+}
+
+#[derive(Deserialize)]
+pub struct PendingActionRequest {
+    pub action: DestructiveAction,
+}
+
+#[derive(Deserialize)]
+pub struct PendingActionQuery {
+    pub status: Option<String>,
+}
+
+#[tracing::instrument(name = "Request destructive action", skip(inner, admin, request))]
+pub async fn request_pending_action(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Json(request): Json<PendingActionRequest>,
+) -> Result<(StatusCode, Json<PendingAction>), (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let pending = queue_pending_action(&db, &admin, &request.action).await?;
+
+    Ok((StatusCode::ACCEPTED, Json(pending)))
+}
+
+/// Queue an action for a second admin to approve.
+pub async fn queue_pending_action(
+    db: &PgPool,
+    admin: &AdminUser,
+    action: &DestructiveAction,
+) -> Result<PendingAction, (StatusCode, String)> {
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let pending = sqlx::query_as::<_, PendingAction>(
+        r#"INSERT INTO admin_pending_actions (id, action, requested_by, expires_at)
+        VALUES ($1, $2, $3, CURRENT_TIMESTAMP + make_interval(hours => $4)) returning *"#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(SqlJson(action))
+    .bind(&admin.claims.sub)
+    .bind(PENDING_ACTION_TTL_HOURS)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    record_admin_action(
+        &mut *transaction,
+        admin,
+        "pendingAction.request",
+        Some(&pending.id),
+        None,
+        Some(serde_json::json!(action)),
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(pending)
+}
+
+pub async fn list_pending_actions(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+    Query(query): Query<PendingActionQuery>,
+) -> Result<Json<Page<PendingAction>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    expire_pending_actions(&db).await?;
+
+    let pending = sqlx::query_as::<_, PendingAction>(
+        r#"SELECT * FROM admin_pending_actions WHERE status = $1 ORDER BY requested_at DESC"#,
+    )
+    .bind(query.status.unwrap_or_else(|| "pending".to_string()))
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(pending)))
+}
+
+#[tracing::instrument(name = "Approve destructive action", skip(inner, admin))]
+pub async fn approve_pending_action(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(id): Path<String>,
+) -> Result<Json<PendingAction>, (StatusCode, String)> {
+    decide_pending_action(inner, admin, id, true).await
+}
+
+#[tracing::instrument(name = "Deny destructive action", skip(inner, admin))]
+pub async fn deny_pending_action(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(id): Path<String>,
+) -> Result<Json<PendingAction>, (StatusCode, String)> {
+    decide_pending_action(inner, admin, id, false).await
+}
+
+async fn decide_pending_action(
+    inner: InnerState,
+    admin: AdminUser,
+    id: String,
+    approve: bool,
+) -> Result<Json<PendingAction>, (StatusCode, String)> {
+    let InnerState {
+        db,
+        redirect_cache,
+        redirects,
+        ..
+    } = inner;
+
+    expire_pending_actions(&db).await?;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let pending = sqlx::query_as::<_, PendingAction>(
+        r#"SELECT * FROM admin_pending_actions WHERE id = $1 FOR UPDATE"#,
+    )
+    .bind(&id)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    if pending.status != "pending" {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Action is already {}", pending.status),
+        ));
+    }
+
+    if approve && pending.requested_by == admin.claims.sub {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "A different admin must approve this action".to_string(),
+        ));
+    }
+
+    let (status, result) = if approve {
+        let result = pending.action.execute(&mut transaction).await?;
+        ("approved", Some(result))
+    } else {
+        ("denied", None)
+    };
+
+    let decided = sqlx::query_as::<_, PendingAction>(
+        r#"UPDATE admin_pending_actions
+        SET status = $1, decided_by = $2, decided_at = CURRENT_TIMESTAMP, result = $3
+        WHERE id = $4 returning *"#,
+    )
+    .bind(status)
+    .bind(&admin.claims.sub)
+    .bind(&result)
+    .bind(&id)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    let audit_action = if approve {
+        pending.action.name()
+    } else {
+        "pendingAction.deny"
+    };
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        audit_action,
+        Some(pending.action.target()),
+        Some(serde_json::json!({ "pendingActionId": id, "requestedBy": pending.requested_by })),
+        result,
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    if let (true, DestructiveAction::HardDeleteLink { link_id }) = (approve, &pending.action.0) {
+        forget_link(&db, &redirects, &redirect_cache, link_id).await;
+    }
+
+    Ok(Json(decided))
+}
+
+async fn expire_pending_actions(db: &sqlx::PgPool) -> Result<(), (StatusCode, String)> {
+    sqlx::query(
+        r#"UPDATE admin_pending_actions SET status = 'expired'
+        WHERE status = 'pending' AND expires_at < CURRENT_TIMESTAMP"#,
+    )
+    .execute(db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(())
+}
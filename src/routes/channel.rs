@@ -1,25 +1,18 @@
+use crate::casing::Json;
+use crate::pagination::Page;
 use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
 
-use anyhow::{Context, Result};
 use axum::extract::{Path, State};
-use axum::http::{HeaderMap, Response, StatusCode};
-use axum::Json;
-use chrono::{DateTime, Local, NaiveDateTime, Utc};
-use once_cell::sync::Lazy;
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
-use serde::{Deserialize, Serialize};
+use axum::http::{HeaderMap, StatusCode};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
 use serde_json::to_string_pretty;
-use sha3::Digest;
-use sqlx::{Executor, FromRow, PgPool, Postgres, Row, Transaction};
-use std::collections::HashMap;
-use tokio::sync::RwLock;
+use sqlx::FromRow;
 use uuid::Uuid;
 
 use crate::InnerState;
 
-use crate::email::{EmailClient, SendEmailRequest};
-
 #[derive(serde::Serialize, Deserialize, FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct Channel {
@@ -33,11 +26,18 @@ pub struct Channel {
     pub user_id: String,
 }
 
+impl Validate for Channel {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_not_blank("name", &self.name);
+        errors.require_max_length("name", &self.name, 100);
+    }
+}
+
 pub async fn all_channels(
     State(inner): State<InnerState>,
     headers: HeaderMap,
     Path(user_id): Path<String>,
-) -> Result<Json<Vec<Channel>>, (StatusCode, String)> {
+) -> Result<Json<Page<Channel>>, (StatusCode, String)> {
     let fetch_channels_timeout = tokio::time::Duration::from_millis(1000);
     let InnerState { db, .. } = inner;
 
@@ -58,12 +58,12 @@ pub async fn all_channels(
     .map_err(internal_error)?
     .map_err(internal_error)?;
 
-    Ok(Json(channels))
+    Ok(Json(Page::complete(channels)))
 }
 
 pub async fn create_channel(
     State(inner): State<InnerState>,
-    Json(channel): Json<Channel>,
+    Valid(channel): Valid<Channel>,
 ) -> Result<Json<Channel>, (StatusCode, String)> {
     let InnerState { db, .. } = inner;
 
@@ -0,0 +1,252 @@
+use crate::db::links::{NewLink, RedirectBehavior};
+use crate::routes::{cached_click_count, generate_id, insert_checked_link};
+use crate::utils::internal_error;
+use crate::validation::ValidationErrors;
+use crate::InnerState;
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use ring::{hmac, signature};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Requests signed longer ago than this are treated as replays.
+const SIGNATURE_MAX_AGE_SECONDS: i64 = 5 * 60;
+
+/// Discord interaction types and response types we handle.
+const DISCORD_PING: u8 = 1;
+const DISCORD_APPLICATION_COMMAND: u8 = 2;
+const DISCORD_PONG: u8 = 1;
+const DISCORD_CHANNEL_MESSAGE: u8 = 4;
+/// Only show the reply to the user who ran the command.
+const DISCORD_EPHEMERAL_FLAG: u32 = 1 << 6;
+
+#[derive(Deserialize)]
+pub struct DiscordInteraction {
+    #[serde(rename = "type")]
+    pub kind: u8,
+    pub data: Option<DiscordCommand>,
+}
+
+#[derive(Deserialize)]
+pub struct DiscordCommand {
+    pub name: String,
+    #[serde(default)]
+    pub options: Vec<DiscordOption>,
+}
+
+#[derive(Deserialize)]
+pub struct DiscordOption {
+    pub name: String,
+    pub value: Value,
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn unauthorized() -> (StatusCode, String) {
+    (
+        StatusCode::UNAUTHORIZED,
+        "Invalid request signature".to_string(),
+    )
+}
+
+fn not_configured(platform: &str) -> (StatusCode, String) {
+    (
+        StatusCode::NOT_IMPLEMENTED,
+        format!("{} integration is not configured", platform),
+    )
+}
+
+fn check_timestamp(timestamp: &str) -> Result<(), (StatusCode, String)> {
+    let timestamp: i64 = timestamp.parse().map_err(|_| unauthorized())?;
+
+    if (chrono::Utc::now().timestamp() - timestamp).abs() > SIGNATURE_MAX_AGE_SECONDS {
+        return Err(unauthorized());
+    }
+
+    Ok(())
+}
+
+/// Verify Slack's `v0` HMAC-SHA256 signature over the timestamp and raw body.
+fn verify_slack_signature(
+    signing_secret: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), (StatusCode, String)> {
+    let timestamp = header(headers, "x-slack-request-timestamp").ok_or_else(unauthorized)?;
+    let signature = header(headers, "x-slack-signature")
+        .and_then(|signature| signature.strip_prefix("v0="))
+        .and_then(|signature| hex::decode(signature).ok())
+        .ok_or_else(unauthorized)?;
+
+    check_timestamp(timestamp)?;
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, signing_secret.as_bytes());
+    let mut message = format!("v0:{}:", timestamp).into_bytes();
+    message.extend_from_slice(body);
+
+    hmac::verify(&key, &message, &signature).map_err(|_| unauthorized())
+}
+
+/// Verify Discord's Ed25519 signature over the timestamp and raw body.
+fn verify_discord_signature(
+    public_key: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), (StatusCode, String)> {
+    let timestamp = header(headers, "x-signature-timestamp").ok_or_else(unauthorized)?;
+    let signature = header(headers, "x-signature-ed25519")
+        .and_then(|signature| hex::decode(signature).ok())
+        .ok_or_else(unauthorized)?;
+    let public_key = hex::decode(public_key).map_err(internal_error)?;
+
+    check_timestamp(timestamp)?;
+
+    let mut message = timestamp.as_bytes().to_vec();
+    message.extend_from_slice(body);
+
+    signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+        .verify(&message, &signature)
+        .map_err(|_| unauthorized())
+}
+
+/// Run `shorten` or `stats` and describe the outcome in a chat friendly way.
+/// Chat users are not tied to accounts, so their links are anonymous, as
+/// those created through the API without signing in.
+async fn run_chat_command(
+    inner: &InnerState,
+    command: &str,
+    argument: &str,
+) -> Result<String, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    match command {
+        "shorten" => {
+            let mut errors = ValidationErrors::default();
+            errors.require_link_target("url", argument);
+            if !errors.is_empty() {
+                return Ok(format!("`{}` is not a valid web URL", argument));
+            }
+
+            let mut transaction = db.begin().await.map_err(internal_error)?;
+
+            let created = insert_checked_link(
+                &mut transaction,
+                inner,
+                &NewLink {
+                    id: &generate_id(),
+                    target_url: argument,
+                    organization_id: None,
+                    owner_email: None,
+                    expires_at: None,
+                    payload: None,
+                    draft: false,
+                    domain: None,
+                    behavior: &RedirectBehavior::default(),
+                    tags: &[],
+                    campaign: None,
+                },
+            )
+            .await;
+            let created = match created {
+                Ok(created) => created,
+                Err((status, message)) if status.is_client_error() => return Ok(message),
+                Err(err) => return Err(err),
+            };
+
+            transaction.commit().await.map_err(internal_error)?;
+
+            let link = created.committed(inner);
+
+            Ok(format!(
+                "{}/{} now redirects to {}",
+                settings.public_base_url, link.id, link.target_url
+            ))
+        }
+        // Anyone in the workspace may ask, so only as much as the public
+        // badge shows: clicks of active links.
+        "stats" => match cached_click_count(db, argument).await {
+            Ok(clicks) => Ok(format!(
+                "{}/{} has been opened {} times",
+                settings.public_base_url, argument, clicks
+            )),
+            Err((StatusCode::NOT_FOUND, _)) => Ok(format!("No link with id `{}`", argument)),
+            Err(err) => Err(err),
+        },
+        _ => Ok(format!(
+            "Unknown command `{}`, try `shorten <url>` or `stats <id>`",
+            command
+        )),
+    }
+}
+
+#[tracing::instrument(name = "Slack slash command", skip(inner, headers, body))]
+pub async fn slack_command(
+    State(inner): State<InnerState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let signing_secret = inner
+        .settings
+        .slack_signing_secret
+        .as_deref()
+        .ok_or_else(|| not_configured("Slack"))?;
+    verify_slack_signature(signing_secret, &headers, &body)?;
+
+    let (mut command, mut text) = (String::new(), String::new());
+    for (key, value) in url::form_urlencoded::parse(&body) {
+        match key.as_ref() {
+            "command" => command = value.into_owned(),
+            "text" => text = value.into_owned(),
+            _ => {}
+        }
+    }
+
+    let reply = run_chat_command(&inner, command.trim_start_matches('/'), text.trim()).await?;
+
+    Ok(Json(json!({ "response_type": "ephemeral", "text": reply })))
+}
+
+#[tracing::instrument(name = "Discord interaction", skip(inner, headers, body))]
+pub async fn discord_interaction(
+    State(inner): State<InnerState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let public_key = inner
+        .settings
+        .discord_public_key
+        .as_deref()
+        .ok_or_else(|| not_configured("Discord"))?;
+    verify_discord_signature(public_key, &headers, &body)?;
+
+    let interaction: DiscordInteraction = serde_json::from_slice(&body)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Malformed interaction".to_string()))?;
+
+    match (interaction.kind, interaction.data) {
+        (DISCORD_PING, _) => Ok(Json(json!({ "type": DISCORD_PONG }))),
+        (DISCORD_APPLICATION_COMMAND, Some(command)) => {
+            let argument = command
+                .options
+                .iter()
+                .find(|option| matches!(option.name.as_str(), "url" | "id"))
+                .and_then(|option| option.value.as_str())
+                .unwrap_or_default();
+
+            let reply = run_chat_command(&inner, &command.name, argument.trim()).await?;
+
+            Ok(Json(json!({
+                "type": DISCORD_CHANNEL_MESSAGE,
+                "data": { "content": reply, "flags": DISCORD_EPHEMERAL_FLAG },
+            })))
+        }
+        _ => Err((
+            StatusCode::BAD_REQUEST,
+            "Unsupported interaction".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,130 @@
+use crate::configuration::Settings;
+use crate::routes::{render_link_page, PageKind};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::Form;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub const CONSENT_COOKIE: &str = "groupify_consent";
+
+/// One year, in seconds.
+const CONSENT_COOKIE_MAX_AGE: u32 = 365 * 24 * 60 * 60;
+
+/// A visitor's answer to the tracking consent interstitial.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackingConsent {
+    /// Store referer and user agent alongside the click.
+    Granted,
+    /// Count the click but store nothing about the visitor.
+    Denied,
+}
+
+#[derive(Deserialize)]
+pub struct ConsentForm {
+    pub decision: String,
+}
+
+/// Read the visitor id and their recorded decision from the consent cookie.
+pub fn consent_from_cookie(headers: &HeaderMap) -> Option<(String, TrackingConsent)> {
+    let value = headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == CONSENT_COOKIE)
+        .map(|(_, value)| value)?;
+
+    let (visitor_id, decision) = value.rsplit_once('.')?;
+    let consent = match decision {
+        "1" => TrackingConsent::Granted,
+        "0" => TrackingConsent::Denied,
+        _ => return None,
+    };
+
+    Some((visitor_id.to_string(), consent))
+}
+
+/// The page shown instead of redirecting when the visitor has not yet
+/// answered the consent question.
+pub async fn consent_interstitial(
+    db: &PgPool,
+    settings: &Settings,
+    headers: &HeaderMap,
+    link_id: &str,
+) -> Response {
+    let action = format!("/{}/consent", link_id);
+
+    render_link_page(
+        db,
+        settings,
+        headers,
+        PageKind::Interstitial,
+        link_id,
+        &[("consent_action", &action), ("link_id", link_id)],
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Record tracking consent", skip(inner, headers, form))]
+pub async fn record_consent(
+    State(inner): State<InnerState>,
+    Path(link_id): Path<String>,
+    headers: HeaderMap,
+    Form(form): Form<ConsentForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let consent = match form.decision.as_str() {
+        "accept" => TrackingConsent::Granted,
+        "decline" => TrackingConsent::Denied,
+        _ => return Err((StatusCode::BAD_REQUEST, "Unknown decision".to_string())),
+    };
+
+    let visitor_id = consent_from_cookie(&headers)
+        .map(|(visitor_id, _)| visitor_id)
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+
+    let recorded = sqlx::query(
+        r#"INSERT INTO consents (visitor_id, link_id, granted)
+        SELECT $1, id, $3 FROM links WHERE id = $2"#,
+    )
+    .bind(&visitor_id)
+    .bind(&link_id)
+    .bind(consent == TrackingConsent::Granted)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?;
+
+    if recorded.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    let cookie = format!(
+        "{}={}.{}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        CONSENT_COOKIE,
+        visitor_id,
+        if consent == TrackingConsent::Granted {
+            "1"
+        } else {
+            "0"
+        },
+        CONSENT_COOKIE_MAX_AGE
+    );
+
+    // Send the visitor back through `redirect`, which now finds the cookie
+    // and records the click according to their decision.
+    Ok(Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header("Location", format!("/{}", link_id))
+        .header("Set-Cookie", cookie)
+        .body(Body::empty())
+        .expect("This response should always be constructable"))
+}
@@ -0,0 +1,518 @@
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::configuration::Settings;
+use crate::db::links::RedirectBehavior;
+use crate::dns::{self, RecordType};
+use crate::jobs;
+use crate::pagination::Page;
+use crate::routes::{generate_subscription_token, is_domain_name, require_organization_role};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, PgPool};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often pending domains are checked.
+const VERIFICATION_JOB_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a domain stays pending before verification fails and has to be
+/// started again.
+const VERIFICATION_WINDOW_HOURS: i32 = 72;
+
+/// Pending domains checked per run, the ones checked longest ago first.
+const VERIFICATION_BATCH_SIZE: i64 = 100;
+
+/// Label below the domain holding the verification record.
+const VERIFICATION_LABEL: &str = "_groupify-verification";
+
+const VERIFICATION_METHODS: [&str; 2] = ["txt", "cname"];
+
+const CUSTOM_DOMAIN_COLUMNS: &str = r#"domain, organization_id, method, verification_token, status,
+    verification_error, verification_started_at, checked_at, verified_at, created_at, updated_at,
+    redirect_status, cache_ttl_seconds, fallback_url, interstitial"#;
+
+/// A domain an organization serves its links on. It is `pending` until its
+/// verification record is found, then `active`, or `failed` when none shows
+/// up in time.
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomDomain {
+    pub domain: String,
+    pub organization_id: String,
+    pub method: String,
+    #[serde(skip)]
+    pub verification_token: String,
+    pub status: String,
+    /// Why the latest check did not verify the domain.
+    pub verification_error: Option<String>,
+    pub verification_started_at: Option<NaiveDateTime>,
+    pub checked_at: Option<NaiveDateTime>,
+    pub verified_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    /// Behavior of links created under the domain, where they leave it
+    /// unset.
+    #[sqlx(flatten)]
+    pub defaults: RedirectBehavior,
+    /// The record to create for verification, while it is pending.
+    #[sqlx(skip)]
+    pub record: Option<VerificationRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerificationRecord {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub name: String,
+    pub value: String,
+}
+
+impl CustomDomain {
+    /// The record that proves the organization controls the domain, `None`
+    /// for CNAME verification when no zone is configured.
+    fn expected_record(&self, settings: &Settings) -> Option<VerificationRecord> {
+        let name = format!("{}.{}", VERIFICATION_LABEL, self.domain);
+        match self.method.as_str() {
+            "cname" => Some(VerificationRecord {
+                kind: "CNAME",
+                name,
+                value: format!(
+                    "{}.{}",
+                    self.verification_token,
+                    settings.domain_verification_zone.as_deref()?
+                ),
+            }),
+            _ => Some(VerificationRecord {
+                kind: "TXT",
+                name,
+                value: format!("groupify-verification={}", self.verification_token),
+            }),
+        }
+    }
+
+    fn with_record(mut self, settings: &Settings) -> Self {
+        if self.status == "pending" {
+            self.record = self.expected_record(settings);
+        }
+        self
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DomainVerificationRequest {
+    pub domain: String,
+    #[serde(default = "default_verification_method")]
+    pub method: String,
+}
+
+fn default_verification_method() -> String {
+    "txt".to_string()
+}
+
+impl Validate for DomainVerificationRequest {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_not_blank("domain", &self.domain);
+        errors.require_max_length("domain", &self.domain, 253);
+        let domain = self.domain.to_ascii_lowercase();
+        if !is_domain_name(&domain) || !domain.contains('.') {
+            errors.add("domain", "must be a domain name like links.example.com");
+        }
+        if !VERIFICATION_METHODS.contains(&self.method.as_str()) {
+            errors.add("method", "must be txt or cname");
+        }
+    }
+}
+
+async fn find_custom_domain(
+    db: &PgPool,
+    organization_id: &str,
+    domain: &str,
+) -> Result<CustomDomain, (StatusCode, String)> {
+    sqlx::query_as::<_, CustomDomain>(&format!(
+        r#"SELECT {} FROM custom_domains WHERE domain = $1 AND organization_id = $2"#,
+        CUSTOM_DOMAIN_COLUMNS
+    ))
+    .bind(domain)
+    .bind(organization_id)
+    .fetch_optional(db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))
+}
+
+pub async fn list_custom_domains(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<Json<Page<CustomDomain>>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "member").await?;
+
+    let domains = sqlx::query_as::<_, CustomDomain>(&format!(
+        r#"SELECT {} FROM custom_domains WHERE organization_id = $1 ORDER BY domain"#,
+        CUSTOM_DOMAIN_COLUMNS
+    ))
+    .bind(&organization_id)
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?
+    .into_iter()
+    .map(|domain| domain.with_record(&settings))
+    .collect();
+
+    Ok(Json(Page::complete(domains)))
+}
+
+/// Add a domain to the organization, or verify it again when it failed. It
+/// becomes active once the returned record is found.
+#[tracing::instrument(name = "Start domain verification", skip(inner, claims, request))]
+pub async fn start_domain_verification(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+    Valid(request): Valid<DomainVerificationRequest>,
+) -> Result<Json<CustomDomain>, Response> {
+    let InnerState { db, settings, .. } = inner;
+    let domain = request.domain.to_ascii_lowercase();
+
+    require_organization_role(&db, &organization_id, &claims, "admin")
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    if request.method == "cname" && settings.domain_verification_zone.is_none() {
+        let mut errors = ValidationErrors::default();
+        errors.add("method", "cname verification is not available");
+        return Err(errors.into_response());
+    }
+
+    // A domain another organization has claimed is only taken over once its
+    // verification failed. Active domains are left as they are.
+    let claimed = sqlx::query_as::<_, CustomDomain>(&format!(
+        r#"INSERT INTO custom_domains (domain, organization_id, method, verification_token)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (domain) DO UPDATE SET organization_id = excluded.organization_id,
+            method = excluded.method,
+            verification_token = CASE WHEN custom_domains.organization_id = excluded.organization_id
+                THEN custom_domains.verification_token ELSE excluded.verification_token END,
+            status = 'pending', verification_error = NULL,
+            verification_started_at = CURRENT_TIMESTAMP, checked_at = NULL,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE custom_domains.status = 'failed'
+            OR (custom_domains.organization_id = excluded.organization_id
+                AND custom_domains.status = 'pending')
+        RETURNING {}"#,
+        CUSTOM_DOMAIN_COLUMNS
+    ))
+    .bind(&domain)
+    .bind(&organization_id)
+    .bind(&request.method)
+    .bind(generate_subscription_token().to_ascii_lowercase())
+    .fetch_optional(&db)
+    .await
+    .map_err(|err| internal_error(err).into_response())?;
+
+    let custom_domain = match claimed {
+        Some(custom_domain) => {
+            tracing::info!(
+                "Organization {} started verifying {}",
+                organization_id,
+                domain
+            );
+            custom_domain
+        }
+        None => match find_custom_domain(&db, &organization_id, &domain).await {
+            Ok(custom_domain) => custom_domain,
+            Err((StatusCode::NOT_FOUND, _)) => {
+                return Err((
+                    StatusCode::CONFLICT,
+                    "The domain belongs to another organization".to_string(),
+                )
+                    .into_response())
+            }
+            Err(err) => return Err(err.into_response()),
+        },
+    };
+
+    Ok(Json(custom_domain.with_record(&settings)))
+}
+
+/// The domain's verification status, for polling until it is active.
+pub async fn custom_domain(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, domain)): Path<(String, String)>,
+) -> Result<Json<CustomDomain>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "member").await?;
+
+    let custom_domain =
+        find_custom_domain(&db, &organization_id, &domain.to_ascii_lowercase()).await?;
+
+    Ok(Json(custom_domain.with_record(&settings)))
+}
+
+/// Replace the defaults links created under the domain start from. Links
+/// created before keep theirs.
+#[tracing::instrument(name = "Set custom domain defaults", skip(inner, claims, defaults))]
+pub async fn set_custom_domain_defaults(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, domain)): Path<(String, String)>,
+    Valid(defaults): Valid<RedirectBehavior>,
+) -> Result<Json<CustomDomain>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let custom_domain = sqlx::query_as::<_, CustomDomain>(&format!(
+        r#"UPDATE custom_domains SET redirect_status = $3, cache_ttl_seconds = $4,
+            fallback_url = $5, interstitial = $6, updated_at = CURRENT_TIMESTAMP
+        WHERE domain = $1 AND organization_id = $2
+        RETURNING {}"#,
+        CUSTOM_DOMAIN_COLUMNS
+    ))
+    .bind(domain.to_ascii_lowercase())
+    .bind(&organization_id)
+    .bind(defaults.redirect_status)
+    .bind(defaults.cache_ttl_seconds)
+    .bind(&defaults.fallback_url)
+    .bind(defaults.interstitial)
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    Ok(Json(custom_domain.with_record(&settings)))
+}
+
+/// The defaults of a domain links are being created under, which must be
+/// one of the organization's active domains.
+pub async fn active_domain_defaults(
+    db: &PgPool,
+    organization_id: &str,
+    domain: &str,
+) -> Result<RedirectBehavior, (StatusCode, String)> {
+    sqlx::query_as::<_, RedirectBehavior>(
+        r#"SELECT redirect_status, cache_ttl_seconds, fallback_url, interstitial
+        FROM custom_domains WHERE domain = $1 AND organization_id = $2 AND status = 'active'"#,
+    )
+    .bind(domain)
+    .bind(organization_id)
+    .fetch_optional(db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| {
+        (
+            StatusCode::CONFLICT,
+            "The domain is not an active domain of the organization".to_string(),
+        )
+    })
+}
+
+/// Refuse an address outside the organization's active domains and their
+/// subdomains. Identity providers and SCIM clients of an organization can
+/// claim any address, so accounts are only made for them in domains the
+/// organization proved it controls.
+pub async fn require_verified_email_domain<'e, E: PgExecutor<'e>>(
+    executor: E,
+    organization_id: &str,
+    email: &str,
+) -> Result<(), (StatusCode, String)> {
+    let email_domain = email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim_end_matches('.').to_lowercase())
+        .unwrap_or_default();
+
+    let verified: bool = sqlx::query_scalar(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM custom_domains
+            WHERE organization_id = $1 AND status = 'active'
+                AND ($2 = lower(domain) OR $2 LIKE '%.' || lower(domain))
+        )"#,
+    )
+    .bind(organization_id)
+    .bind(&email_domain)
+    .fetch_one(executor)
+    .await
+    .map_err(internal_error)?;
+
+    if !verified {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!(
+                "{} is not a verified domain of the organization",
+                email_domain
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Remove the domain from the organization, along with the certificate
+/// issued for it.
+#[tracing::instrument(name = "Delete custom domain", skip(inner, claims))]
+pub async fn delete_custom_domain(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, domain)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let domain = domain.to_ascii_lowercase();
+
+    require_organization_role(&inner.db, &organization_id, &claims, "admin").await?;
+
+    let mut transaction = inner.db.begin().await.map_err(internal_error)?;
+
+    let deleted =
+        sqlx::query(r#"DELETE FROM custom_domains WHERE domain = $1 AND organization_id = $2"#)
+            .bind(&domain)
+            .bind(&organization_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(internal_error)?
+            .rows_affected();
+    if deleted == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    sqlx::query(r#"DELETE FROM tls_certificates WHERE domain = $1 AND managed"#)
+        .bind(&domain)
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    if let Err(err) = inner.tls_certificates.reload(&inner.db).await {
+        tracing::error!("Could not reload TLS certificates: {}", err);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn run_custom_domain_verification_job(db: PgPool, settings: Arc<Settings>) {
+    jobs::run_periodically(
+        db,
+        "custom_domain_verification",
+        VERIFICATION_JOB_INTERVAL,
+        |db| verify_pending_domains(db, settings.clone()),
+    )
+    .await
+}
+
+/// Look up the records of pending domains, activating those that have
+/// theirs and failing those that ran out of time.
+async fn verify_pending_domains(db: PgPool, settings: Arc<Settings>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE custom_domains SET status = 'failed', updated_at = localtimestamp,
+            verification_error = COALESCE(verification_error, 'No verification record was found')
+        WHERE status = 'pending'
+        AND verification_started_at < localtimestamp - make_interval(hours => $1)"#,
+    )
+    .bind(VERIFICATION_WINDOW_HOURS)
+    .execute(&db)
+    .await?;
+
+    let pending = sqlx::query_as::<_, CustomDomain>(&format!(
+        r#"SELECT {} FROM custom_domains WHERE status = 'pending'
+        ORDER BY checked_at NULLS FIRST LIMIT $1"#,
+        CUSTOM_DOMAIN_COLUMNS
+    ))
+    .bind(VERIFICATION_BATCH_SIZE)
+    .fetch_all(&db)
+    .await?;
+
+    for custom_domain in pending {
+        let outcome = match custom_domain.expected_record(&settings) {
+            Some(record) => check_record(&settings.dns_resolver_url, &record).await,
+            None => Err("cname verification is not available".to_string()),
+        };
+
+        match outcome {
+            Ok(()) => activate(&db, &settings, &custom_domain.domain).await?,
+            Err(err) => {
+                sqlx::query(
+                    r#"UPDATE custom_domains SET checked_at = localtimestamp,
+                        verification_error = $2
+                    WHERE domain = $1 AND status = 'pending'"#,
+                )
+                .bind(&custom_domain.domain)
+                .bind(err)
+                .execute(&db)
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_record(resolver_url: &str, record: &VerificationRecord) -> Result<(), String> {
+    let record_type = match record.kind {
+        "CNAME" => RecordType::Cname,
+        _ => RecordType::Txt,
+    };
+
+    let values = dns::lookup(resolver_url, &record.name, record_type)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    if values
+        .iter()
+        .any(|value| value.eq_ignore_ascii_case(&record.value))
+    {
+        Ok(())
+    } else if values.is_empty() {
+        Err(format!(
+            "No {} record was found at {}",
+            record.kind, record.name
+        ))
+    } else {
+        Err(format!(
+            "The {} record at {} does not match",
+            record.kind, record.name
+        ))
+    }
+}
+
+/// Mark the domain active and, when certificates are issued through ACME,
+/// have one issued for it.
+async fn activate(db: &PgPool, settings: &Settings, domain: &str) -> Result<(), sqlx::Error> {
+    let mut transaction = db.begin().await?;
+
+    sqlx::query(
+        r#"UPDATE custom_domains SET status = 'active', verification_error = NULL,
+            checked_at = localtimestamp, verified_at = localtimestamp,
+            updated_at = localtimestamp
+        WHERE domain = $1 AND status = 'pending'"#,
+    )
+    .bind(domain)
+    .execute(&mut *transaction)
+    .await?;
+
+    if settings.acme_directory_url.is_some() {
+        sqlx::query(
+            r#"INSERT INTO tls_certificates (domain, managed) VALUES ($1, true)
+            ON CONFLICT (domain) DO NOTHING"#,
+        )
+        .bind(domain)
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    tracing::info!("Verified the custom domain {}", domain);
+
+    Ok(())
+}
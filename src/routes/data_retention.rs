@@ -0,0 +1,149 @@
+//! Deleting data past its retention. Raw clicks, the daily rollup and the
+//! admin audit log each have a retention of their own and a job of their
+//! own, so one failing or falling behind never holds up the others.
+
+use crate::configuration::Settings;
+use crate::jobs;
+
+use sqlx::PgPool;
+use std::sync::Arc;
+
+/// How often each class of data is checked for rows past their retention.
+const RETENTION_JOB_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(60 * 60);
+
+/// Rows deleted per statement, so one run never holds too many locks.
+const RETENTION_BATCH_SIZE: i64 = 10_000;
+
+/// Run the retention job of every class of data with a retention set.
+pub async fn run_data_retention_jobs(db: PgPool, settings: Arc<Settings>) {
+    tokio::join!(
+        run_retention_job(
+            db.clone(),
+            "raw_click_retention",
+            settings.raw_click_retention_days,
+            purge_raw_clicks
+        ),
+        run_retention_job(
+            db.clone(),
+            "rollup_retention",
+            settings.rollup_retention_days,
+            purge_rollups
+        ),
+        run_retention_job(
+            db,
+            "audit_log_retention",
+            settings.audit_log_retention_days,
+            purge_audit_log
+        ),
+    );
+}
+
+async fn run_retention_job<F, Fut>(
+    db: PgPool,
+    name: &'static str,
+    retention_days: Option<i32>,
+    purge: F,
+) where
+    F: Fn(PgPool, i32) -> Fut,
+    Fut: std::future::Future<Output = Result<u64, sqlx::Error>>,
+{
+    let Some(days) = retention_days else {
+        return;
+    };
+
+    jobs::run_periodically(db, name, RETENTION_JOB_INTERVAL, |db| {
+        let purged = purge(db, days);
+        async move {
+            let purged = purged.await?;
+            if purged > 0 {
+                tracing::info!("{} purged {} rows older than {} days", name, purged, days);
+            }
+            Ok(())
+        }
+    })
+    .await
+}
+
+/// Delete raw clicks older than `days`. Clicks not rolled up yet are kept
+/// however old, or they would go missing from the statistics.
+async fn purge_raw_clicks(db: PgPool, days: i32) -> Result<u64, sqlx::Error> {
+    let mut purged = 0;
+    loop {
+        let deleted = sqlx::query(
+            r#"DELETE FROM link_statistics WHERE id IN (
+                SELECT id FROM link_statistics
+                WHERE rolled_up AND created_at < localtimestamp - make_interval(days => $1)
+                ORDER BY id LIMIT $2 FOR UPDATE SKIP LOCKED
+            )"#,
+        )
+        .bind(days)
+        .bind(RETENTION_BATCH_SIZE)
+        .execute(&db)
+        .await?
+        .rows_affected();
+
+        purged += deleted;
+        if deleted == 0 {
+            return Ok(purged);
+        }
+    }
+}
+
+/// Delete the days of the rollup older than `days`.
+async fn purge_rollups(db: PgPool, days: i32) -> Result<u64, sqlx::Error> {
+    let mut purged = 0;
+    loop {
+        let deleted = sqlx::query(
+            r#"DELETE FROM link_statistics_daily WHERE ctid = ANY(ARRAY(
+                SELECT ctid FROM link_statistics_daily
+                WHERE day < current_date - $1
+                LIMIT $2 FOR UPDATE SKIP LOCKED
+            ))"#,
+        )
+        .bind(days)
+        .bind(RETENTION_BATCH_SIZE)
+        .execute(&db)
+        .await?
+        .rows_affected();
+
+        purged += deleted;
+        if deleted == 0 {
+            return Ok(purged);
+        }
+    }
+}
+
+/// Delete audit entries older than `days`. The log is otherwise
+/// append-only; its trigger lets through deletes of entries older than the
+/// retention declared for the transaction, and nothing else.
+async fn purge_audit_log(db: PgPool, days: i32) -> Result<u64, sqlx::Error> {
+    let mut purged = 0;
+    loop {
+        let mut transaction = db.begin().await?;
+
+        sqlx::query(r#"SELECT set_config('groupify.audit_log_retention_days', $1, true)"#)
+            .bind(days.to_string())
+            .execute(&mut *transaction)
+            .await?;
+
+        let deleted = sqlx::query(
+            r#"DELETE FROM admin_audit WHERE id IN (
+                SELECT id FROM admin_audit
+                WHERE created_at < CURRENT_TIMESTAMP - make_interval(days => $1)
+                ORDER BY id LIMIT $2
+            )"#,
+        )
+        .bind(days)
+        .bind(RETENTION_BATCH_SIZE)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+
+        transaction.commit().await?;
+
+        purged += deleted;
+        if deleted == 0 {
+            return Ok(purged);
+        }
+    }
+}
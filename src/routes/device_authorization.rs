@@ -0,0 +1,405 @@
+use crate::authentication::{
+    start_session, validate_credentials, AuthError, Claims, Credentials, SessionDevice,
+};
+use crate::casing::Json;
+use crate::client_ip::ClientIp;
+use crate::routes::{generate_subscription_token, get_stored_credentials, render_page, PageKind};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::Form;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// How long a device has to be approved before it must start over.
+const DEVICE_CODE_TTL_SECONDS: i32 = 10 * 60;
+
+/// Minimum delay between two polls from the same device.
+const POLL_INTERVAL_SECONDS: i32 = 5;
+
+/// Scopes a device may request. Scoped tokens never carry admin rights.
+const DEVICE_SCOPES: [&str; 1] = ["links"];
+
+/// Consonants only, so user codes are easy to type and never spell words.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceStart {
+    pub client_name: Option<String>,
+    pub scope: Option<String>,
+}
+
+impl Validate for DeviceStart {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if let Some(client_name) = &self.client_name {
+            errors.require_max_length("clientName", client_name, 100);
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCode {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: i32,
+    pub interval: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceApproval {
+    pub user_code: String,
+    #[serde(default = "approve_by_default")]
+    pub approve: bool,
+    /// A policy of one of the approver's organizations limiting what the
+    /// device may do.
+    pub policy_id: Option<String>,
+}
+
+fn approve_by_default() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct DevicePageQuery {
+    pub code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceVerification {
+    pub user_code: String,
+    pub email: String,
+    pub password: String,
+    pub decision: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevicePoll {
+    pub device_code: String,
+}
+
+#[derive(Serialize)]
+pub struct DeviceToken {
+    pub token: String,
+    pub scope: String,
+}
+
+#[derive(FromRow)]
+struct DeviceAuthorization {
+    status: String,
+    scope: String,
+    policy_id: Option<String>,
+    expired: bool,
+    polled_too_soon: bool,
+    user_id: Option<String>,
+    email: Option<String>,
+    role: Option<String>,
+}
+
+fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut code: String = (0..8)
+        .map(|_| USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())] as char)
+        .collect();
+    code.insert(4, '-');
+    code
+}
+
+fn normalize_user_code(user_code: &str) -> String {
+    let mut code: String = user_code
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|character| character.to_ascii_uppercase())
+        .collect();
+    if code.len() == 8 {
+        code.insert(4, '-');
+    }
+    code
+}
+
+/// Begin a device authorization: the device shows `userCode` to the user and
+/// polls with `deviceCode` until they approve it from a signed in session.
+#[tracing::instrument(name = "Start device authorization", skip(inner, start))]
+pub async fn start_device_authorization(
+    State(inner): State<InnerState>,
+    Valid(start): Valid<DeviceStart>,
+) -> Result<Json<DeviceCode>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let scope = start.scope.unwrap_or_else(|| DEVICE_SCOPES[0].to_string());
+    if !DEVICE_SCOPES.contains(&scope.as_str()) {
+        return Err((StatusCode::BAD_REQUEST, "invalid_scope".to_string()));
+    }
+
+    sqlx::query(r#"DELETE FROM device_authorizations WHERE expires_at < CURRENT_TIMESTAMP"#)
+        .execute(&db)
+        .await
+        .map_err(internal_error)?;
+
+    let device_code = generate_subscription_token();
+    let user_code = generate_user_code();
+
+    sqlx::query(
+        r#"INSERT INTO device_authorizations (device_code, user_code, expires_at, client_name, scope)
+        VALUES ($1, $2, CURRENT_TIMESTAMP + make_interval(secs => $3), $4, $5)"#,
+    )
+    .bind(&device_code)
+    .bind(&user_code)
+    .bind(DEVICE_CODE_TTL_SECONDS as f64)
+    .bind(start.client_name)
+    .bind(scope)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(DeviceCode {
+        device_code,
+        verification_uri: format!("{}/device", settings.public_base_url),
+        verification_uri_complete: format!(
+            "{}/device?code={}",
+            settings.public_base_url, user_code
+        ),
+        user_code,
+        expires_in: DEVICE_CODE_TTL_SECONDS,
+        interval: POLL_INTERVAL_SECONDS,
+    }))
+}
+
+#[tracing::instrument(name = "Approve device authorization", skip(inner, claims, approval))]
+pub async fn approve_device_authorization(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Json(approval): Json<DeviceApproval>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    // A device token must not be able to enroll further devices.
+    if claims.scope.is_some() {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    let user_id = get_stored_credentials(&claims.sub, &db).await?.id;
+
+    if let Some(policy_id) = &approval.policy_id {
+        let in_organization: bool = sqlx::query_scalar(
+            r#"SELECT EXISTS (SELECT 1 FROM organization_policies
+            JOIN organization_members ON organization_members.organization_id = organization_policies.organization_id
+            WHERE organization_policies.id = $1 AND organization_members.user_id = $2)"#,
+        )
+        .bind(policy_id)
+        .bind(&user_id)
+        .fetch_one(&db)
+        .await
+        .map_err(internal_error)?;
+
+        if !in_organization {
+            return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+        }
+    }
+
+    let decided = decide_device_authorization(
+        &db,
+        user_id.as_deref(),
+        &approval.user_code,
+        approval.approve,
+        approval.policy_id.as_deref(),
+    )
+    .await
+    .map_err(internal_error)?;
+
+    if !decided {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Approve or deny a pending device for the user. False when no pending,
+/// unexpired device has the user code.
+async fn decide_device_authorization(
+    db: &PgPool,
+    user_id: Option<&str>,
+    user_code: &str,
+    approve: bool,
+    policy_id: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let updated = sqlx::query(
+        r#"UPDATE device_authorizations SET status = $1, user_id = $2, policy_id = $4
+        WHERE user_code = $3 AND status = 'pending' AND expires_at > CURRENT_TIMESTAMP"#,
+    )
+    .bind(if approve { "approved" } else { "denied" })
+    .bind(user_id)
+    .bind(normalize_user_code(user_code))
+    .bind(policy_id)
+    .execute(db)
+    .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
+
+/// The page `verificationUri` points to, for users without a client that
+/// approves devices for them.
+pub async fn device_page(
+    State(inner): State<InnerState>,
+    Query(query): Query<DevicePageQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let InnerState { db, settings, .. } = inner;
+
+    let user_code = query
+        .code
+        .as_deref()
+        .map(normalize_user_code)
+        .unwrap_or_default();
+
+    render_page(
+        &db,
+        &settings,
+        &headers,
+        PageKind::Device,
+        &[("user_code", &user_code)],
+    )
+    .await
+}
+
+/// Sign in and decide on a device from the verification page. Device
+/// tokens never reach this form, so a password is always required.
+#[tracing::instrument(name = "Verify device", skip(inner, headers, form))]
+pub async fn verify_device(
+    State(inner): State<InnerState>,
+    headers: HeaderMap,
+    Form(form): Form<DeviceVerification>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let approve = form.decision == "approve";
+    let credentials = Credentials {
+        email: form.email,
+        password: form.password,
+    };
+
+    let decided = match validate_credentials(&credentials, &db).await {
+        Ok(user) => {
+            decide_device_authorization(&db, user.id.as_deref(), &form.user_code, approve, None)
+                .await
+                .map_err(internal_error)?
+        }
+        Err(AuthError::InvalidCredentials(_)) => false,
+        Err(AuthError::UnexpectedError(err)) => return Err(internal_error(&*err)),
+    };
+
+    let user_code = normalize_user_code(&form.user_code);
+    let outcome = match (decided, approve) {
+        (true, true) => "device_approved",
+        (true, false) => "device_denied",
+        (false, _) => "device_failed",
+    };
+
+    Ok(render_page(
+        &db,
+        &settings,
+        &headers,
+        PageKind::Device,
+        &[("user_code", &user_code), (outcome, "true")],
+    )
+    .await)
+}
+
+/// Exchange an approved device code for a scoped token, exactly once. The
+/// token belongs to a session of its own, which the user may revoke like
+/// any other, and cannot be refreshed, so it lasts as long as the session.
+#[tracing::instrument(name = "Poll device authorization", skip(inner, client, headers, poll))]
+pub async fn poll_device_authorization(
+    State(inner): State<InnerState>,
+    client: ClientIp,
+    headers: HeaderMap,
+    Json(poll): Json<DevicePoll>,
+) -> Result<Json<DeviceToken>, (StatusCode, String)> {
+    let InnerState {
+        db,
+        jwt_keys,
+        settings,
+        ..
+    } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let authorization = sqlx::query_as::<_, DeviceAuthorization>(
+        r#"SELECT status, scope, policy_id, expires_at <= CURRENT_TIMESTAMP as expired,
+            COALESCE(last_polled_at > CURRENT_TIMESTAMP - make_interval(secs => $2), false) as polled_too_soon,
+            users.id as user_id, users.email, users.role
+        FROM device_authorizations LEFT JOIN users ON users.id = device_authorizations.user_id
+        WHERE device_code = $1
+        FOR UPDATE OF device_authorizations"#,
+    )
+    .bind(&poll.device_code)
+    .bind(POLL_INTERVAL_SECONDS as f64)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::BAD_REQUEST, "invalid_grant".to_string()))?;
+
+    let issue = authorization.status == "approved" && !authorization.expired;
+
+    sqlx::query(
+        r#"UPDATE device_authorizations SET last_polled_at = CURRENT_TIMESTAMP,
+        status = CASE WHEN $2 THEN 'issued' ELSE status END
+        WHERE device_code = $1"#,
+    )
+    .bind(&poll.device_code)
+    .bind(issue)
+    .execute(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    let error = match authorization.status.as_str() {
+        _ if authorization.expired => "expired_token",
+        "pending" if authorization.polled_too_soon => "slow_down",
+        "pending" => "authorization_pending",
+        "denied" => "access_denied",
+        "approved" => {
+            let device = SessionDevice::new(&settings, &client, &headers);
+            let session = start_session(
+                &db,
+                authorization.user_id.as_deref().unwrap_or_default(),
+                settings.refresh_token_lifetime,
+                &device,
+            )
+            .await
+            .map_err(internal_error)?;
+
+            let email = authorization.email.unwrap_or_default();
+            let mut claims = Claims::for_session(
+                &email,
+                authorization.role.as_deref().unwrap_or("user"),
+                &session.session_id,
+                settings.refresh_token_lifetime,
+            )
+            .with_scope(&authorization.scope);
+            if let Some(policy_id) = &authorization.policy_id {
+                claims = claims.with_policy(policy_id);
+            }
+            let token = jwt_keys.sign(&claims).await?;
+
+            return Ok(Json(DeviceToken {
+                token,
+                scope: authorization.scope,
+            }));
+        }
+        _ => "invalid_grant",
+    };
+
+    Err((StatusCode::BAD_REQUEST, error.to_string()))
+}
@@ -0,0 +1,155 @@
+//! Grafana's simple JSON datasource, charting the daily clicks of the
+//! caller's links and organizations from the statistics rollup. Grafana is
+//! pointed at `/grafana` with the caller's token as a bearer header.
+
+use crate::authentication::Claims;
+use crate::db::links::{self, ClickScope};
+use crate::policy::Action;
+use crate::routes::{
+    authorize_link, cached_statistics, get_stored_credentials, require_organization_role,
+    StatisticsCacheKey,
+};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+const LINK_TARGET_PREFIX: &str = "link:";
+const ORGANIZATION_TARGET_PREFIX: &str = "organization:";
+
+const MAX_SEARCH_RESULTS: i64 = 1000;
+
+/// Days charted by a single query, so a wide range cannot scan everything.
+const MAX_QUERY_DAYS: i64 = 366;
+
+#[derive(Deserialize)]
+pub struct GrafanaSearch {
+    /// Text the offered targets contain, as typed in the query editor.
+    #[serde(default)]
+    pub target: String,
+}
+
+#[derive(Deserialize)]
+pub struct GrafanaQuery {
+    pub range: GrafanaRange,
+    pub targets: Vec<GrafanaTarget>,
+}
+
+#[derive(Deserialize)]
+pub struct GrafanaRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct GrafanaTarget {
+    /// `link:<id>` or `organization:<id>`.
+    pub target: String,
+}
+
+#[derive(Serialize)]
+pub struct GrafanaSeries {
+    pub target: String,
+    /// `[clicks, unix milliseconds]` per day, oldest first.
+    pub datapoints: Vec<(i64, i64)>,
+}
+
+/// Answers Grafana's connection test once the token checks out.
+pub async fn grafana_datasource(_claims: Claims) -> StatusCode {
+    StatusCode::OK
+}
+
+/// Targets the caller may chart.
+#[tracing::instrument(name = "Search Grafana targets", skip(inner, claims, search))]
+pub async fn grafana_search(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Json(search): Json<GrafanaSearch>,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let user_id = get_stored_credentials(&claims.sub, &db).await?.id;
+
+    let targets: Vec<String> = sqlx::query_scalar(
+        r#"SELECT target FROM (
+            SELECT $3 || links.id AS target FROM links
+            WHERE links.owner_id = $1 OR links.organization_id IN (
+                SELECT organization_id FROM organization_members WHERE user_id = $1
+            )
+            UNION ALL
+            SELECT $4 || organization_id FROM organization_members WHERE user_id = $1
+        ) targets
+        WHERE strpos(target, $2) > 0
+        ORDER BY target LIMIT $5"#,
+    )
+    .bind(user_id)
+    .bind(&search.target)
+    .bind(LINK_TARGET_PREFIX)
+    .bind(ORGANIZATION_TARGET_PREFIX)
+    .bind(MAX_SEARCH_RESULTS)
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(targets))
+}
+
+/// Daily clicks of every target over the range.
+#[tracing::instrument(name = "Query Grafana series", skip(inner, claims, query))]
+pub async fn grafana_query(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Json(query): Json<GrafanaQuery>,
+) -> Result<Json<Vec<GrafanaSeries>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let from = query.range.from.date_naive();
+    let to = query.range.to.date_naive();
+    if to < from || (to - from).num_days() >= MAX_QUERY_DAYS {
+        return Err((StatusCode::BAD_REQUEST, "Invalid range".to_string()));
+    }
+
+    let mut series = Vec::with_capacity(query.targets.len());
+
+    for GrafanaTarget { target } in query.targets {
+        let (scope, key) = if let Some(link_id) = target.strip_prefix(LINK_TARGET_PREFIX) {
+            authorize_link(&db, link_id, &claims, Action::ReadLinkStatistics).await?;
+
+            (
+                ClickScope::Link(link_id),
+                StatisticsCacheKey::link("grafana", link_id, (from, to)),
+            )
+        } else if let Some(organization_id) = target.strip_prefix(ORGANIZATION_TARGET_PREFIX) {
+            require_organization_role(&db, organization_id, &claims, "member").await?;
+
+            (
+                ClickScope::Organization(organization_id),
+                StatisticsCacheKey::organization("grafana", organization_id, (from, to)),
+            )
+        } else {
+            return Err((StatusCode::BAD_REQUEST, "Unknown target".to_string()));
+        };
+
+        let datapoints = cached_statistics(key, || async {
+            Ok(links::daily_clicks(&db, &scope, from, to, false)
+                .await
+                .map_err(internal_error)?
+                .into_iter()
+                .map(|(day, clicks)| {
+                    let midnight = day.and_hms_opt(0, 0, 0).unwrap_or_default();
+                    (clicks, midnight.and_utc().timestamp_millis())
+                })
+                .collect::<Vec<_>>())
+        })
+        .await?
+        .value;
+
+        series.push(GrafanaSeries { target, datapoints });
+    }
+
+    Ok(Json(series))
+}
@@ -1,25 +1,18 @@
+use crate::casing::Json;
+use crate::pagination::Page;
 use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
 
-use anyhow::{Context, Result};
 use axum::extract::{Path, State};
-use axum::http::{Response, StatusCode};
-use axum::Json;
-use chrono::{DateTime, NaiveDateTime, Utc};
-use once_cell::sync::Lazy;
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
-use sha3::Digest;
-use sqlx::{Executor, FromRow, PgPool, Postgres, Row, Transaction};
-use std::collections::HashMap;
-use tokio::sync::RwLock;
+use sqlx::FromRow;
 use uuid::Uuid;
 
 use crate::InnerState;
 
-use crate::email::{EmailClient, SendEmailRequest};
-
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Group {
@@ -31,10 +24,18 @@ pub struct Group {
     pub user_id: String,
 }
 
+impl Validate for Group {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_not_blank("name", &self.name);
+        errors.require_max_length("name", &self.name, 100);
+        errors.require_not_blank("icon", &self.icon);
+    }
+}
+
 pub async fn all_groups(
     State(inner): State<InnerState>,
     Path(user_id): Path<String>,
-) -> Result<Json<Vec<Group>>, (StatusCode, String)> {
+) -> Result<Json<Page<Group>>, (StatusCode, String)> {
     let InnerState { db, .. } = inner;
 
     let fetch_groups_timeout = tokio::time::Duration::from_millis(1000);
@@ -51,12 +52,12 @@ pub async fn all_groups(
     .map_err(internal_error)?
     .map_err(internal_error)?;
 
-    Ok(Json(groups))
+    Ok(Json(Page::complete(groups)))
 }
 
 pub async fn create_group(
     State(inner): State<InnerState>,
-    Json(group): Json<Group>,
+    Valid(group): Valid<Group>,
 ) -> Result<Json<Group>, (StatusCode, String)> {
     let InnerState { db, .. } = inner;
 
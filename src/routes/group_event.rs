@@ -0,0 +1,330 @@
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::db::links::{NewLink, RedirectBehavior};
+use crate::pagination::Page;
+use crate::routes::{
+    generate_id, generate_subscription_token, get_stored_credentials, insert_checked_link,
+};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupEvent {
+    pub id: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub group_id: Option<String>,
+    pub title: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub url: Option<String>,
+    pub link_id: Option<String>,
+    pub starts_at: NaiveDateTime,
+    pub ends_at: Option<NaiveDateTime>,
+}
+
+impl Validate for GroupEvent {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_not_blank("title", &self.title);
+        errors.require_max_length("title", &self.title, 200);
+        if let Some(description) = &self.description {
+            errors.require_max_length("description", description, 5000);
+        }
+        if let Some(location) = &self.location {
+            errors.require_max_length("location", location, 500);
+        }
+        if let Some(url) = &self.url {
+            errors.require_link_target("url", url);
+        }
+        if self.ends_at.is_some_and(|ends_at| ends_at < self.starts_at) {
+            errors.add("endsAt", "must not be before startsAt");
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarFeed {
+    pub feed_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct CalendarFeedQuery {
+    pub token: String,
+}
+
+/// Check the caller owns the group.
+pub async fn require_group_owner(
+    db: &PgPool,
+    group_id: &str,
+    claims: &Claims,
+) -> Result<String, (StatusCode, String)> {
+    let user_id = get_stored_credentials(&claims.sub, db)
+        .await?
+        .id
+        .unwrap_or_default();
+
+    sqlx::query_scalar::<_, String>(r#"SELECT id FROM groups WHERE id = $1 AND user_id = $2"#)
+        .bind(group_id)
+        .bind(&user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    Ok(user_id)
+}
+
+#[tracing::instrument(name = "Create group event", skip(inner, claims, event))]
+pub async fn create_group_event(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(group_id): Path<String>,
+    Valid(event): Valid<GroupEvent>,
+) -> Result<Json<GroupEvent>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner.clone();
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let created = match &event.url {
+        Some(url) => Some(
+            insert_checked_link(
+                &mut transaction,
+                &inner,
+                &NewLink {
+                    id: &generate_id(),
+                    target_url: url,
+                    organization_id: None,
+                    owner_email: Some(&claims.sub),
+                    expires_at: None,
+                    payload: None,
+                    draft: false,
+                    domain: None,
+                    behavior: &RedirectBehavior::default(),
+                    tags: &[],
+                    campaign: None,
+                },
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let url = created
+        .as_ref()
+        .map(|created| created.link.target_url.clone());
+    let link_id = created.as_ref().map(|created| created.link.id.clone());
+
+    let event = sqlx::query_as::<_, GroupEvent>(
+        r#"INSERT INTO group_events (id, group_id, title, description, location, url, link_id, starts_at, ends_at)
+        values($1, $2, $3, $4, $5, $6, $7, $8, $9) returning *"#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&group_id)
+    .bind(event.title)
+    .bind(event.description)
+    .bind(event.location)
+    .bind(url)
+    .bind(link_id)
+    .bind(event.starts_at)
+    .bind(event.ends_at)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    if let Some(created) = created {
+        created.committed(&inner);
+    }
+
+    Ok(Json(event))
+}
+
+pub async fn all_group_events(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(group_id): Path<String>,
+) -> Result<Json<Page<GroupEvent>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    Ok(Json(Page::complete(
+        fetch_group_events(&db, &group_id).await?,
+    )))
+}
+
+async fn fetch_group_events(
+    db: &PgPool,
+    group_id: &str,
+) -> Result<Vec<GroupEvent>, (StatusCode, String)> {
+    sqlx::query_as::<_, GroupEvent>(
+        r#"SELECT * FROM group_events WHERE group_id = $1 ORDER BY starts_at"#,
+    )
+    .bind(group_id)
+    .fetch_all(db)
+    .await
+    .map_err(internal_error)
+}
+
+/// Issue a new secret feed URL for the group, invalidating the previous one.
+#[tracing::instrument(name = "Rotate group calendar token", skip(inner, claims))]
+pub async fn rotate_calendar_token(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(group_id): Path<String>,
+) -> Result<Json<CalendarFeed>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    let token = generate_subscription_token();
+
+    sqlx::query(
+        r#"UPDATE groups SET calendar_token = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2"#,
+    )
+    .bind(&token)
+    .bind(&group_id)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(CalendarFeed {
+        feed_url: format!(
+            "{}/groups/{}/events.ics?token={}",
+            settings.public_base_url, group_id, token
+        ),
+    }))
+}
+
+pub async fn group_events_feed(
+    State(inner): State<InnerState>,
+    Path(group_id): Path<String>,
+    Query(query): Query<CalendarFeedQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let group_name: String =
+        sqlx::query_scalar(r#"SELECT name FROM groups WHERE id = $1 AND calendar_token = $2"#)
+            .bind(&group_id)
+            .bind(&query.token)
+            .fetch_optional(&db)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let events = fetch_group_events(&db, &group_id).await?;
+
+    let mut calendar = String::new();
+    push_line(&mut calendar, "BEGIN:VCALENDAR");
+    push_line(&mut calendar, "VERSION:2.0");
+    push_line(&mut calendar, "PRODID:-//Groupify//Group Events//EN");
+    push_line(&mut calendar, "CALSCALE:GREGORIAN");
+    push_line(
+        &mut calendar,
+        &format!("X-WR-CALNAME:{}", ical_text(&group_name)),
+    );
+
+    for event in events {
+        let mut description = event.description.unwrap_or_default();
+        if let Some(link_id) = &event.link_id {
+            if !description.is_empty() {
+                description.push_str("\n\n");
+            }
+            description.push_str(&format!("{}/{}", settings.public_base_url, link_id));
+        }
+
+        push_line(&mut calendar, "BEGIN:VEVENT");
+        push_line(
+            &mut calendar,
+            &format!("UID:{}@groupify", event.id.unwrap_or_default()),
+        );
+        push_line(
+            &mut calendar,
+            &format!(
+                "DTSTAMP:{}",
+                ical_time(&event.updated_at.unwrap_or(event.starts_at))
+            ),
+        );
+        push_line(
+            &mut calendar,
+            &format!("DTSTART:{}", ical_time(&event.starts_at)),
+        );
+        if let Some(ends_at) = &event.ends_at {
+            push_line(&mut calendar, &format!("DTEND:{}", ical_time(ends_at)));
+        }
+        push_line(
+            &mut calendar,
+            &format!("SUMMARY:{}", ical_text(&event.title)),
+        );
+        if !description.is_empty() {
+            push_line(
+                &mut calendar,
+                &format!("DESCRIPTION:{}", ical_text(&description)),
+            );
+        }
+        if let Some(location) = &event.location {
+            push_line(&mut calendar, &format!("LOCATION:{}", ical_text(location)));
+        }
+        if let Some(link_id) = &event.link_id {
+            push_line(
+                &mut calendar,
+                &format!("URL:{}/{}", settings.public_base_url, link_id),
+            );
+        }
+        push_line(&mut calendar, "END:VEVENT");
+    }
+
+    push_line(&mut calendar, "END:VCALENDAR");
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/calendar; charset=utf-8")
+        .header("Cache-Control", "private, max-age=300")
+        .body(Body::from(calendar))
+        .expect("This response should always be constructable"))
+}
+
+fn ical_time(time: &NaiveDateTime) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escape a TEXT value as RFC 5545 section 3.3.11 requires.
+fn ical_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Append a content line, folding it so no physical line exceeds 75 octets.
+fn push_line(calendar: &mut String, line: &str) {
+    let mut octets = 0;
+
+    for character in line.chars() {
+        let width = character.len_utf8();
+        if octets + width > 75 {
+            calendar.push_str("\r\n ");
+            octets = 1;
+        }
+        calendar.push(character);
+        octets += width;
+    }
+
+    calendar.push_str("\r\n");
+}
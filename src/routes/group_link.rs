@@ -0,0 +1,234 @@
+//! Links collected into a group, and clicks counted across all of them.
+
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::db::links::{self, ClickScope, GroupLink};
+use crate::pagination::Page;
+use crate::policy::Action;
+use crate::routes::{
+    authorize_link, cached_statistics, require_group_owner, Cached, StatisticsCacheKey,
+};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::TimeseriesPoint;
+
+/// Days group statistics may span, and reach back without `from`.
+const MAX_DAYS: i64 = 1_000;
+const DEFAULT_DAYS: i64 = 30;
+
+const MAX_TITLE_LENGTH: usize = 200;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupLinkEntry {
+    /// Shown on the public page of the group instead of the target.
+    pub title: Option<String>,
+    /// Where the link comes on the page, lowest first. Links added without
+    /// one go last, and keep theirs when updated without one.
+    pub position: Option<i32>,
+}
+
+impl Validate for GroupLinkEntry {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if let Some(title) = &self.title {
+            errors.require_not_blank("title", title);
+            errors.require_max_length("title", title, MAX_TITLE_LENGTH);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupStatisticsQuery {
+    /// First day counted, inclusive.
+    pub from: Option<NaiveDate>,
+    /// Last day counted, inclusive, today by default.
+    pub to: Option<NaiveDate>,
+    /// Leave out clicks of bots, such as chat apps previewing the links.
+    #[serde(default)]
+    pub exclude_bots: bool,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupLinkClicks {
+    pub link_id: String,
+    pub clicks: i64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupStatistics {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Estimated clicks on all links of the group.
+    pub clicks: i64,
+    /// Clicks per link, most clicked first, links without any left out.
+    pub links: Vec<GroupLinkClicks>,
+    /// Clicks per day of the range in order, days without clicks included.
+    pub points: Vec<TimeseriesPoint>,
+}
+
+#[tracing::instrument(name = "List group links", skip(inner, claims))]
+pub async fn group_links(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(group_id): Path<String>,
+) -> Result<Json<Page<GroupLink>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    let links = links::list_in_group(&db, &group_id)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(links)))
+}
+
+#[tracing::instrument(name = "Add group link", skip(inner, claims, entry))]
+pub async fn add_group_link(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((group_id, link_id)): Path<(String, String)>,
+    Valid(entry): Valid<GroupLinkEntry>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    authorize_link(&db, &link_id, &claims, Action::ReadLink).await?;
+
+    sqlx::query(
+        r#"INSERT INTO group_links (group_id, link_id, title, position)
+        VALUES ($1, $2, $3, coalesce($4, (
+            SELECT coalesce(max(position) + 1, 0) FROM group_links WHERE group_id = $1
+        )))
+        ON CONFLICT (group_id, link_id) DO UPDATE SET title = excluded.title,
+            position = coalesce($4, group_links.position)"#,
+    )
+    .bind(&group_id)
+    .bind(&link_id)
+    .bind(entry.title.as_deref().map(str::trim))
+    .bind(entry.position)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(name = "Remove group link", skip(inner, claims))]
+pub async fn remove_group_link(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((group_id, link_id)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    let removed = sqlx::query(r#"DELETE FROM group_links WHERE group_id = $1 AND link_id = $2"#)
+        .bind(&group_id)
+        .bind(&link_id)
+        .execute(&db)
+        .await
+        .map_err(internal_error)?
+        .rows_affected();
+    if removed == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(name = "Group statistics", skip(inner, claims))]
+pub async fn group_statistics(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(group_id): Path<String>,
+    Query(query): Query<GroupStatisticsQuery>,
+) -> Result<Cached<GroupStatistics>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let to = query.to.unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let from = query
+        .from
+        .unwrap_or_else(|| to - Duration::days(DEFAULT_DAYS - 1));
+    if from > to {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "From must not be after to".to_string(),
+        ));
+    }
+    if (to - from).num_days() >= MAX_DAYS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Statistics may span at most {} days", MAX_DAYS),
+        ));
+    }
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    // Keyed by the links of the group, so adding or removing one, or a click
+    // on any of them, leaves cached statistics behind.
+    let link_ids: Vec<String> = links::list_in_group(&db, &group_id)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|member| member.link.id)
+        .collect();
+    let key = StatisticsCacheKey::links(
+        "group",
+        &link_ids,
+        (&group_id, from, to, query.exclude_bots),
+    );
+
+    cached_statistics(key, || async {
+        let daily: HashMap<NaiveDateTime, i64> = links::daily_clicks(
+            &db,
+            &ClickScope::Group(&group_id),
+            from,
+            to,
+            query.exclude_bots,
+        )
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|(day, clicks)| (day.and_time(NaiveTime::MIN), clicks))
+        .collect();
+        let per_link = links::group_link_clicks(&db, &group_id, from, to, query.exclude_bots)
+            .await
+            .map_err(internal_error)?;
+
+        let start = from.and_time(NaiveTime::MIN);
+        let end = (to + Duration::days(1)).and_time(NaiveTime::MIN);
+        let points = std::iter::successors(Some(start), |day| Some(*day + Duration::days(1)))
+            .take_while(|day| *day < end)
+            .map(|day| TimeseriesPoint {
+                start: day,
+                clicks: daily.get(&day).copied().unwrap_or(0),
+            })
+            .collect();
+
+        Ok(GroupStatistics {
+            from,
+            to,
+            clicks: per_link.iter().map(|(_, clicks)| clicks).sum(),
+            links: per_link
+                .into_iter()
+                .map(|(link_id, clicks)| GroupLinkClicks { link_id, clicks })
+                .collect(),
+            points,
+        })
+    })
+    .await
+}
@@ -0,0 +1,182 @@
+//! Public pages listing the links of a group, linktree style. A group has
+//! one once its owner picks a slug for it. Links on the page point to
+//! their short URLs, so clicks from it are recorded like any other.
+
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::db::links;
+use crate::link_state::LinkState;
+use crate::routes::{public_response, render_page, require_group_owner, PageKind};
+use crate::templates::html_escape;
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors, MIN_SLUG_LENGTH};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::header::ACCEPT;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Debug, Deserialize)]
+pub struct GroupPageUpdate {
+    pub slug: String,
+}
+
+impl Validate for GroupPageUpdate {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_slug("slug", &self.slug, MIN_SLUG_LENGTH);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupPage {
+    pub slug: String,
+    pub url: String,
+}
+
+#[derive(FromRow)]
+struct PublishedGroup {
+    id: String,
+    name: String,
+    icon: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicGroupLink {
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicGroupPage {
+    pub name: String,
+    pub icon: String,
+    /// The active links of the group, in order.
+    pub links: Vec<PublicGroupLink>,
+}
+
+/// Whether the client asked for JSON rather than a page, as single page
+/// apps rendering the group themselves do.
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json") && !accept.contains("text/html"))
+}
+
+#[tracing::instrument(name = "Publish group page", skip(inner, claims))]
+pub async fn publish_group_page(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(group_id): Path<String>,
+    Valid(update): Valid<GroupPageUpdate>,
+) -> Result<Json<GroupPage>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    let slug = update.slug.to_ascii_lowercase();
+    sqlx::query(r#"UPDATE groups SET slug = $2, updated_at = CURRENT_TIMESTAMP WHERE id = $1"#)
+        .bind(&group_id)
+        .bind(&slug)
+        .execute(&db)
+        .await
+        .map_err(|err| match err.as_database_error() {
+            Some(err) if err.is_unique_violation() => {
+                (StatusCode::CONFLICT, "Slug is already taken".to_string())
+            }
+            _ => internal_error(err),
+        })?;
+
+    Ok(Json(GroupPage {
+        url: format!("{}/g/{}", settings.public_base_url, slug),
+        slug,
+    }))
+}
+
+#[tracing::instrument(name = "Unpublish group page", skip(inner, claims))]
+pub async fn unpublish_group_page(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(group_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    sqlx::query(r#"UPDATE groups SET slug = NULL, updated_at = CURRENT_TIMESTAMP WHERE id = $1"#)
+        .bind(&group_id)
+        .execute(&db)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(name = "Public group page", skip(inner, headers))]
+pub async fn public_group_page(
+    State(inner): State<InnerState>,
+    Path(slug): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let group =
+        sqlx::query_as::<_, PublishedGroup>(r#"SELECT id, name, icon FROM groups WHERE slug = $1"#)
+            .bind(slug.to_ascii_lowercase())
+            .fetch_optional(&db)
+            .await
+            .map_err(internal_error)?;
+
+    let Some(group) = group else {
+        if wants_json(&headers) {
+            return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+        }
+        return Ok(render_page(&db, &settings, &headers, PageKind::NotFound, &[]).await);
+    };
+
+    let links: Vec<PublicGroupLink> = links::list_in_group(&db, &group.id)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .filter(|member| member.link.state == LinkState::Active)
+        .map(|member| PublicGroupLink {
+            title: member.title.unwrap_or(member.link.target_url),
+            url: format!("{}/{}", settings.public_base_url, member.link.id),
+        })
+        .collect();
+
+    if wants_json(&headers) {
+        let page = PublicGroupPage {
+            name: group.name,
+            icon: group.icon,
+            links,
+        };
+        let body = serde_json::to_string(&page).map_err(internal_error)?;
+        return Ok(public_response("application/json", body));
+    }
+
+    let items: String = links
+        .iter()
+        .map(|link| {
+            format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                html_escape(&link.url),
+                html_escape(&link.title)
+            )
+        })
+        .collect();
+    let values = [
+        ("title", group.name.as_str()),
+        ("group_name", group.name.as_str()),
+        ("group_icon", group.icon.as_str()),
+        ("group_links", items.as_str()),
+    ];
+
+    Ok(render_page(&db, &settings, &headers, PageKind::Group, &values).await)
+}
@@ -0,0 +1,271 @@
+//! The blocked and allowed hosts operators add to those configured, which
+//! link targets are held against wherever links are created or changed.
+
+use crate::authentication::AdminUser;
+use crate::casing::Json;
+use crate::configuration::Settings;
+use crate::host_rules::{self, HostList, HostPattern};
+use crate::pagination::Page;
+use crate::routes::record_admin_action;
+use crate::utils::internal_error;
+use crate::validation::ValidationErrors;
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, PgPool};
+use std::net::SocketAddr;
+use url::{Host, Url};
+
+/// How long checking a target waits for its name to resolve.
+const HOST_RESOLUTION_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(2);
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct HostRule {
+    pub pattern: String,
+    /// `blocked` or `allowed`.
+    pub list: String,
+    /// None for rules configured with `BLOCKED_HOSTS` or `ALLOWED_HOSTS`,
+    /// which cannot be changed at runtime.
+    pub created_by: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HostRuleChange {
+    pub list: HostList,
+}
+
+async fn stored_host_rules(db: &PgPool) -> Result<Vec<HostRule>, sqlx::Error> {
+    sqlx::query_as::<_, HostRule>(r#"SELECT * FROM host_rules ORDER BY pattern"#)
+        .fetch_all(db)
+        .await
+}
+
+fn configured_host_rules(settings: &Settings) -> impl Iterator<Item = (HostList, &HostPattern)> {
+    let blocked = settings
+        .blocked_hosts
+        .iter()
+        .map(|pattern| (HostList::Blocked, pattern));
+    let allowed = settings
+        .allowed_hosts
+        .iter()
+        .map(|pattern| (HostList::Allowed, pattern));
+    blocked.chain(allowed)
+}
+
+/// Refuse a target whose host is blocked, or whose name resolves to a
+/// blocked address, as a domain pointed at an internal address is as
+/// dangerous as the address. Names that do not resolve yet are let through:
+/// visitors' browsers resolve them, and the service only fetches targets
+/// through `PublicResolver`. Targets on the service itself are refused, so
+/// short links never redirect to one another. Targets without a host, such
+/// as `mailto:` ones, are let through.
+pub async fn require_allowed_host(
+    db: &PgPool,
+    settings: &Settings,
+    target_url: &Url,
+) -> Result<(), (StatusCode, String)> {
+    let Some(host) = target_url.host() else {
+        return Ok(());
+    };
+
+    let own_host = Url::parse(&settings.public_base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string));
+    if let (Host::Domain(domain), Some(own_host)) = (&host, own_host) {
+        if domain.trim_end_matches('.').eq_ignore_ascii_case(&own_host) {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Links cannot point to other short links".to_string(),
+            ));
+        }
+    }
+
+    let stored: Vec<(HostList, HostPattern)> = stored_host_rules(db)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .filter_map(|rule| {
+            Some((
+                HostList::parse(&rule.list)?,
+                HostPattern::parse(&rule.pattern)?,
+            ))
+        })
+        .collect();
+    let rules = || {
+        configured_host_rules(settings).chain(stored.iter().map(|(list, pattern)| (*list, pattern)))
+    };
+
+    if host_rules::is_blocked(rules(), &host) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Links to {} are not allowed", host),
+        ));
+    }
+
+    // A domain allowed by name may resolve wherever it likes.
+    let allowed_by_name = rules().any(|(list, pattern)| {
+        list == HostList::Allowed
+            && matches!(pattern, HostPattern::Domain(_))
+            && pattern.matches(&host)
+    });
+    if let (Host::Domain(domain), false) = (&host, allowed_by_name) {
+        let port = target_url.port_or_known_default().unwrap_or(443);
+        let resolved = tokio::time::timeout(
+            HOST_RESOLUTION_TIMEOUT,
+            tokio::net::lookup_host((*domain, port)),
+        )
+        .await;
+        let addrs: Vec<SocketAddr> = match resolved {
+            Ok(Ok(addrs)) => addrs.collect(),
+            _ => {
+                tracing::debug!("Could not resolve {} to check it", domain);
+                Vec::new()
+            }
+        };
+        if addrs
+            .iter()
+            .any(|addr| host_rules::is_blocked(rules(), &host_rules::ip_host(addr.ip())))
+        {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "Links to {} are not allowed, as it resolves to an internal address",
+                    host
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Configured rules first, then those added at runtime.
+pub async fn list_host_rules(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Result<Json<Page<HostRule>>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let mut rules: Vec<HostRule> = configured_host_rules(&settings)
+        .map(|(list, pattern)| HostRule {
+            pattern: pattern.to_string(),
+            list: list.as_str().to_string(),
+            created_by: None,
+            created_at: None,
+        })
+        .collect();
+    rules.extend(stored_host_rules(&db).await.map_err(internal_error)?);
+
+    Ok(Json(Page::complete(rules)))
+}
+
+/// Block or allow a host, or move it to the other list. Links already
+/// pointing at it are left alone. Networks take their slash percent
+/// encoded, as in `10.0.0.0%2F8`.
+#[tracing::instrument(name = "Set host rule", skip(inner, admin, change))]
+pub async fn set_host_rule(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(pattern): Path<String>,
+    Json(change): Json<HostRuleChange>,
+) -> Result<Json<HostRule>, Response> {
+    let InnerState { db, .. } = inner;
+
+    let Some(parsed) = HostPattern::parse(&pattern) else {
+        let mut errors = ValidationErrors::default();
+        errors.add(
+            "pattern",
+            "should be a domain, an IP address or a CIDR network",
+        );
+        return Err(errors.into_response());
+    };
+    let pattern = parsed.to_string();
+
+    let mut transaction = db
+        .begin()
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+
+    let before: Option<String> =
+        sqlx::query_scalar(r#"SELECT list FROM host_rules WHERE pattern = $1 FOR UPDATE"#)
+            .bind(&pattern)
+            .fetch_optional(&mut *transaction)
+            .await
+            .map_err(|err| internal_error(err).into_response())?;
+
+    let rule = sqlx::query_as::<_, HostRule>(
+        r#"INSERT INTO host_rules (pattern, list, created_by) VALUES ($1, $2, $3)
+        ON CONFLICT (pattern) DO UPDATE SET list = excluded.list
+        RETURNING *"#,
+    )
+    .bind(&pattern)
+    .bind(change.list.as_str())
+    .bind(&admin.claims.sub)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|err| internal_error(err).into_response())?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "host_rule.set",
+        Some(&pattern),
+        before.map(|list| json!({ "list": list })),
+        Some(json!({ "list": rule.list })),
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+
+    Ok(Json(rule))
+}
+
+/// Drop a host added at runtime from its list. Configured ones stay until
+/// the configuration changes.
+#[tracing::instrument(name = "Delete host rule", skip(inner, admin))]
+pub async fn delete_host_rule(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(pattern): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let pattern = HostPattern::parse(&pattern)
+        .map(|pattern| pattern.to_string())
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let before: String =
+        sqlx::query_scalar(r#"DELETE FROM host_rules WHERE pattern = $1 RETURNING list"#)
+            .bind(&pattern)
+            .fetch_optional(&mut *transaction)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "host_rule.delete",
+        Some(&pattern),
+        Some(json!({ "list": before })),
+        None,
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,69 @@
+use crate::authentication::AdminUser;
+use crate::casing::Json;
+use crate::leader::{self, REPLICA_ID, SCHEDULER_LEASE};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::FromRow;
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderLease {
+    pub holder: String,
+    pub acquired_at: NaiveDateTime,
+    pub heartbeat_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub expired: bool,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct JobLease {
+    pub name: String,
+    pub holder: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderStatus {
+    /// The replica answering this request.
+    pub replica: String,
+    pub is_leader: bool,
+    pub leader: Option<LeaderLease>,
+    pub jobs: Vec<JobLease>,
+}
+
+pub async fn leader_status(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Result<Json<LeaderStatus>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let leader = sqlx::query_as::<_, LeaderLease>(
+        r#"SELECT holder, acquired_at, heartbeat_at, expires_at,
+            expires_at < CURRENT_TIMESTAMP as expired
+        FROM leader_leases WHERE name = $1"#,
+    )
+    .bind(SCHEDULER_LEASE)
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?;
+
+    let jobs = sqlx::query_as::<_, JobLease>(r#"SELECT * FROM job_leases ORDER BY name"#)
+        .fetch_all(&db)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(LeaderStatus {
+        replica: REPLICA_ID.to_string(),
+        is_leader: leader::is_leader(),
+        leader,
+        jobs,
+    }))
+}
@@ -0,0 +1,152 @@
+//! How an organization's links perform against each other, from the
+//! statistics rollup.
+
+use crate::authentication::Claims;
+use crate::db::links;
+use crate::routes::{cached_statistics, require_organization_role, Cached, StatisticsCacheKey};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_COMPARISON_DAYS: i64 = 7;
+const MAX_COMPARISON_DAYS: i64 = 90;
+
+const DEFAULT_COMPARISON_LIMIT: i64 = 100;
+const MAX_COMPARISON_LIMIT: i64 = 1000;
+
+/// Relative change below which a link counts as holding steady.
+const FLAT_CHANGE: f64 = 0.05;
+
+#[derive(Debug, Deserialize)]
+pub struct ComparisonQuery {
+    /// Length of the period compared with the one before, ending today.
+    pub days: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Trend {
+    Up,
+    Down,
+    Flat,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComparisonRow {
+    pub rank: usize,
+    pub id: String,
+    pub target_url: String,
+    pub clicks: i64,
+    /// Fraction of the organization's clicks in the period.
+    pub share: f64,
+    pub previous_clicks: i64,
+    /// Relative change from the previous period, unless it had no clicks.
+    pub change: Option<f64>,
+    pub trend: Trend,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkComparison {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub total_clicks: i64,
+    pub links: Vec<ComparisonRow>,
+}
+
+/// The organization's links ranked by clicks over the last `days` days,
+/// with each link's share of them and its trend against the days before.
+#[tracing::instrument(name = "Compare organization links", skip(inner, claims))]
+pub async fn compare_organization_links(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+    Query(query): Query<ComparisonQuery>,
+) -> Result<Cached<LinkComparison>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "member").await?;
+
+    let days = query
+        .days
+        .unwrap_or(DEFAULT_COMPARISON_DAYS)
+        .clamp(1, MAX_COMPARISON_DAYS);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_COMPARISON_LIMIT)
+        .clamp(1, MAX_COMPARISON_LIMIT);
+
+    let to = chrono::Utc::now().date_naive();
+    let from = to - chrono::Duration::days(days - 1);
+    let previous_from = from - chrono::Duration::days(days);
+
+    let key = StatisticsCacheKey::organization("comparison", &organization_id, (from, limit));
+
+    cached_statistics(key, || async {
+        let compared = links::compare_organization_links(
+            &db,
+            &organization_id,
+            previous_from,
+            from,
+            to,
+            limit,
+        )
+        .await
+        .map_err(internal_error)?;
+
+        Ok(rank_links(from, to, compared))
+    })
+    .await
+}
+
+fn rank_links(
+    from: NaiveDate,
+    to: NaiveDate,
+    compared: Vec<links::ComparedLink>,
+) -> LinkComparison {
+    let total_clicks = compared.first().map_or(0, |link| link.total_clicks);
+
+    let links = compared
+        .into_iter()
+        .enumerate()
+        .map(|(index, link)| {
+            let change = (link.previous_clicks > 0)
+                .then(|| (link.clicks - link.previous_clicks) as f64 / link.previous_clicks as f64);
+            let trend = match change {
+                Some(change) if change > FLAT_CHANGE => Trend::Up,
+                Some(change) if change < -FLAT_CHANGE => Trend::Down,
+                Some(_) => Trend::Flat,
+                None if link.clicks > 0 => Trend::Up,
+                None => Trend::Flat,
+            };
+
+            ComparisonRow {
+                rank: index + 1,
+                share: if total_clicks > 0 {
+                    link.clicks as f64 / total_clicks as f64
+                } else {
+                    0.0
+                },
+                id: link.id,
+                target_url: link.target_url,
+                clicks: link.clicks,
+                previous_clicks: link.previous_clicks,
+                change,
+                trend,
+            }
+        })
+        .collect();
+
+    LinkComparison {
+        from,
+        to,
+        total_clicks,
+        links,
+    }
+}
@@ -0,0 +1,55 @@
+use crate::db::links;
+use crate::jobs;
+use crate::link_changes::announce_link_changes;
+
+use sqlx::PgPool;
+
+/// How often expired links are looked for.
+const EXPIRATION_JOB_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(60 * 60);
+
+/// How long an expired link keeps answering 410 Gone before it is deleted
+/// and its id may be taken again.
+const EXPIRED_LINK_RETENTION_DAYS: i32 = 30;
+
+/// Links deleted per transaction, so one run never holds too many locks.
+const EXPIRATION_BATCH_SIZE: i64 = 1_000;
+
+pub async fn run_link_expiration_job(db: PgPool) {
+    jobs::run_periodically(
+        db,
+        "link_expiration",
+        EXPIRATION_JOB_INTERVAL,
+        purge_expired_links,
+    )
+    .await
+}
+
+/// Delete links that expired over the retention period ago, except those a
+/// group playlist points at.
+async fn purge_expired_links(db: PgPool) -> Result<(), sqlx::Error> {
+    loop {
+        let mut transaction = db.begin().await?;
+
+        let link_ids: Vec<String> = sqlx::query_scalar(
+            r#"SELECT id FROM links
+            WHERE expires_at < CURRENT_TIMESTAMP - make_interval(days => $1)
+            AND NOT EXISTS (SELECT 1 FROM group_playlists WHERE group_playlists.link_id = links.id)
+            ORDER BY expires_at LIMIT $2 FOR UPDATE SKIP LOCKED"#,
+        )
+        .bind(EXPIRED_LINK_RETENTION_DAYS)
+        .bind(EXPIRATION_BATCH_SIZE)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        if link_ids.is_empty() {
+            return Ok(());
+        }
+
+        links::hard_delete(&mut transaction, &link_ids).await?;
+        announce_link_changes(&mut *transaction, &link_ids).await?;
+
+        transaction.commit().await?;
+
+        tracing::info!("Purged {} expired links", link_ids.len());
+    }
+}
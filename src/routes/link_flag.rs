@@ -0,0 +1,196 @@
+//! Links whose target the URL scanner found unsafe, waiting for operators
+//! to look at them. Targets found unsafe are refused instead when
+//! `REJECT_UNSAFE_LINKS` is set.
+
+use crate::authentication::AdminUser;
+use crate::casing::Json;
+use crate::configuration::Settings;
+use crate::db::links;
+use crate::link_changes::forget_link;
+use crate::link_state::LinkState;
+use crate::pagination::Page;
+use crate::routes::record_admin_action;
+use crate::url_scanner::UrlScanner;
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkFlag {
+    pub id: String,
+    pub link_id: String,
+    /// The target when it was flagged, which it may have moved on from.
+    pub target_url: String,
+    pub threats: Vec<String>,
+    pub flagged_at: Option<NaiveDateTime>,
+    pub reviewed_at: Option<NaiveDateTime>,
+    pub reviewed_by: Option<String>,
+    /// `dismissed` or `disabled`, once reviewed.
+    pub resolution: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlagResolution {
+    /// The target is fine; the link stays as it is.
+    Dismissed,
+    /// Stop the link from redirecting.
+    Disabled,
+}
+
+impl FlagResolution {
+    fn as_str(self) -> &'static str {
+        match self {
+            FlagResolution::Dismissed => "dismissed",
+            FlagResolution::Disabled => "disabled",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlagReview {
+    pub resolution: FlagResolution,
+}
+
+/// The threats the scanner knows the target for, refusing it when unsafe
+/// targets are. A scanner failing lets the target through, so an outage
+/// of it never stops links from being made.
+pub async fn scan_target(
+    url_scanner: &UrlScanner,
+    settings: &Settings,
+    target_url: &str,
+) -> Result<Vec<String>, (StatusCode, String)> {
+    let threats = match url_scanner.scan(target_url).await {
+        Ok(threats) => threats,
+        Err(err) => {
+            tracing::warn!("Could not scan {}: {}", target_url, err);
+            return Ok(Vec::new());
+        }
+    };
+
+    if !threats.is_empty() && settings.reject_unsafe_links {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Target is known for {}", threats.join(", ").to_lowercase()),
+        ));
+    }
+
+    Ok(threats)
+}
+
+/// Flag the link for review when its target was found unsafe.
+pub async fn flag_link<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_id: &str,
+    target_url: &str,
+    threats: &[String],
+) -> Result<(), sqlx::Error> {
+    if threats.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(
+        r#"INSERT INTO link_flags (id, link_id, target_url, threats) VALUES ($1, $2, $3, $4)"#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(link_id)
+    .bind(target_url)
+    .bind(threats)
+    .execute(executor)
+    .await?;
+
+    tracing::warn!("Flagged link {} for {}", link_id, threats.join(", "));
+
+    Ok(())
+}
+
+/// Flags not reviewed yet, oldest first.
+pub async fn list_link_flags(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Result<Json<Page<LinkFlag>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let flags = sqlx::query_as::<_, LinkFlag>(
+        r#"SELECT * FROM link_flags WHERE reviewed_at IS NULL ORDER BY flagged_at, id"#,
+    )
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(flags)))
+}
+
+/// Dismiss a flag, or disable its link. Other flags of the link are left
+/// for review.
+#[tracing::instrument(name = "Review link flag", skip(inner, admin, review))]
+pub async fn review_link_flag(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(flag_id): Path<String>,
+    Json(review): Json<FlagReview>,
+) -> Result<Json<LinkFlag>, (StatusCode, String)> {
+    let InnerState {
+        db,
+        redirect_cache,
+        redirects,
+        ..
+    } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let flag = sqlx::query_as::<_, LinkFlag>(
+        r#"UPDATE link_flags SET reviewed_at = CURRENT_TIMESTAMP, reviewed_by = $2,
+            resolution = $3
+        WHERE id = $1 AND reviewed_at IS NULL
+        RETURNING *"#,
+    )
+    .bind(&flag_id)
+    .bind(&admin.claims.sub)
+    .bind(review.resolution.as_str())
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let mut disabled_from = None;
+    if let FlagResolution::Disabled = review.resolution {
+        let from = links::lock_state(&mut transaction, &flag.link_id)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+        // Drafts and links disabled or deleted already do not redirect.
+        if from.can_transition_to(LinkState::Disabled) {
+            links::set_state(&mut *transaction, &flag.link_id, LinkState::Disabled)
+                .await
+                .map_err(internal_error)?;
+            disabled_from = Some(from);
+        }
+    }
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "link_flag.review",
+        Some(&flag.link_id),
+        disabled_from.map(|from| serde_json::json!({ "state": from })),
+        serde_json::to_value(&flag).ok(),
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    if disabled_from.is_some() {
+        forget_link(&db, &redirects, &redirect_cache, &flag.link_id).await;
+    }
+
+    Ok(Json(flag))
+}
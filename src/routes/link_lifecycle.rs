@@ -0,0 +1,88 @@
+use crate::authentication::{AdminUser, Claims};
+use crate::casing::Json;
+use crate::client_ip::ClientIp;
+use crate::db::links::{self, Link};
+use crate::link_changes::forget_link;
+use crate::link_state::LinkState;
+use crate::policy::Action;
+use crate::routes::{authorize_link, record_admin_action};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkTransition {
+    pub state: LinkState,
+}
+
+/// Move one of the caller's links to another state, such as pausing it or
+/// publishing a draft. Disabling links and enabling disabled ones is up to
+/// operators, who may do so on any link and have it audited.
+#[tracing::instrument(name = "Transition link", skip(inner, claims, client))]
+pub async fn transition_link(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    client: ClientIp,
+    Path(link_id): Path<String>,
+    Json(transition): Json<LinkTransition>,
+) -> Result<Json<Link>, (StatusCode, String)> {
+    let InnerState {
+        db,
+        redirect_cache,
+        redirects,
+        ..
+    } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::TransitionLink).await?;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let from = links::lock_state(&mut transaction, &link_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+    let to = transition.state;
+
+    if !from.can_transition_to(to) {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Link cannot go from {} to {}", from.as_str(), to.as_str()),
+        ));
+    }
+
+    let by_operator = from.is_operator_only() || to.is_operator_only();
+    if by_operator && !claims.is_admin() {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    let link = links::set_state(&mut *transaction, &link_id, to)
+        .await
+        .map_err(internal_error)?;
+
+    if by_operator {
+        let admin = AdminUser {
+            claims,
+            ip: Some(client.ip.to_string()),
+        };
+        record_admin_action(
+            &mut *transaction,
+            &admin,
+            "link.transition",
+            Some(&link_id),
+            Some(json!({ "state": from })),
+            Some(json!({ "state": to })),
+        )
+        .await?;
+    }
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    forget_link(&db, &redirects, &redirect_cache, &link_id).await;
+
+    Ok(Json(link))
+}
@@ -0,0 +1,81 @@
+//! Previews of where links lead, with the title, description and image of
+//! the target page, so frontends can show link cards without fetching
+//! across origins themselves.
+
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::db::links::{self, LinkPreview};
+use crate::link_payload::URL_KIND;
+use crate::open_graph;
+use crate::policy::Action;
+use crate::routes::authorize_link;
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use sqlx::PgPool;
+
+/// How long a fetched preview is shown before the page is fetched again.
+const PREVIEW_MAX_AGE_HOURS: i32 = 7 * 24;
+
+/// Fetch the preview of a link's new target in the background, so creating
+/// or updating the link does not wait on someone else's server.
+pub fn refresh_link_preview(db: PgPool, link_id: String, target_url: String) {
+    tokio::spawn(async move {
+        let preview = match open_graph::fetch_preview(&target_url).await {
+            Ok(preview) => preview,
+            Err(err) => {
+                tracing::info!("No preview of link {}: {}", link_id, err);
+                return;
+            }
+        };
+
+        if let Err(err) = links::store_preview(&db, &link_id, &target_url, &preview).await {
+            tracing::error!("Could not store the preview of link {}: {}", link_id, err);
+        }
+    });
+}
+
+/// The preview of the link's target, fetched again first when it is older
+/// than a week. A stale preview is still shown while the page cannot be
+/// fetched. Links carrying a payload have none.
+#[tracing::instrument(name = "Link preview", skip(inner, claims))]
+pub async fn link_preview(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+) -> Result<Json<LinkPreview>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::ReadLink).await?;
+
+    let mut preview = links::preview(&db, &link_id, PREVIEW_MAX_AGE_HOURS)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+    if !preview.stale || preview.kind != URL_KIND {
+        return Ok(Json(preview));
+    }
+
+    match open_graph::fetch_preview(&preview.target_url).await {
+        Ok(page) => {
+            let fetched_at = links::store_preview(&db, &link_id, &preview.target_url, &page)
+                .await
+                .map_err(internal_error)?;
+            preview.preview = page;
+            preview.fetched_at = fetched_at.or(preview.fetched_at);
+        }
+        Err(err) if preview.fetched_at.is_some() => {
+            tracing::info!("Showing a stale preview of link {}: {}", link_id, err);
+        }
+        Err(err) => {
+            return Err((
+                StatusCode::BAD_GATEWAY,
+                format!("Could not fetch a preview: {}", err),
+            ))
+        }
+    }
+
+    Ok(Json(preview))
+}
@@ -0,0 +1,274 @@
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::db::links;
+use crate::link_changes::forget_link;
+use crate::policy::Action;
+use crate::redirect_response::{
+    DEFAULT_CACHE_CONTROL_HEADER_VALUE, PRIVATE_CACHE_CONTROL_HEADER_VALUE,
+};
+use crate::routes::{authorize_link, consent_from_cookie, require_allowed_host};
+use crate::routing_rules::{self, Device, RoutingRule, Visitor};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::header::{ACCEPT_LANGUAGE, USER_AGENT};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use url::Url;
+
+/// Rules a link may have, each tried on every redirect.
+const MAX_ROUTING_RULES: usize = 50;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingRules {
+    /// Tried in order, the first holding picking the target.
+    pub rules: Vec<RoutingRule>,
+}
+
+impl Validate for RoutingRules {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if self.rules.len() > MAX_ROUTING_RULES {
+            errors.add(
+                "rules",
+                format!("must hold at most {} rules", MAX_ROUTING_RULES),
+            );
+        }
+
+        for rule in &self.rules {
+            errors.require_link_target("rules[].targetUrl", &rule.target_url);
+            if rule.when.country.iter().any(|country| {
+                country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic())
+            }) {
+                errors.add("rules[].when.country", "must be two letter country codes");
+            }
+            if rule.when.language.iter().any(|language| {
+                language.is_empty() || !language.chars().all(|c| c.is_ascii_alphabetic())
+            }) {
+                errors.add("rules[].when.language", "must be primary language subtags");
+            }
+            if rule
+                .when
+                .time
+                .is_some_and(|window| window.from == window.to)
+            {
+                errors.add("rules[].when.time", "must not be empty");
+            }
+        }
+    }
+}
+
+#[tracing::instrument(name = "Get link routing rules", skip(inner, claims))]
+pub async fn link_routing_rules(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+) -> Result<Json<RoutingRules>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::ReadLink).await?;
+
+    let rules = links::routing_rules(&db, &link_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    Ok(Json(RoutingRules { rules }))
+}
+
+/// Replace a link's routing rules. An empty list sends every visitor to
+/// the link's target again. Where redirects are served from the snapshot,
+/// the change applies once it is next rebuilt.
+#[tracing::instrument(name = "Set link routing rules", skip(inner, claims, rules))]
+pub async fn set_link_routing_rules(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+    Valid(mut rules): Valid<RoutingRules>,
+) -> Result<Json<RoutingRules>, (StatusCode, String)> {
+    let InnerState {
+        db,
+        redirect_cache,
+        redirects,
+        settings,
+        ..
+    } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::RouteLink).await?;
+
+    // Stored as links store their target, which validation checked parses.
+    for rule in &mut rules.rules {
+        if let Ok(url) = Url::parse(&rule.target_url) {
+            require_allowed_host(&db, &settings, &url).await?;
+            rule.target_url = url.to_string();
+        }
+    }
+
+    let updated = links::set_routing_rules(&db, &link_id, &rules.rules)
+        .await
+        .map_err(internal_error)?;
+    if !updated {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    forget_link(&db, &redirects, &redirect_cache, &link_id).await;
+
+    Ok(Json(rules))
+}
+
+/// A visitor to simulate following a link, every attribute optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VisitorSimulation {
+    pub user_agent: Option<String>,
+    /// Country code, as the CDN would report it.
+    pub country: Option<String>,
+    pub accept_language: Option<String>,
+    /// Time of day in UTC, now when absent.
+    pub time: Option<NaiveTime>,
+    /// Query string the short link is followed with, without the `?`.
+    pub query: Option<String>,
+    /// Any other request headers, such as `Cookie` for a visitor who
+    /// already answered the consent prompt.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// What the rules were evaluated against.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedVisitor {
+    pub country: Option<String>,
+    pub device: Device,
+    pub language: Option<String>,
+    pub time: NaiveTime,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedirectSimulation {
+    /// Status of the response the visitor would get.
+    pub status: u16,
+    /// Where the visitor would be sent, when redirected.
+    pub location: Option<String>,
+    pub cache_control: Option<&'static str>,
+    /// Position of the rule picking the target, absent when none holds.
+    pub matched_rule: Option<usize>,
+    pub rule: Option<RoutingRule>,
+    /// Whether the visitor would first be asked for tracking consent.
+    pub consent_required: bool,
+    pub visitor: SimulatedVisitor,
+}
+
+/// Evaluate a link's routing rules for a made up visitor, answering with
+/// what a redirect would do, without counting a click.
+#[tracing::instrument(name = "Simulate link redirect", skip(inner, claims))]
+pub async fn simulate_link_redirect(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+    Json(simulation): Json<VisitorSimulation>,
+) -> Result<Json<RedirectSimulation>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::ReadLink).await?;
+
+    let headers = simulated_headers(&simulation)?;
+    let country = simulation
+        .country
+        .as_deref()
+        .map(|country| country.trim().to_ascii_uppercase());
+    let mut visitor = Visitor::new(&headers, simulation.query.as_deref(), country.as_deref());
+    if let Some(time) = simulation.time {
+        visitor.time = time;
+    }
+
+    let simulated_visitor = SimulatedVisitor {
+        country: visitor.country.map(str::to_string),
+        device: visitor.device,
+        language: visitor.language.clone(),
+        time: visitor.time,
+    };
+
+    let Some(link) = links::find_redirect_target(&db, &link_id)
+        .await
+        .map_err(internal_error)?
+    else {
+        let gone = links::is_gone(&db, &link_id)
+            .await
+            .map_err(internal_error)?;
+        let status = if gone {
+            StatusCode::GONE
+        } else {
+            StatusCode::NOT_FOUND
+        };
+
+        return Ok(Json(RedirectSimulation {
+            status: status.as_u16(),
+            location: None,
+            cache_control: None,
+            matched_rule: None,
+            rule: None,
+            consent_required: false,
+            visitor: simulated_visitor,
+        }));
+    };
+
+    let matched = routing_rules::matching_rule(&link.routing_rules, &visitor);
+    let location = matched.map_or(link.target_url.as_str(), |(_, rule)| {
+        rule.target_url.as_str()
+    });
+    let cache_control = if link.routing_rules.is_empty() {
+        DEFAULT_CACHE_CONTROL_HEADER_VALUE
+    } else {
+        PRIVATE_CACHE_CONTROL_HEADER_VALUE
+    };
+    let consent_required =
+        settings.require_tracking_consent && consent_from_cookie(&headers).is_none();
+
+    Ok(Json(RedirectSimulation {
+        status: if consent_required {
+            StatusCode::OK.as_u16()
+        } else {
+            StatusCode::TEMPORARY_REDIRECT.as_u16()
+        },
+        location: (!consent_required).then(|| location.to_string()),
+        cache_control: (!consent_required).then_some(cache_control),
+        matched_rule: matched.map(|(index, _)| index),
+        rule: matched.map(|(_, rule)| rule.clone()),
+        consent_required,
+        visitor: simulated_visitor,
+    }))
+}
+
+/// The request headers the simulated visitor would send.
+fn simulated_headers(simulation: &VisitorSimulation) -> Result<HeaderMap, (StatusCode, String)> {
+    let invalid = |name: &str| (StatusCode::BAD_REQUEST, format!("Invalid header {}", name));
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in &simulation.headers {
+        headers.append(
+            HeaderName::try_from(name.as_str()).map_err(|_| invalid(name))?,
+            HeaderValue::try_from(value.as_str()).map_err(|_| invalid(name))?,
+        );
+    }
+    if let Some(user_agent) = &simulation.user_agent {
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::try_from(user_agent.as_str()).map_err(|_| invalid("User-Agent"))?,
+        );
+    }
+    if let Some(accept_language) = &simulation.accept_language {
+        headers.insert(
+            ACCEPT_LANGUAGE,
+            HeaderValue::try_from(accept_language.as_str())
+                .map_err(|_| invalid("Accept-Language"))?,
+        );
+    }
+
+    Ok(headers)
+}
@@ -0,0 +1,267 @@
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::db::links::{self, NewScheduledChange, ScheduledChange};
+use crate::jobs;
+use crate::link_changes::announce_link_changes;
+use crate::pagination::Page;
+use crate::policy::Action;
+use crate::routes::{authorize_link, flag_link, require_allowed_host, scan_target};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{FromRow, PgPool};
+use url::Url;
+
+/// How often due target changes are looked for.
+const SCHEDULED_CHANGES_JOB_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(60);
+
+/// Changes applied per transaction, so one run never holds too many locks.
+const SCHEDULED_CHANGES_BATCH_SIZE: i64 = 100;
+
+/// Name of the job applying due changes.
+pub const SCHEDULED_CHANGES_JOB: &str = "link_scheduled_changes";
+
+/// Who the audit log names as having applied a scheduled change.
+const SCHEDULER_ACTOR: &str = "scheduler";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleLinkChange {
+    pub change_at: NaiveDateTime,
+    pub target_url: String,
+}
+
+impl Validate for ScheduleLinkChange {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_link_target("targetUrl", &self.target_url);
+        errors.require_future("changeAt", self.change_at);
+    }
+}
+
+/// Switch one of the caller's links to a new target at a later time, such
+/// as from the registration form to the recording once an event is over.
+#[tracing::instrument(name = "Schedule link change", skip(inner, claims))]
+pub async fn schedule_link_change(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+    Valid(change): Valid<ScheduleLinkChange>,
+) -> Result<(StatusCode, Json<ScheduledChange>), (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::ScheduleLink).await?;
+
+    // Stored as links store their target, which validation checked parses.
+    let target_url = Url::parse(&change.target_url)
+        .map_err(|_| (StatusCode::CONFLICT, "Url malformed".into()))?;
+    require_allowed_host(&db, &settings, &target_url).await?;
+    let target_url = target_url.to_string();
+
+    let scheduled = links::schedule_change(
+        &db,
+        &NewScheduledChange {
+            link_id: &link_id,
+            target_url: &target_url,
+            change_at: change.change_at,
+            created_by: &claims.sub,
+        },
+    )
+    .await
+    .map_err(internal_error)?;
+
+    Ok((StatusCode::CREATED, Json(scheduled)))
+}
+
+/// The changes scheduled for one of the caller's links, those already
+/// applied included.
+#[tracing::instrument(name = "List link scheduled changes", skip(inner, claims))]
+pub async fn link_scheduled_changes(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+) -> Result<Json<Page<ScheduledChange>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::ReadLink).await?;
+
+    let changes = links::scheduled_changes(&db, &link_id)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(changes)))
+}
+
+#[tracing::instrument(name = "Cancel link scheduled change", skip(inner, claims))]
+pub async fn cancel_link_scheduled_change(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((link_id, change_id)): Path<(String, i32)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::ScheduleLink).await?;
+
+    let cancelled = links::cancel_scheduled_change(&db, &link_id, change_id)
+        .await
+        .map_err(internal_error)?;
+    if !cancelled {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn run_scheduled_link_changes_job(inner: InnerState) {
+    jobs::run_periodically(
+        inner.db.clone(),
+        SCHEDULED_CHANGES_JOB,
+        SCHEDULED_CHANGES_JOB_INTERVAL,
+        move |db| apply_due_changes(db, inner.clone()),
+    )
+    .await
+}
+
+#[derive(FromRow)]
+struct DueChange {
+    id: i32,
+    link_id: String,
+    target_url: String,
+    change_at: NaiveDateTime,
+    created_by: String,
+}
+
+/// Apply every change whose time has come, oldest first, recording each in
+/// the admin audit log with the target it replaced. Changes to deleted
+/// links are left alone. Targets are checked again as when a link is
+/// changed, and a change whose target is refused now is not applied.
+async fn apply_due_changes(db: PgPool, inner: InnerState) -> Result<(), sqlx::Error> {
+    loop {
+        let mut transaction = db.begin().await?;
+
+        let due: Vec<DueChange> = sqlx::query_as(
+            r#"SELECT c.id, c.link_id, c.target_url, c.change_at, c.created_by
+            FROM link_scheduled_changes c JOIN links ON links.id = c.link_id
+            WHERE c.applied_at IS NULL AND c.refused_reason IS NULL
+            AND c.change_at <= localtimestamp AND links.deleted_at IS NULL
+            ORDER BY c.change_at, c.id LIMIT $1 FOR UPDATE OF c SKIP LOCKED"#,
+        )
+        .bind(SCHEDULED_CHANGES_BATCH_SIZE)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        if due.is_empty() {
+            return Ok(());
+        }
+
+        let mut changed = Vec::new();
+        let mut retry_later = false;
+        for change in &due {
+            let threats = match check_target(&inner, &change.target_url).await {
+                Ok(threats) => threats,
+                Err((status, reason)) if status.is_client_error() => {
+                    sqlx::query(
+                        r#"UPDATE link_scheduled_changes SET refused_reason = $1 WHERE id = $2"#,
+                    )
+                    .bind(&reason)
+                    .bind(change.id)
+                    .execute(&mut *transaction)
+                    .await?;
+
+                    tracing::warn!(
+                        "Refused scheduled change {} of link {}: {}",
+                        change.id,
+                        change.link_id,
+                        reason
+                    );
+                    continue;
+                }
+                // Left for the next run, rather than for this one to pick
+                // up again and again.
+                Err((_, reason)) => {
+                    tracing::error!(
+                        "Could not check scheduled change {} of link {}: {}",
+                        change.id,
+                        change.link_id,
+                        reason
+                    );
+                    retry_later = true;
+                    break;
+                }
+            };
+
+            let previous_target_url: String =
+                sqlx::query_scalar(r#"SELECT target_url FROM links WHERE id = $1 FOR UPDATE"#)
+                    .bind(&change.link_id)
+                    .fetch_one(&mut *transaction)
+                    .await?;
+
+            sqlx::query(r#"UPDATE links SET target_url = $1 WHERE id = $2"#)
+                .bind(&change.target_url)
+                .bind(&change.link_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            flag_link(
+                &mut *transaction,
+                &change.link_id,
+                &change.target_url,
+                &threats,
+            )
+            .await?;
+
+            sqlx::query(
+                r#"UPDATE link_scheduled_changes SET applied_at = localtimestamp WHERE id = $1"#,
+            )
+            .bind(change.id)
+            .execute(&mut *transaction)
+            .await?;
+
+            sqlx::query(
+                r#"INSERT INTO admin_audit (actor, action, target, before, after) VALUES ($1, $2, $3, $4, $5)"#,
+            )
+            .bind(SCHEDULER_ACTOR)
+            .bind("link.scheduled_change")
+            .bind(&change.link_id)
+            .bind(json!({ "targetUrl": previous_target_url }))
+            .bind(json!({
+                "targetUrl": change.target_url,
+                "scheduledChangeId": change.id,
+                "changeAt": change.change_at,
+                "scheduledBy": change.created_by,
+            }))
+            .execute(&mut *transaction)
+            .await?;
+
+            changed.push(change.link_id.clone());
+        }
+
+        announce_link_changes(&mut *transaction, &changed).await?;
+
+        transaction.commit().await?;
+
+        tracing::info!("Applied {} scheduled link changes", changed.len());
+
+        if retry_later {
+            return Ok(());
+        }
+    }
+}
+
+/// Check a target as when a link is changed, since host rules and what the
+/// scanner knows may have changed since the change was scheduled.
+async fn check_target(
+    inner: &InnerState,
+    target_url: &str,
+) -> Result<Vec<String>, (StatusCode, String)> {
+    let url =
+        Url::parse(target_url).map_err(|_| (StatusCode::CONFLICT, "Url malformed".to_string()))?;
+    require_allowed_host(&inner.db, &inner.settings, &url).await?;
+
+    scan_target(&inner.url_scanner, &inner.settings, target_url).await
+}
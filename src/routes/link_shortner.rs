@@ -1,43 +1,253 @@
+use crate::authentication::{AdminUser, Claims};
+use crate::casing::Json;
+use crate::click_buffer::BufferedClick;
+use crate::client_ip::ClientIp;
+use crate::db::links::{
+    self, CounterLinkStatistics, Link, LinkListFilter, LinkListKey, NewLink, RecordedClick,
+    RedirectBehavior, StatisticsKey, StatisticsPage,
+};
+use crate::link_changes::forget_link;
+use crate::link_payload::{LinkPayload, URL_KIND};
+use crate::pagination::{Page, Pagination};
+use crate::policy::{Action, Resource};
+use crate::redirect_response::{
+    cached_for, etag_matches, location, not_modified, private, target_etag, temporary_redirect,
+};
+use crate::routes::{
+    active_domain_defaults, authorize_link, authorize_organization, cached_statistics,
+    charge_link_quota, consent_from_cookie, consent_interstitial, enqueue_new_link, flag_link,
+    is_reserved_slug, payload_page, queue_pending_action, record_admin_action,
+    refresh_link_preview, render_link_page, render_page, require_allowed_host, require_link_access,
+    require_token_policies, require_usable_custom_id, scan_target, send_quota_warning, wake_outbox,
+    Cached, DestructiveAction, PageKind, PendingAction, QuotaWarning, StatisticsCacheKey,
+    TrackingConsent,
+};
+use crate::routing_rules::{self, Visitor};
+use crate::user_agent;
 use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors, MIN_PREMIUM_SLUG_LENGTH};
 use crate::InnerState;
 
-use axum::body::Body;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, RawQuery, State};
+use axum::http::header::{REFERER, USER_AGENT};
 use axum::http::{HeaderMap, StatusCode};
-use axum::response::{IntoResponse, Response};
-use axum::Json;
+use axum::response::Response;
 use base64::engine::general_purpose;
 use base64::Engine;
+use chrono::{NaiveDate, NaiveDateTime};
 use rand::Rng;
-use sqlx::{FromRow, PgPool, Row};
-use std::sync::Arc;
+use sqlx::{FromRow, Postgres, Transaction};
 use url::Url;
 
-const DEFAULT_CACHE_CONTROL_HEADER_VALUE: &str =
-    "public, max-age=300, s-maxage=300, state-while-revalidate=300, stale-if-error=300";
+const MAX_CLICK_SAMPLE_RATE: i32 = 10_000;
 
-#[derive(serde::Deserialize, serde::Serialize, FromRow)]
-#[serde(rename_all = "camelCase")]
-pub struct Link {
-    pub id: String,
-    pub target_url: String,
-}
+const REDIRECT_STATUSES: [i32; 4] = [301, 302, 307, 308];
+
+pub const MAX_LINK_TAGS: usize = 20;
+pub const MAX_TAG_LENGTH: usize = 50;
+pub const MAX_CAMPAIGN_LENGTH: usize = 100;
+
+/// A year, as long as any cache is told to keep a response.
+pub const MAX_CACHE_TTL_SECONDS: i32 = 365 * 24 * 60 * 60;
+
+const DEFAULT_STATISTICS_LIMIT: i64 = 500;
+const MAX_STATISTICS_LIMIT: i64 = 1000;
+
+const DEFAULT_LINK_LIST_LIMIT: i64 = 50;
+const MAX_LINK_LIST_LIMIT: i64 = 200;
+
+const DEFAULT_TAIL_LENGTH: i64 = 100;
+const MAX_TAIL_LENGTH: i64 = 1000;
 
 #[derive(serde::Deserialize, FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct LinkTarget {
+    /// Left out for links carrying a payload.
+    #[serde(default)]
     pub target_url: String,
+    pub organization_id: Option<String>,
+    /// A vanity id to use instead of a random one. Ignored on updates.
+    #[sqlx(default)]
+    pub custom_id: Option<String>,
+    /// When the link stops redirecting. Updates without it make the link
+    /// never expire.
+    #[sqlx(default)]
+    pub expires_at: Option<NaiveDateTime>,
+    /// A contact card or Wi-Fi network to show instead of redirecting.
+    /// Only taken on creation.
+    #[sqlx(skip)]
+    pub payload: Option<LinkPayload>,
+    /// Create the link as a draft, not redirecting until it is moved to
+    /// `active`. Ignored on updates.
+    #[serde(default)]
+    #[sqlx(skip)]
+    pub draft: bool,
+    /// An active custom domain of the organization to create the link
+    /// under, whose defaults fill in the behavior left unset. Ignored on
+    /// updates, like the behavior.
+    #[sqlx(skip)]
+    pub domain: Option<String>,
+    #[serde(flatten)]
+    #[sqlx(skip)]
+    pub behavior: RedirectBehavior,
+    /// Labels policies may tell the link apart by, like its campaign.
+    /// Updates replace them, leaving none when left out.
+    #[serde(default)]
+    #[sqlx(skip)]
+    pub tags: Vec<String>,
+    #[sqlx(skip)]
+    pub campaign: Option<String>,
+}
+
+impl LinkTarget {
+    fn resource<'a>(&'a self, domain: Option<&'a str>) -> Resource<'a> {
+        Resource {
+            tags: &self.tags,
+            campaign: self.campaign.as_deref(),
+            domain,
+        }
+    }
+}
+
+impl Validate for LinkTarget {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        match &self.payload {
+            Some(payload) => {
+                payload.validate(errors);
+                if !self.target_url.is_empty() {
+                    errors.add("targetUrl", "must be left out for links with a payload");
+                }
+            }
+            None => errors.require_link_target("targetUrl", &self.target_url),
+        }
+        if let Some(custom_id) = &self.custom_id {
+            // How short it may be depends on it being a premium slug,
+            // checked on creation.
+            errors.require_slug("customId", custom_id, MIN_PREMIUM_SLUG_LENGTH);
+            if is_reserved_slug(custom_id) {
+                errors.add("customId", "is reserved");
+            }
+        }
+        if let Some(expires_at) = self.expires_at {
+            errors.require_future("expiresAt", expires_at);
+        }
+        if self.domain.is_some() && self.organization_id.is_none() {
+            errors.add("domain", "needs an organizationId");
+        }
+        if self.tags.len() > MAX_LINK_TAGS {
+            errors.add("tags", format!("must hold at most {} tags", MAX_LINK_TAGS));
+        }
+        for tag in &self.tags {
+            errors.require_not_blank("tags[]", tag);
+            errors.require_max_length("tags[]", tag, MAX_TAG_LENGTH);
+        }
+        if let Some(campaign) = &self.campaign {
+            errors.require_not_blank("campaign", campaign);
+            errors.require_max_length("campaign", campaign, MAX_CAMPAIGN_LENGTH);
+        }
+        self.behavior.validate(errors);
+    }
+}
+
+impl Validate for RedirectBehavior {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if let Some(status) = self.redirect_status {
+            if !REDIRECT_STATUSES.contains(&status) {
+                errors.add("redirectStatus", "must be 301, 302, 307 or 308");
+            }
+        }
+        if let Some(seconds) = self.cache_ttl_seconds {
+            errors.require_range(
+                "cacheTtlSeconds",
+                seconds.into(),
+                0,
+                MAX_CACHE_TTL_SECONDS.into(),
+            );
+        }
+        if let Some(fallback_url) = &self.fallback_url {
+            errors.require_link_target("fallbackUrl", fallback_url);
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkSampling {
+    /// Record one in this many clicks, each standing for all of them.
+    pub click_sample_rate: i32,
+}
+
+impl Validate for LinkSampling {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_range(
+            "clickSampleRate",
+            self.click_sample_rate.into(),
+            1,
+            MAX_CLICK_SAMPLE_RATE.into(),
+        );
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteOptions {
+    /// Check everything that could refuse the change and answer with its
+    /// result, without keeping it. A created link's id is not reserved.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TailQuery {
+    /// How many clicks to return.
+    pub n: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct TailClick {
+    #[serde(flatten)]
+    pub click: RecordedClick,
+    /// Whether the click was a bot's.
+    pub bot: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkListQuery {
+    /// Only links whose target contains this, ignoring case.
+    pub target_url: Option<String>,
+    /// First day of creation, inclusive.
+    pub created_from: Option<NaiveDate>,
+    /// Last day of creation, inclusive.
+    pub created_to: Option<NaiveDate>,
+}
+
+/// The creation time and id of the last link of a page.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LinkListCursor {
+    created_at: Option<NaiveDateTime>,
+    id: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkStatisticsQuery {
+    /// Leave out clicks of bots, such as chat apps previewing the link.
+    #[serde(default)]
+    pub exclude_bots: bool,
 }
 
-#[derive(serde::Serialize, FromRow)]
+/// The last referer and user agent of a page of statistics, which are
+/// returned in that order.
+#[derive(serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct CounterLinkStatistics {
-    pub amount: Option<i64>,
-    pub referer: Option<String>,
-    pub user_agent: Option<String>,
+struct StatisticsCursor {
+    referer: Option<String>,
+    user_agent: Option<String>,
 }
 
-fn generate_id() -> String {
+pub fn generate_id() -> String {
     let random_number = rand::thread_rng().gen_range(0..u32::MAX);
     general_purpose::URL_SAFE_NO_PAD.encode(random_number.to_string())
 }
@@ -45,133 +255,735 @@ fn generate_id() -> String {
 pub async fn redirect(
     State(inner): State<InnerState>,
     Path(requested_link): Path<String>,
+    RawQuery(raw_query): RawQuery,
+    client: ClientIp,
     headers: HeaderMap,
 ) -> Result<Response, (StatusCode, String)> {
-    let InnerState { db, .. } = inner;
+    let InnerState {
+        db,
+        settings,
+        clicks,
+        redirects,
+        redirect_cache,
+        link_filter,
+        visitor_salts,
+        ..
+    } = inner;
 
-    let link = sqlx::query_as::<_, Link>(r#" select id, target_url from links where id = $1"#)
-        .bind(&requested_link)
-        .fetch_optional(&db)
-        .await
-        .map_err(internal_error)?
-        .ok_or_else(|| "Not Found".to_string())
-        .map_err(|err| (StatusCode::NOT_FOUND, err))?;
+    // Even a not found page would query the templates, so skip it too.
+    if !link_filter.might_exist(&requested_link) {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    let link = match redirects.lookup(&requested_link) {
+        Some(link) => Some(link),
+        None => match redirect_cache.get(&requested_link).await {
+            Some(link) => Some(link),
+            None => {
+                let link = links::find_redirect_target(&db, &requested_link)
+                    .await
+                    .map_err(internal_error)?;
+                if let Some(link) = &link {
+                    redirect_cache.put(link).await;
+                }
+                link
+            }
+        },
+    };
+
+    let Some(link) = link else {
+        // Disabled and expired links still exist, so tell visitors they are
+        // gone for good. Deleted ones are not found, as if never created.
+        let gone = links::find_gone_link(&db, &requested_link)
+            .await
+            .map_err(internal_error)?;
+
+        if let Some(fallback_url) = gone.as_ref().and_then(|gone| gone.fallback_url.clone()) {
+            let etag = target_etag(&fallback_url);
+            let location = location(fallback_url).map_err(internal_error)?;
+            return Ok(temporary_redirect(location, etag));
+        }
+
+        let values = [("link_id", requested_link.as_str())];
+        let page = if gone.is_some() {
+            render_link_page(
+                &db,
+                &settings,
+                &headers,
+                PageKind::Gone,
+                &requested_link,
+                &values,
+            )
+            .await
+        } else {
+            render_page(&db, &settings, &headers, PageKind::NotFound, &values).await
+        };
 
-    tracing::debug!(
-        "Redirecting link id {} to {}",
-        requested_link,
+        return Ok(page);
+    };
+
+    // Visits to landing pages are not counted, scans of their QR code never
+    // reaching us anyway.
+    if let Some(payload) = &link.payload {
+        return Ok(payload_page(&db, &settings, &headers, &link.id, payload).await);
+    }
+
+    let country = || client.country(&settings, &headers);
+
+    // Routed links may send each visitor elsewhere, so only the visitor's
+    // own cache may keep the redirect.
+    let routed = !link.routing_rules.is_empty();
+    let target_url = if routed {
+        let country = country();
+        let visitor = Visitor::new(&headers, raw_query.as_deref(), country.as_deref());
+        routing_rules::route(&link.routing_rules, &visitor)
+            .map(str::to_string)
+            .unwrap_or(link.target_url)
+    } else {
         link.target_url
-    );
+    };
+
+    tracing::debug!("Redirecting link id {} to {}", requested_link, target_url);
+
+    let behavior = link.behavior;
+    let interstitial = behavior.interstitial == Some(true);
 
-    let referer_header = headers
-        .get("referer")
-        .map(|value| value.to_str().unwrap_or_default().to_string());
+    // A cache revalidating its copy is not a visitor, so nothing is counted.
+    let etag = target_etag(&target_url);
+    if !interstitial && etag_matches(&headers, &etag) {
+        let response = cache_as_asked(not_modified(etag), &behavior);
+        return Ok(if routed { private(response) } else { response });
+    }
 
-    let user_agent_header = headers
-        .get("user-agent")
-        .map(|value| value.to_str().unwrap_or_default().to_string());
+    let consent = if settings.require_tracking_consent {
+        match consent_from_cookie(&headers) {
+            Some((_, consent)) => consent,
+            None => return Ok(consent_interstitial(&db, &settings, &headers, &link.id).await),
+        }
+    } else {
+        TrackingConsent::Granted
+    };
 
-    let insert_statistics_timeout = tokio::time::Duration::from_millis(1000);
+    let sample_rate = link.click_sample_rate.max(1);
+    if sample_rate == 1 || rand::thread_rng().gen_range(0..sample_rate) == 0 {
+        let (referer_header, user_agent_header, country) = if consent == TrackingConsent::Granted {
+            (
+                headers
+                    .get(REFERER)
+                    .map(|value| value.to_str().unwrap_or_default().to_string()),
+                headers
+                    .get(USER_AGENT)
+                    .map(|value| value.to_str().unwrap_or_default().to_string()),
+                country(),
+            )
+        } else {
+            (None, None, None)
+        };
 
-    let saved_statistics = tokio::time::timeout(
-        insert_statistics_timeout,
-        sqlx::query_as::<_, CounterLinkStatistics>(
-            r#"
-                insert into link_statistics(link_id, referer, user_agent)
-                values($1, $2, $3)
-                "#,
+        let mut click = BufferedClick::new(
+            requested_link,
+            referer_header,
+            user_agent_header,
+            country,
+            sample_rate,
+        );
+        // Bots are told apart whatever the consent, only people having
+        // their browser and system recorded with it.
+        let agent = user_agent::classify(
+            headers
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok()),
+        );
+        click.is_bot = agent.bot;
+        if consent == TrackingConsent::Granted {
+            click.browser = agent.browser;
+            click.os = agent.os;
+            click.visitor_hash = visitor_salts
+                .visitor_hash(
+                    &db,
+                    click.clicked_at.date(),
+                    client.ip,
+                    click.user_agent.as_deref(),
+                )
+                .await;
+        }
+        if let Err(err) = clicks.record(click).await {
+            tracing::error!("Could not buffer link statistics: {}", err);
+        }
+    }
+
+    if interstitial {
+        let link_url = format!("{}/{}", settings.public_base_url, link.id);
+        let values = [
+            ("link_id", link.id.as_str()),
+            ("link_url", link_url.as_str()),
+            ("target_url", target_url.as_str()),
+        ];
+        return Ok(render_link_page(
+            &db,
+            &settings,
+            &headers,
+            PageKind::Preview,
+            &link.id,
+            &values,
         )
-        .bind(requested_link)
-        .bind(referer_header)
-        .bind(user_agent_header)
-        .fetch_one(&db),
-    )
-    .await
-    .map_err(internal_error)?;
+        .await);
+    }
+
+    let location = location(target_url).map_err(internal_error)?;
 
-    Ok(Response::builder()
-        .status(StatusCode::TEMPORARY_REDIRECT)
-        .header("Location", link.target_url)
-        .header("Cache-Control", DEFAULT_CACHE_CONTROL_HEADER_VALUE)
-        .body(Body::empty())
-        .expect("This response should always be constructable"))
+    let mut response = cache_as_asked(temporary_redirect(location, etag), &behavior);
+    if let Some(status) = behavior
+        .redirect_status
+        .and_then(|status| u16::try_from(status).ok())
+        .and_then(|status| StatusCode::from_u16(status).ok())
+    {
+        *response.status_mut() = status;
+    }
+    Ok(if routed { private(response) } else { response })
+}
+
+/// Apply the link's own cache lifetime, if it has one.
+fn cache_as_asked(response: Response, behavior: &RedirectBehavior) -> Response {
+    match behavior
+        .cache_ttl_seconds
+        .and_then(|seconds| u32::try_from(seconds).ok())
+    {
+        Some(seconds) => cached_for(response, seconds),
+        None => response,
+    }
+}
+
+/// Show where a link leads without following it or counting a click.
+pub async fn preview_link(
+    State(inner): State<InnerState>,
+    Path(requested_link): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState {
+        db,
+        settings,
+        link_filter,
+        ..
+    } = inner;
+
+    if !link_filter.might_exist(&requested_link) {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    let target_url = links::find_active_target_url(&db, &requested_link)
+        .await
+        .map_err(internal_error)?;
+
+    let link_url = format!("{}/{}", settings.public_base_url, requested_link);
+    let page = match target_url {
+        Some(target_url) => {
+            let values = [
+                ("link_id", requested_link.as_str()),
+                ("link_url", link_url.as_str()),
+                ("target_url", target_url.as_str()),
+            ];
+            render_link_page(
+                &db,
+                &settings,
+                &headers,
+                PageKind::Preview,
+                &requested_link,
+                &values,
+            )
+            .await
+        }
+        None => {
+            let values = [("link_id", requested_link.as_str())];
+            render_page(&db, &settings, &headers, PageKind::NotFound, &values).await
+        }
+    };
+
+    Ok(page)
 }
 
 pub async fn create_link(
     State(inner): State<InnerState>,
-    Json(new_link): Json<LinkTarget>,
+    claims: Option<Claims>,
+    Query(options): Query<WriteOptions>,
+    Valid(new_link): Valid<LinkTarget>,
 ) -> Result<Json<Link>, (StatusCode, String)> {
-    let InnerState { db, .. } = inner;
+    let InnerState { db, settings, .. } = inner.clone();
+
+    let domain = new_link.domain.as_deref().map(str::to_ascii_lowercase);
+    let resource = new_link.resource(domain.as_deref());
+    match (&new_link.organization_id, &claims) {
+        (Some(organization_id), Some(claims)) => {
+            authorize_organization(&db, organization_id, claims, Action::CreateLink, &resource)
+                .await?;
+        }
+        (Some(_), None) => {
+            return Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string()));
+        }
+        (None, Some(claims)) => {
+            require_token_policies(&db, claims, Action::CreateLink, &resource).await?;
+        }
+        (None, None) => {}
+    }
+
+    if let Some(custom_id) = &new_link.custom_id {
+        require_usable_custom_id(&db, custom_id, new_link.organization_id.as_deref()).await?;
+    }
+
+    let behavior = match (&domain, &new_link.organization_id) {
+        (Some(domain), Some(organization_id)) => {
+            new_link
+                .behavior
+                .or(&active_domain_defaults(&db, organization_id, domain).await?)
+        }
+        _ => new_link.behavior,
+    };
+
+    let owner_email = claims.map(|claims| claims.sub);
+    let new_link_id = new_link.custom_id.clone().unwrap_or_else(generate_id);
+
+    // Links carrying a payload lead to their own landing page.
+    let target_url = match &new_link.payload {
+        Some(_) => format!("{}/{}", settings.public_base_url, new_link_id),
+        None => new_link.target_url.clone(),
+    };
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let created = insert_checked_link(
+        &mut transaction,
+        &inner,
+        &NewLink {
+            id: &new_link_id,
+            target_url: &target_url,
+            organization_id: new_link.organization_id.as_deref(),
+            owner_email: owner_email.as_deref(),
+            expires_at: new_link.expires_at,
+            payload: new_link.payload.as_ref(),
+            draft: new_link.draft,
+            domain: domain.as_deref(),
+            behavior: &behavior,
+            tags: &new_link.tags,
+            campaign: new_link.campaign.as_deref(),
+        },
+    )
+    .await?;
+
+    if options.dry_run {
+        transaction.rollback().await.map_err(internal_error)?;
+        return Ok(Json(created.link));
+    }
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(Json(created.committed(&inner)))
+}
+
+/// A link [`insert_checked_link`] created, with what is left to do once
+/// its transaction is committed.
+pub struct CreatedLink {
+    pub link: Link,
+    has_owner: bool,
+    quota_warning: Option<QuotaWarning>,
+}
+
+impl CreatedLink {
+    /// Wake the hooks, fetch the preview and warn the owner about their
+    /// quota. Call once the transaction creating the link is committed.
+    pub fn committed(self, inner: &InnerState) -> Link {
+        if self.has_owner {
+            wake_outbox();
+        }
+
+        if self.link.kind == URL_KIND {
+            refresh_link_preview(
+                inner.db.clone(),
+                self.link.id.clone(),
+                self.link.target_url.clone(),
+            );
+        }
+
+        if let Some(warning) = self.quota_warning {
+            send_quota_warning(
+                inner.db.clone(),
+                inner.email_client.clone(),
+                &inner.settings,
+                warning,
+            );
+        }
 
-    let url = Url::parse(&new_link.target_url)
-        .map_err(|_| (StatusCode::CONFLICT, "url malformed".into()))?
-        .to_string();
+        self.link
+    }
+}
+
+/// Create a link in the transaction, as every route creating one must: its
+/// target checked against the host rules and the URL scanner, unless it
+/// carries a payload, counted against its owner's quota and announced to
+/// the owner's hooks. Authorizing the caller is left to the route.
+pub async fn insert_checked_link(
+    transaction: &mut Transaction<'_, Postgres>,
+    inner: &InnerState,
+    new_link: &NewLink<'_>,
+) -> Result<CreatedLink, (StatusCode, String)> {
+    let InnerState {
+        db,
+        settings,
+        url_scanner,
+        ..
+    } = inner;
 
-    let new_link_id = generate_id();
+    let (target_url, threats) = match new_link.payload {
+        Some(_) => (new_link.target_url.to_string(), Vec::new()),
+        None => {
+            let url = Url::parse(new_link.target_url)
+                .map_err(|_| (StatusCode::CONFLICT, "url malformed".into()))?;
+            require_allowed_host(db, settings, &url).await?;
+            let url = url.to_string();
+            let threats = scan_target(url_scanner, settings, &url).await?;
+            (url, threats)
+        }
+    };
     let fetch_statistics_timeout = tokio::time::Duration::from_millis(1000);
 
-    let new_link = tokio::time::timeout(
+    let link = tokio::time::timeout(
         fetch_statistics_timeout,
-        sqlx::query_as::<_, Link>(
-            r#"INSERT INTO links (id, target_url) VALUES ($1, $2) RETURNING id, target_url"#,
-        )
-        .bind(new_link_id)
-        .bind(url)
-        .fetch_one(&db),
+        links::insert_link(
+            &mut **transaction,
+            &NewLink {
+                target_url: &target_url,
+                ..*new_link
+            },
+        ),
     )
     .await
     .map_err(internal_error)?
-    .map_err(internal_error)?;
+    .map_err(|err| match err.as_database_error() {
+        Some(err) if err.is_unique_violation() => (
+            StatusCode::CONFLICT,
+            "Custom id is already taken".to_string(),
+        ),
+        _ => internal_error(err),
+    })?;
+
+    let quota_warning = charge_link_quota(transaction, settings, &link.id).await?;
 
-    Ok(Json(new_link))
+    flag_link(&mut **transaction, &link.id, &target_url, &threats)
+        .await
+        .map_err(internal_error)?;
+
+    let has_owner = new_link.owner_email.is_some();
+    if has_owner {
+        enqueue_new_link(&mut **transaction, &link.id)
+            .await
+            .map_err(internal_error)?;
+    }
+
+    Ok(CreatedLink {
+        link,
+        has_owner,
+        quota_warning,
+    })
 }
 
+/// Change where one of the caller's links goes. Other links of their
+/// organization take an admin.
 pub async fn update_link(
     State(inner): State<InnerState>,
+    claims: Claims,
     Path(link_id): Path<String>,
-    Json(update_link): Json<LinkTarget>,
+    Query(options): Query<WriteOptions>,
+    Valid(update_link): Valid<LinkTarget>,
 ) -> Result<Json<Link>, (StatusCode, String)> {
-    let InnerState { db, .. } = inner;
+    let InnerState {
+        db,
+        redirect_cache,
+        redirects,
+        settings,
+        url_scanner,
+        ..
+    } = inner;
+
+    // Changing a link must leave it one the caller may still change.
+    let access = authorize_link(&db, &link_id, &claims, Action::UpdateLink).await?;
+    require_link_access(
+        &claims,
+        &access,
+        Action::UpdateLink,
+        &update_link.resource(access.domain.as_deref()),
+    )?;
 
     let url = Url::parse(&update_link.target_url)
-        .map_err(|_| (StatusCode::CONFLICT, "Url malformed".into()))?
-        .to_string();
+        .map_err(|_| (StatusCode::CONFLICT, "Url malformed".into()))?;
+    require_allowed_host(&db, &settings, &url).await?;
+    let url = url.to_string();
+    let threats = scan_target(&url_scanner, &settings, &url).await?;
 
     let fetch_statistics_timeout = tokio::time::Duration::from_millis(1000);
 
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
     let link = tokio::time::timeout(
         fetch_statistics_timeout,
-        sqlx::query_as::<_, Link>(
-            r#"update links set target_url = $1 where id = $2 returning id, target_url"#,
-        )
-        .bind(url)
-        .bind(link_id)
-        .fetch_one(&db),
+        links::update_target(
+            &mut *transaction,
+            &link_id,
+            &url,
+            update_link.expires_at,
+            &update_link.tags,
+            update_link.campaign.as_deref(),
+        ),
     )
     .await
     .map_err(internal_error)?
     .map_err(internal_error)?;
 
+    flag_link(&mut *transaction, &link_id, &url, &threats)
+        .await
+        .map_err(internal_error)?;
+
+    if options.dry_run {
+        transaction.rollback().await.map_err(internal_error)?;
+    } else {
+        transaction.commit().await.map_err(internal_error)?;
+        forget_link(&db, &redirects, &redirect_cache, &link_id).await;
+        if link.kind == URL_KIND {
+            refresh_link_preview(db, link_id, link.target_url.clone());
+        }
+    }
+
     Ok(Json(link))
 }
 
-pub async fn get_link_statistics(
+/// The caller's links and their organizations', newest first.
+#[tracing::instrument(name = "List links", skip(inner, claims))]
+pub async fn list_links(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Query(query): Query<LinkListQuery>,
+    pagination: Pagination,
+) -> Result<Json<Page<Link>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let after = pagination.cursor::<LinkListCursor>()?;
+    let limit = pagination.limit(DEFAULT_LINK_LIST_LIMIT, MAX_LINK_LIST_LIMIT);
+
+    let filter = LinkListFilter {
+        target_url: query.target_url.as_deref().filter(|url| !url.is_empty()),
+        created_from: query.created_from,
+        created_to: query.created_to,
+    };
+    let after = after.as_ref().map(|after| LinkListKey {
+        created_at: after.created_at,
+        id: &after.id,
+    });
+
+    let links = links::list_visible_to(&db, &claims.sub, &filter, after.as_ref(), limit)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(Page::after_last(links, limit, |last| {
+        LinkListCursor {
+            created_at: last.created_at,
+            id: last.id.clone(),
+        }
+    })))
+}
+
+/// Delete one of the caller's links, or as an admin one of their
+/// organization's. Its statistics are kept, and its id stays taken.
+#[tracing::instrument(name = "Delete link", skip(inner, claims))]
+pub async fn delete_link(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState {
+        db,
+        redirect_cache,
+        redirects,
+        ..
+    } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::DeleteLink).await?;
+
+    let deleted = links::soft_delete(&db, &link_id)
+        .await
+        .map_err(internal_error)?;
+    if !deleted {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    forget_link(&db, &redirects, &redirect_cache, &link_id).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Ask to erase a link with its statistics, freeing its id, which a
+/// second admin has to approve like other destructive actions.
+#[tracing::instrument(name = "Hard delete link", skip(inner, admin))]
+pub async fn hard_delete_link(
     State(inner): State<InnerState>,
+    admin: AdminUser,
     Path(link_id): Path<String>,
-) -> Result<Json<Vec<CounterLinkStatistics>>, (StatusCode, String)> {
+) -> Result<(StatusCode, Json<PendingAction>), (StatusCode, String)> {
     let InnerState { db, .. } = inner;
 
+    let exists: bool = sqlx::query_scalar(r#"SELECT EXISTS (SELECT 1 FROM links WHERE id = $1)"#)
+        .bind(&link_id)
+        .fetch_one(&db)
+        .await
+        .map_err(internal_error)?;
+    if !exists {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    let pending =
+        queue_pending_action(&db, &admin, &DestructiveAction::HardDeleteLink { link_id }).await?;
+
+    Ok((StatusCode::ACCEPTED, Json(pending)))
+}
+
+/// Clicks and unique visitors per referer and user agent on one of the
+/// caller's links or their organization's, a page at a time.
+pub async fn get_link_statistics(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+    Query(query): Query<LinkStatisticsQuery>,
+    pagination: Pagination,
+) -> Result<Cached<Page<CounterLinkStatistics>>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::ReadLinkStatistics).await?;
+
+    let after = pagination.cursor::<StatisticsCursor>()?;
+    let limit = pagination.limit(DEFAULT_STATISTICS_LIMIT, MAX_STATISTICS_LIMIT);
+
     let fetch_statistics_timeout = tokio::time::Duration::from_millis(1000);
 
-    let statistics = tokio::time::timeout(
-        fetch_statistics_timeout,
-        sqlx::query_as::<_, CounterLinkStatistics>(r#"select count(*) as amount, referer, user_agent from link_statistics group by link_id, referer, user_agent having link_id = $1"#)
-            .bind(link_id)
-            .fetch_all(&db),
-    )
+    let page = StatisticsPage {
+        after: after.as_ref().map(|after| StatisticsKey {
+            referer: after.referer.as_deref(),
+            user_agent: after.user_agent.as_deref(),
+        }),
+        limit,
+        min_clicks: settings.statistics_min_bucket_clicks,
+        exclude_bots: query.exclude_bots,
+    };
+
+    let key = StatisticsCacheKey::link(
+        "statistics",
+        &link_id,
+        (limit, &pagination.cursor, query.exclude_bots),
+    );
+
+    cached_statistics(key, || async {
+        let statistics = tokio::time::timeout(
+            fetch_statistics_timeout,
+            links::statistics_page(&db, &link_id, &page),
+        )
         .await
         .map_err(internal_error)?
         .map_err(internal_error)?;
 
-    Ok(Json(statistics))
+        let mut page = Page::after_last(statistics, limit, |last| StatisticsCursor {
+            referer: last.referer.clone(),
+            user_agent: last.user_agent.clone(),
+        });
+
+        // After every bucket shown on its own, on the last page.
+        if page.next_cursor.is_none() && settings.statistics_min_bucket_clicks > 0 {
+            let other = links::other_statistics(
+                &db,
+                &link_id,
+                settings.statistics_min_bucket_clicks,
+                query.exclude_bots,
+            )
+            .await
+            .map_err(internal_error)?;
+            page.items.extend(other);
+        }
+
+        Ok(page)
+    })
+    .await
+}
+
+/// Sample clicks on a link that gets too many to record each one.
+#[tracing::instrument(name = "Set link sampling", skip(inner, admin, sampling))]
+pub async fn set_link_sampling(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(link_id): Path<String>,
+    Valid(sampling): Valid<LinkSampling>,
+) -> Result<Json<LinkSampling>, (StatusCode, String)> {
+    let InnerState {
+        db,
+        redirect_cache,
+        redirects,
+        ..
+    } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let before = links::lock_click_sample_rate(&mut *transaction, &link_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    links::set_click_sample_rate(&mut *transaction, &link_id, sampling.click_sample_rate)
+        .await
+        .map_err(internal_error)?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "link.sampling",
+        Some(&link_id),
+        Some(serde_json::json!({ "clickSampleRate": before })),
+        Some(serde_json::json!({ "clickSampleRate": sampling.click_sample_rate })),
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    forget_link(&db, &redirects, &redirect_cache, &link_id).await;
+
+    Ok(Json(sampling))
+}
+
+/// The latest clicks on one of the caller's links, newest first, to check
+/// tracking works. Clicks show up once the click buffer flushed them.
+#[tracing::instrument(name = "Tail link statistics", skip(inner, claims))]
+pub async fn tail_link_statistics(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+    Query(query): Query<TailQuery>,
+) -> Result<Json<Page<TailClick>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    authorize_link(&db, &link_id, &claims, Action::ReadLinkStatistics).await?;
+
+    let limit = query
+        .n
+        .unwrap_or(DEFAULT_TAIL_LENGTH)
+        .clamp(1, MAX_TAIL_LENGTH);
+
+    let clicks = links::recent_clicks(&db, &link_id, limit)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|click| TailClick {
+            bot: click.is_bot,
+            click,
+        })
+        .collect();
+
+    // Only the newest clicks, so neither continued nor counted.
+    Ok(Json(Page {
+        items: clicks,
+        next_cursor: None,
+        total: None,
+    }))
 }
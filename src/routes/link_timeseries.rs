@@ -0,0 +1,155 @@
+//! Clicks on a link per hour or day, for charting them over time.
+
+use crate::authentication::Claims;
+use crate::db::links::{self, ClickScope};
+use crate::policy::Action;
+use crate::routes::{authorize_link, cached_statistics, Cached, StatisticsCacheKey};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Days a series may span, by bucket, so none has more than about a
+/// thousand points.
+const MAX_HOURLY_DAYS: i64 = 31;
+const MAX_DAILY_DAYS: i64 = 1_000;
+
+/// How far back a series without `from` reaches, by bucket.
+const DEFAULT_HOURLY_DAYS: i64 = 2;
+const DEFAULT_DAILY_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeBucket {
+    Hour,
+    #[default]
+    Day,
+}
+
+impl TimeBucket {
+    fn step(self) -> Duration {
+        match self {
+            TimeBucket::Hour => Duration::hours(1),
+            TimeBucket::Day => Duration::days(1),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeseriesQuery {
+    #[serde(default)]
+    pub bucket: TimeBucket,
+    /// First day counted, inclusive.
+    pub from: Option<NaiveDate>,
+    /// Last day counted, inclusive, today by default.
+    pub to: Option<NaiveDate>,
+    /// Leave out clicks of bots, such as chat apps previewing the link.
+    #[serde(default)]
+    pub exclude_bots: bool,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeseriesPoint {
+    /// Start of the bucket, in UTC.
+    pub start: NaiveDateTime,
+    /// Estimated clicks, sampled ones standing for all they were picked from.
+    pub clicks: i64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClickTimeseries {
+    pub bucket: TimeBucket,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Every bucket of the range in order, those without clicks included.
+    pub points: Vec<TimeseriesPoint>,
+}
+
+#[tracing::instrument(name = "Link click timeseries", skip(inner, claims))]
+pub async fn link_statistics_timeseries(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(link_id): Path<String>,
+    Query(query): Query<TimeseriesQuery>,
+) -> Result<Cached<ClickTimeseries>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let (default_days, max_days) = match query.bucket {
+        TimeBucket::Hour => (DEFAULT_HOURLY_DAYS, MAX_HOURLY_DAYS),
+        TimeBucket::Day => (DEFAULT_DAILY_DAYS, MAX_DAILY_DAYS),
+    };
+    let to = query.to.unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let from = query
+        .from
+        .unwrap_or_else(|| to - Duration::days(default_days - 1));
+    if from > to {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "From must not be after to".to_string(),
+        ));
+    }
+    if (to - from).num_days() >= max_days {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("A series may span at most {} days", max_days),
+        ));
+    }
+
+    authorize_link(&db, &link_id, &claims, Action::ReadLinkStatistics).await?;
+
+    let key = StatisticsCacheKey::link(
+        "timeseries",
+        &link_id,
+        (query.bucket, from, to, query.exclude_bots),
+    );
+
+    cached_statistics(key, || async {
+        let start = from.and_time(NaiveTime::MIN);
+        let end = (to + Duration::days(1)).and_time(NaiveTime::MIN);
+
+        // Days come from the rollup, hours from the raw clicks.
+        let clicks: HashMap<NaiveDateTime, i64> = match query.bucket {
+            TimeBucket::Hour => links::hourly_clicks(&db, &link_id, start, end, query.exclude_bots)
+                .await
+                .map_err(internal_error)?
+                .into_iter()
+                .collect(),
+            TimeBucket::Day => links::daily_clicks(
+                &db,
+                &ClickScope::Link(&link_id),
+                from,
+                to,
+                query.exclude_bots,
+            )
+            .await
+            .map_err(internal_error)?
+            .into_iter()
+            .map(|(day, clicks)| (day.and_time(NaiveTime::MIN), clicks))
+            .collect(),
+        };
+
+        let step = query.bucket.step();
+        let points = std::iter::successors(Some(start), |bucket| Some(*bucket + step))
+            .take_while(|bucket| *bucket < end)
+            .map(|bucket| TimeseriesPoint {
+                start: bucket,
+                clicks: clicks.get(&bucket).copied().unwrap_or(0),
+            })
+            .collect();
+
+        Ok(ClickTimeseries {
+            bucket: query.bucket,
+            from,
+            to,
+            points,
+        })
+    })
+    .await
+}
@@ -1,16 +1,17 @@
-use crate::authentication::{validate_credentials, Credentials};
-use crate::{COUNTER_KEY, InnerState};
+use crate::authentication::{
+    start_session, validate_credentials, Claims, Credentials, SessionDevice,
+};
+use crate::client_ip::ClientIp;
+use crate::routes::send_new_device_alert;
+use crate::InnerState;
 
 use axum::extract::State;
 use axum::Form;
 use axum::http::{HeaderMap, Response};
 use reqwest::StatusCode;
 
-use serde::{Deserialize, Serialize};
 use axum::body::Body;
 use axum::response::{Html};
-use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
-use tower_sessions::Session;
 
 use crate::authentication::AuthError;
 
@@ -22,16 +23,6 @@ pub enum LoginError {
     UnexpectedError(#[from] anyhow::Error),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    role: String,
-    exp: usize,
-}
-
-#[derive(Default, Deserialize, Serialize)]
-pub struct Counter(pub(crate) usize);
-
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
@@ -40,9 +31,11 @@ pub struct FormData {
 
 pub async fn login_user(
     State(inner): State<InnerState>,
+    client: ClientIp,
+    headers: HeaderMap,
     Form(form): Form<FormData>
 ) -> Result<Response<Body>, String> {
-    let InnerState { db, .. } = inner;
+    let InnerState { db, email_client, jwt_keys, settings, .. } = inner;
 
     let credentials = Credentials {
         email: form.email,
@@ -50,8 +43,36 @@ pub async fn login_user(
     };
 
     match validate_credentials(&credentials, &db).await {
-        Ok(user_id) => {
-            let token = generate_token(&credentials.email.clone());
+        Ok(user) => {
+            let device = SessionDevice::new(&settings, &client, &headers);
+            let session = start_session(
+                &db,
+                user.id.as_deref().unwrap_or_default(),
+                settings.refresh_token_lifetime,
+                &device,
+            )
+            .await
+            .map_err(|err| err.to_string())?;
+            if session.new_device {
+                send_new_device_alert(
+                    db,
+                    email_client,
+                    &settings,
+                    &user,
+                    &session.session_id,
+                    device,
+                );
+            }
+
+            // This form answers with the token alone, which cannot be
+            // refreshed, so it lasts as long as its session.
+            let claims = Claims::for_session(
+                &user.email,
+                user.role.as_deref().unwrap_or("user"),
+                &session.session_id,
+                settings.refresh_token_lifetime,
+            );
+            let token = jwt_keys.sign(&claims).await.map_err(|(_, err)| err)?;
 
            Ok(Response::builder()
             .status(StatusCode::ACCEPTED)
@@ -65,7 +86,7 @@ pub async fn login_user(
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
 
-            Err(e.to_string().into())
+            Err(e.to_string())
         }
     }
 }
@@ -73,14 +94,3 @@ pub async fn login_user(
 pub async fn root(headers: HeaderMap) -> Html<String> {
     Html(format!("<h1>{:?}</h1>", headers))
 }
-
-fn generate_token(username: &str) -> String {
-    let key = "CJleHAiOjE3MTc1MzMyMzV9";
-    let claims = Claims {
-        sub: username.to_owned(),
-        role: "user".to_owned(),
-        exp: (chrono::Utc::now() + chrono::Duration::days(90)).timestamp() as usize,
-    };
-    let header = Header::new(Algorithm::HS256);
-    encode(&header, &claims, &EncodingKey::from_secret(key.as_ref())).unwrap()
-}
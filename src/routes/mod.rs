@@ -1,18 +1,116 @@
+mod account;
+mod admin;
+mod admin_approval;
 pub(crate) mod health_check;
+mod host_rule;
+mod link_comparison;
+mod link_expiration;
+mod link_flag;
+mod link_lifecycle;
+mod link_preview;
+mod link_routing;
+mod link_schedule;
 mod link_shortner;
+mod link_timeseries;
 mod channel;
+mod chat_command;
+mod consent;
+mod custom_domain;
+mod data_retention;
+mod device_authorization;
 mod group;
+mod grafana;
+mod group_event;
+mod group_link;
+mod group_page;
 mod subscriptions;
 mod subscription_confirm;
 mod user;
+mod user_deletion;
+mod leader;
 mod login;
+mod organization;
+mod oauth_login;
+mod openapi;
+mod organization_encryption_key;
+mod organization_export;
+mod organization_invitation;
+mod organization_policy;
+mod playlist;
+mod premium_slug;
+mod page_template;
+mod payload_page;
+mod public_widget;
+mod qr_code;
+mod saml_login;
+mod scim;
+mod sessions;
+mod slug;
+mod status_page;
+mod statistics_cache;
+mod statistics_query;
+mod statistics_rollup;
+mod telegram;
+mod tls_certificate;
+mod trigger;
+mod usage;
+mod usage_record;
 
 
+pub use account::*;
+pub use admin::*;
+pub use admin_approval::*;
 pub use health_check::*;
+pub use host_rule::*;
+pub use link_comparison::*;
+pub use link_expiration::*;
+pub use link_flag::*;
+pub use link_lifecycle::*;
+pub use link_preview::*;
+pub use link_routing::*;
+pub use link_schedule::*;
 pub use link_shortner::*;
+pub use link_timeseries::*;
 pub use channel::*;
+pub use chat_command::*;
+pub use consent::*;
+pub use custom_domain::*;
+pub use data_retention::*;
+pub use device_authorization::*;
 pub use group::*;
+pub use grafana::*;
+pub use group_event::*;
+pub use group_link::*;
+pub use group_page::*;
 pub use subscriptions::*;
 pub use subscription_confirm::*;
 pub use user::*;
-pub use login::*;
\ No newline at end of file
+pub use user_deletion::*;
+pub use login::*;
+pub use organization::*;
+pub use oauth_login::*;
+pub use openapi::*;
+pub use organization_encryption_key::*;
+pub use organization_export::*;
+pub use organization_invitation::*;
+pub use organization_policy::*;
+pub use playlist::*;
+pub use premium_slug::*;
+pub use leader::*;
+pub use page_template::*;
+pub use payload_page::*;
+pub use public_widget::*;
+pub use qr_code::*;
+pub use saml_login::*;
+pub use scim::*;
+pub use sessions::*;
+pub use slug::*;
+pub use status_page::*;
+pub use statistics_cache::*;
+pub use statistics_query::*;
+pub use statistics_rollup::*;
+pub use telegram::*;
+pub use tls_certificate::*;
+pub use trigger::*;
+pub use usage::*;
+pub use usage_record::*;
@@ -0,0 +1,341 @@
+//! Logging in through an OAuth2 provider instead of with a password. The
+//! first login creates an account, or links one with the same verified
+//! email address.
+
+use crate::authentication::{require_not_suspended, start_session, JwtKeys, SessionDevice};
+use crate::casing::Json;
+use crate::client_ip::ClientIp;
+use crate::configuration::Settings;
+use crate::email::EmailClient;
+use crate::oauth::{OAuthClient, OAuthIdentity, OAuthProvider};
+use crate::routes::{
+    create_user, generate_subscription_token, issue_access_token, send_new_device_alert, User,
+};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use sqlx::PgPool;
+use url::Url;
+
+const OAUTH_STATE_TTL_MINUTES: i32 = 10;
+
+/// Ties the callback to the browser that started the login, so nobody can
+/// get a victim logged into the attacker's account by sending them a link.
+const OAUTH_STATE_COOKIE: &str = "groupify_oauth_state";
+
+#[derive(Deserialize)]
+pub struct OAuthCallback {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
+fn oauth_client(inner: &InnerState, provider: &str) -> Result<OAuthClient, (StatusCode, String)> {
+    let provider = OAuthProvider::parse(provider)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    inner.oauth.get(provider).cloned().ok_or_else(|| {
+        (
+            StatusCode::NOT_IMPLEMENTED,
+            format!("Logging in with {} is not configured", provider.name()),
+        )
+    })
+}
+
+fn bad_gateway(err: reqwest::Error) -> (StatusCode, String) {
+    tracing::error!("OAuth request failed: {}", err);
+    (
+        StatusCode::BAD_GATEWAY,
+        "OAuth provider request failed".to_string(),
+    )
+}
+
+fn state_from_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == OAUTH_STATE_COOKIE)
+        .map(|(_, value)| value)
+}
+
+#[tracing::instrument(name = "Start OAuth login", skip(inner))]
+pub async fn start_oauth_login(
+    State(inner): State<InnerState>,
+    Path(provider): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let client = oauth_client(&inner, &provider)?;
+    let InnerState { db, .. } = inner;
+
+    sqlx::query(
+        r#"DELETE FROM oauth_login_states
+        WHERE created_at <= CURRENT_TIMESTAMP - make_interval(mins => $1)"#,
+    )
+    .bind(OAUTH_STATE_TTL_MINUTES)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?;
+
+    let state = generate_subscription_token();
+
+    sqlx::query(r#"INSERT INTO oauth_login_states (state, provider) VALUES ($1, $2)"#)
+        .bind(&state)
+        .bind(&provider)
+        .execute(&db)
+        .await
+        .map_err(internal_error)?;
+
+    let cookie = format!(
+        "{}={}; Path=/auth/oauth; Max-Age={}; HttpOnly; SameSite=Lax",
+        OAUTH_STATE_COOKIE,
+        state,
+        OAUTH_STATE_TTL_MINUTES * 60
+    );
+
+    Ok(Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header("Location", client.authorize_url(&state))
+        .header("Set-Cookie", cookie)
+        .body(Body::empty())
+        .expect("This response should always be constructable"))
+}
+
+#[tracing::instrument(name = "Finish OAuth login", skip(inner, client_ip, headers, callback))]
+pub async fn oauth_login_callback(
+    State(inner): State<InnerState>,
+    Path(provider): Path<String>,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    Query(callback): Query<OAuthCallback>,
+) -> Result<Response, (StatusCode, String)> {
+    let client = oauth_client(&inner, &provider)?;
+    let InnerState {
+        db,
+        email_client,
+        jwt_keys,
+        settings,
+        ..
+    } = inner;
+
+    if state_from_cookie(&headers) != Some(callback.state.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Unknown or expired state".to_string(),
+        ));
+    }
+    let claimed = sqlx::query(
+        r#"DELETE FROM oauth_login_states
+        WHERE state = $1 AND provider = $2
+            AND created_at > CURRENT_TIMESTAMP - make_interval(mins => $3)"#,
+    )
+    .bind(&callback.state)
+    .bind(&provider)
+    .bind(OAUTH_STATE_TTL_MINUTES)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?
+    .rows_affected();
+    if claimed == 0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Unknown or expired state".to_string(),
+        ));
+    }
+
+    let code = match (callback.code, callback.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Authorization failed: {}", error.unwrap_or_default()),
+            ))
+        }
+    };
+
+    let access_token = client.exchange_code(&code).await.map_err(bad_gateway)?;
+    let identity = client.identity(&access_token).await.map_err(bad_gateway)?;
+
+    let user = find_or_create_user(&db, &provider, &identity).await?;
+
+    let device = SessionDevice::new(&settings, &client_ip, &headers);
+    finish_external_login(&db, &email_client, &jwt_keys, &settings, &user, device).await
+}
+
+/// Log in a user who proved who they are to an identity provider: start a
+/// session and hand out its tokens, in the fragment of
+/// `OAUTH_LOGIN_REDIRECT_URL` when one is set.
+pub async fn finish_external_login(
+    db: &PgPool,
+    email_client: &EmailClient,
+    jwt_keys: &JwtKeys,
+    settings: &Settings,
+    user: &User,
+    device: SessionDevice,
+) -> Result<Response, (StatusCode, String)> {
+    if user.deleted_at.is_some() || require_not_suspended(user).is_err() {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Authentication failed".to_string(),
+        ));
+    }
+
+    let session = start_session(
+        db,
+        user.id.as_deref().unwrap_or_default(),
+        settings.refresh_token_lifetime,
+        &device,
+    )
+    .await
+    .map_err(internal_error)?;
+    if session.new_device {
+        send_new_device_alert(
+            db.clone(),
+            email_client.clone(),
+            settings,
+            user,
+            &session.session_id,
+            device,
+        );
+    }
+    let Json(tokens) = issue_access_token(jwt_keys, settings, user, session).await?;
+
+    let Some(redirect_url) = &settings.oauth_login_redirect_url else {
+        return Ok(Json(tokens).into_response());
+    };
+
+    // In the fragment, which browsers never send on, so the tokens stay out
+    // of server logs along the way.
+    let mut location = Url::parse(redirect_url).map_err(internal_error)?;
+    location.set_fragment(Some(
+        &url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("token", &tokens.token)
+            .append_pair("refreshToken", &tokens.refresh_token)
+            .append_pair("expiresIn", &tokens.expires_in.to_string())
+            .finish(),
+    ));
+
+    Ok(Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header("Location", location.to_string())
+        .body(Body::empty())
+        .expect("This response should always be constructable"))
+}
+
+/// The user the identity belongs to. An identity seen for the first time is
+/// linked to the account with its verified email address, which has to be
+/// confirmed so nobody can register an address ahead of its owner and then
+/// share their account. Accounts an organization's identity provider logs
+/// into are never linked, as it keeps logging into them. Without such an
+/// account, one is created.
+#[tracing::instrument(name = "Find or create OAuth user", skip(db))]
+async fn find_or_create_user(
+    db: &PgPool,
+    provider: &str,
+    identity: &OAuthIdentity,
+) -> Result<User, (StatusCode, String)> {
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let linked = sqlx::query_as::<_, User>(
+        r#"SELECT users.* FROM oauth_identities
+        JOIN users ON users.id = oauth_identities.user_id
+        WHERE oauth_identities.provider = $1 AND oauth_identities.subject = $2"#,
+    )
+    .bind(provider)
+    .bind(&identity.subject)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    let user = match linked {
+        Some(user) => {
+            sqlx::query(
+                r#"UPDATE oauth_identities SET email = $3, last_login_at = CURRENT_TIMESTAMP
+                WHERE provider = $1 AND subject = $2"#,
+            )
+            .bind(provider)
+            .bind(&identity.subject)
+            .bind(&identity.email)
+            .execute(&mut *transaction)
+            .await
+            .map_err(internal_error)?;
+
+            user
+        }
+        None => {
+            let email = identity.email.clone().ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    "The provider did not share a verified email address".to_string(),
+                )
+            })?;
+
+            let existing = sqlx::query_as::<_, User>(r#"SELECT * FROM users WHERE email = $1"#)
+                .bind(&email)
+                .fetch_optional(&mut *transaction)
+                .await
+                .map_err(internal_error)?;
+
+            let user = match existing {
+                Some(user)
+                    if user.email_confirmed_at.is_some() && user.is_sso_user != Some(true) =>
+                {
+                    user
+                }
+                Some(_) => {
+                    return Err((
+                        StatusCode::CONFLICT,
+                        "Email is already registered".to_string(),
+                    ))
+                }
+                None => {
+                    // Never used to log in, until its owner resets it.
+                    let user_id = create_user(
+                        &mut transaction,
+                        User {
+                            email: email.clone(),
+                            encrypted_password: generate_subscription_token(),
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+
+                    sqlx::query_as::<_, User>(
+                        r#"UPDATE users SET email_confirmed_at = CURRENT_TIMESTAMP,
+                            display_name = $2, updated_at = CURRENT_TIMESTAMP
+                        WHERE id = $1 returning *"#,
+                    )
+                    .bind(&user_id)
+                    .bind(&identity.name)
+                    .fetch_one(&mut *transaction)
+                    .await
+                    .map_err(internal_error)?
+                }
+            };
+
+            sqlx::query(
+                r#"INSERT INTO oauth_identities (provider, subject, user_id, email)
+                VALUES ($1, $2, $3, $4)"#,
+            )
+            .bind(provider)
+            .bind(&identity.subject)
+            .bind(&user.id)
+            .bind(&email)
+            .execute(&mut *transaction)
+            .await
+            .map_err(internal_error)?;
+
+            user
+        }
+    };
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(user)
+}
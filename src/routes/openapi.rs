@@ -0,0 +1,358 @@
+//! OpenAPI 3 description of the link endpoints, written by hand next to
+//! the handlers it describes, and a Swagger UI to browse it. Field names
+//! are given in the default camelCase; deployments answering in snake_case
+//! rename them accordingly.
+
+use crate::routes::{MAX_CACHE_TTL_SECONDS, MAX_CAMPAIGN_LENGTH, MAX_LINK_TAGS, MAX_TAG_LENGTH};
+use crate::validation::{MAX_SLUG_LENGTH, MAX_URL_LENGTH, MIN_PREMIUM_SLUG_LENGTH};
+use crate::InnerState;
+
+use axum::extract::State;
+use axum::response::Html;
+use axum::Json;
+use serde_json::{json, Value};
+
+/// Swagger UI release loaded from the CDN.
+const SWAGGER_UI_VERSION: &str = "5.17.14";
+
+/// The document is plain JSON rather than going through `casing::Json`,
+/// which would rename OpenAPI's own keys.
+pub async fn openapi_document(State(inner): State<InnerState>) -> Json<Value> {
+    Json(json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Groupify API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Short links and their click statistics.",
+        },
+        "servers": [{ "url": inner.settings.public_base_url }],
+        "paths": {
+            "/create": {
+                "post": {
+                    "operationId": "createLink",
+                    "summary": "Create a link",
+                    "description": "Signed in callers own the link; anonymous links have no owner. Links carrying a payload serve a landing page instead of redirecting.",
+                    "security": [{}, { "bearer": [] }],
+                    "parameters": [{ "$ref": "#/components/parameters/DryRun" }],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/LinkTarget" },
+                            },
+                        },
+                    },
+                    "responses": {
+                        "200": link_response("The created link"),
+                        "401": text_response("An organization was given without signing in"),
+                        "403": text_response("The caller may not create such a link in the organization, or the custom id is a premium slug assigned elsewhere"),
+                        "409": text_response("The custom id is already taken"),
+                        "422": { "$ref": "#/components/responses/ValidationFailed" },
+                        "429": text_response("Too many links created recently"),
+                    },
+                },
+            },
+            "/{id}": {
+                "parameters": [{ "$ref": "#/components/parameters/LinkId" }],
+                "get": {
+                    "operationId": "redirect",
+                    "summary": "Follow a link",
+                    "description": "Redirects to the link's target, counting the click. Links carrying a payload answer with their landing page, and a consent page is shown first where tracking needs consent.",
+                    "responses": {
+                        "307": {
+                            "description": "Redirect to the target",
+                            "headers": {
+                                "Location": { "schema": { "type": "string", "format": "uri" } },
+                            },
+                        },
+                        "200": html_response("A landing page or the tracking consent page"),
+                        "304": { "description": "The cached redirect is still current" },
+                        "404": html_response("No such link"),
+                        "410": html_response("The link expired or was disabled"),
+                        "429": text_response("Too many redirects from this address"),
+                    },
+                },
+                "patch": {
+                    "operationId": "updateLink",
+                    "summary": "Change a link's target and expiry",
+                    "description": "An update without `expiresAt` makes the link never expire. `customId` and `payload` are ignored.",
+                    "parameters": [{ "$ref": "#/components/parameters/DryRun" }],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/LinkTarget" },
+                            },
+                        },
+                    },
+                    "responses": {
+                        "200": link_response("The updated link"),
+                        "403": text_response("The caller may not change the link, or not into what the update would make of it"),
+                        "404": text_response("No such link, or one of others"),
+                        "422": { "$ref": "#/components/responses/ValidationFailed" },
+                    },
+                },
+            },
+            "/{id}/statistics": {
+                "get": {
+                    "operationId": "getLinkStatistics",
+                    "summary": "Clicks per referer and user agent",
+                    "parameters": [
+                        { "$ref": "#/components/parameters/LinkId" },
+                        {
+                            "name": "limit",
+                            "in": "query",
+                            "schema": { "type": "integer", "minimum": 1 },
+                        },
+                        {
+                            "name": "cursor",
+                            "in": "query",
+                            "description": "`nextCursor` of the previous page",
+                            "schema": { "type": "string" },
+                        },
+                        {
+                            "name": "excludeBots",
+                            "in": "query",
+                            "description": "Leave out clicks of bots, such as chat apps previewing the link",
+                            "schema": { "type": "boolean", "default": false },
+                        },
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "A page of statistics, cached privately for a while",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "$ref": "#/components/schemas/StatisticsPage" },
+                                },
+                            },
+                        },
+                        "400": text_response("The cursor is malformed"),
+                    },
+                },
+            },
+        },
+        "components": {
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+            },
+            "parameters": {
+                "LinkId": {
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                },
+                "DryRun": {
+                    "name": "dryRun",
+                    "in": "query",
+                    "description": "Check everything that could refuse the change and answer with its result, without keeping it",
+                    "schema": { "type": "boolean", "default": false },
+                },
+            },
+            "responses": {
+                "ValidationFailed": {
+                    "description": "The body is invalid",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/ValidationFailure" },
+                        },
+                    },
+                },
+            },
+            "schemas": {
+                "LinkTarget": {
+                    "type": "object",
+                    "properties": {
+                        "targetUrl": {
+                            "type": "string",
+                            "format": "uri",
+                            "maxLength": MAX_URL_LENGTH,
+                            "description": "Left out for links with a payload",
+                        },
+                        "organizationId": { "type": "string", "nullable": true },
+                        "customId": {
+                            "type": "string",
+                            "nullable": true,
+                            "pattern": "^[A-Za-z0-9_-]+$",
+                            "minLength": MIN_PREMIUM_SLUG_LENGTH,
+                            "maxLength": MAX_SLUG_LENGTH,
+                            "description": "A vanity id instead of a random one. Shorter than 3 characters only for premium slugs.",
+                        },
+                        "expiresAt": {
+                            "type": "string",
+                            "format": "date-time",
+                            "nullable": true,
+                        },
+                        "payload": { "$ref": "#/components/schemas/LinkPayload" },
+                        "draft": {
+                            "type": "boolean",
+                            "default": false,
+                            "description": "Create the link without redirecting until moved to active",
+                        },
+                        "domain": {
+                            "type": "string",
+                            "nullable": true,
+                            "description": "An active custom domain of the organization, whose defaults fill in the four fields below when left out",
+                        },
+                        "redirectStatus": {
+                            "type": "integer",
+                            "nullable": true,
+                            "enum": [301, 302, 307, 308],
+                            "description": "307 when unset",
+                        },
+                        "cacheTtlSeconds": {
+                            "type": "integer",
+                            "nullable": true,
+                            "minimum": 0,
+                            "maximum": MAX_CACHE_TTL_SECONDS,
+                            "description": "How long caches may keep the redirect, 300 when unset",
+                        },
+                        "fallbackUrl": {
+                            "type": "string",
+                            "format": "uri",
+                            "nullable": true,
+                            "description": "Where visitors go once the link is disabled or expired",
+                        },
+                        "interstitial": {
+                            "type": "boolean",
+                            "nullable": true,
+                            "description": "Show where the link leads instead of redirecting right away",
+                        },
+                        "tags": {
+                            "type": "array",
+                            "items": { "type": "string", "maxLength": MAX_TAG_LENGTH },
+                            "maxItems": MAX_LINK_TAGS,
+                            "description": "Labels organization policies may tell the link apart by. Updates replace them.",
+                        },
+                        "campaign": {
+                            "type": "string",
+                            "nullable": true,
+                            "maxLength": MAX_CAMPAIGN_LENGTH,
+                        },
+                    },
+                },
+                "LinkPayload": {
+                    "type": "object",
+                    "nullable": true,
+                    "required": ["kind"],
+                    "properties": {
+                        "kind": { "type": "string", "enum": ["vcard", "wifi"] },
+                    },
+                    "additionalProperties": true,
+                    "description": "A contact card (`name`, `organization`, `title`, `phone`, `email`, `website`, `note`) or a Wi-Fi network (`ssid`, `password`, `security`, `hidden`)",
+                },
+                "Link": {
+                    "type": "object",
+                    "required": ["id", "kind", "targetUrl", "state"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "kind": { "type": "string", "enum": ["url", "vcard", "wifi"] },
+                        "targetUrl": { "type": "string", "format": "uri" },
+                        "state": {
+                            "type": "string",
+                            "enum": ["draft", "active", "paused", "expired", "disabled", "deleted"],
+                        },
+                        "expiresAt": { "type": "string", "format": "date-time", "nullable": true },
+                        "createdAt": { "type": "string", "format": "date-time", "nullable": true },
+                        "domain": { "type": "string", "nullable": true },
+                        "redirectStatus": { "type": "integer", "nullable": true },
+                        "cacheTtlSeconds": { "type": "integer", "nullable": true },
+                        "fallbackUrl": { "type": "string", "format": "uri", "nullable": true },
+                        "interstitial": { "type": "boolean", "nullable": true },
+                        "tags": { "type": "array", "items": { "type": "string" } },
+                        "campaign": { "type": "string", "nullable": true },
+                    },
+                },
+                "LinkStatistics": {
+                    "type": "object",
+                    "properties": {
+                        "amount": {
+                            "type": "integer",
+                            "nullable": true,
+                            "description": "Estimated clicks, sampled clicks scaled by their rate",
+                        },
+                        "referer": { "type": "string", "nullable": true },
+                        "userAgent": { "type": "string", "nullable": true },
+                        "sampled": { "type": "boolean", "nullable": true },
+                        "visitors": {
+                            "type": "integer",
+                            "nullable": true,
+                            "description": "Unique visitors, each counted once a day",
+                        },
+                        "bot": { "type": "boolean", "nullable": true },
+                        "browser": { "type": "string", "nullable": true },
+                        "os": { "type": "string", "nullable": true },
+                    },
+                },
+                "StatisticsPage": {
+                    "type": "object",
+                    "properties": {
+                        "items": {
+                            "type": "array",
+                            "items": { "$ref": "#/components/schemas/LinkStatistics" },
+                        },
+                        "nextCursor": { "type": "string", "nullable": true },
+                        "total": { "type": "integer", "nullable": true },
+                    },
+                },
+                "ValidationFailure": {
+                    "type": "object",
+                    "properties": {
+                        "message": { "type": "string" },
+                        "errors": {
+                            "type": "object",
+                            "description": "Messages per field",
+                            "additionalProperties": {
+                                "type": "array",
+                                "items": { "type": "string" },
+                            },
+                        },
+                    },
+                },
+            },
+        },
+    }))
+}
+
+pub async fn swagger_ui() -> Html<String> {
+    Html(format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Groupify API</title>
+<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@{version}/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="https://unpkg.com/swagger-ui-dist@{version}/swagger-ui-bundle.js"></script>
+<script>SwaggerUIBundle({{ url: "/openapi.json", dom_id: "#swagger-ui" }});</script>
+</body>
+</html>
+"##,
+        version = SWAGGER_UI_VERSION
+    ))
+}
+
+fn link_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": { "schema": { "$ref": "#/components/schemas/Link" } },
+        },
+    })
+}
+
+fn text_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    })
+}
+
+fn html_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/html": { "schema": { "type": "string" } } },
+    })
+}
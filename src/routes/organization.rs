@@ -0,0 +1,465 @@
+use crate::authentication::Claims;
+use crate::branding::{self, Branding};
+use crate::casing::Json;
+use crate::db::links::{self, LinkAccess};
+use crate::pagination::Page;
+use crate::policy::{self, Action, Resource, Statement};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Organization {
+    pub id: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub name: String,
+}
+
+impl Validate for Organization {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_not_blank("name", &self.name);
+        errors.require_max_length("name", &self.name, 100);
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationMember {
+    pub organization_id: String,
+    pub user_id: String,
+    pub email: String,
+    pub role: String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+/// Member roles, from least to most privileged.
+pub const ORGANIZATION_ROLES: [&str; 3] = ["member", "admin", "owner"];
+
+fn role_rank(role: &str) -> Option<usize> {
+    ORGANIZATION_ROLES.iter().position(|r| *r == role)
+}
+
+/// The statements of the policies the caller's token carries, None when it
+/// carries none.
+fn token_statements<'s>(claims: &Claims, statements: &'s [Statement]) -> Option<&'s [Statement]> {
+    (!claims.policies.is_empty()).then_some(statements)
+}
+
+#[derive(FromRow)]
+struct MemberAccess {
+    user_id: String,
+    role: String,
+    role_statements: sqlx::types::Json<Vec<Statement>>,
+    token_statements: sqlx::types::Json<Vec<Statement>>,
+}
+
+/// Look up the caller's role in the organization and check it is at least
+/// `minimum_role`, or that policies let them act on the organization as
+/// with it. Returns the caller's user id.
+pub async fn require_organization_role<'e, E: PgExecutor<'e>>(
+    executor: E,
+    organization_id: &str,
+    claims: &Claims,
+    minimum_role: &str,
+) -> Result<String, (StatusCode, String)> {
+    authorize_organization(
+        executor,
+        organization_id,
+        claims,
+        Action::on_organization(minimum_role),
+        &Resource::default(),
+    )
+    .await
+}
+
+/// Check the caller's role in the organization, and the policies bearing
+/// on them, allow the action on `resource`. Returns the caller's user id.
+pub async fn authorize_organization<'e, E: PgExecutor<'e>>(
+    executor: E,
+    organization_id: &str,
+    claims: &Claims,
+    action: Action,
+    resource: &Resource<'_>,
+) -> Result<String, (StatusCode, String)> {
+    let member = sqlx::query_as::<_, MemberAccess>(&format!(
+        r#"SELECT users.id AS user_id, organization_members.role, {}
+        FROM organization_members
+        JOIN users ON users.id = organization_members.user_id
+        WHERE organization_members.organization_id = $1 AND users.email = $2"#,
+        policy::statements_sql("$1", "organization_members.role", "$3")
+    ))
+    .bind(organization_id)
+    .bind(&claims.sub)
+    .bind(&claims.policies)
+    .fetch_optional(executor)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let granted = role_rank(&member.role) >= role_rank(action.minimum_role());
+    if policy::permits(
+        granted,
+        &member.role_statements,
+        token_statements(claims, &member.token_statements),
+        action,
+        resource,
+    ) {
+        Ok(member.user_id)
+    } else {
+        Err((StatusCode::FORBIDDEN, "Forbidden".to_string()))
+    }
+}
+
+/// Check the policies the caller's token carries allow the action, for
+/// resources outside organizations, which no role bears on.
+pub async fn require_token_policies(
+    db: &PgPool,
+    claims: &Claims,
+    action: Action,
+    resource: &Resource<'_>,
+) -> Result<(), (StatusCode, String)> {
+    if claims.policies.is_empty() {
+        return Ok(());
+    }
+
+    let statements: sqlx::types::Json<Vec<Statement>> = sqlx::query_scalar(&format!(
+        "SELECT {}",
+        policy::TOKEN_STATEMENTS.replace("{ids}", "$1")
+    ))
+    .bind(&claims.policies)
+    .fetch_one(db)
+    .await
+    .map_err(internal_error)?;
+
+    if policy::permits(true, &[], Some(&statements), action, resource) {
+        Ok(())
+    } else {
+        Err((StatusCode::FORBIDDEN, "Forbidden".to_string()))
+    }
+}
+
+/// Check the caller may take the action on the link: any action on links
+/// they created, as long as they are still in the organization of those
+/// that have one, and on the other links of their organization those their
+/// role there allows, as the policies bearing on them refine it. Admins may
+/// act on every link. Links of others are not found, as unknown ones.
+pub async fn authorize_link(
+    db: &PgPool,
+    link_id: &str,
+    claims: &Claims,
+    action: Action,
+) -> Result<LinkAccess, (StatusCode, String)> {
+    let access = links::access_of(db, link_id, &claims.sub, &claims.policies)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    require_link_access(claims, &access, action, &access.resource())?;
+    Ok(access)
+}
+
+/// Check the caller's access to a link allows the action on `resource`,
+/// such as the link as a change would leave it.
+pub fn require_link_access(
+    claims: &Claims,
+    access: &LinkAccess,
+    action: Action,
+    resource: &Resource,
+) -> Result<(), (StatusCode, String)> {
+    if claims.is_admin() {
+        return Ok(());
+    }
+
+    let is_owner =
+        access.is_owner && (!access.in_organization || access.organization_role.is_some());
+    if !is_owner && access.organization_role.is_none() {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    let granted = is_owner
+        || access
+            .organization_role
+            .as_deref()
+            .is_some_and(|role| role_rank(role) >= role_rank(action.minimum_role()));
+    if policy::permits(
+        granted,
+        &access.role_statements,
+        token_statements(claims, &access.token_statements),
+        action,
+        resource,
+    ) {
+        Ok(())
+    } else {
+        Err((StatusCode::FORBIDDEN, "Forbidden".to_string()))
+    }
+}
+
+#[tracing::instrument(name = "Create organization", skip(inner, claims, organization))]
+pub async fn create_organization(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Valid(organization): Valid<Organization>,
+) -> Result<Json<Organization>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let uuid = Uuid::new_v4().to_string();
+
+    let organization = sqlx::query_as::<_, Organization>(
+        r#"INSERT INTO organizations (id, name) values($1, $2) returning *"#,
+    )
+    .bind(&uuid)
+    .bind(organization.name)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    sqlx::query(
+        r#"INSERT INTO organization_members (organization_id, user_id, role)
+        SELECT $1, id, 'owner' FROM users WHERE email = $2"#,
+    )
+    .bind(&uuid)
+    .bind(&claims.sub)
+    .execute(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(Json(organization))
+}
+
+pub async fn organization_members(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<Json<Page<OrganizationMember>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "member").await?;
+
+    let members = fetch_organization_members(&db, &organization_id).await?;
+
+    Ok(Json(Page::complete(members)))
+}
+
+pub async fn fetch_organization_members<'e, E: PgExecutor<'e>>(
+    executor: E,
+    organization_id: &str,
+) -> Result<Vec<OrganizationMember>, (StatusCode, String)> {
+    sqlx::query_as::<_, OrganizationMember>(
+        r#"SELECT organization_members.organization_id, organization_members.user_id, users.email,
+        organization_members.role, organization_members.created_at
+        FROM organization_members JOIN users ON users.id = organization_members.user_id
+        WHERE organization_members.organization_id = $1
+        ORDER BY organization_members.created_at"#,
+    )
+    .bind(organization_id)
+    .fetch_all(executor)
+    .await
+    .map_err(internal_error)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MemberRole {
+    pub role: String,
+}
+
+impl Validate for MemberRole {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        require_organization_role_name(errors, &self.role);
+    }
+}
+
+pub fn require_organization_role_name(errors: &mut ValidationErrors, role: &str) {
+    if role_rank(role).is_none() {
+        errors.add(
+            "role",
+            format!("must be one of {}", ORGANIZATION_ROLES.join(", ")),
+        );
+    }
+}
+
+/// Lock the organization, so changes to its members happen one at a time,
+/// then read the member's role and how many owners the organization has.
+async fn lock_member_role(
+    transaction: &mut Transaction<'_, Postgres>,
+    organization_id: &str,
+    user_id: &str,
+) -> Result<(String, i64), (StatusCode, String)> {
+    sqlx::query(r#"SELECT id FROM organizations WHERE id = $1 FOR UPDATE"#)
+        .bind(organization_id)
+        .execute(&mut **transaction)
+        .await
+        .map_err(internal_error)?;
+
+    sqlx::query_as::<_, (String, i64)>(
+        r#"SELECT role, (SELECT count(*) FROM organization_members
+            WHERE organization_id = $1 AND role = 'owner')
+        FROM organization_members WHERE organization_id = $1 AND user_id = $2"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))
+}
+
+pub fn last_owner() -> (StatusCode, String) {
+    (
+        StatusCode::CONFLICT,
+        "An organization needs an owner".to_string(),
+    )
+}
+
+/// Change a member's role. Admins manage members and admins, while making
+/// or unmaking owners takes an owner, and the last owner stays one.
+#[tracing::instrument(name = "Update organization member", skip(inner, claims, update))]
+pub async fn update_organization_member(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, user_id)): Path<(String, String)>,
+    Valid(update): Valid<MemberRole>,
+) -> Result<Json<OrganizationMember>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    require_organization_role(&mut *transaction, &organization_id, &claims, "admin").await?;
+    let (role, owners) = lock_member_role(&mut transaction, &organization_id, &user_id).await?;
+    if role == "owner" || update.role == "owner" {
+        require_organization_role(&mut *transaction, &organization_id, &claims, "owner").await?;
+    }
+    if role == "owner" && update.role != "owner" && owners <= 1 {
+        return Err(last_owner());
+    }
+
+    sqlx::query(
+        r#"UPDATE organization_members SET role = $3 WHERE organization_id = $1 AND user_id = $2"#,
+    )
+    .bind(&organization_id)
+    .bind(&user_id)
+    .bind(&update.role)
+    .execute(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    let member = fetch_organization_members(&mut *transaction, &organization_id)
+        .await?
+        .into_iter()
+        .find(|member| member.user_id == user_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(Json(member))
+}
+
+/// Remove a member, or leave the organization. Removing others takes an
+/// admin, or an owner for owners, and the last owner cannot leave.
+#[tracing::instrument(name = "Remove organization member", skip(inner, claims))]
+pub async fn remove_organization_member(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, user_id)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let caller_id =
+        require_organization_role(&mut *transaction, &organization_id, &claims, "member").await?;
+    let (role, owners) = lock_member_role(&mut transaction, &organization_id, &user_id).await?;
+    if caller_id != user_id {
+        let minimum_role = if role == "owner" { "owner" } else { "admin" };
+        require_organization_role(&mut *transaction, &organization_id, &claims, minimum_role)
+            .await?;
+    }
+    if role == "owner" && owners <= 1 {
+        return Err(last_owner());
+    }
+
+    sqlx::query(r#"DELETE FROM organization_members WHERE organization_id = $1 AND user_id = $2"#)
+        .bind(&organization_id)
+        .bind(&user_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The organization's branding, empty when it has none.
+pub async fn organization_branding(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<Json<Branding>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "member").await?;
+
+    let branding = branding::for_organization(&db, &organization_id)
+        .await
+        .map_err(internal_error)?
+        .map(|branding| (*branding).clone())
+        .unwrap_or_default();
+
+    Ok(Json(branding))
+}
+
+/// Replace the organization's branding, shown on the pages and QR codes of
+/// its links.
+#[tracing::instrument(name = "Update organization branding", skip(inner, claims, branding))]
+pub async fn update_organization_branding(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+    Valid(branding): Valid<Branding>,
+) -> Result<Json<Branding>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let primary_color = branding
+        .primary_color
+        .map(|color| color.to_ascii_lowercase());
+    let background_color = branding
+        .background_color
+        .map(|color| color.to_ascii_lowercase());
+
+    let branding = sqlx::query_as::<_, Branding>(
+        r#"INSERT INTO organization_branding
+            (organization_id, logo_url, primary_color, background_color, footer)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (organization_id) DO UPDATE SET logo_url = excluded.logo_url,
+            primary_color = excluded.primary_color, background_color = excluded.background_color,
+            footer = excluded.footer, updated_at = CURRENT_TIMESTAMP
+        RETURNING logo_url, primary_color, background_color, footer"#,
+    )
+    .bind(&organization_id)
+    .bind(&branding.logo_url)
+    .bind(primary_color)
+    .bind(background_color)
+    .bind(&branding.footer)
+    .fetch_one(&db)
+    .await
+    .map_err(internal_error)?;
+
+    branding::forget(&organization_id);
+
+    Ok(Json(branding))
+}
@@ -0,0 +1,311 @@
+//! Encryption keys organizations bring for their data at rest: their
+//! exports and the settings of their SAML connection. The service only
+//! keeps a key sealed with its own, so revoking it leaves whatever it
+//! encrypted unreadable for good.
+//!
+//! SCIM identities and invitations are not sealed. They are looked up by
+//! their external id, group name and email, which sealed values could not
+//! be matched on.
+
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::configuration::Settings;
+use crate::routes::{require_organization_role, SamlConnection};
+use crate::sealing::SealingKey;
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use anyhow::Context;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use base64::engine::general_purpose;
+use base64::Engine;
+use chrono::NaiveDateTime;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct EncryptionKeyUpload {
+    /// 32 base64 encoded bytes of an AES-256 key.
+    pub key: String,
+}
+
+impl Validate for EncryptionKeyUpload {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if SealingKey::from_base64(&self.key).is_none() {
+            errors.add("key", "must be 32 base64 encoded bytes");
+        }
+    }
+}
+
+/// What the service tells about a key, never the key itself.
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptionKey {
+    pub id: String,
+    pub organization_id: String,
+    /// Start of the SHA-256 digest of the key, to tell which one is set.
+    pub fingerprint: String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+/// The key of an organization, ready to seal and open its data.
+pub struct OrganizationKey {
+    pub id: String,
+    pub key: SealingKey,
+}
+
+fn key_context(organization_id: &str, key_id: &str) -> String {
+    format!("organization-key:{}:{}", organization_id, key_id)
+}
+
+pub fn export_context(export_id: &str) -> String {
+    format!("organization-export:{}", export_id)
+}
+
+fn fingerprint(key: &str) -> String {
+    let bytes = general_purpose::STANDARD
+        .decode(key.trim())
+        .unwrap_or_default();
+
+    digest(&SHA256, &bytes).as_ref()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn key_encryption_key(settings: &Settings) -> Result<&SealingKey, (StatusCode, String)> {
+    settings
+        .organization_key_encryption_key
+        .as_ref()
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_IMPLEMENTED,
+                "Organization encryption keys are not configured".to_string(),
+            )
+        })
+}
+
+/// The key the organization brought, or `None` when it has none.
+pub async fn organization_key<'e, E: PgExecutor<'e>>(
+    executor: E,
+    settings: &Settings,
+    organization_id: &str,
+) -> anyhow::Result<Option<OrganizationKey>> {
+    let stored: Option<(String, String)> = sqlx::query_as(
+        r#"SELECT id, sealed_key FROM organization_encryption_keys WHERE organization_id = $1"#,
+    )
+    .bind(organization_id)
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch organization key.")?;
+
+    let Some((id, sealed_key)) = stored else {
+        return Ok(None);
+    };
+
+    let key = settings
+        .organization_key_encryption_key
+        .as_ref()
+        .context("Organization keys are stored, but ORGANIZATION_KEY_ENCRYPTION_KEY is unset.")?;
+    let key = crate::sealing::open(Some(key), &key_context(organization_id, &id), &sealed_key)?;
+    let key = SealingKey::from_base64(&key).context("Organization key is malformed.")?;
+
+    Ok(Some(OrganizationKey { id, key }))
+}
+
+pub async fn organization_encryption_key(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<Json<EncryptionKey>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let key = sqlx::query_as::<_, EncryptionKey>(
+        r#"SELECT id, organization_id, fingerprint, created_at FROM organization_encryption_keys
+        WHERE organization_id = $1"#,
+    )
+    .bind(&organization_id)
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    Ok(Json(key))
+}
+
+/// Set the organization's key, or rotate it when it has one. Exports and
+/// the SAML connection are sealed again with the new key before the
+/// previous one is dropped, apart from those sealed with a key revoked
+/// before, which stay unreadable.
+#[tracing::instrument(name = "Set organization encryption key", skip(inner, claims, upload))]
+pub async fn set_organization_encryption_key(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+    Valid(upload): Valid<EncryptionKeyUpload>,
+) -> Result<Json<EncryptionKey>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "owner").await?;
+    let key_encryption_key = key_encryption_key(&settings)?;
+
+    let key_id = Uuid::new_v4().to_string();
+    let key = SealingKey::from_base64(&upload.key).ok_or_else(|| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Malformed key".to_string(),
+        )
+    })?;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    // Exports being stored wait, so none ends up sealed with a dropped key.
+    sqlx::query(r#"SELECT 1 FROM organizations WHERE id = $1 FOR UPDATE"#)
+        .bind(&organization_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+
+    let previous = organization_key(&mut *transaction, &settings, &organization_id)
+        .await
+        .map_err(|err| internal_error(&*err))?;
+
+    let exports: Vec<(String, Vec<u8>, Option<String>)> = sqlx::query_as(
+        r#"SELECT id, archive, encryption_key_id FROM organization_exports
+        WHERE organization_id = $1 AND archive IS NOT NULL"#,
+    )
+    .bind(&organization_id)
+    .fetch_all(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    for (export_id, archive, encryption_key_id) in exports {
+        let context = export_context(&export_id);
+        let archive = match (&encryption_key_id, &previous) {
+            (None, _) => archive,
+            (Some(id), Some(previous)) if *id == previous.id => previous
+                .key
+                .open_bytes(&context, &archive)
+                .map_err(internal_error)?,
+            (Some(_), _) => continue,
+        };
+
+        sqlx::query(
+            r#"UPDATE organization_exports SET archive = $1, encryption_key_id = $2 WHERE id = $3"#,
+        )
+        .bind(key.seal_bytes(&context, &archive))
+        .bind(&key_id)
+        .bind(&export_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+    }
+
+    let connection = sqlx::query_as::<_, SamlConnection>(
+        r#"SELECT * FROM saml_connections WHERE organization_id = $1"#,
+    )
+    .bind(&organization_id)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    if let Some(mut connection) = connection {
+        let readable = match (&connection.encryption_key_id, &previous) {
+            (None, _) => true,
+            (Some(id), Some(previous)) if *id == previous.id => {
+                connection.open(&previous.key).map_err(internal_error)?;
+                true
+            }
+            (Some(_), _) => false,
+        };
+
+        if readable {
+            connection.seal(&key);
+            sqlx::query(
+                r#"UPDATE saml_connections
+                SET idp_entity_id = $1, idp_sso_url = $2, idp_certificate = $3,
+                    encryption_key_id = $4
+                WHERE organization_id = $5"#,
+            )
+            .bind(&connection.idp_entity_id)
+            .bind(&connection.idp_sso_url)
+            .bind(&connection.idp_certificate)
+            .bind(&key_id)
+            .bind(&organization_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(internal_error)?;
+        }
+    }
+
+    sqlx::query(r#"DELETE FROM organization_encryption_keys WHERE organization_id = $1"#)
+        .bind(&organization_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+
+    let stored = sqlx::query_as::<_, EncryptionKey>(
+        r#"INSERT INTO organization_encryption_keys (id, organization_id, sealed_key, fingerprint)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, organization_id, fingerprint, created_at"#,
+    )
+    .bind(&key_id)
+    .bind(&organization_id)
+    .bind(key_encryption_key.seal(&key_context(&organization_id, &key_id), upload.key.trim()))
+    .bind(fingerprint(&upload.key))
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(Json(stored))
+}
+
+/// Drop the organization's key, shredding everything sealed with it.
+/// Exports made and SAML connections set afterwards are stored without
+/// encryption until a new key is set.
+#[tracing::instrument(name = "Revoke organization encryption key", skip(inner, claims))]
+pub async fn revoke_organization_encryption_key(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "owner").await?;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    sqlx::query(r#"SELECT 1 FROM organizations WHERE id = $1 FOR UPDATE"#)
+        .bind(&organization_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+
+    let revoked =
+        sqlx::query(r#"DELETE FROM organization_encryption_keys WHERE organization_id = $1"#)
+            .bind(&organization_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(internal_error)?
+            .rows_affected();
+    if revoked == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    tracing::info!(
+        "Revoked the encryption key of organization {}",
+        organization_id
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,269 @@
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::configuration::Settings;
+use crate::routes::{
+    export_context, fetch_organization_members, organization_key, require_organization_role,
+};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationExport {
+    pub id: String,
+    pub organization_id: String,
+    pub requested_by: String,
+    pub status: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+    pub error: Option<String>,
+}
+
+#[tracing::instrument(name = "Request organization export", skip(inner, claims))]
+pub async fn request_organization_export(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<(StatusCode, Json<OrganizationExport>), (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let export = sqlx::query_as::<_, OrganizationExport>(
+        r#"INSERT INTO organization_exports (id, organization_id, requested_by) values($1, $2, $3)
+        returning id, organization_id, requested_by, status, created_at, completed_at, error"#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&organization_id)
+    .bind(&claims.sub)
+    .fetch_one(&db)
+    .await
+    .map_err(internal_error)?;
+
+    let export_id = export.id.clone();
+    tokio::spawn(
+        async move { run_organization_export(db, settings, organization_id, export_id).await },
+    );
+
+    Ok((StatusCode::ACCEPTED, Json(export)))
+}
+
+pub async fn organization_export_status(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, export_id)): Path<(String, String)>,
+) -> Result<Json<OrganizationExport>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let export = sqlx::query_as::<_, OrganizationExport>(
+        r#"SELECT id, organization_id, requested_by, status, created_at, completed_at, error
+        FROM organization_exports WHERE id = $1 AND organization_id = $2"#,
+    )
+    .bind(export_id)
+    .bind(organization_id)
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    Ok(Json(export))
+}
+
+/// The archive of an export, opened with the organization's key when it
+/// was sealed with one. Gone for good once that key is revoked.
+pub async fn download_organization_export(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, export_id)): Path<(String, String)>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let stored: Option<(Option<Vec<u8>>, Option<String>)> = sqlx::query_as(
+        r#"SELECT archive, encryption_key_id FROM organization_exports
+        WHERE id = $1 AND organization_id = $2"#,
+    )
+    .bind(&export_id)
+    .bind(&organization_id)
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?;
+
+    let (archive, encryption_key_id) =
+        stored.ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+    let archive =
+        archive.ok_or_else(|| (StatusCode::CONFLICT, "Export is not ready".to_string()))?;
+
+    let archive = match encryption_key_id {
+        None => archive,
+        Some(encryption_key_id) => {
+            let key = organization_key(&db, &settings, &organization_id)
+                .await
+                .map_err(|err| internal_error(&*err))?
+                .filter(|key| key.id == encryption_key_id)
+                .ok_or_else(|| {
+                    (
+                        StatusCode::GONE,
+                        "The key this export was encrypted with has been revoked".to_string(),
+                    )
+                })?;
+
+            key.key
+                .open_bytes(&export_context(&export_id), &archive)
+                .map_err(internal_error)?
+        }
+    };
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"organization-{}-export-{}.json\"",
+                organization_id, export_id
+            ),
+        )
+        .body(Body::from(archive))
+        .expect("This response should always be constructable"))
+}
+
+#[tracing::instrument(name = "Run organization export", skip(db, settings))]
+async fn run_organization_export(
+    db: PgPool,
+    settings: Arc<Settings>,
+    organization_id: String,
+    export_id: String,
+) {
+    let outcome = match compile_organization_archive(&db, &organization_id).await {
+        Ok(archive) => store_archive(&db, &settings, &organization_id, &export_id, archive).await,
+        Err(err) => Err(err),
+    };
+
+    if let Err(err) = outcome {
+        tracing::error!("Organization export failed: {:?}", err);
+        let update = sqlx::query(
+            r#"UPDATE organization_exports SET status = 'failed', completed_at = CURRENT_TIMESTAMP, error = $1 WHERE id = $2"#,
+        )
+        .bind(err.to_string())
+        .bind(&export_id)
+        .execute(&db)
+        .await;
+
+        if let Err(err) = update {
+            tracing::error!("Could not store organization export {}: {}", export_id, err);
+        }
+    }
+}
+
+/// Store the archive, sealed with the organization's key if it has one.
+async fn store_archive(
+    db: &PgPool,
+    settings: &Settings,
+    organization_id: &str,
+    export_id: &str,
+    archive: Vec<u8>,
+) -> anyhow::Result<()> {
+    let mut transaction = db.begin().await?;
+
+    // Keeps the key from being rotated or revoked until the archive is in.
+    sqlx::query(r#"SELECT 1 FROM organizations WHERE id = $1 FOR SHARE"#)
+        .bind(organization_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    let (archive, encryption_key_id) =
+        match organization_key(&mut *transaction, settings, organization_id).await? {
+            Some(key) => (
+                key.key.seal_bytes(&export_context(export_id), &archive),
+                Some(key.id),
+            ),
+            None => (archive, None),
+        };
+
+    sqlx::query(
+        r#"UPDATE organization_exports SET status = 'completed', completed_at = CURRENT_TIMESTAMP, archive = $1, encryption_key_id = $2 WHERE id = $3"#,
+    )
+    .bind(archive)
+    .bind(encryption_key_id)
+    .bind(export_id)
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Gather everything belonging to the organization into one JSON document.
+async fn compile_organization_archive(
+    db: &PgPool,
+    organization_id: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let organization: Value =
+        sqlx::query_scalar(r#"SELECT to_jsonb(organizations) FROM organizations WHERE id = $1"#)
+            .bind(organization_id)
+            .fetch_one(db)
+            .await
+            .context("Failed to fetch organization.")?;
+
+    let members = fetch_organization_members(db, organization_id)
+        .await
+        .map_err(|(_, err)| anyhow::anyhow!(err))?;
+
+    let links: Vec<Value> = sqlx::query_scalar(
+        r#"SELECT to_jsonb(links) FROM links WHERE organization_id = $1 ORDER BY id"#,
+    )
+    .bind(organization_id)
+    .fetch_all(db)
+    .await
+    .context("Failed to fetch links.")?;
+
+    let statistics: Vec<Value> = sqlx::query_scalar(
+        r#"SELECT jsonb_build_object('linkId', link_id, 'referer', referer, 'userAgent', user_agent, 'amount', sum(sample_rate))
+        FROM link_statistics
+        WHERE link_id IN (SELECT id FROM links WHERE organization_id = $1)
+        GROUP BY link_id, referer, user_agent
+        ORDER BY link_id"#,
+    )
+    .bind(organization_id)
+    .fetch_all(db)
+    .await
+    .context("Failed to fetch statistics.")?;
+
+    let audit: Vec<Value> = sqlx::query_scalar(
+        r#"SELECT to_jsonb(admin_audit) FROM admin_audit
+        WHERE target = $1 OR target IN (SELECT id FROM links WHERE organization_id = $1)
+        ORDER BY id"#,
+    )
+    .bind(organization_id)
+    .fetch_all(db)
+    .await
+    .context("Failed to fetch audit entries.")?;
+
+    let archive = serde_json::json!({
+        "exportedAt": chrono::Utc::now(),
+        "organization": organization,
+        "members": members,
+        "links": links,
+        "statistics": statistics,
+        "audit": audit,
+    });
+
+    Ok(serde_json::to_vec_pretty(&archive)?)
+}
@@ -0,0 +1,291 @@
+//! Invitations into an organization, sent by email and accepted by whoever
+//! logs in with the address they were sent to.
+
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::configuration::Settings;
+use crate::email::EmailClient;
+use crate::i18n;
+use crate::pagination::Page;
+use crate::routes::{
+    fetch_organization_members, generate_subscription_token, get_stored_credentials,
+    require_organization_role, require_organization_role_name, OrganizationMember,
+};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const INVITATION_LIFETIME_DAYS: i32 = 7;
+
+#[derive(Debug, Deserialize)]
+pub struct NewInvitation {
+    pub email: String,
+    /// Role the invitee joins with, `member` by default.
+    pub role: Option<String>,
+}
+
+impl Validate for NewInvitation {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_email("email", self.email.trim());
+        if let Some(role) = &self.role {
+            require_organization_role_name(errors, role);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Invitation {
+    pub id: String,
+    pub organization_id: String,
+    pub email: String,
+    pub role: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct InvitationAcceptance {
+    pub token: String,
+}
+
+/// Invite someone by email. Admins invite members and admins, and owners
+/// invite owners too. Inviting an address again replaces its pending
+/// invitation.
+#[tracing::instrument(name = "Invite organization member", skip(inner, claims, invitation))]
+pub async fn invite_organization_member(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+    Valid(invitation): Valid<NewInvitation>,
+) -> Result<(StatusCode, Json<Invitation>), (StatusCode, String)> {
+    let InnerState {
+        db,
+        email_client,
+        settings,
+        ..
+    } = inner;
+
+    let role = invitation.role.as_deref().unwrap_or("member");
+    let minimum_role = if role == "owner" { "owner" } else { "admin" };
+    let inviter_id =
+        require_organization_role(&db, &organization_id, &claims, minimum_role).await?;
+
+    let email = invitation.email.trim().to_lowercase();
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+    let (invitation, token) = insert_invitation(
+        &mut transaction,
+        &organization_id,
+        &email,
+        role,
+        Some(&inviter_id),
+    )
+    .await
+    .map_err(internal_error)?;
+    transaction.commit().await.map_err(internal_error)?;
+
+    send_invitation_email(email_client, &settings, &invitation, &token);
+
+    Ok((StatusCode::CREATED, Json(invitation)))
+}
+
+/// Store an invitation of `email`, replacing its pending one, and return it
+/// with the token to accept it with. `inviter_id` is None for invitations
+/// SCIM sends on the organization's behalf.
+pub async fn insert_invitation(
+    transaction: &mut Transaction<'_, Postgres>,
+    organization_id: &str,
+    email: &str,
+    role: &str,
+    inviter_id: Option<&str>,
+) -> Result<(Invitation, String), sqlx::Error> {
+    let token = generate_subscription_token();
+
+    sqlx::query(
+        r#"DELETE FROM organization_invitations
+        WHERE organization_id = $1 AND email = $2 AND accepted_at IS NULL"#,
+    )
+    .bind(organization_id)
+    .bind(email)
+    .execute(&mut **transaction)
+    .await?;
+
+    let invitation = sqlx::query_as::<_, Invitation>(
+        r#"INSERT INTO organization_invitations
+            (id, token, organization_id, email, role, invited_by, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP + make_interval(days => $7))
+        RETURNING id, organization_id, email, role, created_at, expires_at"#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&token)
+    .bind(organization_id)
+    .bind(email)
+    .bind(role)
+    .bind(inviter_id)
+    .bind(INVITATION_LIFETIME_DAYS)
+    .fetch_one(&mut **transaction)
+    .await?;
+
+    Ok((invitation, token))
+}
+
+/// Invitations not accepted yet and not expired, newest first.
+pub async fn organization_invitations(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<Json<Page<Invitation>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let invitations = sqlx::query_as::<_, Invitation>(
+        r#"SELECT id, organization_id, email, role, created_at, expires_at
+        FROM organization_invitations
+        WHERE organization_id = $1 AND accepted_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        ORDER BY created_at DESC"#,
+    )
+    .bind(&organization_id)
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(invitations)))
+}
+
+#[tracing::instrument(name = "Revoke organization invitation", skip(inner, claims))]
+pub async fn revoke_organization_invitation(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, invitation_id)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let revoked = sqlx::query(
+        r#"DELETE FROM organization_invitations
+        WHERE organization_id = $1 AND id = $2 AND accepted_at IS NULL"#,
+    )
+    .bind(&organization_id)
+    .bind(&invitation_id)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?
+    .rows_affected();
+    if revoked == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Join the organization of an invitation sent to the caller's address.
+/// Members already in it keep their role.
+#[tracing::instrument(
+    name = "Accept organization invitation",
+    skip(inner, claims, acceptance)
+)]
+pub async fn accept_organization_invitation(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Json(acceptance): Json<InvitationAcceptance>,
+) -> Result<Json<OrganizationMember>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let user = get_stored_credentials(&claims.sub, &db).await?;
+    let user_id = user.id.unwrap_or_default();
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    // Invitations sent to another address are not found, so tokens that
+    // got forwarded are no use.
+    let (organization_id, role): (String, String) = sqlx::query_as(
+        r#"UPDATE organization_invitations SET accepted_at = CURRENT_TIMESTAMP
+        WHERE token = $1 AND email = lower($2) AND accepted_at IS NULL
+            AND expires_at > CURRENT_TIMESTAMP
+        RETURNING organization_id, role"#,
+    )
+    .bind(&acceptance.token)
+    .bind(&user.email)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    sqlx::query(
+        r#"INSERT INTO organization_members (organization_id, user_id, role)
+        VALUES ($1, $2, $3) ON CONFLICT (organization_id, user_id) DO NOTHING"#,
+    )
+    .bind(&organization_id)
+    .bind(&user_id)
+    .bind(&role)
+    .execute(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    let member = fetch_organization_members(&mut *transaction, &organization_id)
+        .await?
+        .into_iter()
+        .find(|member| member.user_id == user_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(Json(member))
+}
+
+/// Send the invitation in the background, so a slow or failing email
+/// service does not hold up or undo it.
+pub fn send_invitation_email(
+    email_client: EmailClient,
+    settings: &Settings,
+    invitation: &Invitation,
+    token: &str,
+) {
+    let Some(template_id) = settings.invitation_template_id.clone() else {
+        tracing::info!(
+            "Not sending invitation {} without INVITATION_TEMPLATE_ID",
+            invitation.id
+        );
+        return;
+    };
+
+    let email = invitation.email.clone();
+    let mut template_model = HashMap::new();
+    template_model.insert("locale".to_owned(), i18n::DEFAULT_LOCALE.to_owned());
+    template_model.insert("product_name".to_owned(), "Groupify".to_owned());
+    template_model.insert("role".to_owned(), invitation.role.clone());
+    template_model.insert(
+        "action_url".to_owned(),
+        format!(
+            "{}/invitations/accept?token={}",
+            settings.public_base_url, token
+        ),
+    );
+    template_model.insert("support_email".to_owned(), "admin@groupify.dev".to_owned());
+
+    tokio::spawn(async move {
+        let sent = email_client
+            .send_email(
+                &email,
+                "organization-invitation",
+                template_model,
+                &template_id,
+            )
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = sent {
+            tracing::error!("Could not send invitation: {}", err);
+        }
+    });
+}
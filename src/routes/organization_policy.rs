@@ -0,0 +1,202 @@
+//! Policies organizations attach to their roles, or hand to devices they
+//! approve, refining what members may do by the attributes of links. See
+//! `policy` for how they are evaluated.
+
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::pagination::Page;
+use crate::policy::{self, Statement};
+use crate::routes::{require_organization_role, ORGANIZATION_ROLES};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json as SqlJson;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Statements a policy may hold, each checked on every authorization it
+/// bears on.
+const MAX_POLICY_STATEMENTS: usize = 50;
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationPolicy {
+    pub id: String,
+    pub organization_id: String,
+    pub name: String,
+    /// Roles the policy applies to, all of them when empty.
+    pub roles: Vec<String>,
+    pub statements: SqlJson<Vec<Statement>>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub statements: Vec<Statement>,
+}
+
+impl Validate for PolicyDefinition {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_not_blank("name", &self.name);
+        errors.require_max_length("name", &self.name, 100);
+        if self
+            .roles
+            .iter()
+            .any(|role| !ORGANIZATION_ROLES.contains(&role.as_str()))
+        {
+            errors.add("roles", "must be member, admin or owner");
+        }
+        if self.statements.is_empty() {
+            errors.add("statements", "must not be empty");
+        }
+        if self.statements.len() > MAX_POLICY_STATEMENTS {
+            errors.add(
+                "statements",
+                format!("must hold at most {} statements", MAX_POLICY_STATEMENTS),
+            );
+        }
+
+        for statement in &self.statements {
+            if statement.actions.is_empty() {
+                errors.add("statements[].actions", "must not be empty");
+            }
+            if let Some(action) = statement
+                .actions
+                .iter()
+                .find(|action| !policy::is_action_pattern(action))
+            {
+                errors.add(
+                    "statements[].actions",
+                    format!("{} is not a known action", action),
+                );
+            }
+            let conditions = &statement.conditions;
+            if conditions
+                .tags
+                .iter()
+                .chain(&conditions.campaigns)
+                .chain(&conditions.domains)
+                .any(|value| value.trim().is_empty())
+            {
+                errors.add("statements[].conditions", "must not hold empty values");
+            }
+        }
+    }
+}
+
+/// The organization's policies, for its admins.
+pub async fn list_organization_policies(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<Json<Page<OrganizationPolicy>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let policies = sqlx::query_as::<_, OrganizationPolicy>(
+        r#"SELECT * FROM organization_policies WHERE organization_id = $1 ORDER BY created_at, id"#,
+    )
+    .bind(&organization_id)
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(policies)))
+}
+
+/// Add a policy, bearing on its roles right away. Only owners may, as a
+/// policy can give members more than their role.
+#[tracing::instrument(name = "Create organization policy", skip(inner, claims, definition))]
+pub async fn create_organization_policy(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+    Valid(definition): Valid<PolicyDefinition>,
+) -> Result<(StatusCode, Json<OrganizationPolicy>), (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "owner").await?;
+
+    let policy = sqlx::query_as::<_, OrganizationPolicy>(
+        r#"INSERT INTO organization_policies (id, organization_id, name, roles, statements)
+        VALUES ($1, $2, $3, $4, $5) RETURNING *"#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&organization_id)
+    .bind(definition.name.trim())
+    .bind(&definition.roles)
+    .bind(SqlJson(&definition.statements))
+    .fetch_one(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok((StatusCode::CREATED, Json(policy)))
+}
+
+/// Replace a policy, which tokens carrying it follow from then on.
+#[tracing::instrument(name = "Update organization policy", skip(inner, claims, definition))]
+pub async fn update_organization_policy(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, policy_id)): Path<(String, String)>,
+    Valid(definition): Valid<PolicyDefinition>,
+) -> Result<Json<OrganizationPolicy>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "owner").await?;
+
+    let policy = sqlx::query_as::<_, OrganizationPolicy>(
+        r#"UPDATE organization_policies SET name = $3, roles = $4, statements = $5,
+        updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND organization_id = $2 RETURNING *"#,
+    )
+    .bind(&policy_id)
+    .bind(&organization_id)
+    .bind(definition.name.trim())
+    .bind(&definition.roles)
+    .bind(SqlJson(&definition.statements))
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    Ok(Json(policy))
+}
+
+/// Delete a policy. Tokens carrying it may do nothing any more, as they
+/// are limited to what their policies allow.
+#[tracing::instrument(name = "Delete organization policy", skip(inner, claims))]
+pub async fn delete_organization_policy(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path((organization_id, policy_id)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "owner").await?;
+
+    let deleted =
+        sqlx::query(r#"DELETE FROM organization_policies WHERE id = $1 AND organization_id = $2"#)
+            .bind(&policy_id)
+            .bind(&organization_id)
+            .execute(&db)
+            .await
+            .map_err(internal_error)?;
+
+    if deleted.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
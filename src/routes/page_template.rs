@@ -0,0 +1,396 @@
+use crate::authentication::AdminUser;
+use crate::branding::{self, Branding};
+use crate::casing::Json;
+use crate::configuration::Settings;
+use crate::i18n;
+use crate::pagination::Page;
+use crate::routes::record_admin_action;
+use crate::templates::Template;
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// Domain of templates used when the requested host has none of its own.
+pub const DEFAULT_TEMPLATE_DOMAIN: &str = "*";
+
+/// The templates of the theme. Every one of them can be replaced per domain,
+/// in the database or in the theme directory.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PageKind {
+    /// Wraps every other page, which it receives as `{{{content}}}`.
+    Layout,
+    NotFound,
+    Gone,
+    /// The tracking consent question, which must post `decision` to
+    /// `{{consent_action}}`.
+    Interstitial,
+    /// Where a link leads, shown without recording a click.
+    Preview,
+    /// The landing page of a vCard link, offering `{{vcard_url}}`.
+    Contact,
+    /// The landing page of a Wi-Fi link.
+    Wifi,
+    /// The public page of a group, listing its links as `{{{group_links}}}`.
+    Group,
+    /// Where a device's user code is approved, which must post `user_code`,
+    /// `email`, `password` and `decision` to `/device`.
+    Device,
+    /// Confirms revoking a session from a new device email, which must post
+    /// `token` to `/auth/sessions/revoke`. The token comes as `{{token}}` or,
+    /// through the short link, in the fragment of the page's URL.
+    RevokeSession,
+}
+
+impl PageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PageKind::Layout => "layout",
+            PageKind::NotFound => "not_found",
+            PageKind::Gone => "gone",
+            PageKind::Interstitial => "interstitial",
+            PageKind::Preview => "preview",
+            PageKind::Contact => "contact",
+            PageKind::Wifi => "wifi",
+            PageKind::Group => "group",
+            PageKind::Device => "device",
+            PageKind::RevokeSession => "revoke_session",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            PageKind::NotFound => StatusCode::NOT_FOUND,
+            PageKind::Gone => StatusCode::GONE,
+            PageKind::Layout
+            | PageKind::Interstitial
+            | PageKind::Preview
+            | PageKind::Contact
+            | PageKind::Wifi
+            | PageKind::Group
+            | PageKind::Device
+            | PageKind::RevokeSession => StatusCode::OK,
+        }
+    }
+
+    fn built_in(&self) -> &'static str {
+        match self {
+            PageKind::Layout => include_str!("../../templates/layout.html"),
+            PageKind::NotFound => include_str!("../../templates/not_found.html"),
+            PageKind::Gone => include_str!("../../templates/gone.html"),
+            PageKind::Interstitial => include_str!("../../templates/interstitial.html"),
+            PageKind::Preview => include_str!("../../templates/preview.html"),
+            PageKind::Contact => include_str!("../../templates/contact.html"),
+            PageKind::Wifi => include_str!("../../templates/wifi.html"),
+            PageKind::Group => include_str!("../../templates/group.html"),
+            PageKind::Device => include_str!("../../templates/device.html"),
+            PageKind::RevokeSession => include_str!("../../templates/revoke_session.html"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PageTemplate {
+    pub domain: String,
+    pub kind: String,
+    pub body: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize)]
+pub struct PageTemplateUpdate {
+    pub domain: Option<String>,
+    pub body: String,
+}
+
+impl Validate for PageTemplateUpdate {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_not_blank("body", &self.body);
+        if let Err(err) = Template::parse(&self.body) {
+            errors.add("body", err.to_string());
+        }
+
+        let domain_is_valid = self.domain.as_deref().is_none_or(|domain| {
+            domain == DEFAULT_TEMPLATE_DOMAIN || is_domain_name(&domain.to_ascii_lowercase())
+        });
+        if !domain_is_valid {
+            errors.add("domain", "must be a domain name or *");
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageTemplateQuery {
+    pub domain: Option<String>,
+}
+
+/// The host the request was made to, lowercased and without the port, if it
+/// is a plausible domain name. Anything else could escape the theme
+/// directory.
+pub fn request_domain(headers: &HeaderMap) -> Option<String> {
+    let host = headers.get("host")?.to_str().ok()?;
+    let domain = host.rsplit_once(':').map_or(host, |(domain, _)| domain);
+    let domain = domain.to_ascii_lowercase();
+
+    is_domain_name(&domain).then_some(domain)
+}
+
+pub(crate) fn is_domain_name(domain: &str) -> bool {
+    !domain.is_empty()
+        && !domain.contains("..")
+        && domain
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || matches!(character, '.' | '-'))
+}
+
+async fn database_template(db: &PgPool, domain: &str, kind: PageKind) -> Option<String> {
+    sqlx::query_scalar(r#"SELECT body FROM page_templates WHERE domain = $1 AND kind = $2"#)
+        .bind(domain)
+        .bind(kind.as_str())
+        .fetch_optional(db)
+        .await
+        .unwrap_or_else(|err| {
+            tracing::error!("Could not load page template: {}", err);
+            None
+        })
+}
+
+async fn disk_template(
+    settings: &Settings,
+    domain: Option<&str>,
+    kind: PageKind,
+) -> Option<String> {
+    let mut path = settings.theme_dir.clone()?;
+    if let Some(domain) = domain {
+        path.push(domain);
+    }
+    path.push(format!("{}.html", kind.as_str()));
+
+    tokio::fs::read_to_string(path).await.ok()
+}
+
+/// Find the most specific template for the page: the domain's own, then
+/// the default domain's, database before disk.
+async fn find_template(
+    db: &PgPool,
+    settings: &Settings,
+    domain: Option<&str>,
+    kind: PageKind,
+) -> Option<String> {
+    if let Some(domain) = domain {
+        if let Some(template) = database_template(db, domain, kind).await {
+            return Some(template);
+        }
+        if let Some(template) = disk_template(settings, Some(domain), kind).await {
+            return Some(template);
+        }
+    }
+
+    if let Some(template) = database_template(db, DEFAULT_TEMPLATE_DOMAIN, kind).await {
+        return Some(template);
+    }
+    disk_template(settings, None, kind).await
+}
+
+/// Render one template of the theme, falling back to the built in one when
+/// the theme has none or it does not parse.
+async fn render_template(
+    db: &PgPool,
+    settings: &Settings,
+    domain: Option<&str>,
+    kind: PageKind,
+    values: &[(&str, &str)],
+) -> String {
+    if let Some(source) = find_template(db, settings, domain, kind).await {
+        match Template::parse(&source) {
+            Ok(template) => return template.render(values),
+            Err(err) => tracing::error!("Invalid {} template: {}", kind.as_str(), err),
+        }
+    }
+
+    Template::parse(kind.built_in())
+        .expect("The built in templates should always parse")
+        .render(values)
+}
+
+pub async fn render_page(
+    db: &PgPool,
+    settings: &Settings,
+    headers: &HeaderMap,
+    kind: PageKind,
+    values: &[(&str, &str)],
+) -> Response {
+    render(db, settings, headers, kind, values, None).await
+}
+
+/// Render a page about a link, branded as the link's organization has
+/// chosen.
+pub async fn render_link_page(
+    db: &PgPool,
+    settings: &Settings,
+    headers: &HeaderMap,
+    kind: PageKind,
+    link_id: &str,
+    values: &[(&str, &str)],
+) -> Response {
+    let branding = branding::for_link(db, link_id).await;
+
+    render(db, settings, headers, kind, values, branding.as_deref()).await
+}
+
+async fn render(
+    db: &PgPool,
+    settings: &Settings,
+    headers: &HeaderMap,
+    kind: PageKind,
+    values: &[(&str, &str)],
+    branding: Option<&Branding>,
+) -> Response {
+    let domain = request_domain(headers);
+    let locale = i18n::negotiate(headers);
+
+    let mut values = values.to_vec();
+    if let Some(branding) = branding {
+        values.extend(branding.page_values());
+    }
+    if let Some(domain) = &domain {
+        values.push(("domain", domain));
+    }
+    values.push(("lang", locale));
+    values.extend(i18n::translations(locale, "page."));
+
+    let title = format!("page.{}.title", kind.as_str());
+    if let Some(title) = i18n::translate(locale, &title) {
+        values.push(("title", title));
+    }
+
+    let content = render_template(db, settings, domain.as_deref(), kind, &values).await;
+
+    // Pages that are a complete document bring their own layout.
+    let trimmed = content.trim_start().to_ascii_lowercase();
+    let page = if trimmed.starts_with("<!doctype") || trimmed.starts_with("<html") {
+        content
+    } else {
+        values.push(("content", &content));
+        render_template(db, settings, domain.as_deref(), PageKind::Layout, &values).await
+    };
+
+    Response::builder()
+        .status(kind.status())
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Cache-Control", "no-store")
+        .header("Content-Language", locale)
+        .header("Vary", "Accept-Language")
+        .body(Body::from(page))
+        .expect("This response should always be constructable")
+}
+
+pub async fn list_page_templates(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Result<Json<Page<PageTemplate>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let templates =
+        sqlx::query_as::<_, PageTemplate>(r#"SELECT * FROM page_templates ORDER BY domain, kind"#)
+            .fetch_all(&db)
+            .await
+            .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(templates)))
+}
+
+#[tracing::instrument(name = "Update page template", skip(inner, admin, update))]
+pub async fn update_page_template(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(kind): Path<PageKind>,
+    Valid(update): Valid<PageTemplateUpdate>,
+) -> Result<Json<PageTemplate>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let domain = update
+        .domain
+        .unwrap_or_else(|| DEFAULT_TEMPLATE_DOMAIN.to_string())
+        .to_ascii_lowercase();
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let template = sqlx::query_as::<_, PageTemplate>(
+        r#"INSERT INTO page_templates (domain, kind, body) VALUES ($1, $2, $3)
+        ON CONFLICT (domain, kind) DO UPDATE SET body = excluded.body, updated_at = CURRENT_TIMESTAMP
+        returning *"#,
+    )
+    .bind(&domain)
+    .bind(kind.as_str())
+    .bind(update.body)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "page_template.update",
+        Some(&format!("{}/{}", domain, kind.as_str())),
+        None,
+        None,
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(Json(template))
+}
+
+#[tracing::instrument(name = "Delete page template", skip(inner, admin))]
+pub async fn delete_page_template(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(kind): Path<PageKind>,
+    Query(query): Query<PageTemplateQuery>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let domain = query
+        .domain
+        .unwrap_or_else(|| DEFAULT_TEMPLATE_DOMAIN.to_string())
+        .to_ascii_lowercase();
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let deleted = sqlx::query(r#"DELETE FROM page_templates WHERE domain = $1 AND kind = $2"#)
+        .bind(&domain)
+        .bind(kind.as_str())
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+
+    if deleted.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "page_template.delete",
+        Some(&format!("{}/{}", domain, kind.as_str())),
+        None,
+        None,
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,92 @@
+use crate::configuration::Settings;
+use crate::db::links;
+use crate::link_payload::LinkPayload;
+use crate::routes::{render_link_page, PageKind};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use sqlx::PgPool;
+
+/// The landing page a link carrying a payload serves instead of
+/// redirecting.
+pub async fn payload_page(
+    db: &PgPool,
+    settings: &Settings,
+    headers: &HeaderMap,
+    link_id: &str,
+    payload: &LinkPayload,
+) -> Response {
+    let qr_url = format!("{}/{}/qr.svg", settings.public_base_url, link_id);
+
+    match payload {
+        LinkPayload::Vcard(contact) => {
+            let vcard_url = format!("{}/{}/contact.vcf", settings.public_base_url, link_id);
+            let field = |value: &Option<String>| value.clone().unwrap_or_default();
+            let (organization, title, phone, email, website, note) = (
+                field(&contact.organization),
+                field(&contact.title),
+                field(&contact.phone),
+                field(&contact.email),
+                field(&contact.website),
+                field(&contact.note),
+            );
+            let values = [
+                ("link_id", link_id),
+                ("qr_url", qr_url.as_str()),
+                ("vcard_url", vcard_url.as_str()),
+                ("contact_name", contact.name.as_str()),
+                ("contact_organization", organization.as_str()),
+                ("contact_title", title.as_str()),
+                ("contact_phone", phone.as_str()),
+                ("contact_email", email.as_str()),
+                ("contact_website", website.as_str()),
+                ("contact_note", note.as_str()),
+            ];
+
+            render_link_page(db, settings, headers, PageKind::Contact, link_id, &values).await
+        }
+        LinkPayload::Wifi(network) => {
+            let values = [
+                ("link_id", link_id),
+                ("qr_url", qr_url.as_str()),
+                ("wifi_ssid", network.ssid.as_str()),
+                (
+                    "wifi_password",
+                    network.password.as_deref().unwrap_or_default(),
+                ),
+            ];
+
+            render_link_page(db, settings, headers, PageKind::Wifi, link_id, &values).await
+        }
+    }
+}
+
+/// The contact card of an active vCard link, for the landing page to offer
+/// as a download.
+pub async fn link_vcard(
+    State(inner): State<InnerState>,
+    Path(link_id): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let Some(Some(LinkPayload::Vcard(contact))) = links::active_payload(&db, &link_id)
+        .await
+        .map_err(internal_error)?
+    else {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    };
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/vcard; charset=utf-8")
+        .header(
+            "Content-Disposition",
+            r#"attachment; filename="contact.vcf""#,
+        )
+        .body(Body::from(contact.to_vcard()))
+        .expect("This response should always be constructable"))
+}
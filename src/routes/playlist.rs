@@ -0,0 +1,323 @@
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::db::links::{NewLink, RedirectBehavior};
+use crate::routes::{
+    generate_id, generate_subscription_token, get_stored_credentials, insert_checked_link,
+    require_group_owner,
+};
+use crate::spotify::{PlaylistTrack, SpotifyClient};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Html;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// Spotify authorization requests older than this are rejected.
+const OAUTH_STATE_TTL_MINUTES: i32 = 10;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpotifyAuthorization {
+    pub authorize_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct SpotifyCallback {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupPlaylist {
+    pub group_id: String,
+    pub playlist_id: String,
+    pub playlist_url: String,
+    pub owner_user_id: String,
+    pub link_id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupPlaylistTracks {
+    #[serde(flatten)]
+    pub playlist: GroupPlaylist,
+    pub tracks: Vec<PlaylistTrack>,
+}
+
+#[derive(FromRow)]
+struct SpotifyAccount {
+    spotify_user_id: String,
+    access_token: String,
+    refresh_token: String,
+    fresh: bool,
+}
+
+fn spotify_client(inner: &InnerState) -> Result<SpotifyClient, (StatusCode, String)> {
+    inner.spotify.clone().ok_or_else(|| {
+        (
+            StatusCode::NOT_IMPLEMENTED,
+            "Spotify integration is not configured".to_string(),
+        )
+    })
+}
+
+fn bad_gateway(err: reqwest::Error) -> (StatusCode, String) {
+    tracing::error!("Spotify request failed: {}", err);
+    (
+        StatusCode::BAD_GATEWAY,
+        "Spotify request failed".to_string(),
+    )
+}
+
+#[tracing::instrument(name = "Start Spotify connection", skip(inner, claims))]
+pub async fn connect_spotify(
+    State(inner): State<InnerState>,
+    claims: Claims,
+) -> Result<Json<SpotifyAuthorization>, (StatusCode, String)> {
+    let spotify = spotify_client(&inner)?;
+    let user = get_stored_credentials(&claims.sub, &inner.db).await?;
+
+    let state = generate_subscription_token();
+
+    sqlx::query(r#"INSERT INTO spotify_oauth_states (state, user_id) VALUES ($1, $2)"#)
+        .bind(&state)
+        .bind(user.id)
+        .execute(&inner.db)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(SpotifyAuthorization {
+        authorize_url: spotify.authorize_url(&state),
+    }))
+}
+
+#[tracing::instrument(name = "Finish Spotify connection", skip(inner, callback))]
+pub async fn spotify_callback(
+    State(inner): State<InnerState>,
+    Query(callback): Query<SpotifyCallback>,
+) -> Result<Html<&'static str>, (StatusCode, String)> {
+    let spotify = spotify_client(&inner)?;
+    let InnerState { db, .. } = inner;
+
+    let user_id: String = sqlx::query_scalar(
+        r#"DELETE FROM spotify_oauth_states
+        WHERE state = $1 AND created_at > CURRENT_TIMESTAMP - make_interval(mins => $2)
+        returning user_id"#,
+    )
+    .bind(&callback.state)
+    .bind(OAUTH_STATE_TTL_MINUTES)
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            "Unknown or expired state".to_string(),
+        )
+    })?;
+
+    let code = match (callback.code, callback.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Spotify authorization failed: {}",
+                    error.unwrap_or_default()
+                ),
+            ))
+        }
+    };
+
+    let tokens = spotify.exchange_code(&code).await.map_err(bad_gateway)?;
+    let refresh_token = tokens.refresh_token.ok_or_else(|| {
+        (
+            StatusCode::BAD_GATEWAY,
+            "Spotify returned no refresh token".to_string(),
+        )
+    })?;
+    let spotify_user_id = spotify
+        .current_user_id(&tokens.access_token)
+        .await
+        .map_err(bad_gateway)?;
+
+    sqlx::query(
+        r#"INSERT INTO spotify_accounts (user_id, spotify_user_id, access_token, refresh_token, expires_at)
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP + make_interval(secs => $5))
+        ON CONFLICT (user_id) DO UPDATE SET spotify_user_id = excluded.spotify_user_id,
+            access_token = excluded.access_token, refresh_token = excluded.refresh_token,
+            expires_at = excluded.expires_at, updated_at = CURRENT_TIMESTAMP"#,
+    )
+    .bind(user_id)
+    .bind(spotify_user_id)
+    .bind(tokens.access_token)
+    .bind(refresh_token)
+    .bind(tokens.expires_in as f64)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Html(
+        "<h1>Spotify connected</h1><p>You can close this window.</p>",
+    ))
+}
+
+/// A usable access token for the user's Spotify account, refreshing it
+/// first when it is about to expire.
+async fn spotify_access_token(
+    db: &PgPool,
+    spotify: &SpotifyClient,
+    user_id: &str,
+) -> Result<(String, String), (StatusCode, String)> {
+    let account = sqlx::query_as::<_, SpotifyAccount>(
+        r#"SELECT spotify_user_id, access_token, refresh_token,
+        expires_at > CURRENT_TIMESTAMP + interval '60 seconds' as fresh
+        FROM spotify_accounts WHERE user_id = $1"#,
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| {
+        (
+            StatusCode::PRECONDITION_FAILED,
+            "Spotify account is not connected".to_string(),
+        )
+    })?;
+
+    if account.fresh {
+        return Ok((account.access_token, account.spotify_user_id));
+    }
+
+    let tokens = spotify
+        .refresh(&account.refresh_token)
+        .await
+        .map_err(bad_gateway)?;
+
+    sqlx::query(
+        r#"UPDATE spotify_accounts SET access_token = $1, refresh_token = COALESCE($2, refresh_token),
+        expires_at = CURRENT_TIMESTAMP + make_interval(secs => $3), updated_at = CURRENT_TIMESTAMP
+        WHERE user_id = $4"#,
+    )
+    .bind(&tokens.access_token)
+    .bind(tokens.refresh_token)
+    .bind(tokens.expires_in as f64)
+    .bind(user_id)
+    .execute(db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok((tokens.access_token, account.spotify_user_id))
+}
+
+#[tracing::instrument(name = "Create group playlist", skip(inner, claims))]
+pub async fn create_group_playlist(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(group_id): Path<String>,
+) -> Result<Json<GroupPlaylist>, (StatusCode, String)> {
+    let spotify = spotify_client(&inner)?;
+    let InnerState { db, .. } = inner.clone();
+
+    let user_id = get_stored_credentials(&claims.sub, &db)
+        .await?
+        .id
+        .unwrap_or_default();
+
+    let group_name: String =
+        sqlx::query_scalar(r#"SELECT name FROM groups WHERE id = $1 AND user_id = $2"#)
+            .bind(&group_id)
+            .bind(&user_id)
+            .fetch_optional(&db)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let (access_token, spotify_user_id) = spotify_access_token(&db, &spotify, &user_id).await?;
+
+    let playlist = spotify
+        .create_collaborative_playlist(&access_token, &spotify_user_id, &group_name)
+        .await
+        .map_err(bad_gateway)?;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let created = insert_checked_link(
+        &mut transaction,
+        &inner,
+        &NewLink {
+            id: &generate_id(),
+            target_url: playlist.url(),
+            organization_id: None,
+            owner_email: Some(&claims.sub),
+            expires_at: None,
+            payload: None,
+            draft: false,
+            domain: None,
+            behavior: &RedirectBehavior::default(),
+            tags: &[],
+            campaign: None,
+        },
+    )
+    .await?;
+
+    let group_playlist = sqlx::query_as::<_, GroupPlaylist>(
+        r#"INSERT INTO group_playlists (group_id, playlist_id, playlist_url, owner_user_id, link_id)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (group_id) DO UPDATE SET playlist_id = excluded.playlist_id,
+            playlist_url = excluded.playlist_url, owner_user_id = excluded.owner_user_id,
+            link_id = excluded.link_id
+        returning group_id, playlist_id, playlist_url, owner_user_id, link_id"#,
+    )
+    .bind(&group_id)
+    .bind(&playlist.id)
+    .bind(playlist.url())
+    .bind(&user_id)
+    .bind(&created.link.id)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    created.committed(&inner);
+
+    Ok(Json(group_playlist))
+}
+
+/// The tracks of the group's playlist, read with its owner's Spotify
+/// account, so only the group's owner may read them.
+pub async fn group_playlist(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(group_id): Path<String>,
+) -> Result<Json<GroupPlaylistTracks>, (StatusCode, String)> {
+    let spotify = spotify_client(&inner)?;
+    let InnerState { db, .. } = inner;
+
+    require_group_owner(&db, &group_id, &claims).await?;
+
+    let playlist = sqlx::query_as::<_, GroupPlaylist>(
+        r#"SELECT group_id, playlist_id, playlist_url, owner_user_id, link_id
+        FROM group_playlists WHERE group_id = $1"#,
+    )
+    .bind(&group_id)
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let (access_token, _) = spotify_access_token(&db, &spotify, &playlist.owner_user_id).await?;
+
+    let tracks = spotify
+        .playlist_tracks(&access_token, &playlist.playlist_id)
+        .await
+        .map_err(bad_gateway)?;
+
+    Ok(Json(GroupPlaylistTracks { playlist, tracks }))
+}
@@ -0,0 +1,150 @@
+use crate::authentication::AdminUser;
+use crate::casing::Json;
+use crate::pagination::Page;
+use crate::routes::{is_reserved_slug, record_admin_action, PremiumSlug};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors, MIN_PREMIUM_SLUG_LENGTH};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PremiumSlugAssignment {
+    /// The organization allowed to use the slug, or nobody when absent.
+    pub organization_id: Option<String>,
+}
+
+impl Validate for PremiumSlugAssignment {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if let Some(organization_id) = &self.organization_id {
+            errors.require_not_blank("organizationId", organization_id);
+        }
+    }
+}
+
+pub async fn list_premium_slugs(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Result<Json<Page<PremiumSlug>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let slugs = sqlx::query_as::<_, PremiumSlug>(r#"SELECT * FROM premium_slugs ORDER BY slug"#)
+        .fetch_all(&db)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(slugs)))
+}
+
+/// Provision a premium slug, or assign one to another organization. Links
+/// already using the slug keep it.
+#[tracing::instrument(name = "Assign premium slug", skip(inner, admin, assignment))]
+pub async fn assign_premium_slug(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(slug): Path<String>,
+    Valid(assignment): Valid<PremiumSlugAssignment>,
+) -> Result<Json<PremiumSlug>, Response> {
+    let InnerState { db, .. } = inner;
+
+    let mut errors = ValidationErrors::default();
+    errors.require_slug("slug", &slug, MIN_PREMIUM_SLUG_LENGTH);
+    if is_reserved_slug(&slug) {
+        errors.add("slug", "is reserved");
+    }
+    if !errors.is_empty() {
+        return Err(errors.into_response());
+    }
+    let slug = slug.to_lowercase();
+
+    let mut transaction = db
+        .begin()
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+
+    let before: Option<Option<String>> = sqlx::query_scalar(
+        r#"SELECT organization_id FROM premium_slugs WHERE slug = $1 FOR UPDATE"#,
+    )
+    .bind(&slug)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|err| internal_error(err).into_response())?;
+
+    let premium = sqlx::query_as::<_, PremiumSlug>(
+        r#"INSERT INTO premium_slugs (slug, organization_id, created_by, assigned_at)
+        VALUES ($1, $2, $3, CASE WHEN $2::text IS NULL THEN NULL ELSE CURRENT_TIMESTAMP END)
+        ON CONFLICT (slug) DO UPDATE SET organization_id = excluded.organization_id,
+        assigned_at = excluded.assigned_at
+        RETURNING *"#,
+    )
+    .bind(&slug)
+    .bind(&assignment.organization_id)
+    .bind(&admin.claims.sub)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|err| match err.as_database_error() {
+        Some(err) if err.is_foreign_key_violation() => {
+            (StatusCode::NOT_FOUND, "Organization not found".to_string()).into_response()
+        }
+        _ => internal_error(err).into_response(),
+    })?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "premium_slug.assign",
+        Some(&slug),
+        before.map(|organization_id| json!({ "organizationId": organization_id })),
+        Some(json!({ "organizationId": premium.organization_id })),
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+
+    Ok(Json(premium))
+}
+
+/// Make a premium slug an ordinary one again. Links already using it keep
+/// it.
+#[tracing::instrument(name = "Release premium slug", skip(inner, admin))]
+pub async fn release_premium_slug(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(slug): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let before = sqlx::query_scalar::<_, Option<String>>(
+        r#"DELETE FROM premium_slugs WHERE slug = lower($1) RETURNING organization_id"#,
+    )
+    .bind(&slug)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "premium_slug.release",
+        Some(&slug.to_lowercase()),
+        Some(json!({ "organizationId": before })),
+        None,
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,185 @@
+use crate::badge;
+use crate::db::links;
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Counts served from memory are at most this old.
+const CLICK_COUNT_TTL: Duration = Duration::from_secs(60);
+
+const MAX_BADGE_LABEL_LENGTH: usize = 32;
+
+/// Upper bound on cached links before the cache is emptied.
+const CLICK_COUNT_CACHE_CAPACITY: usize = 10_000;
+
+const PUBLIC_CACHE_CONTROL_HEADER_VALUE: &str =
+    "public, max-age=300, s-maxage=600, stale-while-revalidate=600, stale-if-error=86400";
+
+static CLICK_COUNTS: Lazy<RwLock<HashMap<String, (Instant, i64)>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicClickCount {
+    pub link_id: String,
+    pub clicks: i64,
+}
+
+/// Total clicks of an active link, cached briefly so embedded widgets do not
+/// turn every page view into a database query.
+pub async fn cached_click_count(db: &PgPool, link_id: &str) -> Result<i64, (StatusCode, String)> {
+    if let Some((fetched_at, clicks)) = CLICK_COUNTS
+        .read()
+        .expect("The click count cache lock should never be poisoned")
+        .get(link_id)
+    {
+        if fetched_at.elapsed() < CLICK_COUNT_TTL {
+            return Ok(*clicks);
+        }
+    }
+
+    let clicks = links::click_count(db, link_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let mut cache = CLICK_COUNTS
+        .write()
+        .expect("The click count cache lock should never be poisoned");
+    if cache.len() >= CLICK_COUNT_CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(link_id.to_string(), (Instant::now(), clicks));
+
+    Ok(clicks)
+}
+
+/// Responses any origin may embed or fetch.
+pub fn public_response(content_type: &str, body: impl Into<Body>) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .header("Cache-Control", PUBLIC_CACHE_CONTROL_HEADER_VALUE)
+        .header("Access-Control-Allow-Origin", "*")
+        .body(body.into())
+        .expect("This response should always be constructable")
+}
+
+pub async fn public_link_clicks(
+    State(inner): State<InnerState>,
+    Path(link_id): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let clicks = cached_click_count(&db, &link_id).await?;
+
+    let body =
+        serde_json::to_string(&PublicClickCount { link_id, clicks }).map_err(internal_error)?;
+
+    Ok(public_response("application/json", body))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BadgeQuery {
+    pub label: Option<String>,
+    pub color: Option<String>,
+    pub label_color: Option<String>,
+}
+
+struct Badge {
+    label: String,
+    value: String,
+    label_color: String,
+    color: String,
+}
+
+async fn click_badge(
+    db: &PgPool,
+    link_id: &str,
+    query: BadgeQuery,
+) -> Result<Badge, (StatusCode, String)> {
+    let color = |color: Option<String>, default: &str| match color {
+        Some(color) => badge::parse_color(&color)
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, "Unknown color".to_string())),
+        None => Ok(default.to_string()),
+    };
+
+    let label = query.label.unwrap_or_else(|| "clicks".to_string());
+    if label.is_empty() || label.chars().count() > MAX_BADGE_LABEL_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "Label is too long".to_string()));
+    }
+
+    Ok(Badge {
+        label,
+        value: badge::format_count(cached_click_count(db, link_id).await?),
+        label_color: color(query.label_color, badge::DEFAULT_LABEL_COLOR)?,
+        color: color(query.color, badge::DEFAULT_COLOR)?,
+    })
+}
+
+/// Like `public_response`, with an ETag so caches can revalidate cheaply.
+fn badge_response(headers: &HeaderMap, content_type: &str, body: Vec<u8>) -> Response {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+
+    let matches = headers
+        .get("if-none-match")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+
+    let mut response = if matches {
+        let mut response = public_response(content_type, Body::empty());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        response
+    } else {
+        public_response(content_type, body)
+    };
+    response.headers_mut().insert(
+        "ETag",
+        etag.parse()
+            .expect("A hex ETag should always be a valid header"),
+    );
+    response
+}
+
+pub async fn public_link_clicks_badge(
+    State(inner): State<InnerState>,
+    Path(link_id): Path<String>,
+    Query(query): Query<BadgeQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let badge = click_badge(&db, &link_id, query).await?;
+    let svg = badge::render_svg(&badge.label, &badge.value, &badge.label_color, &badge.color);
+
+    Ok(badge_response(&headers, "image/svg+xml", svg.into_bytes()))
+}
+
+pub async fn public_link_clicks_badge_png(
+    State(inner): State<InnerState>,
+    Path(link_id): Path<String>,
+    Query(query): Query<BadgeQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let badge = click_badge(&db, &link_id, query).await?;
+    let png = badge::render_png(&badge.label, &badge.value, &badge.label_color, &badge.color);
+
+    Ok(badge_response(&headers, "image/png", png))
+}
@@ -0,0 +1,218 @@
+use crate::authentication::Claims;
+use crate::branding;
+use crate::db::links;
+use crate::pdf::{self, Font, PdfDocument, PAGE_HEIGHT, PAGE_WIDTH};
+use crate::policy::Action;
+use crate::qr::QrCode;
+use crate::routes::authorize_link;
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+/// Pixels per module in PNG renderings.
+const PNG_SCALE: usize = 8;
+
+/// Links on one sheet, five pages' worth.
+const MAX_SHEET_LINKS: usize = 30;
+
+const MAX_SHEET_CAPTION_LENGTH: usize = 32;
+
+/// Cards per page, cut apart along the dashed lines between them.
+const SHEET_COLUMNS: usize = 2;
+const SHEET_ROWS: usize = 3;
+
+/// Width of the QR code on a card, in points, quiet zone included.
+const SHEET_QR_SIDE: f64 = 190.0;
+
+const SHEET_CAPTION_SIZE: f64 = 14.0;
+const SHEET_URL_SIZE: f64 = 10.0;
+
+const QR_CACHE_CONTROL_HEADER_VALUE: &str = "public, max-age=86400";
+
+/// Encode the public short URL of an active link, or the payload itself
+/// for links carrying one.
+pub async fn link_qr_code(
+    db: &PgPool,
+    public_base_url: &str,
+    link_id: &str,
+) -> Result<QrCode, (StatusCode, String)> {
+    let payload = links::active_payload(db, link_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let data = match payload {
+        Some(payload) => payload.qr_text(),
+        None => format!("{}/{}", public_base_url, link_id),
+    };
+
+    QrCode::encode(data.as_bytes()).map_err(internal_error)
+}
+
+fn image_response(content_type: &str, body: impl Into<Body>) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .header("Cache-Control", QR_CACHE_CONTROL_HEADER_VALUE)
+        .body(body.into())
+        .expect("This response should always be constructable")
+}
+
+pub async fn link_qr_code_png(
+    State(inner): State<InnerState>,
+    Path(link_id): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let qr = link_qr_code(&db, &settings.public_base_url, &link_id).await?;
+
+    Ok(image_response("image/png", qr.to_png(PNG_SCALE)))
+}
+
+pub async fn link_qr_code_svg(
+    State(inner): State<InnerState>,
+    Path(link_id): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let qr = link_qr_code(&db, &settings.public_base_url, &link_id).await?;
+
+    // Organizations may color the modules and put their logo in the middle.
+    let branding = branding::for_link(&db, &link_id).await;
+    let dark = branding
+        .as_ref()
+        .and_then(|branding| branding.primary_color.as_deref())
+        .unwrap_or("#000");
+    let svg = match branding
+        .as_ref()
+        .and_then(|branding| branding.logo_url.as_deref())
+    {
+        Some(logo_url) => qr.to_svg_with_logo(dark, "#fff", logo_url),
+        None => qr.to_svg(dark, "#fff"),
+    };
+
+    Ok(image_response("image/svg+xml", svg))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QrSheet {
+    pub links: Vec<QrSheetLink>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QrSheetLink {
+    pub id: String,
+    /// Printed above the short URL, such as a table number.
+    pub caption: Option<String>,
+}
+
+impl Validate for QrSheet {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if self.links.is_empty() || self.links.len() > MAX_SHEET_LINKS {
+            errors.add(
+                "links",
+                format!("must hold between 1 and {} links", MAX_SHEET_LINKS),
+            );
+        }
+
+        for link in &self.links {
+            if let Some(caption) = &link.caption {
+                errors.require_max_length("links[].caption", caption, MAX_SHEET_CAPTION_LENGTH);
+            }
+        }
+    }
+}
+
+/// A printable A4 PDF of cards, each with the QR code of one of the
+/// caller's links, its caption and short URL, in the order given.
+#[tracing::instrument(name = "QR code sheet", skip(inner, claims))]
+pub async fn qr_code_sheet(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Valid(sheet): Valid<QrSheet>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let mut cards = Vec::with_capacity(sheet.links.len());
+    for link in &sheet.links {
+        authorize_link(&db, &link.id, &claims, Action::ReadLink).await?;
+
+        let qr = link_qr_code(&db, &settings.public_base_url, &link.id).await?;
+        let short_url = format!("{}/{}", settings.public_base_url, link.id);
+        cards.push((qr, link.caption.as_deref(), short_url));
+    }
+
+    let cell_width = PAGE_WIDTH / SHEET_COLUMNS as f64;
+    let cell_height = PAGE_HEIGHT / SHEET_ROWS as f64;
+
+    let mut document = PdfDocument::default();
+    for page in cards.chunks(SHEET_COLUMNS * SHEET_ROWS) {
+        let mut content = String::from("0.75 G 0.5 w [4 4] 0 d\n");
+        for (index, (qr, caption, short_url)) in page.iter().enumerate() {
+            let left = (index % SHEET_COLUMNS) as f64 * cell_width;
+            let bottom = PAGE_HEIGHT - (index / SHEET_COLUMNS + 1) as f64 * cell_height;
+            let center = left + cell_width / 2.0;
+            let qr_bottom = bottom + cell_height - 20.0 - SHEET_QR_SIDE;
+
+            content.push_str(&format!(
+                "{:.2} {:.2} {:.2} {:.2} re S\n",
+                left, bottom, cell_width, cell_height
+            ));
+            content.push_str(&qr.to_pdf(center - SHEET_QR_SIDE / 2.0, qr_bottom, SHEET_QR_SIDE));
+
+            let mut baseline = qr_bottom - SHEET_CAPTION_SIZE;
+            if let Some(caption) = caption {
+                content.push_str(&centered_text(
+                    center,
+                    baseline,
+                    Font::Bold,
+                    SHEET_CAPTION_SIZE,
+                    caption,
+                ));
+                baseline -= SHEET_CAPTION_SIZE + 4.0;
+            }
+            let display_url = short_url
+                .split_once("://")
+                .map_or(short_url.as_str(), |(_, rest)| rest);
+            content.push_str(&centered_text(
+                center,
+                baseline,
+                Font::Regular,
+                SHEET_URL_SIZE,
+                display_url,
+            ));
+        }
+        document.add_page(content);
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/pdf")
+        .header(
+            "Content-Disposition",
+            r#"attachment; filename="qr-codes.pdf""#,
+        )
+        .body(Body::from(document.finish()))
+        .expect("This response should always be constructable"))
+}
+
+/// Text centered on `center` as near as an average Helvetica glyph width
+/// allows, since the font's metrics are not at hand.
+fn centered_text(center: f64, baseline: f64, font: Font, size: f64, value: &str) -> String {
+    let average_width = match font {
+        Font::Regular => 0.52,
+        Font::Bold => 0.58,
+    };
+    let width = value.chars().count() as f64 * size * average_width;
+
+    pdf::text(center - width / 2.0, baseline, font, size, value)
+}
@@ -0,0 +1,573 @@
+//! Logging in through an organization's SAML identity provider. Owners set
+//! the provider up with its entity id, login URL and signing certificate,
+//! and give it our metadata. The first login creates an account, which then
+//! joins the organization, unless SCIM manages who is in it. When the
+//! organization brought an encryption key, the provider's settings are
+//! sealed with it.
+
+use crate::authentication::{Claims, SessionDevice};
+use crate::casing::Json;
+use crate::client_ip::ClientIp;
+use crate::configuration::Settings;
+use crate::routes::{
+    create_user, finish_external_login, generate_subscription_token, organization_key,
+    require_organization_role, require_verified_email_domain, User,
+};
+use crate::saml::{self, IdentityProvider, SamlIdentity, ServiceProvider};
+use crate::sealing::{SealingError, SealingKey};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::Form;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+const SAML_REQUEST_TTL_MINUTES: i32 = 10;
+
+/// Ties the response to the browser that started the login. The identity
+/// provider posts it from another site, so the cookie has to allow that.
+const SAML_REQUEST_COOKIE: &str = "groupify_saml_request";
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct SamlConnection {
+    pub organization_id: String,
+    pub idp_entity_id: String,
+    pub idp_sso_url: String,
+    /// PEM encoded certificate the identity provider signs with.
+    pub idp_certificate: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    /// The organization key the settings are sealed with, if any.
+    #[serde(skip)]
+    pub encryption_key_id: Option<String>,
+}
+
+impl SamlConnection {
+    fn settings(&mut self) -> [&mut String; 3] {
+        [
+            &mut self.idp_entity_id,
+            &mut self.idp_sso_url,
+            &mut self.idp_certificate,
+        ]
+    }
+
+    /// Seal the provider's settings, each bound to the organization and the
+    /// setting it is.
+    pub fn seal(&mut self, key: &SealingKey) {
+        let organization_id = self.organization_id.clone();
+        for (index, setting) in self.settings().into_iter().enumerate() {
+            *setting = key.seal(&setting_context(&organization_id, index), setting);
+        }
+    }
+
+    pub fn open(&mut self, key: &SealingKey) -> Result<(), SealingError> {
+        let organization_id = self.organization_id.clone();
+        for (index, setting) in self.settings().into_iter().enumerate() {
+            *setting = crate::sealing::open(
+                Some(key),
+                &setting_context(&organization_id, index),
+                setting,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn setting_context(organization_id: &str, index: usize) -> String {
+    format!("saml-connection:{}:{}", organization_id, index)
+}
+
+/// What to enter at the identity provider, when it cannot import the
+/// metadata.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SamlServiceProvider {
+    pub entity_id: String,
+    pub acs_url: String,
+    pub metadata_url: String,
+    pub login_url: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SamlConfiguration {
+    #[serde(flatten)]
+    pub connection: SamlConnection,
+    pub service_provider: SamlServiceProvider,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SamlConnectionChange {
+    pub idp_entity_id: String,
+    pub idp_sso_url: String,
+    pub idp_certificate: String,
+}
+
+impl Validate for SamlConnectionChange {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_not_blank("idpEntityId", &self.idp_entity_id);
+        errors.require_web_url("idpSsoUrl", &self.idp_sso_url);
+        if saml::parse_certificate(&self.idp_certificate).is_none() {
+            errors.add(
+                "idpCertificate",
+                "must be a PEM encoded certificate with an RSA or ECDSA key",
+            );
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SamlPost {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+}
+
+fn service_provider_urls(public_base_url: &str, organization_id: &str) -> SamlServiceProvider {
+    let service_provider = ServiceProvider::new(public_base_url, organization_id);
+    SamlServiceProvider {
+        metadata_url: service_provider.entity_id.clone(),
+        login_url: format!("{}/auth/saml/{}/start", public_base_url, organization_id),
+        entity_id: service_provider.entity_id,
+        acs_url: service_provider.acs_url,
+    }
+}
+
+/// The organization's connection, opened with its key when it was sealed
+/// with one. Gone for good once that key is revoked.
+async fn find_connection(
+    db: &PgPool,
+    settings: &Settings,
+    organization_id: &str,
+) -> Result<SamlConnection, (StatusCode, String)> {
+    let mut connection = sqlx::query_as::<_, SamlConnection>(
+        r#"SELECT * FROM saml_connections WHERE organization_id = $1"#,
+    )
+    .bind(organization_id)
+    .fetch_optional(db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    if let Some(encryption_key_id) = &connection.encryption_key_id {
+        let key = organization_key(db, settings, organization_id)
+            .await
+            .map_err(|err| internal_error(&*err))?
+            .filter(|key| key.id == *encryption_key_id)
+            .ok_or_else(|| {
+                (
+                    StatusCode::GONE,
+                    "The key this SAML connection was encrypted with has been revoked".to_string(),
+                )
+            })?;
+
+        connection.open(&key.key).map_err(internal_error)?;
+    }
+
+    Ok(connection)
+}
+
+fn request_from_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SAML_REQUEST_COOKIE)
+        .map(|(_, value)| value)
+}
+
+pub async fn organization_saml_connection(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<Json<SamlConfiguration>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let connection = find_connection(&db, &settings, &organization_id).await?;
+
+    Ok(Json(SamlConfiguration {
+        connection,
+        service_provider: service_provider_urls(&settings.public_base_url, &organization_id),
+    }))
+}
+
+/// Set up or change the organization's identity provider, sealed with the
+/// organization's key if it has one. Accounts that logged in through the
+/// previous one keep their identity, so changing providers should keep the
+/// name ids.
+#[tracing::instrument(name = "Set SAML connection", skip(inner, claims, change))]
+pub async fn set_saml_connection(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+    Valid(change): Valid<SamlConnectionChange>,
+) -> Result<Json<SamlConfiguration>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "owner").await?;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    // Keeps the key from being rotated or revoked until the connection is in.
+    sqlx::query(r#"SELECT 1 FROM organizations WHERE id = $1 FOR SHARE"#)
+        .bind(&organization_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+
+    let key = organization_key(&mut *transaction, &settings, &organization_id)
+        .await
+        .map_err(|err| internal_error(&*err))?;
+
+    let mut sealed = SamlConnection {
+        organization_id: organization_id.clone(),
+        idp_entity_id: change.idp_entity_id.trim().to_string(),
+        idp_sso_url: change.idp_sso_url,
+        idp_certificate: change.idp_certificate.trim().to_string(),
+        created_at: None,
+        updated_at: None,
+        encryption_key_id: key.as_ref().map(|key| key.id.clone()),
+    };
+    if let Some(key) = &key {
+        sealed.seal(&key.key);
+    }
+
+    let mut connection = sqlx::query_as::<_, SamlConnection>(
+        r#"INSERT INTO saml_connections
+            (organization_id, idp_entity_id, idp_sso_url, idp_certificate, encryption_key_id)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (organization_id) DO UPDATE
+        SET idp_entity_id = excluded.idp_entity_id, idp_sso_url = excluded.idp_sso_url,
+            idp_certificate = excluded.idp_certificate,
+            encryption_key_id = excluded.encryption_key_id, updated_at = CURRENT_TIMESTAMP
+        RETURNING *"#,
+    )
+    .bind(&sealed.organization_id)
+    .bind(&sealed.idp_entity_id)
+    .bind(&sealed.idp_sso_url)
+    .bind(&sealed.idp_certificate)
+    .bind(&sealed.encryption_key_id)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    if let Some(key) = &key {
+        connection.open(&key.key).map_err(internal_error)?;
+    }
+
+    Ok(Json(SamlConfiguration {
+        connection,
+        service_provider: service_provider_urls(&settings.public_base_url, &organization_id),
+    }))
+}
+
+/// Stop logging in through the identity provider. Accounts it created stay,
+/// and log in with a password after resetting it.
+#[tracing::instrument(name = "Delete SAML connection", skip(inner, claims))]
+pub async fn delete_saml_connection(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "owner").await?;
+
+    let deleted = sqlx::query(r#"DELETE FROM saml_connections WHERE organization_id = $1"#)
+        .bind(&organization_id)
+        .execute(&db)
+        .await
+        .map_err(internal_error)?
+        .rows_affected();
+    if deleted == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn saml_metadata(
+    State(inner): State<InnerState>,
+    Path(organization_id): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    find_connection(&db, &settings, &organization_id).await?;
+
+    let metadata = ServiceProvider::new(&settings.public_base_url, &organization_id).metadata();
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/samlmetadata+xml")
+        .body(Body::from(metadata))
+        .expect("This response should always be constructable"))
+}
+
+#[tracing::instrument(name = "Start SAML login", skip(inner))]
+pub async fn start_saml_login(
+    State(inner): State<InnerState>,
+    Path(organization_id): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let connection = find_connection(&db, &settings, &organization_id).await?;
+
+    sqlx::query(
+        r#"DELETE FROM saml_login_requests
+        WHERE created_at <= CURRENT_TIMESTAMP - make_interval(mins => $1)"#,
+    )
+    .bind(SAML_REQUEST_TTL_MINUTES)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?;
+
+    // IDs have to start with a letter or an underscore.
+    let request_id = format!("_{}", generate_subscription_token());
+
+    sqlx::query(r#"INSERT INTO saml_login_requests (id, organization_id) VALUES ($1, $2)"#)
+        .bind(&request_id)
+        .bind(&organization_id)
+        .execute(&db)
+        .await
+        .map_err(internal_error)?;
+
+    let location = ServiceProvider::new(&settings.public_base_url, &organization_id)
+        .authn_request_url(&connection.idp_sso_url, &request_id, &request_id)
+        .map_err(internal_error)?;
+
+    let cookie = format!(
+        "{}={}; Path=/auth/saml; Max-Age={}; HttpOnly; Secure; SameSite=None",
+        SAML_REQUEST_COOKIE,
+        request_id,
+        SAML_REQUEST_TTL_MINUTES * 60
+    );
+
+    Ok(Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header("Location", location)
+        .header("Set-Cookie", cookie)
+        .body(Body::empty())
+        .expect("This response should always be constructable"))
+}
+
+/// The assertion consumer service the identity provider posts its response
+/// to. The response has to answer a login started in the same browser
+/// moments ago, which is then used up.
+#[tracing::instrument(name = "Finish SAML login", skip(inner, client, headers, post))]
+pub async fn saml_assertion_consumer(
+    State(inner): State<InnerState>,
+    Path(organization_id): Path<String>,
+    client: ClientIp,
+    headers: HeaderMap,
+    Form(post): Form<SamlPost>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState {
+        db,
+        email_client,
+        jwt_keys,
+        settings,
+        ..
+    } = inner;
+
+    let connection = find_connection(&db, &settings, &organization_id).await?;
+    let identity_provider = IdentityProvider {
+        entity_id: connection.idp_entity_id,
+        // Checked when it was set.
+        certificate: saml::parse_certificate(&connection.idp_certificate).ok_or_else(|| {
+            tracing::error!("The SAML certificate of {} is unreadable", organization_id);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "The SAML certificate is unreadable".to_string(),
+            )
+        })?,
+    };
+    let service_provider = ServiceProvider::new(&settings.public_base_url, &organization_id);
+
+    let identity =
+        saml::verify_response(&post.saml_response, &service_provider, &identity_provider).map_err(
+            |err| {
+                tracing::warn!("Refused SAML response for {}: {}", organization_id, err);
+                (StatusCode::UNAUTHORIZED, err.to_string())
+            },
+        )?;
+
+    if request_from_cookie(&headers) != Some(identity.in_response_to.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Unknown or expired login request".to_string(),
+        ));
+    }
+    let claimed = sqlx::query(
+        r#"DELETE FROM saml_login_requests
+        WHERE id = $1 AND organization_id = $2
+            AND created_at > CURRENT_TIMESTAMP - make_interval(mins => $3)"#,
+    )
+    .bind(&identity.in_response_to)
+    .bind(&organization_id)
+    .bind(SAML_REQUEST_TTL_MINUTES)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?
+    .rows_affected();
+    if claimed == 0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Unknown or expired login request".to_string(),
+        ));
+    }
+
+    let user = find_or_create_user(&db, &organization_id, &identity).await?;
+
+    let device = SessionDevice::new(&settings, &client, &headers);
+    finish_external_login(&db, &email_client, &jwt_keys, &settings, &user, device).await
+}
+
+/// The user the identity belongs to. Unlike addresses Google or GitHub
+/// verified, an organization's identity provider can claim any address, so
+/// an identity seen for the first time has to be in one of the
+/// organization's verified domains, and only takes over an account the
+/// organization created itself, through SCIM. Otherwise an account is
+/// created, when the address has none yet. It is left unconfirmed, as the
+/// address never was.
+#[tracing::instrument(name = "Find or create SAML user", skip(db))]
+async fn find_or_create_user(
+    db: &PgPool,
+    organization_id: &str,
+    identity: &SamlIdentity,
+) -> Result<User, (StatusCode, String)> {
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let linked = sqlx::query_as::<_, User>(
+        r#"SELECT users.* FROM saml_identities
+        JOIN users ON users.id = saml_identities.user_id
+        WHERE saml_identities.organization_id = $1 AND saml_identities.name_id = $2"#,
+    )
+    .bind(organization_id)
+    .bind(&identity.name_id)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    let user = match linked {
+        Some(user) => {
+            sqlx::query(
+                r#"UPDATE saml_identities SET email = $3, last_login_at = CURRENT_TIMESTAMP
+                WHERE organization_id = $1 AND name_id = $2"#,
+            )
+            .bind(organization_id)
+            .bind(&identity.name_id)
+            .bind(&identity.email)
+            .execute(&mut *transaction)
+            .await
+            .map_err(internal_error)?;
+
+            user
+        }
+        None => {
+            let email = identity.email.clone().ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    "The identity provider did not share an email address".to_string(),
+                )
+            })?;
+            require_verified_email_domain(&mut *transaction, organization_id, &email).await?;
+
+            let existing = sqlx::query_as::<_, User>(r#"SELECT * FROM users WHERE email = $1"#)
+                .bind(&email)
+                .fetch_optional(&mut *transaction)
+                .await
+                .map_err(internal_error)?;
+
+            let user = match existing {
+                Some(user) => {
+                    let provisioned: bool = sqlx::query_scalar(
+                        r#"SELECT EXISTS (
+                            SELECT 1 FROM scim_users
+                            WHERE organization_id = $1 AND user_id = $2 AND created_user
+                        )"#,
+                    )
+                    .bind(organization_id)
+                    .bind(&user.id)
+                    .fetch_one(&mut *transaction)
+                    .await
+                    .map_err(internal_error)?;
+                    if !provisioned {
+                        return Err((
+                            StatusCode::CONFLICT,
+                            "Email is already registered".to_string(),
+                        ));
+                    }
+
+                    user
+                }
+                None => {
+                    // Never used to log in, until its owner resets it.
+                    let user_id = create_user(
+                        &mut transaction,
+                        User {
+                            email: email.clone(),
+                            encrypted_password: generate_subscription_token(),
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+
+                    sqlx::query_as::<_, User>(
+                        r#"UPDATE users SET is_sso_user = true, display_name = $2,
+                            updated_at = CURRENT_TIMESTAMP
+                        WHERE id = $1 returning *"#,
+                    )
+                    .bind(&user_id)
+                    .bind(&identity.name)
+                    .fetch_one(&mut *transaction)
+                    .await
+                    .map_err(internal_error)?
+                }
+            };
+
+            sqlx::query(
+                r#"INSERT INTO saml_identities (organization_id, name_id, user_id, email)
+                VALUES ($1, $2, $3, $4)"#,
+            )
+            .bind(organization_id)
+            .bind(&identity.name_id)
+            .bind(&user.id)
+            .bind(&email)
+            .execute(&mut *transaction)
+            .await
+            .map_err(internal_error)?;
+
+            user
+        }
+    };
+
+    // Where SCIM provisions members, it alone decides who is one.
+    sqlx::query(
+        r#"INSERT INTO organization_members (organization_id, user_id, role)
+        SELECT $1, $2, 'member'
+        WHERE NOT EXISTS (SELECT 1 FROM scim_tokens WHERE organization_id = $1)
+        ON CONFLICT (organization_id, user_id) DO NOTHING"#,
+    )
+    .bind(organization_id)
+    .bind(&user.id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(user)
+}
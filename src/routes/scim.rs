@@ -0,0 +1,1552 @@
+//! SCIM 2.0 provisioning, through which an organization's identity provider
+//! adds, updates and removes its members. The provider authenticates with
+//! a token an owner generates for the organization, and only ever sees the
+//! users and groups it provisioned there.
+//!
+//! Only addresses in the organization's verified domains are provisioned.
+//! Provisioned users are members while active, those who had an account
+//! before once they accept the invitation they are sent. Groups are kept
+//! as the provider pushes them, and members of one named after a role, such
+//! as `admin`, take that role; the others are members.
+
+use crate::authentication::Claims;
+use crate::casing;
+use crate::configuration::Settings;
+use crate::email::EmailClient;
+use crate::routes::{
+    create_user, generate_subscription_token, insert_invitation, last_owner,
+    require_organization_role, require_verified_email_domain, send_invitation_email, Invitation,
+    User, ORGANIZATION_ROLES,
+};
+use crate::utils::internal_error;
+use crate::validation::ValidationErrors;
+use crate::InnerState;
+
+use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose;
+use base64::Engine;
+use chrono::NaiveDateTime;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+const CONFIG_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig";
+
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+const DEFAULT_SCIM_COUNT: i64 = 100;
+const MAX_SCIM_COUNT: i64 = 200;
+
+/// A failure as SCIM reports it, with the `scimType` providers act on.
+#[derive(Debug)]
+pub struct ScimError {
+    status: StatusCode,
+    scim_type: Option<&'static str>,
+    detail: String,
+}
+
+impl ScimError {
+    fn new(status: StatusCode, scim_type: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            scim_type: Some(scim_type),
+            detail: detail.into(),
+        }
+    }
+
+    fn not_found() -> Self {
+        (StatusCode::NOT_FOUND, "Not Found".to_string()).into()
+    }
+}
+
+impl From<(StatusCode, String)> for ScimError {
+    fn from((status, detail): (StatusCode, String)) -> Self {
+        Self {
+            status,
+            scim_type: None,
+            detail,
+        }
+    }
+}
+
+impl IntoResponse for ScimError {
+    fn into_response(self) -> Response {
+        let mut error = json!({
+            "schemas": [ERROR_SCHEMA],
+            "status": self.status.as_u16().to_string(),
+            "detail": self.detail,
+        });
+        if let Some(scim_type) = self.scim_type {
+            error["scimType"] = json!(scim_type);
+        }
+        scim_response(self.status, &error)
+    }
+}
+
+fn scim_response<T: Serialize>(status: StatusCode, body: &T) -> Response {
+    (status, [("Content-Type", SCIM_CONTENT_TYPE)], Json(body)).into_response()
+}
+
+/// SCIM tokens are only stored hashed, like refresh tokens.
+fn hash_scim_token(token: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(digest(&SHA256, token.as_bytes()))
+}
+
+/// The organization whose identity provider is calling, from its bearer
+/// token.
+pub struct ScimClient {
+    pub organization_id: String,
+}
+
+#[axum::async_trait]
+impl FromRequestParts<InnerState> for ScimClient {
+    type Rejection = ScimError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &InnerState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| {
+                ScimError::from((StatusCode::UNAUTHORIZED, "Missing bearer token".to_string()))
+            })?;
+
+        let organization_id: Option<String> =
+            sqlx::query_scalar(r#"SELECT organization_id FROM scim_tokens WHERE token_hash = $1"#)
+                .bind(hash_scim_token(token))
+                .fetch_optional(&state.db)
+                .await
+                .map_err(internal_error)?;
+
+        organization_id
+            .map(|organization_id| Self { organization_id })
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "Invalid SCIM token".to_string()).into())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimToken {
+    /// Shown this once; only a hash of it is kept.
+    pub token: String,
+    pub base_url: String,
+}
+
+/// Generate the token the organization's identity provider provisions
+/// with, replacing the one it had.
+#[tracing::instrument(name = "Generate SCIM token", skip(inner, claims))]
+pub async fn generate_scim_token(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<casing::Json<ScimToken>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let user_id = require_organization_role(&db, &organization_id, &claims, "owner").await?;
+
+    let token = generate_subscription_token();
+    sqlx::query(
+        r#"INSERT INTO scim_tokens (organization_id, token_hash, created_by) VALUES ($1, $2, $3)
+        ON CONFLICT (organization_id) DO UPDATE
+        SET token_hash = excluded.token_hash, created_by = excluded.created_by,
+            created_at = CURRENT_TIMESTAMP"#,
+    )
+    .bind(&organization_id)
+    .bind(hash_scim_token(&token))
+    .bind(&user_id)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(casing::Json(ScimToken {
+        token,
+        base_url: format!("{}/scim/v2", settings.public_base_url),
+    }))
+}
+
+/// Stop the organization's identity provider from provisioning. Members it
+/// provisioned stay.
+#[tracing::instrument(name = "Revoke SCIM token", skip(inner, claims))]
+pub async fn revoke_scim_token(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "owner").await?;
+
+    let revoked = sqlx::query(r#"DELETE FROM scim_tokens WHERE organization_id = $1"#)
+        .bind(&organization_id)
+        .execute(&db)
+        .await
+        .map_err(internal_error)?
+        .rows_affected();
+    if revoked == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn scim_service_provider_config() -> Response {
+    let config = json!({
+        "schemas": [CONFIG_SCHEMA],
+        "patch": { "supported": true },
+        "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+        "filter": { "supported": true, "maxResults": MAX_SCIM_COUNT },
+        "changePassword": { "supported": false },
+        "sort": { "supported": false },
+        "etag": { "supported": false },
+        "authenticationSchemes": [{
+            "type": "oauthbearertoken",
+            "name": "OAuth Bearer Token",
+            "description": "The SCIM token an owner generated for the organization",
+        }],
+    });
+    scim_response(StatusCode::OK, &config)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimListQuery {
+    pub filter: Option<String>,
+    /// 1-based index of the first resource.
+    pub start_index: Option<i64>,
+    pub count: Option<i64>,
+}
+
+impl ScimListQuery {
+    fn offset(&self) -> i64 {
+        self.start_index.unwrap_or(1).max(1) - 1
+    }
+
+    fn limit(&self) -> i64 {
+        self.count
+            .unwrap_or(DEFAULT_SCIM_COUNT)
+            .clamp(0, MAX_SCIM_COUNT)
+    }
+
+    /// The value of an `<attribute> eq "<value>"` filter, the only kind
+    /// providers send to look resources up.
+    fn equals(&self, attributes: &[&str]) -> Result<HashMap<String, String>, ScimError> {
+        let Some(filter) = &self.filter else {
+            return Ok(HashMap::new());
+        };
+
+        let invalid = || {
+            ScimError::new(
+                StatusCode::BAD_REQUEST,
+                "invalidFilter",
+                format!(
+                    "Only {} eq \"...\" filters are supported",
+                    attributes.join(", ")
+                ),
+            )
+        };
+
+        let mut parts = filter.trim().splitn(3, ' ');
+        let (Some(attribute), Some(operator), Some(value)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let attribute = attributes
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(attribute))
+            .ok_or_else(invalid)?;
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .filter(|_| operator.eq_ignore_ascii_case("eq"))
+            .ok_or_else(invalid)?;
+
+        Ok(HashMap::from([(attribute.to_string(), value.to_string())]))
+    }
+}
+
+fn list_response(total: i64, query: &ScimListQuery, resources: Vec<Value>) -> Response {
+    let list = json!({
+        "schemas": [LIST_SCHEMA],
+        "totalResults": total,
+        "startIndex": query.offset() + 1,
+        "itemsPerPage": resources.len(),
+        "Resources": resources,
+    });
+    scim_response(StatusCode::OK, &list)
+}
+
+fn meta(
+    settings: &Settings,
+    resource_type: &str,
+    id: &str,
+    created: Option<NaiveDateTime>,
+    last_modified: Option<NaiveDateTime>,
+) -> Value {
+    json!({
+        "resourceType": resource_type,
+        "created": created.map(|at| at.and_utc().to_rfc3339()),
+        "lastModified": last_modified.map(|at| at.and_utc().to_rfc3339()),
+        "location": format!("{}/scim/v2/{}s/{}", settings.public_base_url, resource_type, id),
+    })
+}
+
+#[derive(Debug, FromRow)]
+struct ProvisionedUser {
+    id: String,
+    email: String,
+    display_name: Option<String>,
+    external_id: Option<String>,
+    active: bool,
+    /// Whether the organization created the account, and so may change it.
+    created_user: bool,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, FromRow)]
+struct GroupMembership {
+    user_id: String,
+    group_id: String,
+    display_name: String,
+}
+
+impl ProvisionedUser {
+    fn to_scim(&self, settings: &Settings, groups: &[&GroupMembership]) -> Value {
+        json!({
+            "schemas": [USER_SCHEMA],
+            "id": self.id,
+            "externalId": self.external_id,
+            "userName": self.email,
+            "displayName": self.display_name,
+            "name": { "formatted": self.display_name },
+            "emails": [{ "value": self.email, "primary": true }],
+            "active": self.active,
+            "groups": groups
+                .iter()
+                .map(|group| json!({ "value": group.group_id, "display": group.display_name }))
+                .collect::<Vec<_>>(),
+            "meta": meta(settings, "User", &self.id, self.created_at, self.updated_at),
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ScimName {
+    pub formatted: Option<String>,
+    #[serde(rename = "givenName")]
+    pub given_name: Option<String>,
+    #[serde(rename = "familyName")]
+    pub family_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    pub user_name: String,
+    pub external_id: Option<String>,
+    pub display_name: Option<String>,
+    pub name: Option<ScimName>,
+    #[serde(default)]
+    pub emails: Vec<ScimEmail>,
+    pub active: Option<bool>,
+}
+
+impl ScimUser {
+    /// The primary email address, or the user name when there is none.
+    fn email(&self) -> Result<String, ScimError> {
+        let email = self
+            .emails
+            .iter()
+            .find(|email| email.primary)
+            .or_else(|| self.emails.first())
+            .map(|email| email.value.as_str())
+            .unwrap_or(&self.user_name)
+            .trim()
+            .to_lowercase();
+
+        let mut errors = ValidationErrors::default();
+        errors.require_email("userName", &email);
+        if !errors.is_empty() {
+            return Err(ScimError::new(
+                StatusCode::BAD_REQUEST,
+                "invalidValue",
+                "userName or the primary email should be an email address",
+            ));
+        }
+
+        Ok(email)
+    }
+
+    fn display_name(&self) -> Option<String> {
+        self.display_name.clone().or_else(|| {
+            let name = self.name.as_ref()?;
+            name.formatted.clone().or_else(|| {
+                let parts: Vec<&str> = [&name.given_name, &name.family_name]
+                    .into_iter()
+                    .flatten()
+                    .map(String::as_str)
+                    .collect();
+                (!parts.is_empty()).then(|| parts.join(" "))
+            })
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+fn invalid_patch(detail: impl Into<String>) -> ScimError {
+    ScimError::new(StatusCode::BAD_REQUEST, "invalidValue", detail)
+}
+
+/// Lock the organization, so changes to its members happen one at a time.
+async fn lock_organization(
+    transaction: &mut Transaction<'_, Postgres>,
+    organization_id: &str,
+) -> Result<(), ScimError> {
+    sqlx::query(r#"SELECT id FROM organizations WHERE id = $1 FOR UPDATE"#)
+        .bind(organization_id)
+        .execute(&mut **transaction)
+        .await
+        .map_err(internal_error)?;
+    Ok(())
+}
+
+/// Bring a provisioned user's membership in line with whether they are
+/// active and their groups: the highest role among groups named after one,
+/// and member otherwise. Users no longer provisioned leave. The last owner
+/// stays one. Accounts the organization did not create only join by
+/// accepting an invitation, see `invite_existing_user`, and are kept in
+/// line from then on.
+async fn sync_member(
+    transaction: &mut Transaction<'_, Postgres>,
+    organization_id: &str,
+    user_id: &str,
+) -> Result<(), ScimError> {
+    let provisioned: Option<(bool, bool)> = sqlx::query_as(
+        r#"SELECT active, created_user FROM scim_users
+        WHERE organization_id = $1 AND user_id = $2"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+    let active = provisioned.map(|(active, _)| active);
+    let created_user = provisioned.is_some_and(|(_, created_user)| created_user);
+
+    let group_names: Vec<String> = sqlx::query_scalar(
+        r#"SELECT lower(scim_groups.display_name) FROM scim_group_members
+        JOIN scim_groups ON scim_groups.id = scim_group_members.group_id
+        WHERE scim_groups.organization_id = $1 AND scim_group_members.user_id = $2"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+
+    let wanted = active.filter(|active| *active).map(|_| {
+        ORGANIZATION_ROLES
+            .iter()
+            .rev()
+            .copied()
+            .find(|role| group_names.iter().any(|name| name == role))
+            .unwrap_or("member")
+    });
+
+    let current: Option<(String, i64)> = sqlx::query_as(
+        r#"SELECT role, (SELECT count(*) FROM organization_members
+            WHERE organization_id = $1 AND role = 'owner')
+        FROM organization_members WHERE organization_id = $1 AND user_id = $2"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+
+    if let Some((role, owners)) = &current {
+        if role == "owner" && wanted != Some("owner") && *owners <= 1 {
+            return Err(last_owner().into());
+        }
+    }
+
+    match wanted {
+        Some(_) if current.is_none() && !created_user => {}
+        Some(role) => {
+            sqlx::query(
+                r#"INSERT INTO organization_members (organization_id, user_id, role)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (organization_id, user_id) DO UPDATE SET role = excluded.role"#,
+            )
+            .bind(organization_id)
+            .bind(user_id)
+            .bind(role)
+            .execute(&mut **transaction)
+            .await
+            .map_err(internal_error)?;
+        }
+        None => {
+            sqlx::query(
+                r#"DELETE FROM organization_members WHERE organization_id = $1 AND user_id = $2"#,
+            )
+            .bind(organization_id)
+            .bind(user_id)
+            .execute(&mut **transaction)
+            .await
+            .map_err(internal_error)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Invite an active provisioned user whose account the organization did
+/// not create, unless they are a member or have a pending invitation, for
+/// them to agree to join. The invitation is returned to be sent once the
+/// transaction commits.
+async fn invite_existing_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    organization_id: &str,
+    user_id: &str,
+) -> Result<Option<(Invitation, String)>, ScimError> {
+    let email: Option<String> = sqlx::query_scalar(
+        r#"SELECT users.email FROM scim_users JOIN users ON users.id = scim_users.user_id
+        WHERE scim_users.organization_id = $1 AND scim_users.user_id = $2
+            AND scim_users.active AND NOT scim_users.created_user
+            AND NOT EXISTS (
+                SELECT 1 FROM organization_members
+                WHERE organization_id = $1 AND user_id = $2
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM organization_invitations
+                WHERE organization_id = $1 AND email = users.email
+                    AND accepted_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            )"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+
+    match email {
+        Some(email) => {
+            let invitation =
+                insert_invitation(transaction, organization_id, &email, "member", None)
+                    .await
+                    .map_err(internal_error)?;
+            Ok(Some(invitation))
+        }
+        None => Ok(None),
+    }
+}
+
+async fn fetch_provisioned_users<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    organization_id: &str,
+    user_id: Option<&str>,
+    filters: &HashMap<String, String>,
+    query: Option<&ScimListQuery>,
+) -> Result<Vec<ProvisionedUser>, ScimError> {
+    sqlx::query_as::<_, ProvisionedUser>(
+        r#"SELECT users.id, users.email, users.display_name, scim_users.external_id,
+            scim_users.active, scim_users.created_user, scim_users.created_at,
+            scim_users.updated_at
+        FROM scim_users JOIN users ON users.id = scim_users.user_id
+        WHERE scim_users.organization_id = $1
+        AND ($2::text IS NULL OR users.id = $2)
+        AND ($3::text IS NULL OR users.email = lower($3))
+        AND ($4::text IS NULL OR scim_users.external_id = $4)
+        ORDER BY scim_users.created_at, users.id
+        OFFSET $5 LIMIT $6"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .bind(filters.get("userName"))
+    .bind(filters.get("externalId"))
+    .bind(query.map(ScimListQuery::offset).unwrap_or(0))
+    .bind(query.map(ScimListQuery::limit).unwrap_or(1))
+    .fetch_all(executor)
+    .await
+    .map_err(|err| internal_error(err).into())
+}
+
+async fn user_groups(
+    db: &PgPool,
+    organization_id: &str,
+    user_ids: &[String],
+) -> Result<Vec<GroupMembership>, ScimError> {
+    sqlx::query_as::<_, GroupMembership>(
+        r#"SELECT scim_group_members.user_id, scim_groups.id AS group_id, scim_groups.display_name
+        FROM scim_group_members JOIN scim_groups ON scim_groups.id = scim_group_members.group_id
+        WHERE scim_groups.organization_id = $1 AND scim_group_members.user_id = ANY($2)
+        ORDER BY scim_groups.display_name"#,
+    )
+    .bind(organization_id)
+    .bind(user_ids)
+    .fetch_all(db)
+    .await
+    .map_err(|err| internal_error(err).into())
+}
+
+async fn scim_user_response(
+    db: &PgPool,
+    settings: &Settings,
+    organization_id: &str,
+    user_id: &str,
+    status: StatusCode,
+) -> Result<Response, ScimError> {
+    let user = fetch_provisioned_users(db, organization_id, Some(user_id), &HashMap::new(), None)
+        .await?
+        .pop()
+        .ok_or_else(ScimError::not_found)?;
+    let groups = user_groups(db, organization_id, std::slice::from_ref(&user.id)).await?;
+
+    Ok(scim_response(
+        status,
+        &user.to_scim(settings, &groups.iter().collect::<Vec<_>>()),
+    ))
+}
+
+pub async fn list_scim_users(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Query(query): Query<ScimListQuery>,
+) -> Result<Response, ScimError> {
+    let InnerState { db, settings, .. } = inner;
+
+    let filters = query.equals(&["userName", "externalId"])?;
+
+    let total: i64 = sqlx::query_scalar(
+        r#"SELECT count(*) FROM scim_users JOIN users ON users.id = scim_users.user_id
+        WHERE scim_users.organization_id = $1
+        AND ($2::text IS NULL OR users.email = lower($2))
+        AND ($3::text IS NULL OR scim_users.external_id = $3)"#,
+    )
+    .bind(&client.organization_id)
+    .bind(filters.get("userName"))
+    .bind(filters.get("externalId"))
+    .fetch_one(&db)
+    .await
+    .map_err(internal_error)?;
+
+    let users =
+        fetch_provisioned_users(&db, &client.organization_id, None, &filters, Some(&query)).await?;
+    let user_ids: Vec<String> = users.iter().map(|user| user.id.clone()).collect();
+    let groups = user_groups(&db, &client.organization_id, &user_ids).await?;
+
+    let resources = users
+        .iter()
+        .map(|user| {
+            let groups: Vec<&GroupMembership> = groups
+                .iter()
+                .filter(|group| group.user_id == user.id)
+                .collect();
+            user.to_scim(&settings, &groups)
+        })
+        .collect();
+
+    Ok(list_response(total, &query, resources))
+}
+
+pub async fn get_scim_user(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Path(user_id): Path<String>,
+) -> Result<Response, ScimError> {
+    let InnerState { db, settings, .. } = inner;
+
+    scim_user_response(
+        &db,
+        &settings,
+        &client.organization_id,
+        &user_id,
+        StatusCode::OK,
+    )
+    .await
+}
+
+/// Provision a user, which has to be in one of the organization's verified
+/// domains. Addresses without an account get one, unconfirmed, which signs
+/// in through the identity provider or after resetting the password it was
+/// created with. Existing accounts are invited rather than made members.
+#[tracing::instrument(name = "Provision SCIM user", skip(inner, client, user))]
+pub async fn create_scim_user(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Json(user): Json<ScimUser>,
+) -> Result<Response, ScimError> {
+    let InnerState {
+        db,
+        email_client,
+        settings,
+        ..
+    } = inner;
+
+    let email = user.email()?;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+    lock_organization(&mut transaction, &client.organization_id).await?;
+    require_verified_email_domain(&mut *transaction, &client.organization_id, &email).await?;
+
+    let existing: Option<String> = sqlx::query_scalar(r#"SELECT id FROM users WHERE email = $1"#)
+        .bind(&email)
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+
+    let created_user = existing.is_none();
+    let user_id = match existing {
+        Some(user_id) => user_id,
+        None => {
+            let user_id = create_user(
+                &mut transaction,
+                User {
+                    email: email.clone(),
+                    encrypted_password: generate_subscription_token(),
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+            sqlx::query(
+                r#"UPDATE users SET is_sso_user = true, display_name = $2,
+                    updated_at = CURRENT_TIMESTAMP
+                WHERE id = $1"#,
+            )
+            .bind(&user_id)
+            .bind(user.display_name())
+            .execute(&mut *transaction)
+            .await
+            .map_err(internal_error)?;
+
+            user_id
+        }
+    };
+
+    sqlx::query(
+        r#"INSERT INTO scim_users (organization_id, user_id, external_id, active, created_user)
+        VALUES ($1, $2, $3, $4, $5)"#,
+    )
+    .bind(&client.organization_id)
+    .bind(&user_id)
+    .bind(&user.external_id)
+    .bind(user.active.unwrap_or(true))
+    .bind(created_user)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|err| match err.as_database_error() {
+        Some(err) if err.is_unique_violation() => ScimError::new(
+            StatusCode::CONFLICT,
+            "uniqueness",
+            "User is already provisioned",
+        ),
+        _ => internal_error(err).into(),
+    })?;
+
+    sync_member(&mut transaction, &client.organization_id, &user_id).await?;
+    let invitation =
+        invite_existing_user(&mut transaction, &client.organization_id, &user_id).await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    if let Some((invitation, token)) = invitation {
+        send_invitation_email(email_client, &settings, &invitation, &token);
+    }
+
+    scim_user_response(
+        &db,
+        &settings,
+        &client.organization_id,
+        &user_id,
+        StatusCode::CREATED,
+    )
+    .await
+}
+
+/// What a provider may change of a provisioned user. The user name is the
+/// account's email address, which only its owner changes.
+#[derive(Debug)]
+struct UserChange {
+    active: bool,
+    display_name: Option<String>,
+    external_id: Option<String>,
+}
+
+/// Apply a provider's change to a provisioned user. The display name is
+/// only changed on accounts the organization created, as it is shown
+/// wherever the account is.
+async fn apply_user_change(
+    db: &PgPool,
+    email_client: EmailClient,
+    settings: &Settings,
+    organization_id: &str,
+    user_id: &str,
+    change: impl FnOnce(&ProvisionedUser) -> Result<UserChange, ScimError>,
+) -> Result<(), ScimError> {
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+    lock_organization(&mut transaction, organization_id).await?;
+
+    let user = fetch_provisioned_users(
+        &mut *transaction,
+        organization_id,
+        Some(user_id),
+        &HashMap::new(),
+        None,
+    )
+    .await?
+    .pop()
+    .ok_or_else(ScimError::not_found)?;
+    let change = change(&user)?;
+
+    sqlx::query(
+        r#"UPDATE scim_users SET active = $3, external_id = $4, updated_at = CURRENT_TIMESTAMP
+        WHERE organization_id = $1 AND user_id = $2"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .bind(change.active)
+    .bind(&change.external_id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    if user.created_user && change.display_name != user.display_name {
+        sqlx::query(
+            r#"UPDATE users SET display_name = $2, updated_at = CURRENT_TIMESTAMP WHERE id = $1"#,
+        )
+        .bind(user_id)
+        .bind(&change.display_name)
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?;
+    }
+
+    sync_member(&mut transaction, organization_id, user_id).await?;
+    let invitation = invite_existing_user(&mut transaction, organization_id, user_id).await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    if let Some((invitation, token)) = invitation {
+        send_invitation_email(email_client, settings, &invitation, &token);
+    }
+
+    Ok(())
+}
+
+/// Replace a provisioned user. Deactivating them removes them from the
+/// organization until they are activated again.
+#[tracing::instrument(name = "Replace SCIM user", skip(inner, client, user))]
+pub async fn replace_scim_user(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Path(user_id): Path<String>,
+    Json(user): Json<ScimUser>,
+) -> Result<Response, ScimError> {
+    let InnerState {
+        db,
+        email_client,
+        settings,
+        ..
+    } = inner;
+
+    let email = user.email()?;
+    apply_user_change(
+        &db,
+        email_client,
+        &settings,
+        &client.organization_id,
+        &user_id,
+        |current| {
+            if current.email != email {
+                return Err(ScimError::new(
+                    StatusCode::BAD_REQUEST,
+                    "mutability",
+                    "userName cannot be changed",
+                ));
+            }
+            Ok(UserChange {
+                active: user.active.unwrap_or(true),
+                display_name: user.display_name(),
+                external_id: user.external_id.clone(),
+            })
+        },
+    )
+    .await?;
+
+    scim_user_response(
+        &db,
+        &settings,
+        &client.organization_id,
+        &user_id,
+        StatusCode::OK,
+    )
+    .await
+}
+
+/// Apply `active`, `displayName` and `externalId` replacements, as
+/// providers send them to deactivate users.
+#[tracing::instrument(name = "Patch SCIM user", skip(inner, client, patch))]
+pub async fn patch_scim_user(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Path(user_id): Path<String>,
+    Json(patch): Json<PatchRequest>,
+) -> Result<Response, ScimError> {
+    let InnerState {
+        db,
+        email_client,
+        settings,
+        ..
+    } = inner;
+
+    apply_user_change(
+        &db,
+        email_client,
+        &settings,
+        &client.organization_id,
+        &user_id,
+        |current| {
+            let mut change = UserChange {
+                active: current.active,
+                display_name: current.display_name.clone(),
+                external_id: current.external_id.clone(),
+            };
+
+            for operation in &patch.operations {
+                let op = operation.op.to_ascii_lowercase();
+                if op != "replace" && op != "add" {
+                    return Err(invalid_patch(format!(
+                        "Users only take replace operations, not {}",
+                        operation.op
+                    )));
+                }
+
+                // Without a path, the value holds the attributes to replace.
+                let replaced: Vec<(String, Value)> = match (&operation.path, &operation.value) {
+                    (Some(path), Some(value)) => vec![(path.clone(), value.clone())],
+                    (None, Some(Value::Object(values))) => values
+                        .iter()
+                        .map(|(path, value)| (path.clone(), value.clone()))
+                        .collect(),
+                    _ => return Err(invalid_patch("Operations need a value")),
+                };
+
+                for (path, value) in replaced {
+                    match path.as_str() {
+                        "active" => {
+                            // Some providers send booleans as strings.
+                            change.active = match &value {
+                                Value::Bool(active) => *active,
+                                Value::String(active) => active.eq_ignore_ascii_case("true"),
+                                _ => return Err(invalid_patch("active should be a boolean")),
+                            }
+                        }
+                        "displayName" => change.display_name = value.as_str().map(str::to_string),
+                        "externalId" => change.external_id = value.as_str().map(str::to_string),
+                        _ => {
+                            return Err(ScimError::new(
+                                StatusCode::BAD_REQUEST,
+                                "invalidPath",
+                                format!("{} cannot be patched", path),
+                            ))
+                        }
+                    }
+                }
+            }
+
+            Ok(change)
+        },
+    )
+    .await?;
+
+    scim_user_response(
+        &db,
+        &settings,
+        &client.organization_id,
+        &user_id,
+        StatusCode::OK,
+    )
+    .await
+}
+
+/// Deprovision a user, removing them from the organization and its groups.
+/// Their account stays.
+#[tracing::instrument(name = "Deprovision SCIM user", skip(inner, client))]
+pub async fn delete_scim_user(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Path(user_id): Path<String>,
+) -> Result<StatusCode, ScimError> {
+    let InnerState { db, .. } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+    lock_organization(&mut transaction, &client.organization_id).await?;
+
+    let deleted =
+        sqlx::query(r#"DELETE FROM scim_users WHERE organization_id = $1 AND user_id = $2"#)
+            .bind(&client.organization_id)
+            .bind(&user_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(internal_error)?
+            .rows_affected();
+    if deleted == 0 {
+        return Err(ScimError::not_found());
+    }
+
+    sqlx::query(
+        r#"DELETE FROM scim_group_members WHERE user_id = $2
+        AND group_id IN (SELECT id FROM scim_groups WHERE organization_id = $1)"#,
+    )
+    .bind(&client.organization_id)
+    .bind(&user_id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    sync_member(&mut transaction, &client.organization_id, &user_id).await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, FromRow)]
+struct ProvisionedGroup {
+    id: String,
+    display_name: String,
+    external_id: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, FromRow)]
+struct GroupMember {
+    group_id: String,
+    user_id: String,
+    email: String,
+}
+
+impl ProvisionedGroup {
+    fn to_scim(&self, settings: &Settings, members: &[&GroupMember]) -> Value {
+        json!({
+            "schemas": [GROUP_SCHEMA],
+            "id": self.id,
+            "externalId": self.external_id,
+            "displayName": self.display_name,
+            "members": members
+                .iter()
+                .map(|member| json!({ "value": member.user_id, "display": member.email }))
+                .collect::<Vec<_>>(),
+            "meta": meta(settings, "Group", &self.id, self.created_at, self.updated_at),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScimMember {
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    pub display_name: String,
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub members: Vec<ScimMember>,
+}
+
+async fn fetch_groups<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    organization_id: &str,
+    group_id: Option<&str>,
+    filters: &HashMap<String, String>,
+    query: Option<&ScimListQuery>,
+) -> Result<Vec<ProvisionedGroup>, ScimError> {
+    sqlx::query_as::<_, ProvisionedGroup>(
+        r#"SELECT id, display_name, external_id, created_at, updated_at FROM scim_groups
+        WHERE organization_id = $1
+        AND ($2::text IS NULL OR id = $2)
+        AND ($3::text IS NULL OR lower(display_name) = lower($3))
+        AND ($4::text IS NULL OR external_id = $4)
+        ORDER BY created_at, id
+        OFFSET $5 LIMIT $6"#,
+    )
+    .bind(organization_id)
+    .bind(group_id)
+    .bind(filters.get("displayName"))
+    .bind(filters.get("externalId"))
+    .bind(query.map(ScimListQuery::offset).unwrap_or(0))
+    .bind(query.map(ScimListQuery::limit).unwrap_or(1))
+    .fetch_all(executor)
+    .await
+    .map_err(|err| internal_error(err).into())
+}
+
+async fn group_members<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    group_ids: &[String],
+) -> Result<Vec<GroupMember>, ScimError> {
+    sqlx::query_as::<_, GroupMember>(
+        r#"SELECT scim_group_members.group_id, users.id AS user_id, users.email
+        FROM scim_group_members JOIN users ON users.id = scim_group_members.user_id
+        WHERE scim_group_members.group_id = ANY($1)
+        ORDER BY users.email"#,
+    )
+    .bind(group_ids)
+    .fetch_all(executor)
+    .await
+    .map_err(|err| internal_error(err).into())
+}
+
+async fn scim_group_response(
+    db: &PgPool,
+    settings: &Settings,
+    organization_id: &str,
+    group_id: &str,
+    status: StatusCode,
+) -> Result<Response, ScimError> {
+    let group = fetch_groups(db, organization_id, Some(group_id), &HashMap::new(), None)
+        .await?
+        .pop()
+        .ok_or_else(ScimError::not_found)?;
+    let members = group_members(db, std::slice::from_ref(&group.id)).await?;
+
+    Ok(scim_response(
+        status,
+        &group.to_scim(settings, &members.iter().collect::<Vec<_>>()),
+    ))
+}
+
+pub async fn list_scim_groups(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Query(query): Query<ScimListQuery>,
+) -> Result<Response, ScimError> {
+    let InnerState { db, settings, .. } = inner;
+
+    let filters = query.equals(&["displayName", "externalId"])?;
+
+    let total: i64 = sqlx::query_scalar(
+        r#"SELECT count(*) FROM scim_groups WHERE organization_id = $1
+        AND ($2::text IS NULL OR lower(display_name) = lower($2))
+        AND ($3::text IS NULL OR external_id = $3)"#,
+    )
+    .bind(&client.organization_id)
+    .bind(filters.get("displayName"))
+    .bind(filters.get("externalId"))
+    .fetch_one(&db)
+    .await
+    .map_err(internal_error)?;
+
+    let groups = fetch_groups(&db, &client.organization_id, None, &filters, Some(&query)).await?;
+    let group_ids: Vec<String> = groups.iter().map(|group| group.id.clone()).collect();
+    let members = group_members(&db, &group_ids).await?;
+
+    let resources = groups
+        .iter()
+        .map(|group| {
+            let members: Vec<&GroupMember> = members
+                .iter()
+                .filter(|member| member.group_id == group.id)
+                .collect();
+            group.to_scim(&settings, &members)
+        })
+        .collect();
+
+    Ok(list_response(total, &query, resources))
+}
+
+pub async fn get_scim_group(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Path(group_id): Path<String>,
+) -> Result<Response, ScimError> {
+    let InnerState { db, settings, .. } = inner;
+
+    scim_group_response(
+        &db,
+        &settings,
+        &client.organization_id,
+        &group_id,
+        StatusCode::OK,
+    )
+    .await
+}
+
+/// Set who is in a group to `user_ids`, then bring the membership of
+/// everyone who joined or left in line.
+async fn set_group_members(
+    transaction: &mut Transaction<'_, Postgres>,
+    organization_id: &str,
+    group_id: &str,
+    user_ids: &[String],
+) -> Result<(), ScimError> {
+    let provisioned: i64 = sqlx::query_scalar(
+        r#"SELECT count(*) FROM scim_users WHERE organization_id = $1 AND user_id = ANY($2)"#,
+    )
+    .bind(organization_id)
+    .bind(user_ids)
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+    let mut distinct = user_ids.to_vec();
+    distinct.sort();
+    distinct.dedup();
+    if provisioned != distinct.len() as i64 {
+        return Err(ScimError::new(
+            StatusCode::BAD_REQUEST,
+            "invalidValue",
+            "Members should be users provisioned to the organization",
+        ));
+    }
+
+    let before: Vec<String> = sqlx::query_scalar(
+        r#"DELETE FROM scim_group_members WHERE group_id = $1 RETURNING user_id"#,
+    )
+    .bind(group_id)
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+
+    sqlx::query(
+        r#"INSERT INTO scim_group_members (group_id, user_id)
+        SELECT $1, unnest($2::text[])"#,
+    )
+    .bind(group_id)
+    .bind(&distinct)
+    .execute(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+
+    // Renaming a group changes the role of everyone in it too.
+    let mut affected = before;
+    affected.extend(distinct);
+    affected.sort();
+    affected.dedup();
+    for user_id in affected {
+        sync_member(transaction, organization_id, &user_id).await?;
+    }
+
+    Ok(())
+}
+
+fn group_name_taken(err: sqlx::Error) -> ScimError {
+    match err.as_database_error() {
+        Some(db_err) if db_err.is_unique_violation() => ScimError::new(
+            StatusCode::CONFLICT,
+            "uniqueness",
+            "A group with this name exists",
+        ),
+        _ => internal_error(err).into(),
+    }
+}
+
+#[tracing::instrument(name = "Provision SCIM group", skip(inner, client, group))]
+pub async fn create_scim_group(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Json(group): Json<ScimGroup>,
+) -> Result<Response, ScimError> {
+    let InnerState { db, settings, .. } = inner;
+
+    let group_id = Uuid::new_v4().to_string();
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+    lock_organization(&mut transaction, &client.organization_id).await?;
+
+    sqlx::query(
+        r#"INSERT INTO scim_groups (id, organization_id, display_name, external_id)
+        VALUES ($1, $2, $3, $4)"#,
+    )
+    .bind(&group_id)
+    .bind(&client.organization_id)
+    .bind(&group.display_name)
+    .bind(&group.external_id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(group_name_taken)?;
+
+    let user_ids: Vec<String> = group
+        .members
+        .into_iter()
+        .map(|member| member.value)
+        .collect();
+    set_group_members(
+        &mut transaction,
+        &client.organization_id,
+        &group_id,
+        &user_ids,
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    scim_group_response(
+        &db,
+        &settings,
+        &client.organization_id,
+        &group_id,
+        StatusCode::CREATED,
+    )
+    .await
+}
+
+/// Rename a group and set its members, from a replacement or a patch.
+async fn apply_group_change(
+    db: &PgPool,
+    organization_id: &str,
+    group_id: &str,
+    change: impl FnOnce(&mut ProvisionedGroup, &mut Vec<String>) -> Result<(), ScimError>,
+) -> Result<(), ScimError> {
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+    lock_organization(&mut transaction, organization_id).await?;
+
+    let mut group = fetch_groups(
+        &mut *transaction,
+        organization_id,
+        Some(group_id),
+        &HashMap::new(),
+        None,
+    )
+    .await?
+    .pop()
+    .ok_or_else(ScimError::not_found)?;
+    let mut user_ids: Vec<String> =
+        group_members(&mut *transaction, std::slice::from_ref(&group.id))
+            .await?
+            .into_iter()
+            .map(|member| member.user_id)
+            .collect();
+
+    change(&mut group, &mut user_ids)?;
+
+    sqlx::query(
+        r#"UPDATE scim_groups SET display_name = $2, external_id = $3,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1"#,
+    )
+    .bind(group_id)
+    .bind(&group.display_name)
+    .bind(&group.external_id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(group_name_taken)?;
+
+    set_group_members(&mut transaction, organization_id, group_id, &user_ids).await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Replace SCIM group", skip(inner, client, replacement))]
+pub async fn replace_scim_group(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Path(group_id): Path<String>,
+    Json(replacement): Json<ScimGroup>,
+) -> Result<Response, ScimError> {
+    let InnerState { db, settings, .. } = inner;
+
+    apply_group_change(
+        &db,
+        &client.organization_id,
+        &group_id,
+        |group, user_ids| {
+            group.display_name = replacement.display_name;
+            group.external_id = replacement.external_id;
+            *user_ids = replacement
+                .members
+                .into_iter()
+                .map(|member| member.value)
+                .collect();
+            Ok(())
+        },
+    )
+    .await?;
+
+    scim_group_response(
+        &db,
+        &settings,
+        &client.organization_id,
+        &group_id,
+        StatusCode::OK,
+    )
+    .await
+}
+
+/// The user ids of a `members` patch value, a list of `{"value": ...}`.
+fn patched_members(value: Option<&Value>) -> Result<Vec<String>, ScimError> {
+    let members: Vec<ScimMember> = match value {
+        Some(value) => serde_json::from_value(value.clone())
+            .map_err(|_| invalid_patch("members should be a list of { \"value\": id }"))?,
+        None => Vec::new(),
+    };
+    Ok(members.into_iter().map(|member| member.value).collect())
+}
+
+/// Add, remove or replace members, as providers push them, and rename the
+/// group.
+#[tracing::instrument(name = "Patch SCIM group", skip(inner, client, patch))]
+pub async fn patch_scim_group(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Path(group_id): Path<String>,
+    Json(patch): Json<PatchRequest>,
+) -> Result<Response, ScimError> {
+    let InnerState { db, settings, .. } = inner;
+
+    apply_group_change(
+        &db,
+        &client.organization_id,
+        &group_id,
+        |group, user_ids| {
+            for operation in &patch.operations {
+                let op = operation.op.to_ascii_lowercase();
+                let path = operation.path.as_deref().unwrap_or_default();
+
+                // `members[value eq "id"]` names a single member to remove.
+                let filtered_member = path
+                    .strip_prefix("members[value eq \"")
+                    .and_then(|rest| rest.strip_suffix("\"]"));
+
+                match (op.as_str(), path, filtered_member) {
+                    ("add", "members", _) => {
+                        user_ids.extend(patched_members(operation.value.as_ref())?);
+                    }
+                    ("remove", "members", _) if operation.value.is_none() => user_ids.clear(),
+                    ("remove", "members", _) => {
+                        let removed = patched_members(operation.value.as_ref())?;
+                        user_ids.retain(|user_id| !removed.contains(user_id));
+                    }
+                    ("remove", _, Some(member)) => user_ids.retain(|user_id| user_id != member),
+                    ("replace", "members", _) => {
+                        *user_ids = patched_members(operation.value.as_ref())?;
+                    }
+                    ("replace", "displayName", _) => {
+                        group.display_name = operation
+                            .value
+                            .as_ref()
+                            .and_then(Value::as_str)
+                            .ok_or_else(|| invalid_patch("displayName should be a string"))?
+                            .to_string();
+                    }
+                    ("replace", "externalId", _) => {
+                        group.external_id = operation
+                            .value
+                            .as_ref()
+                            .and_then(Value::as_str)
+                            .map(str::to_string);
+                    }
+                    ("replace", "", _) => {
+                        let Some(Value::Object(values)) = &operation.value else {
+                            return Err(invalid_patch("Operations without a path need an object"));
+                        };
+                        if let Some(display_name) =
+                            values.get("displayName").and_then(Value::as_str)
+                        {
+                            group.display_name = display_name.to_string();
+                        }
+                        if let Some(external_id) = values.get("externalId") {
+                            group.external_id = external_id.as_str().map(str::to_string);
+                        }
+                    }
+                    _ => {
+                        return Err(ScimError::new(
+                            StatusCode::BAD_REQUEST,
+                            "invalidPath",
+                            format!("Cannot {} {}", operation.op, path),
+                        ))
+                    }
+                }
+            }
+            Ok(())
+        },
+    )
+    .await?;
+
+    scim_group_response(
+        &db,
+        &settings,
+        &client.organization_id,
+        &group_id,
+        StatusCode::OK,
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Delete SCIM group", skip(inner, client))]
+pub async fn delete_scim_group(
+    State(inner): State<InnerState>,
+    client: ScimClient,
+    Path(group_id): Path<String>,
+) -> Result<StatusCode, ScimError> {
+    let InnerState { db, .. } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+    lock_organization(&mut transaction, &client.organization_id).await?;
+
+    let members: Vec<String> =
+        sqlx::query_scalar(r#"SELECT user_id FROM scim_group_members WHERE group_id = $1"#)
+            .bind(&group_id)
+            .fetch_all(&mut *transaction)
+            .await
+            .map_err(internal_error)?;
+
+    let deleted = sqlx::query(r#"DELETE FROM scim_groups WHERE id = $1 AND organization_id = $2"#)
+        .bind(&group_id)
+        .bind(&client.organization_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(internal_error)?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(ScimError::not_found());
+    }
+
+    for user_id in members {
+        sync_member(&mut transaction, &client.organization_id, &user_id).await?;
+    }
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
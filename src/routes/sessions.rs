@@ -0,0 +1,228 @@
+//! The sessions a user is logged in with, which they may revoke one by one,
+//! and the email telling them of a login from a new device or country. The
+//! email carries a short link to a page revoking that session in one click,
+//! without signing in, as whoever logged in may have changed the password.
+//! The revoke token rides in the fragment of the short link, which browsers
+//! keep across the redirect and never send, so it is stored nowhere.
+
+use crate::authentication::{
+    active_sessions, issue_revoke_token, revoke_session, revoke_session_by_token, ActiveSession,
+    Claims, SessionDevice,
+};
+use crate::casing::Json;
+use crate::configuration::Settings;
+use crate::db::links::{self, NewLink, RedirectBehavior};
+use crate::email::EmailClient;
+use crate::i18n;
+use crate::pagination::Page;
+use crate::routes::{generate_id, get_stored_credentials, render_page, PageKind, User};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::Form;
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+/// How long the revoke link of a new device email keeps working.
+const REVOKE_LINK_TTL_DAYS: i64 = 7;
+
+#[derive(Deserialize)]
+pub struct RevokePageQuery {
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeForm {
+    pub token: String,
+}
+
+/// The caller's active sessions, with where each was started from.
+#[tracing::instrument(name = "List sessions", skip(inner, claims))]
+pub async fn list_sessions(
+    State(inner): State<InnerState>,
+    claims: Claims,
+) -> Result<Json<Page<ActiveSession>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let user = get_stored_credentials(&claims.sub, &db).await?;
+
+    let sessions = active_sessions(&db, user.id.as_deref().unwrap_or_default())
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|session| ActiveSession {
+            current: claims.sid.as_deref() == Some(session.id.as_str()),
+            ..session
+        })
+        .collect();
+
+    Ok(Json(Page::complete(sessions)))
+}
+
+/// Revoke one of the caller's sessions, which also stops the access tokens
+/// issued for it from working.
+#[tracing::instrument(name = "Delete session", skip(inner, claims))]
+pub async fn delete_session(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(session_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    // A device token must not be able to log its user out.
+    if claims.scope.is_some() {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    let user = get_stored_credentials(&claims.sub, &db).await?;
+
+    let revoked = revoke_session(&db, user.id.as_deref().unwrap_or_default(), &session_id)
+        .await
+        .map_err(internal_error)?;
+    if !revoked {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Where the revoke link of a new device email leads: a page asking to
+/// confirm, as following a link must not change anything.
+pub async fn revoke_session_page(
+    State(inner): State<InnerState>,
+    Query(query): Query<RevokePageQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let InnerState { db, settings, .. } = inner;
+
+    let token = query.token.unwrap_or_default();
+
+    render_page(
+        &db,
+        &settings,
+        &headers,
+        PageKind::RevokeSession,
+        &[("token", &token)],
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Revoke session from email", skip(inner, headers, form))]
+pub async fn revoke_session_from_email(
+    State(inner): State<InnerState>,
+    headers: HeaderMap,
+    Form(form): Form<RevokeForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let revoked = revoke_session_by_token(&db, &form.token)
+        .await
+        .map_err(internal_error)?;
+    let outcome = if revoked {
+        "session_revoked"
+    } else {
+        "revoke_failed"
+    };
+
+    Ok(render_page(
+        &db,
+        &settings,
+        &headers,
+        PageKind::RevokeSession,
+        &[(outcome, "true")],
+    )
+    .await)
+}
+
+/// Tell the user of a login from a new device or country, in the
+/// background so a slow email never holds up the login.
+pub fn send_new_device_alert(
+    db: PgPool,
+    email_client: EmailClient,
+    settings: &Settings,
+    user: &User,
+    session_id: &str,
+    device: SessionDevice,
+) {
+    let Some(template_id) = settings.new_device_template_id.clone() else {
+        tracing::info!(
+            "Not sending a new device alert for session {} without NEW_DEVICE_TEMPLATE_ID",
+            session_id
+        );
+        return;
+    };
+
+    let public_base_url = settings.public_base_url.clone();
+    let email = user.email.clone();
+    let locale = user
+        .locale
+        .clone()
+        .unwrap_or_else(|| i18n::DEFAULT_LOCALE.to_string());
+    let session_id = session_id.to_string();
+
+    tokio::spawn(async move {
+        let revoke_url = match revoke_link(&db, &public_base_url, &session_id).await {
+            Ok(revoke_url) => revoke_url,
+            Err(err) => {
+                tracing::error!("Could not create a revoke link: {}", err);
+                return;
+            }
+        };
+
+        let unknown = || "unknown".to_string();
+        let mut template_model = HashMap::new();
+        template_model.insert("locale".to_owned(), locale);
+        template_model.insert("product_name".to_owned(), "Groupify".to_owned());
+        template_model.insert("browser".to_owned(), device.browser.unwrap_or_else(unknown));
+        template_model.insert("os".to_owned(), device.os.unwrap_or_else(unknown));
+        template_model.insert("country".to_owned(), device.country.unwrap_or_else(unknown));
+        template_model.insert("ip".to_owned(), device.ip.to_string());
+        template_model.insert("action_url".to_owned(), revoke_url);
+        template_model.insert("support_email".to_owned(), "admin@groupify.dev".to_owned());
+
+        let sent = email_client
+            .send_email(&email, "new-device", template_model, &template_id)
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = sent {
+            tracing::error!("Could not send a new device alert: {}", err);
+        }
+    });
+}
+
+/// A short link to revoke the session with, expiring after
+/// `REVOKE_LINK_TTL_DAYS`. It has no owner, so it shows up in nobody's links.
+async fn revoke_link(
+    db: &PgPool,
+    public_base_url: &str,
+    session_id: &str,
+) -> Result<String, sqlx::Error> {
+    let revoke_token = issue_revoke_token(db, session_id).await?;
+    let target_url = format!("{}/auth/sessions/revoke", public_base_url);
+
+    let link = links::insert_link(
+        db,
+        &NewLink {
+            id: &generate_id(),
+            target_url: &target_url,
+            organization_id: None,
+            owner_email: None,
+            expires_at: Some(
+                chrono::Utc::now().naive_utc() + chrono::Duration::days(REVOKE_LINK_TTL_DAYS),
+            ),
+            payload: None,
+            draft: false,
+            domain: None,
+            behavior: &RedirectBehavior::default(),
+            tags: &[],
+            campaign: None,
+        },
+    )
+    .await?;
+
+    Ok(format!("{}/{}#{}", public_base_url, link.id, revoke_token))
+}
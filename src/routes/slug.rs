@@ -0,0 +1,215 @@
+//! Custom link ids. Besides ids in use, a slug may not shadow the first
+//! segment of another route. Premium slugs, such as two-character codes,
+//! are provisioned by operators and only the organization one is assigned
+//! to may use it.
+
+use crate::casing::Json;
+use crate::db::links;
+use crate::utils::internal_error;
+use crate::validation::{
+    ValidationErrors, MAX_SLUG_LENGTH, MIN_PREMIUM_SLUG_LENGTH, MIN_SLUG_LENGTH,
+};
+use crate::InnerState;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// First path segments of other routes, compared ignoring case so a slug
+/// cannot pass for one either.
+const RESERVED_SLUGS: [&str; 32] = [
+    ".well-known",
+    "admin",
+    "api",
+    "auth",
+    "authorize",
+    "channel",
+    "channels",
+    "create",
+    "device",
+    "docs",
+    "forget-password",
+    "g",
+    "grafana",
+    "group",
+    "groups",
+    "health",
+    "integrations",
+    "invitations",
+    "links",
+    "login",
+    "metrics",
+    "organizations",
+    "public",
+    "qr",
+    "scim",
+    "static",
+    "statistics",
+    "status",
+    "subscription",
+    "triggers",
+    "usage",
+    "users",
+];
+
+/// Free alternatives offered for a slug in use.
+const SUGGESTIONS: usize = 3;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SlugAvailability {
+    Free,
+    /// Nobody may use it.
+    Reserved,
+    /// Only the organization it is assigned to may use it.
+    Premium,
+    Taken,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PremiumSlug {
+    pub slug: String,
+    /// Who may use the slug, nobody until it is assigned.
+    pub organization_id: Option<String>,
+    pub created_by: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub assigned_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SlugQuery {
+    pub slug: String,
+}
+
+#[derive(Serialize)]
+pub struct SlugCheck {
+    pub slug: String,
+    pub status: SlugAvailability,
+    /// Free slugs close to a taken one.
+    pub suggestions: Vec<String>,
+}
+
+pub fn is_reserved_slug(slug: &str) -> bool {
+    RESERVED_SLUGS
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(slug))
+}
+
+/// The premium slug, compared ignoring case like reserved slugs are.
+pub async fn find_premium_slug(
+    db: &PgPool,
+    slug: &str,
+) -> Result<Option<PremiumSlug>, sqlx::Error> {
+    sqlx::query_as::<_, PremiumSlug>(r#"SELECT * FROM premium_slugs WHERE slug = lower($1)"#)
+        .bind(slug)
+        .fetch_optional(db)
+        .await
+}
+
+pub async fn slug_availability(db: &PgPool, slug: &str) -> Result<SlugAvailability, sqlx::Error> {
+    if is_reserved_slug(slug) {
+        return Ok(SlugAvailability::Reserved);
+    }
+
+    if links::link_exists(db, slug).await? {
+        return Ok(SlugAvailability::Taken);
+    }
+
+    if find_premium_slug(db, slug).await?.is_some() {
+        return Ok(SlugAvailability::Premium);
+    }
+
+    // Only premium slugs may be this short.
+    if slug.len() < MIN_SLUG_LENGTH {
+        return Ok(SlugAvailability::Reserved);
+    }
+
+    Ok(SlugAvailability::Free)
+}
+
+/// Refuse a custom id that is a premium slug not assigned to the
+/// organization the link is created in, or that is shorter than custom
+/// ids may be without being one.
+pub async fn require_usable_custom_id(
+    db: &PgPool,
+    custom_id: &str,
+    organization_id: Option<&str>,
+) -> Result<(), (StatusCode, String)> {
+    match find_premium_slug(db, custom_id)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(premium)
+            if premium.organization_id.is_some()
+                && premium.organization_id.as_deref() == organization_id =>
+        {
+            Ok(())
+        }
+        Some(_) => Err((
+            StatusCode::FORBIDDEN,
+            "Custom id is reserved for another organization".to_string(),
+        )),
+        None if custom_id.len() < MIN_SLUG_LENGTH => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "Custom id must be between {} and {} characters",
+                MIN_SLUG_LENGTH, MAX_SLUG_LENGTH
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Whether a custom slug can be used, checked as creating a link with it
+/// would, so forms can tell before submitting.
+#[tracing::instrument(name = "Check slug availability", skip(inner))]
+pub async fn link_availability(
+    State(inner): State<InnerState>,
+    Query(query): Query<SlugQuery>,
+) -> Result<Json<SlugCheck>, Response> {
+    let InnerState { db, .. } = inner;
+
+    let mut errors = ValidationErrors::default();
+    errors.require_slug("slug", &query.slug, MIN_PREMIUM_SLUG_LENGTH);
+    if !errors.is_empty() {
+        return Err(errors.into_response());
+    }
+
+    let status = slug_availability(&db, &query.slug)
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+
+    let suggestions = if status == SlugAvailability::Free {
+        Vec::new()
+    } else {
+        suggest_slugs(&db, &query.slug)
+            .await
+            .map_err(|err| internal_error(err).into_response())?
+    };
+
+    Ok(Json(SlugCheck {
+        slug: query.slug,
+        status,
+        suggestions,
+    }))
+}
+
+/// The slug with a number appended, first the ones nobody uses.
+async fn suggest_slugs(db: &PgPool, slug: &str) -> Result<Vec<String>, sqlx::Error> {
+    let stem = &slug[..slug.len().min(MAX_SLUG_LENGTH - 3)];
+    let candidates: Vec<String> = (2..12).map(|n| format!("{}-{}", stem, n)).collect();
+
+    sqlx::query_scalar(
+        r#"SELECT candidate FROM unnest($1::text[]) WITH ORDINALITY AS candidates (candidate, position)
+        WHERE NOT EXISTS (SELECT 1 FROM links WHERE links.id = candidates.candidate)
+        ORDER BY position LIMIT $2"#,
+    )
+    .bind(candidates)
+    .bind(SUGGESTIONS as i64)
+    .fetch_all(db)
+    .await
+}
@@ -0,0 +1,186 @@
+//! Statistics answers kept in memory for a few seconds, so dashboards
+//! refreshing on a timer do not aggregate the same clicks over and over.
+//!
+//! Entries name the links they were computed from. The rollup job sends a
+//! `statistics_rolled_up` notification per link it rolled up, which every
+//! replica listens for to evict that link's entries along with every
+//! organization-wide one. Clicks flushed between rollups show up once an
+//! entry expires.
+
+use crate::casing::Json;
+
+use axum::http::header::CACHE_CONTROL;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use once_cell::sync::Lazy;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+pub const STATISTICS_ROLLED_UP_CHANNEL: &str = "statistics_rolled_up";
+
+/// How long an answer is served from memory, and cached by the caller.
+const STATISTICS_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Upper bound on cached answers before the cache is emptied.
+const STATISTICS_CACHE_CAPACITY: usize = 10_000;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+static STATISTICS_CACHE: Lazy<RwLock<HashMap<String, CachedStatistics>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+struct CachedStatistics {
+    fetched_at: Instant,
+    link_ids: Vec<String>,
+    organization_wide: bool,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+/// What an answer is computed from, and the parameters telling it apart
+/// from other answers of the same endpoint.
+pub struct StatisticsCacheKey {
+    key: String,
+    link_ids: Vec<String>,
+    organization_wide: bool,
+}
+
+impl StatisticsCacheKey {
+    pub fn link(endpoint: &str, link_id: &str, parameters: impl std::fmt::Debug) -> Self {
+        Self::links(endpoint, &[link_id.to_string()], parameters)
+    }
+
+    pub fn links(endpoint: &str, link_ids: &[String], parameters: impl std::fmt::Debug) -> Self {
+        Self {
+            key: format!("{}:{:?}:{:?}", endpoint, link_ids, parameters),
+            link_ids: link_ids.to_vec(),
+            organization_wide: false,
+        }
+    }
+
+    pub fn organization(
+        endpoint: &str,
+        organization_id: &str,
+        parameters: impl std::fmt::Debug,
+    ) -> Self {
+        Self {
+            key: format!("{}:{}:{:?}", endpoint, organization_id, parameters),
+            link_ids: Vec::new(),
+            organization_wide: true,
+        }
+    }
+}
+
+/// An answer and how much longer it may be cached, sent as JSON with a
+/// matching `Cache-Control`.
+pub struct Cached<T> {
+    pub value: T,
+    pub max_age: Duration,
+}
+
+impl<T: serde::Serialize> IntoResponse for Cached<T> {
+    fn into_response(self) -> Response {
+        let cache_control = format!("private, max-age={}", self.max_age.as_secs());
+
+        ([(CACHE_CONTROL, cache_control)], Json(self.value)).into_response()
+    }
+}
+
+/// The answer for `key` from memory while it is fresh, or from `fetch`.
+/// Only call once the caller is known to be allowed to see it.
+pub async fn cached_statistics<T, F, Fut>(
+    key: StatisticsCacheKey,
+    fetch: F,
+) -> Result<Cached<T>, (StatusCode, String)>
+where
+    T: Clone + Send + Sync + 'static,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, (StatusCode, String)>>,
+{
+    if let Some(entry) = STATISTICS_CACHE
+        .read()
+        .expect("The statistics cache lock should never be poisoned")
+        .get(&key.key)
+    {
+        let age = entry.fetched_at.elapsed();
+        if let (true, Some(value)) = (age < STATISTICS_CACHE_TTL, entry.value.downcast_ref::<T>()) {
+            return Ok(Cached {
+                value: value.clone(),
+                max_age: STATISTICS_CACHE_TTL - age,
+            });
+        }
+    }
+
+    let value = fetch().await?;
+
+    let mut cache = STATISTICS_CACHE
+        .write()
+        .expect("The statistics cache lock should never be poisoned");
+    if cache.len() >= STATISTICS_CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(
+        key.key,
+        CachedStatistics {
+            fetched_at: Instant::now(),
+            link_ids: key.link_ids,
+            organization_wide: key.organization_wide,
+            value: Arc::new(value.clone()),
+        },
+    );
+
+    Ok(Cached {
+        value,
+        max_age: STATISTICS_CACHE_TTL,
+    })
+}
+
+fn evict_link(link_id: &str) {
+    STATISTICS_CACHE
+        .write()
+        .expect("The statistics cache lock should never be poisoned")
+        .retain(|_, entry| {
+            !entry.organization_wide && !entry.link_ids.iter().any(|id| id == link_id)
+        });
+}
+
+fn evict_all() {
+    STATISTICS_CACHE
+        .write()
+        .expect("The statistics cache lock should never be poisoned")
+        .clear();
+}
+
+/// Evict answers as the rollup reports links, for as long as the process
+/// runs.
+pub async fn run_statistics_cache_invalidator(db: PgPool) {
+    loop {
+        if let Err(err) = listen_for_rollups(&db).await {
+            tracing::error!("Statistics cache listener failed: {:?}", err);
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen_for_rollups(db: &PgPool) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(db).await?;
+    listener.listen(STATISTICS_ROLLED_UP_CHANNEL).await?;
+
+    // Rollups may have been missed while not listening.
+    evict_all();
+
+    loop {
+        match listener.try_recv().await? {
+            Some(notification) => evict_link(notification.payload()),
+            None => {
+                tracing::warn!("Statistics cache listener lost its connection");
+                evict_all();
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Statistics of many links in one request, for dashboards listing links.
+
+use crate::authentication::Claims;
+use crate::db::links;
+use crate::policy::Action;
+use crate::routes::{authorize_link, cached_statistics, Cached, StatisticsCacheKey};
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+const MAX_BATCH_LINKS: usize = 100;
+
+/// Days a single query may span.
+const MAX_BATCH_DAYS: i64 = 366;
+
+/// How far back a query without `from` reaches.
+const DEFAULT_BATCH_DAYS: i64 = 30;
+
+const TOP_REFERERS: i64 = 10;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMetric {
+    /// Estimated clicks over the range.
+    Clicks,
+    /// Estimated clicks per day, leaving out days without any.
+    DailyClicks,
+    /// The referers sending the most clicks, of those sending enough to
+    /// show on their own.
+    TopReferers,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchStatisticsRequest {
+    pub link_ids: Vec<String>,
+    pub metric: BatchMetric,
+    /// First day counted, 30 days before `to` by default.
+    pub from: Option<NaiveDate>,
+    /// Last day counted, today by default.
+    pub to: Option<NaiveDate>,
+}
+
+impl BatchStatisticsRequest {
+    fn range(&self) -> (NaiveDate, NaiveDate) {
+        let to = self.to.unwrap_or_else(|| chrono::Utc::now().date_naive());
+        let from = self
+            .from
+            .unwrap_or_else(|| to - chrono::Duration::days(DEFAULT_BATCH_DAYS - 1));
+        (from, to)
+    }
+}
+
+impl Validate for BatchStatisticsRequest {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if self.link_ids.is_empty() || self.link_ids.len() > MAX_BATCH_LINKS {
+            errors.add(
+                "linkIds",
+                format!("must list between 1 and {} links", MAX_BATCH_LINKS),
+            );
+        }
+
+        let (from, to) = self.range();
+        if from > to {
+            errors.add("from", "must not be after to");
+        } else if (to - from).num_days() >= MAX_BATCH_DAYS {
+            errors.add(
+                "from",
+                format!("must be at most {} days before to", MAX_BATCH_DAYS),
+            );
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyClicks {
+    pub day: NaiveDate,
+    pub clicks: i64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefererClicks {
+    pub referer: Option<String>,
+    pub clicks: i64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+pub enum BatchValue {
+    Clicks(i64),
+    DailyClicks(Vec<DailyClicks>),
+    TopReferers(Vec<RefererClicks>),
+}
+
+/// One metric for every requested link, keyed by link id. Links without
+/// clicks in the range get zero or an empty list, as do unknown ones and
+/// those the caller may not read the statistics of.
+#[tracing::instrument(name = "Query link statistics", skip(inner, claims, request))]
+pub async fn query_statistics(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Valid(request): Valid<BatchStatisticsRequest>,
+) -> Result<Cached<HashMap<String, BatchValue>>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let (from, to) = request.range();
+    let link_ids = &request.link_ids;
+    let mut visible = Vec::with_capacity(link_ids.len());
+    for link_id in link_ids {
+        match authorize_link(&db, link_id, &claims, Action::ReadLinkStatistics).await {
+            Ok(_) => visible.push(link_id.clone()),
+            Err((StatusCode::NOT_FOUND | StatusCode::FORBIDDEN, _)) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    visible.sort();
+    visible.dedup();
+
+    // Keyed by the links the caller may see too, so callers seeing
+    // different ones of the same request get answers of their own.
+    let key = StatisticsCacheKey::links("query", link_ids, (request.metric, from, to, &visible));
+
+    cached_statistics(key, || {
+        fetch_batch(
+            &db,
+            link_ids,
+            &visible,
+            request.metric,
+            from,
+            to,
+            settings.statistics_min_bucket_clicks,
+        )
+    })
+    .await
+}
+
+/// The metric of the `visible` links, and that of a link without clicks for
+/// the rest of `link_ids`.
+async fn fetch_batch(
+    db: &PgPool,
+    link_ids: &[String],
+    visible: &[String],
+    metric: BatchMetric,
+    from: NaiveDate,
+    to: NaiveDate,
+    min_referer_clicks: i64,
+) -> Result<HashMap<String, BatchValue>, (StatusCode, String)> {
+    let results = match metric {
+        BatchMetric::Clicks => {
+            let mut results: HashMap<String, BatchValue> = link_ids
+                .iter()
+                .map(|link_id| (link_id.clone(), BatchValue::Clicks(0)))
+                .collect();
+
+            for (link_id, clicks) in links::batch_clicks(db, visible, from, to)
+                .await
+                .map_err(internal_error)?
+            {
+                results.insert(link_id, BatchValue::Clicks(clicks));
+            }
+
+            results
+        }
+        BatchMetric::DailyClicks => {
+            let mut days: HashMap<String, Vec<DailyClicks>> = link_ids
+                .iter()
+                .map(|link_id| (link_id.clone(), Vec::new()))
+                .collect();
+
+            for (link_id, day, clicks) in links::batch_daily_clicks(db, visible, from, to)
+                .await
+                .map_err(internal_error)?
+            {
+                days.entry(link_id)
+                    .or_default()
+                    .push(DailyClicks { day, clicks });
+            }
+
+            days.into_iter()
+                .map(|(link_id, days)| (link_id, BatchValue::DailyClicks(days)))
+                .collect()
+        }
+        BatchMetric::TopReferers => {
+            let mut referers: HashMap<String, Vec<RefererClicks>> = link_ids
+                .iter()
+                .map(|link_id| (link_id.clone(), Vec::new()))
+                .collect();
+
+            for (link_id, referer, clicks) in
+                links::batch_top_referers(db, visible, from, to, TOP_REFERERS, min_referer_clicks)
+                    .await
+                    .map_err(internal_error)?
+            {
+                referers
+                    .entry(link_id)
+                    .or_default()
+                    .push(RefererClicks { referer, clicks });
+            }
+
+            referers
+                .into_iter()
+                .map(|(link_id, referers)| (link_id, BatchValue::TopReferers(referers)))
+                .collect()
+        }
+    };
+
+    Ok(results)
+}
@@ -0,0 +1,73 @@
+use crate::jobs;
+use crate::routes::STATISTICS_ROLLED_UP_CHANNEL;
+
+use sqlx::PgPool;
+
+/// How often raw clicks are folded into the daily rollup.
+const ROLLUP_JOB_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(5 * 60);
+
+/// Clicks rolled up per statement, so one run never holds too many locks.
+const ROLLUP_BATCH_SIZE: i64 = 10_000;
+
+pub async fn run_statistics_rollup_job(db: PgPool) {
+    jobs::run_periodically(db, "statistics_rollup", ROLLUP_JOB_INTERVAL, roll_up_statistics).await
+}
+
+/// Move every click not yet rolled up into `link_statistics_daily`. Marking
+/// the clicks and adding them to the rollup is one statement, so readers
+/// combining the rollup with the remaining raw clicks never count one twice,
+/// however late it was flushed. A visitor is added to the rollup of a day
+/// with their first click of it rolled up, the clicks rolled up before
+/// still being there to tell. Every link rolled up is announced on
+/// `statistics_rolled_up` for replicas to drop cached statistics of it.
+async fn roll_up_statistics(db: PgPool) -> Result<(), sqlx::Error> {
+    loop {
+        let link_ids: Vec<String> = sqlx::query_scalar(
+            r#"WITH batch AS (
+                UPDATE link_statistics SET rolled_up = true
+                WHERE id IN (
+                    SELECT id FROM link_statistics WHERE NOT rolled_up
+                    ORDER BY id LIMIT $1 FOR UPDATE SKIP LOCKED
+                )
+                RETURNING link_id, coalesce(created_at, CURRENT_TIMESTAMP)::date AS day, referer, user_agent, sample_rate, visitor_hash,
+                    is_bot, browser, os
+            ), visits AS (
+                SELECT batch.*, visitor_hash IS NOT NULL AND NOT EXISTS (
+                    SELECT 1 FROM link_statistics seen
+                    WHERE seen.link_id = batch.link_id AND seen.visitor_hash = batch.visitor_hash AND seen.rolled_up
+                        AND coalesce(seen.created_at, CURRENT_TIMESTAMP)::date = batch.day
+                        AND seen.referer IS NOT DISTINCT FROM batch.referer
+                        AND seen.user_agent IS NOT DISTINCT FROM batch.user_agent
+                        AND seen.is_bot = batch.is_bot
+                ) AS new_visitor
+                FROM batch
+            ), rolled_up AS (
+                INSERT INTO link_statistics_daily (link_id, day, referer, user_agent, is_bot, amount, sampled, visitors, browser, os)
+                SELECT link_id, day, referer, user_agent, is_bot, sum(sample_rate), bool_or(sample_rate > 1),
+                    count(DISTINCT visitor_hash) FILTER (WHERE new_visitor), max(browser), max(os)
+                FROM visits GROUP BY link_id, day, referer, user_agent, is_bot
+                ON CONFLICT (link_id, day, referer, user_agent, is_bot) DO UPDATE
+                SET amount = link_statistics_daily.amount + excluded.amount,
+                    sampled = link_statistics_daily.sampled OR excluded.sampled,
+                    visitors = link_statistics_daily.visitors + excluded.visitors,
+                    browser = coalesce(link_statistics_daily.browser, excluded.browser),
+                    os = coalesce(link_statistics_daily.os, excluded.os)
+                RETURNING link_id
+            )
+            SELECT DISTINCT link_id FROM rolled_up"#,
+        )
+        .bind(ROLLUP_BATCH_SIZE)
+        .fetch_all(&db)
+        .await?;
+
+        if link_ids.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query(r#"SELECT pg_notify($1, link_id) FROM unnest($2::text[]) AS link_id"#)
+            .bind(STATISTICS_ROLLED_UP_CHANNEL)
+            .bind(&link_ids)
+            .execute(&db)
+            .await?;
+    }
+}
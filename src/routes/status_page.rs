@@ -0,0 +1,534 @@
+//! Public status of the service: how its components are doing right now,
+//! incidents admins post about, and uptime from checks the leader records
+//! every minute. Minutes without a check count as down, as nothing was
+//! running to record one.
+
+use crate::authentication::AdminUser;
+use crate::casing::Json;
+use crate::jobs;
+use crate::pagination::Page;
+use crate::redirect_cache::RedirectCache;
+use crate::routes::{record_admin_action, OUTBOX_DISPATCHER_TASK};
+use crate::task_health;
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How often the leader records the status of every component.
+const STATUS_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Checks older than the longest uptime window are dropped.
+const STATUS_CHECK_RETENTION_DAYS: i32 = 30;
+
+/// Uptime windows, in days.
+const UPTIME_WINDOWS: [i32; 3] = [1, 7, 30];
+
+/// Resolved incidents stay on the page this long.
+const RECENT_INCIDENT_DAYS: i32 = 14;
+
+/// How long a replica answers with the status it last worked out, so the
+/// public endpoint cannot be used to load the database.
+const STATUS_CACHE_TTL: Duration = Duration::from_secs(10);
+
+const COMPONENT_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Events waiting longer than this mean the webhook dispatcher is behind.
+const MAX_OUTBOX_DELAY_MINUTES: i32 = 5;
+
+pub const STATUS_COMPONENTS: [&str; 4] = ["api", "database", "cache", "webhook_dispatcher"];
+
+const INCIDENT_IMPACTS: [&str; 3] = ["minor", "major", "critical"];
+
+const MAX_INCIDENT_TITLE_LENGTH: usize = 200;
+const MAX_INCIDENT_MESSAGE_LENGTH: usize = 5_000;
+
+static STATUS_CACHE: Lazy<Mutex<Option<(Instant, StatusPage)>>> = Lazy::new(|| Mutex::new(None));
+
+/// From best to worst, so the worst of several is their maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentStatus {
+    Operational,
+    Degraded,
+    Down,
+}
+
+impl ComponentStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ComponentStatus::Operational => "operational",
+            ComponentStatus::Degraded => "degraded",
+            ComponentStatus::Down => "down",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentHealth {
+    pub name: &'static str,
+    pub status: ComponentStatus,
+}
+
+/// Percentages of checks a component was not down in, over the last day,
+/// week and month. `None` for windows without any check.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Uptime {
+    pub day: Option<f64>,
+    pub week: Option<f64>,
+    pub month: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Incident {
+    pub id: String,
+    pub title: String,
+    pub message: String,
+    /// `minor`, `major` or `critical`.
+    pub impact: String,
+    /// The components affected, any of `STATUS_COMPONENTS`.
+    pub components: Vec<String>,
+    pub started_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    /// `None` while the incident is ongoing.
+    pub resolved_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusPage {
+    /// The worst status of any component.
+    pub status: ComponentStatus,
+    pub components: Vec<ComponentHealth>,
+    /// Ongoing incidents and those resolved in the last two weeks, latest
+    /// first.
+    pub incidents: Vec<Incident>,
+    pub uptime: BTreeMap<String, Uptime>,
+    pub checked_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewIncident {
+    pub title: String,
+    pub message: String,
+    pub impact: String,
+    #[serde(default)]
+    pub components: Vec<String>,
+}
+
+impl Validate for NewIncident {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        validate_incident(
+            errors,
+            Some(&self.title),
+            Some(&self.message),
+            Some(&self.impact),
+            Some(&self.components),
+        );
+    }
+}
+
+/// Fields left out stay as they are.
+#[derive(Debug, Deserialize)]
+pub struct IncidentUpdate {
+    pub title: Option<String>,
+    pub message: Option<String>,
+    pub impact: Option<String>,
+    pub components: Option<Vec<String>>,
+    /// Resolve the incident, or reopen it with `false`.
+    pub resolved: Option<bool>,
+}
+
+impl Validate for IncidentUpdate {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        validate_incident(
+            errors,
+            self.title.as_deref(),
+            self.message.as_deref(),
+            self.impact.as_deref(),
+            self.components.as_deref(),
+        );
+    }
+}
+
+fn validate_incident(
+    errors: &mut ValidationErrors,
+    title: Option<&str>,
+    message: Option<&str>,
+    impact: Option<&str>,
+    components: Option<&[String]>,
+) {
+    if let Some(title) = title {
+        errors.require_not_blank("title", title);
+        errors.require_max_length("title", title, MAX_INCIDENT_TITLE_LENGTH);
+    }
+    if let Some(message) = message {
+        errors.require_not_blank("message", message);
+        errors.require_max_length("message", message, MAX_INCIDENT_MESSAGE_LENGTH);
+    }
+    if impact.is_some_and(|impact| !INCIDENT_IMPACTS.contains(&impact)) {
+        errors.add("impact", "must be minor, major or critical");
+    }
+    if components.is_some_and(|components| {
+        components
+            .iter()
+            .any(|component| !STATUS_COMPONENTS.contains(&component.as_str()))
+    }) {
+        errors.add(
+            "components",
+            "must be api, database, cache or webhook_dispatcher",
+        );
+    }
+}
+
+/// The status of every component as this replica sees it. The API is up
+/// for as long as there is anyone to ask.
+async fn check_components(db: &PgPool, redirect_cache: &RedirectCache) -> Vec<ComponentHealth> {
+    let database = tokio::time::timeout(
+        COMPONENT_CHECK_TIMEOUT,
+        sqlx::query_scalar::<_, Option<NaiveDateTime>>(
+            r#"SELECT min(created_at) FROM outbox_events"#,
+        )
+        .fetch_one(db),
+    )
+    .await;
+    let (database, oldest_event) = match database {
+        Ok(Ok(oldest_event)) => (ComponentStatus::Operational, oldest_event),
+        Ok(Err(err)) => {
+            tracing::warn!("Status check could not reach the database: {}", err);
+            (ComponentStatus::Down, None)
+        }
+        Err(_) => {
+            tracing::warn!("Status check timed out on the database");
+            (ComponentStatus::Down, None)
+        }
+    };
+
+    let mut components = vec![
+        ComponentHealth {
+            name: "api",
+            status: ComponentStatus::Operational,
+        },
+        ComponentHealth {
+            name: "database",
+            status: database,
+        },
+    ];
+
+    if redirect_cache.is_enabled() {
+        let status = match redirect_cache.ping(COMPONENT_CHECK_TIMEOUT).await {
+            Ok(()) => ComponentStatus::Operational,
+            Err(err) => {
+                tracing::warn!("Status check could not reach the cache: {:#}", err);
+                ComponentStatus::Down
+            }
+        };
+        components.push(ComponentHealth {
+            name: "cache",
+            status,
+        });
+    }
+
+    let dispatcher = task_health::statuses()
+        .into_iter()
+        .find(|task| task.name == OUTBOX_DISPATCHER_TASK);
+    let behind = oldest_event.is_some_and(|oldest_event| {
+        oldest_event
+            < chrono::Local::now().naive_local()
+                - chrono::Duration::minutes(MAX_OUTBOX_DELAY_MINUTES.into())
+    });
+    let status = match dispatcher {
+        None => ComponentStatus::Down,
+        Some(task) if task.stuck => ComponentStatus::Down,
+        Some(task) if task.last_failure_at > task.last_success_at || behind => {
+            ComponentStatus::Degraded
+        }
+        Some(_) => ComponentStatus::Operational,
+    };
+    components.push(ComponentHealth {
+        name: "webhook_dispatcher",
+        status,
+    });
+
+    components
+}
+
+async fn recent_incidents(db: &PgPool) -> Result<Vec<Incident>, sqlx::Error> {
+    sqlx::query_as::<_, Incident>(
+        r#"SELECT id, title, message, impact, components, started_at, updated_at, resolved_at
+        FROM status_incidents
+        WHERE resolved_at IS NULL OR resolved_at > CURRENT_TIMESTAMP - make_interval(days => $1)
+        ORDER BY started_at DESC, id"#,
+    )
+    .bind(RECENT_INCIDENT_DAYS)
+    .fetch_all(db)
+    .await
+}
+
+/// Uptime of every component checked in the last month.
+async fn uptime(db: &PgPool) -> Result<BTreeMap<String, Uptime>, sqlx::Error> {
+    // A window starts at its first check, so a new deployment is not down
+    // for the time before it existed.
+    let rows: Vec<(String, i32, f64)> = sqlx::query_as(
+        r#"SELECT component, days, 100 * count(*) FILTER (WHERE status <> 'down')::float8
+            / greatest(count(*), floor(extract(epoch FROM localtimestamp - min(checked_at)) / $2) + 1)
+        FROM status_checks JOIN unnest($1::int[]) AS days
+            ON checked_at > localtimestamp - make_interval(days => days)
+        GROUP BY component, days"#,
+    )
+    .bind(&UPTIME_WINDOWS[..])
+    .bind(STATUS_CHECK_INTERVAL.as_secs_f64())
+    .fetch_all(db)
+    .await?;
+
+    let mut uptime: BTreeMap<String, Uptime> = BTreeMap::new();
+    for (component, days, percentage) in rows {
+        let windows = uptime.entry(component).or_default();
+        let percentage = Some((percentage * 1000.0).round() / 1000.0);
+        match days {
+            1 => windows.day = percentage,
+            7 => windows.week = percentage,
+            _ => windows.month = percentage,
+        }
+    }
+
+    Ok(uptime)
+}
+
+/// Machine-readable status of the service, for status pages and monitors.
+pub async fn service_status(State(inner): State<InnerState>) -> Json<StatusPage> {
+    let cached = STATUS_CACHE
+        .lock()
+        .expect("The status cache lock should never be poisoned")
+        .as_ref()
+        .filter(|(at, _)| at.elapsed() < STATUS_CACHE_TTL)
+        .map(|(_, page)| page.clone());
+    if let Some(page) = cached {
+        return Json(page);
+    }
+
+    let InnerState {
+        db, redirect_cache, ..
+    } = inner;
+
+    let components = check_components(&db, &redirect_cache).await;
+    let database_up = components
+        .iter()
+        .any(|component| component.name == "database" && component.status != ComponentStatus::Down);
+
+    let (incidents, uptime) = if database_up {
+        let (incidents, uptime) = tokio::join!(recent_incidents(&db), uptime(&db));
+        (
+            incidents.unwrap_or_else(|err| {
+                tracing::error!("Could not read incidents: {}", err);
+                Vec::new()
+            }),
+            uptime.unwrap_or_else(|err| {
+                tracing::error!("Could not work out uptime: {}", err);
+                BTreeMap::new()
+            }),
+        )
+    } else {
+        (Vec::new(), BTreeMap::new())
+    };
+
+    let page = StatusPage {
+        status: components
+            .iter()
+            .map(|component| component.status)
+            .max()
+            .unwrap_or(ComponentStatus::Operational),
+        components,
+        incidents,
+        uptime,
+        checked_at: chrono::Utc::now().naive_utc(),
+    };
+
+    *STATUS_CACHE
+        .lock()
+        .expect("The status cache lock should never be poisoned") =
+        Some((Instant::now(), page.clone()));
+
+    Json(page)
+}
+
+pub async fn run_status_check_job(db: PgPool, redirect_cache: Arc<RedirectCache>) {
+    jobs::run_periodically(db, "status_check", STATUS_CHECK_INTERVAL, |db| {
+        record_status_checks(db, redirect_cache.clone())
+    })
+    .await
+}
+
+/// Record the status of every component, for uptime, and drop checks no
+/// window reaches anymore.
+async fn record_status_checks(
+    db: PgPool,
+    redirect_cache: Arc<RedirectCache>,
+) -> Result<(), sqlx::Error> {
+    let components = check_components(&db, &redirect_cache).await;
+    let (names, statuses): (Vec<&str>, Vec<&str>) = components
+        .iter()
+        .map(|component| (component.name, component.status.as_str()))
+        .unzip();
+
+    sqlx::query(
+        r#"INSERT INTO status_checks (component, status)
+        SELECT * FROM unnest($1::text[], $2::text[])"#,
+    )
+    .bind(&names)
+    .bind(&statuses)
+    .execute(&db)
+    .await?;
+
+    sqlx::query(
+        r#"DELETE FROM status_checks
+        WHERE checked_at < localtimestamp - make_interval(days => $1)"#,
+    )
+    .bind(STATUS_CHECK_RETENTION_DAYS)
+    .execute(&db)
+    .await?;
+
+    Ok(())
+}
+
+/// Every incident, latest first.
+pub async fn list_status_incidents(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Result<Json<Page<Incident>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let incidents = sqlx::query_as::<_, Incident>(
+        r#"SELECT id, title, message, impact, components, started_at, updated_at, resolved_at
+        FROM status_incidents ORDER BY started_at DESC, id"#,
+    )
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(incidents)))
+}
+
+#[tracing::instrument(name = "Create status incident", skip(inner, admin, incident))]
+pub async fn create_status_incident(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Valid(incident): Valid<NewIncident>,
+) -> Result<(StatusCode, Json<Incident>), (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let incident = sqlx::query_as::<_, Incident>(
+        r#"INSERT INTO status_incidents (id, title, message, impact, components, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, title, message, impact, components, started_at, updated_at, resolved_at"#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(incident.title.trim())
+    .bind(incident.message.trim())
+    .bind(&incident.impact)
+    .bind(&incident.components)
+    .bind(&admin.claims.sub)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "status_incident.create",
+        Some(&incident.id),
+        None,
+        serde_json::to_value(&incident).ok(),
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    forget_status();
+
+    Ok((StatusCode::CREATED, Json(incident)))
+}
+
+/// Post an update to an incident, resolve it or reopen it.
+#[tracing::instrument(name = "Update status incident", skip(inner, admin, update))]
+pub async fn update_status_incident(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(incident_id): Path<String>,
+    Valid(update): Valid<IncidentUpdate>,
+) -> Result<Json<Incident>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let before = sqlx::query_as::<_, Incident>(
+        r#"SELECT id, title, message, impact, components, started_at, updated_at, resolved_at
+        FROM status_incidents WHERE id = $1 FOR UPDATE"#,
+    )
+    .bind(&incident_id)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    let incident = sqlx::query_as::<_, Incident>(
+        r#"UPDATE status_incidents SET title = coalesce($2, title),
+            message = coalesce($3, message), impact = coalesce($4, impact),
+            components = coalesce($5, components), updated_at = CURRENT_TIMESTAMP,
+            resolved_at = CASE
+                WHEN $6 THEN coalesce(resolved_at, CURRENT_TIMESTAMP)
+                WHEN NOT $6 THEN NULL
+                ELSE resolved_at
+            END
+        WHERE id = $1
+        RETURNING id, title, message, impact, components, started_at, updated_at, resolved_at"#,
+    )
+    .bind(&incident_id)
+    .bind(update.title.as_deref().map(str::trim))
+    .bind(update.message.as_deref().map(str::trim))
+    .bind(&update.impact)
+    .bind(&update.components)
+    .bind(update.resolved)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(internal_error)?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "status_incident.update",
+        Some(&incident.id),
+        serde_json::to_value(&before).ok(),
+        serde_json::to_value(&incident).ok(),
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    forget_status();
+
+    Ok(Json(incident))
+}
+
+/// Show incident changes on this replica right away; others catch up
+/// within `STATUS_CACHE_TTL`.
+fn forget_status() {
+    *STATUS_CACHE
+        .lock()
+        .expect("The status cache lock should never be poisoned") = None;
+}
@@ -5,11 +5,6 @@ use crate::InnerState;
 use crate::routes::{User, get_confirmation_token_from_user};
 use crate::utils::internal_error;
 
-#[derive(serde::Deserialize)]
-pub struct Parameters {
-    subscription_token: String,
-}
-
 #[tracing::instrument(name = "Confirm a pending subscriber", skip(subscription_token, inner))]
 pub async fn confirm(
     State(inner): State<InnerState>,
@@ -36,6 +31,6 @@ pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: String) -> Result<
         .map(|user| user.id)
         .map_err(internal_error)?;
 
-    Ok(id.unwrap_or_else(|| String::new()))
+    Ok(id.unwrap_or_default())
 }
 
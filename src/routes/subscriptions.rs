@@ -2,11 +2,10 @@ use crate::utils::internal_error;
 
 use anyhow::Result;
 use axum::extract::State;
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
 use axum::Json;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use sha3::Digest;
 use sqlx::{Executor, Postgres, Transaction};
 use std::collections::HashMap;
 
@@ -14,12 +13,23 @@ use crate::routes::{create_user, User};
 use crate::InnerState;
 
 use crate::email::EmailClient;
+use crate::i18n;
 
 pub async fn subscribe(
     State(inner): State<InnerState>,
-    Json(user): Json<User>,
+    headers: HeaderMap,
+    Json(mut user): Json<User>,
 ) -> Result<Json<String>, (StatusCode, String)> {
-    let InnerState { email_client, db } = inner;
+    let InnerState {
+        email_client, db, ..
+    } = inner;
+
+    let locale = user
+        .locale
+        .as_deref()
+        .and_then(i18n::resolve)
+        .unwrap_or_else(|| i18n::negotiate(&headers));
+    user.locale = Some(locale.to_string());
 
     let mut transaction = db.begin().await.map_err(internal_error)?;
 
@@ -30,7 +40,7 @@ pub async fn subscribe(
 
     transaction.commit().await.map_err(internal_error)?;
 
-    let resp = send_confirmation_email(&email_client, user, &subscription_token).await?;
+    send_confirmation_email(&email_client, user, &subscription_token).await?;
 
     Ok(Json("OK".to_owned()))
 }
@@ -58,9 +68,12 @@ pub async fn send_confirmation_email(
         subscription_token
     );
 
-    let template_id = "35795627";
+    let locale = user.locale.as_deref().unwrap_or(i18n::DEFAULT_LOCALE);
+    let template_id = i18n::translate(locale, "email.welcome.template_id")
+        .expect("The default catalog should name every email template");
 
     let mut template_model = HashMap::new();
+    template_model.insert("locale".to_owned(), locale.to_owned());
     template_model.insert("product_name".to_owned(), "Groupify".to_owned());
     template_model.insert("action_url".to_owned(), confirmation_link);
     template_model.insert("support_email".to_owned(), "admin@groupify.dev".to_owned());
@@ -87,7 +100,7 @@ pub async fn store_token(
     subscription_token: &str,
 ) -> Result<(), (StatusCode, String)> {
     let query = sqlx::query_as::<_, User>(r#" UPDATE users SET confirmation_token = $1, updated_at = CURRENT_TIMESTAMP, confirmation_sent_at = CURRENT_TIMESTAMP WHERE id = $2"#)
-        .bind(&subscription_token)
+        .bind(subscription_token)
         .bind(subscriber_id);
 
     transaction.execute(query).await.map_err(internal_error)?;
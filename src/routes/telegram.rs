@@ -0,0 +1,241 @@
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::db::links::{NewLink, RedirectBehavior};
+use crate::routes::{
+    generate_id, generate_subscription_token, get_stored_credentials, insert_checked_link,
+};
+use crate::telegram::{TelegramClient, TelegramMessage, TelegramUpdate};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use serde::Serialize;
+use sqlx::PgPool;
+use url::Url;
+
+/// Codes handed out by `link_telegram_account` older than this are rejected.
+const LINK_CODE_TTL_MINUTES: i32 = 10;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TelegramLinkCode {
+    pub code: String,
+    pub start_url: String,
+}
+
+fn telegram_client(inner: &InnerState) -> Result<TelegramClient, (StatusCode, String)> {
+    inner.telegram.clone().ok_or_else(|| {
+        (
+            StatusCode::NOT_IMPLEMENTED,
+            "Telegram integration is not configured".to_string(),
+        )
+    })
+}
+
+/// Start linking a Telegram account: the user opens the returned URL and the
+/// bot receives the code with `/start`.
+#[tracing::instrument(name = "Start Telegram linking", skip(inner, claims))]
+pub async fn link_telegram_account(
+    State(inner): State<InnerState>,
+    claims: Claims,
+) -> Result<Json<TelegramLinkCode>, (StatusCode, String)> {
+    let telegram = telegram_client(&inner)?;
+    let user = get_stored_credentials(&claims.sub, &inner.db).await?;
+
+    let code = generate_subscription_token();
+
+    sqlx::query(r#"INSERT INTO telegram_link_codes (code, user_id) VALUES ($1, $2)"#)
+        .bind(&code)
+        .bind(user.id)
+        .execute(&inner.db)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(TelegramLinkCode {
+        start_url: telegram.start_url(&code),
+        code,
+    }))
+}
+
+/// Receive bot updates. Telegram retries anything but a success, so failures
+/// past the secret check are logged and reported to the chat instead.
+#[tracing::instrument(name = "Telegram webhook", skip(inner, headers, update))]
+pub async fn telegram_webhook(
+    State(inner): State<InnerState>,
+    headers: HeaderMap,
+    Json(update): Json<TelegramUpdate>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let telegram = telegram_client(&inner)?;
+
+    let secret = headers
+        .get("x-telegram-bot-api-secret-token")
+        .and_then(|value| value.to_str().ok());
+    if secret != Some(telegram.webhook_secret.as_str()) {
+        return Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string()));
+    }
+
+    let Some(message) = update.message else {
+        return Ok(StatusCode::OK);
+    };
+
+    let reply = match handle_message(&inner, &message).await {
+        Ok(reply) => reply,
+        Err((_, err)) => {
+            tracing::error!(
+                "Could not handle Telegram update {}: {}",
+                update.update_id,
+                err
+            );
+            BotReply::Text("Something went wrong, please try again later.".to_string())
+        }
+    };
+
+    let sent = match &reply {
+        BotReply::Text(text) => telegram.send_message(message.chat.id, text).await,
+        BotReply::ShortLink { url, qr_code_url } => {
+            telegram.send_photo(message.chat.id, qr_code_url, url).await
+        }
+    };
+
+    if let Err(err) = sent {
+        tracing::error!("Could not reply to Telegram chat: {}", err);
+    }
+
+    Ok(StatusCode::OK)
+}
+
+enum BotReply {
+    Text(String),
+    ShortLink { url: String, qr_code_url: String },
+}
+
+async fn handle_message(
+    inner: &InnerState,
+    message: &TelegramMessage,
+) -> Result<BotReply, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let (Some(from), Some(text)) = (&message.from, &message.text) else {
+        return Ok(BotReply::Text("Send me a URL to shorten.".to_string()));
+    };
+
+    if let Some(code) = text.strip_prefix("/start") {
+        return link_account(db, from.id, code.trim()).await;
+    }
+
+    let owner_email: Option<String> = sqlx::query_scalar(
+        r#"SELECT users.email FROM telegram_accounts
+        JOIN users ON users.id = telegram_accounts.user_id
+        WHERE telegram_accounts.telegram_user_id = $1"#,
+    )
+    .bind(from.id)
+    .fetch_optional(db)
+    .await
+    .map_err(internal_error)?;
+
+    let Some(owner_email) = owner_email else {
+        return Ok(BotReply::Text(
+            "Link your Groupify account first using the link from your account settings."
+                .to_string(),
+        ));
+    };
+
+    let Some(url) = text
+        .split_whitespace()
+        .filter_map(|word| Url::parse(word).ok())
+        .find(|url| {
+            matches!(url.scheme(), "http" | "https")
+                && url.username().is_empty()
+                && url.password().is_none()
+        })
+    else {
+        return Ok(BotReply::Text("Send me a URL to shorten.".to_string()));
+    };
+
+    let mut transaction = db.begin().await.map_err(internal_error)?;
+
+    let created = insert_checked_link(
+        &mut transaction,
+        inner,
+        &NewLink {
+            id: &generate_id(),
+            target_url: url.as_str(),
+            organization_id: None,
+            owner_email: Some(&owner_email),
+            expires_at: None,
+            payload: None,
+            draft: false,
+            domain: None,
+            behavior: &RedirectBehavior::default(),
+            tags: &[],
+            campaign: None,
+        },
+    )
+    .await;
+    let created = match created {
+        Ok(created) => created,
+        Err((StatusCode::TOO_MANY_REQUESTS, _)) => {
+            return Ok(BotReply::Text(
+                "You have used up your links for this month.".to_string(),
+            ));
+        }
+        Err((status, message)) if status.is_client_error() => {
+            return Ok(BotReply::Text(message));
+        }
+        Err(err) => return Err(err),
+    };
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    let link_id = created.committed(inner).id;
+
+    Ok(BotReply::ShortLink {
+        url: format!("{}/{}", settings.public_base_url, link_id),
+        qr_code_url: format!("{}/{}/qr.png", settings.public_base_url, link_id),
+    })
+}
+
+async fn link_account(
+    db: &PgPool,
+    telegram_user_id: i64,
+    code: &str,
+) -> Result<BotReply, (StatusCode, String)> {
+    if code.is_empty() {
+        return Ok(BotReply::Text(
+            "Hi! Link your Groupify account from your account settings, then send me URLs to shorten."
+                .to_string(),
+        ));
+    }
+
+    let user_id: Option<String> = sqlx::query_scalar(
+        r#"DELETE FROM telegram_link_codes
+        WHERE code = $1 AND created_at > CURRENT_TIMESTAMP - make_interval(mins => $2)
+        returning user_id"#,
+    )
+    .bind(code)
+    .bind(LINK_CODE_TTL_MINUTES)
+    .fetch_optional(db)
+    .await
+    .map_err(internal_error)?;
+
+    let Some(user_id) = user_id else {
+        return Ok(BotReply::Text(
+            "That link has expired, please request a new one.".to_string(),
+        ));
+    };
+
+    sqlx::query(
+        r#"INSERT INTO telegram_accounts (telegram_user_id, user_id) VALUES ($1, $2)
+        ON CONFLICT (telegram_user_id) DO UPDATE SET user_id = excluded.user_id"#,
+    )
+    .bind(telegram_user_id)
+    .bind(user_id)
+    .execute(db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(BotReply::Text(
+        "Your account is linked. Send me a URL to shorten it.".to_string(),
+    ))
+}
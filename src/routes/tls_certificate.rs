@@ -0,0 +1,312 @@
+use crate::authentication::AdminUser;
+use crate::casing::Json;
+use crate::pagination::Page;
+use crate::routes::{is_domain_name, record_admin_action};
+use crate::sealing;
+use crate::tls::parse_certificate;
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, PgPool};
+
+const TLS_CERTIFICATE_COLUMNS: &str = r#"domain, managed, certificate_chain IS NOT NULL AS issued,
+    not_after, renewal_error, renewal_attempted_at, created_at, updated_at"#;
+
+/// A domain served over HTTPS, without its key.
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsCertificate {
+    pub domain: String,
+    /// Whether the certificate is issued and renewed through ACME.
+    pub managed: bool,
+    /// Whether there is a certificate yet, which managed domains wait for.
+    pub issued: bool,
+    pub not_after: Option<NaiveDateTime>,
+    /// Why the latest ACME order failed.
+    pub renewal_error: Option<String>,
+    pub renewal_attempted_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsCertificateUpload {
+    /// PEM certificates, the domain's own first.
+    pub certificate_chain: String,
+    /// PEM private key of the domain's certificate.
+    pub private_key: String,
+}
+
+impl Validate for TlsCertificateUpload {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_not_blank("certificateChain", &self.certificate_chain);
+        errors.require_not_blank("privateKey", &self.private_key);
+    }
+}
+
+/// A domain name, or a wildcard covering the names one label below one.
+fn is_certificate_domain(domain: &str) -> bool {
+    is_domain_name(domain.strip_prefix("*.").unwrap_or(domain))
+}
+
+async fn find_tls_certificate(
+    db: &PgPool,
+    domain: &str,
+) -> Result<TlsCertificate, (StatusCode, String)> {
+    sqlx::query_as::<_, TlsCertificate>(&format!(
+        r#"SELECT {} FROM tls_certificates WHERE domain = $1"#,
+        TLS_CERTIFICATE_COLUMNS
+    ))
+    .bind(domain)
+    .fetch_one(db)
+    .await
+    .map_err(internal_error)
+}
+
+/// Load the change on this replica right away; others pick it up on their
+/// next refresh.
+async fn reload_certificates(inner: &InnerState) {
+    if let Err(err) = inner.tls_certificates.reload(&inner.db).await {
+        tracing::error!("Could not reload TLS certificates: {}", err);
+    }
+}
+
+pub async fn list_tls_certificates(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+) -> Result<Json<Page<TlsCertificate>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let certificates = sqlx::query_as::<_, TlsCertificate>(&format!(
+        r#"SELECT {} FROM tls_certificates ORDER BY domain"#,
+        TLS_CERTIFICATE_COLUMNS
+    ))
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(certificates)))
+}
+
+/// Serve the domain with the given certificate, replacing any it had and
+/// ending ACME management of it.
+#[tracing::instrument(name = "Upload TLS certificate", skip(inner, admin, upload))]
+pub async fn upload_tls_certificate(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(domain): Path<String>,
+    Valid(upload): Valid<TlsCertificateUpload>,
+) -> Result<Json<TlsCertificate>, Response> {
+    let domain = domain.to_ascii_lowercase();
+
+    let mut errors = ValidationErrors::default();
+    if !is_certificate_domain(&domain) {
+        errors.add(
+            "domain",
+            "must be a domain name or a wildcard like *.example.com",
+        );
+    }
+    let not_after = match parse_certificate(&upload.certificate_chain, &upload.private_key) {
+        Ok(parsed) if parsed.not_after <= Utc::now().naive_utc() => {
+            errors.add("certificateChain", "has expired");
+            None
+        }
+        Ok(parsed) => Some(parsed.not_after),
+        Err(err) => {
+            errors.add("certificateChain", err.to_string());
+            None
+        }
+    };
+    if !errors.is_empty() {
+        return Err(errors.into_response());
+    }
+
+    let mut transaction = inner
+        .db
+        .begin()
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+
+    let before: Option<(bool, Option<NaiveDateTime>)> = sqlx::query_as(
+        r#"SELECT managed, not_after FROM tls_certificates WHERE domain = $1 FOR UPDATE"#,
+    )
+    .bind(&domain)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|err| internal_error(err).into_response())?;
+
+    sqlx::query(
+        r#"INSERT INTO tls_certificates (domain, certificate_chain, private_key, not_after)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (domain) DO UPDATE SET certificate_chain = excluded.certificate_chain,
+        private_key = excluded.private_key, not_after = excluded.not_after, managed = false,
+        renewal_error = NULL, updated_at = CURRENT_TIMESTAMP"#,
+    )
+    .bind(&domain)
+    .bind(&upload.certificate_chain)
+    .bind(sealing::seal(
+        inner.settings.certificate_encryption_key.as_ref(),
+        &domain,
+        &upload.private_key,
+    ))
+    .bind(not_after)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|err| internal_error(err).into_response())?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "tls_certificate.upload",
+        Some(&domain),
+        before.map(|(managed, not_after)| json!({ "managed": managed, "notAfter": not_after })),
+        Some(json!({ "managed": false, "notAfter": not_after })),
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+
+    reload_certificates(&inner).await;
+
+    Ok(Json(
+        find_tls_certificate(&inner.db, &domain)
+            .await
+            .map_err(IntoResponse::into_response)?,
+    ))
+}
+
+/// Have the domain's certificate issued and renewed through ACME. The
+/// certificate it has is served until the first one is issued.
+#[tracing::instrument(name = "Manage TLS certificate", skip(inner, admin))]
+pub async fn manage_tls_certificate(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(domain): Path<String>,
+) -> Result<Json<TlsCertificate>, Response> {
+    let InnerState { db, settings, .. } = inner;
+    let domain = domain.to_ascii_lowercase();
+
+    if settings.acme_directory_url.is_none() {
+        return Err((
+            StatusCode::CONFLICT,
+            "No ACME directory is configured".to_string(),
+        )
+            .into_response());
+    }
+
+    // Only DNS-01 challenges prove control over wildcards.
+    let valid = if settings.acme_dns_hook_url.is_some() {
+        is_certificate_domain(&domain)
+    } else {
+        is_domain_name(&domain)
+    };
+    if !valid {
+        let mut errors = ValidationErrors::default();
+        errors.add(
+            "domain",
+            "must be a domain name, or a wildcard when a DNS hook is configured",
+        );
+        return Err(errors.into_response());
+    }
+
+    let mut transaction = db
+        .begin()
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+
+    sqlx::query(
+        r#"INSERT INTO tls_certificates (domain, managed) VALUES ($1, true)
+        ON CONFLICT (domain) DO UPDATE SET managed = true, renewal_error = NULL,
+        updated_at = CURRENT_TIMESTAMP"#,
+    )
+    .bind(&domain)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|err| internal_error(err).into_response())?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "tls_certificate.manage",
+        Some(&domain),
+        None,
+        Some(json!({ "managed": true })),
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+
+    Ok(Json(
+        find_tls_certificate(&db, &domain)
+            .await
+            .map_err(IntoResponse::into_response)?,
+    ))
+}
+
+/// Stop serving the domain over HTTPS.
+#[tracing::instrument(name = "Delete TLS certificate", skip(inner, admin))]
+pub async fn delete_tls_certificate(
+    State(inner): State<InnerState>,
+    admin: AdminUser,
+    Path(domain): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let domain = domain.to_ascii_lowercase();
+
+    let mut transaction = inner.db.begin().await.map_err(internal_error)?;
+
+    let (managed, not_after): (bool, Option<NaiveDateTime>) = sqlx::query_as(
+        r#"DELETE FROM tls_certificates WHERE domain = $1 RETURNING managed, not_after"#,
+    )
+    .bind(&domain)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+
+    record_admin_action(
+        &mut *transaction,
+        &admin,
+        "tls_certificate.delete",
+        Some(&domain),
+        Some(json!({ "managed": managed, "notAfter": not_after })),
+        None,
+    )
+    .await?;
+
+    transaction.commit().await.map_err(internal_error)?;
+
+    reload_certificates(&inner).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Answer an ACME authority checking that this service controls a domain.
+pub async fn acme_challenge(
+    State(inner): State<InnerState>,
+    Path(token): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    sqlx::query_scalar(r#"SELECT key_authorization FROM acme_challenges WHERE token = $1"#)
+        .bind(&token)
+        .fetch_optional(&db)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))
+}
@@ -0,0 +1,660 @@
+use crate::authentication::Claims;
+use crate::host_rules::{fetchable_redirects, is_fetchable, PublicResolver};
+use crate::jobs;
+use crate::routes::{get_stored_credentials, require_allowed_host};
+use crate::task_health;
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::NaiveDateTime;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, PgPool};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use url::Url;
+use uuid::Uuid;
+
+const DEFAULT_TRIGGER_LIMIT: i64 = 50;
+const MAX_TRIGGER_LIMIT: i64 = 100;
+
+const OUTBOX_BATCH_SIZE: i64 = 100;
+
+/// How often the outbox is checked for events queued by other replicas.
+const OUTBOX_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Name of the dispatcher in the task health report.
+pub const OUTBOX_DISPATCHER_TASK: &str = "outbox_dispatcher";
+
+/// Woken when this replica queues an event, so it goes out right away.
+static OUTBOX_WAKER: Lazy<Notify> = Lazy::new(Notify::new);
+
+const DEFAULT_DIGEST_INTERVAL_MINUTES: i32 = 15;
+const MAX_DIGEST_INTERVAL_MINUTES: i64 = 24 * 60;
+const DEFAULT_DIGEST_MAX_EVENTS: i32 = 100;
+const MAX_DIGEST_MAX_EVENTS: i64 = 1000;
+
+/// How often digests are checked for having waited their interval.
+const DIGEST_JOB_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Redirects of a hook followed at most.
+const MAX_HOOK_REDIRECTS: usize = 5;
+
+/// Hooks are user supplied URLs, so they are only ever reached at public
+/// addresses, however their names resolve by the time events go out.
+static HOOK_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(fetchable_redirects(MAX_HOOK_REDIRECTS))
+        .build()
+        .expect("The REST hook client should always be constructable")
+});
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerEvent {
+    NewLink,
+    NewClick,
+}
+
+impl TriggerEvent {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TriggerEvent::NewLink => "new_link",
+            TriggerEvent::NewClick => "new_click",
+        }
+    }
+
+    fn parse(event: &str) -> Option<Self> {
+        match event {
+            "new_link" => Some(TriggerEvent::NewLink),
+            "new_click" => Some(TriggerEvent::NewClick),
+            _ => None,
+        }
+    }
+}
+
+/// How a subscription receives its events.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookDelivery {
+    /// One request per event.
+    #[default]
+    Each,
+    /// Clicks batched per link, sent once enough piled up or the oldest
+    /// waited long enough.
+    Digest,
+}
+
+impl HookDelivery {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HookDelivery::Each => "each",
+            HookDelivery::Digest => "digest",
+        }
+    }
+}
+
+/// An event committed along with the change it reports, waiting to be
+/// pushed to REST hooks.
+#[derive(Debug, FromRow)]
+struct OutboxEvent {
+    id: i32,
+    event: String,
+    /// The id of the link the event is about.
+    subject: String,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct NewLinkItem {
+    pub id: String,
+    pub target_url: String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct NewClickItem {
+    /// Zapier deduplicates polled items on this field.
+    pub id: String,
+    pub link_id: String,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    /// How many clicks this one stands for on sampled links.
+    pub sample_rate: i32,
+}
+
+/// The clicks on one link since the previous digest, oldest first.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClickDigest {
+    pub link_id: String,
+    /// Estimated clicks, with sampled clicks scaled by their rate.
+    pub estimated_clicks: i64,
+    pub clicks: Vec<NewClickItem>,
+}
+
+#[derive(FromRow)]
+struct DueDigest {
+    subscription_id: String,
+    link_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct TriggerQuery {
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewTriggerSubscription {
+    pub event: TriggerEvent,
+    pub target_url: String,
+    #[serde(default)]
+    pub delivery: HookDelivery,
+    /// Longest a click waits in a digest.
+    pub digest_interval_minutes: Option<i32>,
+    /// Clicks on one link that send its digest right away.
+    pub digest_max_events: Option<i32>,
+}
+
+impl Validate for NewTriggerSubscription {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        errors.require_web_url("targetUrl", &self.target_url);
+
+        if self.delivery == HookDelivery::Digest && self.event != TriggerEvent::NewClick {
+            errors.add("delivery", "Only click hooks can be delivered as digests");
+        }
+        if let Some(minutes) = self.digest_interval_minutes {
+            errors.require_range(
+                "digestIntervalMinutes",
+                minutes.into(),
+                1,
+                MAX_DIGEST_INTERVAL_MINUTES,
+            );
+        }
+        if let Some(events) = self.digest_max_events {
+            errors.require_range("digestMaxEvents", events.into(), 1, MAX_DIGEST_MAX_EVENTS);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct TriggerSubscription {
+    pub id: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub event: String,
+    pub target_url: String,
+    pub delivery: String,
+    pub digest_interval_minutes: Option<i32>,
+    pub digest_max_events: Option<i32>,
+}
+
+fn trigger_limit(query: &TriggerQuery) -> i64 {
+    query
+        .limit
+        .unwrap_or(DEFAULT_TRIGGER_LIMIT)
+        .clamp(1, MAX_TRIGGER_LIMIT)
+}
+
+/// Most recent links owned by the caller, newest first, as Zapier polls them.
+pub async fn new_links_trigger(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Query(query): Query<TriggerQuery>,
+) -> Result<Json<Vec<NewLinkItem>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let links = sqlx::query_as::<_, NewLinkItem>(
+        r#"SELECT id, target_url, created_at FROM links
+        WHERE owner_id = (SELECT id FROM users WHERE email = $1)
+        ORDER BY created_at DESC, id DESC LIMIT $2"#,
+    )
+    .bind(&claims.sub)
+    .bind(trigger_limit(&query))
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(links))
+}
+
+/// Most recent clicks on links owned by the caller, newest first.
+pub async fn new_clicks_trigger(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Query(query): Query<TriggerQuery>,
+) -> Result<Json<Vec<NewClickItem>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let clicks = sqlx::query_as::<_, NewClickItem>(
+        r#"SELECT link_statistics.id::text as id, link_id, referer, user_agent, link_statistics.created_at,
+            sample_rate
+        FROM link_statistics JOIN links ON links.id = link_statistics.link_id
+        WHERE links.owner_id = (SELECT id FROM users WHERE email = $1)
+        ORDER BY link_statistics.id DESC LIMIT $2"#,
+    )
+    .bind(&claims.sub)
+    .bind(trigger_limit(&query))
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(clicks))
+}
+
+#[tracing::instrument(name = "Subscribe REST hook", skip(inner, claims, subscription))]
+pub async fn subscribe_trigger(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Valid(subscription): Valid<NewTriggerSubscription>,
+) -> Result<(StatusCode, Json<TriggerSubscription>), (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let user_id = get_stored_credentials(&claims.sub, &db).await?.id;
+
+    let target_url = Url::parse(&subscription.target_url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or_else(|| (StatusCode::CONFLICT, "url malformed".to_string()))?;
+    require_allowed_host(&db, &settings, &target_url).await?;
+
+    let digest = subscription.delivery == HookDelivery::Digest;
+
+    let subscription = sqlx::query_as::<_, TriggerSubscription>(
+        r#"INSERT INTO trigger_subscriptions
+            (id, user_id, event, target_url, delivery, digest_interval_minutes, digest_max_events)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        returning id, created_at, event, target_url, delivery, digest_interval_minutes, digest_max_events"#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(user_id)
+    .bind(subscription.event.as_str())
+    .bind(target_url.to_string())
+    .bind(subscription.delivery.as_str())
+    .bind(digest.then(|| {
+        subscription
+            .digest_interval_minutes
+            .unwrap_or(DEFAULT_DIGEST_INTERVAL_MINUTES)
+    }))
+    .bind(digest.then(|| {
+        subscription
+            .digest_max_events
+            .unwrap_or(DEFAULT_DIGEST_MAX_EVENTS)
+    }))
+    .fetch_one(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok((StatusCode::CREATED, Json(subscription)))
+}
+
+#[tracing::instrument(name = "Unsubscribe REST hook", skip(inner, claims))]
+pub async fn unsubscribe_trigger(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(subscription_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let deleted = sqlx::query(
+        r#"DELETE FROM trigger_subscriptions
+        WHERE id = $1 AND user_id = (SELECT id FROM users WHERE email = $2)"#,
+    )
+    .bind(subscription_id)
+    .bind(&claims.sub)
+    .execute(&db)
+    .await
+    .map_err(internal_error)?;
+
+    if deleted.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Queue a new link for the owner's REST hooks. Pass the transaction that
+/// creates the link, so the event is sent exactly when the link exists, and
+/// call `wake_outbox` once it is committed.
+pub async fn enqueue_new_link<'e, E: PgExecutor<'e>>(
+    executor: E,
+    link_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"INSERT INTO outbox_events (event, subject) VALUES ($1, $2)"#)
+        .bind(TriggerEvent::NewLink.as_str())
+        .bind(link_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+pub fn wake_outbox() {
+    OUTBOX_WAKER.notify_one();
+}
+
+/// Send queued events for as long as the process runs. Every replica runs
+/// this; events are claimed with row locks, so each goes out once unless a
+/// replica dies while sending it, in which case it is sent again.
+pub async fn run_outbox_dispatcher(db: PgPool) {
+    task_health::register(OUTBOX_DISPATCHER_TASK, OUTBOX_POLL_INTERVAL);
+
+    loop {
+        match dispatch_outbox(&db).await {
+            Ok(0) => {
+                task_health::succeeded(OUTBOX_DISPATCHER_TASK);
+                tokio::select! {
+                    _ = OUTBOX_WAKER.notified() => {}
+                    _ = tokio::time::sleep(OUTBOX_POLL_INTERVAL) => {}
+                }
+            }
+            Ok(_) => task_health::succeeded(OUTBOX_DISPATCHER_TASK),
+            Err(err) => {
+                tracing::error!("Could not dispatch outbox events: {}", err);
+                task_health::failed(OUTBOX_DISPATCHER_TASK, &err);
+                tokio::time::sleep(OUTBOX_POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Send a batch of events, returning how many there were.
+async fn dispatch_outbox(db: &PgPool) -> Result<usize, sqlx::Error> {
+    let mut transaction = db.begin().await?;
+
+    let events = sqlx::query_as::<_, OutboxEvent>(
+        r#"SELECT id, event, subject FROM outbox_events ORDER BY id LIMIT $1 FOR UPDATE SKIP LOCKED"#,
+    )
+    .bind(OUTBOX_BATCH_SIZE)
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    for event in &events {
+        match TriggerEvent::parse(&event.event) {
+            Some(TriggerEvent::NewLink) => {
+                let link = sqlx::query_as::<_, NewLinkItem>(
+                    r#"SELECT id, target_url, created_at FROM links WHERE id = $1"#,
+                )
+                .bind(&event.subject)
+                .fetch_optional(db)
+                .await?;
+
+                // The link may have been deleted since.
+                if let Some(link) = link {
+                    deliver(db, TriggerEvent::NewLink, &event.subject, &link).await;
+                }
+            }
+            _ => tracing::warn!("Dropping unknown outbox event {:?}", event),
+        }
+    }
+
+    let ids: Vec<i32> = events.iter().map(|event| event.id).collect();
+    sqlx::query(r#"DELETE FROM outbox_events WHERE id = ANY($1)"#)
+        .bind(ids)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(events.len())
+}
+
+/// Push a recorded click to the link owner's REST hooks in the background.
+pub fn notify_new_click(db: PgPool, statistic_id: i32) {
+    tokio::spawn(async move {
+        let click = sqlx::query_as::<_, NewClickItem>(
+            r#"SELECT id::text as id, link_id, referer, user_agent, created_at, sample_rate
+            FROM link_statistics WHERE id = $1"#,
+        )
+        .bind(statistic_id)
+        .fetch_one(&db)
+        .await;
+
+        match click {
+            Ok(click) => deliver_click(&db, statistic_id, &click).await,
+            Err(err) => tracing::error!("Could not load click for REST hooks: {}", err),
+        }
+    });
+}
+
+async fn hook_subscriptions(
+    db: &PgPool,
+    event: TriggerEvent,
+    link_id: &str,
+) -> Option<Vec<TriggerSubscription>> {
+    let subscriptions = sqlx::query_as::<_, TriggerSubscription>(
+        r#"SELECT id, created_at, event, target_url, delivery, digest_interval_minutes, digest_max_events
+        FROM trigger_subscriptions
+        WHERE event = $1 AND user_id = (SELECT owner_id FROM links WHERE id = $2)"#,
+    )
+    .bind(event.as_str())
+    .bind(link_id)
+    .fetch_all(db)
+    .await;
+
+    match subscriptions {
+        Ok(subscriptions) => Some(subscriptions),
+        Err(err) => {
+            tracing::error!("Could not load REST hook subscriptions: {}", err);
+            None
+        }
+    }
+}
+
+async fn deliver<T: Serialize>(db: &PgPool, event: TriggerEvent, link_id: &str, payload: &T) {
+    for subscription in hook_subscriptions(db, event, link_id)
+        .await
+        .unwrap_or_default()
+    {
+        let gone = post_hook(&subscription, payload).await;
+        count_hook_delivery(db, link_id).await;
+        if gone {
+            remove_gone_hook(db, &subscription.id).await;
+        }
+    }
+}
+
+/// Send a click right away to hooks that want every click, and add it to
+/// the digest of the others.
+async fn deliver_click(db: &PgPool, statistic_id: i32, click: &NewClickItem) {
+    let subscriptions = hook_subscriptions(db, TriggerEvent::NewClick, &click.link_id).await;
+
+    for subscription in subscriptions.unwrap_or_default() {
+        if subscription.delivery != HookDelivery::Digest.as_str() {
+            let gone = post_hook(&subscription, click).await;
+            count_hook_delivery(db, &click.link_id).await;
+            if gone {
+                remove_gone_hook(db, &subscription.id).await;
+            }
+            continue;
+        }
+
+        if let Err(err) = add_to_digest(db, &subscription, statistic_id, &click.link_id).await {
+            tracing::error!("Could not add click to REST hook digest: {}", err);
+        }
+    }
+}
+
+async fn add_to_digest(
+    db: &PgPool,
+    subscription: &TriggerSubscription,
+    statistic_id: i32,
+    link_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"INSERT INTO trigger_digest_clicks (subscription_id, statistic_id, link_id)
+        VALUES ($1, $2, $3) ON CONFLICT DO NOTHING"#,
+    )
+    .bind(&subscription.id)
+    .bind(statistic_id)
+    .bind(link_id)
+    .execute(db)
+    .await?;
+
+    let pending: i64 = sqlx::query_scalar(
+        r#"SELECT count(*) FROM trigger_digest_clicks WHERE subscription_id = $1 AND link_id = $2"#,
+    )
+    .bind(&subscription.id)
+    .bind(link_id)
+    .fetch_one(db)
+    .await?;
+
+    let max_events = subscription
+        .digest_max_events
+        .unwrap_or(DEFAULT_DIGEST_MAX_EVENTS);
+    if pending >= max_events.into() {
+        send_digest(db, &subscription.id, link_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Send the digests whose oldest click waited the subscription's interval.
+pub async fn run_trigger_digest_job(db: PgPool) {
+    jobs::run_periodically(db, "trigger_digests", DIGEST_JOB_INTERVAL, send_due_digests).await
+}
+
+async fn send_due_digests(db: PgPool) -> Result<(), sqlx::Error> {
+    let due = sqlx::query_as::<_, DueDigest>(
+        r#"SELECT trigger_digest_clicks.subscription_id, trigger_digest_clicks.link_id
+        FROM trigger_digest_clicks
+        JOIN trigger_subscriptions ON trigger_subscriptions.id = trigger_digest_clicks.subscription_id
+        GROUP BY trigger_digest_clicks.subscription_id, trigger_digest_clicks.link_id,
+            trigger_subscriptions.digest_interval_minutes
+        HAVING min(trigger_digest_clicks.created_at)
+            <= localtimestamp - make_interval(mins => coalesce(trigger_subscriptions.digest_interval_minutes, $1))"#,
+    )
+    .bind(DEFAULT_DIGEST_INTERVAL_MINUTES)
+    .fetch_all(&db)
+    .await?;
+
+    for digest in due {
+        send_digest(&db, &digest.subscription_id, &digest.link_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Send and clear the pending clicks of one link. The clicks are only
+/// cleared once the request was made, so a digest interrupted by a crash is
+/// sent again, and two replicas sending the same digest send it once.
+async fn send_digest(db: &PgPool, subscription_id: &str, link_id: &str) -> Result<(), sqlx::Error> {
+    let mut transaction = db.begin().await?;
+
+    let clicks = sqlx::query_as::<_, NewClickItem>(
+        r#"WITH digest AS (
+            DELETE FROM trigger_digest_clicks WHERE subscription_id = $1 AND link_id = $2
+            RETURNING statistic_id
+        )
+        SELECT id::text as id, link_id, referer, user_agent, created_at, sample_rate
+        FROM link_statistics WHERE id IN (SELECT statistic_id FROM digest) ORDER BY id"#,
+    )
+    .bind(subscription_id)
+    .bind(link_id)
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    if clicks.is_empty() {
+        return Ok(());
+    }
+
+    let subscription = sqlx::query_as::<_, TriggerSubscription>(
+        r#"SELECT id, created_at, event, target_url, delivery, digest_interval_minutes, digest_max_events
+        FROM trigger_subscriptions WHERE id = $1"#,
+    )
+    .bind(subscription_id)
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    let digest = ClickDigest {
+        link_id: link_id.to_string(),
+        estimated_clicks: clicks
+            .iter()
+            .map(|click| i64::from(click.sample_rate))
+            .sum(),
+        clicks,
+    };
+
+    let gone = post_hook(&subscription, &digest).await;
+    count_hook_delivery(&mut *transaction, link_id).await;
+    if gone {
+        // Removed in the transaction holding the digest rows, which would
+        // otherwise block the delete cascading to them.
+        remove_gone_hook(&mut *transaction, &subscription.id).await;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Send one payload to a hook. Returns whether the hook is gone for good.
+async fn post_hook<T: Serialize>(subscription: &TriggerSubscription, payload: &T) -> bool {
+    // Addresses never go through the resolver, so they are checked here,
+    // for hooks subscribed before they were checked on subscribing.
+    if !Url::parse(&subscription.target_url).is_ok_and(|url| is_fetchable(&url)) {
+        tracing::warn!(
+            "REST hook {} points at an internal address",
+            subscription.id
+        );
+        return false;
+    }
+
+    let response = HOOK_CLIENT
+        .post(&subscription.target_url)
+        .json(payload)
+        .send()
+        .await;
+
+    match response {
+        // Zapier answers 410 once the zap is turned off.
+        Ok(response) if response.status() == StatusCode::GONE => return true,
+        Ok(response) if !response.status().is_success() => tracing::warn!(
+            "REST hook {} answered {}",
+            subscription.id,
+            response.status()
+        ),
+        Ok(_) => {}
+        Err(err) => tracing::warn!("REST hook {} failed: {}", subscription.id, err),
+    }
+
+    false
+}
+
+/// Count a request made to a hook for a link against the link's
+/// organization, which is billed for it. Hooks for links outside any
+/// organization are not counted.
+async fn count_hook_delivery<'e, E: PgExecutor<'e>>(executor: E, link_id: &str) {
+    if let Err(err) = sqlx::query(
+        r#"INSERT INTO hook_deliveries_daily (organization_id, day, amount)
+        SELECT organization_id, CURRENT_DATE, 1 FROM links
+        WHERE id = $1 AND organization_id IS NOT NULL
+        ON CONFLICT (organization_id, day) DO UPDATE
+        SET amount = hook_deliveries_daily.amount + 1"#,
+    )
+    .bind(link_id)
+    .execute(executor)
+    .await
+    {
+        tracing::error!("Could not count REST hook delivery: {}", err);
+    }
+}
+
+async fn remove_gone_hook<'e, E: PgExecutor<'e>>(executor: E, subscription_id: &str) {
+    if let Err(err) = sqlx::query(r#"DELETE FROM trigger_subscriptions WHERE id = $1"#)
+        .bind(subscription_id)
+        .execute(executor)
+        .await
+    {
+        tracing::error!("Could not remove gone REST hook: {}", err);
+    }
+}
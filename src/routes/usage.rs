@@ -0,0 +1,242 @@
+//! Monthly link quota. Owners are warned as they approach it and can ask
+//! when they will run out, before creating links is refused outright.
+
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::configuration::Settings;
+use crate::email::EmailClient;
+use crate::i18n;
+use crate::routes::{get_stored_credentials, User};
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum_prometheus::metrics::counter;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+
+/// How far back link creation is averaged when forecasting.
+const FORECAST_WINDOW_DAYS: i64 = 7;
+
+/// The owner crossed a warning threshold with the link just created.
+#[derive(Debug)]
+pub struct QuotaWarning {
+    pub owner_id: String,
+    pub threshold: i32,
+    pub used: i64,
+    pub quota: i64,
+}
+
+#[derive(FromRow)]
+struct UsageRow {
+    used: i64,
+    recent: i64,
+    period_start: NaiveDateTime,
+    period_end: NaiveDateTime,
+    now: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageForecast {
+    /// Links allowed this month, or `None` when unlimited.
+    pub quota: Option<i64>,
+    pub used: i64,
+    pub period_start: NaiveDateTime,
+    pub period_end: NaiveDateTime,
+    /// Links created per day over the last week.
+    pub daily_rate: f64,
+    /// Links created by the end of the month at that rate.
+    pub projected: i64,
+    /// When the quota runs out at that rate, or `None` when it lasts until
+    /// the month ends.
+    pub exhausted_at: Option<NaiveDateTime>,
+}
+
+/// Count a link the transaction just created against its owner's quota,
+/// refusing it once the quota is used up. Returns the warning to send when
+/// the link crosses a threshold the owner was not warned about this month.
+pub async fn charge_link_quota(
+    transaction: &mut Transaction<'_, Postgres>,
+    settings: &Settings,
+    link_id: &str,
+) -> Result<Option<QuotaWarning>, (StatusCode, String)> {
+    let Some(quota) = settings.monthly_link_quota else {
+        return Ok(None);
+    };
+
+    // Locking the owner makes concurrent requests of one owner take turns,
+    // so they cannot both take the last link.
+    let owner_id: Option<String> = sqlx::query_scalar(
+        r#"SELECT users.id FROM links JOIN users ON users.id = links.owner_id
+        WHERE links.id = $1 FOR UPDATE OF users"#,
+    )
+    .bind(link_id)
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+
+    // Anonymous links count against nobody.
+    let Some(owner_id) = owner_id else {
+        return Ok(None);
+    };
+
+    let used: i64 = sqlx::query_scalar(
+        r#"SELECT count(*) FROM links
+        WHERE owner_id = $1 AND created_at >= date_trunc('month', localtimestamp)"#,
+    )
+    .bind(&owner_id)
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+
+    if used > quota {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Link quota exceeded".to_string(),
+        ));
+    }
+
+    let crossed: Vec<i32> = settings
+        .quota_warning_thresholds
+        .iter()
+        .copied()
+        .filter(|threshold| used * 100 >= i64::from(*threshold) * quota)
+        .collect();
+    if crossed.is_empty() {
+        return Ok(None);
+    }
+
+    let threshold: Option<i32> = sqlx::query_scalar(
+        r#"WITH warned AS (
+            INSERT INTO quota_warnings (user_id, period, threshold)
+            SELECT $1, date_trunc('month', localtimestamp)::date, unnest($2::int[])
+            ON CONFLICT DO NOTHING RETURNING threshold
+        )
+        SELECT max(threshold) FROM warned"#,
+    )
+    .bind(&owner_id)
+    .bind(crossed)
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(threshold.map(|threshold| QuotaWarning {
+        owner_id,
+        threshold,
+        used,
+        quota,
+    }))
+}
+
+/// Tell the owner they are running out of links, in the background so a
+/// slow email never holds up the link. Call once the link is committed.
+pub fn send_quota_warning(
+    db: PgPool,
+    email_client: EmailClient,
+    settings: &Settings,
+    warning: QuotaWarning,
+) {
+    counter!("link_quota_warnings", "threshold" => warning.threshold.to_string()).increment(1);
+    tracing::info!("Link quota warning: {:?}", warning);
+
+    let Some(template_id) = settings.quota_warning_template_id.clone() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let user = sqlx::query_as::<_, User>(r#"SELECT * FROM users WHERE id = $1"#)
+            .bind(&warning.owner_id)
+            .fetch_one(&db)
+            .await;
+        let user = match user {
+            Ok(user) => user,
+            Err(err) => {
+                tracing::error!("Could not load user for quota warning: {}", err);
+                return;
+            }
+        };
+
+        let locale = user.locale.as_deref().unwrap_or(i18n::DEFAULT_LOCALE);
+
+        let mut template_model = HashMap::new();
+        template_model.insert("locale".to_owned(), locale.to_owned());
+        template_model.insert("product_name".to_owned(), "Groupify".to_owned());
+        template_model.insert("threshold".to_owned(), warning.threshold.to_string());
+        template_model.insert("used".to_owned(), warning.used.to_string());
+        template_model.insert("quota".to_owned(), warning.quota.to_string());
+        template_model.insert("support_email".to_owned(), "admin@groupify.dev".to_owned());
+
+        let sent = email_client
+            .send_email(&user.email, "quota-warning", template_model, &template_id)
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = sent {
+            tracing::error!("Could not send quota warning: {}", err);
+        }
+    });
+}
+
+/// How much of this month's quota the caller used, and when it runs out if
+/// they keep creating links as they did over the last week.
+#[tracing::instrument(name = "Forecast usage", skip(inner, claims))]
+pub async fn usage_forecast(
+    State(inner): State<InnerState>,
+    claims: Claims,
+) -> Result<Json<UsageForecast>, (StatusCode, String)> {
+    let InnerState { db, settings, .. } = inner;
+
+    let user = get_stored_credentials(&claims.sub, &db).await?;
+
+    let usage = sqlx::query_as::<_, UsageRow>(
+        r#"SELECT
+            count(*) FILTER (WHERE created_at >= date_trunc('month', localtimestamp)) AS used,
+            count(*) FILTER (WHERE created_at >= localtimestamp - make_interval(days => $2::int)) AS recent,
+            date_trunc('month', localtimestamp) AS period_start,
+            date_trunc('month', localtimestamp) + interval '1 month' AS period_end,
+            localtimestamp AS now
+        FROM links WHERE owner_id = $1"#,
+    )
+    .bind(&user.id)
+    .bind(FORECAST_WINDOW_DAYS as i32)
+    .fetch_one(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(forecast(settings.monthly_link_quota, &usage)))
+}
+
+fn forecast(quota: Option<i64>, usage: &UsageRow) -> UsageForecast {
+    const SECONDS_PER_DAY: f64 = 86_400.0;
+
+    let daily_rate = usage.recent as f64 / FORECAST_WINDOW_DAYS as f64;
+    let seconds_left = (usage.period_end - usage.now).num_seconds() as f64;
+    let projected = usage.used + (daily_rate * seconds_left / SECONDS_PER_DAY).round() as i64;
+
+    let exhausted_at = quota.and_then(|quota| {
+        let remaining = quota - usage.used;
+        if remaining <= 0 {
+            return Some(usage.now);
+        }
+        if daily_rate == 0.0 {
+            return None;
+        }
+
+        let seconds = remaining as f64 / daily_rate * SECONDS_PER_DAY;
+        Some(usage.now + chrono::Duration::seconds(seconds as i64))
+            .filter(|exhausted_at| *exhausted_at < usage.period_end)
+    });
+
+    UsageForecast {
+        quota,
+        used: usage.used,
+        period_start: usage.period_start,
+        period_end: usage.period_end,
+        daily_rate,
+        projected,
+        exhausted_at,
+    }
+}
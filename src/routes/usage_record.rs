@@ -0,0 +1,269 @@
+//! Monthly usage of every organization, for billing systems to invoice.
+//! Once a month is over, a record is generated per organization for each
+//! metric and kept as it was, so links deleted or clicks purged later never
+//! change what was billed.
+
+use crate::authentication::{AdminUser, Claims};
+use crate::casing::Json;
+use crate::jobs;
+use crate::pagination::Page;
+use crate::routes::require_organization_role;
+use crate::utils::internal_error;
+use crate::InnerState;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// How often the job checks for a month without records.
+const USAGE_RECORDS_JOB_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UsageMetric {
+    /// Links the organization created in the month.
+    LinksCreated,
+    /// Redirects its links served in the month, estimated for sampled ones.
+    Redirects,
+    /// Requests made to REST hooks for its links in the month.
+    HookDeliveries,
+    /// Bytes its links and export archives took when the month was over.
+    StorageBytes,
+}
+
+impl UsageMetric {
+    pub const ALL: [UsageMetric; 4] = [
+        UsageMetric::LinksCreated,
+        UsageMetric::Redirects,
+        UsageMetric::HookDeliveries,
+        UsageMetric::StorageBytes,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UsageMetric::LinksCreated => "links_created",
+            UsageMetric::Redirects => "redirects",
+            UsageMetric::HookDeliveries => "hook_deliveries",
+            UsageMetric::StorageBytes => "storage_bytes",
+        }
+    }
+
+    /// The quantity of the metric each organization used in the month
+    /// starting at `$1` and ending before `$2`.
+    fn query(self) -> &'static str {
+        match self {
+            UsageMetric::LinksCreated => {
+                r#"SELECT organization_id, count(*) AS quantity FROM links
+                WHERE organization_id IS NOT NULL AND created_at >= $1 AND created_at < $2
+                GROUP BY organization_id"#
+            }
+            UsageMetric::Redirects => {
+                r#"SELECT links.organization_id, sum(clicks.amount)::bigint AS quantity
+                FROM (
+                    SELECT link_id, amount FROM link_statistics_daily
+                    WHERE day >= $1::date AND day < $2::date
+                    UNION ALL
+                    SELECT link_id, sample_rate AS amount FROM link_statistics
+                    WHERE NOT rolled_up AND created_at >= $1 AND created_at < $2
+                ) AS clicks
+                JOIN links ON links.id = clicks.link_id
+                WHERE links.organization_id IS NOT NULL
+                GROUP BY links.organization_id"#
+            }
+            UsageMetric::HookDeliveries => {
+                r#"SELECT organization_id, sum(amount)::bigint AS quantity FROM hook_deliveries_daily
+                WHERE day >= $1::date AND day < $2::date
+                GROUP BY organization_id"#
+            }
+            UsageMetric::StorageBytes => {
+                r#"SELECT organization_id, sum(bytes)::bigint AS quantity FROM (
+                    SELECT organization_id, pg_column_size(links.*) AS bytes FROM links
+                    WHERE organization_id IS NOT NULL AND created_at < $2
+                    UNION ALL
+                    SELECT organization_id, coalesce(octet_length(archive), 0) AS bytes
+                    FROM organization_exports WHERE created_at < $2
+                ) AS stored
+                GROUP BY organization_id"#
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRecord {
+    pub organization_id: String,
+    /// First day of the month the record is for.
+    pub period: NaiveDate,
+    pub metric: String,
+    pub quantity: i64,
+    pub generated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageExportQuery {
+    /// Month to export as `YYYY-MM`, the last one over by default.
+    pub period: Option<String>,
+    #[serde(default)]
+    pub format: UsageExportFormat,
+}
+
+pub async fn run_usage_records_job(db: PgPool) {
+    jobs::run_periodically(
+        db,
+        "usage_records",
+        USAGE_RECORDS_JOB_INTERVAL,
+        generate_usage_records,
+    )
+    .await
+}
+
+fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1)
+        .expect("The first day of a month should always exist")
+}
+
+fn last_closed_period() -> NaiveDate {
+    month_start(Utc::now().date_naive()) - Months::new(1)
+}
+
+/// Record last month's usage of every organization that existed in it.
+/// Months already recorded are left as they are, so a run after records
+/// were sent to billing never changes them.
+async fn generate_usage_records(db: PgPool) -> Result<(), sqlx::Error> {
+    let period = last_closed_period();
+    let period_start = period.and_hms_opt(0, 0, 0).unwrap_or_default();
+    let period_end = (period + Months::new(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap_or_default();
+
+    let mut transaction = db.begin().await?;
+
+    for metric in UsageMetric::ALL {
+        let generated = sqlx::query(&format!(
+            r#"INSERT INTO usage_records (organization_id, period, metric, quantity)
+            SELECT organizations.id, $1::date, $3, coalesce(used.quantity, 0)
+            FROM organizations
+            LEFT JOIN ({}) AS used ON used.organization_id = organizations.id
+            WHERE organizations.created_at < $2
+            ON CONFLICT (organization_id, period, metric) DO NOTHING"#,
+            metric.query()
+        ))
+        .bind(period_start)
+        .bind(period_end)
+        .bind(metric.as_str())
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+
+        if generated > 0 {
+            tracing::info!(
+                "Recorded {} of {} organizations for {}",
+                metric.as_str(),
+                generated,
+                period.format("%Y-%m")
+            );
+        }
+    }
+
+    transaction.commit().await
+}
+
+fn parse_period(period: Option<&str>) -> Result<NaiveDate, (StatusCode, String)> {
+    match period {
+        Some(period) => {
+            NaiveDate::parse_from_str(&format!("{}-01", period), "%Y-%m-%d").map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    "period should be a month as YYYY-MM".to_string(),
+                )
+            })
+        }
+        None => Ok(last_closed_period()),
+    }
+}
+
+/// One line per record, for billing systems importing spreadsheets.
+fn usage_records_csv(records: &[UsageRecord]) -> String {
+    let mut csv = String::from("organization_id,period,metric,quantity\n");
+    for record in records {
+        csv.push_str(&format!(
+            "\"{}\",{},{},{}\n",
+            record.organization_id.replace('"', "\"\""),
+            record.period.format("%Y-%m"),
+            record.metric,
+            record.quantity
+        ));
+    }
+    csv
+}
+
+/// The usage records of an organization, latest month first.
+pub async fn organization_usage_records(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Path(organization_id): Path<String>,
+) -> Result<Json<Page<UsageRecord>>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    require_organization_role(&db, &organization_id, &claims, "admin").await?;
+
+    let records = sqlx::query_as::<_, UsageRecord>(
+        r#"SELECT * FROM usage_records WHERE organization_id = $1
+        ORDER BY period DESC, metric"#,
+    )
+    .bind(&organization_id)
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(Page::complete(records)))
+}
+
+/// Every organization's usage records of a month, as JSON or CSV. Empty
+/// until the job recorded the month.
+pub async fn export_usage_records(
+    State(inner): State<InnerState>,
+    _admin: AdminUser,
+    Query(query): Query<UsageExportQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let period = parse_period(query.period.as_deref())?;
+
+    let records = sqlx::query_as::<_, UsageRecord>(
+        r#"SELECT * FROM usage_records WHERE period = $1 ORDER BY organization_id, metric"#,
+    )
+    .bind(period)
+    .fetch_all(&db)
+    .await
+    .map_err(internal_error)?;
+
+    if query.format == UsageExportFormat::Json {
+        return Ok(Json(Page::complete(records)).into_response());
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/csv; charset=utf-8")
+        .header(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"usage-{}.csv\"",
+                period.format("%Y-%m")
+            ),
+        )
+        .body(Body::from(usage_records_csv(&records)))
+        .expect("This response should always be constructable"))
+}
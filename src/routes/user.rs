@@ -1,12 +1,12 @@
+use crate::authentication::compute_password_hash;
 use crate::utils::internal_error;
 use axum::http::StatusCode;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sha3::Digest;
-use sqlx::{Executor, FromRow, PgPool, Postgres, Row, Transaction};
+use sqlx::{Executor, FromRow, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, FromRow, Clone)]
 pub struct User {
     pub id: Option<String>,
     pub aud: Option<String>,
@@ -41,6 +41,8 @@ pub struct User {
     pub is_sso_user: Option<bool>,
     pub deleted_at: Option<NaiveDateTime>,
     pub display_name: Option<String>,
+    /// Language emails are sent in.
+    pub locale: Option<String>,
 }
 
 #[tracing::instrument(name = "Saving new user in the database", skip(user, transaction))]
@@ -50,25 +52,18 @@ pub async fn create_user(
 ) -> Result<String, (StatusCode, String)> {
     let uuid = Uuid::new_v4().to_string();
 
-    tracing::debug!(
-        "user id {} \
-         user email {}\
-         user password {:?}",
-        uuid,
-        user.email,
-        user.encrypted_password
-    );
+    tracing::debug!("user id {} user email {}", uuid, user.email);
 
-    let encrypted_password = sha3::Sha3_256::digest(user.encrypted_password.as_bytes());
-
-    let encrypted_password = format!("{:x}", encrypted_password);
+    // Hashed the way `validate_credentials` checks it.
+    let encrypted_password = compute_password_hash(user.encrypted_password)?;
 
     let query = sqlx::query_as::<_, User>(
-        r#"INSERT INTO users (id, email, encrypted_password) values($1, $2, $3) returning *"#,
+        r#"INSERT INTO users (id, email, encrypted_password, locale) values($1, $2, $3, $4) returning *"#,
     )
     .bind(&uuid)
     .bind(user.email)
-    .bind(encrypted_password);
+    .bind(encrypted_password)
+    .bind(user.locale);
 
     transaction.execute(query).await.map_err(internal_error)?;
     Ok(uuid)
@@ -100,7 +95,7 @@ pub async fn get_confirmation_token_from_user(
         .map(|user| user.id)
         .map_err(internal_error)?;
 
-    Ok(id.unwrap_or_else(|| String::new()))
+    Ok(id.unwrap_or_default())
 }
 
 #[tracing::instrument(name = "Get user id from token", skip(confirmation_token, pool))]
@@ -115,5 +110,5 @@ pub async fn get_password_confirmation_token_from_user(
         .map(|user| user.id)
         .map_err(internal_error)?;
 
-    Ok(id.unwrap_or_else(|| String::new()))
+    Ok(id.unwrap_or_default())
 }
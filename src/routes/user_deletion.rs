@@ -0,0 +1,254 @@
+use crate::authentication::Claims;
+use crate::casing::Json;
+use crate::jobs;
+use crate::link_changes::announce_link_changes;
+use crate::routes::get_stored_credentials;
+use crate::utils::internal_error;
+use crate::validation::{Valid, Validate, ValidationErrors};
+use crate::InnerState;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+
+/// How long a scheduled deletion can still be cancelled.
+const DELETION_GRACE_PERIOD_DAYS: i32 = 30;
+
+/// How often the background job looks for deletions that are due.
+const DELETION_JOB_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkDisposition {
+    /// Stop redirecting the user's links.
+    Disable,
+    /// Hand the user's links over to another account.
+    Transfer,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletionRequest {
+    pub links: LinkDisposition,
+    /// Email of the account receiving the links when `links` is `transfer`.
+    pub transfer_to: Option<String>,
+}
+
+impl Validate for DeletionRequest {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if self.links == LinkDisposition::Transfer && self.transfer_to.is_none() {
+            errors.add("transferTo", "is required to transfer links");
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct UserDeletion {
+    pub user_id: String,
+    pub requested_at: Option<NaiveDateTime>,
+    pub scheduled_for: NaiveDateTime,
+    pub link_disposition: String,
+    pub transfer_to: Option<String>,
+    pub status: String,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+#[tracing::instrument(name = "Schedule account deletion", skip(inner, claims, request))]
+pub async fn delete_current_user(
+    State(inner): State<InnerState>,
+    claims: Claims,
+    Valid(request): Valid<DeletionRequest>,
+) -> Result<(StatusCode, Json<UserDeletion>), (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let user_id = get_stored_credentials(&claims.sub, &db).await?.id;
+
+    let transfer_to = match (request.links, request.transfer_to) {
+        (LinkDisposition::Transfer, Some(email)) => {
+            let recipient = get_stored_credentials(&email, &db).await.map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    "Unknown transferTo account".to_string(),
+                )
+            })?;
+            if recipient.id == user_id {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "Links cannot be transferred to the account being deleted".to_string(),
+                ));
+            }
+            recipient.id
+        }
+        // Validation already rejected transfers without a recipient.
+        (LinkDisposition::Transfer, None) | (LinkDisposition::Disable, _) => None,
+    };
+
+    let link_disposition = match request.links {
+        LinkDisposition::Disable => "disable",
+        LinkDisposition::Transfer => "transfer",
+    };
+
+    let deletion = sqlx::query_as::<_, UserDeletion>(
+        r#"INSERT INTO user_deletions (user_id, scheduled_for, link_disposition, transfer_to)
+        VALUES ($1, CURRENT_TIMESTAMP + make_interval(days => $2), $3, $4)
+        ON CONFLICT (user_id) DO UPDATE SET requested_at = CURRENT_TIMESTAMP,
+            scheduled_for = excluded.scheduled_for, link_disposition = excluded.link_disposition,
+            transfer_to = excluded.transfer_to, status = 'scheduled'
+        WHERE user_deletions.status <> 'completed'
+        returning *"#,
+    )
+    .bind(&user_id)
+    .bind(DELETION_GRACE_PERIOD_DAYS)
+    .bind(link_disposition)
+    .bind(transfer_to)
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| {
+        (
+            StatusCode::CONFLICT,
+            "Account is already deleted".to_string(),
+        )
+    })?;
+
+    Ok((StatusCode::ACCEPTED, Json(deletion)))
+}
+
+#[tracing::instrument(name = "Cancel account deletion", skip(inner, claims))]
+pub async fn cancel_user_deletion(
+    State(inner): State<InnerState>,
+    claims: Claims,
+) -> Result<Json<UserDeletion>, (StatusCode, String)> {
+    let InnerState { db, .. } = inner;
+
+    let user_id = get_stored_credentials(&claims.sub, &db).await?.id;
+
+    let deletion = sqlx::query_as::<_, UserDeletion>(
+        r#"UPDATE user_deletions SET status = 'cancelled'
+        WHERE user_id = $1 AND status = 'scheduled' returning *"#,
+    )
+    .bind(user_id)
+    .fetch_optional(&db)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "No scheduled deletion".to_string()))?;
+
+    Ok(Json(deletion))
+}
+
+/// Periodically carry out deletions whose grace period has ended.
+pub async fn run_user_deletion_job(db: PgPool) {
+    jobs::run_periodically(db, "user_deletion", DELETION_JOB_INTERVAL, process_due_deletions).await
+}
+
+async fn process_due_deletions(db: PgPool) -> Result<(), sqlx::Error> {
+    let due = sqlx::query_as::<_, UserDeletion>(
+        r#"SELECT * FROM user_deletions WHERE status = 'scheduled' AND scheduled_for <= CURRENT_TIMESTAMP"#,
+    )
+    .fetch_all(&db)
+    .await?;
+
+    // One deletion failing must not hold up the others, which it would on
+    // every run until fixed.
+    for deletion in due {
+        let erased = async {
+            let mut transaction = db.begin().await?;
+            erase_user(&mut transaction, &deletion).await?;
+            transaction.commit().await
+        }
+        .await;
+
+        match erased {
+            Ok(()) => tracing::info!("Deleted account {}", deletion.user_id),
+            Err(err) => tracing::error!("Could not delete account {}: {}", deletion.user_id, err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Dispose of the user's links as requested, drop their personal content
+/// and overwrite every identifying column on the user row. The row itself
+/// stays so foreign keys from statistics and audit history keep resolving,
+/// which also means nothing cascades from it: every table holding personal
+/// data of the user has to be emptied of it here.
+#[tracing::instrument(name = "Erase user", skip(transaction, deletion))]
+async fn erase_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    deletion: &UserDeletion,
+) -> Result<(), sqlx::Error> {
+    if deletion.link_disposition == "transfer" && deletion.transfer_to.is_some() {
+        sqlx::query(r#"UPDATE links SET owner_id = $1 WHERE owner_id = $2"#)
+            .bind(&deletion.transfer_to)
+            .bind(&deletion.user_id)
+            .execute(&mut **transaction)
+            .await?;
+    } else {
+        let disabled: Vec<String> = sqlx::query_scalar(
+            r#"UPDATE links SET state = CASE WHEN state = 'deleted' THEN state ELSE 'disabled' END,
+            disabled_at = coalesce(disabled_at, CURRENT_TIMESTAMP), owner_id = NULL
+            WHERE owner_id = $1 RETURNING id"#,
+        )
+        .bind(&deletion.user_id)
+        .fetch_all(&mut **transaction)
+        .await?;
+
+        // Replicas stop redirecting from their snapshot and cache on commit.
+        announce_link_changes(&mut **transaction, &disabled).await?;
+    }
+
+    for statement in [
+        r#"DELETE FROM channels WHERE user_id = $1"#,
+        r#"DELETE FROM channels WHERE group_id IN (SELECT id FROM groups WHERE user_id = $1)"#,
+        r#"DELETE FROM group_events WHERE group_id IN (SELECT id FROM groups WHERE user_id = $1)"#,
+        r#"DELETE FROM group_playlists WHERE group_id IN (SELECT id FROM groups WHERE user_id = $1)"#,
+        r#"DELETE FROM groups WHERE user_id = $1"#,
+        r#"DELETE FROM organization_members WHERE user_id = $1"#,
+        r#"DELETE FROM telegram_accounts WHERE user_id = $1"#,
+        r#"DELETE FROM telegram_link_codes WHERE user_id = $1"#,
+        r#"DELETE FROM trigger_subscriptions WHERE user_id = $1"#,
+        r#"DELETE FROM spotify_accounts WHERE user_id = $1"#,
+        r#"DELETE FROM spotify_oauth_states WHERE user_id = $1"#,
+        r#"DELETE FROM device_authorizations WHERE user_id = $1"#,
+        r#"DELETE FROM sessions WHERE user_id = $1"#,
+        r#"DELETE FROM oauth_identities WHERE user_id = $1"#,
+        r#"DELETE FROM organization_invitations WHERE email = (SELECT email FROM users WHERE id = $1)"#,
+        r#"DELETE FROM scim_group_members WHERE user_id = $1"#,
+        r#"DELETE FROM scim_users WHERE user_id = $1"#,
+        r#"DELETE FROM saml_identities WHERE user_id = $1"#,
+        r#"DELETE FROM user_devices WHERE user_id = $1"#,
+    ] {
+        sqlx::query(statement)
+            .bind(&deletion.user_id)
+            .execute(&mut **transaction)
+            .await?;
+    }
+
+    sqlx::query(
+        r#"UPDATE users SET
+        email = 'deleted-' || id || '@deleted.invalid',
+        encrypted_password = '', aud = NULL, role = NULL,
+        confirmation_token = NULL, recovery_token = NULL,
+        email_change_token_new = NULL, email_change = NULL, email_change_token_current = NULL,
+        phone = NULL, phone_change = NULL, phone_change_token = NULL,
+        reauthentication_token = NULL, raw_app_meta_data = NULL, raw_user_meta_data = NULL,
+        display_name = NULL, last_sign_in_at = NULL,
+        deleted_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1"#,
+    )
+    .bind(&deletion.user_id)
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query(
+        r#"UPDATE user_deletions SET status = 'completed', completed_at = CURRENT_TIMESTAMP WHERE user_id = $1"#,
+    )
+    .bind(&deletion.user_id)
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
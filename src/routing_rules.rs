@@ -0,0 +1,170 @@
+//! Per-link rules sending some visitors elsewhere than the link's target,
+//! by country, device, language, time of day or query parameters.
+//!
+//! A link's rules are tried in order and the first whose conditions all
+//! hold picks the target; within a condition listing several values, any
+//! of them matches. Visitors no rule matches go to the link's own target.
+
+use crate::i18n;
+
+use axum::http::header::USER_AGENT;
+use axum::http::HeaderMap;
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingRule {
+    /// Always holds when empty, which makes a catch-all rule.
+    #[serde(default)]
+    pub when: Conditions,
+    pub target_url: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Conditions {
+    /// ISO 3166 country codes, as the CDN reports them in `COUNTRY_HEADER`.
+    /// Visitors whose country is unknown match none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub country: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub device: Vec<Device>,
+    /// Primary language subtags, such as `pt`, of the language the visitor
+    /// prefers most.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub language: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<TimeWindow>,
+    /// Query parameters the short link must be followed with, and their
+    /// values.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub query: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Device {
+    Mobile,
+    Tablet,
+    Desktop,
+}
+
+/// A time of day range in UTC, from inclusive to exclusive. Wraps past
+/// midnight when `to` is earlier than `from`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TimeWindow {
+    pub from: NaiveTime,
+    pub to: NaiveTime,
+}
+
+impl TimeWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            self.from <= time || time < self.to
+        }
+    }
+}
+
+/// What the rules can tell about the visitor following a link.
+pub struct Visitor<'a> {
+    pub country: Option<&'a str>,
+    pub device: Device,
+    pub language: Option<String>,
+    pub time: NaiveTime,
+    pub query: Vec<(String, String)>,
+}
+
+impl<'a> Visitor<'a> {
+    pub fn new(headers: &HeaderMap, raw_query: Option<&str>, country: Option<&'a str>) -> Self {
+        let language = i18n::language_preferences(headers)
+            .into_iter()
+            .find(|tag| *tag != "*")
+            .and_then(|tag| tag.split(['-', '_']).next())
+            .map(|primary| primary.to_ascii_lowercase());
+
+        let query = raw_query
+            .map(|query| {
+                url::form_urlencoded::parse(query.as_bytes())
+                    .into_owned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            country,
+            device: device_of(
+                headers
+                    .get(USER_AGENT)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default(),
+            ),
+            language,
+            time: chrono::Utc::now().time(),
+            query,
+        }
+    }
+}
+
+/// Tell devices apart by the tokens browsers put in their user agent.
+/// Android tablets are the Android devices not claiming to be mobile.
+fn device_of(user_agent: &str) -> Device {
+    let user_agent = user_agent.to_ascii_lowercase();
+
+    if user_agent.contains("ipad")
+        || user_agent.contains("tablet")
+        || (user_agent.contains("android") && !user_agent.contains("mobile"))
+    {
+        Device::Tablet
+    } else if user_agent.contains("mobi") || user_agent.contains("iphone") {
+        Device::Mobile
+    } else {
+        Device::Desktop
+    }
+}
+
+impl Conditions {
+    fn hold_for(&self, visitor: &Visitor) -> bool {
+        let country = self.country.is_empty()
+            || visitor.country.is_some_and(|country| {
+                self.country
+                    .iter()
+                    .any(|wanted| wanted.eq_ignore_ascii_case(country))
+            });
+        let device = self.device.is_empty() || self.device.contains(&visitor.device);
+        let language = self.language.is_empty()
+            || visitor.language.as_deref().is_some_and(|language| {
+                self.language
+                    .iter()
+                    .any(|wanted| wanted.eq_ignore_ascii_case(language))
+            });
+        let time = self.time.is_none_or(|window| window.contains(visitor.time));
+        let query = self.query.iter().all(|(name, value)| {
+            visitor
+                .query
+                .iter()
+                .any(|(given_name, given_value)| given_name == name && given_value == value)
+        });
+
+        country && device && language && time && query
+    }
+}
+
+/// The first rule holding for the visitor and its position, if any.
+pub fn matching_rule<'r>(
+    rules: &'r [RoutingRule],
+    visitor: &Visitor,
+) -> Option<(usize, &'r RoutingRule)> {
+    rules
+        .iter()
+        .enumerate()
+        .find(|(_, rule)| rule.when.hold_for(visitor))
+}
+
+/// The target of the first rule holding for the visitor, if any.
+pub fn route<'r>(rules: &'r [RoutingRule], visitor: &Visitor) -> Option<&'r str> {
+    matching_rule(rules, visitor).map(|(_, rule)| rule.target_url.as_str())
+}
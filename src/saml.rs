@@ -0,0 +1,890 @@
+//! SAML 2.0 single sign-on, for organizations whose identity provider does
+//! not speak OpenID Connect. We are the service provider: logins start with
+//! an unsigned `AuthnRequest` sent with the HTTP-Redirect binding, and the
+//! identity provider posts a signed `Response` back to the assertion
+//! consumer service.
+//!
+//! Responses are checked against the certificate the organization
+//! configured, never one they carry themselves. Signatures are XML-DSig
+//! with exclusive canonicalization, RSA or ECDSA keys and SHA-2 digests,
+//! which every current identity provider produces. Encrypted assertions are
+//! not supported.
+
+use crate::der;
+use crate::png;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use ring::digest;
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use roxmltree::{Document, Node, NodeId, ParsingOptions};
+use std::collections::{BTreeMap, BTreeSet};
+use url::Url;
+
+const PROTOCOL_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const METADATA_NS: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+const POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const EMAIL_FORMAT: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+
+/// Attributes identity providers commonly put the address in, when the
+/// name id is not one.
+const EMAIL_ATTRIBUTES: [&str; 4] = [
+    "email",
+    "mail",
+    "urn:oid:0.9.2342.19200300.100.1.3",
+    "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/emailaddress",
+];
+
+const NAME_ATTRIBUTES: [&str; 4] = [
+    "displayName",
+    "name",
+    "urn:oid:2.16.840.1.113730.3.1.241",
+    "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/name",
+];
+
+/// How far the identity provider's clock may be off ours.
+const CLOCK_SKEW_SECONDS: i64 = 3 * 60;
+
+/// Nodes a response may have, far more than real ones do.
+const MAX_RESPONSE_NODES: u32 = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SamlError {
+    #[error("The SAML response is not valid XML")]
+    Malformed,
+    #[error("The identity provider refused the login with {0}")]
+    Refused(String),
+    #[error("{0}")]
+    Invalid(&'static str),
+}
+
+/// Our side of an organization's SAML connection.
+#[derive(Clone, Debug)]
+pub struct ServiceProvider {
+    pub entity_id: String,
+    pub acs_url: String,
+}
+
+impl ServiceProvider {
+    pub fn new(public_base_url: &str, organization_id: &str) -> Self {
+        let base_url = format!("{}/auth/saml/{}", public_base_url, organization_id);
+        Self {
+            entity_id: format!("{}/metadata", base_url),
+            acs_url: format!("{}/acs", base_url),
+        }
+    }
+
+    /// The metadata identity providers import to set up the connection.
+    pub fn metadata(&self) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<md:EntityDescriptor xmlns:md="{}" entityID="{}">
+  <md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{}">
+    <md:NameIDFormat>{}</md:NameIDFormat>
+    <md:AssertionConsumerService Binding="{}" Location="{}" index="0" isDefault="true"/>
+  </md:SPSSODescriptor>
+</md:EntityDescriptor>
+"#,
+            METADATA_NS,
+            escape_xml(&self.entity_id),
+            PROTOCOL_NS,
+            EMAIL_FORMAT,
+            POST_BINDING,
+            escape_xml(&self.acs_url)
+        )
+    }
+
+    /// Where to send the browser to log in at the identity provider, with
+    /// an `AuthnRequest` identified by `request_id`.
+    pub fn authn_request_url(
+        &self,
+        idp_sso_url: &str,
+        request_id: &str,
+        relay_state: &str,
+    ) -> Result<String, url::ParseError> {
+        let request = format!(
+            r#"<samlp:AuthnRequest xmlns:samlp="{}" xmlns:saml="{}" ID="{}" Version="2.0" IssueInstant="{}" Destination="{}" AssertionConsumerServiceURL="{}" ProtocolBinding="{}"><saml:Issuer>{}</saml:Issuer><samlp:NameIDPolicy AllowCreate="true"/></samlp:AuthnRequest>"#,
+            PROTOCOL_NS,
+            ASSERTION_NS,
+            escape_xml(request_id),
+            Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            escape_xml(idp_sso_url),
+            escape_xml(&self.acs_url),
+            POST_BINDING,
+            escape_xml(&self.entity_id)
+        );
+
+        let mut url = Url::parse(idp_sso_url)?;
+        url.query_pairs_mut()
+            .append_pair(
+                "SAMLRequest",
+                &general_purpose::STANDARD.encode(png::deflate_stored(request.as_bytes())),
+            )
+            .append_pair("RelayState", relay_state);
+
+        Ok(url.to_string())
+    }
+}
+
+/// The identity provider an organization set up.
+#[derive(Clone, Debug)]
+pub struct IdentityProvider {
+    pub entity_id: String,
+    /// DER encoded certificate responses are signed with.
+    pub certificate: Vec<u8>,
+}
+
+/// Who the identity provider says logged in.
+#[derive(Debug)]
+pub struct SamlIdentity {
+    /// Id of the user at the identity provider.
+    pub name_id: String,
+    pub email: Option<String>,
+    pub name: Option<String>,
+    /// Id of the `AuthnRequest` the response answers.
+    pub in_response_to: String,
+}
+
+/// Read a certificate as identity providers hand it out: PEM, or the bare
+/// base64 of their metadata. Only certificates with a key responses can be
+/// checked with are taken.
+pub fn parse_certificate(value: &str) -> Option<Vec<u8>> {
+    let base64: String = value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let certificate = general_purpose::STANDARD.decode(base64).ok()?;
+
+    let (algorithm, _) = der::certificate_public_key(&certificate)?;
+    [der::OID_RSA_ENCRYPTION, der::OID_EC_PUBLIC_KEY]
+        .iter()
+        .any(|oid| algorithm.starts_with(oid))
+        .then_some(certificate)
+}
+
+/// Check a base64 encoded `Response` posted to the assertion consumer
+/// service and read who it logs in.
+pub fn verify_response(
+    encoded: &str,
+    service_provider: &ServiceProvider,
+    identity_provider: &IdentityProvider,
+) -> Result<SamlIdentity, SamlError> {
+    let encoded: String = encoded.split_whitespace().collect();
+    let xml = general_purpose::STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|xml| String::from_utf8(xml).ok())
+        .ok_or(SamlError::Malformed)?;
+    // Documents with a DTD are refused, and entities with them.
+    let document = Document::parse_with_options(
+        &xml,
+        ParsingOptions {
+            allow_dtd: false,
+            nodes_limit: MAX_RESPONSE_NODES,
+        },
+    )
+    .map_err(|_| SamlError::Malformed)?;
+
+    let response = document.root_element();
+    if !response.has_tag_name((PROTOCOL_NS, "Response")) {
+        return Err(SamlError::Invalid("The SAML response is not a Response"));
+    }
+    if response
+        .attribute("Destination")
+        .is_some_and(|destination| destination != service_provider.acs_url)
+    {
+        return Err(SamlError::Invalid(
+            "The response is meant for another service",
+        ));
+    }
+
+    let status = child(response, PROTOCOL_NS, "Status")
+        .and_then(|status| child(status, PROTOCOL_NS, "StatusCode"))
+        .and_then(|code| code.attribute("Value"))
+        .unwrap_or_default();
+    if status != SUCCESS {
+        return Err(SamlError::Refused(status.to_string()));
+    }
+
+    if document
+        .descendants()
+        .any(|node| node.has_tag_name((ASSERTION_NS, "EncryptedAssertion")))
+    {
+        return Err(SamlError::Invalid("Encrypted assertions are not supported"));
+    }
+    // With a single assertion, the one checked is the one read, however
+    // the response was wrapped.
+    let mut assertions = document
+        .descendants()
+        .filter(|node| node.has_tag_name((ASSERTION_NS, "Assertion")));
+    let assertion = match (assertions.next(), assertions.next()) {
+        (Some(assertion), None) if assertion.parent() == Some(response) => assertion,
+        _ => {
+            return Err(SamlError::Invalid(
+                "The response should carry one assertion",
+            ))
+        }
+    };
+
+    match (
+        child(assertion, DSIG_NS, "Signature"),
+        child(response, DSIG_NS, "Signature"),
+    ) {
+        (Some(signature), _) => verify_signature(assertion, signature, identity_provider)?,
+        (None, Some(signature)) => verify_signature(response, signature, identity_provider)?,
+        (None, None) => return Err(SamlError::Invalid("The response is not signed")),
+    }
+
+    let issuer = child(assertion, ASSERTION_NS, "Issuer")
+        .map(text)
+        .transpose()?
+        .map(str::trim);
+    if issuer != Some(identity_provider.entity_id.as_str()) {
+        return Err(SamlError::Invalid(
+            "The response comes from another identity provider",
+        ));
+    }
+
+    let now = Utc::now();
+    let skew = Duration::seconds(CLOCK_SKEW_SECONDS);
+    let expired = |node: Node| {
+        node.attribute("NotOnOrAfter")
+            .and_then(parse_time)
+            .is_some_and(|not_on_or_after| not_on_or_after <= now - skew)
+    };
+
+    let conditions = child(assertion, ASSERTION_NS, "Conditions")
+        .ok_or(SamlError::Invalid("The assertion has no conditions"))?;
+    let not_yet_valid = conditions
+        .attribute("NotBefore")
+        .and_then(parse_time)
+        .is_some_and(|not_before| not_before > now + skew);
+    if not_yet_valid || expired(conditions) {
+        return Err(SamlError::Invalid(
+            "The assertion is not valid at this time",
+        ));
+    }
+    let mut restrictions = children(conditions, ASSERTION_NS, "AudienceRestriction").peekable();
+    let for_us = restrictions.peek().is_some()
+        && restrictions.all(|restriction| {
+            children(restriction, ASSERTION_NS, "Audience").any(|audience| {
+                text(audience).is_ok_and(|audience| audience.trim() == service_provider.entity_id)
+            })
+        });
+    if !for_us {
+        return Err(SamlError::Invalid(
+            "The assertion is meant for another service",
+        ));
+    }
+
+    let subject = child(assertion, ASSERTION_NS, "Subject")
+        .ok_or(SamlError::Invalid("The assertion has no subject"))?;
+    let name_id = child(subject, ASSERTION_NS, "NameID")
+        .ok_or(SamlError::Invalid("The assertion has no name id"))?;
+    let name_id_value = Some(text(name_id)?.trim())
+        .filter(|value| !value.is_empty())
+        .ok_or(SamlError::Invalid("The assertion has no name id"))?;
+
+    let confirmation = children(subject, ASSERTION_NS, "SubjectConfirmation")
+        .filter(|confirmation| confirmation.attribute("Method") == Some(BEARER))
+        .find_map(|confirmation| child(confirmation, ASSERTION_NS, "SubjectConfirmationData"))
+        .ok_or(SamlError::Invalid(
+            "The assertion has no bearer confirmation",
+        ))?;
+    if confirmation.attribute("Recipient") != Some(service_provider.acs_url.as_str())
+        || confirmation.attribute("NotOnOrAfter").is_none()
+        || expired(confirmation)
+    {
+        return Err(SamlError::Invalid(
+            "The assertion is not valid at this time",
+        ));
+    }
+    // Logins the identity provider started are refused, as nothing ties
+    // them to the browser they end up in.
+    let in_response_to = confirmation
+        .attribute("InResponseTo")
+        .ok_or(SamlError::Invalid("The response answers no login request"))?;
+
+    let mut attributes: Vec<(&str, &str)> = Vec::new();
+    for attribute in children(assertion, ASSERTION_NS, "AttributeStatement")
+        .flat_map(|statement| children(statement, ASSERTION_NS, "Attribute"))
+    {
+        let (Some(name), Some(value)) = (
+            attribute.attribute("Name"),
+            child(attribute, ASSERTION_NS, "AttributeValue"),
+        ) else {
+            continue;
+        };
+        let value = text(value)?.trim();
+        if !value.is_empty() {
+            attributes.push((name, value));
+        }
+    }
+    let attribute = |names: &[&str]| {
+        names.iter().find_map(|name| {
+            attributes
+                .iter()
+                .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.to_string())
+        })
+    };
+
+    let email = if name_id.attribute("Format") == Some(EMAIL_FORMAT) {
+        Some(name_id_value.to_string())
+    } else {
+        attribute(&EMAIL_ATTRIBUTES)
+    };
+
+    Ok(SamlIdentity {
+        name_id: name_id_value.to_string(),
+        email: email.map(|email| email.to_lowercase()),
+        name: attribute(&NAME_ATTRIBUTES),
+        in_response_to: in_response_to.to_string(),
+    })
+}
+
+fn child<'a, 'input>(
+    node: Node<'a, 'input>,
+    namespace: &str,
+    name: &str,
+) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.has_tag_name((namespace, name)))
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    namespace: &'a str,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.has_tag_name((namespace, name)))
+}
+
+/// The text of an element holding nothing else. Canonicalization leaves
+/// comments out, so a signed `<NameID>a<!---->b</NameID>` is signed as `ab`
+/// while its first text node is `a`: elements holding comments or anything
+/// but one text node are refused rather than read in part.
+fn text<'a>(node: Node<'a, '_>) -> Result<&'a str, SamlError> {
+    let mut nodes = node.children();
+    match (nodes.next(), nodes.next()) {
+        (None, _) => Ok(""),
+        (Some(text), None) if text.is_text() => Ok(text.text().unwrap_or_default()),
+        _ => Err(SamlError::Invalid(
+            "The assertion holds values split by comments or elements",
+        )),
+    }
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn decode_base64(node: Option<Node>) -> Result<Vec<u8>, SamlError> {
+    let encoded: String = node
+        .and_then(|node| node.text())
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect();
+    general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|_| SamlError::Invalid("The signature is not valid base64"))
+}
+
+/// The `PrefixList` of a canonicalization's `InclusiveNamespaces`.
+fn inclusive_prefixes<'a>(method: Node<'a, '_>) -> Vec<&'a str> {
+    child(method, EXC_C14N, "InclusiveNamespaces")
+        .and_then(|inclusive| inclusive.attribute("PrefixList"))
+        .map(|prefixes| prefixes.split_whitespace().collect())
+        .unwrap_or_default()
+}
+
+/// Check that `signature`, a child of `signed`, signs exactly `signed` with
+/// the identity provider's key.
+fn verify_signature(
+    signed: Node,
+    signature: Node,
+    identity_provider: &IdentityProvider,
+) -> Result<(), SamlError> {
+    let id = signed
+        .attribute("ID")
+        .ok_or(SamlError::Invalid("The signed element has no ID"))?;
+    let with_id = signed
+        .document()
+        .descendants()
+        .filter(|node| node.attribute("ID") == Some(id))
+        .count();
+    if with_id != 1 {
+        return Err(SamlError::Invalid("IDs in the response are not unique"));
+    }
+
+    let signed_info = child(signature, DSIG_NS, "SignedInfo")
+        .ok_or(SamlError::Invalid("The signature has no SignedInfo"))?;
+    let canonicalization = child(signed_info, DSIG_NS, "CanonicalizationMethod")
+        .filter(|method| method.attribute("Algorithm") == Some(EXC_C14N))
+        .ok_or(SamlError::Invalid(
+            "Only exclusive canonicalization is supported",
+        ))?;
+
+    let mut references = children(signed_info, DSIG_NS, "Reference");
+    let reference = match (references.next(), references.next()) {
+        (Some(reference), None) => reference,
+        _ => {
+            return Err(SamlError::Invalid(
+                "The signature should have one reference",
+            ))
+        }
+    };
+    if reference.attribute("URI") != Some(format!("#{}", id).as_str()) {
+        return Err(SamlError::Invalid("The signature signs another element"));
+    }
+
+    let mut enveloped = false;
+    let mut prefixes = None;
+    let transforms = child(reference, DSIG_NS, "Transforms")
+        .map(|transforms| children(transforms, DSIG_NS, "Transform").collect::<Vec<_>>())
+        .unwrap_or_default();
+    for transform in transforms {
+        match transform.attribute("Algorithm") {
+            Some(ENVELOPED_SIGNATURE) => enveloped = true,
+            Some(EXC_C14N) => prefixes = Some(inclusive_prefixes(transform)),
+            _ => {
+                return Err(SamlError::Invalid(
+                    "The signature uses an unsupported transform",
+                ))
+            }
+        }
+    }
+    let prefixes = prefixes.ok_or(SamlError::Invalid(
+        "Only exclusive canonicalization is supported",
+    ))?;
+
+    let digest_algorithm = match child(reference, DSIG_NS, "DigestMethod")
+        .and_then(|method| method.attribute("Algorithm"))
+    {
+        Some("http://www.w3.org/2001/04/xmlenc#sha256") => &digest::SHA256,
+        Some("http://www.w3.org/2001/04/xmldsig-more#sha384") => &digest::SHA384,
+        Some("http://www.w3.org/2001/04/xmlenc#sha512") => &digest::SHA512,
+        _ => {
+            return Err(SamlError::Invalid(
+                "The signature uses an unsupported digest",
+            ))
+        }
+    };
+    let omitted = enveloped.then(|| signature.id());
+    let canonical = canonicalize(signed, omitted, &prefixes);
+    let digest_value = decode_base64(child(reference, DSIG_NS, "DigestValue"))?;
+    if digest::digest(digest_algorithm, canonical.as_bytes()).as_ref() != digest_value {
+        return Err(SamlError::Invalid(
+            "The response was changed after it was signed",
+        ));
+    }
+
+    let (key_algorithm, public_key) = der::certificate_public_key(&identity_provider.certificate)
+        .ok_or(SamlError::Invalid(
+        "The configured certificate is unreadable",
+    ))?;
+    let rsa = key_algorithm.starts_with(der::OID_RSA_ENCRYPTION);
+    let p256 = key_algorithm == [der::OID_EC_PUBLIC_KEY, der::OID_PRIME256V1].concat();
+    let p384 = key_algorithm == [der::OID_EC_PUBLIC_KEY, der::OID_SECP384R1].concat();
+    let algorithm: &dyn VerificationAlgorithm = match child(signed_info, DSIG_NS, "SignatureMethod")
+        .and_then(|method| method.attribute("Algorithm"))
+    {
+        Some("http://www.w3.org/2001/04/xmldsig-more#rsa-sha256") if rsa => {
+            &signature::RSA_PKCS1_2048_8192_SHA256
+        }
+        Some("http://www.w3.org/2001/04/xmldsig-more#rsa-sha384") if rsa => {
+            &signature::RSA_PKCS1_2048_8192_SHA384
+        }
+        Some("http://www.w3.org/2001/04/xmldsig-more#rsa-sha512") if rsa => {
+            &signature::RSA_PKCS1_2048_8192_SHA512
+        }
+        Some("http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha256") if p256 => {
+            &signature::ECDSA_P256_SHA256_FIXED
+        }
+        Some("http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha384") if p384 => {
+            &signature::ECDSA_P384_SHA384_FIXED
+        }
+        _ => {
+            return Err(SamlError::Invalid(
+                "The signature uses an unsupported algorithm for the configured certificate",
+            ))
+        }
+    };
+
+    let signature_value = decode_base64(child(signature, DSIG_NS, "SignatureValue"))?;
+    let canonical_info = canonicalize(signed_info, None, &inclusive_prefixes(canonicalization));
+    UnparsedPublicKey::new(algorithm, public_key)
+        .verify(canonical_info.as_bytes(), &signature_value)
+        .map_err(|_| SamlError::Invalid("The response signature is not valid"))
+}
+
+/// Exclusive XML canonicalization, without comments, of `element` and what
+/// it contains but `omitted`. Namespaces are declared where first used, and
+/// those in `inclusive` wherever in scope, like inclusive canonicalization
+/// does.
+fn canonicalize(element: Node, omitted: Option<NodeId>, inclusive: &[&str]) -> String {
+    let mut canonical = String::new();
+    let rendered = BTreeMap::from([(String::new(), String::new())]);
+    write_canonical(element, omitted, inclusive, &rendered, &mut canonical);
+    canonical
+}
+
+/// The qualified name an element was written with.
+fn element_qname<'input>(element: Node<'_, 'input>) -> &'input str {
+    let input = element.document().input_text();
+    input[element.range().start + 1..]
+        .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .next()
+        .unwrap_or_default()
+}
+
+fn write_canonical(
+    element: Node,
+    omitted: Option<NodeId>,
+    inclusive: &[&str],
+    rendered: &BTreeMap<String, String>,
+    canonical: &mut String,
+) {
+    let input = element.document().input_text();
+    let qname = element_qname(element);
+
+    // The namespaces the element and its attributes are in, by prefix, the
+    // default one being the empty prefix.
+    let mut prefixes = BTreeSet::from([qname.split_once(':').map_or("", |(prefix, _)| prefix)]);
+    for attribute in element.attributes() {
+        if let Some((prefix, _)) = input[attribute.range_qname()].split_once(':') {
+            if prefix != "xml" {
+                prefixes.insert(prefix);
+            }
+        }
+    }
+    for prefix in inclusive {
+        prefixes.insert(if *prefix == "#default" { "" } else { prefix });
+    }
+
+    let mut rendered = rendered.clone();
+    let mut declarations = String::new();
+    for prefix in prefixes {
+        let uri = if prefix.is_empty() {
+            element.default_namespace().unwrap_or_default()
+        } else {
+            match element.lookup_namespace_uri(Some(prefix)) {
+                Some(uri) => uri,
+                None => continue,
+            }
+        };
+        if rendered.get(prefix).map(String::as_str) == Some(uri) {
+            continue;
+        }
+        match prefix {
+            "" => declarations.push_str(&format!(r#" xmlns="{}""#, escape_attribute(uri))),
+            prefix => {
+                declarations.push_str(&format!(r#" xmlns:{}="{}""#, prefix, escape_attribute(uri)))
+            }
+        }
+        rendered.insert(prefix.to_string(), uri.to_string());
+    }
+
+    let mut attributes: Vec<_> = element.attributes().collect();
+    attributes
+        .sort_by_key(|attribute| (attribute.namespace().unwrap_or_default(), attribute.name()));
+
+    canonical.push('<');
+    canonical.push_str(qname);
+    canonical.push_str(&declarations);
+    for attribute in attributes {
+        canonical.push_str(&format!(
+            r#" {}="{}""#,
+            &input[attribute.range_qname()],
+            escape_attribute(attribute.value())
+        ));
+    }
+    canonical.push('>');
+
+    for node in element.children() {
+        if Some(node.id()) == omitted {
+            continue;
+        }
+        if node.is_element() {
+            write_canonical(node, omitted, inclusive, &rendered, canonical);
+        } else if node.is_text() {
+            canonical.push_str(&escape_text(node.text().unwrap_or_default()));
+        } else if let Some(pi) = node.pi() {
+            match pi.value {
+                Some(value) => canonical.push_str(&format!("<?{} {}?>", pi.target, value)),
+                None => canonical.push_str(&format!("<?{}?>", pi.target)),
+            }
+        }
+    }
+
+    canonical.push_str("</");
+    canonical.push_str(qname);
+    canonical.push('>');
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "&#xD;")
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
+}
+
+/// Escape a value written into the XML we send.
+fn escape_xml(value: &str) -> String {
+    escape_attribute(value).replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+    const IDP_ENTITY_ID: &str = "https://idp.example.com";
+    const REQUEST_ID: &str = "_request";
+
+    struct Signer {
+        key_pair: EcdsaKeyPair,
+        rng: SystemRandom,
+    }
+
+    impl Signer {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                .expect("A key should be generated");
+            let key_pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                    .expect("The generated key should be readable");
+            Self { key_pair, rng }
+        }
+
+        /// A certificate holding the public key, which is all responses are
+        /// checked with; its own signature is never looked at.
+        fn identity_provider(&self) -> IdentityProvider {
+            let algorithm = der::constructed(
+                der::SEQUENCE,
+                &[der::OID_EC_PUBLIC_KEY, der::OID_PRIME256V1],
+            );
+            let public_key_info = der::constructed(
+                der::SEQUENCE,
+                &[
+                    &algorithm,
+                    &der::bit_string(self.key_pair.public_key().as_ref()),
+                ],
+            );
+            let signature_algorithm =
+                der::constructed(der::SEQUENCE, &[der::OID_ECDSA_WITH_SHA256]);
+            let name = der::constructed(der::SEQUENCE, &[]);
+            let validity = der::constructed(
+                der::SEQUENCE,
+                &[
+                    &der::tlv(der::UTC_TIME, b"200101000000Z"),
+                    &der::tlv(der::UTC_TIME, b"491231235959Z"),
+                ],
+            );
+            let tbs = der::constructed(
+                der::SEQUENCE,
+                &[
+                    &der::tlv(der::CONTEXT_0, &der::tlv(der::INTEGER, &[2])),
+                    &der::tlv(der::INTEGER, &[1]),
+                    &signature_algorithm,
+                    &name,
+                    &validity,
+                    &name,
+                    &public_key_info,
+                ],
+            );
+            let certificate = der::constructed(
+                der::SEQUENCE,
+                &[&tbs, &signature_algorithm, &der::bit_string(&[0])],
+            );
+
+            IdentityProvider {
+                entity_id: IDP_ENTITY_ID.to_string(),
+                certificate,
+            }
+        }
+
+        /// Sign the assertion of `response`, which has an empty
+        /// `{signature}` placeholder right after its issuer.
+        fn sign(&self, response: &str) -> String {
+            let unsigned = response.replace("{signature}", &signature_xml("", ""));
+            let document = Document::parse(&unsigned).expect("The response should parse");
+            let assertion = document
+                .descendants()
+                .find(|node| node.has_tag_name((ASSERTION_NS, "Assertion")))
+                .expect("The response should carry an assertion");
+            let signature = child(assertion, DSIG_NS, "Signature").expect("A signature");
+            let canonical = canonicalize(assertion, Some(signature.id()), &[]);
+            let digest_value = general_purpose::STANDARD
+                .encode(digest::digest(&digest::SHA256, canonical.as_bytes()));
+
+            let digested = response.replace("{signature}", &signature_xml(&digest_value, ""));
+            let document = Document::parse(&digested).expect("The response should parse");
+            let signed_info = document
+                .descendants()
+                .find(|node| node.has_tag_name((DSIG_NS, "SignedInfo")))
+                .expect("The signature should have a SignedInfo");
+            let signature_value = self
+                .key_pair
+                .sign(&self.rng, canonicalize(signed_info, None, &[]).as_bytes())
+                .expect("The SignedInfo should be signed");
+
+            response.replace(
+                "{signature}",
+                &signature_xml(
+                    &digest_value,
+                    &general_purpose::STANDARD.encode(signature_value.as_ref()),
+                ),
+            )
+        }
+    }
+
+    fn signature_xml(digest_value: &str, signature_value: &str) -> String {
+        format!(
+            r##"<ds:Signature xmlns:ds="{DSIG_NS}"><ds:SignedInfo><ds:CanonicalizationMethod Algorithm="{EXC_C14N}"/><ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha256"/><ds:Reference URI="#_assertion"><ds:Transforms><ds:Transform Algorithm="{ENVELOPED_SIGNATURE}"/><ds:Transform Algorithm="{EXC_C14N}"/></ds:Transforms><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue>{digest_value}</ds:DigestValue></ds:Reference></ds:SignedInfo><ds:SignatureValue>{signature_value}</ds:SignatureValue></ds:Signature>"##
+        )
+    }
+
+    fn service_provider() -> ServiceProvider {
+        ServiceProvider::new("https://groupify.example.com", "organization")
+    }
+
+    /// An assertion logging `name_id` in for `audience`, with the signature
+    /// placeholder `Signer::sign` fills.
+    fn assertion(name_id: &str, audience: &str) -> String {
+        let acs_url = service_provider().acs_url;
+        let not_before = (Utc::now() - Duration::minutes(5)).to_rfc3339();
+        let not_on_or_after = (Utc::now() + Duration::minutes(5)).to_rfc3339();
+        format!(
+            r#"<saml:Assertion xmlns:saml="{ASSERTION_NS}" ID="_assertion" Version="2.0" IssueInstant="{not_before}"><saml:Issuer>{IDP_ENTITY_ID}</saml:Issuer>{{signature}}<saml:Subject><saml:NameID Format="{EMAIL_FORMAT}">{name_id}</saml:NameID><saml:SubjectConfirmation Method="{BEARER}"><saml:SubjectConfirmationData InResponseTo="{REQUEST_ID}" Recipient="{acs_url}" NotOnOrAfter="{not_on_or_after}"/></saml:SubjectConfirmation></saml:Subject><saml:Conditions NotBefore="{not_before}" NotOnOrAfter="{not_on_or_after}"><saml:AudienceRestriction><saml:Audience>{audience}</saml:Audience></saml:AudienceRestriction></saml:Conditions></saml:Assertion>"#
+        )
+    }
+
+    fn response(content: &str) -> String {
+        let acs_url = service_provider().acs_url;
+        format!(
+            r#"<samlp:Response xmlns:samlp="{PROTOCOL_NS}" ID="_response" Version="2.0" Destination="{acs_url}" InResponseTo="{REQUEST_ID}"><samlp:Status><samlp:StatusCode Value="{SUCCESS}"/></samlp:Status>{content}</samlp:Response>"#
+        )
+    }
+
+    fn verify(signer: &Signer, response: &str) -> Result<SamlIdentity, SamlError> {
+        verify_response(
+            &general_purpose::STANDARD.encode(response),
+            &service_provider(),
+            &signer.identity_provider(),
+        )
+    }
+
+    #[test]
+    fn signed_response_logs_in() {
+        let signer = Signer::new();
+        let entity_id = service_provider().entity_id;
+        let signed = signer.sign(&response(&assertion("user@corp.com", &entity_id)));
+
+        let identity = verify(&signer, &signed).expect("The response should verify");
+        assert_eq!(identity.name_id, "user@corp.com");
+        assert_eq!(identity.email.as_deref(), Some("user@corp.com"));
+        assert_eq!(identity.in_response_to, REQUEST_ID);
+    }
+
+    #[test]
+    fn changed_name_id_is_refused() {
+        let signer = Signer::new();
+        let entity_id = service_provider().entity_id;
+        let signed = signer.sign(&response(&assertion("user@corp.com", &entity_id)));
+        let changed = signed.replace("user@corp.com", "victim@corp.com");
+
+        assert!(verify(&signer, &changed).is_err());
+    }
+
+    #[test]
+    fn comment_in_name_id_is_refused() {
+        let signer = Signer::new();
+        let entity_id = service_provider().entity_id;
+        let signed = signer.sign(&response(&assertion(
+            "victim@corp.com<!---->.evil.com",
+            &entity_id,
+        )));
+
+        assert!(matches!(
+            verify(&signer, &signed),
+            Err(SamlError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn wrapped_assertion_is_refused() {
+        let signer = Signer::new();
+        let entity_id = service_provider().entity_id;
+        let signed = signer.sign(&response(&assertion("attacker@evil.com", &entity_id)));
+
+        // The signed assertion moved out of the way, next to a forged one
+        // in its place, with and without reusing its ID.
+        let signed_assertion = &signed
+            [signed.find("<saml:Assertion").unwrap()..signed.find("</samlp:Response>").unwrap()];
+        let forged = assertion("victim@corp.com", &entity_id).replace("{signature}", "");
+        for forged in [forged.clone(), forged.replace("_assertion", "_forged")] {
+            let wrapped = response(&format!(
+                "<samlp:Extensions>{}</samlp:Extensions>{}",
+                signed_assertion, forged
+            ));
+            assert!(verify(&signer, &wrapped).is_err());
+
+            let wrapped = response(&format!("{}{}", forged, signed_assertion));
+            assert!(verify(&signer, &wrapped).is_err());
+        }
+    }
+
+    #[test]
+    fn assertion_for_another_service_is_refused() {
+        let signer = Signer::new();
+        let signed = signer.sign(&response(&assertion(
+            "user@corp.com",
+            "https://other.example.com/metadata",
+        )));
+
+        assert!(matches!(
+            verify(&signer, &signed),
+            Err(SamlError::Invalid(
+                "The assertion is meant for another service"
+            ))
+        ));
+    }
+
+    #[test]
+    fn response_signed_by_another_key_is_refused() {
+        let signer = Signer::new();
+        let entity_id = service_provider().entity_id;
+        let signed = Signer::new().sign(&response(&assertion("user@corp.com", &entity_id)));
+
+        assert!(matches!(
+            verify(&signer, &signed),
+            Err(SamlError::Invalid("The response signature is not valid"))
+        ));
+    }
+}
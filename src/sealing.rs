@@ -0,0 +1,114 @@
+//! Encryption of secrets kept in the database, such as the private keys of
+//! TLS certificates, with a key only the service has. Each secret is bound
+//! to what it belongs to, like its domain, so a sealed value copied to
+//! another row does not open.
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+
+/// Marks sealed values, telling them apart from ones stored before a key
+/// was configured.
+const SEALED_PREFIX: &str = "sealed:v1:";
+
+#[derive(Clone)]
+pub struct SealingKey(LessSafeKey);
+
+impl fmt::Debug for SealingKey {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("SealingKey(..)")
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SealingError {
+    #[error("The value is sealed but no key to open it is configured")]
+    NoKey,
+    #[error("The value does not open with the configured key")]
+    WrongKey,
+}
+
+impl SealingKey {
+    /// A key from 32 base64 encoded bytes.
+    pub fn from_base64(encoded: &str) -> Option<Self> {
+        let bytes = general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes).ok()?;
+        Some(Self(LessSafeKey::new(key)))
+    }
+
+    pub fn seal(&self, context: &str, plaintext: &str) -> String {
+        format!(
+            "{}{}",
+            SEALED_PREFIX,
+            general_purpose::STANDARD.encode(self.seal_bytes(context, plaintext.as_bytes()))
+        )
+    }
+
+    /// Seal binary data, such as a whole archive, into the nonce followed by
+    /// the ciphertext.
+    pub fn seal_bytes(&self, context: &str, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("The system should always provide randomness");
+
+        let mut sealed = plaintext.to_vec();
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(context),
+                &mut sealed,
+            )
+            .expect("Secrets should always be short enough to seal");
+
+        [&nonce[..], &sealed].concat()
+    }
+
+    fn open(&self, context: &str, sealed: &str) -> Result<String, SealingError> {
+        let bytes = general_purpose::STANDARD
+            .decode(sealed)
+            .map_err(|_| SealingError::WrongKey)?;
+        let plaintext = self.open_bytes(context, &bytes)?;
+
+        String::from_utf8(plaintext).map_err(|_| SealingError::WrongKey)
+    }
+
+    pub fn open_bytes(&self, context: &str, sealed: &[u8]) -> Result<Vec<u8>, SealingError> {
+        if sealed.len() < NONCE_LEN {
+            return Err(SealingError::WrongKey);
+        }
+        let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+
+        let mut sealed = sealed.to_vec();
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| SealingError::WrongKey)?;
+        let plaintext = self
+            .0
+            .open_in_place(nonce, Aad::from(context), &mut sealed)
+            .map_err(|_| SealingError::WrongKey)?;
+
+        Ok(plaintext.to_vec())
+    }
+}
+
+pub fn is_sealed(stored: &str) -> bool {
+    stored.starts_with(SEALED_PREFIX)
+}
+
+/// Seal `plaintext` when a key is configured, keeping it as is otherwise.
+pub fn seal(key: Option<&SealingKey>, context: &str, plaintext: &str) -> String {
+    match key {
+        Some(key) => key.seal(context, plaintext),
+        None => plaintext.to_string(),
+    }
+}
+
+/// The plaintext of a stored value, sealed or not.
+pub fn open(key: Option<&SealingKey>, context: &str, stored: &str) -> Result<String, SealingError> {
+    match (stored.strip_prefix(SEALED_PREFIX), key) {
+        (Some(sealed), Some(key)) => key.open(context, sealed),
+        (Some(_), None) => Err(SealingError::NoKey),
+        (None, _) => Ok(stored.to_string()),
+    }
+}
@@ -0,0 +1,427 @@
+//! The redirect latency SLO: the share of redirects answered within
+//! `REDIRECT_SLO_LATENCY_MS` and without a server error, held against
+//! `REDIRECT_SLO_OBJECTIVE`. Each replica counts the redirects it serves
+//! per minute for the length of the SLO window, so its error budget covers
+//! what it served since it started, at most the whole window.
+//!
+//! Alerts follow the error budget burn rate over a long and a short window
+//! at once, so they fire on a sustained burn and clear soon after it ends.
+//! They are posted to `SLO_ALERT_WEBHOOK_URL` as they start and stop firing.
+
+use crate::configuration::Settings;
+use crate::leader::REPLICA_ID;
+use crate::task_health;
+
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_prometheus::metrics::gauge;
+use chrono::{NaiveDateTime, Utc};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const ALERTS_TASK: &str = "slo_alerts";
+
+/// How often burn rates are checked against the alert thresholds.
+const ALERT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Redirects an alert needs in its short window, so a lone slow redirect
+/// on a quiet replica does not page anyone.
+const MIN_ALERT_REQUESTS: u64 = 50;
+
+/// Windows burn rates are reported over.
+const BURN_RATE_WINDOWS: [(&str, Duration); 4] = [
+    ("5m", Duration::from_secs(5 * 60)),
+    ("30m", Duration::from_secs(30 * 60)),
+    ("1h", Duration::from_secs(60 * 60)),
+    ("6h", Duration::from_secs(6 * 60 * 60)),
+];
+
+static CONFIG: OnceCell<SloConfig> = OnceCell::new();
+
+static MINUTES: Lazy<Mutex<VecDeque<Minute>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// Alerts firing on this replica, and since when.
+static FIRING: Lazy<Mutex<HashMap<&'static str, NaiveDateTime>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static ALERT_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("The SLO alert client should always be constructable")
+});
+
+struct SloConfig {
+    objective: f64,
+    latency: Duration,
+    window: Duration,
+    fast_burn_rate: f64,
+    slow_burn_rate: f64,
+}
+
+/// Redirects served in one minute since the epoch.
+struct Minute {
+    minute: u64,
+    requests: u64,
+    /// Slower than the threshold, or failed with a server error.
+    bad: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertName {
+    FastBurn,
+    SlowBurn,
+}
+
+impl AlertName {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AlertName::FastBurn => "fast_burn",
+            AlertName::SlowBurn => "slow_burn",
+        }
+    }
+
+    /// The long and the short window the burn rate must exceed the
+    /// threshold over.
+    fn windows(&self) -> (Duration, Duration) {
+        match self {
+            AlertName::FastBurn => (Duration::from_secs(60 * 60), Duration::from_secs(5 * 60)),
+            AlertName::SlowBurn => (
+                Duration::from_secs(6 * 60 * 60),
+                Duration::from_secs(30 * 60),
+            ),
+        }
+    }
+
+    fn threshold(&self, config: &SloConfig) -> f64 {
+        match self {
+            AlertName::FastBurn => config.fast_burn_rate,
+            AlertName::SlowBurn => config.slow_burn_rate,
+        }
+    }
+}
+
+const ALERTS: [AlertName; 2] = [AlertName::FastBurn, AlertName::SlowBurn];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BurnRate {
+    pub window: &'static str,
+    pub requests: u64,
+    pub bad_requests: u64,
+    /// How many times faster than the objective allows the error budget is
+    /// spent, 1 spending exactly all of it over the SLO window.
+    pub burn_rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertStatus {
+    pub name: AlertName,
+    pub threshold: f64,
+    pub long_window_minutes: u64,
+    pub short_window_minutes: u64,
+    pub long_burn_rate: f64,
+    pub short_burn_rate: f64,
+    /// Whether the alert was firing when last checked.
+    pub firing: bool,
+    pub firing_since: Option<NaiveDateTime>,
+    /// Whether both burn rates are over the threshold now.
+    #[serde(skip)]
+    breached: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SloReport {
+    pub replica: String,
+    pub objective: f64,
+    pub latency_threshold_ms: u64,
+    pub window_hours: u64,
+    /// Redirects counted over the SLO window.
+    pub requests: u64,
+    pub bad_requests: u64,
+    /// Share of the error budget of the window left, negative once it is
+    /// overspent.
+    pub error_budget_remaining: f64,
+    pub burn_rates: Vec<BurnRate>,
+    pub alerts: Vec<AlertStatus>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AlertNotification<'a> {
+    alert: AlertName,
+    /// `firing` or `resolved`.
+    status: &'static str,
+    replica: &'a str,
+    objective: f64,
+    latency_threshold_ms: u64,
+    threshold: f64,
+    long_burn_rate: f64,
+    short_burn_rate: f64,
+    error_budget_remaining: f64,
+    at: NaiveDateTime,
+}
+
+pub fn init(settings: &Settings) {
+    let config = SloConfig {
+        objective: settings.redirect_slo_objective,
+        latency: settings.redirect_slo_latency,
+        window: settings.redirect_slo_window,
+        fast_burn_rate: settings.redirect_slo_fast_burn_rate,
+        slow_burn_rate: settings.redirect_slo_slow_burn_rate,
+    };
+    if CONFIG.set(config).is_err() {
+        tracing::warn!("The redirect SLO was already set");
+    }
+}
+
+/// Count the redirect against the SLO once it has been answered.
+pub async fn track_redirect_latency(request: Request, next: Next) -> Response {
+    let started_at = Instant::now();
+    let response = next.run(request).await;
+
+    record(started_at.elapsed(), response.status().is_server_error());
+
+    response
+}
+
+fn record(latency: Duration, failed: bool) {
+    let Some(config) = CONFIG.get() else {
+        return;
+    };
+    let bad = failed || latency > config.latency;
+    let now = current_minute();
+
+    let mut minutes = minutes();
+    match minutes.back_mut() {
+        Some(minute) if minute.minute == now => {
+            minute.requests += 1;
+            minute.bad += u64::from(bad);
+        }
+        _ => minutes.push_back(Minute {
+            minute: now,
+            requests: 1,
+            bad: u64::from(bad),
+        }),
+    }
+
+    let oldest = now.saturating_sub(window_minutes(config.window));
+    while minutes
+        .front()
+        .is_some_and(|minute| minute.minute <= oldest)
+    {
+        minutes.pop_front();
+    }
+}
+
+/// Redirects and bad ones over the last `window`, the current minute
+/// included.
+fn counts(minutes: &VecDeque<Minute>, now: u64, window: Duration) -> (u64, u64) {
+    let oldest = now.saturating_sub(window_minutes(window));
+
+    minutes
+        .iter()
+        .rev()
+        .take_while(|minute| minute.minute > oldest)
+        .fold((0, 0), |(requests, bad), minute| {
+            (requests + minute.requests, bad + minute.bad)
+        })
+}
+
+fn burn_rate(config: &SloConfig, requests: u64, bad: u64) -> f64 {
+    if requests == 0 {
+        return 0.0;
+    }
+    (bad as f64 / requests as f64) / (1.0 - config.objective)
+}
+
+/// How the SLO is doing on this replica. Other replicas keep their own.
+pub fn report() -> Option<SloReport> {
+    let config = CONFIG.get()?;
+    let now = current_minute();
+    let minutes = minutes();
+
+    let (requests, bad_requests) = counts(&minutes, now, config.window);
+    let allowed = requests as f64 * (1.0 - config.objective);
+    let error_budget_remaining = if requests == 0 {
+        1.0
+    } else {
+        1.0 - bad_requests as f64 / allowed
+    };
+
+    let burn_rates = BURN_RATE_WINDOWS
+        .iter()
+        .map(|(window, duration)| {
+            let (requests, bad_requests) = counts(&minutes, now, *duration);
+            BurnRate {
+                window,
+                requests,
+                bad_requests,
+                burn_rate: burn_rate(config, requests, bad_requests),
+            }
+        })
+        .collect();
+
+    let firing = FIRING
+        .lock()
+        .expect("The SLO alert lock should never be poisoned");
+    let alerts = ALERTS
+        .iter()
+        .map(|alert| {
+            let (long_window, short_window) = alert.windows();
+            let (long_requests, long_bad) = counts(&minutes, now, long_window);
+            let (short_requests, short_bad) = counts(&minutes, now, short_window);
+            let long_burn_rate = burn_rate(config, long_requests, long_bad);
+            let short_burn_rate = burn_rate(config, short_requests, short_bad);
+            let threshold = alert.threshold(config);
+            let firing_since = firing.get(alert.as_str()).copied();
+
+            AlertStatus {
+                name: *alert,
+                threshold,
+                long_window_minutes: window_minutes(long_window),
+                short_window_minutes: window_minutes(short_window),
+                long_burn_rate,
+                short_burn_rate,
+                firing: firing_since.is_some(),
+                firing_since,
+                breached: short_requests >= MIN_ALERT_REQUESTS
+                    && long_burn_rate > threshold
+                    && short_burn_rate > threshold,
+            }
+        })
+        .collect();
+
+    Some(SloReport {
+        replica: REPLICA_ID.to_string(),
+        objective: config.objective,
+        latency_threshold_ms: config.latency.as_millis().try_into().unwrap_or(u64::MAX),
+        window_hours: config.window.as_secs() / (60 * 60),
+        requests,
+        bad_requests,
+        error_budget_remaining,
+        burn_rates,
+        alerts,
+    })
+}
+
+/// Check the burn rates every minute for as long as the process runs,
+/// exporting them as gauges and notifying the webhook when an alert starts
+/// or stops firing.
+pub async fn run_alerts(settings: Arc<Settings>) {
+    let mut interval = tokio::time::interval(ALERT_INTERVAL);
+    task_health::register(ALERTS_TASK, ALERT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let Some(report) = report() else {
+            task_health::idle(ALERTS_TASK);
+            continue;
+        };
+
+        gauge!("redirect_slo_error_budget_remaining").set(report.error_budget_remaining);
+        for rate in &report.burn_rates {
+            gauge!("redirect_slo_burn_rate", "window" => rate.window).set(rate.burn_rate);
+        }
+
+        let mut last_error = None;
+        for alert in &report.alerts {
+            let Some(status) = transition(alert) else {
+                continue;
+            };
+            match status {
+                "firing" => tracing::warn!(
+                    alert = alert.name.as_str(),
+                    burn_rate = alert.short_burn_rate,
+                    "Redirect SLO alert firing"
+                ),
+                _ => tracing::info!(alert = alert.name.as_str(), "Redirect SLO alert resolved"),
+            }
+
+            if let Some(url) = &settings.slo_alert_webhook_url {
+                if let Err(err) = notify(url, &report, alert, status).await {
+                    tracing::warn!("Could not post the SLO alert: {}", err);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        match last_error {
+            Some(err) => task_health::failed(ALERTS_TASK, &err),
+            None => task_health::succeeded(ALERTS_TASK),
+        }
+    }
+}
+
+/// Note whether the alert started or stopped firing, and which.
+fn transition(alert: &AlertStatus) -> Option<&'static str> {
+    let mut firing = FIRING
+        .lock()
+        .expect("The SLO alert lock should never be poisoned");
+    let name = alert.name.as_str();
+
+    match (alert.breached, firing.contains_key(name)) {
+        (true, false) => {
+            firing.insert(name, Utc::now().naive_utc());
+            Some("firing")
+        }
+        (false, true) => {
+            firing.remove(name);
+            Some("resolved")
+        }
+        _ => None,
+    }
+}
+
+async fn notify(
+    url: &str,
+    report: &SloReport,
+    alert: &AlertStatus,
+    status: &'static str,
+) -> Result<(), reqwest::Error> {
+    ALERT_CLIENT
+        .post(url)
+        .json(&AlertNotification {
+            alert: alert.name,
+            status,
+            replica: &report.replica,
+            objective: report.objective,
+            latency_threshold_ms: report.latency_threshold_ms,
+            threshold: alert.threshold,
+            long_burn_rate: alert.long_burn_rate,
+            short_burn_rate: alert.short_burn_rate,
+            error_budget_remaining: report.error_budget_remaining,
+            at: Utc::now().naive_utc(),
+        })
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+fn minutes() -> std::sync::MutexGuard<'static, VecDeque<Minute>> {
+    MINUTES
+        .lock()
+        .expect("The SLO minutes lock should never be poisoned")
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The clock should be past the epoch")
+        .as_secs()
+        / 60
+}
+
+fn window_minutes(window: Duration) -> u64 {
+    window.as_secs() / 60
+}
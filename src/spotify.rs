@@ -0,0 +1,226 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+const SPOTIFY_SCOPES: &str =
+    "playlist-modify-public playlist-modify-private playlist-read-collaborative";
+
+#[derive(Clone, Debug)]
+pub struct SpotifyClient {
+    http_client: Client,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    accounts_base_url: String,
+    api_base_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpotifyTokens {
+    pub access_token: String,
+    /// Only returned on the initial exchange and when Spotify rotates it.
+    pub refresh_token: Option<String>,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyUser {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExternalUrls {
+    spotify: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpotifyPlaylist {
+    pub id: String,
+    external_urls: ExternalUrls,
+}
+
+impl SpotifyPlaylist {
+    pub fn url(&self) -> &str {
+        &self.external_urls.spotify
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaylistTracksPage {
+    items: Vec<PlaylistItem>,
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaylistItem {
+    added_at: Option<String>,
+    track: Option<Track>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Track {
+    name: String,
+    uri: String,
+    artists: Vec<Artist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Artist {
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistTrack {
+    pub name: String,
+    pub artists: Vec<String>,
+    pub uri: String,
+    pub added_at: Option<String>,
+}
+
+impl SpotifyClient {
+    pub fn new(client_id: String, client_secret: String, redirect_uri: String) -> Self {
+        Self {
+            http_client: Client::new(),
+            client_id,
+            client_secret,
+            redirect_uri,
+            accounts_base_url: "https://accounts.spotify.com".to_owned(),
+            api_base_url: "https://api.spotify.com/v1".to_owned(),
+        }
+    }
+
+    /// Build a client from `SPOTIFY_CLIENT_ID`, `SPOTIFY_CLIENT_SECRET` and
+    /// `SPOTIFY_REDIRECT_URI`, or `None` when the integration is not set up.
+    pub fn from_env() -> Option<Self> {
+        Some(Self::new(
+            std::env::var("SPOTIFY_CLIENT_ID").ok()?,
+            std::env::var("SPOTIFY_CLIENT_SECRET").ok()?,
+            std::env::var("SPOTIFY_REDIRECT_URI").ok()?,
+        ))
+    }
+
+    pub fn authorize_url(&self, state: &str) -> String {
+        let mut url = Url::parse(&format!("{}/authorize", self.accounts_base_url))
+            .expect("The Spotify accounts URL should always be valid");
+
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client_id)
+            .append_pair("response_type", "code")
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", SPOTIFY_SCOPES)
+            .append_pair("state", state);
+
+        url.to_string()
+    }
+
+    pub async fn exchange_code(&self, code: &str) -> Result<SpotifyTokens, reqwest::Error> {
+        self.token_request(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+        ])
+        .await
+    }
+
+    pub async fn refresh(&self, refresh_token: &str) -> Result<SpotifyTokens, reqwest::Error> {
+        self.token_request(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ])
+        .await
+    }
+
+    async fn token_request(&self, form: &[(&str, &str)]) -> Result<SpotifyTokens, reqwest::Error> {
+        self.http_client
+            .post(format!("{}/api/token", self.accounts_base_url))
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    pub async fn current_user_id(&self, access_token: &str) -> Result<String, reqwest::Error> {
+        let user: SpotifyUser = self
+            .http_client
+            .get(format!("{}/me", self.api_base_url))
+            .bearer_auth(access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(user.id)
+    }
+
+    /// Create a private collaborative playlist that anyone following its
+    /// link can add tracks to.
+    pub async fn create_collaborative_playlist(
+        &self,
+        access_token: &str,
+        spotify_user_id: &str,
+        name: &str,
+    ) -> Result<SpotifyPlaylist, reqwest::Error> {
+        self.http_client
+            .post(format!(
+                "{}/users/{}/playlists",
+                self.api_base_url, spotify_user_id
+            ))
+            .bearer_auth(access_token)
+            .json(&serde_json::json!({
+                "name": name,
+                "public": false,
+                "collaborative": true,
+                "description": "Shared playlist created by Groupify",
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    pub async fn playlist_tracks(
+        &self,
+        access_token: &str,
+        playlist_id: &str,
+    ) -> Result<Vec<PlaylistTrack>, reqwest::Error> {
+        let mut tracks = Vec::new();
+        let mut next = Some(format!(
+            "{}/playlists/{}/tracks?fields=next,items(added_at,track(name,uri,artists(name)))",
+            self.api_base_url, playlist_id
+        ));
+
+        while let Some(url) = next {
+            let page: PlaylistTracksPage = self
+                .http_client
+                .get(url)
+                .bearer_auth(access_token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            tracks.extend(page.items.into_iter().filter_map(|item| {
+                item.track.map(|track| PlaylistTrack {
+                    name: track.name,
+                    artists: track
+                        .artists
+                        .into_iter()
+                        .map(|artist| artist.name)
+                        .collect(),
+                    uri: track.uri,
+                    added_at: item.added_at,
+                })
+            }));
+            next = page.next;
+        }
+
+        Ok(tracks)
+    }
+}
@@ -0,0 +1,112 @@
+//! What the background loops of this replica last did, so one that stopped
+//! making progress without failing shows up. Each loop reports after every
+//! iteration; a loop is stuck once it missed a few of them in a row.
+
+use chrono::{NaiveDateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Missed iterations after which a loop counts as stuck.
+const STUCK_AFTER_ITERATIONS: u32 = 3;
+
+static TASKS: Lazy<Mutex<BTreeMap<&'static str, TaskRecord>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+struct TaskRecord {
+    every: Duration,
+    last_iteration: Instant,
+    last_success_at: Option<NaiveDateTime>,
+    last_failure_at: Option<NaiveDateTime>,
+    last_error: Option<String>,
+    error_count: u64,
+    queue_depth: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskStatus {
+    pub name: &'static str,
+    /// How often the loop wakes up at the longest.
+    pub interval_seconds: u64,
+    pub last_success_at: Option<NaiveDateTime>,
+    pub last_failure_at: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    /// Failures since the replica started.
+    pub error_count: u64,
+    /// Work waiting for the loop, for loops that have a queue.
+    pub queue_depth: Option<i64>,
+    /// Whether the loop has not come around for several intervals.
+    pub stuck: bool,
+}
+
+/// Start tracking a loop waking up at least every `every`.
+pub fn register(name: &'static str, every: Duration) {
+    tasks().insert(
+        name,
+        TaskRecord {
+            every,
+            last_iteration: Instant::now(),
+            last_success_at: None,
+            last_failure_at: None,
+            last_error: None,
+            error_count: 0,
+            queue_depth: None,
+        },
+    );
+}
+
+/// The loop came around without doing anything, such as a job that is not
+/// due.
+pub fn idle(name: &'static str) {
+    if let Some(task) = tasks().get_mut(name) {
+        task.last_iteration = Instant::now();
+    }
+}
+
+pub fn succeeded(name: &'static str) {
+    if let Some(task) = tasks().get_mut(name) {
+        task.last_iteration = Instant::now();
+        task.last_success_at = Some(Utc::now().naive_utc());
+    }
+}
+
+pub fn failed(name: &'static str, err: &dyn Display) {
+    if let Some(task) = tasks().get_mut(name) {
+        task.last_iteration = Instant::now();
+        task.last_failure_at = Some(Utc::now().naive_utc());
+        task.last_error = Some(err.to_string());
+        task.error_count += 1;
+    }
+}
+
+pub fn report_queue_depth(name: &'static str, depth: i64) {
+    if let Some(task) = tasks().get_mut(name) {
+        task.queue_depth = Some(depth);
+    }
+}
+
+pub fn statuses() -> Vec<TaskStatus> {
+    tasks()
+        .iter()
+        .map(|(name, task)| TaskStatus {
+            name,
+            interval_seconds: task.every.as_secs(),
+            last_success_at: task.last_success_at,
+            last_failure_at: task.last_failure_at,
+            last_error: task.last_error.clone(),
+            error_count: task.error_count,
+            queue_depth: task.queue_depth,
+            stuck: task.last_iteration.elapsed() > task.every * STUCK_AFTER_ITERATIONS,
+        })
+        .collect()
+}
+
+fn tasks() -> std::sync::MutexGuard<'static, BTreeMap<&'static str, TaskRecord>> {
+    TASKS
+        .lock()
+        .expect("The task health lock should never be poisoned")
+}
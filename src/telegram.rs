@@ -0,0 +1,99 @@
+use reqwest::Client;
+use serde::Deserialize;
+
+#[derive(Clone, Debug)]
+pub struct TelegramClient {
+    http_client: Client,
+    bot_token: String,
+    pub bot_username: String,
+    pub webhook_secret: String,
+    api_base_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TelegramUpdate {
+    pub update_id: i64,
+    pub message: Option<TelegramMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TelegramMessage {
+    pub chat: TelegramChat,
+    pub from: Option<TelegramUser>,
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TelegramChat {
+    pub id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TelegramUser {
+    pub id: i64,
+}
+
+impl TelegramClient {
+    pub fn new(bot_token: String, bot_username: String, webhook_secret: String) -> Self {
+        Self {
+            http_client: Client::new(),
+            bot_token,
+            bot_username,
+            webhook_secret,
+            api_base_url: "https://api.telegram.org".to_owned(),
+        }
+    }
+
+    /// Build a client from `TELEGRAM_BOT_TOKEN`, `TELEGRAM_BOT_USERNAME` and
+    /// `TELEGRAM_WEBHOOK_SECRET`, or `None` when the bot is not set up.
+    pub fn from_env() -> Option<Self> {
+        Some(Self::new(
+            std::env::var("TELEGRAM_BOT_TOKEN").ok()?,
+            std::env::var("TELEGRAM_BOT_USERNAME").ok()?,
+            std::env::var("TELEGRAM_WEBHOOK_SECRET").ok()?,
+        ))
+    }
+
+    /// Deep link that opens the bot and sends it `/start <payload>`.
+    pub fn start_url(&self, payload: &str) -> String {
+        format!("https://t.me/{}?start={}", self.bot_username, payload)
+    }
+
+    pub async fn send_message(&self, chat_id: i64, text: &str) -> Result<(), reqwest::Error> {
+        self.call(
+            "sendMessage",
+            serde_json::json!({ "chat_id": chat_id, "text": text }),
+        )
+        .await
+    }
+
+    /// Send an image Telegram downloads from `photo_url` itself.
+    pub async fn send_photo(
+        &self,
+        chat_id: i64,
+        photo_url: &str,
+        caption: &str,
+    ) -> Result<(), reqwest::Error> {
+        self.call(
+            "sendPhoto",
+            serde_json::json!({ "chat_id": chat_id, "photo": photo_url, "caption": caption }),
+        )
+        .await
+    }
+
+    /// Errors have the URL stripped since it embeds the bot token.
+    async fn call(&self, method: &str, body: serde_json::Value) -> Result<(), reqwest::Error> {
+        self.http_client
+            .post(format!(
+                "{}/bot{}/{}",
+                self.api_base_url, self.bot_token, method
+            ))
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.without_url())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! A small, logic-less template language for the server rendered pages.
+//!
+//! `{{name}}` inserts an HTML escaped value, `{{{name}}}` inserts it as is and
+//! `{{#if name}}...{{else}}...{{/if}}` renders a section only when the value
+//! is present and not empty. Templates are parsed once per render, so themes
+//! on disk can be edited while the server runs.
+
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum TemplateError {
+    Unclosed(usize),
+    UnexpectedTag(String),
+    MissingEndIf,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Unclosed(offset) => write!(f, "Unclosed tag at byte {}", offset),
+            TemplateError::UnexpectedTag(tag) => write!(f, "Unexpected {{{{{}}}}}", tag),
+            TemplateError::MissingEndIf => write!(f, "Missing {{{{/if}}}}"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+#[derive(Debug)]
+enum Node<'a> {
+    Text(&'a str),
+    Escaped(&'a str),
+    Raw(&'a str),
+    If {
+        name: &'a str,
+        then: Vec<Node<'a>>,
+        otherwise: Vec<Node<'a>>,
+    },
+}
+
+/// What stopped the parsing of a block.
+enum Stop {
+    Eof,
+    Else,
+    EndIf,
+}
+
+#[derive(Debug)]
+pub struct Template<'a> {
+    nodes: Vec<Node<'a>>,
+}
+
+impl<'a> Template<'a> {
+    pub fn parse(source: &'a str) -> Result<Self, TemplateError> {
+        let mut rest = source;
+        let (nodes, end) = parse_block(source, &mut rest)?;
+
+        match end {
+            Stop::Eof => Ok(Template { nodes }),
+            Stop::Else => Err(TemplateError::UnexpectedTag("else".to_string())),
+            Stop::EndIf => Err(TemplateError::UnexpectedTag("/if".to_string())),
+        }
+    }
+
+    pub fn render(&self, values: &[(&str, &str)]) -> String {
+        let mut rendered = String::new();
+        render_nodes(&self.nodes, values, &mut rendered);
+        rendered
+    }
+}
+
+fn parse_block<'a>(
+    source: &'a str,
+    rest: &mut &'a str,
+) -> Result<(Vec<Node<'a>>, Stop), TemplateError> {
+    let mut nodes = Vec::new();
+
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            nodes.push(Node::Text(&rest[..start]));
+        }
+        let offset = source.len() - rest.len() + start;
+        let tag = &rest[start..];
+
+        let (raw, open, close) = if tag.starts_with("{{{") {
+            (true, 3, "}}}")
+        } else {
+            (false, 2, "}}")
+        };
+        let end = tag.find(close).ok_or(TemplateError::Unclosed(offset))?;
+        let name = tag[open..end].trim();
+        *rest = &tag[end + close.len()..];
+
+        if raw {
+            nodes.push(Node::Raw(name));
+        } else if let Some(condition) = name.strip_prefix("#if ") {
+            let (then, end) = parse_block(source, rest)?;
+            let otherwise = match end {
+                Stop::Eof => return Err(TemplateError::MissingEndIf),
+                Stop::EndIf => Vec::new(),
+                Stop::Else => match parse_block(source, rest)? {
+                    (otherwise, Stop::EndIf) => otherwise,
+                    (_, Stop::Else) => {
+                        return Err(TemplateError::UnexpectedTag("else".to_string()))
+                    }
+                    (_, Stop::Eof) => return Err(TemplateError::MissingEndIf),
+                },
+            };
+            nodes.push(Node::If {
+                name: condition.trim(),
+                then,
+                otherwise,
+            });
+        } else if name == "else" {
+            return Ok((nodes, Stop::Else));
+        } else if name == "/if" {
+            return Ok((nodes, Stop::EndIf));
+        } else if name.starts_with('#') || name.starts_with('/') {
+            return Err(TemplateError::UnexpectedTag(name.to_string()));
+        } else {
+            nodes.push(Node::Escaped(name));
+        }
+    }
+
+    if !rest.is_empty() {
+        nodes.push(Node::Text(rest));
+        *rest = "";
+    }
+
+    Ok((nodes, Stop::Eof))
+}
+
+fn render_nodes(nodes: &[Node], values: &[(&str, &str)], rendered: &mut String) {
+    let lookup = |name: &str| {
+        values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+    };
+
+    for node in nodes {
+        match node {
+            Node::Text(text) => rendered.push_str(text),
+            Node::Escaped(name) => {
+                rendered.push_str(&html_escape(lookup(name).unwrap_or_default()))
+            }
+            Node::Raw(name) => rendered.push_str(lookup(name).unwrap_or_default()),
+            Node::If {
+                name,
+                then,
+                otherwise,
+            } => {
+                let present = lookup(name).is_some_and(|value| !value.is_empty());
+                render_nodes(if present { then } else { otherwise }, values, rendered);
+            }
+        }
+    }
+}
+
+pub fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
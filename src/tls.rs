@@ -0,0 +1,490 @@
+//! HTTPS for branded short domains served directly, without a proxy in
+//! front. Certificates are kept in `tls_certificates` by domain, uploaded
+//! by operators or issued through ACME, with their private keys sealed
+//! when a `CERTIFICATE_ENCRYPTION_KEY` is configured. Every replica loads
+//! them into memory and picks one by the name the client asks for (SNI).
+//! Clients asking for no name, or for one without a certificate, are
+//! turned away during the handshake.
+
+use crate::configuration::Settings;
+use crate::der;
+use crate::sealing::{self, SealingKey};
+use crate::task_health;
+
+use axum::extract::ConnectInfo;
+use axum::Router;
+use chrono::NaiveDateTime;
+use hyper::body::Incoming;
+use hyper::Request;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tower::ServiceExt;
+
+/// How often certificates changed by other replicas are picked up.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a client gets to finish the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const REFRESHER_TASK: &str = "tls_certificate_refresher";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CertificateError {
+    #[error("No certificate found in the chain")]
+    NoCertificate,
+    #[error("No private key found, expected a PEM encoded PKCS#8, RSA or EC key")]
+    NoPrivateKey,
+    #[error("The private key is not one that can sign a handshake")]
+    UnsupportedKey,
+    #[error("The certificate has no readable expiry")]
+    UnreadableExpiry,
+    #[error("The PEM is malformed")]
+    Malformed(#[from] io::Error),
+}
+
+/// A certificate chain and its key, checked to be usable for handshakes.
+pub struct ParsedCertificate {
+    pub certified_key: Arc<CertifiedKey>,
+    /// Expiry of the leaf certificate.
+    pub not_after: NaiveDateTime,
+}
+
+/// Parse a PEM certificate chain, leaf first, and the PEM private key of
+/// the leaf.
+pub fn parse_certificate(
+    chain_pem: &str,
+    key_pem: &str,
+) -> Result<ParsedCertificate, CertificateError> {
+    let chain: Vec<Certificate> = rustls_pemfile::certs(&mut chain_pem.as_bytes())?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let leaf = chain.first().ok_or(CertificateError::NoCertificate)?;
+    let not_after =
+        der::certificate_not_after(&leaf.0).ok_or(CertificateError::UnreadableExpiry)?;
+
+    let mut key = None;
+    let mut key_reader = key_pem.as_bytes();
+    while let Some(item) = rustls_pemfile::read_one(&mut key_reader)? {
+        if let rustls_pemfile::Item::PKCS8Key(der)
+        | rustls_pemfile::Item::RSAKey(der)
+        | rustls_pemfile::Item::ECKey(der) = item
+        {
+            key = Some(PrivateKey(der));
+            break;
+        }
+    }
+    let key = key.ok_or(CertificateError::NoPrivateKey)?;
+    let signing_key =
+        sign::any_supported_type(&key).map_err(|_| CertificateError::UnsupportedKey)?;
+
+    Ok(ParsedCertificate {
+        certified_key: Arc::new(CertifiedKey::new(chain, signing_key)),
+        not_after,
+    })
+}
+
+#[derive(FromRow)]
+struct StoredCertificate {
+    domain: String,
+    certificate_chain: String,
+    private_key: String,
+}
+
+/// The certificates of every domain, as last loaded from the database.
+pub struct CertificateStore {
+    certificates: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+    sealing_key: Option<SealingKey>,
+}
+
+impl CertificateStore {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            certificates: RwLock::new(HashMap::new()),
+            sealing_key: settings.certificate_encryption_key.clone(),
+        }
+    }
+
+    /// Replace the certificates with those in the database. A certificate
+    /// that no longer parses is left out, so its domain stops handshaking
+    /// rather than the whole store keeping stale ones.
+    pub async fn reload(&self, db: &PgPool) -> Result<usize, sqlx::Error> {
+        let stored: Vec<StoredCertificate> = sqlx::query_as(
+            r#"SELECT domain, certificate_chain, private_key FROM tls_certificates
+            WHERE certificate_chain IS NOT NULL AND private_key IS NOT NULL"#,
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut certificates = HashMap::with_capacity(stored.len());
+        for certificate in stored {
+            let private_key = match sealing::open(
+                self.sealing_key.as_ref(),
+                &certificate.domain,
+                &certificate.private_key,
+            ) {
+                Ok(private_key) => private_key,
+                Err(err) => {
+                    tracing::error!(
+                        "Could not open the private key of {}: {}",
+                        certificate.domain,
+                        err
+                    );
+                    continue;
+                }
+            };
+
+            match parse_certificate(&certificate.certificate_chain, &private_key) {
+                Ok(parsed) => {
+                    certificates.insert(certificate.domain, parsed.certified_key);
+                }
+                Err(err) => tracing::error!(
+                    "Could not load the certificate of {}: {}",
+                    certificate.domain,
+                    err
+                ),
+            }
+        }
+
+        let loaded = certificates.len();
+        *self
+            .certificates
+            .write()
+            .expect("The certificate store lock should never be poisoned") = certificates;
+        Ok(loaded)
+    }
+
+    pub async fn run_refresher(self: Arc<Self>, db: PgPool) {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        task_health::register(REFRESHER_TASK, REFRESH_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            match self.reload(&db).await {
+                Ok(loaded) => {
+                    tracing::trace!("Loaded {} TLS certificates", loaded);
+                    task_health::succeeded(REFRESHER_TASK);
+                }
+                Err(err) => {
+                    tracing::error!("Could not load TLS certificates: {}", err);
+                    task_health::failed(REFRESHER_TASK, &err);
+                }
+            }
+        }
+    }
+
+    /// The certificate for `name`, or else a wildcard one covering it.
+    fn find(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        let certificates = self
+            .certificates
+            .read()
+            .expect("The certificate store lock should never be poisoned");
+
+        certificates.get(name).cloned().or_else(|| {
+            let (_, parent) = name.split_once('.')?;
+            certificates.get(&format!("*.{}", parent)).cloned()
+        })
+    }
+}
+
+impl ResolvesServerCert for CertificateStore {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let name = client_hello.server_name()?.to_ascii_lowercase();
+        let certificate = self.find(&name);
+        if certificate.is_none() {
+            tracing::debug!("No TLS certificate for {}", name);
+        }
+        certificate
+    }
+}
+
+/// Seal the private keys stored before an encryption key was configured.
+pub async fn seal_stored_keys(db: &PgPool, key: &SealingKey) -> Result<(), sqlx::Error> {
+    let certificates: Vec<(String, String)> = sqlx::query_as(
+        r#"SELECT domain, private_key FROM tls_certificates WHERE private_key IS NOT NULL"#,
+    )
+    .fetch_all(db)
+    .await?;
+    let certificates: Vec<_> = certificates
+        .into_iter()
+        .filter(|(_, private_key)| !sealing::is_sealed(private_key))
+        .collect();
+    for (domain, private_key) in &certificates {
+        sqlx::query(r#"UPDATE tls_certificates SET private_key = $2 WHERE domain = $1"#)
+            .bind(domain)
+            .bind(key.seal(domain, private_key))
+            .execute(db)
+            .await?;
+    }
+
+    let accounts: Vec<(String, String)> =
+        sqlx::query_as(r#"SELECT directory_url, private_key FROM acme_accounts"#)
+            .fetch_all(db)
+            .await?;
+    let accounts: Vec<_> = accounts
+        .into_iter()
+        .filter(|(_, private_key)| !sealing::is_sealed(private_key))
+        .collect();
+    for (directory_url, private_key) in &accounts {
+        sqlx::query(r#"UPDATE acme_accounts SET private_key = $2 WHERE directory_url = $1"#)
+            .bind(directory_url)
+            .bind(key.seal(directory_url, private_key))
+            .execute(db)
+            .await?;
+    }
+
+    if !certificates.is_empty() || !accounts.is_empty() {
+        tracing::info!(
+            "Sealed the private keys of {} certificates and {} ACME accounts",
+            certificates.len(),
+            accounts.len()
+        );
+    }
+    Ok(())
+}
+
+/// Accept HTTPS connections on `address` for as long as the process runs,
+/// serving `app` on each the way the plain listener does.
+pub async fn serve(address: SocketAddr, app: Router, certificates: Arc<CertificateStore>) {
+    let listener = TcpListener::bind(address)
+        .await
+        .expect("Could not initialize the TLS listener");
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(certificates);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let config = Arc::new(config);
+
+    tracing::debug!("listening for TLS on {}", address);
+
+    loop {
+        let (tcp, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::warn!("Could not accept a TLS connection: {}", err);
+                continue;
+            }
+        };
+
+        let config = config.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, TlsStream::accept(tcp, config)).await
+                {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(err)) => {
+                        tracing::debug!("TLS handshake with {} failed: {}", remote, err);
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::debug!("TLS handshake with {} timed out", remote);
+                        return;
+                    }
+                };
+
+            let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(remote));
+                app.clone().oneshot(request)
+            });
+
+            if let Err(err) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("TLS connection with {} failed: {}", remote, err);
+            }
+        });
+    }
+}
+
+/// A server side TLS session over a TCP connection.
+struct TlsStream {
+    io: TcpStream,
+    session: ServerConnection,
+    /// Whether close_notify was queued, so retried shutdowns send one.
+    closing: bool,
+}
+
+/// Blocking IO on top of the TCP connection for rustls, turning "not ready
+/// yet" into `WouldBlock` once the waker is registered.
+struct SyncIo<'a, 'b> {
+    io: &'a mut TcpStream,
+    cx: &'a mut Context<'b>,
+}
+
+impl Read for SyncIo<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buf = ReadBuf::new(buf);
+        match Pin::new(&mut *self.io).poll_read(self.cx, &mut buf) {
+            Poll::Ready(Ok(())) => Ok(buf.filled().len()),
+            Poll::Ready(Err(err)) => Err(err),
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl Write for SyncIo<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_write(self.cx, buf) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match Pin::new(&mut *self.io).poll_flush(self.cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+fn would_block_to_pending<T>(result: io::Result<T>) -> Poll<io::Result<T>> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+        result => Poll::Ready(result),
+    }
+}
+
+impl TlsStream {
+    async fn accept(io: TcpStream, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let session = ServerConnection::new(config).map_err(io::Error::other)?;
+        let mut stream = Self {
+            io,
+            session,
+            closing: false,
+        };
+        std::future::poll_fn(|cx| stream.poll_handshake(cx)).await?;
+        Ok(stream)
+    }
+
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            while self.session.wants_write() {
+                ready!(self.poll_write_tls(cx))?;
+            }
+            if !self.session.is_handshaking() {
+                return Poll::Ready(Ok(()));
+            }
+            if ready!(self.poll_read_tls(cx))? == 0 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+    }
+
+    /// Feed records from the connection to the session.
+    fn poll_read_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let mut io = SyncIo {
+            io: &mut self.io,
+            cx,
+        };
+        let read = ready!(would_block_to_pending(self.session.read_tls(&mut io)))?;
+
+        if let Err(err) = self.session.process_new_packets() {
+            // Let the peer know why, as far as the connection allows.
+            let _ = self.poll_write_tls(cx);
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, err)));
+        }
+        Poll::Ready(Ok(read))
+    }
+
+    /// Send records the session has queued.
+    fn poll_write_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let mut io = SyncIo {
+            io: &mut self.io,
+            cx,
+        };
+        would_block_to_pending(self.session.write_tls(&mut io))
+    }
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            while this.session.wants_write() {
+                ready!(this.poll_write_tls(cx))?;
+            }
+
+            match this.session.reader().read(buf.initialize_unfilled()) {
+                Ok(read) => {
+                    buf.advance(read);
+                    return Poll::Ready(Ok(()));
+                }
+                // The peer closed without saying so, which ends the stream
+                // all the same.
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Poll::Ready(Ok(()))
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+
+            ready!(this.poll_read_tls(cx))?;
+        }
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let written = this.session.writer().write(buf)?;
+
+        while this.session.wants_write() {
+            match this.poll_write_tls(cx) {
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                // What was taken is queued, and goes out on the next write
+                // or flush.
+                Poll::Pending if written > 0 => break,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.session.writer().flush()?;
+        while this.session.wants_write() {
+            ready!(this.poll_write_tls(cx))?;
+        }
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.closing {
+            this.session.send_close_notify();
+            this.closing = true;
+        }
+        while this.session.wants_write() {
+            ready!(this.poll_write_tls(cx))?;
+        }
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
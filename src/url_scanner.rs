@@ -0,0 +1,99 @@
+//! Checking link targets against lists of phishing and malware sites:
+//! Google Safe Browsing when `SAFE_BROWSING_API_KEY` is set, and nothing
+//! otherwise. Other scanners are added as backends.
+
+use crate::configuration::Settings;
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+
+static SCANNER_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(2))
+        .build()
+        .expect("The URL scanner client should always be constructable")
+});
+
+const SAFE_BROWSING_THREAT_TYPES: [&str; 4] = [
+    "MALWARE",
+    "SOCIAL_ENGINEERING",
+    "UNWANTED_SOFTWARE",
+    "POTENTIALLY_HARMFUL_APPLICATION",
+];
+
+pub struct UrlScanner {
+    backend: Backend,
+}
+
+enum Backend {
+    SafeBrowsing { url: String, api_key: String },
+    Disabled,
+}
+
+#[derive(Deserialize)]
+struct SafeBrowsingMatches {
+    #[serde(default)]
+    matches: Vec<SafeBrowsingMatch>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SafeBrowsingMatch {
+    threat_type: String,
+}
+
+impl UrlScanner {
+    pub fn new(settings: &Settings) -> Self {
+        let backend = match &settings.safe_browsing_api_key {
+            Some(api_key) => Backend::SafeBrowsing {
+                url: format!("{}/v4/threatMatches:find", settings.safe_browsing_url),
+                api_key: api_key.clone(),
+            },
+            None => Backend::Disabled,
+        };
+
+        Self { backend }
+    }
+
+    /// The threats the scanner knows `url` for, such as `MALWARE`, sorted.
+    /// Empty when it knows of none.
+    pub async fn scan(&self, url: &str) -> Result<Vec<String>, reqwest::Error> {
+        let (endpoint, api_key) = match &self.backend {
+            Backend::SafeBrowsing { url, api_key } => (url, api_key),
+            Backend::Disabled => return Ok(Vec::new()),
+        };
+
+        let found = SCANNER_CLIENT
+            .post(endpoint)
+            .query(&[("key", api_key)])
+            .json(&json!({
+                "client": {
+                    "clientId": "groupify",
+                    "clientVersion": env!("CARGO_PKG_VERSION"),
+                },
+                "threatInfo": {
+                    "threatTypes": SAFE_BROWSING_THREAT_TYPES,
+                    "platformTypes": ["ANY_PLATFORM"],
+                    "threatEntryTypes": ["URL"],
+                    "threatEntries": [{ "url": url }],
+                },
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<SafeBrowsingMatches>()
+            .await?;
+
+        let mut threats: Vec<String> = found
+            .matches
+            .into_iter()
+            .map(|found| found.threat_type)
+            .collect();
+        threats.sort();
+        threats.dedup();
+
+        Ok(threats)
+    }
+}
@@ -0,0 +1,65 @@
+//! Telling bots from people by the user agent they send. Chat apps and
+//! social networks fetch every link shared on them to show a preview, and
+//! crawlers follow links too, so their requests are recorded as clicks of
+//! bots for statistics to leave out.
+
+use woothee::parser::Parser;
+use woothee::woothee::VALUE_UNKNOWN;
+
+/// Categories of agents people browse with. Crawlers, HTTP libraries and
+/// agents the parser does not know are bots.
+const HUMAN_CATEGORIES: [&str; 4] = ["pc", "smartphone", "mobilephone", "appliance"];
+
+/// Lowercase user agent fragments of crawlers, link previews and scripts,
+/// for those passing themselves off as browsers.
+const BOT_USER_AGENT_MARKERS: [&str; 12] = [
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "preview",
+    "facebookexternalhit",
+    "headless",
+    "curl",
+    "wget",
+    "python-requests",
+    "go-http-client",
+    "okhttp",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AgentClass {
+    pub bot: bool,
+    /// Browser of a person, such as `Chrome`.
+    pub browser: Option<String>,
+    /// Operating system of a person, such as `Windows 10` or `iPhone`.
+    pub os: Option<String>,
+}
+
+pub fn classify(user_agent: Option<&str>) -> AgentClass {
+    let parsed = user_agent
+        .filter(|user_agent| !user_agent.trim().is_empty() && !looks_like_bot(user_agent))
+        .and_then(|user_agent| Parser::new().parse(user_agent));
+
+    match parsed {
+        Some(parsed) if HUMAN_CATEGORIES.contains(&parsed.category) => {
+            let known = |value: &str| (value != VALUE_UNKNOWN).then(|| value.to_string());
+            AgentClass {
+                bot: false,
+                browser: known(parsed.name),
+                os: known(parsed.os),
+            }
+        }
+        _ => AgentClass {
+            bot: true,
+            ..AgentClass::default()
+        },
+    }
+}
+
+fn looks_like_bot(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_ascii_lowercase();
+    BOT_USER_AGENT_MARKERS
+        .iter()
+        .any(|marker| user_agent.contains(marker))
+}
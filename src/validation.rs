@@ -0,0 +1,172 @@
+//! Validation of request bodies. Handlers take `Valid<T>` instead of
+//! `Json<T>` for DTOs implementing `Validate`, and callers get a 422 listing
+//! every problem by field instead of the first error a handler runs into.
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, Request};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::NaiveDateTime;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use url::Url;
+
+/// Longest target URL a link may have.
+pub const MAX_URL_LENGTH: usize = 2048;
+
+pub const MIN_SLUG_LENGTH: usize = 3;
+/// Shortest premium slug operators may provision, see `routes::slug`.
+pub const MIN_PREMIUM_SLUG_LENGTH: usize = 1;
+pub const MAX_SLUG_LENGTH: usize = 64;
+
+/// Longest address RFC 5321 lets through.
+const MAX_EMAIL_LENGTH: usize = 254;
+
+pub trait Validate {
+    fn validate(&self, errors: &mut ValidationErrors);
+}
+
+/// Messages per field, keyed by the field's name in the JSON body.
+#[derive(Debug, Default)]
+pub struct ValidationErrors {
+    errors: BTreeMap<&'static str, Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct ValidationFailure<'a> {
+    message: &'static str,
+    errors: &'a BTreeMap<&'static str, Vec<String>>,
+}
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.errors.entry(field).or_default().push(message.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn require_not_blank(&mut self, field: &'static str, value: &str) {
+        if value.trim().is_empty() {
+            self.add(field, "must not be empty");
+        }
+    }
+
+    pub fn require_max_length(&mut self, field: &'static str, value: &str, max: usize) {
+        if value.chars().count() > max {
+            self.add(field, format!("must be at most {} characters", max));
+        }
+    }
+
+    /// An absolute http or https URL, as links may only point to web pages.
+    pub fn require_web_url(&mut self, field: &'static str, value: &str) {
+        if value.trim().is_empty() {
+            self.add(field, "must not be empty");
+            return;
+        }
+
+        self.require_max_length(field, value, MAX_URL_LENGTH);
+        match Url::parse(value) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(_) => self.add(field, "must be an http or https URL"),
+            Err(_) => self.add(field, "must be an absolute URL"),
+        }
+    }
+
+    /// A web URL links may redirect to. Credentials are refused, as a
+    /// target like `https://bank.example@evil.example` reads as another
+    /// site than the one visitors are sent to.
+    pub fn require_link_target(&mut self, field: &'static str, value: &str) {
+        self.require_web_url(field, value);
+        if let Ok(url) = Url::parse(value) {
+            if !url.username().is_empty() || url.password().is_some() {
+                self.add(field, "must not contain a username or password");
+            }
+        }
+    }
+
+    pub fn require_email(&mut self, field: &'static str, value: &str) {
+        self.require_max_length(field, value, MAX_EMAIL_LENGTH);
+        let looks_like_email = value
+            .split_once('@')
+            .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+            && !value.contains(char::is_whitespace);
+        if !looks_like_email {
+            self.add(field, "must be an email address");
+        }
+    }
+
+    /// A custom link id: letters, digits, `-` and `_`, which survive in a
+    /// URL path unescaped, like generated ids. Only premium slugs may be
+    /// shorter than `MIN_SLUG_LENGTH`.
+    pub fn require_slug(&mut self, field: &'static str, value: &str, min_length: usize) {
+        if value.len() < min_length || value.len() > MAX_SLUG_LENGTH {
+            self.add(
+                field,
+                format!(
+                    "must be between {} and {} characters",
+                    min_length, MAX_SLUG_LENGTH
+                ),
+            );
+        }
+        if !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            self.add(field, "must only contain letters, digits, - and _");
+        }
+    }
+
+    pub fn require_range(&mut self, field: &'static str, value: i64, min: i64, max: i64) {
+        if value < min || value > max {
+            self.add(field, format!("must be between {} and {}", min, max));
+        }
+    }
+
+    pub fn require_future(&mut self, field: &'static str, value: NaiveDateTime) {
+        if value <= chrono::Utc::now().naive_utc() {
+            self.add(field, "must be in the future");
+        }
+    }
+}
+
+impl IntoResponse for ValidationErrors {
+    fn into_response(self) -> Response {
+        let failure = ValidationFailure {
+            message: "Validation failed",
+            errors: &self.errors,
+        };
+
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(failure)).into_response()
+    }
+}
+
+/// A JSON body that passed its `Validate` checks.
+pub struct Valid<T>(pub T);
+
+#[axum::async_trait]
+impl<S, T> FromRequest<S> for Valid<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(request, state)
+            .await
+            .map_err(|rejection: JsonRejection| rejection.into_response())?;
+
+        let mut errors = ValidationErrors::default();
+        value.validate(&mut errors);
+
+        if errors.is_empty() {
+            Ok(Valid(value))
+        } else {
+            Err(errors.into_response())
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Unique visitors are told apart by a hash of their address and user
+//! agent, keyed with a random salt of the day that every replica shares
+//! through the database. Salts of past days are deleted once a newer one is
+//! drawn and never drawn again, so hashes of different days cannot be
+//! matched up, nor the hash of an address recomputed, even with the database.
+
+use chrono::NaiveDate;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::PgPool;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+const SALT_LENGTH: usize = 32;
+
+#[derive(Default)]
+pub struct VisitorSalts {
+    /// The salt of the latest day asked for.
+    current: Mutex<Option<(NaiveDate, hmac::Key)>>,
+}
+
+impl VisitorSalts {
+    /// The hash of a visitor of `day`, the same for all their clicks that
+    /// day. None when the salt of the day is gone or cannot be had, the
+    /// click then counting as no visitor.
+    pub async fn visitor_hash(
+        &self,
+        db: &PgPool,
+        day: NaiveDate,
+        ip: IpAddr,
+        user_agent: Option<&str>,
+    ) -> Option<String> {
+        let key = match self.cached(day) {
+            Some(key) => key,
+            None => {
+                let salt = match salt_of(db, day).await {
+                    Ok(salt) => salt?,
+                    Err(err) => {
+                        tracing::error!("Could not get the visitor salt: {}", err);
+                        return None;
+                    }
+                };
+                let key = hmac::Key::new(hmac::HMAC_SHA256, &salt);
+                *self.lock() = Some((day, key.clone()));
+                key
+            }
+        };
+
+        let visitor = format!("{}\n{}", ip.to_canonical(), user_agent.unwrap_or_default());
+        Some(hex::encode(hmac::sign(&key, visitor.as_bytes())))
+    }
+
+    fn cached(&self, day: NaiveDate) -> Option<hmac::Key> {
+        self.lock()
+            .as_ref()
+            .filter(|(cached_day, _)| *cached_day == day)
+            .map(|(_, key)| key.clone())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<(NaiveDate, hmac::Key)>> {
+        self.current
+            .lock()
+            .expect("The visitor salt lock should never be poisoned")
+    }
+}
+
+/// The salt of `day`, drawn by whichever replica asks first. Drawing one
+/// deletes those of the days before, and a day with a newer salt gets none.
+async fn salt_of(db: &PgPool, day: NaiveDate) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    let mut salt = [0u8; SALT_LENGTH];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .expect("The system random generator should never fail");
+
+    let mut transaction = db.begin().await?;
+
+    sqlx::query(
+        r#"INSERT INTO visitor_salts (day, salt)
+        SELECT $1, $2 WHERE NOT EXISTS (SELECT 1 FROM visitor_salts WHERE day > $1)
+        ON CONFLICT (day) DO NOTHING"#,
+    )
+    .bind(day)
+    .bind(&salt[..])
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query(r#"DELETE FROM visitor_salts WHERE day < $1"#)
+        .bind(day)
+        .execute(&mut *transaction)
+        .await?;
+
+    let salt = sqlx::query_scalar(r#"SELECT salt FROM visitor_salts WHERE day = $1"#)
+        .bind(day)
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(salt)
+}